@@ -0,0 +1,156 @@
+//! Benchmark of the streaming pipeline under load: 100 clients stream replies at once
+//! through the proxy, from a simulated Copilot sending 50 tokens a second. A reply of
+//! `TOKENS` tokens takes half a second upstream, so time above that is the proxy's.
+
+use axum::Router;
+use axum::body::Body;
+use axum::response::Response;
+use axum::routing::{get, post};
+use criterion::{Criterion, SamplingMode, criterion_group, criterion_main};
+use futures_util::StreamExt;
+use passenger_rs::auth::CopilotTokenResponse;
+use passenger_rs::config::Config;
+use passenger_rs::server::Server;
+use passenger_rs::storage;
+use serde_json::json;
+use std::convert::Infallible;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Clients streaming at once
+const CLIENTS: usize = 100;
+
+/// Tokens in each reply
+const TOKENS: usize = 25;
+
+/// Time between tokens from the simulated Copilot: 50 tokens a second
+const TOKEN_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A streamed Copilot reply of `TOKENS` chunks, one every `TOKEN_INTERVAL`
+async fn chat_completions() -> Response {
+    let chunks = futures_util::stream::unfold(0, |i| async move {
+        if i > TOKENS {
+            return None;
+        }
+        tokio::time::sleep(TOKEN_INTERVAL).await;
+        let line = if i == TOKENS {
+            "data: [DONE]\n\n".to_string()
+        } else {
+            let chunk = json!({
+                "id": "chatcmpl-1",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": [{"index": 0, "delta": {"content": format!(" token{}", i)}}]
+            });
+            format!("data: {}\n\n", chunk)
+        };
+        Some((Ok::<_, Infallible>(line), i + 1))
+    });
+    Response::builder()
+        .header("content-type", "text/event-stream")
+        .body(Body::from_stream(chunks))
+        .unwrap()
+}
+
+async fn models() -> axum::Json<serde_json::Value> {
+    axum::Json(json!({
+        "github-copilot": { "models": {
+            "gpt-4o": { "id": "gpt-4o", "name": "GPT-4o", "family": "gpt-4o" }
+        } }
+    }))
+}
+
+async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    addr
+}
+
+/// Start the simulated Copilot and a proxy in front of it, returning the proxy's address
+async fn start(tokens: &TempDir) -> String {
+    let copilot = serve(
+        Router::new()
+            .route("/chat/completions", post(chat_completions))
+            .route("/models", get(models)),
+    )
+    .await;
+
+    let token_path = tokens.path().join("token.json");
+    let token = CopilotTokenResponse {
+        token: "copilot-token".to_string(),
+        expires_at: u64::MAX / 2,
+        refresh_in: 0,
+        fetched_at: None,
+        entitlements: Default::default(),
+    };
+    storage::save_token_to_path(&token, Some(&token_path)).unwrap();
+
+    let config = Config::parse(&format!(
+        r#"
+        [github]
+        copilot_models_url = "http://{copilot}/models"
+
+        [copilot]
+        api_base_url = "http://{copilot}"
+
+        [profiles.bench]
+        access_token_path = "{access}"
+        copilot_token_path = "{token}"
+        "#,
+        access = tokens.path().join("access_token.json").display(),
+        token = token_path.display(),
+    ))
+    .unwrap();
+    serve(Server::new(&config).router).await
+}
+
+/// Stream one reply through the proxy, returning its size
+async fn stream_reply(client: &reqwest::Client, proxy: &str) -> usize {
+    let response = client
+        .post(format!("http://{}/bench/v1/chat/completions", proxy))
+        .json(&json!({
+            "model": "gpt-4o",
+            "stream": true,
+            "messages": [{"role": "user", "content": "Count to 25"}]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    let mut body = response.bytes_stream();
+    let mut size = 0;
+    while let Some(bytes) = body.next().await {
+        size += bytes.unwrap().len();
+    }
+    size
+}
+
+fn concurrent_streams(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let tokens = tempfile::tempdir().unwrap();
+    let proxy = runtime.block_on(start(&tokens));
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(CLIENTS)
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("streaming");
+    group
+        .sampling_mode(SamplingMode::Flat)
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(10));
+    group.bench_function(format!("{}_clients_50_tokens_per_second", CLIENTS), |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let streams = (0..CLIENTS).map(|_| stream_reply(&client, &proxy));
+                let sizes = futures_util::future::join_all(streams).await;
+                assert!(sizes.iter().all(|size| *size > 0));
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_streams);
+criterion_main!(benches);
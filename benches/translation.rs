@@ -0,0 +1,134 @@
+//! Benchmarks of the translation hot paths: converting client requests for Copilot,
+//! parsing Copilot's SSE lines and serializing large conversations.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use passenger_rs::copilot::CopilotChatRequest;
+use passenger_rs::copilot::stream::{ChatDeltas, chat_deltas};
+use passenger_rs::openai::completion::models::OpenAIChatRequest;
+use serde_json::{Value, json};
+use std::hint::black_box;
+
+/// An OpenAI chat request of `turns` user and assistant messages of `words` words each,
+/// with a tool declared
+fn chat_request(turns: usize, words: usize) -> Value {
+    let text = "lorem ipsum dolor sit amet ".repeat(words / 5);
+    let messages: Vec<Value> = (0..turns)
+        .map(|i| {
+            let role = if i % 2 == 0 { "user" } else { "assistant" };
+            json!({"role": role, "content": format!("{} {}", i, text)})
+        })
+        .collect();
+    json!({
+        "model": "gpt-4o",
+        "messages": messages,
+        "stream": true,
+        "tools": [{
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "description": "Current weather of a city",
+                "parameters": {
+                    "type": "object",
+                    "properties": {"city": {"type": "string"}},
+                    "required": ["city"]
+                }
+            }
+        }]
+    })
+}
+
+fn openai_to_copilot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("openai_to_copilot");
+    for turns in [2, 50, 500] {
+        let request = chat_request(turns, 50);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(turns),
+            &request,
+            |b, request| {
+                b.iter_batched(
+                    || serde_json::from_value::<OpenAIChatRequest>(request.clone()).unwrap(),
+                    CopilotChatRequest::from,
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+/// The SSE lines of a streamed reply of `tokens` content chunks
+fn sse_lines(tokens: usize) -> Vec<String> {
+    let mut lines: Vec<String> = (0..tokens)
+        .map(|i| {
+            let chunk = json!({
+                "id": "chatcmpl-1",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": [{"index": 0, "delta": {"content": format!(" token{}", i)}}]
+            });
+            format!("data: {}", chunk)
+        })
+        .collect();
+    lines.push("data: [DONE]".to_string());
+    lines
+}
+
+fn sse_line_translation(c: &mut Criterion) {
+    let lines = sse_lines(1000);
+    let mut group = c.benchmark_group("sse_line_translation");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("chat_deltas_line", |b| {
+        b.iter(|| {
+            let mut parser = ChatDeltas::default();
+            for line in &lines {
+                black_box(parser.line(black_box(line)));
+            }
+        })
+    });
+    group.bench_function("chat_deltas_stream", |b| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        b.iter(|| {
+            let lines = futures_util::stream::iter(lines.clone().into_iter().map(Ok));
+            runtime.block_on(async {
+                use futures_util::StreamExt;
+                chat_deltas(lines)
+                    .for_each(|delta| async { drop(black_box(delta)) })
+                    .await
+            })
+        })
+    });
+    group.finish();
+}
+
+fn large_message_serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_message_serialization");
+    for words in [1_000, 100_000] {
+        let request: OpenAIChatRequest = serde_json::from_value(chat_request(4, words)).unwrap();
+        let request = CopilotChatRequest::from(request);
+        let bytes = serde_json::to_vec(&request).unwrap();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("serialize", words),
+            &request,
+            |b, request| b.iter(|| serde_json::to_vec(black_box(request)).unwrap()),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("deserialize", words),
+            &bytes,
+            |b, bytes| {
+                b.iter(|| serde_json::from_slice::<OpenAIChatRequest>(black_box(bytes)).unwrap())
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    openai_to_copilot,
+    sse_line_translation,
+    large_message_serialization
+);
+criterion_main!(benches);
@@ -0,0 +1,291 @@
+use crate::config::Config;
+use crate::copilot::client::CopilotClient;
+use crate::server::Server;
+use anyhow::{Result, anyhow};
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::time::{Duration, Instant};
+
+/// Subjects synthetic prompts cycle through, so consecutive requests differ
+const TOPICS: &[&str] = &[
+    "the water cycle",
+    "how a compiler works",
+    "the history of the printing press",
+    "why the sky is blue",
+    "how vaccines train the immune system",
+    "the rules of chess",
+    "how HTTP caching works",
+    "the life cycle of a star",
+];
+
+/// Where `passenger-rs bench` sends its requests
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// A proxy served on a local port for the run, with the loaded configuration
+    Local,
+    /// A running proxy, or any OpenAI-compatible server, at this base URL
+    Url(String),
+    /// Copilot's chat completions endpoint, bypassing the proxy
+    Upstream,
+}
+
+/// How hard to drive the target
+#[derive(Debug, Clone)]
+pub struct Load {
+    pub model: String,
+    pub concurrency: usize,
+    pub requests: usize,
+}
+
+/// One request of a run
+#[derive(Debug)]
+pub struct Sample {
+    pub latency: Duration,
+    /// Completion tokens of the reply, or why the request failed
+    pub outcome: Result<u64, String>,
+}
+
+/// Latency percentiles, throughput and errors of a run
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Report {
+    pub requests: usize,
+    pub errors: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Completion tokens over the wall-clock time of the run
+    pub tokens_per_second: f64,
+    pub requests_per_second: f64,
+    /// Distinct errors and how often each occurred, most frequent first
+    pub error_counts: Vec<(String, usize)>,
+}
+
+impl Report {
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.requests as f64
+        }
+    }
+}
+
+/// The `i`th synthetic chat completion request for `model`
+pub fn request(model: &str, i: usize) -> Value {
+    let topic = TOPICS[i % TOPICS.len()];
+    json!({
+        "model": model,
+        "messages": [{
+            "role": "user",
+            "content": format!("In one short paragraph, explain {}. (request {})", topic, i),
+        }],
+        "stream": false,
+    })
+}
+
+/// Send `load` to `target`, `load.concurrency` requests at a time, and report on the run
+pub async fn run(config: &Config, target: &Target, load: &Load) -> Result<Report> {
+    let client = Client::new();
+    let endpoint = match target {
+        Target::Local => {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            let url = format!("http://{}/v1/chat/completions", listener.local_addr()?);
+            let router = Server::new(config).router;
+            tokio::spawn(async move { axum::serve(listener, router).await });
+            Endpoint::Proxy(url)
+        }
+        Target::Url(base) => Endpoint::Proxy(format!(
+            "{}/v1/chat/completions",
+            base.trim_end_matches('/')
+        )),
+        Target::Upstream => {
+            let copilot = CopilotClient::new(config.clone())?;
+            let token = copilot.token().await?;
+            Endpoint::Copilot(Box::new(copilot), token)
+        }
+    };
+
+    let started = Instant::now();
+    let samples: Vec<Sample> = futures_util::stream::iter(0..load.requests)
+        .map(|i| {
+            let body = request(&load.model, i);
+            let builder = endpoint.post(config, &client).json(&body);
+            async move {
+                let sent = Instant::now();
+                let outcome = complete(builder).await.map_err(|e| e.to_string());
+                Sample {
+                    latency: sent.elapsed(),
+                    outcome,
+                }
+            }
+        })
+        .buffer_unordered(load.concurrency.max(1))
+        .collect()
+        .await;
+
+    Ok(summarise(&samples, started.elapsed()))
+}
+
+enum Endpoint {
+    Proxy(String),
+    /// Copilot itself, with this Copilot token
+    Copilot(Box<CopilotClient>, String),
+}
+
+impl Endpoint {
+    fn post(&self, config: &Config, client: &Client) -> reqwest::RequestBuilder {
+        match self {
+            Endpoint::Proxy(url) => client.post(url),
+            Endpoint::Copilot(copilot, token) => copilot
+                .post("/chat/completions", token)
+                .timeout(config.copilot.timeouts.total(false)),
+        }
+    }
+}
+
+/// The completion tokens of the reply
+async fn complete(builder: reqwest::RequestBuilder) -> Result<u64> {
+    let response = builder.send().await.map_err(|e| {
+        anyhow!(if e.is_timeout() {
+            "timed out".to_string()
+        } else {
+            format!("connection failed: {}", e.without_url())
+        })
+    })?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow!("HTTP {}", status.as_u16()));
+    }
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|_| anyhow!("unreadable response"))?;
+    Ok(body["usage"]["completion_tokens"].as_u64().unwrap_or(0))
+}
+
+/// The latency below which `fraction` of `sorted` fall, by the nearest-rank method
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Report on `samples`, sent over `elapsed`. Percentiles cover successful requests only.
+pub fn summarise(samples: &[Sample], elapsed: Duration) -> Report {
+    let mut latencies: Vec<Duration> = samples
+        .iter()
+        .filter(|sample| sample.outcome.is_ok())
+        .map(|sample| sample.latency)
+        .collect();
+    latencies.sort();
+    let tokens: u64 = samples
+        .iter()
+        .filter_map(|sample| sample.outcome.as_ref().ok())
+        .sum();
+
+    let mut error_counts: Vec<(String, usize)> = Vec::new();
+    for error in samples
+        .iter()
+        .filter_map(|sample| sample.outcome.as_ref().err())
+    {
+        match error_counts.iter_mut().find(|(seen, _)| seen == error) {
+            Some((_, count)) => *count += 1,
+            None => error_counts.push((error.clone(), 1)),
+        }
+    }
+    error_counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    Report {
+        requests: samples.len(),
+        errors: samples.len() - latencies.len(),
+        p50: percentile(&latencies, 0.5),
+        p90: percentile(&latencies, 0.9),
+        p99: percentile(&latencies, 0.99),
+        max: latencies.last().copied().unwrap_or_default(),
+        tokens_per_second: tokens as f64 / seconds,
+        requests_per_second: samples.len() as f64 / seconds,
+        error_counts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_summarise() {
+        let sample = |millis: u64, outcome: Result<u64, &str>| Sample {
+            latency: Duration::from_millis(millis),
+            outcome: outcome.map_err(str::to_string),
+        };
+        let mut samples: Vec<Sample> = (1..=10).map(|i| sample(i * 100, Ok(20))).collect();
+        samples.push(sample(5, Err("HTTP 429")));
+        samples.push(sample(5, Err("timed out")));
+        samples.push(sample(5, Err("HTTP 429")));
+
+        let report = summarise(&samples, Duration::from_secs(2));
+        assert_eq!(report.requests, 13);
+        assert_eq!(report.errors, 3);
+        assert_eq!(report.p50, Duration::from_millis(500));
+        assert_eq!(report.p90, Duration::from_millis(900));
+        assert_eq!(report.p99, Duration::from_millis(1000));
+        assert_eq!(report.max, Duration::from_millis(1000));
+        assert_eq!(report.tokens_per_second, 100.0);
+        assert_eq!(
+            report.error_counts,
+            [("HTTP 429".to_string(), 2), ("timed out".to_string(), 1)]
+        );
+        assert!((report.error_rate() - 3.0 / 13.0).abs() < 1e-9);
+
+        let empty = summarise(&[], Duration::ZERO);
+        assert_eq!(empty.p99, Duration::ZERO);
+        assert_eq!(empty.error_rate(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_run_against_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(body_partial_json(
+                json!({"model": "gpt-4o", "stream": false}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{"message": {"role": "assistant", "content": "Hi"}}],
+                "usage": {"completion_tokens": 7},
+            })))
+            .expect(6)
+            .mount(&server)
+            .await;
+
+        let load = Load {
+            model: "gpt-4o".to_string(),
+            concurrency: 3,
+            requests: 6,
+        };
+        let report = run(&Config::default(), &Target::Url(server.uri()), &load)
+            .await
+            .unwrap();
+        assert_eq!(report.requests, 6);
+        assert_eq!(report.errors, 0);
+        assert!(report.tokens_per_second > 0.0);
+
+        let load = Load {
+            model: "o3".to_string(),
+            ..load
+        };
+        let report = run(&Config::default(), &Target::Url(server.uri()), &load)
+            .await
+            .unwrap();
+        assert_eq!(report.errors, 6);
+        assert_eq!(report.error_counts, [("HTTP 404".to_string(), 6)]);
+    }
+}
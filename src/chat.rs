@@ -0,0 +1,267 @@
+use crate::config::Config;
+use crate::copilot::CopilotChatRequest;
+use crate::copilot::client::CopilotClient;
+use crate::copilot::stream::ChatDelta;
+use anyhow::{Context, Result, anyhow};
+use futures_util::TryStreamExt;
+use serde_json::{Value, json};
+use std::io::{IsTerminal, Write};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+const HELP: &str = "Commands:
+  /model [name]    show or switch the model
+  /system [prompt] set the system prompt, or clear it
+  /clear           forget the conversation
+  /exit            leave";
+
+/// A line typed at the `passenger-rs chat` prompt
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Message(String),
+    Model(Option<String>),
+    System(Option<String>),
+    Clear,
+    Help,
+    Exit,
+    Unknown(String),
+}
+
+impl Input {
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        let Some(command) = line.strip_prefix('/') else {
+            return Some(Input::Message(line.to_string()));
+        };
+
+        let (name, argument) = match command.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim().to_string())),
+            None => (command, None),
+        };
+        Some(match name {
+            "model" => Input::Model(argument),
+            "system" => Input::System(argument),
+            "clear" => Input::Clear,
+            "help" => Input::Help,
+            "exit" | "quit" => Input::Exit,
+            _ => Input::Unknown(name.to_string()),
+        })
+    }
+}
+
+/// A multi-turn conversation with one Copilot model at a time
+#[derive(Debug)]
+pub struct ChatSession {
+    pub model: String,
+    pub system: Option<String>,
+    /// User and assistant messages so far
+    pub messages: Vec<Value>,
+}
+
+impl ChatSession {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            system: None,
+            messages: Vec::new(),
+        }
+    }
+
+    /// The streaming chat completion request for the conversation so far
+    pub fn request(&self) -> Value {
+        let system = self
+            .system
+            .iter()
+            .map(|system| json!({"role": "system", "content": system}));
+        let messages: Vec<Value> = system.chain(self.messages.iter().cloned()).collect();
+        json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": true,
+        })
+    }
+}
+
+/// The user message of a one-shot prompt: piped `context`, if any, followed by `prompt`
+pub fn one_shot_message(prompt: Option<&str>, context: Option<&str>) -> Result<String> {
+    let context = context
+        .map(str::trim_end)
+        .filter(|context| !context.is_empty());
+    match (context, prompt) {
+        (Some(context), Some(prompt)) => Ok(format!("{}\n\n{}", context, prompt)),
+        (Some(text), None) | (None, Some(text)) => Ok(text.to_string()),
+        (None, None) => Err(anyhow!("No prompt given: pass one or pipe it on stdin")),
+    }
+}
+
+/// Send a single prompt, reading piped stdin as context, and stream the reply to stdout
+pub async fn prompt(
+    config: &Config,
+    model: &str,
+    system: Option<&str>,
+    prompt: Option<&str>,
+) -> Result<()> {
+    let context = if std::io::stdin().is_terminal() {
+        None
+    } else {
+        let mut context = String::new();
+        tokio::io::stdin()
+            .read_to_string(&mut context)
+            .await
+            .context("Failed to read stdin")?;
+        Some(context)
+    };
+
+    let mut session = ChatSession::new(model);
+    session.system = system.map(str::to_string);
+    session.messages.push(json!({
+        "role": "user",
+        "content": one_shot_message(prompt, context.as_deref())?,
+    }));
+
+    let copilot = CopilotClient::new(config.clone())?;
+    complete(&copilot, &session.request()).await?;
+    Ok(())
+}
+
+/// Run the REPL on stdin/stdout until `/exit` or end of input
+pub async fn run(config: &Config, model: &str) -> Result<()> {
+    let copilot = CopilotClient::new(config.clone())?;
+    let mut session = ChatSession::new(model);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    println!("Chatting with {}. Type /help for commands.", session.model);
+    loop {
+        print!("> ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            println!();
+            return Ok(());
+        };
+
+        match Input::parse(&line) {
+            None => {}
+            Some(Input::Message(content)) => {
+                session
+                    .messages
+                    .push(json!({"role": "user", "content": content}));
+                match complete(&copilot, &session.request()).await {
+                    Ok(reply) => session
+                        .messages
+                        .push(json!({"role": "assistant", "content": reply})),
+                    Err(e) => {
+                        // Drop the unanswered message so it can be sent again
+                        session.messages.pop();
+                        println!("Error: {:#}", e);
+                    }
+                }
+            }
+            Some(Input::Model(None)) => println!("Model: {}", session.model),
+            Some(Input::Model(Some(model))) => {
+                println!("Switched to {}", model);
+                session.model = model;
+            }
+            Some(Input::System(system)) => {
+                println!(
+                    "{}",
+                    if system.is_some() {
+                        "System prompt set"
+                    } else {
+                        "System prompt cleared"
+                    }
+                );
+                session.system = system;
+            }
+            Some(Input::Clear) => {
+                session.messages.clear();
+                println!("Conversation cleared");
+            }
+            Some(Input::Help) => println!("{}", HELP),
+            Some(Input::Exit) => return Ok(()),
+            Some(Input::Unknown(name)) => println!("Unknown command /{}. {}", name, HELP),
+        }
+    }
+}
+
+/// Stream the reply to `request` to stdout as it arrives, returning its full text
+async fn complete(copilot: &CopilotClient, request: &Value) -> Result<String> {
+    let request: CopilotChatRequest =
+        serde_json::from_value(request.clone()).context("Invalid chat request")?;
+    let mut deltas = copilot.chat_stream(&request).await?;
+
+    let mut reply = String::new();
+    while let Some(delta) = deltas.try_next().await? {
+        if let ChatDelta::Content(content) = delta {
+            print!("{}", content);
+            std::io::stdout().flush()?;
+            reply.push_str(&content);
+        }
+    }
+    println!();
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(Input::parse("  "), None);
+        assert_eq!(
+            Input::parse("Hello there"),
+            Some(Input::Message("Hello there".to_string()))
+        );
+        assert_eq!(Input::parse("/model"), Some(Input::Model(None)));
+        assert_eq!(
+            Input::parse("/model claude-sonnet-4"),
+            Some(Input::Model(Some("claude-sonnet-4".to_string())))
+        );
+        assert_eq!(
+            Input::parse("/system Answer in French."),
+            Some(Input::System(Some("Answer in French.".to_string())))
+        );
+        assert_eq!(Input::parse("/quit"), Some(Input::Exit));
+        assert_eq!(
+            Input::parse("/nope"),
+            Some(Input::Unknown("nope".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_session_request() {
+        let mut session = ChatSession::new("gpt-4o");
+        session.system = Some("Be terse.".to_string());
+        session
+            .messages
+            .push(json!({"role": "user", "content": "Hi"}));
+
+        assert_eq!(
+            session.request(),
+            json!({
+                "model": "gpt-4o",
+                "messages": [
+                    {"role": "system", "content": "Be terse."},
+                    {"role": "user", "content": "Hi"}
+                ],
+                "stream": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_one_shot_message() {
+        assert_eq!(
+            one_shot_message(Some("Summarise this"), Some("fn main() {}\n")).unwrap(),
+            "fn main() {}\n\nSummarise this"
+        );
+        assert_eq!(one_shot_message(Some("Hi"), Some("\n")).unwrap(), "Hi");
+        assert_eq!(
+            one_shot_message(None, Some("Explain ownership")).unwrap(),
+            "Explain ownership"
+        );
+        assert!(one_shot_message(None, None).is_err());
+    }
+}
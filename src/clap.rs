@@ -1,20 +1,32 @@
-use crate::auth;
-use crate::config::Config;
-use crate::login;
-use crate::storage;
-use anyhow::Result;
-use clap::Parser;
-use std::path::Path;
-use tracing::info;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use passenger_rs::auth;
+use passenger_rs::bench::{self, Load, Target};
+use passenger_rs::chat;
+use passenger_rs::config::{Config, DEFAULT_CONFIG};
+use passenger_rs::copilot::account::AccountReport;
+use passenger_rs::eval::{self, Suite};
+use passenger_rs::login;
+use passenger_rs::migration;
+#[cfg(feature = "keys")]
+use passenger_rs::server::keys::KeyStore;
+use passenger_rs::server::mcp;
+use passenger_rs::stdio;
+use passenger_rs::storage;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Configuration file loaded when `--config` is not given, if it exists
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
 
 /// Command-line arguments for passenger-rs
 #[derive(Parser, Debug)]
 #[command(name = "passenger-rs")]
 #[command(author, version = "#VERSION", about, long_about = None)]
 pub struct Args {
-    /// Path to the configuration file
-    #[arg(short, long, default_value = "config.toml")]
-    pub config: String,
+    /// Path to the configuration file; without it, config.toml when present, else built-in defaults
+    #[arg(short, long)]
+    pub config: Option<String>,
 
     /// Perform GitHub OAuth device flow login
     #[arg(long)]
@@ -24,13 +36,137 @@ pub struct Args {
     #[arg(long)]
     pub refresh_token: bool,
 
-    /// Path to the access token file (defaults to ~/.config/passenger-rs/access_token.json)
+    /// Path to the access token file (defaults to access_token.json in the platform config directory)
     #[arg(long)]
     pub access_token_path: Option<String>,
 
-    /// Path to the Copilot token file (defaults to ~/.config/passenger-rs/token.json)
+    /// Path to the Copilot token file (defaults to token.json in the platform config directory)
     #[arg(long)]
     pub copilot_token_path: Option<String>,
+
+    /// Default log level (error, warn, info, debug, trace); overrides `[logging] level`
+    #[arg(long)]
+    pub log_level: Option<tracing::Level>,
+
+    /// Serve chat completions over JSON-RPC on stdin/stdout instead of HTTP
+    #[arg(long)]
+    pub stdio: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands; without one, passenger-rs starts the proxy server
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Check token storage for problems such as group/world-readable token files
+    Doctor,
+    /// Show the Copilot subscription, entitlements and monthly quotas
+    Status,
+    /// Chat with a Copilot model in the terminal, without starting the server
+    Chat {
+        /// Model to chat with; `/model` switches it during the session
+        #[arg(long, default_value = "gpt-4o")]
+        model: String,
+    },
+    /// Send one prompt and print the reply to stdout; piped stdin is sent as context
+    Run {
+        /// Model to send the prompt to
+        #[arg(short, long, default_value = "gpt-4o")]
+        model: String,
+        /// System prompt
+        #[arg(short, long)]
+        system: Option<String>,
+        /// The prompt; optional when stdin is piped
+        prompt: Option<String>,
+    },
+    /// Serve the Model Context Protocol on stdin/stdout, exposing Copilot chat as tools
+    Mcp,
+    /// Run a YAML suite of prompts against models and report pass/fail and latency
+    Eval {
+        /// Path to the eval suite
+        #[arg(long)]
+        suite: PathBuf,
+        /// Model to run the suite against, repeatable; overrides the suite's `models`
+        #[arg(long = "model")]
+        models: Vec<String>,
+    },
+    /// Drive the proxy with synthetic prompts and report latency percentiles, tokens/sec
+    /// and error rates
+    Bench {
+        /// Model to request
+        #[arg(long, default_value = "gpt-4o")]
+        model: String,
+        /// Requests in flight at once
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Requests to send in total
+        #[arg(long, default_value_t = 20)]
+        requests: usize,
+        /// Base URL of a running proxy to drive, instead of one served for the run
+        #[arg(long, conflicts_with = "upstream")]
+        url: Option<String>,
+        /// Send straight to Copilot, bypassing the proxy
+        #[arg(long)]
+        upstream: bool,
+    },
+    /// Manage the client API keys required under `[keys] enabled`
+    #[cfg(feature = "keys")]
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+    /// Check a configuration file, or print the default one
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+/// `passenger-rs config` subcommands, run before the configuration is loaded
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Report unknown keys, bad values, invalid URLs and conflicting options; fails on any
+    Validate {
+        /// Configuration file to check; defaults to `--config`
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Print the default configuration, with every option commented
+    PrintDefault,
+    /// Bring a configuration up to the current version in place, keeping the original as
+    /// `<file>.bak`
+    Migrate {
+        /// Configuration file to migrate; defaults to `--config`
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+}
+
+/// `passenger-rs keys` subcommands
+#[cfg(feature = "keys")]
+#[derive(Subcommand, Debug)]
+pub enum KeysCommand {
+    /// Create a key and print it; it is not stored and cannot be shown again
+    Create {
+        /// Who or what the key is for
+        #[arg(long)]
+        label: String,
+        /// Requests allowed per calendar month; unlimited when omitted
+        #[arg(long)]
+        monthly_quota: Option<u64>,
+        /// Model the key may use, repeatable; any model when omitted
+        #[arg(long = "model")]
+        models: Vec<String>,
+        /// Also create a key pair to sign requests with, printing its private key after
+        /// the key
+        #[arg(long)]
+        signing_key: bool,
+    },
+    /// List keys with their usage this month
+    List,
+    /// Revoke a key, by the id shown by `keys list`
+    Revoke { id: String },
 }
 
 impl Args {
@@ -39,37 +175,136 @@ impl Args {
         Self::parse()
     }
 
-    /// Validate that the config file exists
-    pub fn validate_config_path(&self) -> Result<()> {
-        let config_path = Path::new(&self.config);
+    /// Default log level: `--log-level`, else `warn` for `run` so its output stays clean
+    pub fn log_level(&self) -> Option<tracing::Level> {
+        match self.command {
+            Some(Command::Run { .. }) => self.log_level.or(Some(tracing::Level::WARN)),
+            _ => self.log_level,
+        }
+    }
 
-        if !config_path.exists() {
-            return Err(anyhow::anyhow!(
-                "Configuration file does not exist: {}\n\
-                 Please create a config.toml file or specify a valid path with --config",
-                self.config
-            ));
+    /// The configuration file to load: `--config`, else `config.toml` if there is one
+    pub fn config_path(&self) -> Option<&str> {
+        match &self.config {
+            Some(path) => Some(path),
+            None => Path::new(DEFAULT_CONFIG_PATH)
+                .is_file()
+                .then_some(DEFAULT_CONFIG_PATH),
         }
+    }
 
-        if !config_path.is_file() {
-            return Err(anyhow::anyhow!(
-                "Configuration path is not a file: {}",
-                self.config
-            ));
+    /// Load the configuration file, or the built-in defaults when there is none
+    pub fn load_config(&self) -> Result<Config> {
+        match self.config_path() {
+            Some(path) => {
+                validate_config_path(path)?;
+                Config::from_file(path)
+            }
+            None => Ok(Config::default()),
         }
+    }
 
-        Ok(())
+    /// Run a `config` subcommand, which must not need a loadable configuration.
+    /// Returns Ok(true) if one was run.
+    pub fn execute_config_command(&self) -> Result<bool> {
+        let Some(Command::Config { command }) = &self.command else {
+            return Ok(false);
+        };
+
+        match command {
+            ConfigCommand::Validate { file } => {
+                let path = file.clone().unwrap_or_else(|| {
+                    PathBuf::from(self.config.as_deref().unwrap_or(DEFAULT_CONFIG_PATH))
+                });
+                validate_config(&path)?;
+            }
+            ConfigCommand::PrintDefault => print!("{}", DEFAULT_CONFIG),
+            ConfigCommand::Migrate { file } => {
+                let path = file.clone().unwrap_or_else(|| {
+                    PathBuf::from(self.config.as_deref().unwrap_or(DEFAULT_CONFIG_PATH))
+                });
+                migrate_config(&path)?;
+            }
+        }
+        Ok(true)
     }
 
     /// Execute the appropriate command based on parsed arguments
     /// Returns Ok(true) if a command was executed, Ok(false) if server should start
     pub async fn execute_command(&self, config: &Config) -> Result<bool> {
+        match &self.command {
+            Some(Command::Doctor) => {
+                self.handle_doctor()?;
+                return Ok(true);
+            }
+            Some(Command::Status) => {
+                self.handle_status(config).await?;
+                return Ok(true);
+            }
+            Some(Command::Chat { model }) => {
+                self.verify_token_exists()?;
+                chat::run(config, model).await?;
+                return Ok(true);
+            }
+            Some(Command::Run {
+                model,
+                system,
+                prompt,
+            }) => {
+                self.verify_token_exists()?;
+                chat::prompt(config, model, system.as_deref(), prompt.as_deref()).await?;
+                return Ok(true);
+            }
+            Some(Command::Mcp) => {
+                self.verify_token_exists()?;
+                mcp::serve_stdio(config).await?;
+                return Ok(true);
+            }
+            Some(Command::Eval { suite, models }) => {
+                self.handle_eval(config, suite, models).await?;
+                return Ok(true);
+            }
+            Some(Command::Bench {
+                model,
+                concurrency,
+                requests,
+                url,
+                upstream,
+            }) => {
+                let target = match url {
+                    Some(url) => Target::Url(url.clone()),
+                    None if *upstream => Target::Upstream,
+                    None => Target::Local,
+                };
+                let load = Load {
+                    model: model.clone(),
+                    concurrency: *concurrency,
+                    requests: *requests,
+                };
+                self.handle_bench(config, &target, &load).await?;
+                return Ok(true);
+            }
+            #[cfg(feature = "keys")]
+            Some(Command::Keys { command }) => {
+                self.handle_keys(config, command)?;
+                return Ok(true);
+            }
+            Some(Command::Config { .. }) => return self.execute_config_command(),
+            None => {}
+        }
+
         // Handle login if requested
         if self.login {
             self.handle_login(config).await?;
             return Ok(true);
         }
 
+        if self.stdio {
+            self.verify_token_exists()?;
+            stdio::serve(config).await?;
+            return Ok(true);
+        }
+
         // Handle token refresh if requested
         if self.refresh_token {
             self.handle_refresh_token(config).await?;
@@ -117,13 +352,14 @@ impl Args {
                 info!("Access token found, requesting new Copilot token...");
 
                 // Create HTTP client
-                let client = reqwest::Client::new();
+                let client = config.copilot.build_client()?;
 
                 // Get new Copilot token
                 match auth::get_copilot_token(
                     &client,
                     &config.github.copilot_token_url,
                     &access_token_response.access_token,
+                    &config.copilot.headers,
                 )
                 .await
                 {
@@ -150,6 +386,282 @@ impl Args {
         }
     }
 
+    /// Handle the `doctor` subcommand
+    fn handle_doctor(&self) -> Result<()> {
+        info!("Checking token storage...");
+
+        if let Ok(storage_dir) = storage::get_storage_dir() {
+            info!("Storage directory: {}", storage_dir.display());
+        }
+
+        for path in self.token_paths()?.iter().filter(|path| path.is_file()) {
+            info!("Token file: {}", path.display());
+        }
+
+        if self.warn_insecure_token_files()? {
+            return Err(anyhow::anyhow!("Insecure token storage detected"));
+        }
+
+        info!("✓ Token storage permissions look good");
+        Ok(())
+    }
+
+    /// Handle the `eval` subcommand
+    async fn handle_eval(&self, config: &Config, suite: &Path, models: &[String]) -> Result<()> {
+        self.verify_token_exists()?;
+        let suite = Suite::from_file(suite)?;
+        let models = if models.is_empty() {
+            &suite.models
+        } else {
+            models
+        };
+        if models.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No models to evaluate: list them under `models` in the suite or pass --model"
+            ));
+        }
+
+        info!(
+            "Running {} cases against {}",
+            suite.cases.len(),
+            models.join(", ")
+        );
+        let outcomes = eval::run(config, &suite, models).await?;
+
+        for outcome in &outcomes {
+            let verdict = if outcome.passed() { "PASS" } else { "FAIL" };
+            info!(
+                "{} {} / {} ({} ms)",
+                verdict,
+                outcome.model,
+                outcome.case,
+                outcome.latency.as_millis()
+            );
+            for failure in &outcome.failures {
+                info!("    {}", failure);
+            }
+        }
+
+        let summaries = eval::summarise(&outcomes);
+        for summary in &summaries {
+            info!(
+                "{}: {}/{} passed, latency mean {} ms, median {} ms, max {} ms",
+                summary.model,
+                summary.passed,
+                summary.total,
+                summary.mean.as_millis(),
+                summary.median.as_millis(),
+                summary.max.as_millis()
+            );
+        }
+
+        let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+        if failed > 0 {
+            return Err(anyhow::anyhow!(
+                "{} of {} eval runs failed",
+                failed,
+                outcomes.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Handle the `bench` subcommand
+    async fn handle_bench(&self, config: &Config, target: &Target, load: &Load) -> Result<()> {
+        if !matches!(target, Target::Url(_)) {
+            self.verify_token_exists()?;
+        }
+        info!(
+            "Sending {} requests to {}, {} at a time",
+            load.requests, load.model, load.concurrency
+        );
+        let report = bench::run(config, target, load).await?;
+
+        info!(
+            "{} requests, {} failed ({:.1}%)",
+            report.requests,
+            report.errors,
+            report.error_rate() * 100.0
+        );
+        info!(
+            "Latency p50 {} ms, p90 {} ms, p99 {} ms, max {} ms",
+            report.p50.as_millis(),
+            report.p90.as_millis(),
+            report.p99.as_millis(),
+            report.max.as_millis()
+        );
+        info!(
+            "Throughput {:.2} requests/s, {:.1} completion tokens/s",
+            report.requests_per_second, report.tokens_per_second
+        );
+        for (error, count) in &report.error_counts {
+            info!("    {} x {}", count, error);
+        }
+        Ok(())
+    }
+
+    /// Handle the `status` subcommand
+    /// Handle the keys subcommands, on the `[keys]` database whether or not keys are enabled
+    #[cfg(feature = "keys")]
+    fn handle_keys(&self, config: &Config, command: &KeysCommand) -> Result<()> {
+        let store = KeyStore::open(&config.keys)?;
+        match command {
+            KeysCommand::Create {
+                label,
+                monthly_quota,
+                models,
+                signing_key,
+            } => {
+                let (key, secret) = store.create(label, *monthly_quota, models)?;
+                info!("Created key {} ({})", key.id, key.label);
+                if !config.keys.enabled {
+                    warn!("Keys are only checked with `[keys] enabled = true`");
+                }
+                println!("{}", secret);
+                if *signing_key {
+                    if !config.keys.signed_requests {
+                        warn!("Signatures are only checked with `[keys] signed_requests = true`");
+                    }
+                    print!("{}", store.create_signing_key(&key.id)?);
+                }
+            }
+            KeysCommand::List => {
+                for key in store.list()? {
+                    info!(
+                        "{} {}: {} requests this month{}, models: {}{}",
+                        key.id,
+                        key.label,
+                        key.requests_this_month,
+                        key.monthly_quota
+                            .map(|quota| format!(" of {}", quota))
+                            .unwrap_or_default(),
+                        if key.models.is_empty() {
+                            "any".to_string()
+                        } else {
+                            key.models.join(", ")
+                        },
+                        if key.revoked { " (revoked)" } else { "" },
+                    );
+                }
+            }
+            KeysCommand::Revoke { id } => {
+                if !store.revoke(id)? {
+                    return Err(anyhow::anyhow!("No key with id {}", id));
+                }
+                info!("Revoked key {}", id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_status(&self, config: &Config) -> Result<()> {
+        let copilot_token_path = self.copilot_token_path.as_deref().map(Path::new);
+        let token = storage::load_token_from_path(copilot_token_path)
+            .context("No Copilot token found; run with --login first")?;
+
+        let buffer_secs = config.copilot.token.expiry_buffer_secs;
+        if storage::is_token_expired(&token, buffer_secs) {
+            info!("Copilot token: expired (refreshed on next use)");
+        } else if storage::is_token_due_for_refresh(&token, buffer_secs) {
+            info!("Copilot token: due for refresh (refreshed on next use)");
+        } else {
+            info!("Copilot token: valid until {}", token.expires_at);
+        }
+
+        let entitlements = &token.entitlements;
+        if let Some(sku) = &entitlements.sku {
+            info!("SKU: {}", sku);
+        }
+        if let Some(chat_enabled) = entitlements.chat_enabled {
+            info!("Chat enabled: {}", chat_enabled);
+        }
+        if let Some(limited) = &entitlements.limited_user_quotas {
+            let mut limited: Vec<_> = limited.iter().collect();
+            limited.sort();
+            for (name, remaining) in limited {
+                info!("Copilot Free {} remaining: {}", name, remaining);
+            }
+        }
+
+        let access_token_path = self.access_token_path.as_deref().map(Path::new);
+        let user = match storage::load_access_token_from_path(access_token_path)? {
+            Some(access_token) => {
+                let client = config.copilot.build_client()?;
+                auth::get_copilot_user(
+                    &client,
+                    &config.github.copilot_user_url,
+                    &access_token.access_token,
+                    &config.copilot.headers,
+                )
+                .await
+                .inspect_err(|e| warn!("Could not fetch Copilot quotas: {}", e))
+                .ok()
+            }
+            None => {
+                warn!("No access token found on disk, cannot report monthly quotas");
+                None
+            }
+        };
+
+        let report = AccountReport::new(token.entitlements, user);
+        if let Some(plan) = &report.plan {
+            info!("Plan: {}", plan);
+        }
+        for (name, quota) in &report.quotas {
+            if quota.unlimited {
+                info!("Quota {}: unlimited", name);
+            } else {
+                info!(
+                    "Quota {}: {} of {} remaining ({:.0}%)",
+                    name, quota.remaining, quota.entitlement, quota.percent_remaining
+                );
+            }
+        }
+        if let Some(reset) = &report.quota_reset_date {
+            info!("Quotas reset on {}", reset);
+        }
+        for warning in &report.warnings {
+            warn!("⚠ {}", warning);
+        }
+
+        Ok(())
+    }
+
+    /// Token files (custom or default paths) and the storage directory
+    fn token_paths(&self) -> Result<Vec<PathBuf>> {
+        let access_token_path = match self.access_token_path {
+            Some(ref path) => PathBuf::from(path),
+            None => storage::get_access_token_path()?,
+        };
+        let copilot_token_path = match self.copilot_token_path {
+            Some(ref path) => PathBuf::from(path),
+            None => storage::get_token_path()?,
+        };
+
+        Ok(vec![
+            storage::get_storage_dir()?,
+            access_token_path,
+            copilot_token_path,
+        ])
+    }
+
+    /// Warn about token files other users can read. Returns true if any were found.
+    pub fn warn_insecure_token_files(&self) -> Result<bool> {
+        let insecure = storage::find_insecure_paths(&self.token_paths()?);
+
+        for entry in &insecure {
+            warn!(
+                "⚠ {} is accessible by other users (mode {:o}); tokens grant access to your Copilot account. Fix with: chmod {} {}",
+                entry.path.display(),
+                entry.mode,
+                if entry.path.is_dir() { "700" } else { "600" },
+                entry.path.display()
+            );
+        }
+
+        Ok(!insecure.is_empty())
+    }
+
     /// Verify that required token exists before starting server
     pub fn verify_token_exists(&self) -> Result<()> {
         // Check if we have a valid token (from custom or default path)
@@ -177,6 +689,105 @@ impl Args {
     }
 }
 
+/// Validate that the config file exists
+fn validate_config_path(path: &str) -> Result<()> {
+    let config_path = Path::new(path);
+
+    if !config_path.exists() {
+        return Err(anyhow::anyhow!(
+            "Configuration file does not exist: {}\n\
+             Please create it or leave out --config to use the built-in defaults",
+            path
+        ));
+    }
+
+    if !config_path.is_file() {
+        return Err(anyhow::anyhow!(
+            "Configuration path is not a file: {}",
+            path
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check the configuration at `path`, printing every problem found
+fn validate_config(path: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(path)
+        .context(format!("Failed to read config file: {}", path.display()))?;
+
+    let migrated = migration::migrate(&contents);
+    if let Ok(Some(_)) = migrated {
+        println!(
+            "{} predates config version {}; `config migrate` updates it",
+            path.display(),
+            migration::CONFIG_VERSION
+        );
+    }
+    let parsed = match migrated {
+        Ok(Some(migrated)) => Config::parse(&migrated),
+        Ok(None) => Config::parse(&contents),
+        Err(e) => Err(e),
+    };
+    let config = match parsed {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return Err(anyhow::anyhow!("Invalid configuration: {}", path.display()));
+        }
+    };
+
+    let problems = config.problems();
+    if problems.is_empty() {
+        println!("✓ {} is valid", path.display());
+        return Ok(());
+    }
+
+    for problem in &problems {
+        eprintln!("{}: {}", path.display(), problem);
+    }
+    Err(anyhow::anyhow!(
+        "{} problem(s) in {}",
+        problems.len(),
+        path.display()
+    ))
+}
+
+/// Migrate the configuration at `path` in place, after copying it to `<path>.bak`
+fn migrate_config(path: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(path)
+        .context(format!("Failed to read config file: {}", path.display()))?;
+
+    let Some(migrated) = migration::migrate(&contents)? else {
+        println!(
+            "✓ {} is already at config version {}",
+            path.display(),
+            migration::CONFIG_VERSION
+        );
+        return Ok(());
+    };
+    Config::parse(&migrated)
+        .context("The migrated configuration is invalid; nothing was written")?;
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    std::fs::copy(path, &backup).context(format!(
+        "Failed to back up config file to {}",
+        backup.display()
+    ))?;
+    std::fs::write(path, migrated)
+        .context(format!("Failed to write config file: {}", path.display()))?;
+
+    println!(
+        "✓ Migrated {} to config version {}; the original is at {}",
+        path.display(),
+        migration::CONFIG_VERSION,
+        backup.display()
+    );
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -213,4 +824,129 @@ mod tests {
         let args = args.unwrap();
         assert!(args.login);
     }
+
+    #[test]
+    fn test_doctor_subcommand() {
+        let args = Args::try_parse_from(vec!["passenger-rs", "doctor"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Doctor)));
+
+        let args = Args::try_parse_from(vec!["passenger-rs"]).unwrap();
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn test_status_subcommand() {
+        let args = Args::try_parse_from(vec!["passenger-rs", "status"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Status)));
+    }
+
+    #[cfg(feature = "keys")]
+    #[test]
+    fn test_keys_subcommand() {
+        let args = Args::try_parse_from(vec![
+            "passenger-rs",
+            "keys",
+            "create",
+            "--label",
+            "ci",
+            "--monthly-quota",
+            "1000",
+            "--model",
+            "gpt-4o",
+            "--model",
+            "gpt-4.1",
+        ])
+        .unwrap();
+        match args.command {
+            Some(Command::Keys {
+                command:
+                    KeysCommand::Create {
+                        label,
+                        monthly_quota,
+                        models,
+                        signing_key,
+                    },
+            }) => {
+                assert_eq!(label, "ci");
+                assert_eq!(monthly_quota, Some(1000));
+                assert_eq!(models, ["gpt-4o", "gpt-4.1"]);
+                assert!(!signing_key);
+            }
+            command => panic!("unexpected command {:?}", command),
+        }
+
+        let args =
+            Args::try_parse_from(vec!["passenger-rs", "keys", "revoke", "1a2b3c4d"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Keys {
+                command: KeysCommand::Revoke { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn test_config_subcommand() {
+        let args = Args::try_parse_from(vec![
+            "passenger-rs",
+            "config",
+            "validate",
+            "--file",
+            "other.toml",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Config {
+                command: ConfigCommand::Validate { file: Some(ref file) }
+            }) if file == Path::new("other.toml")
+        ));
+
+        let args = Args::try_parse_from(vec!["passenger-rs", "config", "print-default"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Config {
+                command: ConfigCommand::PrintDefault
+            })
+        ));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        assert!(validate_config(Path::new("config.toml")).is_ok());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            DEFAULT_CONFIG.replace(
+                "api_base_url = \"https://api.githubcopilot.com\"",
+                "api_base_url = \"api.githubcopilot.com\"",
+            ),
+        )
+        .unwrap();
+        let error = validate_config(&path).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("1 problem(s) in {}", path.display())
+        );
+    }
+
+    #[test]
+    fn test_migrate_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let old = DEFAULT_CONFIG.replace("version = 1\n", "");
+        std::fs::write(&path, &old).unwrap();
+
+        migrate_config(&path).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("config.toml.bak")).unwrap(),
+            old
+        );
+        let migrated = std::fs::read_to_string(&path).unwrap();
+        assert!(migrated.starts_with("version = 1\n"));
+        assert_eq!(Config::parse(&migrated).unwrap().version, 1);
+    }
 }
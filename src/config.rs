@@ -1,32 +1,1619 @@
+use crate::migration;
+use crate::openai::completion::models::ResponseFormat;
+use crate::openai::responses::models::prompt_response::ReasoningEffort;
 use anyhow::{Context, Result};
+use ipnet::IpNet;
+use reqwest::{Client, NoProxy, Proxy, Url};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::warn;
+
+/// The shipped `config.toml`, with every option commented
+pub const DEFAULT_CONFIG: &str = include_str!("../config.toml");
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct Config {
+    /// Schema version, brought up to date on load and by `passenger-rs config migrate`
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
     pub github: GithubConfig,
+    #[serde(default)]
     pub copilot: CopilotConfig,
+    #[serde(default)]
     pub server: ServerConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub echo: EchoConfig,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub endpoints: EndpointsConfig,
+    #[serde(default)]
+    pub azure: AzureConfig,
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+    /// Named presets under `[presets.<name>]`, selected with model `preset:<name>`
+    #[serde(default)]
+    pub presets: HashMap<String, PresetConfig>,
+    /// Model ids standing for a model with pinned parameters, under `[virtual_models."<id>"]`
+    #[serde(default)]
+    pub virtual_models: HashMap<String, VirtualModelConfig>,
+    /// Configurations served under `/<name>/...`, under `[profiles.<name>]`
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    #[serde(default)]
+    pub files: FilesConfig,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    #[serde(default)]
+    pub models: ModelsConfig,
+    #[serde(default)]
+    pub premium: PremiumConfig,
+    #[serde(default)]
+    pub users: UsersConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub keys: KeysConfig,
+    #[serde(default)]
+    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub mcp: McpConfig,
+    #[serde(default)]
+    pub web_search: WebSearchConfig,
+    /// `/v1/audio` backend, under `[audio]`
+    #[serde(default)]
+    pub audio: BackendConfig,
+    /// `/v1/images` backend, under `[images]`
+    #[serde(default)]
+    pub images: BackendConfig,
+}
+
+/// Built-in defaults, used when there is no `config.toml`; every key a file sets overrides them
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: migration::CONFIG_VERSION,
+            github: Default::default(),
+            copilot: Default::default(),
+            server: Default::default(),
+            logging: Default::default(),
+            debug: Default::default(),
+            echo: Default::default(),
+            access_log: Default::default(),
+            admin: Default::default(),
+            endpoints: Default::default(),
+            azure: Default::default(),
+            ollama: Default::default(),
+            presets: Default::default(),
+            virtual_models: Default::default(),
+            profiles: Default::default(),
+            files: Default::default(),
+            moderation: Default::default(),
+            models: Default::default(),
+            premium: Default::default(),
+            users: Default::default(),
+            history: Default::default(),
+            keys: Default::default(),
+            oidc: Default::default(),
+            mcp: Default::default(),
+            web_search: Default::default(),
+            audio: Default::default(),
+            images: Default::default(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct GithubConfig {
+    #[serde(default = "default_device_code_url")]
     pub device_code_url: String,
+    #[serde(default = "default_oauth_token_url")]
     pub oauth_token_url: String,
+    #[serde(default = "default_copilot_token_url")]
     pub copilot_token_url: String,
+    #[serde(default = "default_copilot_models_url")]
     pub copilot_models_url: String,
+    /// Copilot subscription and monthly quotas, reported by `/v1/account` and `status`
+    #[serde(default = "default_copilot_user_url")]
+    pub copilot_user_url: String,
+    #[serde(default = "default_client_id")]
     pub client_id: String,
 }
 
+impl Default for GithubConfig {
+    fn default() -> Self {
+        Self {
+            device_code_url: default_device_code_url(),
+            oauth_token_url: default_oauth_token_url(),
+            copilot_token_url: default_copilot_token_url(),
+            copilot_models_url: default_copilot_models_url(),
+            copilot_user_url: default_copilot_user_url(),
+            client_id: default_client_id(),
+        }
+    }
+}
+
+fn default_device_code_url() -> String {
+    "https://github.com/login/device/code".to_string()
+}
+
+fn default_oauth_token_url() -> String {
+    "https://github.com/login/oauth/access_token".to_string()
+}
+
+fn default_copilot_token_url() -> String {
+    "https://api.github.com/copilot_internal/v2/token".to_string()
+}
+
+fn default_copilot_models_url() -> String {
+    "https://models.dev/api.json".to_string()
+}
+
+fn default_copilot_user_url() -> String {
+    "https://api.github.com/copilot_internal/user".to_string()
+}
+
+/// GitHub Copilot's public OAuth client id, the same for everyone
+fn default_client_id() -> String {
+    "Iv1.b507a08c87ecfe98".to_string()
+}
+
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct CopilotConfig {
+    #[serde(default = "default_api_base_url")]
     pub api_base_url: String,
+    #[serde(default)]
+    pub headers: CopilotHeadersConfig,
+    /// Identification headers for particular clients, in place of `headers`
+    #[serde(default)]
+    pub clients: Vec<CopilotClientConfig>,
+    /// Headers of Copilot's replies passed on to clients, e.g. `x-request-id`
+    #[serde(default)]
+    pub response_headers: Vec<String>,
+    #[serde(default)]
+    pub timeouts: CopilotTimeoutsConfig,
+    #[serde(default)]
+    pub token: CopilotTokenConfig,
+    #[serde(default)]
+    pub proxy: CopilotProxyConfig,
+    #[serde(default)]
+    pub tls: CopilotTlsConfig,
+    #[serde(default)]
+    pub tool_calls: CopilotToolCallsConfig,
+    #[serde(default)]
+    pub context: CopilotContextConfig,
+    #[serde(default)]
+    pub fan_out: CopilotFanOutConfig,
+    #[serde(default)]
+    pub racing: CopilotRacingConfig,
+    #[serde(default)]
+    pub structured_outputs: CopilotStructuredOutputsConfig,
+    #[serde(default)]
+    pub reasoning: CopilotReasoningConfig,
+    #[serde(default)]
+    pub empty_choices: CopilotEmptyChoicesConfig,
+    #[serde(default)]
+    pub content_filter: CopilotContentFilterConfig,
+    #[serde(default)]
+    pub deduplication: CopilotDeduplicationConfig,
+    #[serde(default)]
+    pub pacing: CopilotPacingConfig,
+    #[serde(default)]
+    pub fallbacks: CopilotFallbacksConfig,
+    #[serde(default)]
+    pub completions: CopilotCompletionsConfig,
+}
+
+impl Default for CopilotConfig {
+    fn default() -> Self {
+        Self {
+            api_base_url: default_api_base_url(),
+            headers: Default::default(),
+            clients: Default::default(),
+            response_headers: Default::default(),
+            timeouts: Default::default(),
+            token: Default::default(),
+            proxy: Default::default(),
+            tls: Default::default(),
+            tool_calls: Default::default(),
+            context: Default::default(),
+            fan_out: Default::default(),
+            racing: Default::default(),
+            structured_outputs: Default::default(),
+            reasoning: Default::default(),
+            empty_choices: Default::default(),
+            content_filter: Default::default(),
+            deduplication: Default::default(),
+            pacing: Default::default(),
+            fallbacks: Default::default(),
+            completions: Default::default(),
+        }
+    }
+}
+
+fn default_api_base_url() -> String {
+    "https://api.githubcopilot.com".to_string()
+}
+
+impl CopilotConfig {
+    /// Build the HTTP client used for both GitHub auth and Copilot chat traffic
+    pub fn build_client(&self) -> Result<Client> {
+        let mut builder = Client::builder().connect_timeout(self.timeouts.connect());
+        builder = crate::tls::configure(builder, &self.tls)?;
+
+        if let Some(proxy) = self.proxy.proxy()? {
+            builder = builder.proxy(proxy);
+        }
+
+        builder.build().context("Failed to build HTTP client")
+    }
+}
+
+/// Identification headers sent to GitHub and the Copilot API.
+///
+/// GitHub periodically changes which editor identifiers it accepts, so these are
+/// configurable under `[copilot.headers]`. Any entry in `extra` is sent as-is with
+/// every request forwarded to Copilot.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotHeadersConfig {
+    #[serde(default = "default_integration_id")]
+    pub integration_id: String,
+    #[serde(default = "default_editor_version")]
+    pub editor_version: String,
+    #[serde(default = "default_editor_plugin_version")]
+    pub editor_plugin_version: String,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    #[serde(default)]
+    pub extra: HashMap<String, String>,
+}
+
+impl Default for CopilotHeadersConfig {
+    fn default() -> Self {
+        Self {
+            integration_id: default_integration_id(),
+            editor_version: default_editor_version(),
+            editor_plugin_version: default_editor_plugin_version(),
+            user_agent: default_user_agent(),
+            extra: HashMap::new(),
+        }
+    }
+}
+
+/// Identification headers for some clients, under `[[copilot.clients]]`. Requests whose
+/// `User-Agent` contains `user_agent`, or whose API key has the fingerprint `key_id`, are
+/// forwarded with the headers set here in place of those of `[copilot.headers]`; the first
+/// matching entry wins.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotClientConfig {
+    pub user_agent: Option<String>,
+    pub key_id: Option<String>,
+    pub integration_id: Option<String>,
+    pub editor_version: Option<String>,
+    pub editor_plugin_version: Option<String>,
+}
+
+impl CopilotClientConfig {
+    /// `headers` with this client's values in place
+    pub fn apply(&self, headers: &CopilotHeadersConfig) -> CopilotHeadersConfig {
+        let mut headers = headers.clone();
+        if let Some(integration_id) = &self.integration_id {
+            headers.integration_id = integration_id.clone();
+        }
+        if let Some(editor_version) = &self.editor_version {
+            headers.editor_version = editor_version.clone();
+        }
+        if let Some(editor_plugin_version) = &self.editor_plugin_version {
+            headers.editor_plugin_version = editor_plugin_version.clone();
+        }
+        headers
+    }
+}
+
+fn default_integration_id() -> String {
+    "vscode-chat".to_string()
+}
+
+fn default_editor_version() -> String {
+    "Neovim/0.6.1".to_string()
+}
+
+fn default_editor_plugin_version() -> String {
+    "copilot.vim/1.16.0".to_string()
+}
+
+fn default_user_agent() -> String {
+    "GithubCopilot/1.155.0".to_string()
+}
+
+/// Outbound proxy for GitHub and Copilot traffic, under `[copilot.proxy]`.
+///
+/// Without a `url`, the standard `HTTPS_PROXY`, `HTTP_PROXY`, `ALL_PROXY` and
+/// `NO_PROXY` environment variables are honoured.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotProxyConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` proxy URL
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Comma-separated hosts that bypass the proxy, in `NO_PROXY` syntax
+    pub no_proxy: Option<String>,
+}
+
+impl CopilotProxyConfig {
+    /// The explicitly configured proxy, if any
+    pub fn proxy(&self) -> Result<Option<Proxy>> {
+        let Some(ref url) = self.url else {
+            return Ok(None);
+        };
+
+        let mut url = Url::parse(url).context(format!("Invalid proxy URL: {}", url))?;
+
+        // Credentials go in the URL so they apply to SOCKS5 as well as HTTP proxies
+        if let Some(ref username) = self.username {
+            url.set_username(username)
+                .map_err(|_| anyhow::anyhow!("Proxy URL cannot carry credentials: {}", url))?;
+            url.set_password(self.password.as_deref())
+                .map_err(|_| anyhow::anyhow!("Proxy URL cannot carry credentials: {}", url))?;
+        }
+
+        let proxy = Proxy::all(url)
+            .context("Invalid proxy configuration")?
+            .no_proxy(self.no_proxy.as_deref().and_then(NoProxy::from_string));
+
+        Ok(Some(proxy))
+    }
+}
+
+/// TLS trust settings for GitHub and Copilot traffic, under `[copilot.tls]`.
+///
+/// `ca_certificates` are PEM files trusted in addition to the platform roots (e.g. a
+/// corporate MITM proxy CA). `pins` maps a host to `sha256/<base64>` SubjectPublicKeyInfo
+/// pins; connections to that host fail unless a certificate in the chain matches one.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotTlsConfig {
+    #[serde(default)]
+    pub ca_certificates: Vec<String>,
+    #[serde(default)]
+    pub pins: HashMap<String, Vec<String>>,
+}
+
+/// Copilot token refresh, under `[copilot.token]`. A token is refreshed once it is within
+/// `expiry_buffer_secs` of expiring, or once the `refresh_in` Copilot gave with it has passed.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotTokenConfig {
+    #[serde(default = "default_token_expiry_buffer")]
+    pub expiry_buffer_secs: u64,
+}
+
+impl Default for CopilotTokenConfig {
+    fn default() -> Self {
+        Self {
+            expiry_buffer_secs: default_token_expiry_buffer(),
+        }
+    }
+}
+
+fn default_token_expiry_buffer() -> u64 {
+    60
+}
+
+/// Timeouts for upstream Copilot calls, in seconds, under `[copilot.timeouts]`.
+///
+/// `first_byte` bounds the wait for response headers and `total` the whole exchange,
+/// body included. Streaming responses have their own, longer, `stream_*` limits.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotTimeoutsConfig {
+    #[serde(default = "default_connect_timeout")]
+    pub connect_secs: u64,
+    #[serde(default = "default_first_byte_timeout")]
+    pub first_byte_secs: u64,
+    #[serde(default = "default_total_timeout")]
+    pub total_secs: u64,
+    #[serde(default = "default_first_byte_timeout")]
+    pub stream_first_byte_secs: u64,
+    #[serde(default = "default_stream_total_timeout")]
+    pub stream_total_secs: u64,
+}
+
+impl CopilotTimeoutsConfig {
+    pub fn connect(&self) -> Duration {
+        Duration::from_secs(self.connect_secs)
+    }
+
+    pub fn first_byte(&self, stream: bool) -> Duration {
+        Duration::from_secs(if stream {
+            self.stream_first_byte_secs
+        } else {
+            self.first_byte_secs
+        })
+    }
+
+    pub fn total(&self, stream: bool) -> Duration {
+        Duration::from_secs(if stream {
+            self.stream_total_secs
+        } else {
+            self.total_secs
+        })
+    }
+}
+
+impl Default for CopilotTimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            connect_secs: default_connect_timeout(),
+            first_byte_secs: default_first_byte_timeout(),
+            total_secs: default_total_timeout(),
+            stream_first_byte_secs: default_first_byte_timeout(),
+            stream_total_secs: default_stream_total_timeout(),
+        }
+    }
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+fn default_first_byte_timeout() -> u64 {
+    60
+}
+
+fn default_total_timeout() -> u64 {
+    300
+}
+
+fn default_stream_total_timeout() -> u64 {
+    1800
+}
+
+/// Post-processing of tool calls in non-streaming responses, under `[copilot.tool_calls]`.
+///
+/// `repair` rewrites malformed `arguments` into valid JSON where it can; `validate`
+/// checks them against the tool's parameter schema from the request. Problems are
+/// logged and reported in the `X-Passenger-Tool-Calls` response header.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotToolCallsConfig {
+    #[serde(default)]
+    pub repair: bool,
+    #[serde(default)]
+    pub validate: bool,
+}
+
+/// Context-window management under `[copilot.context]`.
+///
+/// When enabled, prompts estimated to exceed the model's context limit (from the
+/// Copilot models endpoint) lose their oldest non-system messages instead of being
+/// rejected upstream. `reserve_tokens` is kept free for the reply unless the request
+/// sets `max_tokens`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotContextConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub strategy: ContextStrategy,
+    #[serde(default = "default_reserve_tokens")]
+    pub reserve_tokens: u64,
+}
+
+impl Default for CopilotContextConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strategy: ContextStrategy::default(),
+            reserve_tokens: default_reserve_tokens(),
+        }
+    }
+}
+
+/// What happens to the messages that no longer fit
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextStrategy {
+    /// Drop them
+    #[default]
+    Truncate,
+    /// Replace them with a model-written summary; falls back to dropping them
+    Summarize,
+}
+
+fn default_reserve_tokens() -> u64 {
+    4096
+}
+
+/// Parallel generation for `n` and `best_of` chat completion requests, under
+/// `[copilot.fan_out]`. Requests asking for more than `max_requests` candidates are
+/// rejected. `ranking` orders the `best_of` candidates, of which the first `n` are
+/// returned; `judge` asks `judge_model` (the request's model by default) and falls
+/// back to `longest` if that fails.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotFanOutConfig {
+    #[serde(default = "default_fan_out_max_requests")]
+    pub max_requests: u32,
+    #[serde(default)]
+    pub ranking: FanOutRanking,
+    pub judge_model: Option<String>,
+}
+
+impl Default for CopilotFanOutConfig {
+    fn default() -> Self {
+        Self {
+            max_requests: default_fan_out_max_requests(),
+            ranking: FanOutRanking::default(),
+            judge_model: None,
+        }
+    }
+}
+
+/// How the best of several candidate answers is chosen
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FanOutRanking {
+    /// The candidate with the most content
+    #[default]
+    Longest,
+    /// A model call comparing the candidates
+    Judge,
+}
+
+fn default_fan_out_max_requests() -> u32 {
+    4
+}
+
+/// Speculative racing under `[copilot.racing]`: requests for `strong_model` are also
+/// sent to `fast_model`, and `policy` decides which answer the client gets. An answer
+/// arrives with its first streamed chunk, or its whole body when not streaming.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotRacingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub fast_model: Option<String>,
+    pub strong_model: Option<String>,
+    #[serde(default)]
+    pub policy: RacePolicy,
+    /// `fastest`: how long a non-streaming fast answer waits for the strong one
+    #[serde(default = "default_upgrade_grace_ms")]
+    pub upgrade_grace_ms: u64,
+    /// `strong_unless_timeout`: how long the strong model gets before the fast answer is used
+    #[serde(default = "default_strong_timeout_ms")]
+    pub strong_timeout_ms: u64,
+}
+
+impl Default for CopilotRacingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fast_model: None,
+            strong_model: None,
+            policy: RacePolicy::default(),
+            upgrade_grace_ms: default_upgrade_grace_ms(),
+            strong_timeout_ms: default_strong_timeout_ms(),
+        }
+    }
+}
+
+impl CopilotRacingConfig {
+    pub fn upgrade_grace(&self) -> Duration {
+        Duration::from_millis(self.upgrade_grace_ms)
+    }
+
+    pub fn strong_timeout(&self) -> Duration {
+        Duration::from_millis(self.strong_timeout_ms)
+    }
+
+    /// The fast model to race `model` against, if racing applies to it
+    pub fn fast_model_for(&self, model: &str) -> Option<&str> {
+        match (&self.fast_model, &self.strong_model) {
+            (Some(fast), Some(strong)) if self.enabled && strong == model => Some(fast),
+            _ => None,
+        }
+    }
+}
+
+/// Which of the two raced answers is returned
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RacePolicy {
+    /// The first answer, upgraded to the strong one if it follows within the grace period
+    #[default]
+    Fastest,
+    /// The strong answer, unless it misses `strong_timeout_ms` or fails. Both requests are
+    /// sent at once, so a fast answer is usually ready by then
+    StrongUnlessTimeout,
+}
+
+fn default_upgrade_grace_ms() -> u64 {
+    500
+}
+
+fn default_strong_timeout_ms() -> u64 {
+    3000
+}
+
+/// Server-side enforcement of `response_format` JSON schemas with `strict: true`, under
+/// `[copilot.structured_outputs]`. Non-streaming replies that do not match the schema are
+/// sent back to the model with the validation error, up to `max_retries` times; with 0,
+/// they are only reported.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotStructuredOutputsConfig {
+    #[serde(default = "default_schema_max_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for CopilotStructuredOutputsConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_schema_max_retries(),
+        }
+    }
+}
+
+fn default_schema_max_retries() -> u32 {
+    2
+}
+
+/// Recovery from Copilot's intermittent non-streaming replies without any `choices`, under
+/// `[copilot.empty_choices]`. They are retried up to `retries` times, with every `tool`
+/// message repeated as a `user` message when `duplicate_tool_messages` is set, before
+/// failing with `502 Bad Gateway`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotEmptyChoicesConfig {
+    #[serde(default = "default_empty_choices_retries")]
+    pub retries: u32,
+    #[serde(default)]
+    pub duplicate_tool_messages: bool,
+}
+
+impl Default for CopilotEmptyChoicesConfig {
+    fn default() -> Self {
+        Self {
+            retries: default_empty_choices_retries(),
+            duplicate_tool_messages: false,
+        }
+    }
+}
+
+fn default_empty_choices_retries() -> u32 {
+    2
+}
+
+/// Copilot's content filtering, under `[copilot.content_filter]`. Its
+/// `content_filter_results` and `prompt_filter_results` annotations are passed on to
+/// OpenAI clients unless `annotations` is off. With `retry_sanitized`, a non-streaming
+/// request Copilot filtered is sent once more with a sanitized prompt before the filtered
+/// reply is returned.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotContentFilterConfig {
+    #[serde(default = "default_content_filter_annotations")]
+    pub annotations: bool,
+    #[serde(default)]
+    pub retry_sanitized: bool,
+}
+
+impl Default for CopilotContentFilterConfig {
+    fn default() -> Self {
+        Self {
+            annotations: default_content_filter_annotations(),
+            retry_sanitized: false,
+        }
+    }
+}
+
+fn default_content_filter_annotations() -> bool {
+    true
+}
+
+/// Coalescing of identical concurrent requests, under `[copilot.deduplication]`. With
+/// `enabled`, a non-streaming request with `temperature = 0` that matches one already in
+/// flight waits for that request's reply instead of calling Copilot again.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotDeduplicationConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Pacing of requests to Copilot, under `[copilot.pacing]`, to stay under its burst limits.
+/// With `requests_per_second`, requests leave at most at that average rate, `burst` of them
+/// at once; the rest queue, and are refused with `429` rather than queue longer than
+/// `max_wait_secs`. Responses report their time in the queue in `x-passenger-queue-wait-ms`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotPacingConfig {
+    pub requests_per_second: Option<f64>,
+    #[serde(default = "default_pacing_burst")]
+    pub burst: u32,
+    #[serde(default = "default_pacing_max_wait")]
+    pub max_wait_secs: u64,
+}
+
+impl Default for CopilotPacingConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: None,
+            burst: default_pacing_burst(),
+            max_wait_secs: default_pacing_max_wait(),
+        }
+    }
+}
+
+fn default_pacing_burst() -> u32 {
+    1
+}
+
+fn default_pacing_max_wait() -> u64 {
+    30
+}
+
+/// Models to fall back on, under `[copilot.fallbacks]`. When Copilot answers a request for a
+/// model of `chains` with `429`, `503` or a model-unavailable error, the request goes to the
+/// next model of its chain, in order. A model that failed so is skipped for `cooldown_secs`,
+/// unless every model of the chain is.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotFallbacksConfig {
+    #[serde(default)]
+    pub chains: HashMap<String, Vec<String>>,
+    #[serde(default = "default_fallback_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+impl Default for CopilotFallbacksConfig {
+    fn default() -> Self {
+        Self {
+            chains: HashMap::new(),
+            cooldown_secs: default_fallback_cooldown(),
+        }
+    }
+}
+
+fn default_fallback_cooldown() -> u64 {
+    60
+}
+
+/// Copilot's code completion (fill-in-the-middle) API behind `/v1/completions` and raw
+/// `/api/generate`, under `[copilot.completions]`. Every request goes to `engine`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotCompletionsConfig {
+    #[serde(default = "default_completions_base_url")]
+    pub base_url: String,
+    #[serde(default = "default_completions_engine")]
+    pub engine: String,
+}
+
+impl CopilotCompletionsConfig {
+    pub fn url(&self) -> String {
+        format!(
+            "{}/v1/engines/{}/completions",
+            self.base_url.trim_end_matches('/'),
+            self.engine
+        )
+    }
+}
+
+impl Default for CopilotCompletionsConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_completions_base_url(),
+            engine: default_completions_engine(),
+        }
+    }
+}
+
+fn default_completions_base_url() -> String {
+    "https://proxy.individual.githubcopilot.com".to_string()
+}
+
+fn default_completions_engine() -> String {
+    "gpt-4o-copilot".to_string()
+}
+
+/// What OpenAI-format chat responses do with the reasoning of reasoning models, under
+/// `[copilot.reasoning]`
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CopilotReasoningConfig {
+    #[serde(default)]
+    pub output: ReasoningOutput,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningOutput {
+    /// In the `reasoning_content` and `reasoning` fields next to `content`
+    #[default]
+    Include,
+    /// Dropped
+    Strip,
+    /// Prepended to `content` inside `<think>` tags
+    Fold,
+}
+
+/// A curated request profile. The preset's `model` replaces the virtual model name,
+/// `system_prompt` is prepended to the conversation, and `temperature` and `stop`
+/// apply unless the client set its own.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PresetConfig {
+    pub model: String,
+    pub system_prompt: Option<String>,
+    pub temperature: Option<f32>,
+    pub stop: Option<Vec<String>>,
+}
+
+/// A model id of its own for `model` with some parameters pinned. Requests for it go to
+/// `model`, and each parameter set here replaces whatever the client sent.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualModelConfig {
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    /// e.g. `{ type = "json_object" }`
+    pub response_format: Option<ResponseFormat>,
+    pub reasoning_effort: Option<ReasoningEffort>,
+}
+
+/// A configuration served under its own URL prefix, under `[profiles.<name>]`. The API at
+/// `/<name>/v1/...` authenticates with the tokens at `access_token_path` and
+/// `copilot_token_path`, as written by `--login` with the same options, uses
+/// `default_model` for requests naming none, and accepts at most `requests_per_minute`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub access_token_path: Option<String>,
+    pub copilot_token_path: Option<String>,
+    pub default_model: Option<String>,
+    pub requests_per_minute: Option<u32>,
+}
+
+/// Top-level paths a profile name would shadow
+const RESERVED_PROFILE_NAMES: &[&str] =
+    &["v1", "api", "openai", "admin", "mcp", "health", "metrics"];
+
+/// Check a `[profiles]` name can be used as a URL prefix
+fn validate_profile_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!(
+            "Invalid profile name \"{}\": use letters, digits, '-' and '_'",
+            name
+        );
+    }
+    if RESERVED_PROFILE_NAMES.contains(&name) {
+        anyhow::bail!(
+            "Profile name \"{}\" clashes with the /{} routes",
+            name,
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Local storage behind `/v1/files`, under `[files]`. Files go to `directory`, or a
+/// `files` directory next to the tokens when unset.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FilesConfig {
+    pub directory: Option<String>,
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            max_upload_bytes: default_max_upload_bytes(),
+        }
+    }
+}
+
+fn default_max_upload_bytes() -> usize {
+    100 * 1024 * 1024
+}
+
+/// Conversation log behind `/v1/history`, under `[history]`. When `enabled`, every chat
+/// exchange is stored per session in the SQLite database at `path`, or `history.sqlite`
+/// next to the tokens when unset.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub path: Option<String>,
+}
+
+/// Client API keys, under `[keys]`, managed with `passenger-rs keys`. When `enabled`, API
+/// requests need a key that has not been revoked, sent as a bearer token or in `api-key`.
+/// Keys are stored hashed in the SQLite database at `path`, or `keys.sqlite` next to the
+/// tokens when unset.
+///
+/// With `signed_requests`, a request may instead carry an Ed25519 signature made with a
+/// key's signing key, which never sends the key itself. Signatures more than `signature_max_age_secs` away
+/// from the proxy's clock, or seen before, are refused.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KeysConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub path: Option<String>,
+    #[serde(default)]
+    pub signed_requests: bool,
+    #[serde(default = "default_signature_max_age")]
+    pub signature_max_age_secs: u64,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: None,
+            signed_requests: false,
+            signature_max_age_secs: default_signature_max_age(),
+        }
+    }
+}
+
+fn default_signature_max_age() -> u64 {
+    300
+}
+
+/// Client authentication with JWTs from an OpenID Connect issuer, under `[oidc]`. When
+/// `enabled`, API requests need a bearer token issued by `issuer` (for `audience`, when
+/// set) and signed with one of its keys, fetched from `jwks_url` or the issuer's discovery
+/// document and cached for `jwks_cache_secs`. Client API keys are still accepted under
+/// `[keys] enabled`.
+///
+/// The `user_claim` names the user in the access log and in `[users]` limits, in place of
+/// the OpenAI `user` field. Members of a group in `groups_claim` get the
+/// `group_requests_per_minute` of that group, the highest when several match.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub issuer: String,
+    pub audience: Option<String>,
+    pub jwks_url: Option<String>,
+    #[serde(default = "default_jwks_cache")]
+    pub jwks_cache_secs: u64,
+    #[serde(default = "default_user_claim")]
+    pub user_claim: String,
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    #[serde(default)]
+    pub group_requests_per_minute: HashMap<String, u32>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            issuer: String::new(),
+            audience: None,
+            jwks_url: None,
+            jwks_cache_secs: default_jwks_cache(),
+            user_claim: default_user_claim(),
+            groups_claim: default_groups_claim(),
+            group_requests_per_minute: HashMap::new(),
+        }
+    }
+}
+
+fn default_jwks_cache() -> u64 {
+    3600
+}
+
+fn default_user_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+/// Model Context Protocol server, under `[mcp]`. `passenger-rs mcp` always serves it on
+/// stdio; `enabled` also serves it over SSE at `/mcp/sse` on the proxy's port.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct McpConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Model `ask_copilot` uses when the host does not pick one
+    #[serde(default = "default_mcp_model")]
+    pub default_model: String,
+    /// MCP servers whose tools the proxy offers to models and runs itself, by name
+    #[serde(default)]
+    pub servers: HashMap<String, McpServerConfig>,
+    /// Whether the proxy runs server-side tools, unless a request's `X-Passenger-Auto-Tools`
+    /// header says otherwise
+    #[serde(default = "default_auto_tools")]
+    pub auto_tools: bool,
+    /// Rounds of server-side tool calls before the model must answer
+    #[serde(default = "default_max_tool_rounds")]
+    pub max_tool_rounds: u32,
+}
+
+impl Default for McpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_model: default_mcp_model(),
+            servers: HashMap::new(),
+            auto_tools: default_auto_tools(),
+            max_tool_rounds: default_max_tool_rounds(),
+        }
+    }
+}
+
+/// An MCP server started over stdio, under `[mcp.servers.<name>]`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+fn default_mcp_model() -> String {
+    "gpt-4o".to_string()
+}
+
+fn default_auto_tools() -> bool {
+    true
+}
+
+fn default_max_tool_rounds() -> u32 {
+    8
+}
+
+/// Built-in `web_search` tool, under `[web_search]`. When `enabled`, models may call it
+/// and the proxy sends the query to the search API at `url` itself.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WebSearchConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Search API queried with `GET`, e.g. a SearXNG instance's `/search`
+    #[serde(default)]
+    pub url: String,
+    /// Query string parameter carrying the search terms
+    #[serde(default = "default_web_search_query_param")]
+    pub query_param: String,
+    /// Further query string parameters, e.g. `format = "json"`
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    /// Headers sent with every search, e.g. an API key
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Results passed to the model per search
+    #[serde(default = "default_web_search_max_results")]
+    pub max_results: usize,
+}
+
+impl Default for WebSearchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            query_param: default_web_search_query_param(),
+            params: HashMap::new(),
+            headers: HashMap::new(),
+            max_results: default_web_search_max_results(),
+        }
+    }
+}
+
+fn default_web_search_query_param() -> String {
+    "q".to_string()
+}
+
+fn default_web_search_max_results() -> usize {
+    5
+}
+
+/// Another OpenAI-compatible API serving what Copilot cannot, such as audio under `[audio]`
+/// and images under `[images]`. Without `backend_url`, those endpoints answer
+/// `501 Not Implemented`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BackendConfig {
+    /// Base URL requests are forwarded to, e.g. `http://localhost:8000/v1`
+    pub backend_url: Option<String>,
+    /// Sent as a bearer token to `backend_url`
+    pub api_key: Option<String>,
+}
+
+/// Model name handling, under `[models]`. Requested models are resolved against the
+/// Copilot model catalogue, through `aliases` first, and unknown ones are rejected with
+/// `404 model_not_found` unless `validate` is off.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ModelsConfig {
+    #[serde(default = "default_validate_models")]
+    pub validate: bool,
+    /// Client model name to Copilot model id, e.g. `"gpt-4" = "gpt-4.1"`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Copilot model id to the `reasoning_effort` of requests that set none, e.g. `"o3-mini" = "high"`
+    #[serde(default)]
+    pub reasoning_effort: HashMap<String, ReasoningEffort>,
+    /// Model id to how `/v1/models`, `/api/tags` and `/api/show` present it
+    #[serde(default)]
+    pub display: HashMap<String, ModelDisplay>,
+    /// Copilot model id to how system and developer messages are sent to it
+    #[serde(default)]
+    pub messages: HashMap<String, ModelMessagesConfig>,
+}
+
+impl Default for ModelsConfig {
+    fn default() -> Self {
+        Self {
+            validate: default_validate_models(),
+            aliases: HashMap::new(),
+            reasoning_effort: HashMap::new(),
+            display: HashMap::new(),
+            messages: HashMap::new(),
+        }
+    }
+}
+
+impl ModelsConfig {
+    pub fn display(&self, id: &str) -> Option<&ModelDisplay> {
+        self.display.get(id)
+    }
+
+    /// `[models.messages]` of a model, or the defaults when it has no entry
+    pub fn messages(&self, id: &str) -> ModelMessagesConfig {
+        self.messages.get(id).cloned().unwrap_or_default()
+    }
+
+    /// Sort key listing models with an `order` first, lowest first
+    pub fn display_order(&self, id: &str) -> (bool, i64) {
+        match self.display(id).and_then(|display| display.order) {
+            Some(order) => (false, order),
+            None => (true, 0),
+        }
+    }
+
+    /// The model id given `name` as display name, for clients that send it back as the model
+    pub fn id_for_display_name(&self, name: &str) -> Option<&str> {
+        self.display
+            .iter()
+            .find(|(_, display)| display.name.as_deref() == Some(name))
+            .map(|(id, _)| id.as_str())
+    }
+}
+
+/// System message handling for a model, under `[models.messages."<model>"]`.
+/// `developer_as_system` (on by default, for every model) sends OpenAI's `developer`
+/// messages as `system` ones; `merge_system` joins consecutive system messages into one.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ModelMessagesConfig {
+    #[serde(default = "default_developer_as_system")]
+    pub developer_as_system: bool,
+    #[serde(default)]
+    pub merge_system: bool,
+}
+
+impl Default for ModelMessagesConfig {
+    fn default() -> Self {
+        Self {
+            developer_as_system: default_developer_as_system(),
+            merge_system: false,
+        }
+    }
+}
+
+fn default_developer_as_system() -> bool {
+    true
+}
+
+/// A `[models.display."<id>"]` entry
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDisplay {
+    /// Shown instead of the id, e.g. `"Claude Sonnet 4.5 (work)"`
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Position in model lists, lowest first. Models without one follow, by id.
+    pub order: Option<i64>,
+}
+
+fn default_validate_models() -> bool {
+    true
+}
+
+/// Premium request accounting, under `[premium]`. Each client request counts as its
+/// model's multiplier: `multipliers` first, then built-in estimates, then
+/// `default_multiplier`. Past `monthly_budget`, premium requests are refused.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PremiumConfig {
+    #[serde(default)]
+    pub multipliers: HashMap<String, f64>,
+    #[serde(default = "default_premium_multiplier")]
+    pub default_multiplier: f64,
+    pub monthly_budget: Option<f64>,
+}
+
+impl Default for PremiumConfig {
+    fn default() -> Self {
+        Self {
+            multipliers: HashMap::new(),
+            default_multiplier: default_premium_multiplier(),
+            monthly_budget: None,
+        }
+    }
+}
+
+/// Limits per end user, for requests that send OpenAI's `user` field, under `[users]`.
+/// Each user gets at most `requests_per_minute`; with `limit_by_ip`, so does each client
+/// address sending requests without a `user`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UsersConfig {
+    pub requests_per_minute: Option<u32>,
+    #[serde(default)]
+    pub limit_by_ip: bool,
+}
+
+fn default_premium_multiplier() -> f64 {
+    1.0
+}
+
+/// Rules behind `/v1/moderations`, under `[moderation]`. Each entry in `categories`
+/// flags input matching any of its `keywords` (case-insensitive whole words) or
+/// `patterns` (regular expressions). With `model` set, that Copilot model also
+/// classifies the input and the higher score wins.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ModerationConfig {
+    pub model: Option<String>,
+    #[serde(default)]
+    pub categories: HashMap<String, ModerationCategoryConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ModerationCategoryConfig {
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
+    #[serde(default = "default_port")]
     pub port: u16,
+    #[serde(default = "default_host")]
     pub host: String,
+    /// Largest request body accepted, chunked or not; file uploads have `[files] max_upload_bytes`
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Reverse proxies, as addresses or CIDR ranges, whose `X-Forwarded-For` and `X-Real-IP`
+    /// headers name the client
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Client addresses or CIDR ranges allowed to use the proxy; anyone when empty
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+}
+
+impl ServerConfig {
+    pub fn trusted_proxy_networks(&self) -> Result<Vec<IpNet>> {
+        networks(&self.trusted_proxies).context("Invalid trusted proxy")
+    }
+
+    pub fn allowed_client_networks(&self) -> Result<Vec<IpNet>> {
+        networks(&self.allowed_clients).context("Invalid allowed client")
+    }
+}
+
+/// Addresses and CIDR ranges as networks, a bare address standing for itself alone
+fn networks(entries: &[String]) -> Result<Vec<IpNet>> {
+    entries
+        .iter()
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow::anyhow!("{:?} is neither an address nor a CIDR range", entry))
+        })
+        .collect()
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: default_port(),
+            host: default_host(),
+            max_body_bytes: default_max_body_bytes(),
+            trusted_proxies: Vec::new(),
+            allowed_clients: Vec::new(),
+        }
+    }
+}
+
+fn default_port() -> u16 {
+    8081
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_max_body_bytes() -> usize {
+    32 * 1024 * 1024
+}
+
+/// Diagnostics under `[debug]`. With `dump_payloads`, each request to Copilot and its reply
+/// are written as sent and received to a pair of files in `dump_dir`, or a `payloads`
+/// directory next to the tokens when unset. Bearer tokens, and the JSON fields and headers
+/// named in `redact`, are replaced with `[REDACTED]`.
+///
+/// With `dry_run`, requests with `X-Passenger-Dry-Run: true` are answered with the request
+/// the proxy would send to Copilot, which is not sent.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DebugConfig {
+    #[serde(default)]
+    pub dump_payloads: bool,
+    pub dump_dir: Option<String>,
+    #[serde(default)]
+    pub redact: Vec<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A local test model, under `[echo]`. When `enabled`, chat requests for `model` are never
+/// sent to Copilot: the reply is the last user message, streamed at `tokens_per_second`
+/// (as fast as possible when unset), for trying out clients and load testing without
+/// using any Copilot quota.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EchoConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_echo_model")]
+    pub model: String,
+    pub tokens_per_second: Option<f64>,
+}
+
+impl Default for EchoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: default_echo_model(),
+            tokens_per_second: None,
+        }
+    }
+}
+
+impl EchoConfig {
+    /// Whether requests for `model` are answered by the echo model
+    pub fn serves(&self, model: &str) -> bool {
+        self.enabled && model == self.model
+    }
+}
+
+fn default_echo_model() -> String {
+    "passenger-echo".to_string()
+}
+
+/// Log verbosity under `[logging]`; `--log-level` takes precedence over `level`
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Extra `RUST_LOG`-style directives, e.g. `passenger_rs::server::ollama=debug`
+    pub filter: Option<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            filter: None,
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+/// HTTP access log under `[access_log]`, kept apart from the tracing output. When
+/// `enabled`, one line per request is appended to `path`, or written to stdout when unset.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Apache common log format
+    Common,
+    /// Apache combined log format, followed by the route, model and duration
+    #[default]
+    Combined,
+    /// One JSON object per line
+    Json,
+}
+
+/// Admin API under `/admin`, off unless `enabled`. Requests must carry the `token` as
+/// `Authorization: Bearer <token>`, so enabling it needs one.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    /// Refuse an admin API any client could use, e.g. to turn on request logging
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.token.as_deref().is_none_or(str::is_empty) {
+            anyhow::bail!("`admin.enabled` needs a `token`");
+        }
+        Ok(())
+    }
+}
+
+/// Endpoint families served, under `[endpoints]`; all are on by default. Turning one off
+/// removes its routes, which then answer `404 Not Found` like any unknown path.
+/// `/v1/chat/completions`, `/v1/models` and `/health` are always served, and `/admin` has
+/// `[admin] enabled`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointsConfig {
+    /// `/api/...` and `/v1/api/...`, and the `[ollama] port` listener
+    #[serde(default = "default_endpoint_enabled")]
+    pub ollama: bool,
+    /// `/v1/responses`
+    #[serde(default = "default_endpoint_enabled")]
+    pub responses: bool,
+    /// `/v1/completions`
+    #[serde(default = "default_endpoint_enabled")]
+    pub completions: bool,
+    /// `/v1/compare`
+    #[serde(default = "default_endpoint_enabled")]
+    pub compare: bool,
+    /// `/openai/deployments/...`
+    #[serde(default = "default_endpoint_enabled")]
+    pub azure: bool,
+    /// `/v1/copilot/conversation`
+    #[serde(default = "default_endpoint_enabled")]
+    pub copilot_conversation: bool,
+    /// `/v1/moderations`
+    #[serde(default = "default_endpoint_enabled")]
+    pub moderations: bool,
+    /// `/v1/files`
+    #[serde(default = "default_endpoint_enabled")]
+    pub files: bool,
+    /// `/v1/audio/...`
+    #[serde(default = "default_endpoint_enabled")]
+    pub audio: bool,
+    /// `/v1/images/...`
+    #[serde(default = "default_endpoint_enabled")]
+    pub images: bool,
+    /// `/v1/account` and `/v1/usage/premium`
+    #[serde(default = "default_endpoint_enabled")]
+    pub account: bool,
+    /// `/metrics`
+    #[serde(default = "default_endpoint_enabled")]
+    pub metrics: bool,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            ollama: true,
+            responses: true,
+            completions: true,
+            compare: true,
+            azure: true,
+            copilot_conversation: true,
+            moderations: true,
+            files: true,
+            audio: true,
+            images: true,
+            account: true,
+            metrics: true,
+        }
+    }
+}
+
+fn default_endpoint_enabled() -> bool {
+    true
+}
+
+/// Azure OpenAI-compatible route under `[azure]`. `deployments` maps deployment
+/// names to Copilot models; with `api_key` set, clients must send it in `api-key`.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AzureConfig {
+    #[serde(default)]
+    pub deployments: HashMap<String, String>,
+    pub api_key: Option<String>,
+}
+
+/// Ollama compatibility under `[ollama]`. With `model_management` on, `/api/pull`,
+/// `/api/delete` and `/api/create` answer with success without doing anything, for
+/// clients such as Open WebUI that treat missing routes as a broken server.
+///
+/// With `port` set, a dedicated listener on `[server] host` serves the Ollama routes
+/// at their usual paths, plus the OpenAI-compatible routes Ollama also exposes.
+///
+/// `thinking` passes the reasoning of reasoning models on in Ollama's `thinking` field.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OllamaConfig {
+    #[serde(default)]
+    pub model_management: bool,
+    pub port: Option<u16>,
+    #[serde(default = "default_ollama_thinking")]
+    pub thinking: bool,
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            model_management: false,
+            port: None,
+            thinking: default_ollama_thinking(),
+        }
+    }
+}
+
+fn default_ollama_thinking() -> bool {
+    true
 }
 
 impl Config {
@@ -35,11 +1622,211 @@ impl Config {
         let contents =
             fs::read_to_string(path).context(format!("Failed to read config file: {}", path))?;
 
-        let config: Config =
-            toml::from_str(&contents).context("Failed to parse config file as TOML")?;
+        let config = match migration::migrate(&contents)? {
+            Some(migrated) => {
+                warn!(
+                    "{} predates config version {}; run `passenger-rs config migrate` to update it",
+                    path,
+                    migration::CONFIG_VERSION
+                );
+                Self::parse(&migrated)
+            }
+            None => Self::parse(&contents),
+        }
+        .context("Failed to parse config file as TOML")?;
+
+        // Refuse at startup whatever `config validate` reports, rather than on the first
+        // request that needs it
+        let problems = config.problems();
+        if !problems.is_empty() {
+            anyhow::bail!("Invalid config file {}: {}", path, problems.join("; "));
+        }
 
         Ok(config)
     }
+
+    /// Refuse options of features this build left out, rather than serving without them:
+    /// without `keys`, `[keys] enabled` would leave the proxy open
+    fn check_features(&self) -> Result<()> {
+        if self.keys.enabled && !cfg!(feature = "keys") {
+            anyhow::bail!("`keys.enabled`: passenger-rs was built without the `keys` feature");
+        }
+        if self.history.enabled && !cfg!(feature = "history") {
+            anyhow::bail!(
+                "`history.enabled`: passenger-rs was built without the `history` feature"
+            );
+        }
+        Ok(())
+    }
+
+    /// Parse a configuration, rejecting unknown keys. Errors name the offending key, next to
+    /// TOML's line and column.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let deserializer = toml::Deserializer::parse(contents)?;
+        serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let path = e.path().to_string();
+            let error = e.into_inner();
+            if path == "." {
+                anyhow::anyhow!(error)
+            } else {
+                anyhow::anyhow!("in `{}`: {}", path, error)
+            }
+        })
+    }
+
+    /// What a parsed configuration gets wrong beyond its types: invalid URLs, bad profile
+    /// names and options that contradict each other
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut urls = vec![
+            (
+                "github.device_code_url",
+                self.github.device_code_url.as_str(),
+            ),
+            (
+                "github.oauth_token_url",
+                self.github.oauth_token_url.as_str(),
+            ),
+            (
+                "github.copilot_token_url",
+                self.github.copilot_token_url.as_str(),
+            ),
+            (
+                "github.copilot_models_url",
+                self.github.copilot_models_url.as_str(),
+            ),
+            (
+                "github.copilot_user_url",
+                self.github.copilot_user_url.as_str(),
+            ),
+            ("copilot.api_base_url", self.copilot.api_base_url.as_str()),
+            (
+                "copilot.completions.base_url",
+                self.copilot.completions.base_url.as_str(),
+            ),
+        ];
+        if !self.web_search.url.is_empty() {
+            urls.push(("web_search.url", self.web_search.url.as_str()));
+        }
+        for (key, backend) in [("audio", &self.audio), ("images", &self.images)] {
+            if let Some(url) = &backend.backend_url {
+                urls.push((key, url.as_str()));
+            }
+        }
+        if self.oidc.enabled {
+            urls.push(("oidc.issuer", self.oidc.issuer.as_str()));
+        }
+        if let Some(url) = &self.oidc.jwks_url {
+            urls.push(("oidc.jwks_url", url.as_str()));
+        }
+        for (key, url) in urls {
+            match Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                Ok(_) => problems.push(format!("`{}`: not an http(s) URL: {}", key, url)),
+                Err(e) => problems.push(format!("`{}`: invalid URL {:?}: {}", key, url, e)),
+            }
+        }
+
+        if let Err(e) = self.copilot.proxy.proxy() {
+            problems.push(format!("`copilot.proxy`: {:#}", e));
+        }
+        if let Err(e) = self.server.trusted_proxy_networks() {
+            problems.push(format!("`server.trusted_proxies`: {:#}", e));
+        }
+        if let Err(e) = self.server.allowed_client_networks() {
+            problems.push(format!("`server.allowed_clients`: {:#}", e));
+        }
+        if let Err(e) = self.admin.validate() {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self.check_features() {
+            problems.push(e.to_string());
+        }
+        if self.copilot.proxy.password.is_some() && self.copilot.proxy.username.is_none() {
+            problems.push("`copilot.proxy`: `password` is set without `username`".to_string());
+        }
+        for name in self.profiles.keys() {
+            if let Err(e) = validate_profile_name(name) {
+                problems.push(format!("`profiles.{}`: {}", name, e));
+            }
+        }
+
+        for (i, client) in self.copilot.clients.iter().enumerate() {
+            if client.user_agent.is_none() && client.key_id.is_none() {
+                problems.push(format!(
+                    "`copilot.clients[{}]`: needs `user_agent` or `key_id` to match any request",
+                    i
+                ));
+            }
+        }
+
+        let racing = &self.copilot.racing;
+        if racing.enabled {
+            match (&racing.fast_model, &racing.strong_model) {
+                (Some(fast), Some(strong)) if fast == strong => problems.push(format!(
+                    "`copilot.racing`: `fast_model` and `strong_model` are both {}",
+                    fast
+                )),
+                (Some(_), Some(_)) => {}
+                _ => problems.push(
+                    "`copilot.racing`: `enabled` needs both `fast_model` and `strong_model`"
+                        .to_string(),
+                ),
+            }
+        }
+        if self.copilot.fan_out.judge_model.is_some()
+            && self.copilot.fan_out.ranking != FanOutRanking::Judge
+        {
+            problems.push(
+                "`copilot.fan_out`: `judge_model` is only used with `ranking = \"judge\"`"
+                    .to_string(),
+            );
+        }
+        if self.web_search.enabled && self.web_search.url.is_empty() {
+            problems.push("`web_search`: `enabled` needs a `url`".to_string());
+        }
+        let pacing = &self.copilot.pacing;
+        if pacing
+            .requests_per_second
+            .is_some_and(|rate| !(rate > 0.0 && rate.is_finite()))
+        {
+            problems.push("`copilot.pacing.requests_per_second` must be above 0".to_string());
+        }
+        if pacing.burst == 0 {
+            problems.push("`copilot.pacing.burst` must be at least 1".to_string());
+        }
+        let mut chains: Vec<_> = self.copilot.fallbacks.chains.iter().collect();
+        chains.sort();
+        for (model, chain) in chains {
+            if chain.contains(model) {
+                problems.push(format!(
+                    "`copilot.fallbacks.chains.{}`: a model cannot fall back on itself",
+                    model
+                ));
+            }
+        }
+        if self.keys.signed_requests && !self.keys.enabled {
+            problems.push(
+                "`keys.signed_requests`: signatures are only checked with `keys.enabled = true`"
+                    .to_string(),
+            );
+        }
+        if self.ollama.port.is_some() && !self.endpoints.ollama {
+            problems.push(
+                "`ollama.port`: no Ollama listener is started with `endpoints.ollama = false`"
+                    .to_string(),
+            );
+        }
+        if self.ollama.port == Some(self.server.port) {
+            problems.push(format!(
+                "`ollama.port`: {} is already the `server.port`",
+                self.server.port
+            ));
+        }
+
+        problems
+    }
 }
 
 #[cfg(test)]
@@ -72,5 +1859,565 @@ mod tests {
         assert_eq!(config.copilot.api_base_url, "https://api.githubcopilot.com");
         assert_eq!(config.server.port, 8081);
         assert_eq!(config.server.host, "127.0.0.1");
+        assert_eq!(config.copilot.headers.integration_id, "vscode-chat");
+        assert!(config.copilot.headers.extra.is_empty());
+        assert_eq!(config.copilot.timeouts.connect_secs, 10);
+        assert_eq!(config.logging.level, "info");
+        assert!(config.logging.filter.is_none());
+        assert!(!config.admin.enabled);
+        assert!(!config.access_log.enabled);
+        assert_eq!(config.access_log.format, AccessLogFormat::Combined);
+        assert!(config.azure.deployments.is_empty());
+        assert!(config.profiles.is_empty());
+        assert!(!config.ollama.model_management);
+        assert!(config.ollama.port.is_none());
+        assert!(config.ollama.thinking);
+        assert!(config.moderation.categories.is_empty());
+        assert!(config.models.validate);
+        assert!(!config.history.enabled);
+        assert!(!config.keys.enabled);
+        assert!(!config.mcp.enabled);
+        assert_eq!(config.mcp.default_model, "gpt-4o");
+        assert!(config.mcp.servers.is_empty());
+        assert!(config.mcp.auto_tools);
+        assert_eq!(config.mcp.max_tool_rounds, 8);
+        assert!(!config.web_search.enabled);
+        assert!(config.audio.backend_url.is_none());
+        assert!(config.images.backend_url.is_none());
+        assert_eq!(config.web_search.query_param, "q");
+        assert_eq!(config.web_search.max_results, 5);
+        assert_eq!(config.server.max_body_bytes, 32 * 1024 * 1024);
+        assert!(config.models.reasoning_effort.is_empty());
+        assert!(config.premium.monthly_budget.is_none());
+    }
+
+    #[test]
+    fn test_defaults_match_config_toml() {
+        let shipped = Config::from_file("config.toml").unwrap();
+        let empty = Config::parse("").unwrap();
+
+        for config in [Config::default(), empty] {
+            assert_eq!(
+                config.github.device_code_url,
+                shipped.github.device_code_url
+            );
+            assert_eq!(
+                config.github.oauth_token_url,
+                shipped.github.oauth_token_url
+            );
+            assert_eq!(
+                config.github.copilot_token_url,
+                shipped.github.copilot_token_url
+            );
+            assert_eq!(
+                config.github.copilot_models_url,
+                shipped.github.copilot_models_url
+            );
+            assert_eq!(config.github.client_id, shipped.github.client_id);
+            assert_eq!(config.copilot.api_base_url, shipped.copilot.api_base_url);
+            assert_eq!(config.server.port, shipped.server.port);
+            assert_eq!(config.server.host, shipped.server.host);
+        }
+
+        let config = Config::parse("[server]\nport = 9000\n").unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.server.host, "127.0.0.1");
+    }
+
+    #[test]
+    fn test_logging_and_admin_config() {
+        let toml = r#"
+            [logging]
+            level = "warn"
+            filter = "passenger_rs::server::ollama=debug"
+
+            [admin]
+            enabled = true
+            token = "secret"
+        "#;
+
+        #[derive(Deserialize)]
+        struct Sections {
+            logging: LoggingConfig,
+            admin: AdminConfig,
+        }
+
+        let sections: Sections = toml::from_str(toml).unwrap();
+        assert_eq!(sections.logging.level, "warn");
+        assert_eq!(
+            sections.logging.filter.as_deref(),
+            Some("passenger_rs::server::ollama=debug")
+        );
+        assert!(sections.admin.enabled);
+        assert_eq!(sections.admin.token.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn test_models_reasoning_effort_config() {
+        let models: ModelsConfig = toml::from_str(
+            r#"
+            [reasoning_effort]
+            "o3-mini" = "high"
+            "gpt-5" = "minimal"
+        "#,
+        )
+        .unwrap();
+
+        assert!(models.validate);
+        assert_eq!(
+            models.reasoning_effort.get("o3-mini"),
+            Some(&ReasoningEffort::High)
+        );
+        assert_eq!(
+            models.reasoning_effort.get("gpt-5"),
+            Some(&ReasoningEffort::Minimal)
+        );
+    }
+
+    #[test]
+    fn test_virtual_models_config() {
+        let toml = r#"
+            ["gpt-4o-json"]
+            model = "gpt-4o"
+            temperature = 0
+            response_format = { type = "json_object" }
+        "#;
+        let virtual_models: HashMap<String, VirtualModelConfig> = toml::from_str(toml).unwrap();
+
+        let virtual_model = &virtual_models["gpt-4o-json"];
+        assert_eq!(virtual_model.model, "gpt-4o");
+        assert_eq!(virtual_model.temperature, Some(0.0));
+        assert!(matches!(
+            virtual_model.response_format,
+            Some(ResponseFormat::JsonObject)
+        ));
+        assert!(virtual_model.reasoning_effort.is_none());
+    }
+
+    #[test]
+    fn test_models_display_config() {
+        let models: ModelsConfig = toml::from_str(
+            r#"
+            [display."claude-sonnet-4.5"]
+            name = "Claude Sonnet 4.5 (work)"
+            description = "Best for code review"
+            order = 1
+
+            [display."gpt-4o"]
+            name = "GPT-4o"
+        "#,
+        )
+        .unwrap();
+
+        let display = models.display("claude-sonnet-4.5").unwrap();
+        assert_eq!(display.description.as_deref(), Some("Best for code review"));
+        assert_eq!(models.display_order("claude-sonnet-4.5"), (false, 1));
+        assert_eq!(models.display_order("gpt-4o"), (true, 0));
+        assert!(models.display_order("claude-sonnet-4.5") < models.display_order("gpt-4o"));
+        assert_eq!(
+            models.id_for_display_name("Claude Sonnet 4.5 (work)"),
+            Some("claude-sonnet-4.5")
+        );
+        assert_eq!(models.id_for_display_name("claude-sonnet-4.5"), None);
+    }
+
+    #[test]
+    fn test_copilot_timeouts_override() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [timeouts]
+            total_secs = 30
+            stream_first_byte_secs = 5
+        "#;
+
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        let timeouts = copilot.timeouts;
+        assert_eq!(timeouts.connect(), Duration::from_secs(10));
+        assert_eq!(timeouts.first_byte(false), Duration::from_secs(60));
+        assert_eq!(timeouts.first_byte(true), Duration::from_secs(5));
+        assert_eq!(timeouts.total(false), Duration::from_secs(30));
+        assert_eq!(timeouts.total(true), Duration::from_secs(1800));
+    }
+
+    #[test]
+    fn test_copilot_context_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert!(!copilot.context.enabled);
+        assert_eq!(copilot.context.strategy, ContextStrategy::Truncate);
+        assert_eq!(copilot.context.reserve_tokens, 4096);
+
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [context]
+            enabled = true
+            strategy = "summarize"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert!(copilot.context.enabled);
+        assert_eq!(copilot.context.strategy, ContextStrategy::Summarize);
+        assert_eq!(copilot.context.reserve_tokens, 4096);
+    }
+
+    #[test]
+    fn test_copilot_racing_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [racing]
+            enabled = true
+            fast_model = "gpt-4o-mini"
+            strong_model = "gpt-4o"
+            policy = "strong_unless_timeout"
+        "#;
+
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        let racing = copilot.racing;
+        assert_eq!(racing.policy, RacePolicy::StrongUnlessTimeout);
+        assert_eq!(racing.strong_timeout(), Duration::from_secs(3));
+        assert_eq!(racing.upgrade_grace(), Duration::from_millis(500));
+        assert_eq!(racing.fast_model_for("gpt-4o"), Some("gpt-4o-mini"));
+        assert_eq!(racing.fast_model_for("gpt-4.1"), None);
+
+        let disabled = CopilotRacingConfig {
+            enabled: false,
+            ..racing
+        };
+        assert_eq!(disabled.fast_model_for("gpt-4o"), None);
+    }
+
+    #[test]
+    fn test_copilot_structured_outputs_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.structured_outputs.max_retries, 2);
+
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [structured_outputs]
+            max_retries = 0
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.structured_outputs.max_retries, 0);
+    }
+
+    #[test]
+    fn test_copilot_reasoning_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.reasoning.output, ReasoningOutput::Include);
+
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [reasoning]
+            output = "fold"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.reasoning.output, ReasoningOutput::Fold);
+    }
+
+    #[test]
+    fn test_copilot_empty_choices_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.empty_choices.retries, 2);
+        assert!(!copilot.empty_choices.duplicate_tool_messages);
+
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [empty_choices]
+            retries = 1
+            duplicate_tool_messages = true
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.empty_choices.retries, 1);
+        assert!(copilot.empty_choices.duplicate_tool_messages);
+    }
+
+    #[test]
+    fn test_copilot_content_filter_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert!(copilot.content_filter.annotations);
+        assert!(!copilot.content_filter.retry_sanitized);
+
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [content_filter]
+            annotations = false
+            retry_sanitized = true
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert!(!copilot.content_filter.annotations);
+        assert!(copilot.content_filter.retry_sanitized);
+    }
+
+    #[test]
+    fn test_copilot_completions_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(
+            copilot.completions.url(),
+            "https://proxy.individual.githubcopilot.com/v1/engines/gpt-4o-copilot/completions"
+        );
+
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [completions]
+            base_url = "https://proxy.business.githubcopilot.com/"
+            engine = "copilot-codex"
+        "#;
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(
+            copilot.completions.url(),
+            "https://proxy.business.githubcopilot.com/v1/engines/copilot-codex/completions"
+        );
+    }
+
+    #[test]
+    fn test_mcp_servers_config() {
+        let toml = r#"
+            auto_tools = false
+            max_tool_rounds = 3
+
+            [servers.filesystem]
+            command = "npx"
+            args = ["-y", "@modelcontextprotocol/server-filesystem", "/srv"]
+
+            [servers.search]
+            command = "/usr/local/bin/search-mcp"
+            env = { SEARCH_API_KEY = "secret" }
+        "#;
+        let mcp: McpConfig = toml::from_str(toml).unwrap();
+        assert!(!mcp.enabled);
+        assert!(!mcp.auto_tools);
+        assert_eq!(mcp.max_tool_rounds, 3);
+        assert_eq!(mcp.servers["filesystem"].command, "npx");
+        assert_eq!(mcp.servers["filesystem"].args.len(), 3);
+        assert_eq!(mcp.servers["search"].env["SEARCH_API_KEY"], "secret");
+    }
+
+    #[test]
+    fn test_web_search_config() {
+        let toml = r#"
+            enabled = true
+            url = "https://api.search.brave.com/res/v1/web/search"
+            max_results = 3
+
+            [headers]
+            "X-Subscription-Token" = "secret"
+        "#;
+        let web_search: WebSearchConfig = toml::from_str(toml).unwrap();
+        assert!(web_search.enabled);
+        assert_eq!(web_search.query_param, "q");
+        assert_eq!(web_search.max_results, 3);
+        assert_eq!(web_search.headers["X-Subscription-Token"], "secret");
+        assert!(web_search.params.is_empty());
+    }
+
+    #[test]
+    fn test_profiles_config() {
+        let toml = r#"
+            [work]
+            access_token_path = "/etc/passenger-rs/work/access_token.json"
+            copilot_token_path = "/etc/passenger-rs/work/token.json"
+            default_model = "gpt-4.1"
+            requests_per_minute = 30
+
+            [personal]
+        "#;
+        let profiles: HashMap<String, ProfileConfig> = toml::from_str(toml).unwrap();
+        assert_eq!(profiles["work"].default_model.as_deref(), Some("gpt-4.1"));
+        assert_eq!(profiles["work"].requests_per_minute, Some(30));
+        assert!(profiles["personal"].copilot_token_path.is_none());
+
+        assert!(validate_profile_name("work").is_ok());
+        assert!(validate_profile_name("team-a_2").is_ok());
+        assert!(validate_profile_name("").is_err());
+        assert!(validate_profile_name("a/b").is_err());
+        assert!(validate_profile_name("v1").is_err());
+    }
+
+    #[test]
+    fn test_copilot_headers_override() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [headers]
+            integration_id = "jetbrains-chat"
+            user_agent = "GitHubCopilotChat/0.26.7"
+
+            [headers.extra]
+            "X-Custom" = "value"
+        "#;
+
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert_eq!(copilot.headers.integration_id, "jetbrains-chat");
+        assert_eq!(copilot.headers.user_agent, "GitHubCopilotChat/0.26.7");
+        assert_eq!(copilot.headers.editor_version, "Neovim/0.6.1");
+        assert_eq!(
+            copilot.headers.extra.get("X-Custom").map(String::as_str),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn test_copilot_proxy_config() {
+        let toml = r#"
+            api_base_url = "https://api.githubcopilot.com"
+
+            [proxy]
+            url = "socks5h://proxy.corp.example:1080"
+            username = "alice"
+            password = "secret"
+            no_proxy = "localhost,127.0.0.1"
+        "#;
+
+        let copilot: CopilotConfig = toml::from_str(toml).unwrap();
+        assert!(copilot.proxy.proxy().unwrap().is_some());
+        assert!(copilot.build_client().is_ok());
+
+        let copilot: CopilotConfig =
+            toml::from_str(r#"api_base_url = "https://api.githubcopilot.com""#).unwrap();
+        assert!(copilot.proxy.proxy().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_copilot_proxy_invalid_url() {
+        let proxy = CopilotProxyConfig {
+            url: Some("not a url".to_string()),
+            ..Default::default()
+        };
+
+        assert!(proxy.proxy().is_err());
+    }
+
+    #[test]
+    fn test_parse_names_bad_keys() {
+        let contents = fs::read_to_string("config.toml").unwrap();
+
+        let unknown = contents.replace("[copilot]\n", "[copilot]\napi_base = \"x\"\n");
+        let error = Config::parse(&unknown).unwrap_err().to_string();
+        assert!(error.starts_with("in `copilot.api_base`: "), "{}", error);
+        assert!(error.contains("unknown field `api_base`"), "{}", error);
+
+        let bad_type = format!("{}\n[copilot.timeouts]\nconnect_secs = \"ten\"\n", contents);
+        let error = Config::parse(&bad_type).unwrap_err().to_string();
+        assert!(
+            error.starts_with("in `copilot.timeouts.connect_secs`: "),
+            "{}",
+            error
+        );
+        assert!(error.contains("invalid type"), "{}", error);
+    }
+
+    #[test]
+    fn test_config_problems() {
+        let mut config = Config::from_file("config.toml").unwrap();
+        assert_eq!(config.problems(), Vec::<String>::new());
+
+        config.copilot.api_base_url = "api.githubcopilot.com".to_string();
+        config.copilot.racing.enabled = true;
+        config.copilot.racing.strong_model = Some("gpt-4.1".to_string());
+        config.ollama.port = Some(config.server.port);
+        config.admin.enabled = true;
+        config.admin.token = None;
+        let problems = config.problems();
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].starts_with("`copilot.api_base_url`: invalid URL"));
+        assert_eq!(problems[1], "`admin.enabled` needs a `token`");
+        assert!(problems[2].starts_with("`copilot.racing`: `enabled` needs"));
+        assert!(problems[3].starts_with("`ollama.port`"));
+    }
+
+    #[test]
+    fn test_config_from_file_refuses_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let contents = fs::read_to_string("config.toml").unwrap();
+        let racing = "[copilot.racing]\nenabled = true\nfast_model = \"gpt-4.1\"\nstrong_model = \"gpt-4.1\"\n";
+        fs::write(&path, format!("{}\n{}", contents, racing)).unwrap();
+
+        let error = Config::from_file(path.to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("`fast_model` and `strong_model` are both gpt-4.1"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_routes_through_http_proxy() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // wiremock stands in for the proxy: plain-HTTP requests arrive with an absolute URI
+        let proxy_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(header("proxy-authorization", "Basic YWxpY2U6c2VjcmV0"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&proxy_server)
+            .await;
+
+        let copilot = CopilotConfig {
+            api_base_url: "http://copilot.invalid".to_string(),
+            headers: CopilotHeadersConfig::default(),
+            clients: Vec::new(),
+            response_headers: Vec::new(),
+            timeouts: CopilotTimeoutsConfig::default(),
+            token: CopilotTokenConfig::default(),
+            tls: CopilotTlsConfig::default(),
+            proxy: CopilotProxyConfig {
+                url: Some(proxy_server.uri()),
+                username: Some("alice".to_string()),
+                password: Some("secret".to_string()),
+                no_proxy: None,
+            },
+            tool_calls: CopilotToolCallsConfig::default(),
+            context: CopilotContextConfig::default(),
+            fan_out: CopilotFanOutConfig::default(),
+            racing: CopilotRacingConfig::default(),
+            structured_outputs: CopilotStructuredOutputsConfig::default(),
+            reasoning: CopilotReasoningConfig::default(),
+            empty_choices: CopilotEmptyChoicesConfig::default(),
+            content_filter: CopilotContentFilterConfig::default(),
+            deduplication: CopilotDeduplicationConfig::default(),
+            pacing: CopilotPacingConfig::default(),
+            fallbacks: CopilotFallbacksConfig::default(),
+            completions: CopilotCompletionsConfig::default(),
+        };
+
+        let response = copilot
+            .build_client()
+            .unwrap()
+            .get("http://copilot.invalid/models")
+            .send()
+            .await
+            .unwrap();
+
+        assert!(response.status().is_success());
     }
 }
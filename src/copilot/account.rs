@@ -0,0 +1,133 @@
+use crate::auth::{CopilotEntitlements, CopilotUserResponse, QuotaSnapshot};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Quotas with at most this share left are reported as nearly exhausted
+pub const QUOTA_WARNING_PERCENT: f64 = 10.0;
+
+/// Copilot subscription, entitlements and quotas of the authenticated account
+#[derive(Debug, Serialize)]
+pub struct AccountReport {
+    #[serde(flatten)]
+    pub entitlements: CopilotEntitlements,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_reset_date: Option<String>,
+    pub quotas: BTreeMap<String, QuotaSnapshot>,
+    pub warnings: Vec<String>,
+}
+
+impl AccountReport {
+    /// `user` is `None` when the monthly quotas could not be fetched
+    pub fn new(entitlements: CopilotEntitlements, user: Option<CopilotUserResponse>) -> Self {
+        let user = user.unwrap_or_default();
+        let quotas: BTreeMap<String, QuotaSnapshot> = user.quota_snapshots.into_iter().collect();
+
+        let mut warnings = Vec::new();
+        if entitlements.chat_enabled == Some(false) {
+            warnings.push("Copilot Chat is not enabled for this account".to_string());
+        }
+        for (name, quota) in &quotas {
+            if quota.unlimited || quota.entitlement <= 0.0 {
+                continue;
+            }
+            if quota.remaining <= 0.0 {
+                warnings.push(format!(
+                    "{} quota exhausted ({} per month)",
+                    name, quota.entitlement
+                ));
+            } else if quota.percent_remaining <= QUOTA_WARNING_PERCENT {
+                warnings.push(format!(
+                    "{} quota nearly exhausted: {} of {} left ({:.0}%)",
+                    name, quota.remaining, quota.entitlement, quota.percent_remaining
+                ));
+            }
+        }
+        if let Some(limited) = &entitlements.limited_user_quotas {
+            let mut exhausted: Vec<&String> = limited
+                .iter()
+                .filter(|(_, remaining)| **remaining == 0)
+                .map(|(name, _)| name)
+                .collect();
+            exhausted.sort();
+            for name in exhausted {
+                warnings.push(format!("{} quota of Copilot Free exhausted", name));
+            }
+        }
+
+        AccountReport {
+            entitlements,
+            plan: user.copilot_plan,
+            quota_reset_date: user.quota_reset_date,
+            quotas,
+            warnings,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn quota(entitlement: f64, remaining: f64) -> QuotaSnapshot {
+        QuotaSnapshot {
+            entitlement,
+            remaining,
+            percent_remaining: remaining / entitlement * 100.0,
+            unlimited: false,
+        }
+    }
+
+    #[test]
+    fn test_quota_warnings() {
+        let user = CopilotUserResponse {
+            copilot_plan: Some("individual".to_string()),
+            quota_reset_date: Some("2025-02-01".to_string()),
+            quota_snapshots: HashMap::from([
+                ("premium_interactions".to_string(), quota(300.0, 15.0)),
+                ("completions".to_string(), quota(2000.0, 1500.0)),
+                (
+                    "chat".to_string(),
+                    QuotaSnapshot {
+                        unlimited: true,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+        };
+
+        let report = AccountReport::new(CopilotEntitlements::default(), Some(user));
+
+        assert_eq!(report.plan.as_deref(), Some("individual"));
+        assert_eq!(report.quotas.len(), 3);
+        assert_eq!(
+            report.warnings,
+            vec!["premium_interactions quota nearly exhausted: 15 of 300 left (5%)"]
+        );
+    }
+
+    #[test]
+    fn test_entitlement_warnings_without_quotas() {
+        let entitlements = CopilotEntitlements {
+            chat_enabled: Some(false),
+            limited_user_quotas: Some(HashMap::from([
+                ("chat".to_string(), 0),
+                ("completions".to_string(), 120),
+            ])),
+            ..Default::default()
+        };
+
+        let report = AccountReport::new(entitlements, None);
+
+        assert!(report.quotas.is_empty());
+        assert_eq!(
+            report.warnings,
+            vec![
+                "Copilot Chat is not enabled for this account",
+                "chat quota of Copilot Free exhausted"
+            ]
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use crate::config::ModelMessagesConfig;
+use crate::copilot::models::ModelCapabilities;
+use crate::copilot::{CopilotChatRequest, CopilotContent, CopilotContentPart, CopilotMessage};
+
+/// Response header listing what was changed to fit the target model, e.g. `tools,images`
+pub const ADJUSTMENTS_HEADER: &str = "X-Passenger-Adjusted";
+
+/// Stands in for an image sent to a model without vision support
+const IMAGE_PLACEHOLDER: &str = "[image omitted: the model does not accept images]";
+
+/// What [`CopilotChatRequest::adapt_to`] changed
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ModelAdjustments {
+    pub tools_dropped: bool,
+    pub images_dropped: usize,
+    /// The client's `max_tokens`, when it exceeded the model's output limit
+    pub max_tokens_clamped: Option<u32>,
+    pub logprobs_dropped: bool,
+    pub reasoning_effort_dropped: bool,
+    /// `developer` messages sent as `system` ones
+    pub developer_messages: usize,
+    /// System messages merged into the one before them
+    pub system_messages_merged: usize,
+}
+
+impl ModelAdjustments {
+    /// Value for [`ADJUSTMENTS_HEADER`], if anything was changed
+    pub fn header_value(&self) -> Option<String> {
+        let adjusted: Vec<&str> = [
+            (self.tools_dropped, "tools"),
+            (self.images_dropped > 0, "images"),
+            (self.max_tokens_clamped.is_some(), "max_tokens"),
+            (self.logprobs_dropped, "logprobs"),
+            (self.reasoning_effort_dropped, "reasoning_effort"),
+            (self.developer_messages > 0, "developer_role"),
+            (self.system_messages_merged > 0, "system_messages"),
+        ]
+        .into_iter()
+        .filter_map(|(adjusted, name)| adjusted.then_some(name))
+        .collect();
+
+        (!adjusted.is_empty()).then(|| adjusted.join(","))
+    }
+}
+
+impl CopilotChatRequest {
+    /// Send `developer` messages as `system` ones and merge consecutive system messages, as
+    /// `config` asks, for models that reject the newer role or several system messages
+    pub fn normalize_system_messages(&mut self, config: &ModelMessagesConfig) -> ModelAdjustments {
+        let mut adjustments = ModelAdjustments::default();
+        if config.developer_as_system {
+            for message in &mut self.messages {
+                if message.role == "developer" {
+                    message.role = "system".to_string();
+                    adjustments.developer_messages += 1;
+                }
+            }
+        }
+
+        if config.merge_system {
+            let mut merged: Vec<CopilotMessage> = Vec::with_capacity(self.messages.len());
+            for message in self.messages.drain(..) {
+                match merged.last_mut() {
+                    Some(previous) if previous.role == "system" && message.role == "system" => {
+                        let text = [&previous.content, &message.content]
+                            .into_iter()
+                            .flatten()
+                            .map(CopilotContent::to_text)
+                            .collect::<Vec<String>>()
+                            .join("\n\n");
+                        previous.content = Some(CopilotContent::Text(text));
+                        adjustments.system_messages_merged += 1;
+                    }
+                    _ => merged.push(message),
+                }
+            }
+            self.messages = merged;
+        }
+
+        adjustments
+    }
+
+    /// Strip or clamp what the target model cannot take instead of letting Copilot reject it:
+    /// tools for models without tool calling, images for models without vision,
+    /// `max_tokens` above the model's output limit, and log probabilities for models
+    /// that do not return them.
+    pub fn adapt_to(&mut self, capabilities: &ModelCapabilities) -> ModelAdjustments {
+        let mut adjustments = ModelAdjustments::default();
+
+        if !capabilities.tool_call && self.tools.as_ref().is_some_and(|tools| !tools.is_empty()) {
+            self.tools = None;
+            self.tool_choice = None;
+            self.parallel_tool_calls = None;
+            adjustments.tools_dropped = true;
+        }
+
+        if !capabilities.vision {
+            for message in &mut self.messages {
+                let Some(CopilotContent::Parts(parts)) = &mut message.content else {
+                    continue;
+                };
+                for part in parts.iter_mut() {
+                    if matches!(part, CopilotContentPart::ImageUrl { .. }) {
+                        *part = CopilotContentPart::Text {
+                            text: IMAGE_PLACEHOLDER.to_string(),
+                        };
+                        adjustments.images_dropped += 1;
+                    }
+                }
+            }
+        }
+
+        if capabilities.output > 0
+            && let Some(max_tokens) = self.max_tokens
+            && u64::from(max_tokens) > capabilities.output
+        {
+            self.max_tokens = Some(u32::try_from(capabilities.output).unwrap_or(u32::MAX));
+            adjustments.max_tokens_clamped = Some(max_tokens);
+        }
+
+        if !capabilities.logprobs && (self.logprobs.is_some() || self.top_logprobs.is_some()) {
+            self.logprobs = None;
+            self.top_logprobs = None;
+            adjustments.logprobs_dropped = true;
+        }
+
+        if !capabilities.reasoning_effort && self.reasoning_effort.is_some() {
+            self.reasoning_effort = None;
+            adjustments.reasoning_effort_dropped = true;
+        }
+
+        adjustments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copilot::CopilotImageUrl;
+    use crate::openai::completion::models::{FunctionDefinition, Tool};
+    use crate::openai::responses::models::prompt_response::ReasoningEffort;
+
+    fn capabilities(tool_call: bool, vision: bool) -> ModelCapabilities {
+        ModelCapabilities {
+            context: 128_000,
+            output: 4096,
+            tool_call,
+            vision,
+            logprobs: tool_call,
+            reasoning_effort: tool_call,
+        }
+    }
+
+    fn request() -> CopilotChatRequest {
+        CopilotChatRequest {
+            messages: vec![CopilotMessage {
+                role: "user".to_string(),
+                content: Some(CopilotContent::Parts(vec![
+                    CopilotContentPart::Text {
+                        text: "What is this?".to_string(),
+                    },
+                    CopilotContentPart::ImageUrl {
+                        image_url: CopilotImageUrl {
+                            url: "data:image/png;base64,AAAA".to_string(),
+                        },
+                    },
+                ])),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            }],
+            model: "o1-mini".to_string(),
+            temperature: None,
+            max_tokens: Some(100_000),
+            stream: Some(false),
+            tools: Some(vec![Tool {
+                tool_type: "function".to_string(),
+                function: FunctionDefinition {
+                    name: "get_weather".to_string(),
+                    description: None,
+                    parameters: serde_json::json!({"type": "object"}),
+                },
+            }]),
+            tool_choice: None,
+            parallel_tool_calls: Some(true),
+            stop: None,
+            response_format: None,
+            logprobs: Some(true),
+            top_logprobs: Some(2),
+            reasoning_effort: Some(ReasoningEffort::High),
+            user: None,
+        }
+    }
+
+    #[test]
+    fn test_adapt_to_limited_model() {
+        let mut request = request();
+
+        let adjustments = request.adapt_to(&capabilities(false, false));
+
+        assert_eq!(
+            adjustments,
+            ModelAdjustments {
+                tools_dropped: true,
+                images_dropped: 1,
+                max_tokens_clamped: Some(100_000),
+                logprobs_dropped: true,
+                reasoning_effort_dropped: true,
+                ..ModelAdjustments::default()
+            }
+        );
+        assert_eq!(
+            adjustments.header_value().as_deref(),
+            Some("tools,images,max_tokens,logprobs,reasoning_effort")
+        );
+        assert!(request.logprobs.is_none() && request.top_logprobs.is_none());
+        assert!(request.reasoning_effort.is_none());
+        assert!(request.tools.is_none());
+        assert!(request.parallel_tool_calls.is_none());
+        assert_eq!(request.max_tokens, Some(4096));
+        assert_eq!(
+            request.messages[0].content.as_ref().unwrap().to_text(),
+            format!("What is this?\n{}", IMAGE_PLACEHOLDER)
+        );
+    }
+
+    #[test]
+    fn test_adapt_to_capable_model_changes_nothing() {
+        let mut request = request();
+        request.max_tokens = Some(1000);
+
+        let adjustments = request.adapt_to(&capabilities(true, true));
+
+        assert_eq!(adjustments, ModelAdjustments::default());
+        assert_eq!(adjustments.header_value(), None);
+        assert!(request.tools.is_some());
+        assert_eq!(request.max_tokens, Some(1000));
+        assert_eq!(request.top_logprobs, Some(2));
+        assert_eq!(request.reasoning_effort, Some(ReasoningEffort::High));
+    }
+
+    fn message(role: &str, text: &str) -> CopilotMessage {
+        CopilotMessage {
+            role: role.to_string(),
+            content: Some(text.into()),
+            padding: None,
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_text: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize_system_messages() {
+        let mut request = request();
+        request.messages = vec![
+            message("system", "Be brief."),
+            message("developer", "Answer in French."),
+            message("user", "Hello"),
+            message("system", "Reminder."),
+        ];
+
+        let adjustments = request.normalize_system_messages(&ModelMessagesConfig {
+            developer_as_system: true,
+            merge_system: true,
+        });
+
+        assert_eq!(adjustments.developer_messages, 1);
+        assert_eq!(adjustments.system_messages_merged, 1);
+        assert_eq!(
+            adjustments.header_value().as_deref(),
+            Some("developer_role,system_messages")
+        );
+        let messages: Vec<(&str, String)> = request
+            .messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_ref().unwrap().to_text()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("system", "Be brief.\n\nAnswer in French.".to_string()),
+                ("user", "Hello".to_string()),
+                ("system", "Reminder.".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_system_messages_defaults() {
+        let mut request = request();
+        request.messages = vec![message("system", "Be brief."), message("developer", "Hi")];
+
+        let adjustments = request.normalize_system_messages(&ModelMessagesConfig::default());
+
+        assert_eq!(adjustments.system_messages_merged, 0);
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[1].role, "system");
+    }
+}
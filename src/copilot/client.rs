@@ -0,0 +1,329 @@
+use crate::auth::CopilotTokenResponse;
+use crate::config::Config;
+use crate::copilot::models::CopilotModelsResponse;
+use crate::copilot::stream::{ChatDelta, chat_deltas};
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
+use crate::server::clients::with_copilot_headers;
+use crate::server::sse_lines::SseLines;
+use crate::token_manager;
+use anyhow::{Context, Result, anyhow, bail};
+use futures_util::{Stream, TryStreamExt};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::log::warn;
+
+/// Header Copilot needs on requests with image inputs
+pub(crate) const VISION_REQUEST_HEADER: &str = "Copilot-Vision-Request";
+
+/// Whether any message of a chat request body has an image part
+pub(crate) fn has_image_parts(body: &Value) -> bool {
+    body.get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message.get("content").and_then(Value::as_array))
+        .flatten()
+        .any(|part| part.get("type").and_then(Value::as_str) == Some("image_url"))
+}
+
+/// A client for Copilot's API, for talking to Copilot from Rust without running the proxy.
+/// Copilot tokens are loaded from the configured token store, where `--login` saves
+/// them, and refreshed when they expire. A token Copilot rejects is replaced and the
+/// request sent once more, as the proxy does.
+#[derive(Debug, Clone)]
+pub struct CopilotClient {
+    config: Config,
+    client: Client,
+    token_path: Option<PathBuf>,
+    access_token_path: Option<PathBuf>,
+}
+
+impl CopilotClient {
+    /// A client sending its requests as the proxy does, through `[copilot.proxy]`, with
+    /// its TLS settings and connect timeout
+    pub fn new(config: Config) -> Result<Self> {
+        let client = config.copilot.build_client()?;
+        Ok(Self::with_client(config, client))
+    }
+
+    /// A client sending its requests through `client`
+    pub fn with_client(config: Config, client: Client) -> Self {
+        Self {
+            config,
+            client,
+            token_path: None,
+            access_token_path: None,
+        }
+    }
+
+    /// This client with its Copilot token at `token_path`, and the GitHub token refreshing
+    /// it at `access_token_path`, rather than where `--login` saves them
+    pub fn with_token_paths(
+        mut self,
+        token_path: impl Into<PathBuf>,
+        access_token_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.token_path = Some(token_path.into());
+        self.access_token_path = Some(access_token_path.into());
+        self
+    }
+
+    /// A `POST` to `path` of `[copilot] api_base_url`, with Copilot's identification
+    /// headers and the Copilot `token`
+    pub fn post(&self, path: &str, token: &str) -> RequestBuilder {
+        let url = format!("{}{}", self.config.copilot.api_base_url, path);
+        with_copilot_headers(self.client.post(url), &self.config.copilot, token)
+    }
+
+    /// A valid Copilot token, refreshed if needed
+    pub async fn token(&self) -> Result<String> {
+        Ok(self.copilot_token().await?.token)
+    }
+
+    /// Send `request` to Copilot's chat completions API and return the whole reply
+    pub async fn chat(&self, request: &CopilotChatRequest) -> Result<CopilotChatResponse> {
+        self.send_chat(request, false)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse Copilot response")
+    }
+
+    /// Send `request` to Copilot's chat completions API and return its reply as it is
+    /// streamed, ending with [`ChatDelta::Done`]
+    pub async fn chat_stream(
+        &self,
+        request: &CopilotChatRequest,
+    ) -> Result<impl Stream<Item = Result<ChatDelta>> + Unpin + use<>> {
+        let response = self.send_chat(request, true).await?;
+        let lines = SseLines::new(response.bytes_stream().map_err(std::io::Error::other));
+        Ok(chat_deltas(lines).map_err(anyhow::Error::from))
+    }
+
+    /// The models Copilot offers this account, from `[github] copilot_models_url`
+    pub async fn models(&self) -> Result<CopilotModelsResponse> {
+        let timeout = self.config.copilot.timeouts.total(false);
+        let response = self
+            .send(timeout, |token| {
+                self.client
+                    .get(&self.config.github.copilot_models_url)
+                    .header("Authorization", format!("Bearer {}", token))
+                    .header("Accept", "application/vnd.github+json")
+                    .header("X-GitHub-Api-Version", "2022-11-28")
+                    .timeout(timeout)
+            })
+            .await?;
+        response
+            .json()
+            .await
+            .context("Failed to parse Copilot models")
+    }
+
+    /// Send `request` to `/chat/completions`, asking for a streamed reply when `stream`
+    async fn send_chat(&self, request: &CopilotChatRequest, stream: bool) -> Result<Response> {
+        let mut request = request.clone();
+        request.stream = Some(stream);
+        let body = serde_json::to_value(&request).context("Failed to serialize request")?;
+        let vision = has_image_parts(&body);
+        let timeouts = &self.config.copilot.timeouts;
+
+        self.send(timeouts.first_byte(stream), |token| {
+            let builder = self
+                .post("/chat/completions", token)
+                .timeout(timeouts.total(stream))
+                .json(&body);
+            if vision {
+                builder.header(VISION_REQUEST_HEADER, "true")
+            } else {
+                builder
+            }
+        })
+        .await
+    }
+
+    /// Send the request `build` makes for a Copilot token, failing when no response
+    /// headers arrive within `first_byte`. When Copilot rejects the token, it is replaced
+    /// and the request sent once more.
+    async fn send(
+        &self,
+        first_byte: Duration,
+        build: impl Fn(&str) -> RequestBuilder,
+    ) -> Result<Response> {
+        let token = self.copilot_token().await?;
+        let response = execute(build(&token.token), first_byte).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return checked(response).await;
+        }
+
+        warn!("Copilot API rejected the token, retrying with a new one");
+        let token = token_manager::replace_rejected_token_at(
+            &self.config,
+            &self.client,
+            &token,
+            self.token_path.as_deref(),
+            self.access_token_path.as_deref(),
+        )
+        .await
+        .context("Copilot rejected the token and no new one could be fetched")?;
+        checked(execute(build(&token.token), first_byte).await?).await
+    }
+
+    async fn copilot_token(&self) -> Result<CopilotTokenResponse> {
+        token_manager::get_valid_token_at(
+            &self.config,
+            &self.client,
+            self.token_path.as_deref(),
+            self.access_token_path.as_deref(),
+        )
+        .await
+        .context("No valid Copilot token; run with --login first")
+    }
+}
+
+/// Send `request`, failing when no response headers arrive within `first_byte`
+async fn execute(request: RequestBuilder, first_byte: Duration) -> Result<Response> {
+    tokio::time::timeout(first_byte, request.send())
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "Copilot API did not respond within {}s",
+                first_byte.as_secs()
+            )
+        })?
+        .context("Copilot request failed")
+}
+
+/// `response`, or an error with its body when Copilot did not succeed
+async fn checked(response: Response) -> Result<Response> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!("Copilot API error: {} - {}", status, body);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copilot::finish_reason::FinishReason;
+    use crate::storage;
+    use futures_util::TryStreamExt;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A client of the mock server, with a valid Copilot token in `dir`
+    fn client(server: &MockServer, dir: &tempfile::TempDir) -> CopilotClient {
+        let token_path = dir.path().join("token.json");
+        let token = CopilotTokenResponse {
+            token: "token".to_string(),
+            expires_at: u64::MAX / 2,
+            refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
+        };
+        storage::save_token_to_path(&token, Some(&token_path)).unwrap();
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = server.uri();
+        config.github.copilot_models_url = format!("{}/models", server.uri());
+        CopilotClient::new(config)
+            .unwrap()
+            .with_token_paths(token_path, dir.path().join("access.json"))
+    }
+
+    fn request() -> CopilotChatRequest {
+        serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_post_sends_copilot_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("authorization", "Bearer token"))
+            .and(header("copilot-integration-id", "vscode-chat"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = server.uri();
+        let response = CopilotClient::new(config)
+            .unwrap()
+            .post("/chat/completions", "token")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream() {
+        let server = MockServer::start().await;
+        let body = concat!(
+            "data: {\"id\":\"c1\",\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"id\":\"c1\",\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n"
+        );
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let deltas: Vec<ChatDelta> = client(&server, &dir)
+            .chat_stream(&request())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            deltas,
+            vec![
+                ChatDelta::Content("Hel".to_string()),
+                ChatDelta::Content("lo".to_string()),
+                ChatDelta::Done(FinishReason::Stop),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_models_and_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "github-copilot": { "models": {
+                    "gpt-4o": { "id": "gpt-4o", "name": "GPT-4o", "family": "gpt-4o" }
+                } }
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("bad request"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let client = client(&server, &dir);
+        let models = client.models().await.unwrap();
+        assert_eq!(models.models[0].id, "gpt-4o");
+
+        let error = client.chat(&request()).await.unwrap_err();
+        assert!(error.to_string().contains("400"), "{}", error);
+    }
+}
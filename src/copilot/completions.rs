@@ -0,0 +1,224 @@
+use crate::copilot::finish_reason::FinishReason;
+use crate::openai::text_completion::models::{
+    OpenAICompletionChoice, OpenAICompletionRequest, OpenAICompletionResponse,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Completion length when the client does not set `max_tokens`. OpenAI's default of 16
+/// is too short for code.
+const DEFAULT_MAX_TOKENS: u32 = 500;
+
+/// Request to Copilot's code completion API. Copilot only streams these.
+#[derive(Debug, Clone, Serialize)]
+pub struct CopilotCompletionRequest {
+    pub prompt: String,
+    pub suffix: String,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub n: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    pub stream: bool,
+    pub extra: CompletionExtra,
+}
+
+/// Editor context Copilot expects next to the prompt
+#[derive(Debug, Clone, Serialize)]
+pub struct CompletionExtra {
+    pub language: String,
+    pub next_indent: u32,
+    pub trim_by_indentation: bool,
+}
+
+impl CopilotCompletionRequest {
+    pub fn new(prompt: String, suffix: Option<String>) -> Self {
+        Self {
+            prompt,
+            suffix: suffix.unwrap_or_default(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            temperature: 0.0,
+            top_p: 1.0,
+            n: 1,
+            stop: Vec::new(),
+            stream: true,
+            extra: CompletionExtra {
+                language: String::new(),
+                next_indent: 0,
+                trim_by_indentation: true,
+            },
+        }
+    }
+}
+
+impl TryFrom<OpenAICompletionRequest> for CopilotCompletionRequest {
+    type Error = String;
+
+    fn try_from(request: OpenAICompletionRequest) -> Result<Self, Self::Error> {
+        let mut prompts = request.prompt.into_vec();
+        if prompts.len() != 1 {
+            return Err("prompt must be a single string".to_string());
+        }
+
+        let mut completion = Self::new(prompts.remove(0), request.suffix);
+        completion.max_tokens = request.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+        completion.temperature = request.temperature.unwrap_or(completion.temperature);
+        completion.top_p = request.top_p.unwrap_or(completion.top_p);
+        completion.n = request.n.unwrap_or(1);
+        completion.stop = request.stop.map(|stop| stop.into_vec()).unwrap_or_default();
+        Ok(completion)
+    }
+}
+
+/// One streamed chunk of a Copilot completion
+#[derive(Debug, Deserialize)]
+pub struct CopilotCompletionChunk {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub created: Option<u64>,
+    #[serde(default)]
+    pub choices: Vec<CopilotCompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CopilotCompletionChoice {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+impl CopilotCompletionChunk {
+    /// The chunk in OpenAI's format, reported as `model`
+    pub fn into_openai(self, model: &str) -> OpenAICompletionResponse {
+        OpenAICompletionResponse {
+            id: self.id.unwrap_or_default(),
+            object: "text_completion".to_string(),
+            created: self.created.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("time should go forward")
+                    .as_secs()
+            }),
+            model: model.to_string(),
+            choices: self
+                .choices
+                .into_iter()
+                .map(|choice| OpenAICompletionChoice {
+                    text: choice.text,
+                    index: choice.index,
+                    logprobs: None,
+                    finish_reason: FinishReason::parse(choice.finish_reason.as_deref())
+                        .map(|reason| reason.completion().to_string()),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whole completions, assembled from streamed chunks
+#[derive(Debug, Default)]
+pub struct CompletionText {
+    id: Option<String>,
+    created: Option<u64>,
+    choices: BTreeMap<u32, (String, Option<String>)>,
+}
+
+impl CompletionText {
+    pub fn push(&mut self, chunk: CopilotCompletionChunk) {
+        self.id = self.id.take().or(chunk.id);
+        self.created = self.created.or(chunk.created);
+        for choice in chunk.choices {
+            let (text, finish_reason) = self.choices.entry(choice.index).or_default();
+            text.push_str(&choice.text);
+            if choice.finish_reason.is_some() {
+                *finish_reason = choice.finish_reason;
+            }
+        }
+    }
+
+    /// The text of the first choice
+    pub fn text(&self) -> &str {
+        self.choices
+            .values()
+            .next()
+            .map_or("", |(text, _)| text.as_str())
+    }
+
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.choices
+            .values()
+            .next()
+            .and_then(|(_, finish_reason)| finish_reason.as_deref())
+    }
+
+    pub fn into_openai(self, model: &str) -> OpenAICompletionResponse {
+        CopilotCompletionChunk {
+            id: self.id,
+            created: self.created,
+            choices: self
+                .choices
+                .into_iter()
+                .map(|(index, (text, finish_reason))| CopilotCompletionChoice {
+                    index,
+                    text,
+                    // A whole completion has finished, whether or not Copilot said why
+                    finish_reason: Some(finish_reason.unwrap_or_else(|| "stop".to_string())),
+                })
+                .collect(),
+        }
+        .into_openai(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_openai_request() {
+        let request: OpenAICompletionRequest = serde_json::from_value(json!({
+            "model": "gpt-3.5-turbo-instruct",
+            "prompt": "fn add(a: i32, b: i32) -> i32 {\n",
+            "suffix": "\n}",
+            "stop": "\n\n",
+            "temperature": 0.2
+        }))
+        .unwrap();
+        let completion = CopilotCompletionRequest::try_from(request).unwrap();
+        assert_eq!(completion.suffix, "\n}");
+        assert_eq!(completion.stop, ["\n\n"]);
+        assert_eq!(completion.max_tokens, DEFAULT_MAX_TOKENS);
+        assert_eq!(completion.temperature, 0.2);
+        assert!(completion.stream);
+
+        let request: OpenAICompletionRequest =
+            serde_json::from_value(json!({"prompt": ["a", "b"]})).unwrap();
+        assert!(CopilotCompletionRequest::try_from(request).is_err());
+    }
+
+    #[test]
+    fn test_completion_text_joins_chunks() {
+        let chunks = [
+            json!({"id": "cmpl-1", "created": 1, "choices": [{"index": 0, "text": "    a "}]}),
+            json!({"id": "cmpl-1", "choices": [{"index": 0, "text": "+ b", "finish_reason": "stop"}]}),
+        ];
+        let mut text = CompletionText::default();
+        for chunk in chunks {
+            text.push(serde_json::from_value(chunk).unwrap());
+        }
+        assert_eq!(text.text(), "    a + b");
+        assert_eq!(text.finish_reason(), Some("stop"));
+
+        let response = text.into_openai("gpt-4o-copilot");
+        assert_eq!(response.id, "cmpl-1");
+        assert_eq!(response.object, "text_completion");
+        assert_eq!(response.choices[0].text, "    a + b");
+    }
+}
@@ -0,0 +1,168 @@
+use crate::copilot::finish_reason::FinishReason;
+use crate::copilot::{CopilotChatRequest, CopilotContent, CopilotContentPart, CopilotMessage};
+use serde_json::Value;
+
+/// Annotations Copilot adds next to a reply's choices
+const RESPONSE_ANNOTATIONS: &[&str] = &["prompt_filter_results", "prompt_annotations"];
+
+/// Annotations Copilot adds to each choice
+const CHOICE_ANNOTATIONS: &[&str] = &["content_filter_results", "content_filter_offsets"];
+
+/// What a sanitized retry asks of the model, ahead of the conversation
+const SANITIZED_INSTRUCTION: &str = "Answer within content policy. If part of the request \
+    cannot be answered, answer the rest and say briefly what was left out.";
+
+/// Whether Copilot filtered a reply, from its body: an error whose `code` is
+/// `content_filter`, as Copilot answers a prompt it blocked, or a choice that finished
+/// with `content_filter`
+pub fn is_filtered(body: &[u8]) -> bool {
+    let Ok(reply) = serde_json::from_slice::<Value>(body) else {
+        return false;
+    };
+    if let Some(error) = reply.get("error") {
+        return error["code"] == "content_filter"
+            || error["innererror"]["code"] == "ResponsibleAIPolicyViolation";
+    }
+    reply["choices"].as_array().is_some_and(|choices| {
+        choices.iter().any(|choice| {
+            FinishReason::parse(choice["finish_reason"].as_str())
+                == Some(FinishReason::ContentFilter)
+        })
+    })
+}
+
+/// Remove Copilot's content filter annotations from a reply or streamed chunk, returning
+/// whether there were any
+pub fn strip_annotations(reply: &mut Value) -> bool {
+    let mut stripped = false;
+    if let Some(reply) = reply.as_object_mut() {
+        for key in RESPONSE_ANNOTATIONS {
+            stripped |= reply.remove(*key).is_some();
+        }
+    }
+    if let Some(choices) = reply.get_mut("choices").and_then(Value::as_array_mut) {
+        for choice in choices.iter_mut().filter_map(Value::as_object_mut) {
+            for key in CHOICE_ANNOTATIONS {
+                stripped |= choice.remove(*key).is_some();
+            }
+        }
+    }
+    stripped
+}
+
+/// A streamed `data:` payload without content filter annotations. Chunks without any are
+/// passed through untouched.
+pub fn strip_chunk_annotations(payload: &str) -> String {
+    if !payload.contains("filter_") && !payload.contains("prompt_annotations") {
+        return payload.to_string();
+    }
+    let Ok(mut chunk) = serde_json::from_str::<Value>(payload) else {
+        return payload.to_string();
+    };
+    if strip_annotations(&mut chunk) {
+        chunk.to_string()
+    } else {
+        payload.to_string()
+    }
+}
+
+impl CopilotChatRequest {
+    /// This request for a retry after Copilot's content filter stopped it: images and
+    /// earlier reasoning are dropped, and the model is asked to stay within policy and
+    /// answer what it can
+    pub fn sanitized(&self) -> CopilotChatRequest {
+        let mut request = self.clone();
+        for message in &mut request.messages {
+            message.reasoning_text = None;
+            let has_images = matches!(
+                &message.content,
+                Some(CopilotContent::Parts(parts))
+                    if parts.iter().any(|part| matches!(part, CopilotContentPart::ImageUrl { .. }))
+            );
+            if has_images {
+                message.content = message
+                    .content
+                    .as_ref()
+                    .map(|content| CopilotContent::Text(content.to_text()));
+            }
+        }
+        request.messages.insert(
+            0,
+            CopilotMessage {
+                role: "system".to_string(),
+                content: Some(SANITIZED_INSTRUCTION.into()),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            },
+        );
+        request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_is_filtered() {
+        let blocked = json!({"error": {"code": "content_filter", "message": "blocked"}});
+        assert!(is_filtered(blocked.to_string().as_bytes()));
+
+        let stopped = json!({"choices": [{"finish_reason": "content_filter"}]});
+        assert!(is_filtered(stopped.to_string().as_bytes()));
+
+        let answered = json!({"choices": [{"finish_reason": "stop"}]});
+        assert!(!is_filtered(answered.to_string().as_bytes()));
+        assert!(!is_filtered(b"not json"));
+    }
+
+    #[test]
+    fn test_strip_annotations() {
+        let mut reply = json!({
+            "id": "x",
+            "prompt_filter_results": [{"prompt_index": 0}],
+            "choices": [{
+                "finish_reason": "stop",
+                "content_filter_results": {"hate": {"filtered": false}}
+            }]
+        });
+        assert!(strip_annotations(&mut reply));
+        assert_eq!(
+            reply,
+            json!({"id": "x", "choices": [{"finish_reason": "stop"}]})
+        );
+        assert!(!strip_annotations(&mut reply));
+
+        let chunk = r#"{"choices":[{"delta":{"content":"Hi"}}]}"#;
+        assert_eq!(strip_chunk_annotations(chunk), chunk);
+        let chunk = r#"{"choices":[],"prompt_filter_results":[{"prompt_index":0}]}"#;
+        assert_eq!(strip_chunk_annotations(chunk), r#"{"choices":[]}"#);
+    }
+
+    #[test]
+    fn test_sanitized() {
+        let request: CopilotChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,AA"}}
+                ]
+            }]
+        }))
+        .unwrap();
+
+        let sanitized = request.sanitized();
+        assert_eq!(sanitized.messages.len(), 2);
+        assert_eq!(sanitized.messages[0].role, "system");
+        assert_eq!(
+            sanitized.messages[1].content,
+            Some(CopilotContent::Text("What is this?".to_string()))
+        );
+    }
+}
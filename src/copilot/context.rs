@@ -0,0 +1,311 @@
+use crate::copilot::{CopilotChatRequest, CopilotContent, CopilotContentPart, CopilotMessage};
+use std::fmt::Write;
+
+/// Rough cost of an image input; Copilot models bill a high-detail image at about this
+const IMAGE_TOKENS: u64 = 765;
+/// Per-message framing (role, separators) on top of the content
+const MESSAGE_OVERHEAD_TOKENS: u64 = 4;
+
+/// Cheap token estimate: about four characters per token, which is close enough
+/// for English and code to decide whether a prompt fits the context window.
+pub fn estimate_text_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+pub fn estimate_message_tokens(message: &CopilotMessage) -> u64 {
+    let content = match &message.content {
+        None => 0,
+        Some(CopilotContent::Text(text)) => estimate_text_tokens(text),
+        Some(CopilotContent::Parts(parts)) => parts
+            .iter()
+            .map(|part| match part {
+                CopilotContentPart::Text { text } => estimate_text_tokens(text),
+                CopilotContentPart::ImageUrl { .. } => IMAGE_TOKENS,
+            })
+            .sum(),
+    };
+
+    let tool_calls = message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|call| {
+            estimate_text_tokens(&call.function.name)
+                + estimate_text_tokens(&call.function.arguments)
+        })
+        .sum::<u64>();
+
+    MESSAGE_OVERHEAD_TOKENS + content + tool_calls
+}
+
+/// Estimated prompt size, tool definitions included
+pub fn estimate_request_tokens(request: &CopilotChatRequest) -> u64 {
+    let tools = request
+        .tools
+        .as_ref()
+        .and_then(|tools| serde_json::to_string(tools).ok())
+        .map_or(0, |json| estimate_text_tokens(&json));
+
+    request
+        .messages
+        .iter()
+        .map(estimate_message_tokens)
+        .sum::<u64>()
+        + tools
+}
+
+fn is_system(message: &CopilotMessage) -> bool {
+    matches!(message.role.as_str(), "system" | "developer")
+}
+
+/// Drop the oldest non-system messages until the estimate fits `budget`.
+///
+/// System messages and the latest user turn (with anything after it) are always kept.
+/// An assistant message that made tool calls is dropped together with the tool results
+/// answering it, so the remaining history stays well-formed. Returns the dropped messages,
+/// oldest first, and the index at which they were removed.
+pub fn truncate_to_budget(
+    request: &mut CopilotChatRequest,
+    budget: u64,
+) -> Option<(usize, Vec<CopilotMessage>)> {
+    let mut estimate = estimate_request_tokens(request);
+    if estimate <= budget {
+        return None;
+    }
+
+    let protected_from = request
+        .messages
+        .iter()
+        .rposition(|m| m.role == "user")
+        .unwrap_or(request.messages.len().saturating_sub(1));
+
+    let mut drop = vec![false; request.messages.len()];
+    let mut i = 0;
+    while i < protected_from && estimate > budget {
+        if is_system(&request.messages[i]) {
+            i += 1;
+            continue;
+        }
+
+        // A message plus the tool results that follow it
+        let mut end = i + 1;
+        while end < protected_from && request.messages[end].role == "tool" {
+            end += 1;
+        }
+
+        for (index, message) in request.messages[i..end].iter().enumerate() {
+            drop[i + index] = true;
+            estimate -= estimate_message_tokens(message).min(estimate);
+        }
+        i = end;
+    }
+
+    let first_dropped = drop.iter().position(|d| *d)?;
+    let mut dropped = Vec::new();
+    let mut kept = Vec::new();
+    for (message, drop) in request.messages.drain(..).zip(drop) {
+        if drop {
+            dropped.push(message);
+        } else {
+            kept.push(message);
+        }
+    }
+    request.messages = kept;
+
+    // Dropped messages sit after the leading system messages they were interleaved with
+    let insert_at = request.messages[..]
+        .iter()
+        .take_while(|m| is_system(m))
+        .count()
+        .min(first_dropped);
+
+    Some((insert_at, dropped))
+}
+
+/// A non-streaming request asking `model` to summarise `dropped`
+pub fn summary_request(model: &str, dropped: &[CopilotMessage]) -> CopilotChatRequest {
+    let mut transcript = String::new();
+    for message in dropped {
+        let text = message
+            .content
+            .as_ref()
+            .map(CopilotContent::to_text)
+            .unwrap_or_default();
+        let _ = writeln!(transcript, "{}: {}", message.role, text);
+        for call in message.tool_calls.iter().flatten() {
+            let _ = writeln!(
+                transcript,
+                "{} called {}({})",
+                message.role, call.function.name, call.function.arguments
+            );
+        }
+    }
+
+    CopilotChatRequest {
+        messages: vec![
+            CopilotMessage::text(
+                "system",
+                "Summarise the conversation below so it can be continued without it. \
+                 Keep facts, decisions, code identifiers and open questions; be concise.",
+            ),
+            CopilotMessage::text("user", transcript),
+        ],
+        model: model.to_string(),
+        temperature: None,
+        max_tokens: Some(1024),
+        stream: Some(false),
+        tools: None,
+        tool_choice: None,
+        parallel_tool_calls: None,
+        stop: None,
+        response_format: None,
+        logprobs: None,
+        top_logprobs: None,
+        reasoning_effort: None,
+        user: None,
+    }
+}
+
+/// Stand-in for the dropped messages, placed where they used to be
+pub fn summary_message(summary: &str) -> CopilotMessage {
+    CopilotMessage::text(
+        "system",
+        format!("Summary of the earlier conversation:\n{}", summary),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::completion::models::{FunctionCall, ToolCall};
+
+    fn message(role: &str, chars: usize) -> CopilotMessage {
+        CopilotMessage::text(role, "x".repeat(chars))
+    }
+
+    fn request(messages: Vec<CopilotMessage>) -> CopilotChatRequest {
+        CopilotChatRequest {
+            messages,
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        }
+    }
+
+    fn roles(request: &CopilotChatRequest) -> Vec<&str> {
+        request.messages.iter().map(|m| m.role.as_str()).collect()
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_text_tokens(""), 0);
+        assert_eq!(estimate_text_tokens("abcde"), 2);
+        assert_eq!(estimate_message_tokens(&message("user", 400)), 104);
+
+        let image = CopilotMessage {
+            content: Some(CopilotContent::Parts(vec![CopilotContentPart::ImageUrl {
+                image_url: crate::copilot::CopilotImageUrl {
+                    url: format!("data:image/png;base64,{}", "A".repeat(100_000)),
+                },
+            }])),
+            ..message("user", 0)
+        };
+        assert_eq!(estimate_message_tokens(&image), 4 + IMAGE_TOKENS);
+    }
+
+    #[test]
+    fn test_fitting_request_is_untouched() {
+        let mut request = request(vec![message("system", 40), message("user", 40)]);
+
+        assert!(truncate_to_budget(&mut request, 1000).is_none());
+        assert_eq!(request.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_drops_oldest_non_system_messages() {
+        // Each 400-char message is about 104 tokens
+        let mut request = request(vec![
+            message("system", 400),
+            message("user", 400),
+            message("assistant", 400),
+            message("user", 400),
+            message("assistant", 400),
+            message("user", 400),
+        ]);
+
+        let (insert_at, dropped) = truncate_to_budget(&mut request, 350).unwrap();
+
+        assert_eq!(insert_at, 1);
+        assert_eq!(dropped.len(), 3);
+        assert_eq!(roles(&request), vec!["system", "assistant", "user"]);
+        assert!(estimate_request_tokens(&request) <= 350);
+    }
+
+    #[test]
+    fn test_latest_user_turn_is_never_dropped() {
+        let mut request = request(vec![message("user", 400), message("user", 4000)]);
+
+        let (_, dropped) = truncate_to_budget(&mut request, 100).unwrap();
+
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(request.messages.len(), 1);
+        assert_eq!(estimate_message_tokens(&request.messages[0]), 1004);
+    }
+
+    #[test]
+    fn test_tool_results_dropped_with_their_call() {
+        let call = CopilotMessage {
+            tool_calls: Some(vec![ToolCall {
+                id: Some("call_1".to_string()),
+                tool_type: "function".to_string(),
+                function: FunctionCall {
+                    name: "lookup".to_string(),
+                    arguments: "{}".to_string(),
+                },
+            }]),
+            ..message("assistant", 0)
+        };
+        let mut request = request(vec![
+            message("user", 400),
+            call,
+            message("tool", 400),
+            message("assistant", 400),
+            message("user", 40),
+        ]);
+
+        // Only the first user message needs to go, but the tool call after it is
+        // also dropped by the next step, along with its result
+        let (_, dropped) = truncate_to_budget(&mut request, 120).unwrap();
+
+        assert_eq!(
+            dropped.iter().map(|m| m.role.as_str()).collect::<Vec<_>>(),
+            vec!["user", "assistant", "tool"]
+        );
+        assert_eq!(roles(&request), vec!["assistant", "user"]);
+    }
+
+    #[test]
+    fn test_summary_request_contains_transcript() {
+        let dropped = vec![
+            CopilotMessage::text("user", "My name is Ada"),
+            CopilotMessage::text("assistant", "Hello Ada"),
+        ];
+
+        let summary = summary_request("gpt-4o", &dropped);
+
+        assert_eq!(summary.model, "gpt-4o");
+        assert_eq!(summary.stream, Some(false));
+        let transcript = summary.messages[1].content.as_ref().unwrap().to_text();
+        assert_eq!(transcript, "user: My name is Ada\nassistant: Hello Ada\n");
+    }
+}
@@ -0,0 +1,223 @@
+use crate::copilot::{CopilotChatRequest, CopilotMessage};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Copilot Chat editor conversation request, modelled on the Copilot language server's
+/// `conversation/create` and `conversation/turn` params: the conversation so far as
+/// request/response turns, plus the editor files the user referenced.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationRequest {
+    /// Editors resend this on every turn; it doubles as the Copilot session id
+    #[serde(default)]
+    pub conversation_id: Option<String>,
+    pub model: String,
+    /// Oldest first; the last turn is the one being asked and has no `response`
+    pub turns: Vec<ConversationTurn>,
+    #[serde(default)]
+    pub references: Vec<ConversationReference>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConversationTurn {
+    pub request: String,
+    #[serde(default)]
+    pub response: Option<String>,
+}
+
+/// A file from the editor. The proxy cannot read the client's workspace, so the
+/// editor must send `content` for it to reach the model; otherwise only the URI is.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationReference {
+    #[serde(rename = "type", default = "default_reference_type")]
+    pub kind: String,
+    pub uri: String,
+    #[serde(default)]
+    pub language_id: Option<String>,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub selection: Option<ConversationRange>,
+}
+
+fn default_reference_type() -> String {
+    "file".to_string()
+}
+
+/// Zero-based line/character range, as in LSP
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConversationRange {
+    pub start: ConversationPosition,
+    pub end: ConversationPosition,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConversationPosition {
+    pub line: u32,
+    pub character: u32,
+}
+
+/// Reply to a non-streaming conversation turn
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationResponse {
+    pub conversation_id: String,
+    pub turn_id: String,
+    pub model: String,
+    pub reply: String,
+}
+
+impl TryFrom<ConversationRequest> for CopilotChatRequest {
+    type Error = String;
+
+    fn try_from(request: ConversationRequest) -> Result<Self, Self::Error> {
+        match request.turns.last() {
+            None => return Err("`turns` must contain at least one turn".to_string()),
+            Some(turn) if turn.response.is_some() => {
+                return Err("The last turn must not have a `response` yet".to_string());
+            }
+            Some(_) => {}
+        }
+
+        let mut messages = Vec::new();
+
+        if !request.references.is_empty() {
+            messages.push(CopilotMessage::text(
+                "system",
+                render_references(&request.references),
+            ));
+        }
+
+        for turn in request.turns {
+            messages.push(CopilotMessage::text("user", turn.request));
+            if let Some(response) = turn.response {
+                messages.push(CopilotMessage::text("assistant", response));
+            }
+        }
+
+        Ok(CopilotChatRequest {
+            messages,
+            model: request.model,
+            temperature: None,
+            max_tokens: None,
+            stream: Some(request.stream),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        })
+    }
+}
+
+/// Present the referenced files to the model as a single system message
+fn render_references(references: &[ConversationReference]) -> String {
+    let mut out = String::from("The user is working in an editor and referenced these files:\n");
+
+    for reference in references {
+        let _ = write!(out, "\n{}: {}", reference.kind, reference.uri);
+        if let Some(selection) = &reference.selection {
+            // Editors count lines from zero; people count from one
+            let _ = write!(
+                out,
+                " (selected lines {}-{})",
+                selection.start.line + 1,
+                selection.end.line + 1
+            );
+        }
+        out.push('\n');
+
+        if let Some(content) = &reference.content {
+            let _ = writeln!(
+                out,
+                "```{}\n{}\n```",
+                reference.language_id.as_deref().unwrap_or_default(),
+                content.trim_end_matches('\n')
+            );
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: serde_json::Value) -> ConversationRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_turns_become_messages() {
+        let request = parse(serde_json::json!({
+            "conversationId": "c-1",
+            "model": "gpt-4o",
+            "turns": [
+                {"request": "What does main do?", "response": "It starts the server."},
+                {"request": "And on failure?"}
+            ]
+        }));
+
+        let copilot: CopilotChatRequest = request.try_into().unwrap();
+
+        let roles: Vec<_> = copilot.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        assert_eq!(
+            copilot.messages[2].content.as_ref().unwrap().to_text(),
+            "And on failure?"
+        );
+        assert_eq!(copilot.model, "gpt-4o");
+        assert_eq!(copilot.stream, Some(false));
+    }
+
+    #[test]
+    fn test_references_rendered_as_system_message() {
+        let request = parse(serde_json::json!({
+            "model": "gpt-4o",
+            "turns": [{"request": "Explain this"}],
+            "references": [
+                {
+                    "type": "file",
+                    "uri": "file:///src/main.rs",
+                    "languageId": "rust",
+                    "content": "fn main() {}\n",
+                    "selection": {
+                        "start": {"line": 0, "character": 0},
+                        "end": {"line": 0, "character": 12}
+                    }
+                },
+                {"uri": "file:///README.md"}
+            ]
+        }));
+
+        let copilot: CopilotChatRequest = request.try_into().unwrap();
+
+        assert_eq!(copilot.messages[0].role, "system");
+        let system = copilot.messages[0].content.as_ref().unwrap().to_text();
+        assert!(system.contains(
+            "file: file:///src/main.rs (selected lines 1-1)\n```rust\nfn main() {}\n```"
+        ));
+        assert!(system.contains("file: file:///README.md\n"));
+        assert_eq!(copilot.messages[1].role, "user");
+    }
+
+    #[test]
+    fn test_invalid_turns_rejected() {
+        let empty = parse(serde_json::json!({"model": "gpt-4o", "turns": []}));
+        assert!(CopilotChatRequest::try_from(empty).is_err());
+
+        let answered = parse(serde_json::json!({
+            "model": "gpt-4o",
+            "turns": [{"request": "Hi", "response": "Hello"}]
+        }));
+        assert!(CopilotChatRequest::try_from(answered).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::copilot::{CopilotChatRequest, CopilotMessage};
+use serde_json::Value;
+
+/// Whether a non-streaming Copilot reply parses but carries an empty `choices` array.
+///
+/// Copilot intermittently answers this way, most often to conversations with `tool`
+/// messages. Bodies that are not chat completions are left for the caller to report.
+pub fn has_no_choices(body: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|reply| {
+            reply
+                .get("choices")
+                .and_then(Value::as_array)
+                .map(Vec::is_empty)
+        })
+        .unwrap_or(false)
+}
+
+impl CopilotChatRequest {
+    /// This request with every `tool` message repeated as a `user` message after the last one.
+    ///
+    /// The `tool` messages stay in place, as Copilot checks every assistant `tool_calls`
+    /// has its results, while the `user` copies are what gets Copilot to answer when it
+    /// returned no choices for them.
+    pub fn with_tool_messages_as_user(&self) -> CopilotChatRequest {
+        let mut request = self.clone();
+        let Some(last_tool) = request
+            .messages
+            .iter()
+            .rposition(|message| message.role == "tool")
+        else {
+            return request;
+        };
+
+        let duplicates: Vec<CopilotMessage> = request
+            .messages
+            .iter()
+            .filter(|message| message.role == "tool")
+            .map(|message| CopilotMessage {
+                role: "user".to_string(),
+                content: Some(
+                    format!(
+                        "Tool '{}' ({}) returned: {}",
+                        message.name.as_deref().unwrap_or("unknown_tool"),
+                        message.tool_call_id.as_deref().unwrap_or("unknown_id"),
+                        message
+                            .content
+                            .as_ref()
+                            .map(|content| content.to_text())
+                            .unwrap_or_default()
+                    )
+                    .into(),
+                ),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            })
+            .collect();
+
+        request
+            .messages
+            .splice(last_tool + 1..last_tool + 1, duplicates);
+        request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::completion::models::OpenAIChatRequest;
+
+    #[test]
+    fn test_has_no_choices() {
+        assert!(has_no_choices(
+            br#"{"id": "x", "choices": [], "model": "gpt-4o"}"#
+        ));
+        assert!(!has_no_choices(
+            br#"{"choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}}]}"#
+        ));
+        assert!(!has_no_choices(br#"{"error": "bad"}"#));
+        assert!(!has_no_choices(b"not json"));
+    }
+
+    #[test]
+    fn test_with_tool_messages_as_user() {
+        let request: OpenAIChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "Weather in SF and NYC?"},
+                {"role": "assistant", "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}},
+                    {"id": "call_2", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "name": "get_weather", "content": "72F"},
+                {"role": "tool", "tool_call_id": "call_2", "name": "get_weather", "content": "55F"},
+                {"role": "assistant", "content": "Done"}
+            ]
+        }))
+        .unwrap();
+        let request: CopilotChatRequest = request.into();
+
+        let duplicated = request.with_tool_messages_as_user();
+
+        let roles: Vec<&str> = duplicated
+            .messages
+            .iter()
+            .map(|message| message.role.as_str())
+            .collect();
+        assert_eq!(
+            roles,
+            [
+                "user",
+                "assistant",
+                "tool",
+                "tool",
+                "user",
+                "user",
+                "assistant"
+            ]
+        );
+        assert_eq!(
+            duplicated.messages[4].content.as_ref().unwrap().to_text(),
+            "Tool 'get_weather' (call_1) returned: 72F"
+        );
+        assert_eq!(
+            duplicated.messages[5].content.as_ref().unwrap().to_text(),
+            "Tool 'get_weather' (call_2) returned: 55F"
+        );
+    }
+}
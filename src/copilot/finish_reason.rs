@@ -0,0 +1,195 @@
+use crate::openai::responses::models::prompt_response::IncompleteDetailsReason;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// Why a model stopped generating, from Copilot's `finish_reason`. Copilot passes on
+/// whatever the underlying model reports, so this maps every spelling onto the values
+/// each client API allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    ContentFilter,
+}
+
+impl FinishReason {
+    /// `None` for a choice that has not finished: a `null`, missing or empty reason.
+    /// Unknown reasons count as a normal stop.
+    pub fn parse(reason: Option<&str>) -> Option<Self> {
+        let reason = match reason?.trim() {
+            "" => return None,
+            "length" | "max_tokens" | "model_length" => FinishReason::Length,
+            "tool_calls" | "function_call" | "tool_use" => FinishReason::ToolCalls,
+            "content_filter" | "safety" | "refusal" => FinishReason::ContentFilter,
+            _ => FinishReason::Stop,
+        };
+        Some(reason)
+    }
+
+    /// The reason of a choice known to have finished, a normal stop when Copilot
+    /// gave none
+    pub fn finished(reason: Option<&str>, has_tool_calls: bool) -> Self {
+        Self::parse(reason)
+            .unwrap_or(FinishReason::Stop)
+            .with_tool_calls(has_tool_calls)
+    }
+
+    /// A normal stop after the model called tools is reported as `tool_calls`, as
+    /// some models behind Copilot say `stop`
+    pub fn with_tool_calls(self, has_tool_calls: bool) -> Self {
+        match self {
+            FinishReason::Stop if has_tool_calls => FinishReason::ToolCalls,
+            reason => reason,
+        }
+    }
+
+    /// The chat completion `finish_reason`
+    pub fn openai(self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::ContentFilter => "content_filter",
+        }
+    }
+
+    /// The legacy text completion `finish_reason`, which has no `tool_calls`
+    pub fn completion(self) -> &'static str {
+        match self {
+            FinishReason::ToolCalls => "stop",
+            reason => reason.openai(),
+        }
+    }
+
+    /// The Ollama `done_reason`, which is `stop` or `length`
+    pub fn ollama(self) -> &'static str {
+        match self {
+            FinishReason::Length => "length",
+            _ => "stop",
+        }
+    }
+
+    /// The Responses API `incomplete_details`, for a model that stopped early
+    pub fn incomplete_details(self) -> Option<IncompleteDetailsReason> {
+        let reason = match self {
+            FinishReason::Length => "max_output_tokens",
+            FinishReason::ContentFilter => "content_filter",
+            _ => return None,
+        };
+        Some(IncompleteDetailsReason {
+            reason: reason.to_string(),
+        })
+    }
+}
+
+/// Deserialize a `finish_reason` Copilot may send as `null`, as an empty string
+pub fn null_as_empty<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Rewrites the finish reasons of a streamed chat completion to the values OpenAI
+/// allows, chunk by chunk
+#[derive(Debug, Default)]
+pub struct FinishReasonStream {
+    /// Whether a tool call delta has been streamed
+    tool_calls: bool,
+}
+
+impl FinishReasonStream {
+    /// Rewrite one `data:` payload. Chunks with spec values pass through untouched.
+    pub fn rewrite(&mut self, payload: &str) -> String {
+        if !payload.contains("finish_reason") && !payload.contains("tool_calls") {
+            return payload.to_string();
+        }
+        let Ok(mut chunk) = serde_json::from_str::<Value>(payload) else {
+            return payload.to_string();
+        };
+        let Some(choices) = chunk.get_mut("choices").and_then(Value::as_array_mut) else {
+            return payload.to_string();
+        };
+
+        let mut changed = false;
+        for choice in choices {
+            if choice["delta"]["tool_calls"]
+                .as_array()
+                .is_some_and(|calls| !calls.is_empty())
+            {
+                self.tool_calls = true;
+            }
+            let Some(reason) = FinishReason::parse(choice["finish_reason"].as_str()) else {
+                continue;
+            };
+            let reason = reason.with_tool_calls(self.tool_calls).openai();
+            if choice["finish_reason"] != reason {
+                choice["finish_reason"] = reason.into();
+                changed = true;
+            }
+        }
+
+        if changed {
+            chunk.to_string()
+        } else {
+            payload.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_and_map() {
+        assert_eq!(FinishReason::parse(None), None);
+        assert_eq!(FinishReason::parse(Some("")), None);
+
+        let length = FinishReason::parse(Some("max_tokens")).unwrap();
+        assert_eq!(length.openai(), "length");
+        assert_eq!(length.ollama(), "length");
+        assert_eq!(
+            length.incomplete_details().unwrap().reason,
+            "max_output_tokens"
+        );
+
+        let tools = FinishReason::parse(Some("function_call")).unwrap();
+        assert_eq!(tools.openai(), "tool_calls");
+        assert_eq!(tools.completion(), "stop");
+        assert_eq!(tools.ollama(), "stop");
+        assert!(tools.incomplete_details().is_none());
+
+        let stop = FinishReason::parse(Some("end_turn")).unwrap();
+        assert_eq!(stop.with_tool_calls(true), FinishReason::ToolCalls);
+        assert_eq!(stop.with_tool_calls(false).openai(), "stop");
+        assert_eq!(
+            FinishReason::parse(Some("content_filter"))
+                .unwrap()
+                .incomplete_details()
+                .unwrap()
+                .reason,
+            "content_filter"
+        );
+    }
+
+    #[test]
+    fn test_stream_rewrite() {
+        let mut stream = FinishReasonStream::default();
+        let content =
+            json!({"choices": [{"delta": {"content": "Hi"}, "finish_reason": null}]}).to_string();
+        assert_eq!(stream.rewrite(&content), content);
+
+        let call = json!({"choices": [{"delta": {"tool_calls": [{"index": 0}]}}]}).to_string();
+        assert_eq!(stream.rewrite(&call), call);
+
+        let last = json!({"choices": [{"delta": {}, "finish_reason": "stop"}]}).to_string();
+        let rewritten: Value = serde_json::from_str(&stream.rewrite(&last)).unwrap();
+        assert_eq!(rewritten["choices"][0]["finish_reason"], "tool_calls");
+
+        let mut stream = FinishReasonStream::default();
+        let length = json!({"choices": [{"delta": {}, "finish_reason": "max_tokens"}]});
+        let rewritten: Value = serde_json::from_str(&stream.rewrite(&length.to_string())).unwrap();
+        assert_eq!(rewritten["choices"][0]["finish_reason"], "length");
+    }
+}
@@ -1,12 +1,29 @@
+pub mod account;
+pub mod adaptation;
+pub mod client;
+pub mod completions;
+pub mod content_filter;
+pub mod context;
+pub mod conversation;
+pub mod empty_choices;
+pub mod finish_reason;
 pub mod models;
+pub mod premium;
+pub mod presets;
+pub mod reasoning;
+pub mod stream;
+pub mod structured_outputs;
+pub mod tool_calls;
 pub mod utils;
+pub mod virtual_models;
 
-use crate::openai::completion::models::{Tool, ToolCall, ToolChoice};
+use crate::openai::completion::models::{ResponseFormat, Tool, ToolCall, ToolChoice};
+use crate::openai::responses::models::prompt_response::ReasoningEffort;
 use crate::server::openai::chat_completion::{CopilotChoice, CopilotUsage};
 use serde::{Deserialize, Serialize};
 
 /// Copilot chat completion request
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CopilotChatRequest {
     pub messages: Vec<CopilotMessage>,
     pub model: String,
@@ -20,13 +37,28 @@ pub struct CopilotChatRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// The client's end user, as OpenAI's `user`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CopilotMessage {
     pub role: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>,
+    pub content: Option<CopilotContent>,
     #[serde(default)]
     pub padding: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -35,6 +67,85 @@ pub struct CopilotMessage {
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Chain of thought reasoning models return next to the answer
+    #[serde(
+        default,
+        alias = "reasoning_content",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reasoning_text: Option<String>,
+}
+
+impl CopilotMessage {
+    /// A plain text message from `role`
+    pub fn text(role: &str, content: impl Into<CopilotContent>) -> Self {
+        CopilotMessage {
+            role: role.to_string(),
+            content: Some(content.into()),
+            padding: None,
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_text: None,
+        }
+    }
+}
+
+/// Message content: either a plain string or an array of typed parts (used for vision inputs)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum CopilotContent {
+    Text(String),
+    Parts(Vec<CopilotContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CopilotContentPart {
+    Text { text: String },
+    ImageUrl { image_url: CopilotImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CopilotImageUrl {
+    pub url: String,
+}
+
+impl CopilotContent {
+    /// Flatten the content into plain text, dropping any non-text parts
+    pub fn to_text(&self) -> String {
+        match self {
+            CopilotContent::Text(text) => text.clone(),
+            CopilotContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    CopilotContentPart::Text { text } => Some(text.as_str()),
+                    CopilotContentPart::ImageUrl { .. } => None,
+                })
+                .collect::<Vec<&str>>()
+                .join("\n"),
+        }
+    }
+
+    /// The text of this content, moved out of it when it is plain text
+    pub fn into_text(self) -> String {
+        match self {
+            CopilotContent::Text(text) => text,
+            parts => parts.to_text(),
+        }
+    }
+}
+
+impl From<String> for CopilotContent {
+    fn from(value: String) -> Self {
+        CopilotContent::Text(value)
+    }
+}
+
+impl From<&str> for CopilotContent {
+    fn from(value: &str) -> Self {
+        CopilotContent::Text(value.to_string())
+    }
 }
 
 /// Copilot chat completion response
@@ -50,4 +161,65 @@ pub struct CopilotChatResponse {
     pub choices: Vec<CopilotChoice>,
     #[serde(default)]
     pub usage: Option<CopilotUsage>,
+    /// Copilot's content filter verdicts on the prompt, under `[copilot.content_filter]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_filter_results: Option<serde_json::Value>,
+}
+
+/// One chunk of a streamed Copilot chat completion
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CopilotChatChunk {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub choices: Vec<CopilotChatChunkChoice>,
+    /// On the last chunk, when the request asked for it
+    #[serde(default)]
+    pub usage: Option<CopilotUsage>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CopilotChatChunkChoice {
+    #[serde(default)]
+    pub index: u32,
+    /// Missing from chunks carrying only content filter annotations
+    #[serde(default)]
+    pub delta: CopilotChatDelta,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+/// What a chunk adds to the reply
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CopilotChatDelta {
+    #[serde(default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default, alias = "reasoning_content")]
+    pub reasoning_text: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<CopilotToolCallDelta>>,
+}
+
+/// A piece of a streamed tool call: the call's id and name come first, its arguments in
+/// pieces after, all with the same `index`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CopilotToolCallDelta {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub function: Option<CopilotFunctionDelta>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CopilotFunctionDelta {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub arguments: Option<String>,
 }
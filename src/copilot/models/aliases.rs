@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// The catalogue id a client's model name refers to, or `None` if there is none.
+///
+/// Configured `aliases` are applied first. Then the name is tried as-is, without an
+/// Ollama-style `:tag` and without a `provider/` prefix, matching ids case-insensitively.
+pub fn resolve_model_id<T>(
+    model: &str,
+    aliases: &HashMap<String, String>,
+    catalogue: &HashMap<String, T>,
+) -> Option<String> {
+    let model = aliases.get(model).map_or(model, String::as_str);
+
+    let untagged = model.split_once(':').map_or(model, |(name, _)| name);
+    let candidates = [model, untagged, unprefixed(model), unprefixed(untagged)];
+
+    candidates.into_iter().find_map(|candidate| {
+        if catalogue.contains_key(candidate) {
+            return Some(candidate.to_string());
+        }
+        catalogue
+            .keys()
+            .find(|id| id.eq_ignore_ascii_case(candidate))
+            .cloned()
+    })
+}
+
+fn unprefixed(model: &str) -> &str {
+    model.rsplit_once('/').map_or(model, |(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalogue() -> HashMap<String, ()> {
+        ["gpt-4o", "gpt-4.1", "claude-sonnet-4.5"]
+            .into_iter()
+            .map(|id| (id.to_string(), ()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_model_id() {
+        let aliases = HashMap::from([("gpt-4".to_string(), "gpt-4.1".to_string())]);
+        let resolve = |model| resolve_model_id(model, &aliases, &catalogue());
+
+        assert_eq!(resolve("gpt-4o").as_deref(), Some("gpt-4o"));
+        assert_eq!(resolve("gpt-4").as_deref(), Some("gpt-4.1"));
+        assert_eq!(resolve("GPT-4o").as_deref(), Some("gpt-4o"));
+        assert_eq!(resolve("gpt-4o:latest").as_deref(), Some("gpt-4o"));
+        assert_eq!(
+            resolve("anthropic/claude-sonnet-4.5").as_deref(),
+            Some("claude-sonnet-4.5")
+        );
+        assert_eq!(resolve("gpt-5-turbo"), None);
+    }
+}
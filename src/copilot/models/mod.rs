@@ -1,3 +1,5 @@
+pub mod aliases;
+
 use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 
@@ -67,9 +69,108 @@ pub struct CopilotModelLimit {
     pub output: u64,
 }
 
+/// What a model accepts, as far as request adaptation is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    /// Context window size in tokens, 0 when unknown
+    pub context: u64,
+    /// Maximum output tokens, 0 when unknown
+    pub output: u64,
+    pub tool_call: bool,
+    pub vision: bool,
+    pub logprobs: bool,
+    pub reasoning_effort: bool,
+}
+
+impl CopilotModel {
+    pub fn vision(&self) -> bool {
+        self.modalities.input.iter().any(|input| input == "image")
+    }
+
+    /// Only OpenAI's non-reasoning GPT models return token log probabilities
+    pub fn logprobs(&self) -> bool {
+        self.family.starts_with("gpt") && !self.reasoning
+    }
+}
+
+impl From<&CopilotModel> for ModelCapabilities {
+    fn from(model: &CopilotModel) -> Self {
+        ModelCapabilities {
+            context: model.limit.context,
+            output: model.limit.output,
+            tool_call: model.tool_call,
+            vision: model.vision(),
+            logprobs: model.logprobs(),
+            reasoning_effort: model.reasoning,
+        }
+    }
+}
+
+/// Query parameters narrowing `/v1/models` and `/api/tags`, e.g. `?tool_call=true&family=gpt`.
+///
+/// `capability` takes Ollama capability names, comma-separated: `tools`, `vision`, `thinking`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ModelFilter {
+    pub tool_call: Option<bool>,
+    pub vision: Option<bool>,
+    pub reasoning: Option<bool>,
+    pub family: Option<String>,
+    pub capability: Option<String>,
+}
+
+impl ModelFilter {
+    pub fn is_empty(&self) -> bool {
+        self.tool_call.is_none()
+            && self.vision.is_none()
+            && self.reasoning.is_none()
+            && self.family.is_none()
+            && self.capability.is_none()
+    }
+
+    /// Unknown capability names, which no model can match
+    pub fn validate(&self) -> Result<(), String> {
+        for capability in self.capabilities() {
+            if !["tools", "vision", "thinking"].contains(&capability) {
+                return Err(format!(
+                    "Unknown capability \"{}\", expected tools, vision or thinking",
+                    capability
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, model: &CopilotModel) -> bool {
+        let flag =
+            |wanted: Option<bool>, actual: bool| wanted.is_none_or(|wanted| wanted == actual);
+
+        flag(self.tool_call, model.tool_call)
+            && flag(self.vision, model.vision())
+            && flag(self.reasoning, model.reasoning)
+            && self
+                .family
+                .as_ref()
+                .is_none_or(|family| family.eq_ignore_ascii_case(&model.family))
+            && self.capabilities().all(|capability| match capability {
+                "tools" => model.tool_call,
+                "vision" => model.vision(),
+                "thinking" => model.reasoning,
+                _ => false,
+            })
+    }
+
+    fn capabilities(&self) -> impl Iterator<Item = &str> {
+        self.capability
+            .iter()
+            .flat_map(|capability| capability.split(','))
+            .map(str::trim)
+            .filter(|capability| !capability.is_empty())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::copilot::models::CopilotModelsResponse;
+    use crate::copilot::models::{CopilotModelsResponse, ModelFilter};
 
     #[test]
     fn test_parse_json_models_response() {
@@ -79,4 +180,70 @@ mod tests {
 
         assert_eq!(2, result.models.len())
     }
+
+    #[test]
+    fn test_model_filter() {
+        let json = include_str!("../../resources/models_response.json");
+        let response = serde_json::from_str::<CopilotModelsResponse>(json).unwrap();
+        let matching = |filter: ModelFilter| {
+            let mut ids: Vec<_> = response
+                .models
+                .iter()
+                .filter(|model| filter.matches(model))
+                .map(|model| model.id.as_str())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert!(ModelFilter::default().is_empty());
+        assert_eq!(matching(ModelFilter::default()), vec!["gpt-4.1", "gpt-4o"]);
+        assert_eq!(
+            matching(ModelFilter {
+                tool_call: Some(true),
+                vision: Some(true),
+                family: Some("GPT".to_string()),
+                ..Default::default()
+            }),
+            vec!["gpt-4.1", "gpt-4o"]
+        );
+        assert!(
+            matching(ModelFilter {
+                family: Some("claude".to_string()),
+                ..Default::default()
+            })
+            .is_empty()
+        );
+        assert!(
+            matching(ModelFilter {
+                vision: Some(false),
+                ..Default::default()
+            })
+            .is_empty()
+        );
+        assert!(
+            matching(ModelFilter {
+                capability: Some("thinking".to_string()),
+                ..Default::default()
+            })
+            .is_empty()
+        );
+        assert_eq!(
+            matching(ModelFilter {
+                capability: Some("tools, vision".to_string()),
+                ..Default::default()
+            }),
+            vec!["gpt-4.1", "gpt-4o"]
+        );
+    }
+
+    #[test]
+    fn test_model_filter_rejects_unknown_capability() {
+        let filter = ModelFilter {
+            capability: Some("tools,embedding".to_string()),
+            ..Default::default()
+        };
+
+        assert!(filter.validate().is_err());
+    }
 }
@@ -0,0 +1,61 @@
+use crate::config::PremiumConfig;
+
+/// Estimated premium request multipliers of Copilot models on paid plans.
+/// Models at 0 are included in the plan and do not consume premium requests.
+const MULTIPLIERS: &[(&str, f64)] = &[
+    ("gpt-4o", 0.0),
+    ("gpt-4o-mini", 0.0),
+    ("gpt-4.1", 0.0),
+    ("gpt-5-mini", 0.0),
+    ("grok-code-fast-1", 0.0),
+    ("gpt-5", 1.0),
+    ("o3", 1.0),
+    ("o3-mini", 0.33),
+    ("o4-mini", 0.33),
+    ("o1", 10.0),
+    ("gpt-4.5", 50.0),
+    ("claude-3.5-sonnet", 1.0),
+    ("claude-3.7-sonnet", 1.0),
+    ("claude-3.7-sonnet-thought", 1.25),
+    ("claude-sonnet-4", 1.0),
+    ("claude-sonnet-4.5", 1.0),
+    ("claude-opus-4", 10.0),
+    ("claude-opus-4.1", 10.0),
+    ("gemini-2.0-flash-001", 0.25),
+    ("gemini-2.5-pro", 1.0),
+];
+
+/// Premium requests one request to `model` consumes
+pub fn multiplier(config: &PremiumConfig, model: &str) -> f64 {
+    config
+        .multipliers
+        .get(model)
+        .copied()
+        .or_else(|| {
+            MULTIPLIERS
+                .iter()
+                .find(|(id, _)| *id == model)
+                .map(|(_, multiplier)| *multiplier)
+        })
+        .unwrap_or(config.default_multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_multiplier_lookup_order() {
+        let config = PremiumConfig {
+            multipliers: HashMap::from([("claude-opus-4".to_string(), 5.0)]),
+            default_multiplier: 2.0,
+            monthly_budget: None,
+        };
+
+        assert_eq!(multiplier(&config, "claude-opus-4"), 5.0);
+        assert_eq!(multiplier(&config, "gpt-4.1"), 0.0);
+        assert_eq!(multiplier(&config, "o4-mini"), 0.33);
+        assert_eq!(multiplier(&config, "brand-new-model"), 2.0);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::PresetConfig;
+use crate::copilot::{CopilotChatRequest, CopilotMessage};
+use std::collections::HashMap;
+
+/// Model names starting with this select a configured preset
+pub const PRESET_PREFIX: &str = "preset:";
+
+impl CopilotChatRequest {
+    /// Expands a `preset:<name>` model into the preset's model, system prompt and
+    /// sampling settings. Requests for a regular model are left untouched.
+    pub fn expand_preset(&mut self, presets: &HashMap<String, PresetConfig>) -> Result<(), String> {
+        let Some(name) = self.model.strip_prefix(PRESET_PREFIX) else {
+            return Ok(());
+        };
+
+        let preset = presets
+            .get(name)
+            .ok_or_else(|| format!("Unknown preset \"{}\"", name))?;
+
+        self.model = preset.model.clone();
+
+        if let Some(system_prompt) = &preset.system_prompt {
+            self.messages
+                .insert(0, CopilotMessage::text("system", system_prompt.as_str()));
+        }
+
+        if self.temperature.is_none() {
+            self.temperature = preset.temperature;
+        }
+        if self.stop.is_none() {
+            self.stop = preset.stop.clone();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presets() -> HashMap<String, PresetConfig> {
+        HashMap::from([(
+            "code-review".to_string(),
+            PresetConfig {
+                model: "gpt-4o".to_string(),
+                system_prompt: Some("You are a strict code reviewer.".to_string()),
+                temperature: Some(0.2),
+                stop: Some(vec!["END".to_string()]),
+            },
+        )])
+    }
+
+    fn request(model: &str, temperature: Option<f32>) -> CopilotChatRequest {
+        CopilotChatRequest {
+            messages: vec![CopilotMessage {
+                role: "user".to_string(),
+                content: Some("Review this".into()),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            }],
+            model: model.to_string(),
+            temperature,
+            max_tokens: None,
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        }
+    }
+
+    #[test]
+    fn test_expand_preset() {
+        let mut request = request("preset:code-review", None);
+
+        request.expand_preset(&presets()).unwrap();
+
+        assert_eq!(request.model, "gpt-4o");
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, "system");
+        assert_eq!(
+            request.messages[0].content.as_ref().unwrap().to_text(),
+            "You are a strict code reviewer."
+        );
+        assert_eq!(request.temperature, Some(0.2));
+        assert_eq!(request.stop, Some(vec!["END".to_string()]));
+    }
+
+    #[test]
+    fn test_client_settings_win_over_preset() {
+        let mut request = request("preset:code-review", Some(0.9));
+
+        request.expand_preset(&presets()).unwrap();
+
+        assert_eq!(request.temperature, Some(0.9));
+    }
+
+    #[test]
+    fn test_regular_and_unknown_models() {
+        let mut regular = request("gpt-4o", None);
+        regular.expand_preset(&presets()).unwrap();
+        assert_eq!(regular.messages.len(), 1);
+        assert_eq!(regular.temperature, None);
+
+        let mut unknown = request("preset:missing", None);
+        assert_eq!(
+            unknown.expand_preset(&presets()),
+            Err("Unknown preset \"missing\"".to_string())
+        );
+    }
+}
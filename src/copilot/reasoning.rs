@@ -0,0 +1,195 @@
+use crate::config::ReasoningOutput;
+use crate::copilot::CopilotChatResponse;
+use serde_json::Value;
+
+/// Names upstream models use for reasoning deltas, Copilot's own first
+const REASONING_FIELDS: [&str; 3] = ["reasoning_text", "reasoning_content", "reasoning"];
+
+/// `content` with `reasoning` prepended in `<think>` tags, as clients that parse them expect
+pub fn fold(reasoning: &str, content: &str) -> String {
+    format!("<think>\n{}\n</think>\n\n{}", reasoning, content)
+}
+
+impl CopilotChatResponse {
+    /// Strip or fold every choice's reasoning, per `[copilot.reasoning] output`
+    pub fn apply_reasoning_output(&mut self, output: ReasoningOutput) {
+        for choice in &mut self.choices {
+            let message = &mut choice.message;
+            match output {
+                ReasoningOutput::Include => {}
+                ReasoningOutput::Strip => message.reasoning_text = None,
+                ReasoningOutput::Fold => {
+                    if let Some(reasoning) = message.reasoning_text.take() {
+                        let content = message
+                            .content
+                            .as_ref()
+                            .map(|content| content.to_text())
+                            .unwrap_or_default();
+                        message.content = Some(fold(&reasoning, &content).into());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rewrites the reasoning deltas of a streamed chat completion, chunk by chunk
+#[derive(Debug)]
+pub struct ReasoningStream {
+    output: ReasoningOutput,
+    /// Whether a folded `<think>` block has been opened and not yet closed
+    open: bool,
+}
+
+impl ReasoningStream {
+    pub fn new(output: ReasoningOutput) -> Self {
+        ReasoningStream {
+            output,
+            open: false,
+        }
+    }
+
+    /// Rewrite one `data:` payload. Chunks without reasoning pass through untouched.
+    pub fn rewrite(&mut self, payload: &str) -> String {
+        let Ok(mut chunk) = serde_json::from_str::<Value>(payload) else {
+            return payload.to_string();
+        };
+        let Some(choices) = chunk.get_mut("choices").and_then(Value::as_array_mut) else {
+            return payload.to_string();
+        };
+
+        let mut changed = false;
+        for choice in choices {
+            let finished = choice
+                .get("finish_reason")
+                .is_some_and(|reason| !reason.is_null());
+            let Some(delta) = choice.get_mut("delta").and_then(Value::as_object_mut) else {
+                continue;
+            };
+
+            let reasoning = REASONING_FIELDS
+                .iter()
+                .filter_map(|field| delta.remove(*field))
+                .find_map(|value| value.as_str().map(str::to_string));
+            if reasoning.is_some() {
+                changed = true;
+            }
+
+            match self.output {
+                ReasoningOutput::Include => {
+                    if let Some(reasoning) = reasoning {
+                        delta.insert("reasoning_content".into(), reasoning.clone().into());
+                        delta.insert("reasoning".into(), reasoning.into());
+                    }
+                }
+                ReasoningOutput::Strip => {}
+                ReasoningOutput::Fold => {
+                    let content = delta
+                        .get("content")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_string();
+                    let mut text = String::new();
+                    if let Some(reasoning) = reasoning {
+                        if !self.open {
+                            text.push_str("<think>\n");
+                            self.open = true;
+                        }
+                        text.push_str(&reasoning);
+                    }
+                    if self.open && (!content.is_empty() || finished) {
+                        text.push_str("\n</think>\n\n");
+                        self.open = false;
+                        changed = true;
+                    }
+                    if changed {
+                        text.push_str(&content);
+                        delta.insert("content".into(), text.into());
+                    }
+                }
+            }
+        }
+
+        if changed {
+            chunk.to_string()
+        } else {
+            payload.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn chunk(delta: Value, finish_reason: Option<&str>) -> String {
+        json!({"choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]})
+            .to_string()
+    }
+
+    fn delta(payload: &str) -> Value {
+        serde_json::from_str::<Value>(payload).unwrap()["choices"][0]["delta"].clone()
+    }
+
+    #[test]
+    fn test_stream_include_and_strip() {
+        let thought = chunk(json!({"reasoning_text": "Hmm"}), None);
+        let answer = chunk(json!({"content": "Hi"}), None);
+
+        let mut include = ReasoningStream::new(ReasoningOutput::Include);
+        assert_eq!(
+            delta(&include.rewrite(&thought)),
+            json!({"reasoning_content": "Hmm", "reasoning": "Hmm"})
+        );
+        assert_eq!(include.rewrite(&answer), answer);
+
+        let mut strip = ReasoningStream::new(ReasoningOutput::Strip);
+        assert_eq!(delta(&strip.rewrite(&thought)), json!({}));
+    }
+
+    #[test]
+    fn test_stream_fold() {
+        let mut fold = ReasoningStream::new(ReasoningOutput::Fold);
+
+        let folded: Vec<String> = [
+            chunk(json!({"reasoning_text": "Let me "}), None),
+            chunk(json!({"reasoning_text": "think"}), None),
+            chunk(json!({"content": "4"}), None),
+            chunk(json!({"content": "2"}), Some("stop")),
+        ]
+        .iter()
+        .map(|payload| {
+            delta(&fold.rewrite(payload))["content"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+
+        assert_eq!(folded.concat(), "<think>\nLet me think\n</think>\n\n42");
+    }
+
+    #[test]
+    fn test_response_fold() {
+        let mut response: CopilotChatResponse = serde_json::from_value(json!({
+            "id": "x",
+            "model": "claude-sonnet-4",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "4", "reasoning_text": "2 + 2"},
+                "finish_reason": "stop"
+            }]
+        }))
+        .unwrap();
+
+        response.apply_reasoning_output(ReasoningOutput::Fold);
+
+        let message = &response.choices[0].message;
+        assert!(message.reasoning_text.is_none());
+        assert_eq!(
+            message.content.as_ref().unwrap().to_text(),
+            "<think>\n2 + 2\n</think>\n\n4"
+        );
+    }
+}
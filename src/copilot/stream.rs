@@ -0,0 +1,278 @@
+use crate::copilot::CopilotChatChunk;
+use crate::copilot::finish_reason::FinishReason;
+use crate::server::openai::chat_completion::CopilotUsage;
+use crate::server::stream_errors::malformed_chunk;
+use futures_util::{Stream, StreamExt, TryStreamExt, stream};
+use std::io::Error;
+use tracing::log::warn;
+
+/// A piece of a streamed Copilot chat reply
+#[derive(Debug, PartialEq)]
+#[non_exhaustive]
+pub enum ChatDelta {
+    /// More text of the reply
+    Content(String),
+    /// More of the model's reasoning, from models that share it
+    Reasoning(String),
+    /// Part of a tool call: its id and name come first, the arguments in pieces after
+    ToolCall(ToolCallDelta),
+    /// Tokens the request used, near the end when Copilot reports them
+    Usage(CopilotUsage),
+    /// The end of the reply, and why the model stopped
+    Done(FinishReason),
+}
+
+/// Part of a streamed tool call. Pieces with the same `index` belong to the same call.
+#[derive(Debug, Default, PartialEq)]
+pub struct ToolCallDelta {
+    pub index: u32,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub arguments: String,
+}
+
+/// What one line of Copilot's SSE stream holds
+#[derive(Debug)]
+pub enum StreamLine {
+    /// The deltas of a `data:` chunk, `Done` for `data: [DONE]`
+    Deltas(Vec<ChatDelta>),
+    /// An empty line
+    Skip,
+    /// A line other than `data:`, or a chunk of a shape Copilot does not usually send
+    Unexpected(String),
+    /// A `data:` payload that is not JSON at all
+    Malformed(Error),
+}
+
+/// Turns the lines of Copilot's SSE stream into [`ChatDelta`]s, remembering why the
+/// model stopped until `data: [DONE]` ends the stream. [`CopilotClient::chat_stream`] and
+/// the Ollama endpoints stream through it; `/v1/chat/completions` and `/v1/responses` still
+/// read Copilot's chunks themselves.
+///
+/// [`CopilotClient::chat_stream`]: crate::copilot::client::CopilotClient::chat_stream
+#[derive(Debug, Default)]
+pub struct ChatDeltas {
+    finished: Option<FinishReason>,
+    tool_calls: bool,
+}
+
+impl ChatDeltas {
+    /// The deltas on `line`
+    pub fn line(&mut self, line: &str) -> StreamLine {
+        let Some(payload) = line.strip_prefix("data:").map(str::trim) else {
+            if line.trim().is_empty() {
+                return StreamLine::Skip;
+            }
+            warn!("Unexpected SSE line from Copilot: {}", line);
+            return StreamLine::Unexpected(line.to_string());
+        };
+        if payload.is_empty() {
+            return StreamLine::Skip;
+        }
+        if payload == "[DONE]" {
+            let reason = self
+                .finished
+                .unwrap_or(FinishReason::Stop)
+                .with_tool_calls(self.tool_calls);
+            return StreamLine::Deltas(vec![ChatDelta::Done(reason)]);
+        }
+
+        match serde_json::from_str::<CopilotChatChunk>(payload) {
+            Ok(chunk) => StreamLine::Deltas(self.chunk(chunk)),
+            Err(e) => match malformed_chunk(&e, payload) {
+                Some(e) => StreamLine::Malformed(e),
+                None => {
+                    warn!("Failed to parse Copilot SSE chunk: {} — {}", e, payload);
+                    StreamLine::Unexpected(payload.to_string())
+                }
+            },
+        }
+    }
+
+    fn chunk(&mut self, chunk: CopilotChatChunk) -> Vec<ChatDelta> {
+        let mut deltas = Vec::new();
+        for choice in chunk.choices {
+            let delta = choice.delta;
+            if let Some(reasoning) = delta.reasoning_text.filter(|text| !text.is_empty()) {
+                deltas.push(ChatDelta::Reasoning(reasoning));
+            }
+            if let Some(content) = delta.content.filter(|text| !text.is_empty()) {
+                deltas.push(ChatDelta::Content(content));
+            }
+            for call in delta.tool_calls.into_iter().flatten() {
+                self.tool_calls = true;
+                let function = call.function.unwrap_or_default();
+                deltas.push(ChatDelta::ToolCall(ToolCallDelta {
+                    index: call.index,
+                    id: call.id,
+                    name: function.name,
+                    arguments: function.arguments.unwrap_or_default(),
+                }));
+            }
+            if let Some(reason) = FinishReason::parse(choice.finish_reason.as_deref()) {
+                self.finished = Some(reason);
+            }
+        }
+        if let Some(usage) = chunk.usage {
+            deltas.push(ChatDelta::Usage(usage));
+        }
+        deltas
+    }
+}
+
+/// The deltas of a streamed Copilot reply, from the lines of its SSE body. The stream
+/// fails on a `data:` payload that is not JSON; other unexpected lines are skipped.
+pub fn chat_deltas<S>(lines: S) -> impl Stream<Item = Result<ChatDelta, Error>> + Unpin
+where
+    S: Stream<Item = Result<String, Error>> + Unpin,
+{
+    let mut parser = ChatDeltas::default();
+    lines
+        .map_ok(move |line| parser.line(&line))
+        .flat_map(|line| {
+            let deltas = match line {
+                Ok(StreamLine::Deltas(deltas)) => deltas.into_iter().map(Ok).collect(),
+                Ok(StreamLine::Skip | StreamLine::Unexpected(_)) => Vec::new(),
+                Ok(StreamLine::Malformed(e)) | Err(e) => vec![Err(e)],
+            };
+            stream::iter(deltas)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::sse_lines::{SseLines, chunked};
+    use futures_util::FutureExt;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    fn deltas(lines: &[&str]) -> Vec<ChatDelta> {
+        let mut parser = ChatDeltas::default();
+        lines
+            .iter()
+            .flat_map(|line| match parser.line(line) {
+                StreamLine::Deltas(deltas) => deltas,
+                other => panic!("expected deltas, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_content_reasoning_and_done() {
+        let deltas = deltas(&[
+            r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","reasoning_text":"Hmm"}}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":"length"}]}"#,
+            r#"data: {"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":1,"total_tokens":4}}"#,
+            "data: [DONE]",
+        ]);
+        assert_eq!(
+            deltas,
+            vec![
+                ChatDelta::Reasoning("Hmm".to_string()),
+                ChatDelta::Content("Hi".to_string()),
+                ChatDelta::Usage(CopilotUsage {
+                    prompt_tokens: 3,
+                    completion_tokens: 1,
+                    total_tokens: 4,
+                }),
+                ChatDelta::Done(FinishReason::Length),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_calls() {
+        let deltas = deltas(&[
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]}}]}"#,
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":\"Paris\"}"}}]},"finish_reason":"stop"}]}"#,
+            "data: [DONE]",
+        ]);
+        assert_eq!(
+            deltas,
+            vec![
+                ChatDelta::ToolCall(ToolCallDelta {
+                    index: 0,
+                    id: Some("call_1".to_string()),
+                    name: Some("get_weather".to_string()),
+                    arguments: String::new(),
+                }),
+                ChatDelta::ToolCall(ToolCallDelta {
+                    arguments: r#"{"city":"Paris"}"#.to_string(),
+                    ..Default::default()
+                }),
+                ChatDelta::Done(FinishReason::ToolCalls),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_other_lines() {
+        let mut parser = ChatDeltas::default();
+        assert!(matches!(parser.line(""), StreamLine::Skip));
+        assert!(matches!(
+            parser.line("event: ping"),
+            StreamLine::Unexpected(_)
+        ));
+        assert!(matches!(
+            parser.line(r#"data: {"choices": "none"}"#),
+            StreamLine::Unexpected(_)
+        ));
+        assert!(matches!(
+            parser.line("data: {not valid json}"),
+            StreamLine::Malformed(_)
+        ));
+    }
+
+    /// An SSE body streaming `pieces`, with each of `noise` after the piece it pairs with
+    fn sse_body(pieces: &[String], noise: &[&str]) -> String {
+        let mut body = String::new();
+        for (i, piece) in pieces.iter().enumerate() {
+            let chunk = json!({"choices": [{"index": 0, "delta": {"content": piece}}]});
+            body.push_str(&format!("data: {}\n\n", chunk));
+            if let Some(noise) = noise.get(i) {
+                body.push_str(noise);
+            }
+        }
+        body.push_str("data: [DONE]\n\n");
+        body
+    }
+
+    proptest! {
+        #[test]
+        fn prop_any_line_is_handled(line in any::<String>()) {
+            let mut parser = ChatDeltas::default();
+            parser.line(&line);
+            parser.line(&format!("data: {}", line));
+        }
+
+        #[test]
+        fn prop_content_survives_chunking_and_other_events(
+            pieces in proptest::collection::vec(any::<String>(), 0..8),
+            noise in proptest::collection::vec(
+                prop::sample::select(vec!["", "\n", ": keep-alive\n\n", "event: ping\n", "data:\n"]),
+                0..8,
+            ),
+            cuts in proptest::collection::vec(any::<usize>(), 0..16),
+        ) {
+            let body = sse_body(&pieces, &noise);
+            let lines = SseLines::new(chunked(body.as_bytes(), &cuts));
+            let deltas: Vec<ChatDelta> = chat_deltas(lines)
+                .map(|delta| delta.unwrap())
+                .collect()
+                .now_or_never()
+                .unwrap();
+
+            let (last, deltas) = deltas.split_last().unwrap();
+            prop_assert_eq!(last, &ChatDelta::Done(FinishReason::Stop));
+            let content: String = deltas
+                .iter()
+                .map(|delta| match delta {
+                    ChatDelta::Content(text) => text.as_str(),
+                    other => panic!("expected content, got {:?}", other),
+                })
+                .collect();
+            prop_assert_eq!(content, pieces.concat());
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::copilot::tool_calls::validate;
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse, CopilotMessage};
+use crate::openai::completion::models::ResponseFormat;
+use serde_json::Value;
+
+/// Response header with the number of corrective retries a strict schema needed
+pub const SCHEMA_RETRIES_HEADER: &str = "X-Passenger-Schema-Retries";
+
+/// Response header saying whether the returned output matches the strict schema
+pub const SCHEMA_HEADER: &str = "X-Passenger-Schema";
+
+/// Outcome of enforcing a strict `response_format` schema
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SchemaReport {
+    pub retries: u32,
+    /// Why the returned output does not match the schema, if it does not
+    pub error: Option<String>,
+}
+
+impl SchemaReport {
+    /// Value for [`SCHEMA_HEADER`]
+    pub fn header_value(&self) -> &'static str {
+        if self.error.is_some() {
+            "invalid"
+        } else {
+            "valid"
+        }
+    }
+}
+
+impl CopilotChatRequest {
+    /// The schema of a `json_schema` response format with `strict: true`
+    pub fn strict_schema(&self) -> Option<&Value> {
+        match &self.response_format {
+            Some(ResponseFormat::JsonSchema { json_schema })
+                if json_schema.strict == Some(true) =>
+            {
+                json_schema.schema.as_ref()
+            }
+            _ => None,
+        }
+    }
+
+    /// This request followed by the rejected reply and a message asking the model to fix it
+    pub fn with_schema_correction(&self, reply: &str, error: &str) -> CopilotChatRequest {
+        let mut request = self.clone();
+        request
+            .messages
+            .push(CopilotMessage::text("assistant", reply));
+        request.messages.push(CopilotMessage::text(
+            "user",
+            format!(
+                "Your reply does not match the required JSON schema: {}. \
+                 Reply again with only the corrected JSON, without any other text.",
+                error
+            ),
+        ));
+        request
+    }
+}
+
+/// Text of the first choice, if it has any. Replies made of tool calls have none.
+pub fn output_text(response: &CopilotChatResponse) -> Option<String> {
+    response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.as_ref())
+        .map(|content| content.to_text())
+}
+
+/// Check `output` parses as JSON matching `schema`
+pub fn check_output(output: &str, schema: &Value) -> Result<(), String> {
+    let value: Value = serde_json::from_str(output.trim())
+        .map_err(|e| format!("the reply is not valid JSON ({})", e))?;
+    validate(&value, schema, "$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::completion::models::JsonSchemaFormat;
+    use serde_json::json;
+
+    fn request(strict: Option<bool>) -> CopilotChatRequest {
+        CopilotChatRequest {
+            messages: vec![CopilotMessage {
+                role: "user".to_string(),
+                content: Some("Name a city".into()),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            }],
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            logprobs: None,
+            top_logprobs: None,
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat {
+                    name: "city".to_string(),
+                    description: None,
+                    schema: Some(json!({
+                        "type": "object",
+                        "properties": { "name": { "type": "string" } },
+                        "required": ["name"],
+                        "additionalProperties": false
+                    })),
+                    strict,
+                },
+            }),
+            reasoning_effort: None,
+            user: None,
+        }
+    }
+
+    #[test]
+    fn test_strict_schema_and_check_output() {
+        assert!(request(None).strict_schema().is_none());
+        assert!(request(Some(false)).strict_schema().is_none());
+
+        let request = request(Some(true));
+        let schema = request.strict_schema().unwrap();
+        assert_eq!(check_output(" {\"name\": \"Paris\"} ", schema), Ok(()));
+        assert_eq!(
+            check_output("{}", schema),
+            Err("$ is missing required property \"name\"".to_string())
+        );
+        assert!(
+            check_output("Paris", schema)
+                .unwrap_err()
+                .starts_with("the reply is not valid JSON")
+        );
+    }
+
+    #[test]
+    fn test_with_schema_correction() {
+        let corrected = request(Some(true)).with_schema_correction("{}", "$ is missing \"name\"");
+
+        assert_eq!(corrected.messages.len(), 3);
+        assert_eq!(corrected.messages[1].role, "assistant");
+        assert_eq!(corrected.messages[1].content, Some("{}".into()));
+        assert_eq!(corrected.messages[2].role, "user");
+        assert!(
+            corrected.messages[2]
+                .content
+                .as_ref()
+                .unwrap()
+                .to_text()
+                .contains("$ is missing \"name\"")
+        );
+    }
+}
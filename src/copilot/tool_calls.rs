@@ -0,0 +1,468 @@
+use crate::config::CopilotToolCallsConfig;
+use crate::copilot::CopilotChatResponse;
+use crate::openai::completion::models::Tool;
+use serde_json::Value;
+use tracing::log::warn;
+
+/// Response header set when tool-call arguments were repaired or failed validation
+pub const TOOL_CALLS_HEADER: &str = "X-Passenger-Tool-Calls";
+
+/// Repair and validation of tool-call `arguments` in a non-streaming Copilot response,
+/// per `[copilot.tool_calls]`. Built from the tools declared on the request.
+#[derive(Debug, Default, Clone)]
+pub struct ToolCallCheck {
+    tools: Vec<Tool>,
+    repair: bool,
+    validate: bool,
+}
+
+/// What [`ToolCallCheck::apply`] did to a response
+#[derive(Debug, Default, PartialEq)]
+pub struct ToolCallReport {
+    pub repaired: usize,
+    pub invalid: Vec<String>,
+}
+
+impl ToolCallReport {
+    /// Value for [`TOOL_CALLS_HEADER`], if there is anything to report
+    pub fn header_value(&self) -> Option<&'static str> {
+        if !self.invalid.is_empty() {
+            Some("invalid")
+        } else if self.repaired > 0 {
+            Some("repaired")
+        } else {
+            None
+        }
+    }
+}
+
+impl ToolCallCheck {
+    pub fn new(config: &CopilotToolCallsConfig, tools: Option<&[Tool]>) -> Self {
+        Self {
+            tools: tools.map(<[Tool]>::to_vec).unwrap_or_default(),
+            repair: config.repair,
+            validate: config.validate,
+        }
+    }
+
+    /// Repair and validate every tool call in `response`, rewriting repaired arguments
+    pub fn apply(&self, response: &mut CopilotChatResponse) -> ToolCallReport {
+        let mut report = ToolCallReport::default();
+        if !self.repair && !self.validate {
+            return report;
+        }
+
+        let tool_calls = response
+            .choices
+            .iter_mut()
+            .filter_map(|choice| choice.message.tool_calls.as_mut())
+            .flatten();
+
+        for tool_call in tool_calls {
+            let function = &mut tool_call.function;
+
+            let parsed = match serde_json::from_str::<Value>(&function.arguments) {
+                Ok(value) => Some(value),
+                Err(_) if self.repair => match repair_arguments(&function.arguments) {
+                    Some(value) => {
+                        warn!(
+                            "Repaired malformed arguments for tool call {}: {}",
+                            function.name, function.arguments
+                        );
+                        function.arguments = value.to_string();
+                        report.repaired += 1;
+                        Some(value)
+                    }
+                    None => None,
+                },
+                Err(_) => None,
+            };
+
+            let Some(arguments) = parsed else {
+                let problem = format!("{}: arguments are not valid JSON", function.name);
+                warn!("Invalid tool call from Copilot: {}", problem);
+                report.invalid.push(problem);
+                continue;
+            };
+
+            if !self.validate {
+                continue;
+            }
+
+            let schema = self
+                .tools
+                .iter()
+                .find(|tool| tool.function.name == function.name)
+                .map(|tool| &tool.function.parameters);
+
+            let result = match schema {
+                Some(schema) => validate(&arguments, schema, "arguments"),
+                None => Err("no tool with this name was declared".to_string()),
+            };
+
+            if let Err(e) = result {
+                let problem = format!("{}: {}", function.name, e);
+                warn!("Invalid tool call from Copilot: {}", problem);
+                report.invalid.push(problem);
+            }
+        }
+
+        report
+    }
+}
+
+/// Turn near-JSON into a JSON object: code fences, text around the object, single-quoted
+/// strings, bare keys, trailing commas and Python-style `True`/`False`/`None` are tolerated.
+pub fn repair_arguments(raw: &str) -> Option<Value> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return Some(Value::Object(Default::default()));
+    }
+
+    let object = extract_object(trimmed)?;
+    let normalized = normalize(object);
+
+    match serde_json::from_str::<Value>(&normalized) {
+        Ok(value @ Value::Object(_)) => Some(value),
+        _ => None,
+    }
+}
+
+/// The first balanced `{...}`, skipping anything before or after it
+fn extract_object(text: &str) -> Option<&str> {
+    let start = text.find('{')?;
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+
+    for (offset, c) in text[start..].char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&text[start..start + offset + 1]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Rewrite single-quoted strings, drop trailing commas and map Python literals
+fn normalize(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '\'' => {
+                out.push('"');
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    match chars[i] {
+                        '\\' if i + 1 < chars.len() => {
+                            // `\'` is not a JSON escape
+                            if chars[i + 1] != '\'' {
+                                out.push('\\');
+                            }
+                            out.push(chars[i + 1]);
+                            i += 2;
+                            continue;
+                        }
+                        '"' => out.push_str("\\\""),
+                        other => out.push(other),
+                    }
+                    i += 1;
+                }
+                out.push('"');
+                i += 1;
+            }
+            ',' => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if !matches!(next, Some('}') | Some(']')) {
+                    out.push(',');
+                }
+                i += 1;
+            }
+            c if c.is_ascii_alphabetic() => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| !c.is_ascii_alphanumeric() && *c != '_')
+                    .map_or(chars.len(), |len| i + len);
+                let word: String = chars[i..end].iter().collect();
+                let is_key = chars[end..].iter().find(|c| !c.is_whitespace()) == Some(&':');
+                match word.as_str() {
+                    _ if is_key => out.push_str(&format!("\"{}\"", word)),
+                    "True" => out.push_str("true"),
+                    "False" => out.push_str("false"),
+                    "None" => out.push_str("null"),
+                    _ => out.push_str(&word),
+                }
+                i = end;
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Check `value` against the JSON Schema subset tool definitions use in practice:
+/// `type`, `properties`, `required`, `additionalProperties: false`, `enum` and `items`.
+pub fn validate(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let allowed: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| has_type(value, t)) {
+            return Err(format!("{} should be {}", path, allowed.join(" or ")));
+        }
+    }
+
+    if let Some(Value::Array(options)) = schema.get("enum")
+        && !options.contains(value)
+    {
+        return Err(format!("{} is not one of the allowed values", path));
+    }
+
+    if let Value::Object(object) = value {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    return Err(format!(
+                        "{} is missing required property \"{}\"",
+                        path, name
+                    ));
+                }
+            }
+        }
+
+        for (name, property) in object {
+            let property_path = format!("{}.{}", path, name);
+            match properties.and_then(|p| p.get(name)) {
+                Some(property_schema) => validate(property, property_schema, &property_path)?,
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    return Err(format!("{} is not an allowed property", property_path));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate(item, item_schema, &format!("{}[{}]", path, index))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn weather_tool() -> Tool {
+        serde_json::from_value(json!({
+            "type": "function",
+            "function": {
+                "name": "get_weather",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "city": {"type": "string"},
+                        "unit": {"type": "string", "enum": ["c", "f"]},
+                        "days": {"type": "integer"}
+                    },
+                    "required": ["city"],
+                    "additionalProperties": false
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn response_with_arguments(arguments: &str) -> CopilotChatResponse {
+        serde_json::from_value(json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": arguments}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn arguments(response: &CopilotChatResponse) -> &str {
+        &response.choices[0].message.tool_calls.as_ref().unwrap()[0]
+            .function
+            .arguments
+    }
+
+    fn check(repair: bool, validate: bool) -> ToolCallCheck {
+        let config = CopilotToolCallsConfig { repair, validate };
+        ToolCallCheck::new(&config, Some(&[weather_tool()]))
+    }
+
+    #[test]
+    fn test_repair_arguments() {
+        let cases = [
+            (
+                r#"{"city": "Paris"} I hope this helps"#,
+                json!({"city": "Paris"}),
+            ),
+            (
+                "```json\n{\"city\": \"Paris\"}\n```",
+                json!({"city": "Paris"}),
+            ),
+            (
+                "{'city': 'Paris', 'note': 'it\\'s \"nice\"'}",
+                json!({"city": "Paris", "note": "it's \"nice\""}),
+            ),
+            (
+                r#"{"city": "Paris", "days": [1, 2,],}"#,
+                json!({"city": "Paris", "days": [1, 2]}),
+            ),
+            (
+                r#"{"rain": True, "snow": False, "wind": None}"#,
+                json!({"rain": true, "snow": false, "wind": null}),
+            ),
+            (r#"{city: "Paris"}"#, json!({"city": "Paris"})),
+            ("", json!({})),
+        ];
+
+        for (raw, expected) in cases {
+            assert_eq!(repair_arguments(raw), Some(expected), "input: {}", raw);
+        }
+
+        assert_eq!(repair_arguments("no json here"), None);
+        assert_eq!(repair_arguments(r#"{"city": "Par"#), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = weather_tool().function.parameters;
+
+        assert!(
+            validate(
+                &json!({"city": "Paris", "unit": "c", "days": 3}),
+                &schema,
+                "arguments"
+            )
+            .is_ok()
+        );
+        assert_eq!(
+            validate(&json!({"unit": "c"}), &schema, "arguments"),
+            Err("arguments is missing required property \"city\"".to_string())
+        );
+        assert_eq!(
+            validate(&json!({"city": 1}), &schema, "arguments"),
+            Err("arguments.city should be string".to_string())
+        );
+        assert_eq!(
+            validate(&json!({"city": "Paris", "unit": "k"}), &schema, "arguments"),
+            Err("arguments.unit is not one of the allowed values".to_string())
+        );
+        assert_eq!(
+            validate(&json!({"city": "Paris", "days": 1.5}), &schema, "arguments"),
+            Err("arguments.days should be integer".to_string())
+        );
+        assert_eq!(
+            validate(
+                &json!({"city": "Paris", "country": "FR"}),
+                &schema,
+                "arguments"
+            ),
+            Err("arguments.country is not an allowed property".to_string())
+        );
+    }
+
+    #[test]
+    fn test_apply_repairs_and_validates() {
+        let mut response = response_with_arguments("{'city': 'Paris'}");
+        let report = check(true, true).apply(&mut response);
+
+        assert_eq!(
+            report,
+            ToolCallReport {
+                repaired: 1,
+                invalid: vec![]
+            }
+        );
+        assert_eq!(report.header_value(), Some("repaired"));
+        assert_eq!(arguments(&response), r#"{"city":"Paris"}"#);
+
+        let mut response = response_with_arguments(r#"{"unit": "c"}"#);
+        let report = check(true, true).apply(&mut response);
+
+        assert_eq!(report.header_value(), Some("invalid"));
+        assert_eq!(
+            report.invalid,
+            vec!["get_weather: arguments is missing required property \"city\"".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_apply_disabled_leaves_response_alone() {
+        let mut response = response_with_arguments("{'city': 'Paris'}");
+        let report = check(false, false).apply(&mut response);
+
+        assert_eq!(report, ToolCallReport::default());
+        assert_eq!(arguments(&response), "{'city': 'Paris'}");
+
+        // Validation alone reports, but does not rewrite
+        let report = check(false, true).apply(&mut response);
+        assert_eq!(report.header_value(), Some("invalid"));
+        assert_eq!(arguments(&response), "{'city': 'Paris'}");
+    }
+}
@@ -1,9 +1,15 @@
-use crate::copilot::{CopilotChatRequest, CopilotChatResponse, CopilotMessage};
+use crate::copilot::finish_reason::FinishReason;
+use crate::copilot::{
+    CopilotChatRequest, CopilotChatResponse, CopilotContent, CopilotContentPart, CopilotImageUrl,
+    CopilotMessage,
+};
 use crate::openai::completion::models::{
-    FunctionCall, OpenAIChatRequest, ToolCall as CompletionToolCall,
+    FunctionCall, OpenAIChatRequest, ToolCall as CompletionToolCall, ToolChoice, ToolChoiceFunction,
+};
+use crate::openai::responses::models::prompt_request::Content::{self, InputText};
+use crate::openai::responses::models::prompt_request::{
+    PromptRequest, ToolChoice as ResponsesToolChoice,
 };
-use crate::openai::responses::models::prompt_request::Content::InputText;
-use crate::openai::responses::models::prompt_request::PromptRequest;
 use crate::openai::responses::models::prompt_response::{
     AdditionalParameters, AssistantContent, OutputFunctionCall, OutputMessage, OutputRole,
     OutputTokensDetails, ResponseObject, ResponseStatus, ResponsesToolDefinition, Text, ToolStatus,
@@ -18,162 +24,225 @@ impl From<OpenAIChatRequest> for CopilotChatRequest {
         Self {
             messages: request
                 .messages
-                .iter()
+                .into_iter()
                 .map(|m| CopilotMessage {
-                    role: m.role.clone(),
-                    content: m.content.clone(),
+                    role: m.role,
+                    content: message_content(m.content, m.images),
                     padding: None,
-                    tool_calls: m.tool_calls.clone(),
-                    tool_call_id: m.tool_call_id.clone(),
-                    name: m.name.clone(),
+                    tool_calls: m.tool_calls,
+                    tool_call_id: m.tool_call_id,
+                    name: m.name,
+                    reasoning_text: None,
                 })
                 .collect(),
-            model: request.model.clone(),
+            model: request.model,
             temperature: request.temperature,
             max_tokens: request.max_tokens,
             stream: Some(request.stream),
             tools: request.tools,
             tool_choice: request.tool_choice,
+            parallel_tool_calls: request.parallel_tool_calls,
+            stop: None,
+            response_format: request.response_format,
+            logprobs: request.logprobs,
+            top_logprobs: request.top_logprobs,
+            reasoning_effort: request.reasoning_effort,
+            user: request.user,
         }
     }
 }
 
-impl From<PromptRequest> for CopilotChatRequest {
-    fn from(value: PromptRequest) -> Self {
-        use crate::openai::completion::models::{FunctionDefinition, Tool as OpenAITool};
+impl CopilotChatRequest {
+    /// Checks that `tool_choice` is consistent with the declared `tools`.
+    ///
+    /// `"required"` needs at least one tool, and forcing a specific function needs that
+    /// function to be declared. Copilot's own errors for these cases are opaque, so we
+    /// reject them up front with a message the client can act on.
+    pub fn validate_tool_choice(&self) -> Result<(), String> {
+        let tool_names = self
+            .tools
+            .iter()
+            .flatten()
+            .map(|tool| tool.function.name.as_str())
+            .collect::<Vec<&str>>();
+
+        match &self.tool_choice {
+            None => Ok(()),
+            Some(ToolChoice::String(mode)) => match mode.as_str() {
+                "auto" | "none" => Ok(()),
+                "required" if tool_names.is_empty() => {
+                    Err("tool_choice is \"required\" but no tools were provided".to_string())
+                }
+                "required" => Ok(()),
+                other => Err(format!(
+                    "Invalid tool_choice \"{}\": expected \"auto\", \"none\" or \"required\"",
+                    other
+                )),
+            },
+            Some(ToolChoice::Specific {
+                tool_type,
+                function,
+            }) => {
+                if tool_type != "function" {
+                    return Err(format!(
+                        "Invalid tool_choice type \"{}\": expected \"function\"",
+                        tool_type
+                    ));
+                }
 
-        let mut messages: Vec<CopilotMessage> = vec![];
+                if !tool_names.contains(&function.name.as_str()) {
+                    return Err(format!(
+                        "tool_choice names function \"{}\" which is not declared in tools",
+                        function.name
+                    ));
+                }
 
-        // Add a system message with instructions at the beginning
-        if let Some(instructions) = &value.instructions {
-            messages.insert(
-                0,
-                CopilotMessage {
-                    role: "system".to_string(),
-                    content: Some(instructions.to_string()),
-                    padding: None,
-                    tool_calls: None,
-                    tool_call_id: None,
-                    name: None,
-                },
-            );
+                Ok(())
+            }
         }
+    }
+}
 
-        let mut systems = value
-            .input
-            .iter()
-            .filter(|message| matches!(&message.role, role if role == &Some("system".to_string())))
-            .map(|message| {
-                let content = match &message.content {
-                    Some(contents) => contents
-                        .iter()
-                        .map(|e| match e {
-                            InputText { text } => text.clone(),
-                        })
-                        .collect::<Vec<String>>()
-                        .join("\n"),
-                    _ => "".to_string(),
-                };
-
-                CopilotMessage {
-                    role: "system".to_string(),
-                    content: Some(content),
-                    padding: None,
-                    tool_calls: None,
-                    tool_call_id: None,
-                    name: None,
-                }
-            })
-            .collect::<Vec<CopilotMessage>>();
+impl From<ResponsesToolChoice> for ToolChoice {
+    fn from(value: ResponsesToolChoice) -> Self {
+        match value {
+            ResponsesToolChoice::Mode(mode) => ToolChoice::String(mode),
+            ResponsesToolChoice::Function { tool_type, name } => ToolChoice::Specific {
+                tool_type,
+                function: ToolChoiceFunction { name },
+            },
+        }
+    }
+}
 
-        messages.append(&mut systems);
+/// Build the Copilot content of a message, turning Ollama-style base64 `images`
+/// into OpenAI `image_url` content parts for vision-capable models.
+fn message_content(content: Option<String>, images: Option<Vec<String>>) -> Option<CopilotContent> {
+    let images = match images {
+        Some(images) if !images.is_empty() => images,
+        _ => return content.map(CopilotContent::Text),
+    };
 
-        let mut users = value
-            .input
-            .iter()
-            .filter(|message| matches!(&message.role, role if role == &Some("user".to_string())))
-            .map(|message| {
-                let content = match &message.content {
-                    Some(contents) => contents
-                        .iter()
-                        .map(|e| match e {
-                            InputText { text } => text.clone(),
-                        })
-                        .collect::<Vec<String>>()
-                        .join("\n"),
-                    _ => "".to_string(),
-                };
-
-                CopilotMessage {
-                    role: "user".to_string(),
-                    content: Some(content),
-                    padding: None,
-                    tool_calls: None,
-                    tool_call_id: None,
-                    name: None,
-                }
-            })
-            .collect::<Vec<CopilotMessage>>();
+    let mut parts: Vec<CopilotContentPart> = Vec::with_capacity(images.len() + 1);
 
-        messages.append(&mut users);
+    if let Some(text) = content.filter(|text| !text.is_empty()) {
+        parts.push(CopilotContentPart::Text { text });
+    }
 
-        /*
-         * If empty, then it will be a condition to also not add an "assistant" section to the built up copilot request
-         */
-        let function_call_messages_tool_calls: Vec<CompletionToolCall> = value
-            .input
-            .iter()
-            .filter(|message| matches!(&message.message_type, message_type if message_type == "function_call"))
-            .enumerate()
-            .map(|(id, message)| CompletionToolCall {
-                id: Some(format!("{}", id)),
-                tool_type: "function".to_string(),
-                function: FunctionCall {
-                    name: message
-                        .name
-                        .clone()
-                        .unwrap_or("SHOULD HAVE BEEN SET".to_string()),
-                    arguments: message
-                        .arguments
-                        .clone()
-                        .unwrap_or("SHOULD HAVE BEEN SET".to_string()),
+    parts.extend(
+        images
+            .into_iter()
+            .map(|image| CopilotContentPart::ImageUrl {
+                image_url: CopilotImageUrl {
+                    url: image_data_url(image),
                 },
-            })
-            .collect();
+            }),
+    );
 
-        if !function_call_messages_tool_calls.is_empty() {
-            let function_call_message = CopilotMessage {
-                role: "assistant".to_string(),
-                content: None,
+    Some(CopilotContent::Parts(parts))
+}
+
+/// Ollama sends raw base64 without a MIME type, so sniff it from the encoded magic bytes.
+/// Values that are already URLs (`data:`, `http(s):`) are passed through untouched.
+fn image_data_url(image: String) -> String {
+    if image.starts_with("data:") || image.starts_with("http://") || image.starts_with("https://") {
+        return image;
+    }
+
+    let mime_type = if image.starts_with("/9j/") {
+        "image/jpeg"
+    } else if image.starts_with("R0lGOD") {
+        "image/gif"
+    } else if image.starts_with("UklGR") {
+        "image/webp"
+    } else {
+        "image/png"
+    };
+
+    format!("data:{};base64,{}", mime_type, image)
+}
+
+impl From<PromptRequest> for CopilotChatRequest {
+    fn from(value: PromptRequest) -> Self {
+        use crate::openai::completion::models::{FunctionDefinition, Tool as OpenAITool};
+
+        fn message(role: &str, content: Option<CopilotContent>) -> CopilotMessage {
+            CopilotMessage {
+                role: role.to_string(),
+                content,
                 padding: None,
-                tool_calls: Some(function_call_messages_tool_calls),
+                tool_calls: None,
                 tool_call_id: None,
                 name: None,
-            };
-
-            let tool_calls = match function_call_message.tool_calls {
-                Some(ref tool_calls) => tool_calls.as_slice(),
-                _ => &[],
-            };
-
-            let mut function_call_output_messages: Vec<CopilotMessage> = value
-                .input
-                .iter()
-                .filter(|message| matches!(&message.message_type, message_type if message_type == "function_call_output"))
-                .zip(tool_calls)
+                reasoning_text: None,
+            }
+        }
+
+        fn text(contents: Option<Vec<Content>>) -> CopilotContent {
+            let texts: Vec<String> = contents
+                .into_iter()
+                .flatten()
+                .map(|content| match content {
+                    InputText { text } => text,
+                })
+                .collect();
+            texts.join("\n").into()
+        }
+
+        // The instructions come first, then system messages, then user messages, then
+        // the function calls and their outputs, each taken from the input in one pass
+        let mut messages: Vec<CopilotMessage> = Vec::with_capacity(value.input.len() + 2);
+        if let Some(instructions) = value.instructions {
+            messages.push(message("system", Some(instructions.into())));
+        }
+        let mut users = Vec::new();
+        let mut function_calls: Vec<CompletionToolCall> = Vec::new();
+        let mut outputs = Vec::new();
+        for input in value.input {
+            match input.role.as_deref() {
+                Some("system") => messages.push(message("system", Some(text(input.content)))),
+                Some("user") => users.push(message("user", Some(text(input.content)))),
+                _ => {}
+            }
+            match input.message_type.as_str() {
+                "function_call" => function_calls.push(CompletionToolCall {
+                    id: Some(format!("{}", function_calls.len())),
+                    tool_type: "function".to_string(),
+                    function: FunctionCall {
+                        name: input
+                            .name
+                            .unwrap_or_else(|| "SHOULD HAVE BEEN SET".to_string()),
+                        arguments: input
+                            .arguments
+                            .unwrap_or_else(|| "SHOULD HAVE BEEN SET".to_string()),
+                    },
+                }),
+                "function_call_output" => outputs.push(input.output),
+                _ => {}
+            }
+        }
+        messages.append(&mut users);
+
+        /*
+         * If there are no function calls, no "assistant" section is added to the built up copilot request
+         */
+        if !function_calls.is_empty() {
+            let outputs: Vec<CopilotMessage> = outputs
+                .into_iter()
+                .zip(&function_calls)
                 .enumerate()
-                .map(|(id, (message, tool_call))| CopilotMessage {
-                    role: "tool".to_string(),
-                    content: message.output.clone(),
-                    padding: None,
-                    tool_calls: None,
+                .map(|(id, (output, tool_call))| CopilotMessage {
                     tool_call_id: Some(format!("{}", id)),
                     name: Some(tool_call.function.name.clone()),
+                    ..message("tool", output.map(Into::into))
                 })
                 .collect();
 
-            messages.insert(messages.len(), function_call_message);
-            messages.append(&mut function_call_output_messages);
+            messages.push(CopilotMessage {
+                tool_calls: Some(function_calls),
+                ..message("assistant", None)
+            });
+            messages.extend(outputs);
         }
 
         // Convert tools from PromptRequest format to OpenAI Tool format
@@ -183,21 +252,25 @@ impl From<PromptRequest> for CopilotChatRequest {
             Some(
                 value
                     .tools
-                    .iter()
+                    .into_iter()
                     .map(|tool| {
-                        // Convert ToolParameters to JSON Value for FunctionDefinition
-                        let parameters = serde_json::json!({
-                            "type": tool.parameters.param_type,
-                            "properties": tool.parameters.properties,
-                            "required": tool.parameters.required,
-                            "additionalProperties": tool.parameters.additional_properties,
-                        });
+                        // Convert ToolParameters to JSON Value for FunctionDefinition, moving
+                        // the schema rather than serializing a copy of it
+                        let parameters = serde_json::Value::Object(serde_json::Map::from_iter([
+                            ("type".to_string(), tool.parameters.param_type.into()),
+                            ("properties".to_string(), tool.parameters.properties),
+                            ("required".to_string(), tool.parameters.required.into()),
+                            (
+                                "additionalProperties".to_string(),
+                                tool.parameters.additional_properties.into(),
+                            ),
+                        ]));
 
                         OpenAITool {
-                            tool_type: tool.tool_type.clone(),
+                            tool_type: tool.tool_type,
                             function: FunctionDefinition {
-                                name: tool.name.clone(),
-                                description: Some(tool.description.clone()),
+                                name: tool.name,
+                                description: Some(tool.description),
                                 parameters,
                             },
                         }
@@ -211,9 +284,16 @@ impl From<PromptRequest> for CopilotChatRequest {
             model: value.model,
             temperature: None,
             max_tokens: value.max_output_tokens,
-            stream: Some(false),
+            stream: Some(value.stream),
             tools,
-            tool_choice: None,
+            tool_choice: value.tool_choice.map(Into::into),
+            parallel_tool_calls: value.parallel_tool_calls,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: value.reasoning.and_then(|reasoning| reasoning.effort),
+            user: value.user,
         }
     }
 }
@@ -222,76 +302,68 @@ impl From<CopilotChatResponse> for CompletionResponse {
     fn from(resp: CopilotChatResponse) -> Self {
         // usage mapping
         let usage = resp.usage.map(ResponsesUsage::from);
-        // output mapping
-        let output: Vec<Output> = resp
-            .choices
-            .iter()
-            .enumerate()
-            .flat_map(|(i, choice)| {
-                let msg = &choice.message;
-                // If there are tool_calls, produce FunctionCall, else Message
-                if let Some(tool_calls) = &msg.tool_calls {
-                    tool_calls
-                        .iter()
-                        .map(|tc| {
-                            Output::FunctionCall(OutputFunctionCall {
-                                id: tc.id.clone().unwrap_or_default(),
-                                arguments: tc.function.arguments.clone(),
-                                // arguments: serde_json::from_str(&tc.function.arguments).unwrap_or_default(),
-                                call_id: msg.tool_call_id.clone().unwrap_or_default(),
-                                name: tc.function.name.clone(),
-                                status: ToolStatus::Completed,
-                            })
-                        })
-                        .collect()
-                } else {
-                    // Reasoning: if role is assistant and content is present, treat as Message, else Reasoning variant
-                    vec![Output::Message(OutputMessage {
-                        id: format!("{}-{}", resp.id, i),
-                        role: OutputRole::Assistant,
-                        status: ResponseStatus::Completed,
-                        content: vec![match &msg.content {
-                            Some(content) => AssistantContent::OutputText(Text {
-                                text: content.clone(),
-                            }),
-                            None => AssistantContent::Refusal {
-                                refusal: "No content".to_string(),
-                            },
-                        }],
-                    })]
+        let incomplete_details = resp.choices.iter().find_map(|choice| {
+            FinishReason::parse(Some(&choice.finish_reason))?.incomplete_details()
+        });
+        // output mapping, collecting the tools called along the way
+        let mut output: Vec<Output> = Vec::with_capacity(resp.choices.len());
+        let mut tools = Vec::new();
+        for (i, choice) in resp.choices.into_iter().enumerate() {
+            let msg = choice.message;
+            // If there are tool_calls, produce FunctionCall, else Message
+            if let Some(tool_calls) = msg.tool_calls {
+                for tc in tool_calls {
+                    tools.push(ResponsesToolDefinition {
+                        name: tc.function.name.clone(),
+                        parameters: serde_json::from_str(&tc.function.arguments)
+                            .unwrap_or_default(),
+                        strict: true,
+                        kind: tc.tool_type,
+                        description: String::new(),
+                    });
+                    output.push(Output::FunctionCall(OutputFunctionCall {
+                        id: tc.id.unwrap_or_default(),
+                        arguments: tc.function.arguments,
+                        // arguments: serde_json::from_str(&tc.function.arguments).unwrap_or_default(),
+                        call_id: msg.tool_call_id.clone().unwrap_or_default(),
+                        name: tc.function.name,
+                        status: ToolStatus::Completed,
+                    }));
                 }
-            })
-            .collect();
+            } else {
+                // Reasoning: if role is assistant and content is present, treat as Message, else Reasoning variant
+                output.push(Output::Message(OutputMessage {
+                    id: format!("{}-{}", resp.id, i),
+                    role: OutputRole::Assistant,
+                    status: ResponseStatus::Completed,
+                    content: vec![match msg.content {
+                        Some(content) => AssistantContent::OutputText(Text {
+                            text: content.into_text(),
+                        }),
+                        None => AssistantContent::Refusal {
+                            refusal: "No content".to_string(),
+                        },
+                    }],
+                }));
+            }
+        }
         CompletionResponse {
             id: resp.id,
             object: ResponseObject::Response,
             created_at: resp.created.unwrap_or_default(),
-            status: ResponseStatus::Completed,
+            status: if incomplete_details.is_some() {
+                ResponseStatus::Incomplete
+            } else {
+                ResponseStatus::Completed
+            },
             error: None,
-            incomplete_details: None,
+            incomplete_details,
             instructions: None,
             max_output_tokens: None,
             model: resp.model,
             usage,
             output,
-            tools: {
-                let mut tool_defs = Vec::new();
-                for choice in &resp.choices {
-                    if let Some(tool_calls) = &choice.message.tool_calls {
-                        for tc in tool_calls {
-                            tool_defs.push(ResponsesToolDefinition {
-                                name: tc.function.name.clone(),
-                                parameters: serde_json::from_str(&tc.function.arguments)
-                                    .unwrap_or_default(),
-                                strict: true,
-                                kind: tc.tool_type.clone(),
-                                description: String::new(),
-                            });
-                        }
-                    }
-                }
-                tool_defs
-            },
+            tools,
             additional_parameters: AdditionalParameters::default(),
         }
     }
@@ -396,6 +468,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ollama_images_become_image_url_parts() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "messages": [
+                {
+                    "role": "user",
+                    "content": "What is in this picture?",
+                    "images": ["iVBORw0KGgoAAAANSUhEUg==", "/9j/4AAQSkZJRg=="]
+                }
+            ]
+        }"#;
+        let request: OpenAIChatRequest = serde_json::from_str(json).unwrap();
+
+        let copilot_request: CopilotChatRequest = request.into();
+
+        assert_eq!(
+            copilot_request.messages[0].content,
+            Some(CopilotContent::Parts(vec![
+                CopilotContentPart::Text {
+                    text: "What is in this picture?".to_string()
+                },
+                CopilotContentPart::ImageUrl {
+                    image_url: CopilotImageUrl {
+                        url: "data:image/png;base64,iVBORw0KGgoAAAANSUhEUg==".to_string()
+                    }
+                },
+                CopilotContentPart::ImageUrl {
+                    image_url: CopilotImageUrl {
+                        url: "data:image/jpeg;base64,/9j/4AAQSkZJRg==".to_string()
+                    }
+                },
+            ]))
+        );
+
+        let serialized = serde_json::to_value(&copilot_request.messages[0]).unwrap();
+        assert_eq!(serialized["content"][1]["type"], "image_url");
+    }
+
+    #[test]
+    fn test_message_without_images_keeps_plain_content() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hello", "images": [] }]
+        }"#;
+        let request: OpenAIChatRequest = serde_json::from_str(json).unwrap();
+
+        let copilot_request: CopilotChatRequest = request.into();
+
+        assert_eq!(copilot_request.messages[0].content, Some("Hello".into()));
+    }
+
     #[test]
     fn test_prompt_request_to_copilot_chat_request() {
         // Load rig_openai_prompt_request.json as string
@@ -418,6 +542,7 @@ mod tests {
                 .content
                 .as_ref()
                 .unwrap()
+                .to_text()
                 .contains("Return a comma-separated list of ticker symbols")
         );
 
@@ -428,6 +553,7 @@ mod tests {
                 .content
                 .as_ref()
                 .unwrap()
+                .to_text()
                 .starts_with("Extract the ticker symbols")
         );
 
@@ -446,4 +572,110 @@ mod tests {
             "get_portfolio"
         );
     }
+
+    fn tool_choice_request(tool_choice: &str) -> CopilotChatRequest {
+        let json = format!(
+            r#"{{
+                "model": "gpt-4",
+                "messages": [{{"role": "user", "content": "Hello"}}],
+                "tools": [{{
+                    "type": "function",
+                    "function": {{"name": "get_weather", "parameters": {{"type": "object"}}}}
+                }}],
+                "tool_choice": {}
+            }}"#,
+            tool_choice
+        );
+        let request: OpenAIChatRequest = serde_json::from_str(&json).unwrap();
+        request.into()
+    }
+
+    #[test]
+    fn test_validate_tool_choice_accepts_declared_function() {
+        let request =
+            tool_choice_request(r#"{"type": "function", "function": {"name": "get_weather"}}"#);
+        assert!(request.validate_tool_choice().is_ok());
+
+        assert!(
+            tool_choice_request(r#""required""#)
+                .validate_tool_choice()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_validate_tool_choice_rejects_unknown_function() {
+        let request =
+            tool_choice_request(r#"{"type": "function", "function": {"name": "get_stock_price"}}"#);
+        let err = request.validate_tool_choice().unwrap_err();
+        assert!(err.contains("get_stock_price"));
+    }
+
+    #[test]
+    fn test_validate_tool_choice_rejects_required_without_tools() {
+        let mut request = tool_choice_request(r#""required""#);
+        request.tools = None;
+        assert!(request.validate_tool_choice().is_err());
+
+        assert!(
+            tool_choice_request(r#""sometimes""#)
+                .validate_tool_choice()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_prompt_request_forwards_tool_choice_and_parallel_tool_calls() {
+        let json = r#"{
+            "model": "gpt-4",
+            "input": [{"role": "user", "type": "message", "content": [{"type": "input_text", "text": "What's the weather?"}]}],
+            "tools": [{
+                "type": "function",
+                "name": "get_weather",
+                "description": "Get the weather",
+                "strict": true,
+                "parameters": {"type": "object", "properties": {}, "required": [], "additionalProperties": false}
+            }],
+            "tool_choice": {"type": "function", "name": "get_weather"},
+            "parallel_tool_calls": false
+        }"#;
+        let prompt_request: PromptRequest = serde_json::from_str(json).unwrap();
+
+        let copilot_request: CopilotChatRequest = prompt_request.into();
+
+        assert_eq!(copilot_request.parallel_tool_calls, Some(false));
+        match &copilot_request.tool_choice {
+            Some(ToolChoice::Specific { function, .. }) => {
+                assert_eq!(function.name, "get_weather")
+            }
+            other => panic!("unexpected tool_choice: {:?}", other),
+        }
+        assert!(copilot_request.validate_tool_choice().is_ok());
+    }
+
+    #[test]
+    fn test_reasoning_effort_is_forwarded() {
+        use crate::openai::responses::models::prompt_response::ReasoningEffort;
+
+        let chat: OpenAIChatRequest = serde_json::from_str(
+            r#"{"model": "o3-mini", "messages": [], "reasoning_effort": "low"}"#,
+        )
+        .unwrap();
+        let copilot_request: CopilotChatRequest = chat.into();
+        assert_eq!(copilot_request.reasoning_effort, Some(ReasoningEffort::Low));
+
+        let prompt_request: PromptRequest = serde_json::from_str(
+            r#"{"model": "o3-mini", "input": [], "reasoning": {"effort": "high", "summary": "auto"}}"#,
+        )
+        .unwrap();
+        let copilot_request: CopilotChatRequest = prompt_request.into();
+        assert_eq!(
+            copilot_request.reasoning_effort,
+            Some(ReasoningEffort::High)
+        );
+        assert_eq!(
+            serde_json::to_value(&copilot_request).unwrap()["reasoning_effort"],
+            "high"
+        );
+    }
 }
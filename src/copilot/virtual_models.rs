@@ -0,0 +1,110 @@
+use crate::config::VirtualModelConfig;
+use crate::copilot::CopilotChatRequest;
+use std::collections::HashMap;
+use tracing::log::info;
+
+impl CopilotChatRequest {
+    /// Points a request for a virtual model at its model and applies the pinned
+    /// parameters over the client's. Requests for other models are left untouched.
+    pub fn expand_virtual_model(&mut self, virtual_models: &HashMap<String, VirtualModelConfig>) {
+        let Some(virtual_model) = virtual_models.get(&self.model) else {
+            return;
+        };
+        info!(
+            "Expanding virtual model {} to {}",
+            self.model, virtual_model.model
+        );
+
+        self.model = virtual_model.model.clone();
+        if virtual_model.temperature.is_some() {
+            self.temperature = virtual_model.temperature;
+        }
+        if virtual_model.max_tokens.is_some() {
+            self.max_tokens = virtual_model.max_tokens;
+        }
+        if virtual_model.stop.is_some() {
+            self.stop = virtual_model.stop.clone();
+        }
+        if virtual_model.response_format.is_some() {
+            self.response_format = virtual_model.response_format.clone();
+        }
+        if virtual_model.reasoning_effort.is_some() {
+            self.reasoning_effort = virtual_model.reasoning_effort;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copilot::CopilotMessage;
+    use crate::openai::completion::models::ResponseFormat;
+
+    fn request(model: &str) -> CopilotChatRequest {
+        CopilotChatRequest {
+            messages: vec![CopilotMessage {
+                role: "user".to_string(),
+                content: Some("List three colours".into()),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            }],
+            model: model.to_string(),
+            temperature: Some(0.9),
+            max_tokens: Some(100),
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        }
+    }
+
+    fn virtual_models() -> HashMap<String, VirtualModelConfig> {
+        HashMap::from([(
+            "gpt-4o-json".to_string(),
+            VirtualModelConfig {
+                model: "gpt-4o".to_string(),
+                temperature: Some(0.0),
+                max_tokens: None,
+                stop: None,
+                response_format: Some(ResponseFormat::JsonObject),
+                reasoning_effort: None,
+            },
+        )])
+    }
+
+    #[test]
+    fn test_pinned_parameters_replace_the_clients() {
+        let mut request = request("gpt-4o-json");
+
+        request.expand_virtual_model(&virtual_models());
+
+        assert_eq!(request.model, "gpt-4o");
+        assert_eq!(request.temperature, Some(0.0));
+        assert!(matches!(
+            request.response_format,
+            Some(ResponseFormat::JsonObject)
+        ));
+        // Not pinned, so the client's
+        assert_eq!(request.max_tokens, Some(100));
+        assert_eq!(request.messages.len(), 1);
+    }
+
+    #[test]
+    fn test_other_models_are_untouched() {
+        let mut request = request("gpt-4o");
+
+        request.expand_virtual_model(&virtual_models());
+
+        assert_eq!(request.temperature, Some(0.9));
+        assert!(request.response_format.is_none());
+    }
+}
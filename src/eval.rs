@@ -0,0 +1,352 @@
+use crate::config::Config;
+use crate::server::Server;
+use anyhow::{Context, Result, anyhow};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// A YAML suite of prompts and the assertions their replies must pass, for `passenger-rs eval`
+#[derive(Debug, Deserialize)]
+pub struct Suite {
+    /// Models to run every case against, unless given with `--model`
+    #[serde(default)]
+    pub models: Vec<String>,
+    pub cases: Vec<Case>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Case {
+    pub name: String,
+    /// Messages sent before `prompt`, as in a chat completion request
+    #[serde(default)]
+    pub messages: Vec<Value>,
+    /// A final user message
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// Other chat completion fields, such as `temperature` or `response_format`
+    #[serde(default)]
+    pub parameters: Map<String, Value>,
+    #[serde(default)]
+    pub expect: Expect,
+}
+
+/// Assertions on the reply's content; a case without any passes when the model answers
+#[derive(Debug, Default, Deserialize)]
+pub struct Expect {
+    /// A regular expression the reply must match
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// JSON the reply must parse as. Objects only need the listed fields, so
+    /// `{"answer": 4}` accepts `{"answer": 4, "reasoning": "..."}`.
+    #[serde(default)]
+    pub json: Option<Value>,
+}
+
+/// One case run against one model
+#[derive(Debug)]
+pub struct Outcome {
+    pub case: String,
+    pub model: String,
+    pub latency: Duration,
+    /// Why the case failed, empty when it passed
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Pass rate and latency of one model over a suite
+#[derive(Debug, PartialEq)]
+pub struct ModelSummary {
+    pub model: String,
+    pub passed: usize,
+    pub total: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub max: Duration,
+}
+
+impl Suite {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read eval suite: {}", path.display()))?;
+        let suite: Suite = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse eval suite: {}", path.display()))?;
+        suite.validate()?;
+        Ok(suite)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.cases.is_empty() {
+            return Err(anyhow!("The eval suite has no cases"));
+        }
+        for case in &self.cases {
+            if case.prompt.is_none() && case.messages.is_empty() {
+                return Err(anyhow!("Case '{}' needs a prompt or messages", case.name));
+            }
+            if let Some(pattern) = &case.expect.regex {
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid regex in case '{}'", case.name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Case {
+    /// The chat completion request for `model`
+    pub fn request(&self, model: &str) -> Value {
+        let mut messages = self.messages.clone();
+        if let Some(prompt) = &self.prompt {
+            messages.push(json!({"role": "user", "content": prompt}));
+        }
+
+        let mut request = self.parameters.clone();
+        request.insert("model".to_string(), model.into());
+        request.insert("messages".to_string(), messages.into());
+        request.insert("stream".to_string(), false.into());
+        Value::Object(request)
+    }
+}
+
+impl Expect {
+    /// Why `reply` fails these assertions, empty when it passes
+    pub fn check(&self, reply: &str) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(pattern) = &self.regex
+            && let Ok(regex) = Regex::new(pattern)
+            && !regex.is_match(reply)
+        {
+            failures.push(format!("reply does not match /{}/", pattern));
+        }
+
+        if let Some(expected) = &self.json {
+            match serde_json::from_str::<Value>(strip_code_fence(reply)) {
+                Ok(actual) if json_contains(&actual, expected) => {}
+                Ok(actual) => {
+                    failures.push(format!("reply {} does not contain {}", actual, expected))
+                }
+                Err(e) => failures.push(format!("reply is not JSON: {}", e)),
+            }
+        }
+
+        failures
+    }
+}
+
+/// `reply` without the Markdown code fence models often wrap JSON in
+fn strip_code_fence(reply: &str) -> &str {
+    let trimmed = reply.trim();
+    trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|fenced| fenced.trim_start_matches("json").trim())
+        .unwrap_or(trimmed)
+}
+
+/// Whether `actual` has every field of `expected`, recursively; other values must be equal
+fn json_contains(actual: &Value, expected: &Value) -> bool {
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => expected.iter().all(|(key, value)| {
+            actual
+                .get(key)
+                .is_some_and(|actual| json_contains(actual, value))
+        }),
+        (Value::Array(actual), Value::Array(expected)) => {
+            actual.len() == expected.len()
+                && actual
+                    .iter()
+                    .zip(expected)
+                    .all(|(actual, expected)| json_contains(actual, expected))
+        }
+        _ => actual == expected,
+    }
+}
+
+/// Run every case of `suite` against each of `models`, through a proxy served on a local port
+pub async fn run(config: &Config, suite: &Suite, models: &[String]) -> Result<Vec<Outcome>> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/v1/chat/completions", listener.local_addr()?);
+    let router = Server::new(config).router;
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    let client = reqwest::Client::new();
+    let mut outcomes = Vec::new();
+    for model in models {
+        for case in &suite.cases {
+            let started = Instant::now();
+            let reply = complete(&client, &url, &case.request(model)).await;
+            let latency = started.elapsed();
+
+            let failures = match reply {
+                Ok(reply) => case.expect.check(&reply),
+                Err(e) => vec![e.to_string()],
+            };
+            outcomes.push(Outcome {
+                case: case.name.clone(),
+                model: model.clone(),
+                latency,
+                failures,
+            });
+        }
+    }
+    Ok(outcomes)
+}
+
+/// The content of the first choice of a chat completion
+async fn complete(client: &reqwest::Client, url: &str, request: &Value) -> Result<String> {
+    let response = client.post(url).json(request).send().await?;
+    let status = response.status();
+    let body: Value = response
+        .json()
+        .await
+        .with_context(|| format!("unreadable response ({})", status))?;
+    if !status.is_success() {
+        let message = body["error"]["message"].as_str().unwrap_or("unknown error");
+        return Err(anyhow!("request failed ({}): {}", status, message));
+    }
+
+    body["choices"][0]["message"]["content"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("reply has no content"))
+}
+
+/// Pass rate and latency per model, in the order models were run
+pub fn summarise(outcomes: &[Outcome]) -> Vec<ModelSummary> {
+    let mut models: Vec<&str> = Vec::new();
+    for outcome in outcomes {
+        if !models.contains(&outcome.model.as_str()) {
+            models.push(&outcome.model);
+        }
+    }
+
+    models
+        .into_iter()
+        .map(|model| {
+            let runs: Vec<&Outcome> = outcomes
+                .iter()
+                .filter(|outcome| outcome.model == model)
+                .collect();
+            let mut latencies: Vec<Duration> = runs.iter().map(|run| run.latency).collect();
+            latencies.sort();
+
+            ModelSummary {
+                model: model.to_string(),
+                passed: runs.iter().filter(|run| run.passed()).count(),
+                total: runs.len(),
+                mean: latencies.iter().sum::<Duration>() / latencies.len() as u32,
+                median: latencies[latencies.len() / 2],
+                max: latencies[latencies.len() - 1],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: &str = r#"
+models: [gpt-4o]
+cases:
+  - name: arithmetic
+    prompt: What is 2 + 2? Answer as JSON.
+    parameters:
+      temperature: 0
+    expect:
+      json: {"answer": 4}
+  - name: greeting
+    messages:
+      - {role: system, content: Be terse.}
+    prompt: Say hello
+    expect:
+      regex: "(?i)hello"
+"#;
+
+    #[test]
+    fn test_suite_from_yaml() {
+        let suite: Suite = serde_yaml::from_str(SUITE).unwrap();
+        suite.validate().unwrap();
+
+        assert_eq!(suite.models, ["gpt-4o"]);
+        assert_eq!(
+            suite.cases[0].request("o3"),
+            json!({
+                "model": "o3",
+                "messages": [{"role": "user", "content": "What is 2 + 2? Answer as JSON."}],
+                "stream": false,
+                "temperature": 0
+            })
+        );
+        assert_eq!(
+            suite.cases[1].request("gpt-4o")["messages"],
+            json!([
+                {"role": "system", "content": "Be terse."},
+                {"role": "user", "content": "Say hello"}
+            ])
+        );
+
+        let invalid: Suite =
+            serde_yaml::from_str("cases: [{name: bad, prompt: x, expect: {regex: '('}}]").unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_expect_check() {
+        let suite: Suite = serde_yaml::from_str(SUITE).unwrap();
+        let json = &suite.cases[0].expect;
+        assert!(
+            json.check("```json\n{\"answer\": 4, \"why\": \"sum\"}\n```")
+                .is_empty()
+        );
+        assert_eq!(json.check(r#"{"answer": 5}"#).len(), 1);
+        assert!(json.check("four")[0].starts_with("reply is not JSON"));
+
+        let regex = &suite.cases[1].expect;
+        assert!(regex.check("Hello!").is_empty());
+        assert_eq!(regex.check("Hi"), ["reply does not match /(?i)hello/"]);
+    }
+
+    #[test]
+    fn test_summarise() {
+        let outcome = |model: &str, millis: u64, passed: bool| Outcome {
+            case: "case".to_string(),
+            model: model.to_string(),
+            latency: Duration::from_millis(millis),
+            failures: if passed {
+                vec![]
+            } else {
+                vec!["failed".to_string()]
+            },
+        };
+        let outcomes = [
+            outcome("gpt-4o", 300, true),
+            outcome("o3", 900, false),
+            outcome("gpt-4o", 100, true),
+            outcome("gpt-4o", 200, false),
+        ];
+
+        let summaries = summarise(&outcomes);
+        assert_eq!(
+            summaries[0],
+            ModelSummary {
+                model: "gpt-4o".to_string(),
+                passed: 2,
+                total: 3,
+                mean: Duration::from_millis(200),
+                median: Duration::from_millis(200),
+                max: Duration::from_millis(300),
+            }
+        );
+        assert_eq!(summaries[1].model, "o3");
+        assert_eq!(summaries[1].passed, 0);
+    }
+}
@@ -1,8 +1,50 @@
+//! A proxy serving GitHub Copilot as OpenAI- and Ollama-compatible APIs.
+//!
+//! The [`prelude`] has what embedding the proxy or calling Copilot takes:
+//!
+//! ```no_run
+//! use passenger_rs::prelude::*;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let config = Config::from_file("config.toml")?;
+//! let server = Server::new(&config);
+//! let listener = tokio::net::TcpListener::bind(&server.addr).await?;
+//! axum::serve(listener, server.router).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The command line interface is behind the default `cli` feature; without it the
+//! library builds without its terminal and YAML dependencies and the `login`, `chat`,
+//! `bench` and `eval` modules. The `ollama`, `responses`, `metrics` and `dashboard`
+//! features, also on by default, add the Ollama API, `/v1/responses`, `/metrics` and the
+//! admin dashboard. The `history` and `keys` features, on by default too, add the SQLite
+//! conversation log and client API keys, and with them the bundled SQLite.
+
 pub mod auth;
+#[cfg(feature = "cli")]
+pub mod bench;
+#[cfg(feature = "cli")]
+pub mod chat;
 pub mod config;
 pub mod copilot;
+#[cfg(feature = "cli")]
+pub mod eval;
+pub mod logging;
+#[cfg(feature = "cli")]
 pub mod login;
+pub mod migration;
 pub mod openai;
+pub mod prelude;
 pub mod server;
+pub mod stdio;
 pub mod storage;
+pub mod tls;
 pub mod token_manager;
+pub(crate) mod utc;
+
+pub use config::Config;
+pub use copilot::client::CopilotClient;
+pub use copilot::{CopilotChatRequest, CopilotChatResponse};
+pub use openai::completion::models::{OpenAIChatRequest, OpenAIChatResponse};
+pub use server::{AppError, Server};
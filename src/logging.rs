@@ -0,0 +1,117 @@
+use crate::config::LoggingConfig;
+use anyhow::{Context, Result};
+use std::sync::Mutex;
+use tracing::Level;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry, fmt, reload};
+
+/// Build the filter directives in effect at startup.
+///
+/// `level` (from `--log-level`, else `[logging] level`) sets the default, followed by
+/// the per-module `[logging] filter` directives. `RUST_LOG`, when set, is applied last
+/// so it can still override either for a one-off run.
+pub fn startup_directives(config: &LoggingConfig, level: Option<Level>) -> String {
+    let level = level
+        .map(|level| level.to_string().to_lowercase())
+        .unwrap_or_else(|| config.level.clone());
+
+    let mut directives = vec![level];
+    directives.extend(config.filter.clone());
+    directives.extend(std::env::var("RUST_LOG").ok());
+
+    directives
+        .into_iter()
+        .map(|d| d.trim().to_string())
+        .filter(|d| !d.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parse `RUST_LOG`-style directives, rejecting any that are malformed
+pub fn parse_filter(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::builder()
+        .parse(directives)
+        .map_err(|e| anyhow::anyhow!("Invalid log filter \"{}\": {}", directives, e))
+}
+
+/// Handle on the global log filter, so it can be changed while the server runs
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    current: Mutex<String>,
+}
+
+impl LogFilter {
+    /// Install the global tracing subscriber with a reloadable filter
+    pub fn init(directives: &str) -> Result<Self> {
+        let (filter, handle) = reload::Layer::new(parse_filter(directives)?);
+
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .try_init()
+            .context("Failed to initialise logging")?;
+
+        // Most modules log through the `log` facade. Let every record reach the
+        // tracing filter, otherwise raising the level at runtime would have no effect.
+        tracing::log::set_max_level(tracing::log::LevelFilter::Trace);
+
+        Ok(Self {
+            handle,
+            current: Mutex::new(directives.to_string()),
+        })
+    }
+
+    /// Directives currently in effect
+    pub fn current(&self) -> String {
+        self.current
+            .lock()
+            .expect("log filter lock poisoned")
+            .clone()
+    }
+
+    /// Replace the active directives
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = parse_filter(directives)?;
+        let mut current = self.current.lock().expect("log filter lock poisoned");
+
+        self.handle
+            .reload(filter)
+            .context("Failed to update log filter")?;
+        *current = directives.to_string();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_directives() {
+        // SAFETY: no other test reads or writes RUST_LOG
+        unsafe { std::env::remove_var("RUST_LOG") };
+
+        let config = LoggingConfig {
+            level: "warn".to_string(),
+            filter: Some("passenger_rs::server::ollama=debug".to_string()),
+        };
+
+        assert_eq!(
+            startup_directives(&config, None),
+            "warn,passenger_rs::server::ollama=debug"
+        );
+        assert_eq!(
+            startup_directives(&config, Some(Level::TRACE)),
+            "trace,passenger_rs::server::ollama=debug"
+        );
+        assert_eq!(startup_directives(&LoggingConfig::default(), None), "info");
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert!(parse_filter("info,passenger_rs::server::openai::chat_completion=debug").is_ok());
+        assert!(parse_filter("info,passenger_rs=loud").is_err());
+    }
+}
@@ -5,7 +5,6 @@ use crate::storage;
 use anyhow::Result;
 use crossterm::event::{self, Event, KeyCode};
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::Client;
 use std::io::{self, Write};
 use std::time::Duration;
 use tokio::sync::mpsc;
@@ -14,7 +13,7 @@ use tracing::info;
 
 /// Perform GitHub OAuth device flow login
 pub async fn login(config: &Config) -> Result<()> {
-    let client = Client::new();
+    let client = config.copilot.build_client()?;
 
     // Step 1: Request device code
     info!("Requesting device code from GitHub...");
@@ -22,6 +21,7 @@ pub async fn login(config: &Config) -> Result<()> {
         &client,
         &config.github.device_code_url,
         &config.github.client_id,
+        &config.copilot.headers,
     )
     .await?;
 
@@ -54,6 +54,7 @@ pub async fn login(config: &Config) -> Result<()> {
         &client,
         &config.github.copilot_token_url,
         &access_token_response.access_token,
+        &config.copilot.headers,
     )
     .await?;
 
@@ -1,38 +1,39 @@
-mod auth;
 mod clap;
-mod config;
-mod copilot;
-mod login;
-mod openai;
-mod server;
-mod storage;
-mod token_manager;
 
 use crate::clap::Args;
-use crate::server::Server;
 use anyhow::Result;
-use tracing::{Level, info};
-use tracing_subscriber::FmtSubscriber;
+use passenger_rs::logging::{self, LogFilter};
+use passenger_rs::server::Server;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse command line arguments
     let args = Args::parse_args();
 
-    // Initialize tracing
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::INFO)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+    // `config` subcommands check or print a configuration rather than load it
+    if args.execute_config_command()? {
+        return Ok(());
+    }
 
-    info!("Starting passenger-rs - GitHub Copilot Proxy");
+    // Load configuration, falling back to the built-in defaults without a config file
+    let config = args.load_config()?;
 
-    // Validate configuration file exists
-    args.validate_config_path()?;
+    // Initialize tracing
+    let directives = logging::startup_directives(&config.logging, args.log_level());
+    let log_filter = Arc::new(LogFilter::init(&directives)?);
 
-    // Load configuration
-    let config = config::Config::from_file(&args.config)?;
-    info!("Configuration loaded from {}", args.config);
+    info!("Starting passenger-rs - GitHub Copilot Proxy");
+    match args.config_path() {
+        Some(path) => info!(
+            "Configuration loaded from {} (version {})",
+            path, config.version
+        ),
+        None => info!("No config.toml found; using built-in defaults"),
+    }
+    info!("Log filter: {}", directives);
 
     // Execute any commands (login, refresh-token, etc.)
     // If a command was executed, exit early
@@ -42,10 +43,11 @@ async fn main() -> Result<()> {
 
     // Verify token exists before starting server
     args.verify_token_exists()?;
+    args.warn_insecure_token_files()?;
 
     // Start proxy server
     info!("Starting OpenAI-compatible proxy server...");
-    let server = Server::new(&config);
+    let server = Server::with_log_filter(&config, Some(log_filter));
 
     info!("Server listening on http://{}", server.addr);
     info!(
@@ -56,7 +58,38 @@ async fn main() -> Result<()> {
     info!("Models endpoint: http://{}/v1/models", server.addr);
 
     let listener = tokio::net::TcpListener::bind(&server.addr).await?;
-    axum::serve(listener, server.router).await?;
+
+    match server.ollama {
+        Some(ollama) => {
+            info!("Ollama listener on http://{}", ollama.addr);
+            let ollama_listener = tokio::net::TcpListener::bind(&ollama.addr).await?;
+            tokio::try_join!(
+                axum::serve(
+                    listener,
+                    server
+                        .router
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .into_future(),
+                axum::serve(
+                    ollama_listener,
+                    ollama
+                        .router
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .into_future(),
+            )?;
+        }
+        None => {
+            axum::serve(
+                listener,
+                server
+                    .router
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await?
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,162 @@
+use crate::config::DEFAULT_CONFIG;
+use anyhow::{Context, Result};
+use toml::{Table, Value};
+
+/// Schema version of the configuration this build writes and expects, under `version`
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrades from each version to the next, `MIGRATIONS[n]` taking version `n` to `n + 1`
+const MIGRATIONS: &[fn(&str, &Table) -> String] = &[add_required_keys];
+
+/// Tables whose every key earlier releases required
+const REQUIRED_TABLES: &[&str] = &["github", "copilot", "server"];
+
+/// The schema version of a configuration; files from before versioning are version 0
+pub fn version(table: &Table) -> Result<u32> {
+    match table.get("version") {
+        None => Ok(0),
+        Some(Value::Integer(version)) => u32::try_from(*version)
+            .map_err(|_| anyhow::anyhow!("Invalid config version: {}", version)),
+        Some(value) => Err(anyhow::anyhow!("Invalid config version: {}", value)),
+    }
+}
+
+/// `contents` brought up to [`CONFIG_VERSION`], or `None` when it already is. Comments and
+/// layout are kept: each migration only adds or rewrites the lines it needs to.
+pub fn migrate(contents: &str) -> Result<Option<String>> {
+    let table: Table = toml::from_str(contents).context("Failed to parse config file as TOML")?;
+    let from = version(&table)?;
+    if from > CONFIG_VERSION {
+        anyhow::bail!(
+            "Config version {} is newer than this passenger-rs supports ({}); upgrade passenger-rs",
+            from,
+            CONFIG_VERSION
+        );
+    }
+    if from == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let mut contents = contents.to_string();
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        let table: Table = toml::from_str(&contents).context(format!(
+            "Config migration to version {} failed",
+            version + 1
+        ))?;
+        contents = set_version(&migration(&contents, &table), version as u32 + 1);
+    }
+    Ok(Some(contents))
+}
+
+/// Version 1: add whatever `[github]`, `[copilot]` and `[server]` lack of the default
+/// configuration, keys earlier releases required
+fn add_required_keys(contents: &str, table: &Table) -> String {
+    let defaults: Table = toml::from_str(DEFAULT_CONFIG).expect("the default config is valid");
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+
+    for name in REQUIRED_TABLES {
+        let Some(Value::Table(default)) = defaults.get(*name) else {
+            continue;
+        };
+        let present = table.get(*name).and_then(Value::as_table);
+        let missing: Vec<String> = default
+            .iter()
+            .filter(|(key, value)| {
+                !value.is_table() && !present.is_some_and(|present| present.contains_key(*key))
+            })
+            .map(|(key, value)| format!("{} = {}", key, value))
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        match lines.iter().position(|line| is_header(line, name)) {
+            Some(header) => {
+                lines.splice(header + 1..header + 1, missing);
+            }
+            None => {
+                lines.push(String::new());
+                lines.push(format!("[{}]", name));
+                lines.extend(missing);
+            }
+        }
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Whether `line` opens the table `name`
+fn is_header(line: &str, name: &str) -> bool {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    line.strip_prefix('[')
+        .and_then(|line| line.strip_suffix(']'))
+        .is_some_and(|header| header.trim() == name)
+}
+
+/// `contents` with its top-level `version` set, added at the top when missing
+fn set_version(contents: &str, version: u32) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    let top_level = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+
+    let line = format!("version = {}", version);
+    match lines[..top_level].iter().position(|line| {
+        line.split_once('=')
+            .is_some_and(|(key, _)| key.trim() == "version")
+    }) {
+        Some(i) => lines[i] = line,
+        None => lines.insert(0, line),
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const UNVERSIONED: &str = r#"# My proxy
+[github]
+# Device flow
+device_code_url = "https://github.com/login/device/code"
+oauth_token_url = "https://github.com/login/oauth/access_token"
+copilot_token_url = "https://api.github.com/copilot_internal/v2/token"
+client_id = "Iv1.b507a08c87ecfe98"
+
+[copilot]
+api_base_url = "https://api.githubcopilot.com"
+"#;
+
+    #[test]
+    fn test_migrate_unversioned_config() {
+        let migrated = migrate(UNVERSIONED).unwrap().unwrap();
+
+        assert!(migrated.starts_with("version = 1\n# My proxy\n[github]\n"));
+        assert!(
+            migrated.contains("[github]\ncopilot_models_url = \"https://models.dev/api.json\"\n")
+        );
+        assert!(migrated.contains("# Device flow\n"));
+        assert!(migrated.ends_with("[server]\nhost = \"127.0.0.1\"\nport = 8081\n"));
+
+        let config = Config::parse(&migrated).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.server.port, 8081);
+        assert_eq!(migrate(&migrated).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate_current_and_newer_configs() {
+        assert_eq!(migrate(DEFAULT_CONFIG).unwrap(), None);
+
+        let newer = set_version(DEFAULT_CONFIG, CONFIG_VERSION + 1);
+        let error = migrate(&newer).unwrap_err().to_string();
+        assert!(
+            error.contains("newer than this passenger-rs supports"),
+            "{}",
+            error
+        );
+    }
+}
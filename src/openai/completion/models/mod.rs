@@ -3,6 +3,7 @@ mod utils;
 /**
 * Largely a knock-off from Rig's own OpenAI completion model. Thank you.
 */
+use crate::openai::responses::models::prompt_response::ReasoningEffort;
 use serde::{Deserialize, Serialize};
 
 /// OpenAI-compatible chat completion request
@@ -20,6 +21,46 @@ pub struct OpenAIChatRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(default)]
     pub tool_choice: Option<ToolChoice>,
+    #[serde(default)]
+    pub parallel_tool_calls: Option<bool>,
+    /// End-user identifier; used to keep a stable Copilot session per conversation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Number of choices to return; the proxy issues one Copilot request per choice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Generate this many candidates and return only the best ranked one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_of: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    /// How hard reasoning models think, forwarded to models that support it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+}
+
+/// Requested output format: plain text, any JSON object, or JSON matching a schema
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 /// OpenAI-compatible chat completion response
@@ -31,6 +72,9 @@ pub struct OpenAIChatResponse {
     pub model: String,
     pub choices: Vec<OpenAIChoice>,
     pub usage: OpenAIUsage,
+    /// Content filter verdicts on the prompt, as Azure OpenAI reports them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_filter_results: Option<serde_json::Value>,
 }
 
 /// Tool choice specification
@@ -83,8 +127,10 @@ pub struct ToolChoiceFunction {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(try_from = "ReceivedMessage")]
 pub struct OpenAIMessage {
     pub role: String,
+    /// Text; array content is flattened into it, see [`MessageContent`]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -93,13 +139,109 @@ pub struct OpenAIMessage {
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Images attached to the message: base64 in the Ollama request format, or the URLs of
+    /// `image_url` content parts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    /// Reasoning of reasoning models, under the names DeepSeek- and OpenRouter-style
+    /// clients read it from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+}
+
+/// An [`OpenAIMessage`] as received, before its content is flattened
+#[derive(Debug, Deserialize)]
+struct ReceivedMessage {
+    role: String,
+    content: Option<MessageContent>,
+    tool_calls: Option<Vec<ToolCall>>,
+    tool_call_id: Option<String>,
+    name: Option<String>,
+    images: Option<Vec<String>>,
+    reasoning_content: Option<String>,
+    reasoning: Option<String>,
+}
+
+/// Message content as a string, or as an array of parts: `text` parts, as some clients
+/// send for assistant and system messages, are joined by newlines, and `image_url` parts
+/// become the message's images
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+/// A part of array message content
+#[derive(Debug, Deserialize)]
+struct ContentPart {
+    #[serde(rename = "type")]
+    part_type: String,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    image_url: Option<ImageUrlPart>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageUrlPart {
+    url: String,
+}
+
+impl TryFrom<ReceivedMessage> for OpenAIMessage {
+    type Error = String;
+
+    fn try_from(message: ReceivedMessage) -> Result<Self, Self::Error> {
+        let mut images = message.images;
+        let content = match message.content {
+            None => None,
+            Some(MessageContent::Text(text)) => Some(text),
+            Some(MessageContent::Parts(parts)) => {
+                let mut texts = Vec::new();
+                for part in parts {
+                    match (part.part_type.as_str(), part.text, part.image_url) {
+                        ("text" | "input_text" | "output_text", Some(text), _) => texts.push(text),
+                        ("image_url", _, Some(image_url)) => {
+                            images.get_or_insert_with(Vec::new).push(image_url.url)
+                        }
+                        (part_type, ..) => {
+                            return Err(format!(
+                                "unsupported message content part \"{}\": only text and image_url parts are accepted",
+                                part_type
+                            ));
+                        }
+                    }
+                }
+                Some(texts.join("\n"))
+            }
+        };
+
+        Ok(Self {
+            role: message.role,
+            content,
+            tool_calls: message.tool_calls,
+            tool_call_id: message.tool_call_id,
+            name: message.name,
+            images,
+            reasoning_content: message.reasoning_content,
+            reasoning: message.reasoning,
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenAIChoice {
     pub index: u32,
     pub message: OpenAIMessage,
+    /// Token log probabilities; always present, `null` when not requested or unsupported
+    #[serde(default)]
+    pub logprobs: Option<serde_json::Value>,
     pub finish_reason: String,
+    /// Content filter verdicts on the choice, as Azure OpenAI reports them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -121,4 +263,75 @@ pub struct OpenAIModel {
     pub object: String,
     pub created: u32,
     pub owned_by: String,
+    /// Display name from `[models.display]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_content_as_text_parts() {
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "system", "content": [
+                    {"type": "text", "text": "Be brief."},
+                    {"type": "text", "text": "Answer in French."}
+                ]},
+                {"role": "user", "content": "Hello"},
+                {"role": "assistant", "content": [{"type": "text", "text": "Bonjour"}]},
+                {"role": "assistant", "content": null, "tool_calls": []}
+            ]
+        }))
+        .unwrap();
+
+        let contents: Vec<Option<&str>> = request
+            .messages
+            .iter()
+            .map(|message| message.content.as_deref())
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                Some("Be brief.\nAnswer in French."),
+                Some("Hello"),
+                Some("Bonjour"),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_image_parts_become_images() {
+        let message: OpenAIMessage = serde_json::from_value(json!({
+            "role": "user",
+            "content": [
+                {"type": "text", "text": "What is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(message.content.as_deref(), Some("What is this?"));
+        assert_eq!(
+            message.images,
+            Some(vec!["data:image/png;base64,AAAA".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_content_rejects_other_parts() {
+        let error = serde_json::from_value::<OpenAIMessage>(json!({
+            "role": "user",
+            "content": [{"type": "input_audio", "input_audio": {"data": "AAAA", "format": "wav"}}]
+        }))
+        .unwrap_err();
+        assert!(error.to_string().contains("input_audio"), "{}", error);
+    }
 }
@@ -2,14 +2,11 @@ use crate::copilot::models::{CopilotModel, CopilotModelsResponse};
 use crate::openai::completion::models::{
     OpenAIChatRequest, OpenAIMessage, OpenAIModel, OpenAIModelsResponse,
 };
-impl OpenAIChatRequest {
-    fn assistant_role() -> String {
-        "assistant".to_string()
-    }
+use std::collections::HashSet;
 
-    fn tool_role() -> String {
-        "tool".to_string()
-    }
+impl OpenAIChatRequest {
+    const ASSISTANT_ROLE: &str = "assistant";
+    const TOOL_ROLE: &str = "tool";
 
     fn has_valid_id(id: &Option<String>) -> bool {
         id.as_ref().is_some_and(|s| !s.is_empty())
@@ -23,13 +20,13 @@ impl OpenAIChatRequest {
         let all_tool_messages_have_ids = self
             .messages
             .iter()
-            .filter(|t| t.role == Self::tool_role())
+            .filter(|t| t.role == Self::TOOL_ROLE)
             .all(|msg| Self::has_valid_id(&msg.tool_call_id));
 
         let all_tool_calls_have_ids = self
             .messages
             .iter()
-            .filter(|msg| msg.role == Self::assistant_role())
+            .filter(|msg| msg.role == Self::ASSISTANT_ROLE)
             .filter_map(|msg| msg.tool_calls.as_ref())
             .flat_map(|calls| calls.iter())
             .all(|call| Self::has_valid_id(&call.id));
@@ -40,9 +37,9 @@ impl OpenAIChatRequest {
     /// Applies all necessary transformations for GitHub Copilot compatibility.
     ///
     /// This is the main entry point for preparing requests before sending to Copilot.
-    /// It orchestrates two critical transformations:
-    /// 1. Ensures tool IDs are present (required by OpenAI spec)
-    /// 2. Duplicates tool messages as user messages (works around Copilot quirks)
+    /// It ensures tool IDs are present (required by OpenAI spec). Tool messages are no
+    /// longer duplicated as user messages up front: that only happens when retrying a reply
+    /// without choices, under `[copilot.empty_choices] duplicate_tool_messages`.
     ///
     /// Call this method once on any request that contains tools before forwarding to Copilot.
     pub fn prepare_for_copilot(&mut self) {
@@ -53,13 +50,16 @@ impl OpenAIChatRequest {
     /// Generates and assigns IDs to tool-related messages when they are missing.
     /// This method only modifies the request if ids_present() returns false.
     ///
-    /// It assigns:
-    /// - tool_call_id to messages with role "tool" (indexed sequentially)
-    /// - id to tool_calls in assistant messages (indexed sequentially)
-    /// - name to tool messages (extracted from assistant's tool_calls)
+    /// Messages are walked in order, and each tool message is matched to the nearest
+    /// preceding assistant message with tool_calls. It assigns:
+    /// - id to tool_calls without one (`call_0`, `call_1`, ... skipping ids already in use)
+    /// - tool_call_id to tool messages without one: the first call of their block not yet
+    ///   answered by an earlier tool message, and of the same name when the tool message
+    ///   has one
+    /// - name to tool messages without one (taken from the matching tool call)
     ///
-    /// If the original request already had IDs, this method does nothing,
-    /// preserving the client-provided identifiers.
+    /// Ids and names the client sent are kept, so a request where only some are missing
+    /// keeps the identifiers it already had.
     ///
     /// # Why This Is Necessary
     ///
@@ -72,39 +72,77 @@ impl OpenAIChatRequest {
     /// the generated OpenAIChatRequest structs won't have these IDs. This proxy bridges
     /// that gap by auto-generating them before forwarding to GitHub Copilot.
     fn ensure_tool_ids(&mut self) {
-        if !self.ids_present() {
-            let assistant_tool_name = self
-                .messages
-                .iter()
-                .filter(|message| message.role == Self::assistant_role())
-                .flat_map(|message| match &message.tool_calls {
-                    Some(tool_calls) => tool_calls.clone(),
-                    _ => Vec::new(),
-                })
-                .map(|tool_call| tool_call.function.name)
-                .collect::<Vec<String>>();
-
-            self.messages
-                .iter_mut()
-                .filter(|message| message.role == Self::tool_role())
-                .enumerate()
-                .zip(assistant_tool_name.iter())
-                .for_each(|((idx, message), tool_name)| {
-                    message.name = Some(tool_name.to_string());
-                    message.tool_call_id = Some(format!("{}", idx))
-                });
-
-            self.messages
-                .iter_mut()
-                .filter(|message| message.role == Self::assistant_role())
-                .filter(|message| message.tool_calls.is_some())
-                .for_each(|message| {
-                    if let Some(ref mut tc) = message.tool_calls {
-                        tc.iter_mut().enumerate().for_each(|(idx, tool_call)| {
-                            tool_call.id = Some(format!("{}", idx));
-                        })
+        if self.ids_present() {
+            return;
+        }
+
+        let mut used_ids = self
+            .messages
+            .iter()
+            .filter_map(|message| message.tool_calls.as_ref())
+            .flatten()
+            .filter_map(|tool_call| tool_call.id.clone())
+            .chain(
+                self.messages
+                    .iter()
+                    .filter_map(|message| message.tool_call_id.clone()),
+            )
+            .filter(|id| !id.is_empty())
+            .collect::<HashSet<String>>();
+        let mut next_id = 0;
+        let mut new_id = move || loop {
+            let id = format!("call_{}", next_id);
+            next_id += 1;
+            if used_ids.insert(id.clone()) {
+                return id;
+            }
+        };
+
+        // The nearest preceding assistant message with tool_calls, and which of its calls
+        // have been answered. Calls are looked up there rather than copied out.
+        let mut block: Option<usize> = None;
+        let mut answered: Vec<bool> = Vec::new();
+        for index in 0..self.messages.len() {
+            let (before, rest) = self.messages.split_at_mut(index);
+            let message = &mut rest[0];
+            if message.role == Self::ASSISTANT_ROLE {
+                block = Some(index);
+                answered.clear();
+                for tool_call in message.tool_calls.iter_mut().flatten() {
+                    if !Self::has_valid_id(&tool_call.id) {
+                        tool_call.id = Some(new_id());
                     }
-                });
+                    answered.push(false);
+                }
+            } else if message.role == Self::TOOL_ROLE {
+                let calls = block
+                    .and_then(|block| before[block].tool_calls.as_deref())
+                    .unwrap_or_default();
+                let call = match &message.tool_call_id {
+                    Some(id) if !id.is_empty() => {
+                        calls.iter().position(|call| call.id.as_ref() == Some(id))
+                    }
+                    _ => {
+                        // By name when the tool message has one, as Ollama clients send them,
+                        // so results given out of order still line up; otherwise by order
+                        let unanswered = || (0..calls.len()).filter(|&i| !answered[i]);
+                        unanswered()
+                            .find(|&i| message.name.as_ref() == Some(&calls[i].function.name))
+                            .or_else(|| unanswered().next())
+                    }
+                };
+                let Some(call) = call else {
+                    continue;
+                };
+                answered[call] = true;
+                let call = &calls[call];
+                if !Self::has_valid_id(&message.tool_call_id) {
+                    message.tool_call_id = call.id.clone();
+                }
+                if message.name.is_none() {
+                    message.name = Some(call.function.name.clone());
+                }
+            }
         }
     }
 
@@ -151,7 +189,7 @@ impl OpenAIChatRequest {
 
         // Find all tool messages and create user message duplicates
         for (idx, message) in self.messages.iter().enumerate() {
-            if message.role == Self::tool_role() {
+            if message.role == Self::TOOL_ROLE {
                 last_tool_index = Some(idx);
 
                 let tool_name = message.name.as_deref().unwrap_or("unknown_tool");
@@ -168,6 +206,9 @@ impl OpenAIChatRequest {
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 };
 
                 user_duplicates.push(user_message);
@@ -199,6 +240,147 @@ impl From<CopilotModel> for OpenAIModel {
             object: "model".to_string(),
             created: 1687882411,
             owned_by: value.family,
+            name: None,
+            description: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tool_call(id: Option<&str>, name: &str) -> serde_json::Value {
+        json!({"id": id, "type": "function", "function": {"name": name, "arguments": "{}"}})
+    }
+
+    fn ids(request: &OpenAIChatRequest) -> Vec<(String, Option<String>, Option<String>)> {
+        request
+            .messages
+            .iter()
+            .flat_map(|message| match &message.tool_calls {
+                Some(tool_calls) => tool_calls
+                    .iter()
+                    .map(|call| {
+                        (
+                            "call".to_string(),
+                            call.id.clone(),
+                            Some(call.function.name.clone()),
+                        )
+                    })
+                    .collect(),
+                None if message.role == "tool" => vec![(
+                    "tool".to_string(),
+                    message.tool_call_id.clone(),
+                    message.name.clone(),
+                )],
+                None => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn entry(kind: &str, id: &str, name: &str) -> (String, Option<String>, Option<String>) {
+        (
+            kind.to_string(),
+            Some(id.to_string()),
+            Some(name.to_string()),
+        )
+    }
+
+    #[test]
+    fn test_ensure_tool_ids_across_turns() {
+        let mut request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "weather and time?"},
+                {"role": "assistant", "tool_calls": [
+                    tool_call(None, "get_weather"),
+                    tool_call(None, "get_time")
+                ]},
+                {"role": "tool", "content": "72F"},
+                {"role": "tool", "content": "noon"},
+                {"role": "assistant", "tool_calls": [tool_call(None, "get_news")]},
+                {"role": "tool", "content": "nothing new"}
+            ]
+        }))
+        .unwrap();
+
+        request.prepare_for_copilot();
+
+        assert_eq!(
+            ids(&request),
+            vec![
+                entry("call", "call_0", "get_weather"),
+                entry("call", "call_1", "get_time"),
+                entry("tool", "call_0", "get_weather"),
+                entry("tool", "call_1", "get_time"),
+                entry("call", "call_2", "get_news"),
+                entry("tool", "call_2", "get_news"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ensure_tool_ids_keeps_present_ids() {
+        let mut request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "assistant", "tool_calls": [
+                    tool_call(Some("call_0"), "get_weather"),
+                    tool_call(None, "get_time")
+                ]},
+                // Answered out of order, by id
+                {"role": "tool", "tool_call_id": "call_0", "content": "72F"},
+                {"role": "tool", "content": "noon"}
+            ]
+        }))
+        .unwrap();
+
+        request.prepare_for_copilot();
+
+        assert_eq!(
+            ids(&request),
+            vec![
+                entry("call", "call_0", "get_weather"),
+                entry("call", "call_1", "get_time"),
+                entry("tool", "call_0", "get_weather"),
+                entry("tool", "call_1", "get_time"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ensure_tool_ids_by_name() {
+        // Two rounds without ids, the first answered out of order by name
+        let mut request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "assistant", "tool_calls": [
+                    tool_call(None, "get_weather"),
+                    tool_call(None, "get_time")
+                ]},
+                {"role": "tool", "name": "get_time", "content": "noon"},
+                {"role": "tool", "name": "get_weather", "content": "72F"},
+                {"role": "user", "content": "and tomorrow?"},
+                {"role": "assistant", "tool_calls": [tool_call(None, "get_weather")]},
+                {"role": "tool", "name": "get_weather", "content": "65F"}
+            ]
+        }))
+        .unwrap();
+
+        request.prepare_for_copilot();
+
+        assert_eq!(
+            ids(&request),
+            vec![
+                entry("call", "call_0", "get_weather"),
+                entry("call", "call_1", "get_time"),
+                entry("tool", "call_1", "get_time"),
+                entry("tool", "call_0", "get_weather"),
+                entry("call", "call_2", "get_weather"),
+                entry("tool", "call_2", "get_weather"),
+            ]
+        );
+    }
+}
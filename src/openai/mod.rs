@@ -1,2 +1,4 @@
 pub mod completion;
+pub mod moderation;
 pub mod responses;
+pub mod text_completion;
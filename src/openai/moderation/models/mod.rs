@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// OpenAI moderation request
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModerationRequest {
+    pub input: ModerationInput,
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// A single text, a batch of texts, or one multimodal input
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ModerationInput {
+    Text(String),
+    Texts(Vec<String>),
+    Parts(Vec<ModerationInputPart>),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModerationInputPart {
+    Text { text: String },
+    ImageUrl { image_url: serde_json::Value },
+}
+
+impl ModerationInput {
+    /// One text per result; images cannot be checked and are skipped
+    pub fn texts(&self) -> Vec<String> {
+        match self {
+            ModerationInput::Text(text) => vec![text.clone()],
+            ModerationInput::Texts(texts) => texts.clone(),
+            ModerationInput::Parts(parts) => vec![
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ModerationInputPart::Text { text } => Some(text.as_str()),
+                        ModerationInputPart::ImageUrl { .. } => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ],
+        }
+    }
+}
+
+/// OpenAI moderation response
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModerationResponse {
+    pub id: String,
+    pub model: String,
+    pub results: Vec<ModerationResult>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    pub categories: BTreeMap<String, bool>,
+    pub category_scores: BTreeMap<String, f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_forms() {
+        let parse = |json| serde_json::from_value::<ModerationRequest>(json).unwrap();
+
+        let single = parse(serde_json::json!({"input": "hello"}));
+        assert_eq!(single.input.texts(), vec!["hello"]);
+
+        let batch =
+            parse(serde_json::json!({"input": ["a", "b"], "model": "omni-moderation-latest"}));
+        assert_eq!(batch.input.texts(), vec!["a", "b"]);
+        assert_eq!(batch.model.as_deref(), Some("omni-moderation-latest"));
+
+        let parts = parse(serde_json::json!({"input": [
+            {"type": "text", "text": "look at this"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+        ]}));
+        assert_eq!(parts.input.texts(), vec!["look at this"]);
+    }
+}
@@ -1,6 +1,14 @@
-use serde::{Deserialize, Serialize};
+use crate::openai::responses::models::prompt_response::Reasoning;
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Responses API request.
+///
+/// Deserialization is deliberately tolerant: unknown fields are skipped and duplicate
+/// keys resolve to the last occurrence (Rig emits `role` twice on user input items),
+/// mirroring how `serde_json::Value` would have read the payload.
+#[derive(Debug, Clone, Serialize)]
 pub struct PromptRequest {
     pub input: Vec<Message>,
     pub model: String,
@@ -10,9 +18,26 @@ pub struct PromptRequest {
     pub tools: Vec<Tool>,
     #[serde(default)]
     pub stream: bool,
+    pub tool_choice: Option<ToolChoice>,
+    pub parallel_tool_calls: Option<bool>,
+    pub user: Option<String>,
+    pub reasoning: Option<Reasoning>,
 }
 
+/// Responses API tool choice: `"auto"`, `"none"`, `"required"` or a flat
+/// `{"type": "function", "name": "..."}` object (unlike Chat Completions, the name is not nested).
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolChoice {
+    Mode(String),
+    Function {
+        #[serde(rename = "type")]
+        tool_type: String,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct Message {
     pub role: Option<String>,
     #[serde(rename = "type")]
@@ -53,3 +78,193 @@ pub struct ToolParameters {
 fn default_tools() -> Vec<Tool> {
     vec![]
 }
+
+impl<'de> Deserialize<'de> for PromptRequest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PromptRequestVisitor;
+
+        impl<'de> Visitor<'de> for PromptRequestVisitor {
+            type Value = PromptRequest;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a Responses API request object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<PromptRequest, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut input = None;
+                let mut model = None;
+                let mut instructions = None;
+                let mut max_output_tokens = None;
+                let mut tools = None;
+                let mut stream = None;
+                let mut tool_choice = None;
+                let mut parallel_tool_calls = None;
+                let mut user = None;
+                let mut reasoning = None;
+
+                // Later duplicates overwrite earlier ones
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "input" => input = Some(map.next_value()?),
+                        "model" => model = Some(map.next_value()?),
+                        "instructions" => instructions = map.next_value()?,
+                        "max_output_tokens" => max_output_tokens = map.next_value()?,
+                        "tools" => tools = map.next_value()?,
+                        "stream" => stream = map.next_value()?,
+                        "tool_choice" => tool_choice = map.next_value()?,
+                        "parallel_tool_calls" => parallel_tool_calls = map.next_value()?,
+                        "user" => user = map.next_value()?,
+                        "reasoning" => reasoning = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(PromptRequest {
+                    input: input.ok_or_else(|| de::Error::missing_field("input"))?,
+                    model: model.ok_or_else(|| de::Error::missing_field("model"))?,
+                    instructions,
+                    max_output_tokens,
+                    tools: tools.unwrap_or_else(default_tools),
+                    stream: stream.unwrap_or_default(),
+                    tool_choice,
+                    parallel_tool_calls,
+                    user,
+                    reasoning,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(PromptRequestVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct MessageVisitor;
+
+        impl<'de> Visitor<'de> for MessageVisitor {
+            type Value = Message;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an input item object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Message, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut role = None;
+                let mut message_type = None;
+                let mut content = None;
+                let mut name = None;
+                let mut arguments = None;
+                let mut output = None;
+
+                // Later duplicates overwrite earlier ones
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "role" => role = map.next_value()?,
+                        "type" => message_type = Some(map.next_value()?),
+                        "content" => content = map.next_value()?,
+                        "name" => name = map.next_value()?,
+                        "arguments" => arguments = map.next_value()?,
+                        "output" => output = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(Message {
+                    role,
+                    message_type: message_type.ok_or_else(|| de::Error::missing_field("type"))?,
+                    content,
+                    name,
+                    arguments,
+                    output,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(MessageVisitor)
+    }
+}
+
+/// Parse a raw request body into a [`PromptRequest`].
+///
+/// On failure, returns the JSON path of the offending value (e.g. `input[0].content[1].type`)
+/// alongside the underlying serde error, so callers can point clients at the bad field.
+pub fn parse_prompt_request(body: &str) -> Result<PromptRequest, (String, serde_json::Error)> {
+    let deserializer = &mut serde_json::Deserializer::from_str(body);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        (path, e.into_inner())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_keys_last_one_wins() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "input": [
+                { "role": "assistant", "type": "message", "role": "user",
+                  "content": [{ "type": "input_text", "text": "Hi" }] }
+            ]
+        }"#;
+
+        let request = parse_prompt_request(json).unwrap();
+        assert_eq!(request.input[0].role.as_deref(), Some("user"));
+    }
+
+    #[test]
+    fn test_unknown_fields_are_ignored() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "temperature": 0.2,
+            "input": [{ "type": "message", "role": "user", "status": "completed" }]
+        }"#;
+
+        let request = parse_prompt_request(json).unwrap();
+        assert_eq!(request.model, "gpt-4o");
+        assert!(request.tools.is_empty());
+        assert!(!request.stream);
+    }
+
+    #[test]
+    fn test_error_reports_path() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "input": [
+                { "type": "message", "role": "user",
+                  "content": [{ "type": "input_text", "text": "Hi" }, { "type": "input_text" }] }
+            ]
+        }"#;
+
+        let (path, error) = parse_prompt_request(json).unwrap_err();
+        assert_eq!(path, "input[0].content[1]");
+        assert!(error.to_string().contains("text"));
+    }
+
+    #[test]
+    fn test_missing_model_is_reported() {
+        let (path, error) = parse_prompt_request(r#"{ "input": [] }"#).unwrap_err();
+        assert_eq!(path, ".");
+        assert!(error.to_string().contains("model"));
+    }
+}
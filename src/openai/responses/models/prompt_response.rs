@@ -204,7 +204,7 @@ pub enum OpenAIServiceTier {
 }
 
 /// The amount of reasoning effort that will be used by a given model.
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ReasoningEffort {
     None,
@@ -402,6 +402,16 @@ pub enum ResponseStreamEvent {
     /// Emitted once at the end with the fully assembled `CompletionResponse`.
     #[serde(rename = "response.completed")]
     ResponseCompleted { response: CompletionResponse },
+
+    /// Emitted instead of `response.completed` when the model stopped early, at the
+    /// output token limit or a content filter, with `incomplete_details` set.
+    #[serde(rename = "response.incomplete")]
+    ResponseIncomplete { response: CompletionResponse },
+
+    /// Emitted instead of `response.completed` when generation fails part-way, with
+    /// the `error` set on the response.
+    #[serde(rename = "response.failed")]
+    ResponseFailed { response: CompletionResponse },
 }
 
 /// A text content part used inside streaming lifecycle events.
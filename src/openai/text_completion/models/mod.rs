@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+/// OpenAI legacy completion request, with `suffix` for fill-in-the-middle
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OpenAICompletionRequest {
+    #[serde(default)]
+    pub model: String,
+    pub prompt: OneOrMany,
+    #[serde(default)]
+    pub suffix: Option<String>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub n: Option<u32>,
+    #[serde(default)]
+    pub stop: Option<OneOrMany>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// A string, or an array of them
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        }
+    }
+}
+
+/// OpenAI legacy completion response, or one chunk of it when streaming
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OpenAICompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<OpenAICompletionChoice>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OpenAICompletionChoice {
+    pub text: String,
+    pub index: u32,
+    /// Always `null`: Copilot does not return log probabilities for completions
+    pub logprobs: Option<serde_json::Value>,
+    pub finish_reason: Option<String>,
+}
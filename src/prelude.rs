@@ -0,0 +1,15 @@
+//! The types most uses of the library need, for `use passenger_rs::prelude::*`
+
+pub use crate::config::Config;
+pub use crate::copilot::client::CopilotClient;
+pub use crate::copilot::finish_reason::FinishReason;
+pub use crate::copilot::stream::{ChatDelta, ToolCallDelta};
+pub use crate::copilot::{
+    CopilotChatChunk, CopilotChatRequest, CopilotChatResponse, CopilotContent, CopilotMessage,
+};
+pub use crate::logging::LogFilter;
+pub use crate::openai::completion::models::{
+    OpenAIChatRequest, OpenAIChatResponse, OpenAIChoice, OpenAIMessage, OpenAIUsage,
+};
+pub use crate::openai::responses::models::prompt_response::CompletionResponse;
+pub use crate::server::{AppError, Server};
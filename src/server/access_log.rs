@@ -0,0 +1,426 @@
+use crate::config::{AccessLogConfig, AccessLogFormat};
+use crate::server::AppState;
+use crate::server::client_ip;
+use crate::server::keys::presented_key_id;
+use crate::server::oidc::Identity;
+use crate::utc::UtcTime;
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::{HeaderMap, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures_util::StreamExt as _;
+use serde::Deserialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::log::warn;
+
+/// Access log of `[access_log]`: one line per request, written once its response body has
+/// been sent so that streamed bytes and durations are complete
+pub struct AccessLog {
+    format: AccessLogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn from_config(config: &AccessLogConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let out: Box<dyn Write + Send> = match &config.path {
+            Some(path) => {
+                let path = Path::new(path);
+                if let Some(parent) = path
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty())
+                {
+                    std::fs::create_dir_all(parent)
+                        .context("Failed to create access log directory")?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open access log {}", path.display()))?;
+                Box::new(file)
+            }
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(Some(Self::new(config.format, out)))
+    }
+
+    pub fn new(format: AccessLogFormat, out: Box<dyn Write + Send>) -> Self {
+        Self {
+            format,
+            out: Mutex::new(out),
+        }
+    }
+
+    fn write(&self, entry: &Entry) {
+        let line = entry.format(self.format);
+        let mut out = self.out.lock().expect("access log lock poisoned");
+        if let Err(e) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+            warn!("Failed to write access log: {}", e);
+        }
+    }
+}
+
+/// One request, as logged
+#[derive(Debug)]
+struct Entry {
+    time: UtcTime,
+    remote: Option<String>,
+    method: String,
+    uri: String,
+    version: String,
+    route: Option<String>,
+    model: Option<String>,
+    /// The user of the client's OIDC token, else the OpenAI `user` field of the body
+    user: Option<String>,
+    key_id: Option<String>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    status: u16,
+    bytes: u64,
+    duration: Duration,
+}
+
+impl Entry {
+    fn format(&self, format: AccessLogFormat) -> String {
+        let request = format!("{} {} {}", self.method, self.uri, self.version);
+        let common = format!(
+            "{} - {} [{}] {} {} {}",
+            or_dash(&self.remote),
+            or_dash(&self.key_id),
+            self.time.to_common_log(),
+            quoted(Some(&request)),
+            self.status,
+            if self.bytes == 0 {
+                "-".to_string()
+            } else {
+                self.bytes.to_string()
+            },
+        );
+
+        match format {
+            AccessLogFormat::Common => common,
+            AccessLogFormat::Combined => format!(
+                "{} {} {} {} {} {} {}ms",
+                common,
+                quoted(self.referer.as_deref()),
+                quoted(self.user_agent.as_deref()),
+                quoted(self.route.as_deref()),
+                quoted(self.model.as_deref()),
+                quoted(self.user.as_deref()),
+                self.duration.as_millis(),
+            ),
+            AccessLogFormat::Json => serde_json::json!({
+                "time": self.time.to_rfc3339(),
+                "remote": self.remote,
+                "method": self.method,
+                "uri": self.uri,
+                "route": self.route,
+                "model": self.model,
+                "user": self.user,
+                "key_id": self.key_id,
+                "status": self.status,
+                "bytes": self.bytes,
+                "duration_ms": self.duration.as_millis() as u64,
+                "referer": self.referer,
+                "user_agent": self.user_agent,
+            })
+            .to_string(),
+        }
+    }
+}
+
+fn or_dash(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("-")
+}
+
+/// A double-quoted log field, `"-"` when absent
+fn quoted(value: Option<&str>) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .unwrap_or("-")
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+    )
+}
+
+fn header_value(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Just the model and end user of a request body
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct BodyProbe {
+    pub model: Option<String>,
+    pub user: Option<String>,
+}
+
+/// The `model` and `user` of a JSON request body. Only bodies declaring a length within
+/// `limit` are read; the request is rebuilt from the buffered bytes.
+pub(crate) async fn peek_body(request: Request, limit: usize) -> (Request, BodyProbe) {
+    let is_json = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if !is_json || length.is_none_or(|length| length > limit) {
+        return (request, BodyProbe::default());
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, limit).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read request body for the access log: {}", e);
+            return (
+                Request::from_parts(parts, Body::empty()),
+                BodyProbe::default(),
+            );
+        }
+    };
+
+    let probe = serde_json::from_slice::<BodyProbe>(&bytes)
+        .map(|probe| BodyProbe {
+            model: probe.model.filter(|model| !model.is_empty()),
+            user: probe.user.filter(|user| !user.is_empty()),
+        })
+        .unwrap_or_default();
+    (Request::from_parts(parts, Body::from(bytes)), probe)
+}
+
+/// Completes the entry once the response body is done or dropped
+struct PendingEntry {
+    log: Arc<AccessLog>,
+    entry: Entry,
+    started: Instant,
+}
+
+impl PendingEntry {
+    fn sent(&mut self, bytes: usize) {
+        self.entry.bytes += bytes as u64;
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        self.entry.duration = self.started.elapsed();
+        self.log.write(&self.entry);
+    }
+}
+
+/// Middleware writing each request to the access log
+pub(crate) async fn log_access(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(log) = state.access_log.clone() else {
+        return next.run(request).await;
+    };
+    let started = Instant::now();
+
+    let referer = header_value(request.headers(), header::REFERER);
+    let user_agent = header_value(request.headers(), header::USER_AGENT);
+    let remote = client_ip::current()
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+        .map(|ip| ip.to_string());
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let method = request.method().to_string();
+    let uri = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| request.uri().path().to_string(), |pq| pq.to_string());
+    let version = format!("{:?}", request.version());
+    let key_id = presented_key_id(request.headers());
+
+    let (request, probe) = peek_body(request, state.config().server.max_body_bytes).await;
+    let response = next.run(request).await;
+
+    let mut pending = PendingEntry {
+        log,
+        entry: Entry {
+            time: UtcTime::now(),
+            remote,
+            method,
+            uri,
+            version,
+            route,
+            model: probe.model,
+            user: response
+                .extensions()
+                .get::<Identity>()
+                .map(|identity| identity.user.clone())
+                .or(probe.user),
+            key_id,
+            referer,
+            user_agent,
+            status: response.status().as_u16(),
+            bytes: 0,
+            duration: Duration::ZERO,
+        },
+        started,
+    };
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            pending.sent(bytes.len());
+        }
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::keys::fingerprint;
+    use axum::body::Bytes;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router, middleware};
+    use tower::ServiceExt;
+
+    /// Log output the test can read back
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    /// Send `request` through the middleware and read the whole response, completing the entry
+    async fn send(format: AccessLogFormat, request: Request) -> (StatusCode, Bytes, Buffer) {
+        let buffer = Buffer::default();
+        let mut state = AppState::new(&Config::default(), None);
+        state.access_log = Some(Arc::new(AccessLog::new(format, Box::new(buffer.clone()))));
+        let state = Arc::new(state);
+
+        let router = Router::new()
+            .route(
+                "/v1/echo/{id}",
+                post(|Json(body): Json<serde_json::Value>| async move { Json(body) }),
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), log_access))
+            .with_state(state);
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body, buffer)
+    }
+
+    fn request() -> Request {
+        let body = r#"{"model":"gpt-4o","messages":[],"user":"alice"}"#;
+        Request::post("/v1/echo/42?verbose=1")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, body.len())
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .header(header::USER_AGENT, "curl/8.5.0")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_combined_format() {
+        let (status, _, buffer) = send(AccessLogFormat::Combined, request()).await;
+        assert_eq!(status, 200);
+
+        let line = buffer.contents();
+        let key_id = fingerprint("secret");
+        assert!(line.starts_with(&format!("- - {} [", key_id)), "{}", line);
+        assert!(
+            line.contains(
+                "] \"POST /v1/echo/42?verbose=1 HTTP/1.1\" 200 47 \"-\" \"curl/8.5.0\" \"/v1/echo/{id}\" \"gpt-4o\" \"alice\" "
+            ),
+            "{}",
+            line
+        );
+        assert!(line.ends_with("ms\n"), "{}", line);
+        assert!(!line.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_json_format() {
+        let (_, body, buffer) = send(AccessLogFormat::Json, request()).await;
+        assert_eq!(
+            &body[..],
+            br#"{"messages":[],"model":"gpt-4o","user":"alice"}"#
+        );
+
+        let entry: serde_json::Value = serde_json::from_str(&buffer.contents()).unwrap();
+        assert_eq!(entry["route"], "/v1/echo/{id}");
+        assert_eq!(entry["model"], "gpt-4o");
+        assert_eq!(entry["user"], "alice");
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["bytes"], 47);
+        assert_eq!(entry["remote"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_common_format() {
+        let entry = Entry {
+            time: UtcTime::from_unix(1_767_323_045),
+            remote: Some("127.0.0.1".to_string()),
+            method: "GET".to_string(),
+            uri: "/v1/models".to_string(),
+            version: "HTTP/1.1".to_string(),
+            route: Some("/v1/models".to_string()),
+            model: None,
+            user: None,
+            key_id: None,
+            referer: None,
+            user_agent: None,
+            status: 404,
+            bytes: 0,
+            duration: Duration::from_millis(3),
+        };
+        let line = entry.format(AccessLogFormat::Common);
+        assert!(line.starts_with("127.0.0.1 - - ["), "{}", line);
+        assert!(
+            line.ends_with("] \"GET /v1/models HTTP/1.1\" 404 -"),
+            "{}",
+            line
+        );
+    }
+}
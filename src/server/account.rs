@@ -0,0 +1,56 @@
+use crate::auth::{self, CopilotUserResponse};
+use crate::copilot::account::AccountReport;
+use crate::server::{AppError, AppState, Server};
+use crate::storage;
+use axum::{Json, extract::State};
+use std::sync::Arc;
+use tracing::log::{info, warn};
+
+pub(crate) trait AccountEndpoint {
+    /// Copilot subscription, entitlements and monthly quotas of the proxy's account
+    async fn account(state: State<Arc<AppState>>) -> Result<Json<AccountReport>, AppError>;
+
+    /// Monthly quotas, or `None` when they could not be fetched
+    async fn copilot_user(state: Arc<AppState>) -> Option<CopilotUserResponse>;
+}
+
+impl AccountEndpoint for Server {
+    async fn account(State(state): State<Arc<AppState>>) -> Result<Json<AccountReport>, AppError> {
+        info!("Received account request");
+
+        let token = Self::get_token(state.clone()).await?;
+        let user = Self::copilot_user(state).await;
+
+        let report = AccountReport::new(token.entitlements, user);
+        for warning in &report.warnings {
+            warn!("Copilot account: {}", warning);
+        }
+
+        Ok(Json(report))
+    }
+
+    async fn copilot_user(state: Arc<AppState>) -> Option<CopilotUserResponse> {
+        let config = state.config();
+        let access_token = match storage::load_access_token() {
+            Ok(Some(access_token)) => access_token,
+            Ok(None) => {
+                warn!("No GitHub access token stored, cannot report monthly quotas");
+                return None;
+            }
+            Err(e) => {
+                warn!("Failed to load GitHub access token: {}", e);
+                return None;
+            }
+        };
+
+        auth::get_copilot_user(
+            &state.client,
+            &config.github.copilot_user_url,
+            &access_token.access_token,
+            &config.copilot.headers,
+        )
+        .await
+        .inspect_err(|e| warn!("Could not fetch Copilot quotas: {:?}", e))
+        .ok()
+    }
+}
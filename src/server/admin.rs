@@ -0,0 +1,216 @@
+use crate::config::AdminConfig;
+#[cfg(feature = "dashboard")]
+use crate::server::dashboard::DashboardEndpoints;
+use crate::server::{AppError, AppState, Server, constant_time_eq};
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router, middleware, routing::get};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::log::{error, info};
+
+/// Body of `GET` and `PUT /admin/log-level`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogLevel {
+    /// `RUST_LOG`-style directives, e.g. `info,passenger_rs::server::ollama=debug`
+    pub filter: String,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait AdminEndpoints {
+    async fn get_log_level(state: State<Arc<AppState>>) -> Result<Json<LogLevel>, AppError>;
+
+    async fn set_log_level(
+        state: State<Arc<AppState>>,
+        body: Json<LogLevel>,
+    ) -> Result<Json<LogLevel>, AppError>;
+}
+
+impl AdminEndpoints for Server {
+    async fn get_log_level(State(state): State<Arc<AppState>>) -> Result<Json<LogLevel>, AppError> {
+        let log_filter = log_filter(&state)?;
+
+        Ok(Json(LogLevel {
+            filter: log_filter.current(),
+        }))
+    }
+
+    async fn set_log_level(
+        State(state): State<Arc<AppState>>,
+        Json(body): Json<LogLevel>,
+    ) -> Result<Json<LogLevel>, AppError> {
+        let log_filter = log_filter(&state)?;
+
+        log_filter
+            .set(&body.filter)
+            .map_err(|e| AppError::BadRequest(e.to_string()))?;
+        info!("Log filter changed to: {}", body.filter);
+
+        Ok(Json(body))
+    }
+}
+
+fn log_filter(state: &AppState) -> Result<&crate::logging::LogFilter, AppError> {
+    state.log_filter.as_deref().ok_or_else(|| {
+        error!("Log filter change requested, but logging was not set up by passenger-rs");
+        AppError::InternalServerError("Runtime log filter is not available".to_string())
+    })
+}
+
+/// Routes mounted under `/admin` when `[admin] enabled = true`. The dashboard page is
+/// public; the data it shows comes from the token-protected `/admin/stats`.
+pub(crate) fn router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let router = Router::new().route(
+        "/log-level",
+        get(Server::get_log_level).put(Server::set_log_level),
+    );
+    #[cfg(feature = "dashboard")]
+    let router = router.route("/stats", get(Server::dashboard_stats));
+
+    let public = Router::new();
+    #[cfg(feature = "dashboard")]
+    let public = public.route("/dashboard", get(Server::dashboard));
+
+    router
+        .route_layer(middleware::from_fn_with_state(state, require_admin_token))
+        .merge(public)
+}
+
+/// Reject admin requests without the configured bearer token
+async fn require_admin_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if grants_admin(&state.config().admin, provided) {
+        next.run(request).await
+    } else {
+        error!("Rejecting admin request without a valid admin token");
+        AppError::Unauthorized("Invalid admin token".to_string()).into_response()
+    }
+}
+
+/// Whether `provided` grants admin rights: `[admin]` must be enabled with a `token`, and
+/// `provided` must match it. Without a token nobody is admin.
+pub(crate) fn grants_admin(admin: &AdminConfig, provided: Option<&str>) -> bool {
+    if !admin.enabled {
+        return false;
+    }
+    match (admin.token.as_deref(), provided) {
+        (Some(expected), Some(token)) if !expected.is_empty() => {
+            constant_time_eq(token.as_bytes(), expected.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::http::StatusCode;
+
+    /// Serve the router on an ephemeral port, returning the `/admin/log-level` URL
+    async fn serve(enabled: bool, token: Option<&str>) -> String {
+        format!("{}/log-level", serve_admin(enabled, token).await)
+    }
+
+    /// Serve the router on an ephemeral port, returning the `/admin` URL
+    async fn serve_admin(enabled: bool, token: Option<&str>) -> String {
+        let mut config = Config::default();
+        config.admin.enabled = enabled;
+        config.admin.token = token.map(str::to_string);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        format!("http://{}/admin", addr)
+    }
+
+    #[tokio::test]
+    async fn test_admin_token_is_required() {
+        let url = serve(true, Some("secret")).await;
+        let client = reqwest::Client::new();
+
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client.get(&url).bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "authentication_error");
+        assert_eq!(body["error"]["code"], "invalid_authentication");
+        assert_eq!(body["error"]["message"], "Invalid admin token");
+
+        // Authorised, but this server was built without a reloadable log filter
+        let response = client.get(&url).bearer_auth("secret").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_admin_without_token_grants_nothing() {
+        let url = serve(true, None).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .put(&url)
+            .json(&serde_json::json!({"filter": "trace"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client.get(&url).bearer_auth("").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_disabled_by_default() {
+        let url = serve(false, None).await;
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[cfg(feature = "dashboard")]
+    #[tokio::test]
+    async fn test_dashboard_page_is_public_and_stats_need_the_token() {
+        let url = serve_admin(true, Some("secret")).await;
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("{}/dashboard", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.text().await.unwrap().contains("fetch(\"stats\""));
+
+        let stats = format!("{}/stats", url);
+        let response = client.get(&stats).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .get(&stats)
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats: serde_json::Value = response.json().await.unwrap();
+        // The rejected request above
+        assert_eq!(stats["responses"]["401"], 1);
+        assert!(stats["premium"]["month"].is_string());
+        assert!(stats["models"].is_array());
+    }
+}
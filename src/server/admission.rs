@@ -0,0 +1,88 @@
+use crate::auth::CopilotTokenResponse;
+use crate::copilot::CopilotChatRequest;
+use crate::copilot::adaptation::ModelAdjustments;
+use crate::server::capabilities::ModelAdaptation;
+use crate::server::context_window::ContextWindow;
+use crate::server::overrides::RequestOverrides;
+use crate::server::users::admit_user;
+use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderMap;
+use std::sync::Arc;
+use tracing::log::error;
+
+/// A request let in past its user's and client's limits
+#[derive(Debug)]
+pub(crate) struct Admission {
+    /// The Copilot session the request belongs to
+    pub session_id: String,
+}
+
+/// Admit a request from `user`, the OpenAI `user` field, resolving its session. Every
+/// endpoint forwarding to Copilot starts here, so `[users]` and `[oidc]` limits apply to
+/// all of them alike.
+pub(crate) fn admit(
+    state: &AppState,
+    headers: &HeaderMap,
+    user: Option<&str>,
+) -> Result<Admission, AppError> {
+    let session_id = state.sessions.resolve(headers, user);
+    admit_user(state, user)?;
+    Ok(Admission { session_id })
+}
+
+/// A chat request ready to forward
+pub(crate) struct PreparedChat {
+    /// The state to serve it with, pointed at any overriding Copilot API
+    pub state: Arc<AppState>,
+    pub token: CopilotTokenResponse,
+    /// What was changed for the model to accept the request
+    pub adjustments: ModelAdjustments,
+}
+
+/// The preparation every chat request goes through once admitted
+pub(crate) trait ChatAdmission: ModelAdaptation + ContextWindow {
+    /// Apply the request's override headers, preset and virtual model, get a Copilot token,
+    /// then resolve and adapt the model and fit the prompt in its context window
+    async fn prepare_chat(
+        state: Arc<AppState>,
+        headers: &HeaderMap,
+        admission: &Admission,
+        request: &mut CopilotChatRequest,
+    ) -> Result<PreparedChat, AppError>;
+}
+
+impl ChatAdmission for Server {
+    async fn prepare_chat(
+        state: Arc<AppState>,
+        headers: &HeaderMap,
+        admission: &Admission,
+        request: &mut CopilotChatRequest,
+    ) -> Result<PreparedChat, AppError> {
+        let overrides = RequestOverrides::from_headers(headers, &state.config().admin)?;
+        overrides.apply(request);
+        let state = overrides.state(state);
+        let config = state.config();
+        request.expand_preset(&config.presets).map_err(|e| {
+            error!("Rejecting request: {}", e);
+            AppError::BadRequest(e)
+        })?;
+        request.expand_virtual_model(&config.virtual_models);
+        request.validate_tool_choice().map_err(|e| {
+            error!("Rejecting request with invalid tool_choice: {}", e);
+            AppError::BadRequest(e)
+        })?;
+
+        // Get a valid Copilot token
+        let token = Self::get_token(state.clone()).await?;
+
+        Self::resolve_model(state.clone(), request).await?;
+        let adjustments = Self::adapt_to_model(state.clone(), request).await;
+        Self::fit_context_window(state.clone(), request, &admission.session_id).await;
+
+        Ok(PreparedChat {
+            state,
+            token,
+            adjustments,
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use crate::server::stream_stats::StreamStats;
+use futures_util::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tracing::log::info;
+
+/// Wraps a streaming response body built on top of the upstream Copilot stream.
+///
+/// When a client disconnects, axum drops the response body; dropping this wrapper
+/// drops the upstream `reqwest` body with it, which closes the connection (or resets
+/// the HTTP/2 stream) so Copilot stops generating. A drop before the upstream stream
+/// ended is logged and counted as a cancelled stream; either way the stream's
+/// timings are handed to its [`StreamStats`].
+pub(crate) struct CancellableStream<S> {
+    inner: Pin<Box<S>>,
+    stats: Arc<StreamStats>,
+    finished: bool,
+}
+
+impl<S> CancellableStream<S> {
+    pub(crate) fn new(inner: S, stats: Arc<StreamStats>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            stats,
+            finished: false,
+        }
+    }
+}
+
+impl<S, T, E> Stream for CancellableStream<S>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.as_mut().poll_next(cx);
+
+        // An upstream error ends the response too; that is not a client cancellation
+        if matches!(poll, Poll::Ready(None) | Poll::Ready(Some(Err(_)))) {
+            self.finished = true;
+        }
+
+        poll
+    }
+}
+
+impl<S> Drop for CancellableStream<S> {
+    fn drop(&mut self) {
+        if !self.finished {
+            info!(
+                "Client disconnected from {} stream; aborting upstream Copilot request",
+                self.stats.endpoint()
+            );
+        }
+        self.stats.finish(self.finished);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::metrics::Metrics;
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Records whether the upstream stream has been dropped
+    struct DropFlag(Arc<AtomicBool>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn upstream(dropped: Arc<AtomicBool>) -> impl Stream<Item = Result<u32, String>> {
+        let flag = DropFlag(dropped);
+        futures_util::stream::iter(vec![Ok(1), Ok(2), Ok(3)]).map(move |item| {
+            let _ = &flag;
+            item
+        })
+    }
+
+    #[tokio::test]
+    async fn test_drop_mid_stream_aborts_upstream_and_counts_cancellation() {
+        let metrics = Arc::new(Metrics::default());
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let mut stream = CancellableStream::new(
+            upstream(dropped.clone()),
+            StreamStats::new(metrics.clone(), "chat_completions", "gpt-4o"),
+        );
+        assert_eq!(stream.next().await, Some(Ok(1)));
+        drop(stream);
+
+        assert!(dropped.load(Ordering::SeqCst));
+        assert_eq!(metrics.streams_cancelled("chat_completions"), 1);
+        assert_eq!(metrics.streams_completed("chat_completions"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fully_consumed_stream_counts_completion() {
+        let metrics = Arc::new(Metrics::default());
+        let dropped = Arc::new(AtomicBool::new(false));
+
+        let stream = CancellableStream::new(
+            upstream(dropped),
+            StreamStats::new(metrics.clone(), "ollama_chat", "gpt-4o"),
+        );
+        let items: Vec<_> = stream.collect().await;
+
+        assert_eq!(items, vec![Ok(1), Ok(2), Ok(3)]);
+        assert_eq!(metrics.streams_completed("ollama_chat"), 1);
+        assert_eq!(metrics.streams_cancelled("ollama_chat"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_is_not_a_cancellation() {
+        let metrics = Arc::new(Metrics::default());
+        let upstream = futures_util::stream::iter(vec![Ok(1), Err("boom".to_string()), Ok(2)]);
+
+        let mut stream = CancellableStream::new(
+            upstream,
+            StreamStats::new(metrics.clone(), "responses", "gpt-4o"),
+        );
+        assert_eq!(stream.next().await, Some(Ok(1)));
+        assert!(stream.next().await.unwrap().is_err());
+        drop(stream);
+
+        assert_eq!(metrics.streams_cancelled("responses"), 0);
+        assert_eq!(metrics.streams_completed("responses"), 1);
+    }
+}
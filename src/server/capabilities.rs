@@ -0,0 +1,249 @@
+use crate::copilot::CopilotChatRequest;
+use crate::copilot::adaptation::{ADJUSTMENTS_HEADER, ModelAdjustments};
+use crate::copilot::models::ModelCapabilities;
+use crate::copilot::models::aliases::resolve_model_id;
+use crate::server::openai::list_models::CoPilotListModels;
+use crate::server::{AppError, AppState, Server, keys};
+use axum::http::HeaderValue;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::log::{debug, error, info, warn};
+
+/// Model name standing for the `default_model` of a profile
+pub const DEFAULT_MODEL: &str = "default";
+
+/// How long the model catalogue is trusted before it is fetched again
+const CATALOGUE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long a failed catalogue fetch is remembered, so requests do not each retry it
+const CATALOGUE_RETRY: Duration = Duration::from_secs(60);
+
+/// Capabilities per model id
+pub type Models = Arc<HashMap<String, ModelCapabilities>>;
+
+/// The model catalogue, from the Copilot models endpoint
+#[derive(Debug, Default)]
+pub struct ModelCatalogue {
+    cached: Mutex<Option<(Instant, Models)>>,
+}
+
+impl ModelCatalogue {
+    /// `None` when the catalogue has not been fetched or has expired
+    pub(crate) fn get(&self) -> Option<Models> {
+        let cached = self.cached.lock().expect("model catalogue lock poisoned");
+        match &*cached {
+            Some((expires, models)) if Instant::now() < *expires => Some(models.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn store(&self, models: HashMap<String, ModelCapabilities>) {
+        *self.cached.lock().expect("model catalogue lock poisoned") =
+            Some((Instant::now() + CATALOGUE_TTL, Arc::new(models)));
+    }
+
+    /// Remember a failed fetch as an empty catalogue, against which nothing is validated
+    fn store_failure(&self) {
+        *self.cached.lock().expect("model catalogue lock poisoned") =
+            Some((Instant::now() + CATALOGUE_RETRY, Arc::default()));
+    }
+}
+
+/// Fits requests to what the target model supports
+pub(crate) trait ModelAdaptation: CoPilotListModels {
+    /// The model catalogue, empty when it could not be fetched
+    async fn model_catalogue(state: Arc<AppState>) -> Models;
+
+    /// Capabilities of `model`, or `None` when it is unknown
+    async fn model_capabilities(state: Arc<AppState>, model: &str) -> Option<ModelCapabilities>;
+
+    /// Point `request` at the catalogue id its model refers to, under `[models]`.
+    ///
+    /// Unknown models are rejected with `404 model_not_found` so clients do not get a
+    /// cryptic upstream error. Without a catalogue, the model is forwarded as-is. Requests
+    /// for model `""` or `"default"` get the `default_model` of their profile, and
+    /// `[models.display]` names are taken back to their model id. The resolved model must
+    /// be one the request's API key may use.
+    async fn resolve_model(
+        state: Arc<AppState>,
+        request: &mut CopilotChatRequest,
+    ) -> Result<(), AppError>;
+
+    /// Fill in the model's default `reasoning_effort` from `[models.reasoning_effort]`, then
+    /// strip or clamp what the model cannot take. Unknown models are left alone.
+    async fn adapt_to_model(
+        state: Arc<AppState>,
+        request: &mut CopilotChatRequest,
+    ) -> ModelAdjustments;
+}
+
+impl ModelAdaptation for Server {
+    async fn model_catalogue(state: Arc<AppState>) -> Models {
+        if let Some(catalogue) = state.model_catalogue.get() {
+            return catalogue;
+        }
+
+        match Self::copilot_models(state.clone()).await {
+            Ok(models) => {
+                let catalogue = models
+                    .models
+                    .iter()
+                    .map(|model| (model.id.clone(), ModelCapabilities::from(model)))
+                    .collect();
+                state.model_catalogue.store(catalogue);
+            }
+            Err(e) => {
+                warn!("Could not fetch the model catalogue: {:?}", e);
+                state.model_catalogue.store_failure();
+            }
+        }
+        state.model_catalogue.get().unwrap_or_default()
+    }
+
+    async fn model_capabilities(state: Arc<AppState>, model: &str) -> Option<ModelCapabilities> {
+        Self::model_catalogue(state).await.get(model).copied()
+    }
+
+    async fn resolve_model(
+        state: Arc<AppState>,
+        request: &mut CopilotChatRequest,
+    ) -> Result<(), AppError> {
+        let config = state.config();
+        if (request.model.is_empty() || request.model == DEFAULT_MODEL)
+            && let Some(profile) = state.profile.as_deref()
+            && let Some(default_model) = profile.default_model()
+        {
+            info!("Using model {} of profile {}", default_model, profile.name);
+            request.model = default_model.to_string();
+        }
+
+        let models = &config.models;
+        if let Some(id) = models.id_for_display_name(&request.model) {
+            info!("Resolved display name {} to {}", request.model, id);
+            request.model = id.to_string();
+        }
+
+        if !config.echo.serves(&request.model) {
+            let catalogue = Self::model_catalogue(state.clone()).await;
+            if catalogue.is_empty() {
+                debug!(
+                    "No model catalogue available, forwarding model {} unchecked",
+                    request.model
+                );
+            } else {
+                match resolve_model_id(&request.model, &models.aliases, &catalogue) {
+                    Some(id) if id != request.model => {
+                        info!("Resolved model {} to {}", request.model, id);
+                        request.model = id;
+                    }
+                    None if models.validate => {
+                        error!("Rejecting request for unknown model {}", request.model);
+                        return Err(AppError::ModelNotFound(request.model.clone()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Checked last, against the model Copilot will be asked for
+        keys::admit_model(&request.model)
+    }
+
+    async fn adapt_to_model(
+        state: Arc<AppState>,
+        request: &mut CopilotChatRequest,
+    ) -> ModelAdjustments {
+        let config = state.config();
+        if request.reasoning_effort.is_none() {
+            request.reasoning_effort = config.models.reasoning_effort.get(&request.model).copied();
+        }
+
+        let normalized = request.normalize_system_messages(&config.models.messages(&request.model));
+        if normalized.system_messages_merged > 0 {
+            debug!(
+                "Merged {} system messages for model {}",
+                normalized.system_messages_merged, request.model
+            );
+        }
+
+        let Some(capabilities) = Self::model_capabilities(state, &request.model).await else {
+            debug!(
+                "No capabilities known for model {}, forwarding request unchanged",
+                request.model
+            );
+            return normalized;
+        };
+
+        let adjustments = ModelAdjustments {
+            developer_messages: normalized.developer_messages,
+            system_messages_merged: normalized.system_messages_merged,
+            ..request.adapt_to(&capabilities)
+        };
+        if adjustments.tools_dropped {
+            warn!(
+                "Model {} does not support tool calls, dropped the request's tools",
+                request.model
+            );
+        }
+        if adjustments.images_dropped > 0 {
+            warn!(
+                "Model {} does not accept images, replaced {} image parts with a placeholder",
+                request.model, adjustments.images_dropped
+            );
+        }
+        if let Some(max_tokens) = adjustments.max_tokens_clamped {
+            warn!(
+                "Clamped max_tokens {} to the output limit {} of model {}",
+                max_tokens, capabilities.output, request.model
+            );
+        }
+        if adjustments.reasoning_effort_dropped {
+            warn!(
+                "Model {} does not support reasoning_effort, dropped it",
+                request.model
+            );
+        }
+        adjustments
+    }
+}
+
+/// Report request adjustments on a response
+pub(crate) fn with_adjustments_header(
+    mut response: Response,
+    adjustments: &ModelAdjustments,
+) -> Response {
+    if let Some(value) = adjustments.header_value()
+        && let Ok(value) = HeaderValue::from_str(&value)
+    {
+        response.headers_mut().insert(ADJUSTMENTS_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_catalogue_cache() {
+        let catalogue = ModelCatalogue::default();
+        assert!(catalogue.get().is_none());
+
+        let gpt_4o = ModelCapabilities {
+            context: 128_000,
+            output: 16_384,
+            tool_call: true,
+            vision: true,
+            logprobs: true,
+            reasoning_effort: false,
+        };
+        catalogue.store(HashMap::from([("gpt-4o".to_string(), gpt_4o)]));
+        assert_eq!(catalogue.get().unwrap().get("gpt-4o"), Some(&gpt_4o));
+
+        // A failed fetch is remembered as an empty catalogue
+        catalogue.store_failure();
+        assert!(catalogue.get().unwrap().is_empty());
+    }
+}
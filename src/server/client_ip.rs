@@ -0,0 +1,170 @@
+use crate::server::{AppError, AppState};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tracing::log::debug;
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+const REAL_IP: &str = "x-real-ip";
+
+tokio::task_local! {
+    /// Address of the client being served, past any `[server] trusted_proxies`
+    static CLIENT_IP: Option<IpAddr>;
+}
+
+/// The client's address: the peer's, unless the peer is one of `trusted` proxies, in which
+/// case the nearest untrusted hop of `X-Forwarded-For`, or else `X-Real-IP`
+fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    let Some(peer) = peer.filter(is_trusted) else {
+        return peer;
+    };
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all(FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+    if let Some(first) = forwarded.first() {
+        // Hops are appended by each proxy, so the client is the last one not trusted
+        return Some(
+            forwarded
+                .iter()
+                .rev()
+                .find(|hop| !is_trusted(hop))
+                .copied()
+                .unwrap_or(*first),
+        );
+    }
+
+    headers
+        .get(REAL_IP)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .or(Some(peer))
+}
+
+/// Middleware serving the request with its client's address, as [`current`] reports it
+pub(crate) async fn resolve_client_ip(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = resolve(peer, request.headers(), &state.trusted_proxies);
+
+    CLIENT_IP.scope(client, next.run(request)).await
+}
+
+/// Middleware refusing clients outside `[server] allowed_clients` with `403 Forbidden`
+pub(crate) async fn enforce_allowed_clients(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_allowed(current(), &state.allowed_clients) {
+        return next.run(request).await;
+    }
+
+    debug!(
+        "Refusing {} {} from {:?}, outside [server] allowed_clients",
+        request.method(),
+        request.uri().path(),
+        current()
+    );
+    AppError::Forbidden("Your address is not allowed to use this server".to_string())
+        .into_response()
+}
+
+/// Whether `client` may be served; unknown addresses are not, once there is an allowlist
+fn is_allowed(client: Option<IpAddr>, allowed: &[IpNet]) -> bool {
+    allowed.is_empty() || client.is_some_and(|ip| allowed.iter().any(|net| net.contains(&ip)))
+}
+
+/// Address of the client being served, for limits, logs and metrics
+pub(crate) fn current() -> Option<IpAddr> {
+    CLIENT_IP.try_with(|ip| *ip).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::users::admit_user;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR, "6.6.6.6, 1.2.3.4, 10.0.0.2".parse().unwrap());
+
+        // Forwarded through trusted proxies: the last untrusted hop
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &headers, &trusted),
+            Some(ip("1.2.3.4"))
+        );
+        // From anyone else, the header is ignored
+        assert_eq!(
+            resolve(Some(ip("5.5.5.5")), &headers, &trusted),
+            Some(ip("5.5.5.5"))
+        );
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &headers, &[]),
+            Some(ip("10.0.0.1"))
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(REAL_IP, "1.2.3.4".parse().unwrap());
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &headers, &trusted),
+            Some(ip("1.2.3.4"))
+        );
+        assert_eq!(
+            resolve(Some(ip("10.0.0.1")), &HeaderMap::new(), &trusted),
+            Some(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let allowed: Vec<IpNet> = vec!["192.168.1.0/24".parse().unwrap()];
+
+        assert!(is_allowed(Some(ip("192.168.1.20")), &allowed));
+        assert!(!is_allowed(Some(ip("8.8.8.8")), &allowed));
+        assert!(!is_allowed(None, &allowed));
+        assert!(is_allowed(Some(ip("8.8.8.8")), &[]));
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_user_limited_by_ip() {
+        let mut config = crate::config::Config::default();
+        config.users.requests_per_minute = Some(1);
+        config.users.limit_by_ip = true;
+        let state = AppState::new(&config, None);
+
+        CLIENT_IP
+            .scope(Some(ip("1.2.3.4")), async {
+                assert!(admit_user(&state, None).is_ok());
+                assert!(admit_user(&state, None).is_err());
+                assert!(admit_user(&state, Some("alice")).is_ok());
+            })
+            .await;
+        CLIENT_IP
+            .scope(Some(ip("5.6.7.8")), async {
+                assert!(admit_user(&state, None).is_ok());
+            })
+            .await;
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::{CopilotClientConfig, CopilotConfig, CopilotHeadersConfig};
+use crate::server::AppState;
+use crate::server::keys::presented_key_id;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use reqwest::RequestBuilder;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::log::debug;
+
+tokio::task_local! {
+    /// Identification headers of the client being served, under `[[copilot.clients]]`
+    static CLIENT_HEADERS: CopilotHeadersConfig;
+}
+
+/// The first of `[[copilot.clients]]` matching the request's user agent or API key
+fn matching_client<'a>(
+    clients: &'a [CopilotClientConfig],
+    headers: &HeaderMap,
+) -> Option<&'a CopilotClientConfig> {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let key_id = presented_key_id(headers);
+
+    clients.iter().find(|client| {
+        client
+            .user_agent
+            .as_deref()
+            .is_some_and(|wanted| user_agent.is_some_and(|agent| agent.contains(wanted)))
+            || client
+                .key_id
+                .as_deref()
+                .is_some_and(|wanted| key_id.as_deref() == Some(wanted))
+    })
+}
+
+/// Middleware forwarding the request to Copilot with the headers of its client, when one of
+/// `[[copilot.clients]]` matches
+pub(crate) async fn select_client_headers(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let copilot = &state.config().copilot;
+    let Some(client) = matching_client(&copilot.clients, request.headers()) else {
+        return next.run(request).await;
+    };
+
+    let headers = client.apply(&copilot.headers);
+    debug!(
+        "Forwarding with Copilot-Integration-Id {} for this client",
+        headers.integration_id
+    );
+    CLIENT_HEADERS.scope(headers, next.run(request)).await
+}
+
+/// Headers identifying the proxy to Copilot: those of the client being served, or
+/// `[copilot.headers]`
+pub(crate) fn copilot_headers(config: &CopilotConfig) -> Cow<'_, CopilotHeadersConfig> {
+    CLIENT_HEADERS
+        .try_with(|headers| Cow::Owned(headers.clone()))
+        .unwrap_or(Cow::Borrowed(&config.headers))
+}
+
+/// `builder` with the Copilot `token` and the headers identifying the proxy to Copilot,
+/// per [`copilot_headers`]. Every request to Copilot's API is made with these.
+pub(crate) fn with_copilot_headers(
+    builder: RequestBuilder,
+    config: &CopilotConfig,
+    token: &str,
+) -> RequestBuilder {
+    let headers = copilot_headers(config);
+    let mut builder = builder
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Copilot-Integration-Id", &headers.integration_id)
+        .header("Editor-Version", &headers.editor_version)
+        .header("Editor-Plugin-Version", &headers.editor_plugin_version)
+        .header("User-Agent", &headers.user_agent);
+    for (name, value) in &headers.extra {
+        builder = builder.header(name, value);
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::keys::fingerprint;
+
+    fn clients() -> Vec<CopilotClientConfig> {
+        vec![
+            CopilotClientConfig {
+                user_agent: Some("Zed/".to_string()),
+                integration_id: Some("zed".to_string()),
+                ..Default::default()
+            },
+            CopilotClientConfig {
+                key_id: Some(fingerprint("secret")),
+                integration_id: Some("vscode-chat".to_string()),
+                editor_version: Some("vscode/1.99.0".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_matching_client() {
+        let clients = clients();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, "Zed/0.180.0".parse().unwrap());
+        let client = matching_client(&clients, &headers).unwrap();
+        assert_eq!(client.integration_id.as_deref(), Some("zed"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let client = matching_client(&clients, &headers).unwrap();
+        assert_eq!(client.integration_id.as_deref(), Some("vscode-chat"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, "curl/8.5.0".parse().unwrap());
+        assert!(matching_client(&clients, &headers).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_copilot_headers_in_scope() {
+        let config = Config::default();
+        let default_id = config.copilot.headers.integration_id.clone();
+        assert_eq!(copilot_headers(&config.copilot).integration_id, default_id);
+
+        let headers = clients()[1].apply(&config.copilot.headers);
+        CLIENT_HEADERS
+            .scope(headers, async {
+                let headers = copilot_headers(&config.copilot);
+                assert_eq!(headers.integration_id, "vscode-chat");
+                assert_eq!(headers.editor_version, "vscode/1.99.0");
+                assert_eq!(
+                    headers.editor_plugin_version,
+                    config.copilot.headers.editor_plugin_version
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_with_copilot_headers_in_scope() {
+        let config = Config::default();
+        let headers = clients()[0].apply(&config.copilot.headers);
+        let request = CLIENT_HEADERS
+            .scope(headers, async {
+                let builder = reqwest::Client::new().post("https://api.githubcopilot.com/");
+                with_copilot_headers(builder, &config.copilot, "token")
+                    .build()
+                    .unwrap()
+            })
+            .await;
+
+        let headers = request.headers();
+        assert_eq!(headers["authorization"], "Bearer token");
+        assert_eq!(headers["copilot-integration-id"], "zed");
+        assert_eq!(
+            headers["editor-version"],
+            config.copilot.headers.editor_version.as_str()
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use crate::auth::CopilotTokenResponse;
+use crate::copilot::CopilotChatRequest;
+use crate::copilot::content_filter::{is_filtered, strip_annotations};
+use crate::server::copilot::CopilotIntegration;
+use crate::server::empty_choices::{buffered_any, rebuilt};
+use crate::server::{AppError, AppState, Server};
+use reqwest::Response;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::log::{info, warn};
+
+/// Copilot's content filtering of non-streaming replies, under `[copilot.content_filter]`
+pub(crate) trait ContentFilter: CopilotIntegration {
+    /// Return `response`, or with `retry_sanitized`, the reply to `request` sent once more
+    /// sanitized when Copilot filtered `response` and not the retry. Content filter
+    /// annotations are removed from the reply unless `annotations` is set.
+    async fn handle_content_filter(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        response: Response,
+    ) -> Result<Response, AppError>;
+}
+
+impl ContentFilter for Server {
+    async fn handle_content_filter(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        response: Response,
+    ) -> Result<Response, AppError> {
+        let config = &state.config().copilot.content_filter;
+        if config.annotations && !config.retry_sanitized {
+            return Ok(response);
+        }
+
+        let (mut response, mut body) = buffered_any(response).await?;
+        if config.retry_sanitized && is_filtered(&body) {
+            warn!(
+                "Copilot's content filter stopped a reply from {}, retrying with a sanitized prompt",
+                request.model
+            );
+            let retry = Self::forward_prompt(
+                state.clone(),
+                token,
+                url,
+                &request.sanitized(),
+                session_id,
+                false,
+            )
+            .await?;
+            let (retried, retried_body) = buffered_any(retry).await?;
+            if retried.status().is_success() && !is_filtered(&retried_body) {
+                info!("Sanitized retry for {} was answered", request.model);
+                (response, body) = (retried, retried_body);
+            } else {
+                warn!(
+                    "Sanitized retry for {} was filtered too, returning the first reply",
+                    request.model
+                );
+            }
+        }
+
+        if !config.annotations
+            && response.status().is_success()
+            && let Ok(mut reply) = serde_json::from_slice::<Value>(&body)
+            && strip_annotations(&mut reply)
+        {
+            let mut headers = response.headers().clone();
+            headers.remove(reqwest::header::CONTENT_LENGTH);
+            return Ok(rebuilt(
+                response.status(),
+                headers,
+                reply.to_string().into(),
+            ));
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::openai::completion::models::OpenAIChatRequest;
+    use crate::server::test_token;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn state(config: Config) -> Arc<AppState> {
+        Arc::new(AppState::new(&config, None))
+    }
+
+    fn request() -> CopilotChatRequest {
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Tell me a story"}]
+        }))
+        .unwrap();
+        request.into()
+    }
+
+    fn filtered_reply() -> Response {
+        let reply = json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "prompt_filter_results": [{"prompt_index": 0}],
+            "choices": [{"index": 0, "finish_reason": "content_filter"}]
+        });
+        Response::from(http::Response::new(reply.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_sanitized_retry_and_annotations() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("Answer within content policy"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-2",
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Once upon a time"},
+                    "finish_reason": "stop",
+                    "content_filter_results": {"hate": {"filtered": false}}
+                }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = mock_server.uri();
+        config.copilot.content_filter.retry_sanitized = true;
+        config.copilot.content_filter.annotations = false;
+        let state = state(config);
+        let url = format!("{}/chat/completions", mock_server.uri());
+
+        let response = Server::handle_content_filter(
+            state,
+            test_token(),
+            url,
+            &request(),
+            "session",
+            filtered_reply(),
+        )
+        .await
+        .unwrap();
+        let reply: Value = response.json().await.unwrap();
+        assert_eq!(
+            reply["choices"][0]["message"]["content"],
+            "Once upon a time"
+        );
+        assert!(reply["choices"][0].get("content_filter_results").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_annotations_pass_through_by_default() {
+        let state = state(Config::default());
+        let response = Server::handle_content_filter(
+            state,
+            test_token(),
+            "http://127.0.0.1:1/chat/completions".to_string(),
+            &request(),
+            "session",
+            filtered_reply(),
+        )
+        .await
+        .unwrap();
+        let reply: Value = response.json().await.unwrap();
+        assert_eq!(reply["prompt_filter_results"][0]["prompt_index"], 0);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::ContextStrategy;
+use crate::copilot::context::{
+    estimate_request_tokens, summary_message, summary_request, truncate_to_budget,
+};
+use crate::copilot::{CopilotChatRequest, CopilotMessage};
+use crate::server::capabilities::ModelAdaptation;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::{AppState, Server};
+use std::sync::Arc;
+use tracing::log::{debug, info, warn};
+
+/// Keeps prompts within the target model's context window, under `[copilot.context]`
+pub(crate) trait ContextWindow: CopilotIntegration + ModelAdaptation {
+    /// Context size of `model`, or `None` when it is unknown
+    async fn context_limit(state: Arc<AppState>, model: &str) -> Option<u64>;
+
+    /// Drop (or summarise) the oldest messages of `request` that do not fit.
+    ///
+    /// Never fails the request: when the limit is unknown or summarising fails, the
+    /// prompt is forwarded as-is or merely truncated and Copilot has the final word.
+    async fn fit_context_window(
+        state: Arc<AppState>,
+        request: &mut CopilotChatRequest,
+        session_id: &str,
+    );
+
+    async fn summarize(
+        state: Arc<AppState>,
+        model: &str,
+        dropped: &[CopilotMessage],
+        session_id: &str,
+    ) -> Option<String>;
+}
+
+impl ContextWindow for Server {
+    async fn context_limit(state: Arc<AppState>, model: &str) -> Option<u64> {
+        Self::model_capabilities(state, model)
+            .await
+            .map(|capabilities| capabilities.context)
+            .filter(|context| *context > 0)
+    }
+
+    async fn fit_context_window(
+        state: Arc<AppState>,
+        request: &mut CopilotChatRequest,
+        session_id: &str,
+    ) {
+        let context = &state.config().copilot.context;
+        if !context.enabled {
+            return;
+        }
+
+        let Some(limit) = Self::context_limit(state.clone(), &request.model).await else {
+            debug!(
+                "No context limit known for model {}, forwarding prompt unchanged",
+                request.model
+            );
+            return;
+        };
+
+        let reserve = request.max_tokens.map_or(context.reserve_tokens, u64::from);
+        let budget = limit.saturating_sub(reserve);
+        let before = estimate_request_tokens(request);
+
+        let Some((insert_at, dropped)) = truncate_to_budget(request, budget) else {
+            return;
+        };
+
+        info!(
+            "Prompt for model {} estimated at {} tokens exceeds its budget of {}; dropped {} oldest messages",
+            request.model,
+            before,
+            budget,
+            dropped.len()
+        );
+
+        if context.strategy == ContextStrategy::Summarize
+            && let Some(summary) =
+                Self::summarize(state.clone(), &request.model, &dropped, session_id).await
+        {
+            request
+                .messages
+                .insert(insert_at, summary_message(&summary));
+        }
+
+        let after = estimate_request_tokens(request);
+        if after > budget {
+            warn!(
+                "Prompt for model {} is still estimated at {} tokens after truncation (budget {})",
+                request.model, after, budget
+            );
+        }
+    }
+
+    async fn summarize(
+        state: Arc<AppState>,
+        model: &str,
+        dropped: &[CopilotMessage],
+        session_id: &str,
+    ) -> Option<String> {
+        let response = async {
+            let token = Self::get_token(state.clone()).await?;
+            Self::complete_once(state, token, &summary_request(model, dropped), session_id).await
+        }
+        .await;
+
+        match response {
+            Ok(response) => response
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|choice| choice.message.content)
+                .map(|content| content.to_text())
+                .filter(|summary| !summary.trim().is_empty()),
+            Err(e) => {
+                warn!(
+                    "Could not summarise dropped messages, truncating instead: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::copilot::models::ModelCapabilities;
+    use std::collections::HashMap;
+
+    fn state(enabled: bool, limits: HashMap<String, u64>) -> Arc<AppState> {
+        let mut config = Config::default();
+        config.copilot.context.enabled = enabled;
+        config.copilot.context.reserve_tokens = 100;
+
+        let state = AppState::new(&config, None);
+        state.model_catalogue.store(
+            limits
+                .into_iter()
+                .map(|(model, context)| {
+                    let capabilities = ModelCapabilities {
+                        context,
+                        output: 0,
+                        tool_call: true,
+                        vision: true,
+                        logprobs: true,
+                        reasoning_effort: false,
+                    };
+                    (model, capabilities)
+                })
+                .collect(),
+        );
+
+        Arc::new(state)
+    }
+
+    fn request() -> CopilotChatRequest {
+        let message = |role: &str| CopilotMessage {
+            role: role.to_string(),
+            content: Some("x".repeat(400).into()),
+            padding: None,
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            reasoning_text: None,
+        };
+
+        CopilotChatRequest {
+            messages: vec![
+                message("system"),
+                message("user"),
+                message("assistant"),
+                message("user"),
+            ],
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fit_context_window_truncates() {
+        // Four messages of ~104 tokens against a 420-token window with 100 reserved:
+        // dropping the oldest user message is enough
+        let state = state(true, HashMap::from([("gpt-4o".to_string(), 420)]));
+        let mut request = request();
+
+        Server::fit_context_window(state, &mut request, "session").await;
+
+        let roles: Vec<_> = request.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "assistant", "user"]);
+    }
+
+    #[tokio::test]
+    async fn test_fit_context_window_disabled_or_unknown_model() {
+        let mut request = request();
+        let limits = HashMap::from([("gpt-4o".to_string(), 400)]);
+
+        Server::fit_context_window(state(false, limits), &mut request, "session").await;
+        assert_eq!(request.messages.len(), 4);
+
+        let state = state(true, HashMap::from([("other".to_string(), 400)]));
+        Server::fit_context_window(state, &mut request, "session").await;
+        assert_eq!(request.messages.len(), 4);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::copilot::conversation::{ConversationRequest, ConversationResponse};
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
+use crate::server::admission::{ChatAdmission, PreparedChat, admit};
+use crate::server::capabilities::with_adjustments_header;
+use crate::server::copilot::{CopilotIntegration, upstream_headers, with_upstream_headers};
+use crate::server::openai::chat_completion::CoPilotChatCompletions;
+use crate::server::racing::ModelRacing;
+use crate::server::server_tools::auto_tools;
+use crate::server::session::with_session_header;
+use crate::server::stream_stats::StreamStats;
+use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, extract::State};
+use std::sync::Arc;
+use tracing::log::{error, info};
+
+/// Copilot Chat editor protocol: conversation turns with file references.
+///
+/// Turns are translated into a regular chat request. Streaming replies use the
+/// chat completions SSE format; non-streaming ones a [`ConversationResponse`].
+pub(crate) trait CopilotConversationEndpoint: CoPilotChatCompletions {
+    async fn copilot_conversation(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        request: Json<ConversationRequest>,
+    ) -> Result<Response, AppError>;
+
+    async fn copilot_conversation_no_sse(
+        conversation_id: String,
+        response: reqwest::Response,
+    ) -> Result<Response, AppError>;
+}
+
+impl CopilotConversationEndpoint for Server {
+    async fn copilot_conversation(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        request: Json<ConversationRequest>,
+    ) -> Result<Response, AppError> {
+        let request = request.0;
+        info!(
+            "Received Copilot conversation turn for model: {} ({} turns, {} references)",
+            request.model,
+            request.turns.len(),
+            request.references.len()
+        );
+
+        let is_stream = request.stream;
+
+        let mut admission = admit(&state, &headers, None)?;
+        // The editor's conversation id keeps every turn in one Copilot session
+        if let Some(conversation_id) = &request.conversation_id {
+            admission.session_id = conversation_id.clone();
+        }
+        let session_id = admission.session_id.clone();
+
+        let mut copilot_request = CopilotChatRequest::try_from(request).map_err(|e| {
+            error!("Rejecting invalid conversation request: {}", e);
+            AppError::BadRequest(e)
+        })?;
+        let PreparedChat {
+            state,
+            token,
+            adjustments,
+        } = Self::prepare_chat(state, &headers, &admission, &mut copilot_request).await?;
+        let config = state.config();
+
+        // Forward request to Copilot API
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+
+        let stats = StreamStats::new(
+            state.metrics.clone(),
+            "copilot_conversation",
+            &copilot_request.model,
+        );
+        let reasoning = config.copilot.reasoning.output;
+        let auto_tools = auto_tools(&config.mcp, &headers);
+        let response = Self::forward_raced(
+            state.clone(),
+            token,
+            copilot_url,
+            &copilot_request,
+            &session_id,
+            is_stream,
+            auto_tools,
+        )
+        .await?;
+        stats.served_by(&response);
+
+        let status = response.status();
+        if !status.is_success() {
+            return Self::handle_errors(response).await;
+        }
+        let upstream = upstream_headers(&config.copilot.response_headers, response.headers());
+
+        let response = if is_stream {
+            let annotations = config.copilot.content_filter.annotations;
+            Self::chat_completions_sse(response, stats, reasoning, annotations).await
+        } else {
+            Self::copilot_conversation_no_sse(session_id.clone(), response).await
+        };
+
+        response.map(|response| {
+            let response = with_upstream_headers(response, upstream);
+            with_adjustments_header(with_session_header(response, &session_id), &adjustments)
+        })
+    }
+
+    async fn copilot_conversation_no_sse(
+        conversation_id: String,
+        response: reqwest::Response,
+    ) -> Result<Response, AppError> {
+        let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse Copilot response: {}", e);
+            AppError::upstream("Failed to parse Copilot response", e)
+        })?;
+
+        let reply = copilot_response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .map(|content| content.to_text())
+            .unwrap_or_default();
+
+        info!("Successfully processed Copilot conversation turn");
+        Ok(Json(ConversationResponse {
+            conversation_id,
+            turn_id: copilot_response.id,
+            model: copilot_response.model,
+            reply,
+        })
+        .into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_conversation_no_sse() {
+        let copilot_response = serde_json::json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "It starts the server."},
+                "finish_reason": "stop"
+            }]
+        });
+        let response = reqwest::Response::from(
+            http::Response::builder()
+                .status(200)
+                .body(copilot_response.to_string())
+                .unwrap(),
+        );
+
+        let response = <Server as CopilotConversationEndpoint>::copilot_conversation_no_sse(
+            "c-1".to_string(),
+            response,
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let reply: ConversationResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply.conversation_id, "c-1");
+        assert_eq!(reply.turn_id, "chatcmpl-1");
+        assert_eq!(reply.model, "gpt-4o");
+        assert_eq!(reply.reply, "It starts the server.");
+    }
+}
@@ -1,22 +1,48 @@
 use crate::auth::CopilotTokenResponse;
+use crate::copilot::client::{VISION_REQUEST_HEADER, has_image_parts};
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
+use crate::server::clients::with_copilot_headers;
+use crate::server::fallback::SERVED_MODEL_HEADER;
+use crate::server::payload_dump::PayloadDump;
+use crate::server::premium::PremiumAccounting;
+use crate::server::session::COPILOT_INTERACTION_ID_HEADER;
 use crate::server::{AppError, AppState, Server};
+use crate::server::{dry_run, echo};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
 use reqwest::{IntoUrl, Response};
 use serde::Serialize;
+use serde_json::Value;
 use std::sync::Arc;
-use tracing::log::error;
+use tracing::log::{error, warn};
 
 pub(crate) trait CopilotIntegration {
+    /// Send a prompt to Copilot. When it rejects `token` with 401 Unauthorized, the token is
+    /// replaced and the prompt sent once more.
+    ///
+    /// Prompts naming a model are charged to `[premium]` before they are sent, whether the
+    /// client or the proxy itself asked for them, and refunded unless Copilot answers.
     async fn forward_prompt<U, T>(
         state: Arc<AppState>,
         token: CopilotTokenResponse,
         url: U,
         json: &T,
+        session_id: &str,
+        stream: bool,
     ) -> Result<Response, AppError>
     where
-        U: IntoUrl,
+        U: IntoUrl + Clone,
         T: Serialize + Sized;
 
     async fn handle_errors(response: Response) -> Result<axum::response::Response, AppError>;
+
+    /// Send `request` to the chat completions endpoint without streaming and parse the
+    /// reply, for calls the proxy makes on its own account. Error statuses are errors.
+    async fn complete_once(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        session_id: &str,
+    ) -> Result<CopilotChatResponse, AppError>;
 }
 
 impl CopilotIntegration for Server {
@@ -25,39 +51,484 @@ impl CopilotIntegration for Server {
         token: CopilotTokenResponse,
         url: U,
         json: &T,
+        session_id: &str,
+        stream: bool,
     ) -> Result<Response, AppError>
     where
-        U: IntoUrl,
+        U: IntoUrl + Clone,
         T: Serialize + Sized,
     {
-        state
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", token.token))
-            .header("Copilot-Integration-Id", "vscode-chat")
-            .header("Content-Type", "application/json")
-            .json(&json)
-            .send()
-            .await
-            .map_err(|e| {
-                error!("Failed to send request to Copilot API: {}", e);
-                AppError::InternalServerError(format!(
-                    "Failed to communicate with Copilot API: {}",
-                    e
-                ))
-            })
+        let body = serde_json::to_value(json).map_err(|e| {
+            error!("Failed to serialize request to Copilot API: {}", e);
+            AppError::InternalServerError(format!("Failed to serialize request: {}", e))
+        })?;
+        let model = body.get("model").and_then(Value::as_str);
+        let user = body.get("user").and_then(Value::as_str);
+        if let Some(model) = model {
+            Self::charge_premium(&state, model, user, 1)?;
+        }
+
+        let response = async {
+            let response =
+                send_prompt(&state, &token, url.clone(), &body, session_id, stream).await?;
+            if response.status() != StatusCode::UNAUTHORIZED {
+                return Ok(response);
+            }
+
+            warn!("Copilot API rejected the token, retrying with a new one");
+            let token = Self::replace_token(state.clone(), &token).await?;
+            send_prompt(&state, &token, url, &body, session_id, stream).await
+        }
+        .await;
+
+        let answered = matches!(&response, Ok(response) if response.status().is_success());
+        if let Some(model) = model
+            && !answered
+        {
+            Self::refund_premium(&state, model, user, 1);
+        }
+        response
     }
 
     async fn handle_errors(response: Response) -> Result<axum::response::Response, AppError> {
-        let status = response.status();
-        let error_text = response
-            .text()
+        Err(upstream_error(response).await)
+    }
+
+    async fn complete_once(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        session_id: &str,
+    ) -> Result<CopilotChatResponse, AppError> {
+        let copilot_url = format!("{}/chat/completions", state.config().copilot.api_base_url);
+        let response =
+            Self::forward_prompt(state, token, copilot_url, request, session_id, false).await?;
+        if !response.status().is_success() {
+            return Err(upstream_error(response).await);
+        }
+
+        response
+            .json()
             .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        error!("Copilot API returned error: {} - {}", status, error_text);
-        Err(AppError::InternalServerError(format!(
-            "Copilot API error: {} - {}",
-            status, error_text
-        )))
+            .map_err(|e| AppError::upstream("Failed to parse Copilot response", e))
+    }
+}
+
+/// The error answering a Copilot error `response`
+async fn upstream_error(response: Response) -> AppError {
+    let status = response.status();
+    let error_text = response
+        .text()
+        .await
+        .unwrap_or_else(|_| "Unknown error".to_string());
+    error!("Copilot API returned error: {} - {}", status, error_text);
+    AppError::from_upstream_status(status, &error_text)
+}
+
+/// One attempt at [`CopilotIntegration::forward_prompt`]
+async fn send_prompt<U>(
+    state: &AppState,
+    token: &CopilotTokenResponse,
+    url: U,
+    body: &Value,
+    session_id: &str,
+    stream: bool,
+) -> Result<Response, AppError>
+where
+    U: IntoUrl,
+{
+    let config = state.config();
+    let timeouts = &config.copilot.timeouts;
+
+    let mut request = with_copilot_headers(state.client.post(url), &config.copilot, &token.token)
+        .header(COPILOT_INTERACTION_ID_HEADER, session_id)
+        .header("Content-Type", "application/json")
+        .timeout(timeouts.total(stream));
+
+    if has_image_parts(body) {
+        request = request.header(VISION_REQUEST_HEADER, "true");
+    }
+
+    let request = request.json(body).build().map_err(|e| {
+        error!("Failed to build request to Copilot API: {}", e);
+        AppError::upstream("Failed to build request to Copilot API", e)
+    })?;
+    dry_run::intercept(&request, body).await;
+    if let Some(response) = echo::respond(&config.echo, body, stream) {
+        return Ok(response);
+    }
+    let dump = PayloadDump::request(&config.debug, &request);
+
+    if let Some(pacer) = &state.pacer {
+        pacer.pace().await?;
+    }
+
+    // `execute` resolves once response headers arrive, so this bounds the time to first byte
+    let first_byte = timeouts.first_byte(stream);
+    let response = tokio::time::timeout(first_byte, state.client.execute(request))
+        .await
+        .map_err(|_| {
+            error!(
+                "Copilot API did not respond within {}s",
+                first_byte.as_secs()
+            );
+            AppError::GatewayTimeout(format!(
+                "Copilot API did not respond within {}s",
+                first_byte.as_secs()
+            ))
+        })?
+        .map_err(|e| {
+            error!("Failed to send request to Copilot API: {}", e);
+            AppError::upstream("Failed to communicate with Copilot API", e)
+        })?;
+
+    Ok(match dump {
+        Some(dump) => dump.response(response),
+        None => response,
+    })
+}
+
+/// The headers of a Copilot reply listed in `[copilot] response_headers`, and the model that
+/// answered under `[copilot.fallbacks]`
+pub(crate) fn upstream_headers(allowlist: &[String], headers: &HeaderMap) -> HeaderMap {
+    let mut kept = HeaderMap::new();
+    if let Some(model) = headers.get(SERVED_MODEL_HEADER) {
+        kept.insert(SERVED_MODEL_HEADER, model.clone());
+    }
+    for name in allowlist {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        for value in headers.get_all(&name) {
+            kept.append(name.clone(), value.clone());
+        }
+    }
+    kept
+}
+
+/// Copy headers kept from the Copilot reply onto the client's response, leaving those the
+/// proxy set itself
+pub(crate) fn with_upstream_headers(
+    mut response: axum::response::Response,
+    upstream: HeaderMap,
+) -> axum::response::Response {
+    let headers = response.headers_mut();
+    let mut current = None;
+    for (name, value) in upstream {
+        if let Some(name) = name {
+            current = (!headers.contains_key(&name)).then_some(name);
+        }
+        if let Some(name) = &current {
+            headers.append(name.clone(), value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::config::ProfileConfig;
+    use crate::server::profiles::Profile;
+    use crate::server::test_token;
+    use axum::response::IntoResponse;
+    use serde_json::json;
+    use std::time::Duration;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn state_with_timeouts(first_byte_secs: u64, total_secs: u64) -> Arc<AppState> {
+        let mut config = Config::default();
+        config.copilot.timeouts.first_byte_secs = first_byte_secs;
+        config.copilot.timeouts.total_secs = total_secs;
+        Arc::new(state(config))
+    }
+
+    fn state(config: Config) -> AppState {
+        AppState::new(&config, None)
+    }
+
+    #[tokio::test]
+    async fn test_forward_prompt_first_byte_timeout_is_504() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(3)))
+            .mount(&mock_server)
+            .await;
+
+        let result = Server::forward_prompt(
+            state_with_timeouts(1, 300),
+            test_token(),
+            mock_server.uri(),
+            &serde_json::json!({}),
+            "session",
+            false,
+        )
+        .await;
+
+        let err = result.unwrap_err();
+        assert!(matches!(err, AppError::GatewayTimeout(_)));
+        assert_eq!(
+            err.into_response().status(),
+            axum::http::StatusCode::GATEWAY_TIMEOUT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_complete_once() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Paris"},
+                    "finish_reason": "stop"
+                }]
+            })))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(429).set_body_string("slow down"))
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = mock_server.uri();
+        let state = Arc::new(state(config));
+        let request = CopilotChatRequest {
+            messages: vec![crate::copilot::CopilotMessage::text(
+                "user",
+                "Capital of France?",
+            )],
+            ..serde_json::from_value(json!({"model": "gpt-4o", "messages": []})).unwrap()
+        };
+
+        let response = Server::complete_once(state.clone(), test_token(), &request, "session")
+            .await
+            .unwrap();
+        let reply = response.choices[0].message.content.as_ref().unwrap();
+        assert_eq!(reply.to_text(), "Paris");
+
+        // Error statuses are errors
+        let err = Server::complete_once(state, test_token(), &request, "session")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.into_response().status(),
+            axum::http::StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_prompt_charges_premium() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"choices": []})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.premium.monthly_budget = Some(1.0);
+        let state = Arc::new(state(config));
+        let prompt = json!({"model": "claude-sonnet-4", "user": "alice", "messages": []});
+        let forward = || {
+            Server::forward_prompt(
+                state.clone(),
+                test_token(),
+                mock_server.uri(),
+                &prompt,
+                "session",
+                false,
+            )
+        };
+
+        // Unanswered prompts are refunded
+        let response = forward().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(state.premium.report(None).models.is_empty());
+
+        forward().await.unwrap();
+        let report = state.premium.report(None);
+        assert_eq!(report.models["claude-sonnet-4"].requests, 1);
+        assert_eq!(report.users["alice"].premium_requests, 1.0);
+
+        // Past the budget, prompts are refused before reaching Copilot
+        let err = forward().await.unwrap_err();
+        assert!(matches!(err, AppError::QuotaExceeded(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_forward_prompt_within_timeouts() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .mount(&mock_server)
+            .await;
+
+        let response = Server::forward_prompt(
+            state_with_timeouts(5, 5),
+            test_token(),
+            mock_server.uri(),
+            &serde_json::json!({}),
+            "session",
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_forward_prompt_replaces_rejected_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_path = dir.path().join("token.json");
+        let access_token_path = dir.path().join("access_token.json");
+        std::fs::write(
+            &access_token_path,
+            json!({"access_token": "gho_test", "token_type": "bearer", "scope": ""}).to_string(),
+        )
+        .unwrap();
+        let expires_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 1800;
+        let revoked = CopilotTokenResponse {
+            token: "revoked".to_string(),
+            expires_at,
+            refresh_in: 1500,
+            fetched_at: None,
+            entitlements: Default::default(),
+        };
+        crate::storage::save_token_to_path(&revoked, Some(&token_path)).unwrap();
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "token": "fresh",
+                "expires_at": expires_at,
+                "refresh_in": 1500
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(header("authorization", "Bearer revoked"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(header("authorization", "Bearer fresh"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.github.copilot_token_url = format!("{}/token", mock_server.uri());
+        let mut state = state(config);
+        state.profile = Some(Arc::new(Profile::new(
+            "work",
+            &ProfileConfig {
+                access_token_path: Some(access_token_path.display().to_string()),
+                copilot_token_path: Some(token_path.display().to_string()),
+                ..Default::default()
+            },
+        )));
+
+        let response = Server::forward_prompt(
+            Arc::new(state),
+            revoked,
+            format!("{}/chat/completions", mock_server.uri()),
+            &json!({}),
+            "session",
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert!(response.status().is_success());
+        assert_eq!(
+            crate::storage::load_token_from_path(Some(&token_path))
+                .unwrap()
+                .token,
+            "fresh"
+        );
+        mock_server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn test_forward_prompt_flags_vision_requests() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header(VISION_REQUEST_HEADER, "true"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        for content in [
+            json!([
+                {"type": "text", "text": "What is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}
+            ]),
+            json!("Hello"),
+        ] {
+            Server::forward_prompt(
+                state_with_timeouts(5, 5),
+                test_token(),
+                mock_server.uri(),
+                &json!({"messages": [{"role": "user", "content": content}]}),
+                "session",
+                false,
+            )
+            .await
+            .unwrap();
+        }
+        mock_server.verify().await;
+    }
+
+    #[test]
+    fn test_upstream_headers_pass_through() {
+        let mut upstream = HeaderMap::new();
+        upstream.insert("x-request-id", "abc".parse().unwrap());
+        upstream.insert("x-ratelimit-remaining", "42".parse().unwrap());
+        upstream.insert("content-type", "text/plain".parse().unwrap());
+        upstream.insert("set-cookie", "secret".parse().unwrap());
+        let allowlist =
+            ["X-Request-Id", "x-ratelimit-remaining", "content-type"].map(str::to_string);
+
+        let kept = upstream_headers(&allowlist, &upstream);
+        assert_eq!(kept.len(), 3);
+        assert!(!kept.contains_key("set-cookie"));
+
+        let response =
+            with_upstream_headers(axum::Json(serde_json::json!({})).into_response(), kept);
+        assert_eq!(response.headers()["x-request-id"], "abc");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "42");
+        // The proxy's own header stays
+        assert_eq!(response.headers()["content-type"], "application/json");
     }
 }
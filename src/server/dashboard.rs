@@ -0,0 +1,95 @@
+use crate::server::capabilities::ModelAdaptation;
+use crate::server::metrics::MetricsSnapshot;
+use crate::server::premium::PremiumUsageReport;
+use crate::server::{AppState, Server};
+use crate::storage;
+use axum::Json;
+use axum::extract::State;
+use axum::response::Html;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::log::debug;
+
+/// The dashboard page. It holds no data itself: the admin token is entered in the browser
+/// and used to poll `/admin/stats`.
+const DASHBOARD_HTML: &str = include_str!("dashboard.html");
+
+/// Body of `GET /admin/stats`
+#[derive(Debug, Serialize)]
+pub struct DashboardStats {
+    #[serde(flatten)]
+    pub metrics: MetricsSnapshot,
+    pub premium: PremiumUsageReport,
+    /// `None` when no Copilot token is stored
+    pub copilot_token: Option<TokenExpiry>,
+    pub models: Vec<DashboardModel>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenExpiry {
+    pub expires_at: u64,
+    /// Negative once the token has expired
+    pub expires_in_seconds: i64,
+}
+
+/// A model of the catalogue, as listed on the dashboard
+#[derive(Debug, Serialize)]
+pub struct DashboardModel {
+    pub id: String,
+    pub context: u64,
+    pub output: u64,
+    pub tool_call: bool,
+    pub vision: bool,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait DashboardEndpoints {
+    /// `GET /admin/dashboard`
+    async fn dashboard() -> Html<&'static str>;
+
+    /// `GET /admin/stats`
+    async fn dashboard_stats(state: State<Arc<AppState>>) -> Json<DashboardStats>;
+}
+
+impl DashboardEndpoints for Server {
+    async fn dashboard() -> Html<&'static str> {
+        Html(DASHBOARD_HTML)
+    }
+
+    async fn dashboard_stats(State(state): State<Arc<AppState>>) -> Json<DashboardStats> {
+        let mut models: Vec<_> = Self::model_catalogue(state.clone())
+            .await
+            .iter()
+            .map(|(id, capabilities)| DashboardModel {
+                id: id.clone(),
+                context: capabilities.context,
+                output: capabilities.output,
+                tool_call: capabilities.tool_call,
+                vision: capabilities.vision,
+            })
+            .collect();
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+
+        Json(DashboardStats {
+            metrics: state.metrics.snapshot(),
+            premium: state.premium.report(state.config().premium.monthly_budget),
+            copilot_token: token_expiry(),
+            models,
+        })
+    }
+}
+
+/// Expiry of the stored Copilot token
+fn token_expiry() -> Option<TokenExpiry> {
+    let token = storage::load_token()
+        .map_err(|e| debug!("No Copilot token for the dashboard: {}", e))
+        .ok()?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    Some(TokenExpiry {
+        expires_at: token.expires_at,
+        expires_in_seconds: token.expires_at as i64 - now as i64,
+    })
+}
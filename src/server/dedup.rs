@@ -0,0 +1,243 @@
+use crate::config::CopilotDeduplicationConfig;
+use crate::copilot::CopilotChatRequest;
+use crate::server::AppError;
+use axum::body::Bytes;
+use axum::http::{self, HeaderMap, StatusCode};
+use reqwest::Response;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::sync::watch;
+use tracing::log::info;
+
+/// A Copilot reply, read in full so every waiting request can get a copy
+#[derive(Debug, Clone)]
+struct SharedReply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedReply {
+    async fn read(response: Response) -> Result<Self, AppError> {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| AppError::upstream("Failed to read Copilot response", e))?;
+        Ok(Self {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn response(&self) -> Response {
+        let mut response = http::Response::new(reqwest::Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        Response::from(response)
+    }
+}
+
+type Reply = watch::Receiver<Option<SharedReply>>;
+
+/// Requests forwarded to Copilot and not answered yet, under `[copilot.deduplication]`
+#[derive(Debug, Default)]
+pub struct InFlightRequests {
+    requests: Mutex<HashMap<String, Reply>>,
+}
+
+/// Forgets the leading request once it is answered, fails or is cancelled
+struct Landed<'a> {
+    requests: &'a Mutex<HashMap<String, Reply>>,
+    key: &'a str,
+}
+
+impl Drop for Landed<'_> {
+    fn drop(&mut self) {
+        self.requests
+            .lock()
+            .expect("in-flight requests lock poisoned")
+            .remove(self.key);
+    }
+}
+
+enum Role {
+    Leader(watch::Sender<Option<SharedReply>>),
+    Follower(Reply),
+}
+
+impl InFlightRequests {
+    /// Run `forward`, unless an identical request is already in flight: then wait for its
+    /// reply instead. When the leading request fails or is cancelled, the waiting ones are
+    /// forwarded on their own.
+    pub(crate) async fn coalesce(
+        &self,
+        key: String,
+        forward: impl Future<Output = Result<Response, AppError>>,
+    ) -> Result<Response, AppError> {
+        let role = {
+            let mut requests = self
+                .requests
+                .lock()
+                .expect("in-flight requests lock poisoned");
+            match requests.get(&key) {
+                Some(reply) => Role::Follower(reply.clone()),
+                None => {
+                    let (sender, reply) = watch::channel(None);
+                    requests.insert(key.clone(), reply);
+                    Role::Leader(sender)
+                }
+            }
+        };
+
+        match role {
+            Role::Follower(mut reply) => {
+                info!("Identical request in flight, waiting for its reply");
+                let shared = reply
+                    .wait_for(Option::is_some)
+                    .await
+                    .ok()
+                    .and_then(|reply| reply.clone());
+                match shared {
+                    Some(shared) => Ok(shared.response()),
+                    None => {
+                        info!("Identical request in flight failed, forwarding on its own");
+                        forward.await
+                    }
+                }
+            }
+            Role::Leader(sender) => {
+                let _landed = Landed {
+                    requests: &self.requests,
+                    key: &key,
+                };
+                let shared = SharedReply::read(forward.await?).await?;
+                sender.send_replace(Some(shared.clone()));
+                Ok(shared.response())
+            }
+        }
+    }
+}
+
+/// What identifies `request` among concurrent ones, or `None` when it must not be
+/// coalesced: deduplication is off, it streams, or its temperature is not 0
+pub(crate) fn dedup_key(
+    config: &CopilotDeduplicationConfig,
+    url: &str,
+    token: &str,
+    request: &CopilotChatRequest,
+    stream: bool,
+) -> Option<String> {
+    if !config.enabled || stream || request.temperature != Some(0.0) {
+        return None;
+    }
+    let body = serde_json::to_vec(request).ok()?;
+
+    let mut hasher = Sha256::new();
+    for part in [url.as_bytes(), token.as_bytes(), &body] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    Some(
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn reply(body: &'static str) -> Response {
+        Response::from(http::Response::new(reqwest::Body::from(body)))
+    }
+
+    /// A forwarded request that answers `body` after a while, counting calls
+    async fn forward(calls: Arc<AtomicUsize>, body: &'static str) -> Result<Response, AppError> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(reply(body))
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_share_one_call() {
+        let in_flight = InFlightRequests::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let (first, second) = tokio::join!(
+            in_flight.coalesce("key".to_string(), forward(calls.clone(), "first")),
+            in_flight.coalesce("key".to_string(), forward(calls.clone(), "second")),
+        );
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first.unwrap().text().await.unwrap(), "first");
+        assert_eq!(second.unwrap().text().await.unwrap(), "first");
+        assert!(in_flight.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_followers_forward_when_the_leader_fails() {
+        let in_flight = InFlightRequests::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let failing = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Err(AppError::GatewayTimeout("timed out".to_string()))
+        };
+        let (first, second) = tokio::join!(
+            in_flight.coalesce("key".to_string(), failing),
+            in_flight.coalesce("key".to_string(), forward(calls.clone(), "second")),
+        );
+
+        assert!(first.is_err());
+        assert_eq!(second.unwrap().text().await.unwrap(), "second");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_dedup_key() {
+        let enabled = CopilotDeduplicationConfig { enabled: true };
+        let mut request: CopilotChatRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "messages": [{ "role": "user", "content": "Hi" }],
+            "temperature": 0.0
+        }))
+        .unwrap();
+
+        let key = dedup_key(&enabled, "https://copilot", "token", &request, false);
+        assert!(key.is_some());
+        assert_eq!(
+            key,
+            dedup_key(&enabled, "https://copilot", "token", &request, false)
+        );
+        assert_ne!(
+            key,
+            dedup_key(&enabled, "https://copilot", "other", &request, false)
+        );
+
+        assert_eq!(
+            dedup_key(&enabled, "https://copilot", "token", &request, true),
+            None
+        );
+        let disabled = CopilotDeduplicationConfig::default();
+        assert_eq!(
+            dedup_key(&disabled, "https://copilot", "token", &request, false),
+            None
+        );
+        request.temperature = Some(0.7);
+        assert_eq!(
+            dedup_key(&enabled, "https://copilot", "token", &request, false),
+            None
+        );
+    }
+}
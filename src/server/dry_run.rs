@@ -0,0 +1,160 @@
+use crate::server::{AppError, AppState};
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use reqwest::header::AUTHORIZATION;
+use serde_json::{Map, Value, json};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::log::{error, info};
+
+/// Answers with the request the proxy would send to Copilot, without sending it; needs
+/// `[debug] dry_run`
+pub const DRY_RUN_HEADER: &str = "X-Passenger-Dry-Run";
+
+tokio::task_local! {
+    /// Set while serving a request with [`DRY_RUN_HEADER`], to hand over the request that
+    /// would have gone to Copilot
+    static DRY_RUN: mpsc::UnboundedSender<Value>;
+}
+
+/// Middleware serving requests with [`DRY_RUN_HEADER`] as dry runs, under `[debug] dry_run`
+pub(crate) async fn mark_dry_run(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let wanted = request
+        .headers()
+        .get(DRY_RUN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| matches!(value.trim(), "true" | "1"));
+    if !wanted {
+        return next.run(request).await;
+    }
+    if !state.config().debug.dry_run {
+        error!("Rejecting {} with [debug] dry_run off", DRY_RUN_HEADER);
+        return AppError::BadRequest(format!(
+            "{} requires [debug] dry_run = true",
+            DRY_RUN_HEADER
+        ))
+        .into_response();
+    }
+
+    // The handler stops at the request it would send, which answers the dry run
+    let (captured, mut dry_run) = mpsc::unbounded_channel();
+    let handler = DRY_RUN.scope(captured, next.run(request));
+    tokio::select! {
+        Some(request) = dry_run.recv() => {
+            ([(DRY_RUN_HEADER, "true")], Json(request)).into_response()
+        }
+        response = handler => response,
+    }
+}
+
+/// When serving a dry run, hand `request` over to answer it and never return, so it is not
+/// sent. Returns at once otherwise.
+pub(crate) async fn intercept(request: &reqwest::Request, body: &Value) {
+    let Ok(captured) = DRY_RUN.try_with(Clone::clone) else {
+        return;
+    };
+
+    info!("Dry run: not sending request to {}", request.url());
+    let headers: Map<String, Value> = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| *name != AUTHORIZATION)
+        .filter_map(|(name, value)| {
+            let value = value.to_str().ok()?;
+            Some((name.to_string(), Value::String(value.to_string())))
+        })
+        .collect();
+    let _ = captured.send(json!({
+        "method": request.method().as_str(),
+        "url": request.url().as_str(),
+        "headers": headers,
+        "body": body,
+    }));
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::clients::with_copilot_headers;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Router, middleware};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_intercept_only_dry_runs() {
+        let config = Config::default();
+        let request = with_copilot_headers(
+            reqwest::Client::new().post("https://api.githubcopilot.com/chat/completions"),
+            &config.copilot,
+            "secret",
+        )
+        .build()
+        .unwrap();
+        let body = json!({"model": "gpt-4o", "messages": []});
+
+        intercept(&request, &body).await;
+
+        let (captured, mut dry_runs) = mpsc::unbounded_channel();
+        let intercepted = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            DRY_RUN.scope(captured, intercept(&request, &body)),
+        )
+        .await;
+        assert!(intercepted.is_err(), "a dry run must not go on to send");
+        let dry_run = dry_runs.try_recv().unwrap();
+        assert_eq!(
+            dry_run["url"],
+            "https://api.githubcopilot.com/chat/completions"
+        );
+        assert_eq!(
+            dry_run["headers"]["copilot-integration-id"],
+            config.copilot.headers.integration_id
+        );
+        assert!(dry_run["headers"].get("authorization").is_none());
+        assert_eq!(dry_run["body"], body);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_is_a_successful_response() {
+        let mut config = Config::default();
+        config.debug.dry_run = true;
+        let state = Arc::new(AppState::new(&config, None));
+
+        let handler = || async {
+            let request = reqwest::Client::new()
+                .post("https://api.githubcopilot.com/chat/completions")
+                .build()
+                .unwrap();
+            intercept(&request, &json!({"model": "gpt-4o"})).await;
+            AppError::InternalServerError("sent to Copilot".to_string()).into_response()
+        };
+        let router = Router::new()
+            .route("/v1/chat/completions", post(handler))
+            .layer(middleware::from_fn_with_state(state.clone(), mark_dry_run))
+            .with_state(state);
+
+        let request = Request::post("/v1/chat/completions")
+            .header(DRY_RUN_HEADER, "true")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[DRY_RUN_HEADER], "true");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let dry_run: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(dry_run["body"]["model"], "gpt-4o");
+    }
+}
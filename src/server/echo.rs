@@ -0,0 +1,171 @@
+use crate::config::EchoConfig;
+use crate::copilot::context::{estimate_request_tokens, estimate_text_tokens};
+use crate::copilot::{CopilotChatRequest, CopilotContent};
+use axum::body::Bytes;
+use axum::http::{self, header};
+use futures_util::StreamExt as _;
+use serde_json::{Value, json};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::log::info;
+
+/// The reply of `[echo] model` to a chat request body, made up locally in Copilot's format,
+/// or `None` when the body is for another model
+pub(crate) fn respond(
+    config: &EchoConfig,
+    body: &Value,
+    stream: bool,
+) -> Option<reqwest::Response> {
+    if !config.serves(body.get("model")?.as_str()?) {
+        return None;
+    }
+    let request: CopilotChatRequest = serde_json::from_value(body.clone()).ok()?;
+    let text = last_user_text(&request);
+    info!("Echoing {} characters locally", text.chars().count());
+
+    let id = format!("echo-{}", uuid::Uuid::new_v4().simple());
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let prompt_tokens = estimate_request_tokens(&request);
+    let completion_tokens = estimate_text_tokens(&text);
+    let usage = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    });
+
+    let (content_type, body) = if stream {
+        let chunk = |delta: Value, finish_reason: Option<&str>, usage: Option<&Value>| {
+            let mut chunk = json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "created": created,
+                "model": request.model,
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+            });
+            if let Some(usage) = usage {
+                chunk["usage"] = usage.clone();
+            }
+            Bytes::from(format!("data: {}\n\n", chunk))
+        };
+        let mut events = vec![chunk(
+            json!({"role": "assistant", "content": ""}),
+            None,
+            None,
+        )];
+        events.extend(
+            text.split_inclusive(char::is_whitespace)
+                .map(|token| chunk(json!({"content": token}), None, None)),
+        );
+        events.push(chunk(json!({}), Some("stop"), Some(&usage)));
+        events.push(Bytes::from_static(b"data: [DONE]\n\n"));
+
+        let pause = config
+            .tokens_per_second
+            .filter(|rate| *rate > 0.0)
+            .map(|rate| Duration::from_secs_f64(1.0 / rate));
+        let events = futures_util::stream::iter(events).then(move |event| async move {
+            if let Some(pause) = pause {
+                tokio::time::sleep(pause).await;
+            }
+            Ok::<_, std::io::Error>(event)
+        });
+        ("text/event-stream", reqwest::Body::wrap_stream(events))
+    } else {
+        let completion = json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": request.model,
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": text},
+                "finish_reason": "stop",
+            }],
+            "usage": usage,
+        });
+        (
+            "application/json",
+            reqwest::Body::from(completion.to_string()),
+        )
+    };
+
+    let response = http::Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body)
+        .ok()?;
+    Some(reqwest::Response::from(response))
+}
+
+/// Text of the last user message, which the echo model answers with
+fn last_user_text(request: &CopilotChatRequest) -> String {
+    let message = request
+        .messages
+        .iter()
+        .rev()
+        .find(|message| message.role == "user");
+    message
+        .and_then(|message| message.content.as_ref())
+        .map(CopilotContent::to_text)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> EchoConfig {
+        EchoConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn body() -> Value {
+        json!({
+            "model": "passenger-echo",
+            "messages": [
+                {"role": "system", "content": "Be brief"},
+                {"role": "user", "content": [{"type": "text", "text": "Hello there world"}]},
+            ],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_echo_reply() {
+        assert!(respond(&EchoConfig::default(), &body(), false).is_none());
+        let mut other = body();
+        other["model"] = json!("gpt-4o");
+        assert!(respond(&config(), &other, false).is_none());
+
+        let response = respond(&config(), &body(), false).unwrap();
+        let reply: Value = response.json().await.unwrap();
+        assert_eq!(
+            reply["choices"][0]["message"]["content"],
+            "Hello there world"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_echo_stream() {
+        let response = respond(&config(), &body(), true).unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        let text = response.text().await.unwrap();
+        let contents: Vec<String> = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+            .filter_map(|chunk| {
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(String::from)
+            })
+            .collect();
+        assert_eq!(contents, ["", "Hello ", "there ", "world"]);
+        assert!(text.ends_with("data: [DONE]\n\n"));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::auth::CopilotTokenResponse;
+use crate::copilot::CopilotChatRequest;
+use crate::copilot::empty_choices::has_no_choices;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::{AppError, AppState, Server};
+use axum::body::Bytes;
+use axum::http;
+use reqwest::Response;
+use std::sync::Arc;
+use tracing::log::{error, info, warn};
+
+/// Retries of Copilot's non-streaming replies without any `choices`, under
+/// `[copilot.empty_choices]`
+pub(crate) trait EmptyChoicesRetry: CopilotIntegration {
+    /// Return `response` if it has choices, otherwise ask Copilot again until a reply has
+    /// some or `retries` is reached. The body is buffered and put back, so callers see an
+    /// untouched response; failed retries are returned as they are for `handle_errors`.
+    async fn retry_empty_choices(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        response: Response,
+    ) -> Result<Response, AppError>;
+}
+
+impl EmptyChoicesRetry for Server {
+    async fn retry_empty_choices(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        response: Response,
+    ) -> Result<Response, AppError> {
+        let config = &state.config().copilot.empty_choices;
+        let (response, body) = buffered(response).await?;
+        if !has_no_choices(&body) {
+            return Ok(response);
+        }
+
+        let retry_request = if config.duplicate_tool_messages {
+            request.with_tool_messages_as_user()
+        } else {
+            request.clone()
+        };
+
+        for attempt in 1..=config.retries {
+            warn!(
+                "Copilot returned no choices for {}, retrying ({}/{})",
+                request.model, attempt, config.retries
+            );
+            let retry = Self::forward_prompt(
+                state.clone(),
+                token.clone(),
+                url.clone(),
+                &retry_request,
+                session_id,
+                false,
+            )
+            .await?;
+            if !retry.status().is_success() {
+                return Ok(retry);
+            }
+
+            let (retried, body) = buffered(retry).await?;
+            if !has_no_choices(&body) {
+                info!(
+                    "Copilot returned choices for {} on retry {}",
+                    request.model, attempt
+                );
+                return Ok(retried);
+            }
+        }
+
+        let has_tool_messages = request
+            .messages
+            .iter()
+            .any(|message| message.role == "tool");
+        let hint = if has_tool_messages && !config.duplicate_tool_messages {
+            " The conversation has tool messages, which are known to trigger this: setting \
+             [copilot.empty_choices] duplicate_tool_messages = true may help."
+        } else {
+            ""
+        };
+        error!(
+            "Copilot returned no choices for {} after {} retries",
+            request.model, config.retries
+        );
+        Err(AppError::EmptyChoices(format!(
+            "Copilot returned a reply without any choices for model {} on {} attempts. \
+             This is an intermittent upstream anomaly rather than a problem with the request.{}",
+            request.model,
+            config.retries + 1,
+            hint
+        )))
+    }
+}
+
+/// Read a successful response's body, rebuilding the response around it
+pub(crate) async fn buffered(response: Response) -> Result<(Response, Bytes), AppError> {
+    if !response.status().is_success() {
+        return Ok((response, Default::default()));
+    }
+    buffered_any(response).await
+}
+
+/// Read a response's body whatever its status, rebuilding the response around it
+pub(crate) async fn buffered_any(response: Response) -> Result<(Response, Bytes), AppError> {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| AppError::upstream("Failed to read Copilot response", e))?;
+
+    Ok((rebuilt(status, headers, body.clone()), body))
+}
+
+/// A response with `status` and `headers` around `body`
+pub(crate) fn rebuilt(status: http::StatusCode, headers: http::HeaderMap, body: Bytes) -> Response {
+    let mut rebuilt = http::Response::new(reqwest::Body::from(body));
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+    Response::from(rebuilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::copilot::CopilotChatResponse;
+    use crate::openai::completion::models::OpenAIChatRequest;
+    use crate::server::test_token;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn state(config: Config) -> Arc<AppState> {
+        Arc::new(AppState::new(&config, None))
+    }
+
+    fn request() -> CopilotChatRequest {
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "assistant", "tool_calls": [
+                    {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{}"}}
+                ]},
+                {"role": "tool", "tool_call_id": "call_1", "name": "get_weather", "content": "72F"}
+            ]
+        }))
+        .unwrap();
+        request.into()
+    }
+
+    fn empty_reply() -> Response {
+        let reply = json!({"id": "chatcmpl-1", "model": "gpt-4o", "choices": []});
+        Response::from(http::Response::new(reply.to_string()))
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_duplicated_tool_messages() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("Tool 'get_weather' (call_1) returned"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-2",
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "It is 72F"},
+                    "finish_reason": "stop"
+                }]
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.empty_choices.duplicate_tool_messages = true;
+        let url = format!("{}/chat/completions", mock_server.uri());
+
+        let response = Server::retry_empty_choices(
+            state(config),
+            test_token(),
+            url,
+            &request(),
+            "session",
+            empty_reply(),
+        )
+        .await
+        .unwrap();
+
+        let reply: CopilotChatResponse = response.json().await.unwrap();
+        assert_eq!(reply.choices.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_persistent_empty_choices_fail_descriptively() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"choices": []})))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let config = Config::default();
+        let url = format!("{}/chat/completions", mock_server.uri());
+
+        let result = Server::retry_empty_choices(
+            state(config),
+            test_token(),
+            url,
+            &request(),
+            "session",
+            empty_reply(),
+        )
+        .await;
+
+        match result {
+            Err(AppError::EmptyChoices(message)) => {
+                assert!(message.contains("on 3 attempts"));
+                assert!(message.contains("duplicate_tool_messages = true"));
+            }
+            other => panic!("expected EmptyChoices, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use crate::auth::CopilotTokenResponse;
+use crate::copilot::CopilotChatRequest;
+use crate::server::keys;
+use crate::server::racing::ModelRacing;
+use crate::server::{AppError, AppState, Server};
+use axum::http::{self, HeaderValue, StatusCode};
+use reqwest::Response;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::log::{info, warn};
+
+/// Response header naming the model that answered, for models with `[copilot.fallbacks]`
+pub const SERVED_MODEL_HEADER: &str = "X-Passenger-Served-Model";
+
+/// Error codes with which Copilot refuses a model it cannot serve right now
+const UNAVAILABLE_CODES: &[&str] = &[
+    "model_not_supported",
+    "model_not_available",
+    "model_unavailable",
+];
+
+/// Models that recently failed with `429`, `503` or a model-unavailable error, and until
+/// when fallback chains skip them
+#[derive(Debug, Default)]
+pub struct ModelHealth {
+    unavailable_until: Mutex<HashMap<String, Instant>>,
+}
+
+impl ModelHealth {
+    fn mark_unavailable(&self, model: &str, cooldown: Duration) {
+        self.unavailable_until
+            .lock()
+            .expect("model health lock poisoned")
+            .insert(model.to_string(), Instant::now() + cooldown);
+    }
+
+    fn mark_available(&self, model: &str) {
+        self.unavailable_until
+            .lock()
+            .expect("model health lock poisoned")
+            .remove(model);
+    }
+
+    fn is_available(&self, model: &str) -> bool {
+        self.unavailable_until
+            .lock()
+            .expect("model health lock poisoned")
+            .get(model)
+            .is_none_or(|until| *until <= Instant::now())
+    }
+
+    /// The models of `chain` to try, in order: those not cooling down, or all of them
+    /// when every one is
+    fn candidates<'a>(&self, chain: &[&'a str]) -> Vec<&'a str> {
+        let available: Vec<&str> = chain
+            .iter()
+            .copied()
+            .filter(|model| self.is_available(model))
+            .collect();
+        if available.is_empty() {
+            chain.to_vec()
+        } else {
+            available
+        }
+    }
+}
+
+/// Model fallback chains, under `[copilot.fallbacks]`
+pub(crate) trait ModelFallback: ModelRacing {
+    /// Forward `request` to its model, then down its fallback chain while Copilot answers
+    /// that a model is unavailable or rate limited. The last answer is returned as it is.
+    /// Models the client's API key may not use are left out of the chain.
+    async fn forward_with_fallbacks(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        stream: bool,
+    ) -> Result<Response, AppError>;
+}
+
+impl ModelFallback for Server {
+    async fn forward_with_fallbacks(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        stream: bool,
+    ) -> Result<Response, AppError> {
+        let fallbacks = &state.config().copilot.fallbacks;
+        let Some(chain) = fallbacks.chains.get(&request.model) else {
+            return Self::race_models(state, token, url, request, session_id, stream).await;
+        };
+        // Fallback models are held to the key's models like the requested one
+        let chain: Vec<&str> = std::iter::once(request.model.as_str())
+            .chain(
+                chain
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|model| keys::allows_model(model)),
+            )
+            .collect();
+        let candidates = state.model_health.candidates(&chain);
+        let cooldown = Duration::from_secs(fallbacks.cooldown_secs);
+
+        let mut attempt = request.clone();
+        for (i, model) in candidates.iter().enumerate() {
+            attempt.model = model.to_string();
+            let response = Self::race_models(
+                state.clone(),
+                token.clone(),
+                url.clone(),
+                &attempt,
+                session_id,
+                stream,
+            )
+            .await?;
+
+            let is_last = i + 1 == candidates.len();
+            let (unavailable, response) = if is_last {
+                (false, response)
+            } else {
+                unavailable(response).await?
+            };
+            if !unavailable {
+                if response.status().is_success() {
+                    state.model_health.mark_available(model);
+                }
+                if *model != request.model {
+                    info!("Answering {} with fallback model {}", request.model, model);
+                }
+                return Ok(with_served_model(response, model));
+            }
+
+            warn!(
+                "Model {} is unavailable, falling back on {}",
+                model,
+                candidates[i + 1]
+            );
+            state.model_health.mark_unavailable(model, cooldown);
+        }
+        unreachable!("a fallback chain starts with the requested model")
+    }
+}
+
+/// Whether Copilot refused the request because the model is unavailable or rate limited,
+/// with the response rebuilt around any body read to tell
+async fn unavailable(response: Response) -> Result<(bool, Response), AppError> {
+    let status = response.status();
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+            return Ok((true, response));
+        }
+        StatusCode::BAD_REQUEST | StatusCode::NOT_FOUND => {}
+        _ => return Ok((false, response)),
+    }
+
+    let headers = response.headers().clone();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| AppError::upstream("Failed to read Copilot response", e))?;
+    let error = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|body| body.get("error").cloned())
+        .unwrap_or_default();
+    let code = error
+        .get("code")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let message = error
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_lowercase();
+    let unavailable = UNAVAILABLE_CODES.contains(&code)
+        || (message.contains("model")
+            && ["not supported", "not available", "unavailable"]
+                .iter()
+                .any(|phrase| message.contains(phrase)));
+
+    let mut rebuilt = http::Response::new(reqwest::Body::from(body));
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+    Ok((unavailable, Response::from(rebuilt)))
+}
+
+/// Name the model that answered in [`SERVED_MODEL_HEADER`], unless a raced fast model
+/// already did
+pub(crate) fn with_served_model(mut response: Response, model: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(model) {
+        response
+            .headers_mut()
+            .entry(SERVED_MODEL_HEADER)
+            .or_insert(value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::openai::completion::models::OpenAIChatRequest;
+    use crate::server::keys::ApiKey;
+    use crate::server::test_token;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request(model: &str) -> CopilotChatRequest {
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "Hi"}],
+        }))
+        .unwrap();
+        request.into()
+    }
+
+    async fn mount(server: &MockServer, model: &str, response: ResponseTemplate) {
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({"model": model})))
+            .respond_with(response)
+            .mount(server)
+            .await;
+    }
+
+    #[test]
+    fn test_candidates_skip_unavailable_models() {
+        let health = ModelHealth::default();
+        let chain = ["gpt-5", "gpt-4.1", "gpt-4o"];
+        assert_eq!(health.candidates(&chain), chain);
+
+        health.mark_unavailable("gpt-5", Duration::from_secs(60));
+        assert_eq!(health.candidates(&chain), ["gpt-4.1", "gpt-4o"]);
+
+        health.mark_unavailable("gpt-4.1", Duration::from_secs(60));
+        health.mark_unavailable("gpt-4o", Duration::ZERO);
+        assert_eq!(health.candidates(&chain), ["gpt-4o"]);
+
+        health.mark_unavailable("gpt-4o", Duration::from_secs(60));
+        assert_eq!(health.candidates(&chain), chain);
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_along_the_chain() {
+        let server = MockServer::start().await;
+        mount(&server, "gpt-5", ResponseTemplate::new(429)).await;
+        mount(
+            &server,
+            "gpt-4.1",
+            ResponseTemplate::new(400).set_body_json(json!({
+                "error": {"message": "The requested model is not supported.", "code": "model_not_supported"}
+            })),
+        )
+        .await;
+        mount(
+            &server,
+            "gpt-4o",
+            ResponseTemplate::new(200).set_body_json(json!({"choices": []})),
+        )
+        .await;
+
+        let mut config = Config::default();
+        config.copilot.fallbacks.chains.insert(
+            "gpt-5".to_string(),
+            vec!["gpt-4.1".to_string(), "gpt-4o".to_string()],
+        );
+        let state = Arc::new(AppState::new(&config, None));
+        let url = format!("{}/chat/completions", server.uri());
+
+        let response = Server::forward_with_fallbacks(
+            state.clone(),
+            test_token(),
+            url.clone(),
+            &request("gpt-5"),
+            "session",
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[SERVED_MODEL_HEADER], "gpt-4o");
+        assert!(!state.model_health.is_available("gpt-5"));
+        assert!(!state.model_health.is_available("gpt-4.1"));
+
+        // Models without a chain are forwarded as they are
+        let response = Server::forward_with_fallbacks(
+            state,
+            test_token(),
+            url,
+            &request("gpt-4o"),
+            "s",
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(!response.headers().contains_key(SERVED_MODEL_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_fallbacks_are_held_to_the_key_models() {
+        let server = MockServer::start().await;
+        mount(&server, "gpt-5", ResponseTemplate::new(429)).await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({"model": "claude-opus-4"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"choices": []})))
+            .expect(0)
+            .mount(&server)
+            .await;
+        mount(
+            &server,
+            "gpt-4o-mini",
+            ResponseTemplate::new(200).set_body_json(json!({"choices": []})),
+        )
+        .await;
+
+        let mut config = Config::default();
+        config.copilot.fallbacks.chains.insert(
+            "gpt-5".to_string(),
+            vec!["claude-opus-4".to_string(), "gpt-4o-mini".to_string()],
+        );
+        let state = Arc::new(AppState::new(&config, None));
+        let url = format!("{}/chat/completions", server.uri());
+        let key = ApiKey {
+            id: "0123abcd".to_string(),
+            label: "ci".to_string(),
+            monthly_quota: None,
+            models: vec!["gpt-5".to_string(), "gpt-4o-mini".to_string()],
+            created: 0,
+            revoked: false,
+            requests_this_month: 0,
+        };
+
+        let response = keys::with_key(
+            key,
+            Server::forward_with_fallbacks(
+                state.clone(),
+                test_token(),
+                url,
+                &request("gpt-5"),
+                "session",
+                false,
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers()[SERVED_MODEL_HEADER], "gpt-4o-mini");
+
+        // Only the model that answered is charged
+        let report = state.premium.report(None);
+        assert_eq!(report.models.keys().collect::<Vec<_>>(), ["gpt-4o-mini"]);
+    }
+}
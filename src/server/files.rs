@@ -0,0 +1,596 @@
+use crate::config::FilesConfig;
+use crate::server::multipart;
+use crate::server::users::tenant;
+use crate::server::{AppError, AppState, Server};
+use crate::storage;
+use anyhow::{Context, Result};
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::log::{error, info};
+use uuid::Uuid;
+
+/// Purposes OpenAI accepts for uploads
+const PURPOSES: &[&str] = &[
+    "assistants",
+    "batch",
+    "fine-tune",
+    "vision",
+    "user_data",
+    "evals",
+];
+
+/// OpenAI file object
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    pub bytes: u64,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FileList {
+    pub object: String,
+    pub data: Vec<FileObject>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeletedFile {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListFilesQuery {
+    pub purpose: Option<String>,
+}
+
+/// Marks the responses of the upload route, whose bodies are bounded by
+/// `[files] max_upload_bytes` rather than `[server] max_body_bytes`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UploadResponse;
+
+/// Middleware marking a response as the upload route's, see [`UploadResponse`]
+pub(crate) async fn mark_upload(mut response: Response) -> Response {
+    response.extensions_mut().insert(UploadResponse);
+    response
+}
+
+/// Metadata of a stored file: its file object and the tenant that uploaded it
+#[derive(Debug, Deserialize, Serialize)]
+struct StoredFile {
+    #[serde(flatten)]
+    file: FileObject,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+}
+
+/// Uploaded files on local disk: `<id>` holds the content, `<id>.json` its metadata.
+///
+/// Each file belongs to the tenant that uploaded it, by OIDC user or API key, and is only listed,
+/// read or deleted for that tenant. Without `[oidc]` or `[keys]`, files have no owner.
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// `[files] directory`, or `files` in the token storage directory
+    pub fn from_config(config: &FilesConfig) -> Self {
+        let dir = match &config.directory {
+            Some(directory) => PathBuf::from(directory),
+            None => crate::storage::get_storage_dir()
+                .map(|dir| dir.join("files"))
+                .unwrap_or_else(|_| PathBuf::from("files")),
+        };
+        Self::new(dir)
+    }
+
+    /// Store an upload of `owner`, readable by the current user only like the tokens next
+    /// to it
+    pub async fn create(
+        &self,
+        owner: Option<&str>,
+        filename: &str,
+        purpose: &str,
+        data: &[u8],
+    ) -> Result<FileObject> {
+        let file = FileObject {
+            id: format!("file-{}", Uuid::new_v4().simple()),
+            object: "file".to_string(),
+            bytes: data.len() as u64,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("time should go forward")
+                .as_secs(),
+            filename: filename.to_string(),
+            purpose: purpose.to_string(),
+        };
+
+        let dir = self.dir.clone();
+        let content_path = self.dir.join(&file.id);
+        let metadata_path = self.metadata_path(&file.id);
+        let metadata = serde_json::to_vec(&StoredFile {
+            file: file.clone(),
+            owner: owner.map(str::to_string),
+        })?;
+        let data = data.to_vec();
+        tokio::task::spawn_blocking(move || {
+            storage::create_private_dir(&dir).context("Failed to create files directory")?;
+            storage::write_private_file(&content_path, data).context("Failed to write file")?;
+            storage::write_private_file(&metadata_path, metadata)
+                .context("Failed to write file metadata")
+        })
+        .await
+        .context("Failed to store file")??;
+
+        Ok(file)
+    }
+
+    /// The files of `owner`, newest first
+    pub async fn list(&self, owner: Option<&str>) -> Result<Vec<FileObject>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read files directory"),
+        };
+
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+            if let Some(file) = self.get(owner, id).await? {
+                files.push(file);
+            }
+        }
+
+        files.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        Ok(files)
+    }
+
+    /// File `id`, when it belongs to `owner`
+    pub async fn get(&self, owner: Option<&str>, id: &str) -> Result<Option<FileObject>> {
+        if !valid_id(id) {
+            return Ok(None);
+        }
+
+        let stored: StoredFile = match tokio::fs::read(self.metadata_path(id)).await {
+            Ok(metadata) => serde_json::from_slice(&metadata).context("Corrupt file metadata")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read file metadata"),
+        };
+        Ok((stored.owner.as_deref() == owner).then_some(stored.file))
+    }
+
+    pub async fn content(&self, owner: Option<&str>, id: &str) -> Result<Option<Vec<u8>>> {
+        if self.get(owner, id).await?.is_none() {
+            return Ok(None);
+        }
+
+        let content = tokio::fs::read(self.dir.join(id))
+            .await
+            .context("Failed to read file")?;
+        Ok(Some(content))
+    }
+
+    /// `false` when `owner` had no such file
+    pub async fn delete(&self, owner: Option<&str>, id: &str) -> Result<bool> {
+        if self.get(owner, id).await?.is_none() {
+            return Ok(false);
+        }
+
+        tokio::fs::remove_file(self.metadata_path(id))
+            .await
+            .context("Failed to delete file metadata")?;
+        tokio::fs::remove_file(self.dir.join(id))
+            .await
+            .context("Failed to delete file")?;
+        Ok(true)
+    }
+
+    fn metadata_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+}
+
+/// Ids are generated by [`FileStore::create`]; anything else could escape the directory
+fn valid_id(id: &str) -> bool {
+    id.strip_prefix("file-")
+        .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn storage_error(e: anyhow::Error) -> AppError {
+    error!("File storage error: {:#}", e);
+    AppError::InternalServerError(format!("File storage error: {}", e))
+}
+
+fn not_found(id: &str) -> AppError {
+    AppError::NotFound(format!("No such File object: {}", id))
+}
+
+/// OpenAI Files API backed by [`FileStore`]
+pub(crate) trait FilesEndpoint {
+    async fn upload_file(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Json<FileObject>, AppError>;
+
+    async fn list_files(
+        state: State<Arc<AppState>>,
+        query: Query<ListFilesQuery>,
+    ) -> Result<Json<FileList>, AppError>;
+
+    async fn retrieve_file(
+        state: State<Arc<AppState>>,
+        file_id: Path<String>,
+    ) -> Result<Json<FileObject>, AppError>;
+
+    async fn delete_file(
+        state: State<Arc<AppState>>,
+        file_id: Path<String>,
+    ) -> Result<Json<DeletedFile>, AppError>;
+
+    async fn file_content(
+        state: State<Arc<AppState>>,
+        file_id: Path<String>,
+    ) -> Result<Response, AppError>;
+}
+
+impl FilesEndpoint for Server {
+    async fn upload_file(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Json<FileObject>, AppError> {
+        let boundary = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(multipart::boundary)
+            .ok_or_else(|| {
+                AppError::BadRequest("Expected a multipart/form-data upload".to_string())
+            })?;
+
+        let parts = multipart::parse(&body, boundary).map_err(AppError::BadRequest)?;
+
+        let purpose = parts
+            .iter()
+            .find(|part| part.name == "purpose")
+            .map(|part| String::from_utf8_lossy(&part.data).trim().to_string())
+            .ok_or_else(|| AppError::BadRequest("Missing required field: purpose".to_string()))?;
+        if !PURPOSES.contains(&purpose.as_str()) {
+            return Err(AppError::BadRequest(format!(
+                "Invalid purpose \"{}\": expected one of {}",
+                purpose,
+                PURPOSES.join(", ")
+            )));
+        }
+
+        let file = parts
+            .iter()
+            .find(|part| part.name == "file")
+            .ok_or_else(|| AppError::BadRequest("Missing required field: file".to_string()))?;
+        let filename = file.filename.as_deref().unwrap_or("upload");
+
+        let file = state
+            .files
+            .create(tenant().as_deref(), filename, &purpose, &file.data)
+            .await
+            .map_err(storage_error)?;
+
+        info!(
+            "Stored file {} ({}, {} bytes, purpose {})",
+            file.id, file.filename, file.bytes, file.purpose
+        );
+        Ok(Json(file))
+    }
+
+    async fn list_files(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<ListFilesQuery>,
+    ) -> Result<Json<FileList>, AppError> {
+        let mut files = state
+            .files
+            .list(tenant().as_deref())
+            .await
+            .map_err(storage_error)?;
+        if let Some(purpose) = &query.purpose {
+            files.retain(|file| &file.purpose == purpose);
+        }
+
+        Ok(Json(FileList {
+            object: "list".to_string(),
+            data: files,
+        }))
+    }
+
+    async fn retrieve_file(
+        State(state): State<Arc<AppState>>,
+        Path(file_id): Path<String>,
+    ) -> Result<Json<FileObject>, AppError> {
+        let file = state
+            .files
+            .get(tenant().as_deref(), &file_id)
+            .await
+            .map_err(storage_error)?;
+        match file {
+            Some(file) => Ok(Json(file)),
+            None => Err(not_found(&file_id)),
+        }
+    }
+
+    async fn delete_file(
+        State(state): State<Arc<AppState>>,
+        Path(file_id): Path<String>,
+    ) -> Result<Json<DeletedFile>, AppError> {
+        let deleted = state
+            .files
+            .delete(tenant().as_deref(), &file_id)
+            .await
+            .map_err(storage_error)?;
+        if !deleted {
+            return Err(not_found(&file_id));
+        }
+
+        info!("Deleted file {}", file_id);
+        Ok(Json(DeletedFile {
+            id: file_id,
+            object: "file".to_string(),
+            deleted: true,
+        }))
+    }
+
+    async fn file_content(
+        State(state): State<Arc<AppState>>,
+        Path(file_id): Path<String>,
+    ) -> Result<Response, AppError> {
+        let content = state
+            .files
+            .content(tenant().as_deref(), &file_id)
+            .await
+            .map_err(storage_error)?;
+        match content {
+            Some(content) => Ok((
+                [(header::CONTENT_TYPE, "application/octet-stream")],
+                content,
+            )
+                .into_response()),
+            None => Err(not_found(&file_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::http::HeaderValue;
+
+    fn state(dir: &std::path::Path) -> Arc<AppState> {
+        let mut config = Config::default();
+        config.files.directory = Some(dir.display().to_string());
+        Arc::new(AppState::new(&config, None))
+    }
+
+    fn multipart_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=xyz"),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn test_upload_and_list_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let body = Bytes::from(
+            "--xyz\r\n\
+             Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+             user_data\r\n\
+             --xyz\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\r\n\
+             hello\r\n\
+             --xyz--\r\n",
+        );
+
+        let Json(file) = Server::upload_file(State(state.clone()), multipart_headers(), body)
+            .await
+            .unwrap();
+        assert_eq!(file.filename, "notes.txt");
+        assert_eq!(file.purpose, "user_data");
+        assert_eq!(file.bytes, 5);
+
+        let Json(list) = Server::list_files(
+            State(state.clone()),
+            Query(ListFilesQuery {
+                purpose: Some("batch".to_string()),
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(list.data.is_empty());
+
+        let Json(list) = Server::list_files(
+            State(state.clone()),
+            Query(ListFilesQuery { purpose: None }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(list.data, vec![file.clone()]);
+
+        let missing = Server::retrieve_file(State(state), Path("file-missing".to_string())).await;
+        assert!(matches!(missing, Err(AppError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_invalid_purpose() {
+        let dir = tempfile::tempdir().unwrap();
+        let body = Bytes::from(
+            "--xyz\r\n\
+             Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+             anything\r\n\
+             --xyz\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n\
+             hello\r\n\
+             --xyz--\r\n",
+        );
+
+        let result = Server::upload_file(State(state(dir.path())), multipart_headers(), body).await;
+
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn test_file_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path().join("files"));
+        assert!(store.list(None).await.unwrap().is_empty());
+
+        let file = store
+            .create(None, "input.jsonl", "batch", b"{\"a\":1}\n")
+            .await
+            .unwrap();
+        assert!(file.id.starts_with("file-"));
+        assert_eq!(file.bytes, 8);
+
+        assert_eq!(store.get(None, &file.id).await.unwrap(), Some(file.clone()));
+        assert_eq!(store.list(None).await.unwrap(), vec![file.clone()]);
+        assert_eq!(
+            store.content(None, &file.id).await.unwrap().as_deref(),
+            Some(&b"{\"a\":1}\n"[..])
+        );
+
+        assert!(store.delete(None, &file.id).await.unwrap());
+        assert!(!store.delete(None, &file.id).await.unwrap());
+        assert_eq!(store.get(None, &file.id).await.unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_file_store_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("files");
+        let store = FileStore::new(dir.clone());
+
+        let file = store
+            .create(None, "notes.txt", "assistants", b"secret")
+            .await
+            .unwrap();
+
+        let mode = |path: PathBuf| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(dir.clone()), 0o700);
+        assert_eq!(mode(dir.join(&file.id)), 0o600);
+        assert_eq!(mode(store.metadata_path(&file.id)), 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_file_store_rejects_foreign_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("secret.json"), "{}").unwrap();
+        let store = FileStore::new(dir.path().join("files"));
+
+        assert_eq!(store.get(None, "../secret").await.unwrap(), None);
+        assert_eq!(
+            store.content(None, "file-../../secret").await.unwrap(),
+            None
+        );
+        assert!(valid_id("file-0123abc"));
+        assert!(!valid_id("file-"));
+    }
+
+    #[cfg(feature = "keys")]
+    #[tokio::test]
+    async fn test_files_are_scoped_to_their_tenant() {
+        use crate::server::keys::KeyStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.files.directory = Some(dir.path().join("files").display().to_string());
+        config.keys.enabled = true;
+        config.keys.path = Some(dir.path().join("keys.db").display().to_string());
+        let keys = KeyStore::from_config(&config.keys).unwrap().unwrap();
+        let (_, alice) = keys.create("alice", None, &[]).unwrap();
+        let (_, bob) = keys.create("bob", None, &[]).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{}/v1/files{}", addr, path);
+
+        let file: FileObject = client
+            .post(url(""))
+            .bearer_auth(&alice)
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=xyz")
+            .body(
+                "--xyz\r\n\
+                 Content-Disposition: form-data; name=\"purpose\"\r\n\r\n\
+                 user_data\r\n\
+                 --xyz\r\n\
+                 Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\n\
+                 secret\r\n\
+                 --xyz--\r\n",
+            )
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let file_url = url(&format!("/{}", file.id));
+        let content_url = url(&format!("/{}/content", file.id));
+
+        let list: FileList = client
+            .get(url(""))
+            .bearer_auth(&bob)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(list.data.is_empty());
+        for request in [
+            client.get(&file_url),
+            client.get(&content_url),
+            client.delete(&file_url),
+        ] {
+            let response = request.bearer_auth(&bob).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+        }
+
+        let list: FileList = client
+            .get(url(""))
+            .bearer_auth(&alice)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(list.data, vec![file]);
+        let response = client.get(&content_url).bearer_auth(&alice).send().await;
+        assert_eq!(response.unwrap().text().await.unwrap(), "secret");
+    }
+}
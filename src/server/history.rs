@@ -0,0 +1,413 @@
+use crate::config::HistoryConfig;
+use crate::copilot::tool_calls::ToolCallCheck;
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse, CopilotMessage};
+use crate::server::capabilities::{ModelAdaptation, with_adjustments_header};
+use crate::server::copilot::CopilotIntegration;
+use crate::server::empty_choices::buffered;
+use crate::server::openai::chat_completion::openai_chat_response;
+use crate::server::users::admit_user;
+use crate::server::{AppError, AppState, Server};
+use anyhow::{Context, Result};
+use axum::Json;
+use axum::extract::{Path, State};
+use reqwest::Response;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::log::{error, info, warn};
+use uuid::Uuid;
+
+/// A session in the conversation log
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct SessionSummary {
+    pub id: String,
+    /// Model of the latest exchange
+    pub model: String,
+    pub exchanges: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SessionList {
+    pub object: String,
+    pub data: Vec<SessionSummary>,
+}
+
+/// One request to Copilot and its reply, `None` when it was streamed
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Exchange {
+    pub model: String,
+    pub created: u64,
+    pub request: Value,
+    pub reply: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Transcript {
+    pub id: String,
+    pub object: String,
+    pub exchanges: Vec<Exchange>,
+}
+
+/// Body of `POST /v1/history/sessions/{id}/replay`
+#[derive(Debug, Deserialize)]
+pub struct ReplayRequest {
+    pub model: String,
+}
+
+/// Chat exchanges per session id, in SQLite
+#[derive(Debug)]
+pub struct HistoryStore {
+    connection: Mutex<Connection>,
+}
+
+impl HistoryStore {
+    /// `[history] path`, or `history.sqlite` in the token storage directory.
+    /// `None` unless `[history] enabled`.
+    pub fn from_config(config: &HistoryConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let path = match &config.path {
+            Some(path) => PathBuf::from(path),
+            None => crate::storage::get_storage_dir()
+                .map(|dir| dir.join("history.sqlite"))
+                .unwrap_or_else(|_| PathBuf::from("history.sqlite")),
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent).context("Failed to create history directory")?;
+        }
+
+        let connection = Connection::open(&path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+        Self::new(connection).map(Some)
+    }
+
+    pub fn new(connection: Connection) -> Result<Self> {
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS exchanges (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    session_id TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    created INTEGER NOT NULL,
+                    request TEXT NOT NULL,
+                    reply TEXT
+                );
+                CREATE INDEX IF NOT EXISTS exchanges_session ON exchanges (session_id);",
+            )
+            .context("Failed to create history tables")?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn record(
+        &self,
+        session_id: &str,
+        request: &CopilotChatRequest,
+        reply: Option<&CopilotMessage>,
+    ) -> Result<()> {
+        let body = serde_json::to_string(request)?;
+        let reply = reply.map(serde_json::to_string).transpose()?;
+
+        self.connection()
+            .execute(
+                "INSERT INTO exchanges (session_id, model, created, request, reply)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![session_id, request.model, now(), body, reply],
+            )
+            .context("Failed to record exchange")?;
+        Ok(())
+    }
+
+    /// Sessions, most recently active first
+    pub fn sessions(&self) -> Result<Vec<SessionSummary>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT session_id, COUNT(*), MIN(created), MAX(created),
+                (SELECT model FROM exchanges latest WHERE latest.session_id = exchanges.session_id
+                 ORDER BY id DESC LIMIT 1)
+             FROM exchanges GROUP BY session_id ORDER BY MAX(id) DESC",
+        )?;
+        let sessions = statement
+            .query_map([], |row| {
+                Ok(SessionSummary {
+                    id: row.get(0)?,
+                    exchanges: row.get(1)?,
+                    first_seen: row.get(2)?,
+                    last_seen: row.get(3)?,
+                    model: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(sessions)
+    }
+
+    /// Exchanges of `session_id` in order, `None` for an unknown session
+    pub fn transcript(&self, session_id: &str) -> Result<Option<Transcript>> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT model, created, request, reply FROM exchanges
+             WHERE session_id = ?1 ORDER BY id",
+        )?;
+        let exchanges = statement
+            .query_map([session_id], |row| {
+                let request: String = row.get(2)?;
+                let reply: Option<String> = row.get(3)?;
+                Ok(Exchange {
+                    model: row.get(0)?,
+                    created: row.get(1)?,
+                    request: serde_json::from_str(&request).unwrap_or_default(),
+                    reply: reply.and_then(|reply| serde_json::from_str(&reply).ok()),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok((!exchanges.is_empty()).then(|| Transcript {
+            id: session_id.to_string(),
+            object: "conversation".to_string(),
+            exchanges,
+        }))
+    }
+
+    /// The latest request of `session_id`, which carries the whole thread
+    pub fn latest_request(&self, session_id: &str) -> Result<Option<CopilotChatRequest>> {
+        let request: Option<String> = self
+            .connection()
+            .query_row(
+                "SELECT request FROM exchanges WHERE session_id = ?1 ORDER BY id DESC LIMIT 1",
+                [session_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        request
+            .map(|request| serde_json::from_str(&request).context("Unreadable stored request"))
+            .transpose()
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().expect("history lock poisoned")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should go forward")
+        .as_secs()
+}
+
+fn history_error(e: anyhow::Error) -> AppError {
+    error!("Conversation history error: {:#}", e);
+    AppError::InternalServerError(format!("Conversation history error: {}", e))
+}
+
+fn history(state: &AppState) -> Result<&HistoryStore, AppError> {
+    state
+        .history
+        .as_deref()
+        .ok_or_else(|| AppError::NotFound("Conversation history is disabled".to_string()))
+}
+
+/// Opt-in conversation log under `[history]`, with endpoints to browse and replay it
+pub(crate) trait ConversationHistory: CopilotIntegration {
+    /// Log the exchange of a successful `response` to `request`. Streamed replies are
+    /// logged without their content. Never fails the request: errors are only logged.
+    async fn record_exchange(
+        state: &AppState,
+        session_id: &str,
+        request: &CopilotChatRequest,
+        response: Response,
+        stream: bool,
+    ) -> Result<Response, AppError>;
+
+    async fn history_sessions(state: State<Arc<AppState>>) -> Result<Json<SessionList>, AppError>;
+
+    async fn history_transcript(
+        state: State<Arc<AppState>>,
+        session_id: Path<String>,
+    ) -> Result<Json<Transcript>, AppError>;
+
+    /// Send a session's latest request again, to `model`, and return the reply as a chat
+    /// completion. The replay is not logged, so the session stays as it was.
+    async fn history_replay(
+        state: State<Arc<AppState>>,
+        session_id: Path<String>,
+        body: Json<ReplayRequest>,
+    ) -> Result<axum::response::Response, AppError>;
+}
+
+impl ConversationHistory for Server {
+    async fn record_exchange(
+        state: &AppState,
+        session_id: &str,
+        request: &CopilotChatRequest,
+        response: Response,
+        stream: bool,
+    ) -> Result<Response, AppError> {
+        let Some(history) = state.history.as_deref() else {
+            return Ok(response);
+        };
+        if !response.status().is_success() {
+            return Ok(response);
+        }
+
+        let (response, reply) = if stream {
+            (response, None)
+        } else {
+            let (response, body) = buffered(response).await?;
+            let reply = serde_json::from_slice::<CopilotChatResponse>(&body)
+                .ok()
+                .and_then(|reply| reply.choices.into_iter().next())
+                .map(|choice| choice.message);
+            (response, reply)
+        };
+
+        if let Err(e) = history.record(session_id, request, reply.as_ref()) {
+            warn!("Failed to log exchange of session {}: {:#}", session_id, e);
+        }
+        Ok(response)
+    }
+
+    async fn history_sessions(
+        State(state): State<Arc<AppState>>,
+    ) -> Result<Json<SessionList>, AppError> {
+        info!("Received history sessions request");
+        let sessions = history(&state)?.sessions().map_err(history_error)?;
+        Ok(Json(SessionList {
+            object: "list".to_string(),
+            data: sessions,
+        }))
+    }
+
+    async fn history_transcript(
+        State(state): State<Arc<AppState>>,
+        Path(session_id): Path<String>,
+    ) -> Result<Json<Transcript>, AppError> {
+        info!("Received history transcript request for {}", session_id);
+        history(&state)?
+            .transcript(&session_id)
+            .map_err(history_error)?
+            .map(Json)
+            .ok_or_else(|| AppError::NotFound(format!("No such session: {}", session_id)))
+    }
+
+    async fn history_replay(
+        State(state): State<Arc<AppState>>,
+        Path(session_id): Path<String>,
+        Json(body): Json<ReplayRequest>,
+    ) -> Result<axum::response::Response, AppError> {
+        let config = state.config();
+        let mut request = history(&state)?
+            .latest_request(&session_id)
+            .map_err(history_error)?
+            .ok_or_else(|| AppError::NotFound(format!("No such session: {}", session_id)))?;
+        info!(
+            "Replaying session {} (model {}) against {}",
+            session_id, request.model, body.model
+        );
+        request.model = body.model;
+        request.stream = Some(false);
+        admit_user(&state, request.user.as_deref())?;
+
+        let token = Self::get_token(state.clone()).await?;
+        Self::resolve_model(state.clone(), &mut request).await?;
+        let adjustments = Self::adapt_to_model(state.clone(), &mut request).await;
+
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+        let replay_session = format!("replay-{}", Uuid::new_v4());
+        let response = Self::forward_prompt(
+            state.clone(),
+            token,
+            copilot_url,
+            &request,
+            &replay_session,
+            false,
+        )
+        .await?;
+        if !response.status().is_success() {
+            return Self::handle_errors(response).await;
+        }
+
+        let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse Copilot response: {}", e);
+            AppError::upstream("Failed to parse Copilot response", e)
+        })?;
+        Ok(with_adjustments_header(
+            openai_chat_response(
+                copilot_response,
+                ToolCallCheck::default(),
+                config.copilot.reasoning.output,
+            ),
+            &adjustments,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::completion::models::OpenAIChatRequest;
+
+    fn request(model: &str, content: &str) -> CopilotChatRequest {
+        let request: OpenAIChatRequest = serde_json::from_value(serde_json::json!({
+            "model": model,
+            "messages": [{"role": "user", "content": content}]
+        }))
+        .unwrap();
+        request.into()
+    }
+
+    fn reply(content: &str) -> CopilotMessage {
+        serde_json::from_value(serde_json::json!({"role": "assistant", "content": content}))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_record_and_read_back() {
+        let store = HistoryStore::new(Connection::open_in_memory().unwrap()).unwrap();
+        store
+            .record("a", &request("gpt-4o", "Hi"), Some(&reply("Hello")))
+            .unwrap();
+        store
+            .record("b", &request("gpt-4o", "Ping"), Some(&reply("Pong")))
+            .unwrap();
+        store
+            .record("a", &request("claude-sonnet-4", "And?"), None)
+            .unwrap();
+
+        let sessions = store.sessions().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, "a");
+        assert_eq!(sessions[0].model, "claude-sonnet-4");
+        assert_eq!(sessions[0].exchanges, 2);
+        assert_eq!(sessions[1].id, "b");
+
+        let transcript = store.transcript("a").unwrap().unwrap();
+        assert_eq!(transcript.exchanges.len(), 2);
+        assert_eq!(transcript.exchanges[0].model, "gpt-4o");
+        assert_eq!(
+            transcript.exchanges[0].reply.as_ref().unwrap()["content"],
+            "Hello"
+        );
+        assert!(transcript.exchanges[1].reply.is_none());
+        assert!(store.transcript("c").unwrap().is_none());
+
+        let latest = store.latest_request("a").unwrap().unwrap();
+        assert_eq!(latest.model, "claude-sonnet-4");
+        assert_eq!(
+            latest.messages[0].content.as_ref().unwrap().to_text(),
+            "And?"
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use crate::config::Config;
+use crate::copilot::models::ModelFilter;
+use crate::openai::completion::models::OpenAIChatRequest;
+use crate::server::openai::chat_completion::CoPilotChatCompletions;
+use crate::server::openai::list_models::CoPilotListModels;
+use crate::server::{AppError, AppState, Server};
+use anyhow::Result;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::log::{debug, info, warn};
+use uuid::Uuid;
+
+/// MCP revision implemented, sent back when the host asks for another
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Open `/mcp/sse` streams by session id, fed by `POST /mcp/messages`
+#[derive(Default)]
+pub struct McpSessions {
+    streams: Mutex<HashMap<String, mpsc::Sender<Value>>>,
+}
+
+impl McpSessions {
+    fn open(&self) -> (String, mpsc::Receiver<Value>) {
+        let id = Uuid::new_v4().to_string();
+        let (sender, receiver) = mpsc::channel(32);
+        self.streams
+            .lock()
+            .expect("MCP sessions lock poisoned")
+            .insert(id.clone(), sender);
+        (id, receiver)
+    }
+
+    fn sender(&self, id: &str) -> Option<mpsc::Sender<Value>> {
+        let mut streams = self.streams.lock().expect("MCP sessions lock poisoned");
+        // Streams whose host disconnected are dropped on the next lookup
+        streams.retain(|_, sender| !sender.is_closed());
+        streams.get(id).cloned()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct McpMessageQuery {
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+}
+
+/// The tools advertised by `tools/list`
+fn tools() -> Value {
+    json!([
+        {
+            "name": "ask_copilot",
+            "description": "Ask a GitHub Copilot model a question and return its answer.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": {"type": "string", "description": "The question or task"},
+                    "model": {"type": "string", "description": "Copilot model id, see list_models"},
+                    "system": {"type": "string", "description": "Optional system prompt"}
+                },
+                "required": ["prompt"]
+            }
+        },
+        {
+            "name": "list_models",
+            "description": "List the GitHub Copilot models ask_copilot can use.",
+            "inputSchema": {"type": "object", "properties": {}}
+        }
+    ])
+}
+
+fn text_result(text: String, is_error: bool) -> Value {
+    json!({"content": [{"type": "text", "text": text}], "isError": is_error})
+}
+
+/// Model Context Protocol server exposing Copilot chat as tools, over stdio
+/// (`passenger-rs mcp`) or SSE (`[mcp] enabled`)
+pub(crate) trait McpServer: CoPilotChatCompletions + CoPilotListModels {
+    /// The response to one JSON-RPC message, `None` for notifications
+    async fn mcp_message(state: Arc<AppState>, message: Value) -> Option<Value>;
+
+    /// The result of a `tools/call`, with tool failures reported in `isError`
+    async fn mcp_tool_call(state: Arc<AppState>, params: &Value) -> Result<Value, String>;
+
+    /// `GET /mcp/sse`: the event stream responses are sent on
+    async fn mcp_sse(state: State<Arc<AppState>>) -> Response;
+
+    /// `POST /mcp/messages?sessionId=...`: a message answered on its session's stream
+    async fn mcp_post(
+        state: State<Arc<AppState>>,
+        query: Query<McpMessageQuery>,
+        message: Json<Value>,
+    ) -> Result<StatusCode, AppError>;
+}
+
+impl McpServer for Server {
+    async fn mcp_message(state: Arc<AppState>, message: Value) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let method = message["method"].as_str().unwrap_or_default();
+        debug!("MCP message: {}", method);
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": {"tools": {}},
+                "serverInfo": {"name": "passenger-rs", "version": env!("CARGO_PKG_VERSION")}
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({"tools": tools()})),
+            "tools/call" => Self::mcp_tool_call(state, &message["params"])
+                .await
+                .map_err(|message| json!({"code": -32602, "message": message})),
+            method if method.starts_with("notifications/") => return None,
+            method => {
+                Err(json!({"code": -32601, "message": format!("Unknown method: {}", method)}))
+            }
+        };
+
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+        })
+    }
+
+    async fn mcp_tool_call(state: Arc<AppState>, params: &Value) -> Result<Value, String> {
+        let arguments = &params["arguments"];
+        match params["name"].as_str() {
+            Some("ask_copilot") => {
+                let prompt = arguments["prompt"]
+                    .as_str()
+                    .ok_or("ask_copilot needs a prompt")?;
+                let config = state.config();
+                let model = arguments["model"]
+                    .as_str()
+                    .unwrap_or(&config.mcp.default_model);
+                info!("MCP ask_copilot with model {}", model);
+
+                let mut messages = Vec::new();
+                if let Some(system) = arguments["system"].as_str() {
+                    messages.push(json!({"role": "system", "content": system}));
+                }
+                messages.push(json!({"role": "user", "content": prompt}));
+                let request: OpenAIChatRequest =
+                    serde_json::from_value(json!({"model": model, "messages": messages}))
+                        .map_err(|e| e.to_string())?;
+
+                let response =
+                    match Self::chat_completions(State(state), HeaderMap::new(), Json(request))
+                        .await
+                    {
+                        Ok(response) => response,
+                        Err(e) => e.into_response(),
+                    };
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+
+                Ok(if status.is_success() {
+                    let answer = body["choices"][0]["message"]["content"]
+                        .as_str()
+                        .unwrap_or_default();
+                    text_result(answer.to_string(), false)
+                } else {
+                    let message = body["error"]["message"].as_str().unwrap_or("unknown error");
+                    text_result(
+                        format!("Copilot request failed ({}): {}", status, message),
+                        true,
+                    )
+                })
+            }
+            Some("list_models") => Ok(
+                match Self::list_models(State(state), Query(ModelFilter::default())).await {
+                    Ok(Json(models)) => {
+                        let ids: Vec<String> =
+                            models.data.into_iter().map(|model| model.id).collect();
+                        text_result(ids.join("\n"), false)
+                    }
+                    Err(e) => {
+                        let response = e.into_response();
+                        text_result(
+                            format!("Failed to list models ({})", response.status()),
+                            true,
+                        )
+                    }
+                },
+            ),
+            Some(name) => Err(format!("Unknown tool: {}", name)),
+            None => Err("tools/call needs a tool name".to_string()),
+        }
+    }
+
+    async fn mcp_sse(State(state): State<Arc<AppState>>) -> Response {
+        let (session_id, receiver) = state.mcp_sessions.open();
+        info!("MCP session {} connected", session_id);
+
+        let endpoint = Event::default()
+            .event("endpoint")
+            .data(format!("/mcp/messages?sessionId={}", session_id));
+        let messages = receiver_stream(receiver).map(|message| {
+            Ok::<_, Infallible>(Event::default().event("message").data(message.to_string()))
+        });
+        let events = futures_util::stream::once(async { Ok(endpoint) }).chain(messages);
+        Sse::new(events).into_response()
+    }
+
+    async fn mcp_post(
+        State(state): State<Arc<AppState>>,
+        Query(query): Query<McpMessageQuery>,
+        Json(message): Json<Value>,
+    ) -> Result<StatusCode, AppError> {
+        let sender = state
+            .mcp_sessions
+            .sender(&query.session_id)
+            .ok_or_else(|| {
+                AppError::NotFound(format!("No such MCP session: {}", query.session_id))
+            })?;
+
+        tokio::spawn(async move {
+            if let Some(response) = Self::mcp_message(state, message).await
+                && sender.send(response).await.is_err()
+            {
+                warn!("MCP session closed before its response was sent");
+            }
+        });
+        Ok(StatusCode::ACCEPTED)
+    }
+}
+
+fn receiver_stream(mut receiver: mpsc::Receiver<Value>) -> impl futures_util::Stream<Item = Value> {
+    futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+}
+
+/// Serve MCP on stdin/stdout, one JSON-RPC message per line, until end of input
+pub async fn serve_stdio(config: &Config) -> Result<()> {
+    let state = Arc::new(AppState::new(config, None));
+    let (sender, mut receiver) = mpsc::channel::<Value>(32);
+
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = receiver.recv().await {
+            stdout
+                .write_all(format!("{}\n", message).as_bytes())
+                .await?;
+            stdout.flush().await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                let error = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32700, "message": e.to_string()}
+                });
+                sender.send(error).await?;
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Some(response) = Server::mcp_message(state, message).await {
+                let _ = sender.send(response).await;
+            }
+        });
+    }
+
+    drop(sender);
+    writer.await??;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Arc<AppState> {
+        Arc::new(AppState::new(&Config::default(), None))
+    }
+
+    #[tokio::test]
+    async fn test_initialize_and_list_tools() {
+        let response = Server::mcp_message(
+            state(),
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["result"]["protocolVersion"], PROTOCOL_VERSION);
+
+        let response = Server::mcp_message(
+            state(),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"}),
+        )
+        .await
+        .unwrap();
+        let names: Vec<&str> = response["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["ask_copilot", "list_models"]);
+
+        let notification = Server::mcp_message(
+            state(),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        )
+        .await;
+        assert!(notification.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_tool_calls() {
+        let response = Server::mcp_message(
+            state(),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "tools/call",
+                   "params": {"name": "ask_copilot", "arguments": {}}}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["error"]["message"], "ask_copilot needs a prompt");
+
+        let response = Server::mcp_message(
+            state(),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "tools/call",
+                   "params": {"name": "rm_rf"}}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response["error"]["message"], "Unknown tool: rm_rf");
+    }
+
+    #[tokio::test]
+    async fn test_messages_need_an_open_session() {
+        let sessions = McpSessions::default();
+        let (id, receiver) = sessions.open();
+        assert!(sessions.sender(&id).is_some());
+
+        drop(receiver);
+        assert!(sessions.sender(&id).is_none());
+        assert!(sessions.sender("unknown").is_none());
+    }
+}
@@ -0,0 +1,395 @@
+use crate::config::{McpConfig, McpServerConfig};
+use crate::openai::completion::models::{FunctionDefinition, Tool};
+use anyhow::{Context, Result, anyhow};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{OnceCell, oneshot};
+use tracing::log::{debug, info, warn};
+
+/// How long an MCP server has to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>;
+
+/// A running MCP server, spoken to over its stdin/stdout
+pub struct McpClient {
+    name: String,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    next_id: AtomicU64,
+    /// Killed when the client is dropped
+    _child: Child,
+}
+
+impl McpClient {
+    /// Start the server and complete the MCP handshake
+    pub async fn spawn(name: &str, config: &McpServerConfig) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start MCP server {}", name))?;
+
+        let stdin = Arc::new(tokio::sync::Mutex::new(
+            child.stdin.take().context("MCP server stdin")?,
+        ));
+        let stdout = child.stdout.take().context("MCP server stdout")?;
+        let pending: Pending = Arc::default();
+        tokio::spawn(read_responses(
+            name.to_string(),
+            BufReader::new(stdout),
+            stdin.clone(),
+            pending.clone(),
+        ));
+
+        let client = Self {
+            name: name.to_string(),
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+            _child: child,
+        };
+        client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": "2024-11-05",
+                    "capabilities": {},
+                    "clientInfo": {"name": "passenger-rs", "version": env!("CARGO_PKG_VERSION")}
+                }),
+            )
+            .await?;
+        client
+            .send(json!({"jsonrpc": "2.0", "method": "notifications/initialized"}))
+            .await?;
+        Ok(client)
+    }
+
+    async fn send(&self, message: Value) -> Result<()> {
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(format!("{}\n", message).as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("MCP pending lock poisoned")
+            .insert(id, sender);
+
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))
+            .await?;
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, receiver)
+            .await
+            .map_err(|_| anyhow!("MCP server {} did not answer {}", self.name, method))?
+            .map_err(|_| anyhow!("MCP server {} exited", self.name))?;
+        response.map_err(|e| anyhow!("MCP server {} failed {}: {}", self.name, method, e))
+    }
+
+    /// Every tool the server offers, following `nextCursor` pages
+    pub async fn list_tools(&self) -> Result<Vec<Value>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({"cursor": cursor}),
+                None => json!({}),
+            };
+            let mut page = self.request("tools/list", params).await?;
+            if let Some(page_tools) = page["tools"].as_array_mut() {
+                tools.append(page_tools);
+            }
+            cursor = page["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// The text content of a tool's result; tool errors are returned as text for the model
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let result = self
+            .request("tools/call", json!({"name": name, "arguments": arguments}))
+            .await?;
+        let text = result["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|content| match content["type"].as_str() {
+                Some("text") => content["text"].as_str().map(str::to_string),
+                _ => Some(content.to_string()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(if result["isError"].as_bool().unwrap_or(false) {
+            format!("Error: {}", text)
+        } else {
+            text
+        })
+    }
+}
+
+/// Route the server's responses to their requests, declining requests it makes of us
+async fn read_responses(
+    name: String,
+    stdout: BufReader<tokio::process::ChildStdout>,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+) {
+    let mut lines = stdout.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            debug!("Ignoring non-JSON output of MCP server {}: {}", name, line);
+            continue;
+        };
+
+        if message.get("method").is_some() {
+            if let Some(id) = message.get("id") {
+                let decline = json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {"code": -32601, "message": "Not supported by passenger-rs"}
+                });
+                let mut stdin = stdin.lock().await;
+                let _ = stdin.write_all(format!("{}\n", decline).as_bytes()).await;
+                let _ = stdin.flush().await;
+            }
+            continue;
+        }
+
+        let Some(id) = message["id"].as_u64() else {
+            continue;
+        };
+        let Some(sender) = pending
+            .lock()
+            .expect("MCP pending lock poisoned")
+            .remove(&id)
+        else {
+            continue;
+        };
+        let result = match message.get("error") {
+            Some(error) => Err(error["message"]
+                .as_str()
+                .unwrap_or("unknown error")
+                .to_string()),
+            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        let _ = sender.send(result);
+    }
+    warn!("MCP server {} closed its output", name);
+}
+
+/// A tool of an `[mcp.servers]` entry, as offered to models
+pub struct ServerTool {
+    pub definition: Tool,
+    server: String,
+    /// The tool's name on its server
+    tool: String,
+}
+
+struct Toolset {
+    clients: HashMap<String, McpClient>,
+    tools: Vec<ServerTool>,
+}
+
+/// The tools of every `[mcp.servers]` entry, whose servers start on first use
+#[derive(Default)]
+pub struct McpToolbox {
+    servers: HashMap<String, McpServerConfig>,
+    toolset: OnceCell<Toolset>,
+}
+
+impl McpToolbox {
+    pub fn from_config(config: &McpConfig) -> Self {
+        Self {
+            servers: config.servers.clone(),
+            toolset: OnceCell::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    async fn toolset(&self) -> &Toolset {
+        self.toolset
+            .get_or_init(|| async {
+                let mut clients = HashMap::new();
+                let mut tools = Vec::new();
+                for (name, config) in &self.servers {
+                    let started = async {
+                        let client = McpClient::spawn(name, config).await?;
+                        let listed = client.list_tools().await?;
+                        anyhow::Ok((client, listed))
+                    };
+                    match started.await {
+                        Ok((client, listed)) => {
+                            info!("MCP server {} offers {} tools", name, listed.len());
+                            tools.extend(listed.iter().filter_map(|tool| server_tool(name, tool)));
+                            clients.insert(name.clone(), client);
+                        }
+                        Err(e) => warn!("MCP server {} is unavailable: {:#}", name, e),
+                    }
+                }
+                Toolset { clients, tools }
+            })
+            .await
+    }
+
+    /// Tools of the servers that started
+    pub async fn tools(&self) -> &[ServerTool] {
+        &self.toolset().await.tools
+    }
+
+    /// Whether `name` is one of [`McpToolbox::tools`]
+    pub async fn owns(&self, name: &str) -> bool {
+        self.tools()
+            .await
+            .iter()
+            .any(|tool| tool.definition.function.name == name)
+    }
+
+    /// Run the tool offered as `name`; failures are returned as text for the model
+    pub async fn call(&self, name: &str, arguments: &str) -> String {
+        let toolset = self.toolset().await;
+        let Some(tool) = toolset
+            .tools
+            .iter()
+            .find(|tool| tool.definition.function.name == name)
+        else {
+            return format!("Error: unknown tool {}", name);
+        };
+        let Some(client) = toolset.clients.get(&tool.server) else {
+            return format!("Error: MCP server {} is unavailable", tool.server);
+        };
+
+        let arguments = match serde_json::from_str::<Value>(arguments) {
+            Ok(arguments) => arguments,
+            Err(e) => return format!("Error: invalid arguments: {}", e),
+        };
+        info!("Calling tool {} of MCP server {}", tool.tool, tool.server);
+        match client.call_tool(&tool.tool, arguments).await {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("Tool {} failed: {:#}", name, e);
+                format!("Error: {:#}", e)
+            }
+        }
+    }
+}
+
+/// `tool` of `server`, named `<server>__<tool>` so tools of different servers cannot clash
+fn server_tool(server: &str, tool: &Value) -> Option<ServerTool> {
+    let name = tool["name"].as_str()?;
+    let qualified: String = format!("{}__{}", server, name)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect();
+
+    Some(ServerTool {
+        definition: Tool {
+            tool_type: "function".to_string(),
+            function: FunctionDefinition {
+                name: qualified,
+                description: tool["description"].as_str().map(str::to_string),
+                parameters: tool
+                    .get("inputSchema")
+                    .cloned()
+                    .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+            },
+        },
+        server: server.to_string(),
+        tool: name.to_string(),
+    })
+}
+
+#[cfg(all(test, unix))]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A scripted MCP server offering `get_weather`, answering requests in a fixed order
+    pub(crate) fn weather_server() -> McpServerConfig {
+        let script = r#"
+            read line; echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{}}}'
+            read line
+            read line; echo '{"jsonrpc":"2.0","id":2,"result":{"tools":[{"name":"get_weather","description":"Current weather","inputSchema":{"type":"object","properties":{"city":{"type":"string"}}}}]}}'
+            read line; echo '{"jsonrpc":"2.0","id":3,"result":{"content":[{"type":"text","text":"72F and sunny"}]}}'
+            read line
+        "#;
+        McpServerConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_toolbox_lists_and_calls_tools() {
+        let config = McpConfig {
+            servers: HashMap::from([("weather".to_string(), weather_server())]),
+            ..McpConfig::default()
+        };
+        let toolbox = McpToolbox::from_config(&config);
+
+        let tools = toolbox.tools().await;
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].definition.function.name, "weather__get_weather");
+        assert_eq!(
+            tools[0].definition.function.description.as_deref(),
+            Some("Current weather")
+        );
+        assert!(toolbox.owns("weather__get_weather").await);
+        assert!(!toolbox.owns("get_weather").await);
+
+        assert_eq!(
+            toolbox
+                .call("weather__get_weather", r#"{"city": "SF"}"#)
+                .await,
+            "72F and sunny"
+        );
+        assert!(
+            toolbox
+                .call("weather__get_weather", "{")
+                .await
+                .starts_with("Error")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_servers_are_skipped() {
+        let config = McpConfig {
+            servers: HashMap::from([(
+                "missing".to_string(),
+                McpServerConfig {
+                    command: "/nonexistent/mcp-server".to_string(),
+                    args: vec![],
+                    env: HashMap::new(),
+                },
+            )]),
+            ..McpConfig::default()
+        };
+        let toolbox = McpToolbox::from_config(&config);
+        assert!(!toolbox.is_empty());
+        assert!(toolbox.tools().await.is_empty());
+    }
+}
@@ -0,0 +1,578 @@
+#[cfg(feature = "metrics")]
+use crate::server::Server;
+use crate::server::{AppState, client_ip};
+use crate::utc::UtcTime;
+use axum::body::HttpBody as _;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::StatusCode;
+#[cfg(feature = "metrics")]
+use axum::http::header;
+use axum::middleware::Next;
+#[cfg(feature = "metrics")]
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TIME_TO_FIRST_TOKEN_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0];
+const STREAM_DURATION_BUCKETS: &[f64] = &[1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+const TOKENS_PER_SECOND_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 20.0, 40.0, 80.0, 160.0, 320.0];
+
+/// Failed requests kept for the admin dashboard
+const RECENT_ERRORS: usize = 20;
+/// Error bodies larger than this are not read for their message
+const ERROR_BODY_LIMIT: u64 = 64 * 1024;
+/// Longest error message kept
+const ERROR_MESSAGE_CHARS: usize = 300;
+
+/// Process-wide counters, rendered in Prometheus text format on `/metrics`
+#[derive(Debug, Default)]
+pub struct Metrics {
+    streams_completed: Mutex<BTreeMap<&'static str, u64>>,
+    streams_cancelled: Mutex<BTreeMap<&'static str, u64>>,
+    stream_timings: Mutex<BTreeMap<String, StreamTimings>>,
+    stream_tokens: Mutex<BTreeMap<String, u64>>,
+    responses: Mutex<BTreeMap<u16, u64>>,
+    recent_errors: Mutex<VecDeque<RecentError>>,
+}
+
+/// A request answered with a 4xx or 5xx status
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentError {
+    pub time: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub message: String,
+    /// The client's address, past any `[server] trusted_proxies`
+    pub client: Option<String>,
+}
+
+/// Point-in-time copy of the counters, for the admin dashboard
+#[derive(Debug, Serialize)]
+pub struct MetricsSnapshot {
+    /// Responses by HTTP status
+    pub responses: BTreeMap<u16, u64>,
+    pub streams_completed: BTreeMap<&'static str, u64>,
+    pub streams_cancelled: BTreeMap<&'static str, u64>,
+    /// Tokens generated in completed streams, by model
+    pub stream_tokens: BTreeMap<String, u64>,
+    /// Most recent first
+    pub recent_errors: Vec<RecentError>,
+}
+
+/// Cumulative Prometheus histogram with fixed upper bounds
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Streaming performance histograms for a single model
+#[derive(Debug)]
+struct StreamTimings {
+    time_to_first_token: Histogram,
+    duration: Histogram,
+    tokens_per_second: Histogram,
+}
+
+impl Default for StreamTimings {
+    fn default() -> Self {
+        Self {
+            time_to_first_token: Histogram::new(TIME_TO_FIRST_TOKEN_BUCKETS),
+            duration: Histogram::new(STREAM_DURATION_BUCKETS),
+            tokens_per_second: Histogram::new(TOKENS_PER_SECOND_BUCKETS),
+        }
+    }
+}
+
+impl Metrics {
+    /// An upstream stream was relayed to the client in full (or ended on an upstream error)
+    pub fn record_stream_completed(&self, endpoint: &'static str) {
+        Self::increment(&self.streams_completed, endpoint);
+    }
+
+    /// The client went away mid-stream and the upstream request was aborted
+    pub fn record_stream_cancelled(&self, endpoint: &'static str) {
+        Self::increment(&self.streams_cancelled, endpoint);
+    }
+
+    /// Timings of a stream that ran to completion. Throughput is measured from the
+    /// first token, so it is not skewed by how long Copilot took to start answering.
+    pub fn record_stream_timings(
+        &self,
+        model: &str,
+        time_to_first_token: Option<Duration>,
+        duration: Duration,
+        tokens: u64,
+    ) {
+        *self
+            .stream_tokens
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(model.to_string())
+            .or_default() += tokens;
+
+        let mut timings = self.stream_timings.lock().expect("metrics lock poisoned");
+        let timings = timings.entry(model.to_string()).or_default();
+
+        timings.duration.observe(duration.as_secs_f64());
+
+        if let Some(ttft) = time_to_first_token {
+            timings.time_to_first_token.observe(ttft.as_secs_f64());
+        }
+
+        if let Some(tokens_per_second) = tokens_per_second(time_to_first_token, duration, tokens) {
+            timings.tokens_per_second.observe(tokens_per_second);
+        }
+    }
+
+    /// A response with `status` was sent
+    pub fn record_response(&self, status: u16) {
+        *self
+            .responses
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(status)
+            .or_default() += 1;
+    }
+
+    /// Keep `error` among the most recent failures, dropping the oldest
+    pub fn record_error(&self, error: RecentError) {
+        let mut errors = self.recent_errors.lock().expect("metrics lock poisoned");
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_back();
+        }
+        errors.push_front(error);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            responses: self
+                .responses
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
+            streams_completed: self
+                .streams_completed
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
+            streams_cancelled: self
+                .streams_cancelled
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
+            stream_tokens: self
+                .stream_tokens
+                .lock()
+                .expect("metrics lock poisoned")
+                .clone(),
+            recent_errors: self
+                .recent_errors
+                .lock()
+                .expect("metrics lock poisoned")
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    #[allow(unused)]
+    pub fn streams_cancelled(&self, endpoint: &str) -> u64 {
+        Self::get(&self.streams_cancelled, endpoint)
+    }
+
+    #[allow(unused)]
+    pub fn streams_completed(&self, endpoint: &str) -> u64 {
+        Self::get(&self.streams_completed, endpoint)
+    }
+
+    fn increment(counters: &Mutex<BTreeMap<&'static str, u64>>, endpoint: &'static str) {
+        *counters
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(endpoint)
+            .or_default() += 1;
+    }
+
+    #[allow(unused)]
+    fn get(counters: &Mutex<BTreeMap<&'static str, u64>>, endpoint: &str) -> u64 {
+        counters
+            .lock()
+            .expect("metrics lock poisoned")
+            .get(endpoint)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP passenger_http_responses_total HTTP responses sent, by status"
+        );
+        let _ = writeln!(out, "# TYPE passenger_http_responses_total counter");
+        for (status, value) in self.responses.lock().expect("metrics lock poisoned").iter() {
+            let _ = writeln!(
+                out,
+                "passenger_http_responses_total{{status=\"{}\"}} {}",
+                status, value
+            );
+        }
+
+        Self::render_counter(
+            &mut out,
+            "passenger_streams_completed_total",
+            "Streaming responses relayed until the upstream stream ended",
+            &self.streams_completed,
+        );
+        Self::render_counter(
+            &mut out,
+            "passenger_streams_cancelled_total",
+            "Streaming responses aborted because the client disconnected",
+            &self.streams_cancelled,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP passenger_stream_tokens_total Tokens generated in completed streams"
+        );
+        let _ = writeln!(out, "# TYPE passenger_stream_tokens_total counter");
+        for (model, value) in self
+            .stream_tokens
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "passenger_stream_tokens_total{{model=\"{}\"}} {}",
+                escape_label(model),
+                value
+            );
+        }
+
+        let timings = self.stream_timings.lock().expect("metrics lock poisoned");
+        Self::render_histogram(
+            &mut out,
+            "passenger_stream_time_to_first_token_seconds",
+            "Time from forwarding a streaming request to its first generated token",
+            timings
+                .iter()
+                .map(|(model, t)| (model.as_str(), &t.time_to_first_token)),
+        );
+        Self::render_histogram(
+            &mut out,
+            "passenger_stream_duration_seconds",
+            "Time from forwarding a streaming request until the stream completed",
+            timings
+                .iter()
+                .map(|(model, t)| (model.as_str(), &t.duration)),
+        );
+        Self::render_histogram(
+            &mut out,
+            "passenger_stream_tokens_per_second",
+            "Generated tokens per second after the first token",
+            timings
+                .iter()
+                .map(|(model, t)| (model.as_str(), &t.tokens_per_second)),
+        );
+
+        out
+    }
+
+    fn render_histogram<'a>(
+        out: &mut String,
+        name: &str,
+        help: &str,
+        histograms: impl Iterator<Item = (&'a str, &'a Histogram)>,
+    ) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (model, histogram) in histograms {
+            let model = escape_label(model);
+            for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{model=\"{}\",le=\"{}\"}} {}",
+                    name, model, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{model=\"{}\",le=\"+Inf\"}} {}",
+                name, model, histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{{model=\"{}\"}} {}", name, model, histogram.sum);
+            let _ = writeln!(
+                out,
+                "{}_count{{model=\"{}\"}} {}",
+                name, model, histogram.count
+            );
+        }
+    }
+
+    fn render_counter(
+        out: &mut String,
+        name: &str,
+        help: &str,
+        counters: &Mutex<BTreeMap<&'static str, u64>>,
+    ) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (endpoint, value) in counters.lock().expect("metrics lock poisoned").iter() {
+            let _ = writeln!(out, "{}{{endpoint=\"{}\"}} {}", name, endpoint, value);
+        }
+    }
+}
+
+/// Tokens per second between the first token and the end of the stream, if measurable
+pub(crate) fn tokens_per_second(
+    time_to_first_token: Option<Duration>,
+    duration: Duration,
+    tokens: u64,
+) -> Option<f64> {
+    let generating = duration.checked_sub(time_to_first_token?)?.as_secs_f64();
+    if tokens < 2 || generating <= 0.0 {
+        return None;
+    }
+    // The first token arrives at the start of the window
+    Some((tokens - 1) as f64 / generating)
+}
+
+/// Escape a Prometheus label value
+pub(crate) fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Middleware counting responses by status and keeping recent errors with their message
+pub(crate) async fn record_responses(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    // Unrouted requests, such as those for endpoints off under `[endpoints]`, are no errors
+    let routed = request.extensions().get::<MatchedPath>().is_some();
+    let response = next.run(request).await;
+    let status = response.status();
+    state.metrics.record_response(status.as_u16());
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+    if !routed && status == StatusCode::NOT_FOUND {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let readable = body
+        .size_hint()
+        .upper()
+        .is_some_and(|size| size <= ERROR_BODY_LIMIT);
+    let (body, message) = if readable {
+        match axum::body::to_bytes(body, ERROR_BODY_LIMIT as usize).await {
+            Ok(bytes) => {
+                let message = error_message(&bytes);
+                (axum::body::Body::from(bytes), message)
+            }
+            Err(_) => (axum::body::Body::empty(), String::new()),
+        }
+    } else {
+        (body, String::new())
+    };
+
+    state.metrics.record_error(RecentError {
+        time: UtcTime::now().to_rfc3339(),
+        method,
+        path,
+        status: status.as_u16(),
+        message,
+        client: client_ip::current().map(|ip| ip.to_string()),
+    });
+    Response::from_parts(parts, body)
+}
+
+/// The `error.message` of an OpenAI-style error body, or the body itself, shortened
+fn error_message(body: &[u8]) -> String {
+    let message = serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|json| {
+            json.pointer("/error/message")
+                .or_else(|| json.get("error"))
+                .and_then(|message| message.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| String::from_utf8_lossy(body).into_owned());
+    message.trim().chars().take(ERROR_MESSAGE_CHARS).collect()
+}
+
+#[cfg(feature = "metrics")]
+#[allow(async_fn_in_trait)]
+pub trait MetricsEndpoint {
+    async fn metrics(state: State<Arc<AppState>>) -> Response;
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsEndpoint for Server {
+    async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+        (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            state.metrics.render() + &state.premium.render(),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters() {
+        let metrics = Metrics::default();
+        metrics.record_stream_cancelled("chat_completions");
+        metrics.record_stream_cancelled("chat_completions");
+        metrics.record_stream_completed("ollama_chat");
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("# TYPE passenger_streams_cancelled_total counter"));
+        assert!(
+            rendered.contains("passenger_streams_cancelled_total{endpoint=\"chat_completions\"} 2")
+        );
+        assert!(rendered.contains("passenger_streams_completed_total{endpoint=\"ollama_chat\"} 1"));
+    }
+
+    #[test]
+    fn test_render_stream_timing_histograms() {
+        let metrics = Metrics::default();
+        metrics.record_stream_timings(
+            "gpt-4o",
+            Some(Duration::from_millis(400)),
+            Duration::from_millis(2400),
+            41,
+        );
+        metrics.record_stream_timings("gpt-4o", None, Duration::from_secs(3), 0);
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("# TYPE passenger_stream_time_to_first_token_seconds histogram"));
+        assert!(rendered.contains(
+            "passenger_stream_time_to_first_token_seconds_bucket{model=\"gpt-4o\",le=\"0.25\"} 0"
+        ));
+        assert!(rendered.contains(
+            "passenger_stream_time_to_first_token_seconds_bucket{model=\"gpt-4o\",le=\"0.5\"} 1"
+        ));
+        assert!(
+            rendered
+                .contains("passenger_stream_time_to_first_token_seconds_count{model=\"gpt-4o\"} 1")
+        );
+        assert!(
+            rendered.contains(
+                "passenger_stream_duration_seconds_bucket{model=\"gpt-4o\",le=\"2.5\"} 1"
+            )
+        );
+        assert!(
+            rendered.contains(
+                "passenger_stream_duration_seconds_bucket{model=\"gpt-4o\",le=\"+Inf\"} 2"
+            )
+        );
+        assert!(rendered.contains("passenger_stream_duration_seconds_sum{model=\"gpt-4o\"} 5.4"));
+        // 40 tokens after the first over two seconds
+        assert!(
+            rendered.contains(
+                "passenger_stream_tokens_per_second_bucket{model=\"gpt-4o\",le=\"20\"} 1"
+            )
+        );
+        assert!(
+            rendered.contains(
+                "passenger_stream_tokens_per_second_bucket{model=\"gpt-4o\",le=\"10\"} 0"
+            )
+        );
+    }
+
+    #[test]
+    fn test_recent_errors_keep_the_latest() {
+        let metrics = Metrics::default();
+        for status in 0..RECENT_ERRORS as u16 + 5 {
+            metrics.record_response(500);
+            metrics.record_error(RecentError {
+                time: String::new(),
+                method: "POST".to_string(),
+                path: "/v1/chat/completions".to_string(),
+                status,
+                message: String::new(),
+                client: None,
+            });
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.responses[&500], RECENT_ERRORS as u64 + 5);
+        assert_eq!(snapshot.recent_errors.len(), RECENT_ERRORS);
+        assert_eq!(snapshot.recent_errors[0].status, RECENT_ERRORS as u16 + 4);
+        assert!(
+            metrics
+                .render()
+                .contains("passenger_http_responses_total{status=\"500\"} 25")
+        );
+    }
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            error_message(br#"{"error":{"message":"Model not found","type":"x"}}"#),
+            "Model not found"
+        );
+        assert_eq!(error_message(br#"{"error":"bad"}"#), "bad");
+        assert_eq!(
+            error_message(b"Invalid admin token\n"),
+            "Invalid admin token"
+        );
+    }
+
+    #[test]
+    fn test_tokens_per_second() {
+        let ttft = Some(Duration::from_secs(1));
+
+        assert_eq!(
+            tokens_per_second(ttft, Duration::from_secs(3), 21),
+            Some(10.0)
+        );
+        assert_eq!(tokens_per_second(ttft, Duration::from_secs(3), 1), None);
+        assert_eq!(tokens_per_second(None, Duration::from_secs(3), 21), None);
+        assert_eq!(tokens_per_second(ttft, Duration::from_secs(1), 21), None);
+    }
+}
@@ -1,33 +1,245 @@
 // use passenger_rs::auth::CopilotTokenResponse;
 use crate::auth::CopilotTokenResponse;
 use crate::config::Config;
+use crate::copilot::content_filter::is_filtered;
+use crate::copilot::tool_calls::{TOOL_CALLS_HEADER, ToolCallReport};
+use crate::logging::LogFilter;
+use crate::openai::moderation::rules::ModerationRules;
 use crate::token_manager;
 
+pub mod access_log;
+pub mod account;
+pub mod admin;
+pub(crate) mod admission;
+pub(crate) mod cancellation;
+pub mod capabilities;
+pub(crate) mod client_ip;
+pub(crate) mod clients;
+pub(crate) mod content_filter;
+pub mod context_window;
+pub mod conversation;
 pub mod copilot;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub(crate) mod dedup;
+pub mod dry_run;
+pub(crate) mod echo;
+pub(crate) mod empty_choices;
+pub mod fallback;
+pub mod files;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod keys;
+pub mod mcp;
+pub mod mcp_client;
+pub mod metrics;
+pub(crate) mod multipart;
+pub mod oidc;
+#[cfg(feature = "ollama")]
 pub mod ollama;
 pub mod openai;
+pub(crate) mod overrides;
+pub mod pacing;
+pub(crate) mod payload_dump;
+pub mod premium;
+pub mod profiles;
+pub(crate) mod racing;
+pub(crate) mod server_tools;
+pub mod session;
+pub(crate) mod sse_lines;
+pub(crate) mod stream_errors;
+pub(crate) mod stream_stats;
+pub mod users;
+pub mod web_search;
 
-use self::ollama::chat::*;
-use self::ollama::tags::*;
-use self::ollama::version::*;
+use self::access_log::AccessLog;
+use self::account::AccountEndpoint;
+use self::capabilities::ModelCatalogue;
+use self::conversation::*;
+use self::dedup::InFlightRequests;
+use self::fallback::ModelHealth;
+use self::files::{FileStore, FilesEndpoint, UploadResponse};
+#[cfg(feature = "history")]
+use self::history::{ConversationHistory, HistoryStore};
+#[cfg(feature = "keys")]
+use self::keys::KeyStore;
+use self::mcp::{McpServer, McpSessions};
+use self::mcp_client::McpToolbox;
+use self::metrics::Metrics;
+#[cfg(feature = "metrics")]
+use self::metrics::MetricsEndpoint;
+use self::oidc::OidcVerifier;
+#[cfg(feature = "ollama")]
+use self::ollama::{
+    chat::*,
+    generate::{GenerateContexts, OllamaGenerateEndpoint},
+    manage::*,
+    show::OllamaShow,
+    tags::*,
+    version::*,
+};
+use self::openai::audio::AudioEndpoints;
+use self::openai::azure::*;
 use self::openai::chat_completion::*;
+use self::openai::compare::ChatComparison;
+use self::openai::completions::CopilotTextCompletions;
+use self::openai::images::ImagesEndpoint;
 use self::openai::list_models::*;
+use self::openai::moderations::*;
+#[cfg(feature = "responses")]
 use self::openai::responses_chat::*;
+use self::pacing::UpstreamPacer;
+use self::premium::{PremiumAccounting, PremiumUsage};
+use self::profiles::Profile;
+use self::session::SessionStore;
+use self::users::UserRateLimits;
+use arc_swap::ArcSwap;
 use axum::{
     Json, Router,
-    http::StatusCode,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{get, post},
 };
+use ipnet::IpNet;
 use reqwest::Client;
 use std::sync::Arc;
 use tracing::log::error;
 
+tokio::task_local! {
+    /// The configuration the request being served came in under, with the [`AppState`]
+    /// configuration it was loaded from
+    static REQUEST_CONFIG: (Arc<ArcSwap<Config>>, Arc<Config>);
+}
+
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
-    pub config: Config,
+    /// The configuration, shared by every copy of the state and swappable at runtime.
+    /// Read it through [`AppState::config`].
+    pub config: Arc<ArcSwap<Config>>,
     pub client: Client,
+    pub sessions: Arc<SessionStore>,
+    pub metrics: Arc<Metrics>,
+    pub model_catalogue: Arc<ModelCatalogue>,
+    pub files: Arc<FileStore>,
+    pub moderation: Arc<ModerationRules>,
+    pub premium: Arc<PremiumUsage>,
+    /// Past `/api/generate` turns, replayed from the `context` clients send back
+    #[cfg(feature = "ollama")]
+    pub generate_contexts: Arc<GenerateContexts>,
+    /// Requests other identical ones can wait for, under `[copilot.deduplication]`
+    pub in_flight: Arc<InFlightRequests>,
+    /// Spreads requests to Copilot over time, under `[copilot.pacing]`
+    pub pacer: Option<Arc<UpstreamPacer>>,
+    /// Models recently unavailable, skipped by `[copilot.fallbacks]` chains
+    pub model_health: Arc<ModelHealth>,
+    /// Request counts per end user, under `[users]`
+    pub user_limits: Arc<UserRateLimits>,
+    /// The conversation log, under `[history] enabled`
+    #[cfg(feature = "history")]
+    pub history: Option<Arc<HistoryStore>>,
+    /// Client API keys, under `[keys] enabled`
+    #[cfg(feature = "keys")]
+    pub keys: Option<Arc<KeyStore>>,
+    /// Client JWT validation, under `[oidc] enabled`
+    pub oidc: Option<Arc<OidcVerifier>>,
+    /// Open MCP event streams, under `[mcp] enabled`
+    pub mcp_sessions: Arc<McpSessions>,
+    /// Tools of `[mcp.servers]`, run on the proxy
+    pub mcp_tools: Arc<McpToolbox>,
+    /// The `[profiles]` entry whose prefix the request came in on
+    pub profile: Option<Arc<Profile>>,
+    /// The HTTP access log, under `[access_log] enabled`
+    pub access_log: Option<Arc<AccessLog>>,
+    /// Set when passenger-rs installed the global subscriber, enabling `/admin/log-level`
+    pub log_filter: Option<Arc<LogFilter>>,
+    /// `[server] trusted_proxies`, whose forwarding headers name the client
+    pub trusted_proxies: Arc<Vec<IpNet>>,
+    /// `[server] allowed_clients`; any client when empty
+    pub allowed_clients: Arc<Vec<IpNet>>,
+}
+
+impl AppState {
+    /// The current configuration. While serving a request, the one loaded when it came in,
+    /// so the request sees the same configuration throughout even if it is replaced
+    /// meanwhile. A state with a configuration of its own, like that of an overriding
+    /// Copilot API, reads its own.
+    pub fn config(&self) -> Arc<Config> {
+        REQUEST_CONFIG
+            .try_with(|(source, config)| Arc::ptr_eq(source, &self.config).then(|| config.clone()))
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| self.config.load_full())
+    }
+
+    /// State built from `config`, shared by the HTTP server and the stdio MCP server
+    pub fn new(config: &Config, log_filter: Option<Arc<LogFilter>>) -> Self {
+        let client = config
+            .copilot
+            .build_client()
+            .expect("Failed to build HTTP client");
+        let moderation =
+            ModerationRules::from_config(&config.moderation).expect("Invalid [moderation] rules");
+        let oidc = OidcVerifier::from_config(&config.oidc, client.clone()).map(Arc::new);
+        AppState {
+            config: Arc::new(ArcSwap::from_pointee(config.clone())),
+            client,
+            sessions: Arc::new(SessionStore::default()),
+            metrics: Arc::new(Metrics::default()),
+            model_catalogue: Arc::new(ModelCatalogue::default()),
+            files: Arc::new(FileStore::from_config(&config.files)),
+            moderation: Arc::new(moderation),
+            premium: Arc::new(PremiumUsage::default()),
+            #[cfg(feature = "ollama")]
+            generate_contexts: Arc::new(GenerateContexts::default()),
+            in_flight: Arc::new(InFlightRequests::default()),
+            pacer: UpstreamPacer::from_config(&config.copilot.pacing).map(Arc::new),
+            model_health: Arc::new(ModelHealth::default()),
+            user_limits: Arc::new(UserRateLimits::default()),
+            #[cfg(feature = "history")]
+            history: HistoryStore::from_config(&config.history)
+                .expect("Failed to open the [history] database")
+                .map(Arc::new),
+            #[cfg(feature = "keys")]
+            keys: KeyStore::from_config(&config.keys)
+                .expect("Failed to open the [keys] database")
+                .map(Arc::new),
+            oidc,
+            mcp_sessions: Arc::new(McpSessions::default()),
+            mcp_tools: Arc::new(McpToolbox::from_config(&config.mcp)),
+            profile: None,
+            access_log: AccessLog::from_config(&config.access_log)
+                .expect("Failed to open the [access_log] file")
+                .map(Arc::new),
+            log_filter,
+            trusted_proxies: Arc::new(
+                config
+                    .server
+                    .trusted_proxy_networks()
+                    .expect("Invalid [server] trusted_proxies"),
+            ),
+            allowed_clients: Arc::new(
+                config
+                    .server
+                    .allowed_client_networks()
+                    .expect("Invalid [server] allowed_clients"),
+            ),
+        }
+    }
+}
+
+/// A Copilot token for tests against mock Copilot servers
+#[cfg(test)]
+pub(crate) fn test_token() -> CopilotTokenResponse {
+    CopilotTokenResponse {
+        token: "test".to_string(),
+        expires_at: 0,
+        refresh_in: 0,
+        fetched_at: None,
+        entitlements: Default::default(),
+    }
 }
 
 /// Health check endpoint
@@ -35,81 +247,967 @@ async fn health_check() -> &'static str {
     "OK"
 }
 
-/// Custom error type for API responses
+/// What Ollama answers on `/`, which some clients probe to detect it
+#[cfg(feature = "ollama")]
+async fn ollama_root() -> &'static str {
+    "Ollama is running"
+}
+
+/// Middleware loading the configuration once for the whole request, see [`AppState::config`]
+async fn load_request_config(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let loaded = (state.config.clone(), state.config.load_full());
+    REQUEST_CONFIG.scope(loaded, next.run(request)).await
+}
+
+/// Replace axum's plain-text `413` with an error naming the limit to raise
+async fn explain_payload_too_large(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = state.config();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+
+    let (limit, setting) = if response.extensions().get::<UploadResponse>().is_some() {
+        (config.files.max_upload_bytes, "[files] max_upload_bytes")
+    } else {
+        (config.server.max_body_bytes, "[server] max_body_bytes")
+    };
+    error!("Rejected a request body over the {} byte limit", limit);
+    AppError::PayloadTooLarge(format!(
+        "Request body exceeds the {} byte limit. Raise `{}` to accept larger requests.",
+        limit, setting
+    ))
+    .into_response()
+}
+
+/// Report tool-call repairs or validation failures on a response
+pub(crate) fn with_tool_calls_header(mut response: Response, report: &ToolCallReport) -> Response {
+    if let Some(value) = report.header_value() {
+        response
+            .headers_mut()
+            .insert(TOOL_CALLS_HEADER, HeaderValue::from_static(value));
+    }
+    response
+}
+
+/// Compare secrets without leaking the position of the first mismatch
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Errors answered to clients as an OpenAI error body, with a machine-readable `code`
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum AppError {
     Unauthorized(String),
     InternalServerError(String),
     BadRequest(String),
+    NotFound(String),
+    /// The client's address is outside `[server] allowed_clients`
+    Forbidden(String),
+    /// Copilot did not answer within the configured `[copilot.timeouts]`
+    GatewayTimeout(String),
+    /// Copilot could not be reached, or answered with an error
+    UpstreamError(String),
+    /// Copilot's reply, streamed or not, could not be read or parsed
+    StreamParseError(String),
+    /// No Copilot token could be loaded or refreshed; the proxy needs `--login`
+    TokenRefreshFailed(String),
+    /// The requested model is not in the Copilot model catalogue
+    ModelNotFound(String),
+    /// The `[premium] monthly_budget` would be exceeded
+    QuotaExceeded(String),
+    /// The request body exceeds `[server] max_body_bytes` or `[files] max_upload_bytes`
+    PayloadTooLarge(String),
+    /// Copilot kept answering without any choices, under `[copilot.empty_choices]`
+    EmptyChoices(String),
+    /// Copilot's content filter blocked the prompt
+    ContentFiltered(String),
+    /// A `[profiles]` entry is over its `requests_per_minute`
+    RateLimited(String),
+    /// The endpoint has no backend, e.g. audio without `[audio] backend_url`
+    NotImplemented(String),
+    /// Malformed request body; `param` is the JSON path of the offending value
+    InvalidRequest {
+        message: String,
+        param: String,
+    },
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg),
-            AppError::InternalServerError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-        };
+impl AppError {
+    /// Map a failed upstream call, surfacing timeouts as 504 and unreadable replies as
+    /// [`AppError::StreamParseError`]
+    pub(crate) fn upstream(context: &str, e: reqwest::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+        if e.is_timeout() {
+            AppError::GatewayTimeout(message)
+        } else if e.is_decode() || e.is_body() {
+            AppError::StreamParseError(message)
+        } else {
+            AppError::UpstreamError(message)
+        }
+    }
+
+    /// The error for Copilot answering `status`: client errors other than authentication
+    /// are the request's fault, anything else is Copilot's
+    pub(crate) fn from_upstream_status(status: StatusCode, body: &str) -> Self {
+        let message = format!("Copilot API error: {} - {}", status, body);
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => AppError::RateLimited(message),
+            StatusCode::GATEWAY_TIMEOUT => AppError::GatewayTimeout(message),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AppError::UpstreamError(message),
+            status if status.is_client_error() && is_filtered(body.as_bytes()) => {
+                AppError::ContentFiltered(message)
+            }
+            status if status.is_client_error() => AppError::BadRequest(message),
+            _ => AppError::UpstreamError(message),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Unauthorized(_) | AppError::TokenRefreshFailed(_) => StatusCode::UNAUTHORIZED,
+            AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::BadRequest(_)
+            | AppError::InvalidRequest { .. }
+            | AppError::ContentFiltered(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) | AppError::ModelNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::UpstreamError(_)
+            | AppError::StreamParseError(_)
+            | AppError::EmptyChoices(_) => StatusCode::BAD_GATEWAY,
+            AppError::QuotaExceeded(_) | AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+        }
+    }
+
+    /// The OpenAI error `type`
+    pub fn error_type(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized(_) | AppError::TokenRefreshFailed(_) => "authentication_error",
+            AppError::Forbidden(_) => "permission_error",
+            AppError::BadRequest(_)
+            | AppError::InvalidRequest { .. }
+            | AppError::ContentFiltered(_)
+            | AppError::NotFound(_)
+            | AppError::ModelNotFound(_)
+            | AppError::PayloadTooLarge(_)
+            | AppError::NotImplemented(_) => "invalid_request_error",
+            AppError::GatewayTimeout(_) => "timeout_error",
+            AppError::UpstreamError(_)
+            | AppError::StreamParseError(_)
+            | AppError::EmptyChoices(_) => "upstream_error",
+            AppError::QuotaExceeded(_) => "insufficient_quota",
+            AppError::RateLimited(_) => "requests",
+            AppError::InternalServerError(_) => "server_error",
+        }
+    }
 
-        let body = Json(serde_json::json!({
+    /// The machine-readable `code` of the error body
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized(_) => "invalid_authentication",
+            AppError::InternalServerError(_) => "internal_error",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::InvalidRequest { .. } => "invalid_value",
+            AppError::NotFound(_) => "not_found",
+            AppError::Forbidden(_) => "client_not_allowed",
+            AppError::GatewayTimeout(_) => "upstream_timeout",
+            AppError::UpstreamError(_) => "upstream_error",
+            AppError::StreamParseError(_) => "stream_parse_error",
+            AppError::TokenRefreshFailed(_) => "token_refresh_failed",
+            AppError::ModelNotFound(_) => "model_not_found",
+            AppError::QuotaExceeded(_) => "insufficient_quota",
+            AppError::PayloadTooLarge(_) => "request_too_large",
+            AppError::EmptyChoices(_) => "empty_choices",
+            AppError::ContentFiltered(_) => "content_filter",
+            AppError::RateLimited(_) => "rate_limit_exceeded",
+            AppError::NotImplemented(_) => "not_implemented",
+        }
+    }
+
+    /// The OpenAI error body, `{"error": {...}}`
+    pub(crate) fn body(&self) -> serde_json::Value {
+        serde_json::json!({
             "error": {
-                "message": error_message,
-                "type": "server_error",
+                "message": self.message(),
+                "type": self.error_type(),
+                "param": self.param(),
+                "code": self.code(),
             }
-        }));
+        })
+    }
+
+    pub(crate) fn message(&self) -> String {
+        match self {
+            AppError::ModelNotFound(model) => format!(
+                "The model `{}` does not exist or you do not have access to it.",
+                model
+            ),
+            AppError::InvalidRequest { message, .. } => message.clone(),
+            AppError::Unauthorized(msg)
+            | AppError::InternalServerError(msg)
+            | AppError::BadRequest(msg)
+            | AppError::NotFound(msg)
+            | AppError::Forbidden(msg)
+            | AppError::GatewayTimeout(msg)
+            | AppError::UpstreamError(msg)
+            | AppError::StreamParseError(msg)
+            | AppError::TokenRefreshFailed(msg)
+            | AppError::QuotaExceeded(msg)
+            | AppError::PayloadTooLarge(msg)
+            | AppError::EmptyChoices(msg)
+            | AppError::ContentFiltered(msg)
+            | AppError::RateLimited(msg)
+            | AppError::NotImplemented(msg) => msg.clone(),
+        }
+    }
 
-        (status, body).into_response()
+    fn param(&self) -> Option<&str> {
+        match self {
+            AppError::ModelNotFound(_) => Some("model"),
+            AppError::InvalidRequest { param, .. } => Some(param),
+            _ => None,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(e: reqwest::Error) -> Self {
+        AppError::upstream("Copilot request failed", e)
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        AppError::InternalServerError(format!("{:#}", e))
     }
 }
 
 pub struct Server {
     pub addr: String,
     pub router: Router,
+    /// The dedicated Ollama listener, under `[ollama] port`
+    pub ollama: Option<OllamaListener>,
+}
+
+pub struct OllamaListener {
+    pub addr: String,
+    pub router: Router,
 }
 
 impl Server {
+    #[allow(unused)]
     pub fn new(config: &Config) -> Self {
-        let client = Client::new();
-        let state = AppState {
-            config: config.clone(),
-            client,
-        };
-        let state = Arc::new(state);
+        Self::with_log_filter(config, None)
+    }
+
+    /// Like [`Server::new`], exposing `log_filter` for runtime changes via the admin API
+    pub fn with_log_filter(config: &Config, log_filter: Option<Arc<LogFilter>>) -> Self {
+        let state = Arc::new(AppState::new(config, log_filter));
 
         let app = Self::create_router(state.clone());
         let addr = format!("{}:{}", config.server.host, config.server.port);
 
-        Self { addr, router: app }
+        #[cfg(feature = "ollama")]
+        let ollama = config
+            .ollama
+            .port
+            .filter(|_| config.endpoints.ollama)
+            .map(|port| OllamaListener {
+                addr: format!("{}:{}", config.server.host, port),
+                router: Self::create_ollama_router(state.clone()),
+            });
+        #[cfg(not(feature = "ollama"))]
+        let ollama = None;
+
+        Self {
+            addr,
+            router: app,
+            ollama,
+        }
     }
 
     /// Create the Axum router
     fn create_router(state: Arc<AppState>) -> Router {
-        Router::new()
-            // Openai-compatible endpoints
-            .route("/v1/chat/completions", post(Self::chat_completions))
-            .route("/v1/responses", post(Self::openai_responses_chat))
-            // Ollama-compatible routes: standard /api/... paths
-            .route("/api/chat", post(Self::ollama_chat))
-            .route("/api/tags", get(Self::ollama_tags))
-            .route("/api/version", get(Self::ollama_version))
-            // Ollama-compatible routes: legacy /v1/api/... paths
-            .route("/v1/api/chat", post(Self::ollama_chat))
-            .route("/v1/api/tags", get(Self::ollama_tags))
-            .route("/v1/api/version", get(Self::ollama_version))
-            .route("/v1/models", get(Self::list_models))
+        let config = state.config();
+        let router = Self::api_routes(&state);
+
+        // Conversation log, when enabled
+        #[cfg(feature = "history")]
+        let router = if config.history.enabled {
+            router
+                .route("/v1/history/sessions", get(Self::history_sessions))
+                .route(
+                    "/v1/history/sessions/{session_id}",
+                    get(Self::history_transcript),
+                )
+                .route(
+                    "/v1/history/sessions/{session_id}/replay",
+                    post(Self::history_replay),
+                )
+        } else {
+            router
+        };
+
+        // MCP over SSE, when enabled
+        let router = if config.mcp.enabled {
+            router
+                .route("/mcp/sse", get(Self::mcp_sse))
+                .route("/mcp/messages", post(Self::mcp_post))
+        } else {
+            router
+        };
+
+        let router = Self::with_api_keys(router, &state)
             // other endpoints
-            .route("/health", get(health_check))
-            .with_state(state)
+            .route("/health", get(health_check));
+        #[cfg(feature = "metrics")]
+        let router = if config.endpoints.metrics {
+            router.route("/metrics", get(Self::metrics))
+        } else {
+            router
+        };
+
+        let router = if config.admin.enabled {
+            router.nest("/admin", admin::router(state.clone()))
+        } else {
+            router
+        };
+
+        // The API again under each profile's prefix, bound to that profile
+        let router = config
+            .profiles
+            .iter()
+            .fold(router, |router, (name, profile)| {
+                let mut profile_state = (*state).clone();
+                profile_state.profile = Some(Arc::new(Profile::new(name, profile)));
+                let profile_state = Arc::new(profile_state);
+                router.nest(
+                    &format!("/{}", name),
+                    Self::with_api_keys(Self::api_routes(&profile_state), &profile_state)
+                        .route_layer(middleware::from_fn_with_state(
+                            profile_state.clone(),
+                            profiles::enforce_rate_limit,
+                        ))
+                        .with_state(profile_state),
+                )
+            });
+
+        Self::with_request_middleware(router, &state).with_state(state)
+    }
+
+    /// The API served at the root, and under the prefix of each of `[profiles]`, without
+    /// the families turned off under `[endpoints]`
+    fn api_routes(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+        let config = state.config();
+        let endpoints = &config.endpoints;
+
+        // Openai-compatible endpoints
+        let mut router = Router::new()
+            .route("/v1/chat/completions", post(Self::chat_completions))
+            .route("/v1/models", get(Self::list_models));
+        #[cfg(feature = "responses")]
+        if endpoints.responses {
+            router = router.route("/v1/responses", post(Self::openai_responses_chat));
+        }
+        if endpoints.compare {
+            router = router.route("/v1/compare", post(Self::compare));
+        }
+        if endpoints.completions {
+            router = router.route("/v1/completions", post(Self::completions));
+        }
+        if endpoints.audio {
+            router = router
+                .route("/v1/audio/speech", post(Self::audio_speech))
+                .route("/v1/audio/transcriptions", post(Self::audio_transcriptions));
+        }
+        if endpoints.images {
+            router = router.route("/v1/images/generations", post(Self::image_generations));
+        }
+        if endpoints.moderations {
+            router = router.route("/v1/moderations", post(Self::moderations));
+        }
+        if endpoints.account {
+            router = router
+                .route("/v1/account", get(Self::account))
+                .route("/v1/usage/premium", get(Self::premium_usage));
+        }
+        // Files API, stored locally
+        if endpoints.files {
+            router = router
+                .route(
+                    "/v1/files",
+                    get(Self::list_files)
+                        .post(Self::upload_file)
+                        .layer(DefaultBodyLimit::max(config.files.max_upload_bytes))
+                        .layer(middleware::map_response(files::mark_upload)),
+                )
+                .route(
+                    "/v1/files/{file_id}",
+                    get(Self::retrieve_file).delete(Self::delete_file),
+                )
+                .route("/v1/files/{file_id}/content", get(Self::file_content));
+        }
+        // Azure OpenAI-compatible route
+        if endpoints.azure {
+            router = router.route(
+                "/openai/deployments/{deployment}/chat/completions",
+                post(Self::azure_chat_completions),
+            );
+        }
+        // Copilot Chat editor protocol
+        if endpoints.copilot_conversation {
+            router = router.route("/v1/copilot/conversation", post(Self::copilot_conversation));
+        }
+        #[cfg(feature = "ollama")]
+        if endpoints.ollama {
+            router = router
+                // Ollama-compatible routes: standard /api/... paths
+                .merge(Self::ollama_routes("/api", state))
+                // Ollama-compatible routes: legacy /v1/api/... paths
+                .merge(Self::ollama_routes("/v1/api", state));
+        }
+
+        router
+    }
+
+    /// Require a client API key on the routes so far, under `[keys] enabled`, or a token
+    /// from the OIDC issuer, under `[oidc] enabled`
+    fn with_api_keys(
+        router: Router<Arc<AppState>>,
+        state: &Arc<AppState>,
+    ) -> Router<Arc<AppState>> {
+        #[cfg(feature = "keys")]
+        let router = router.route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            keys::require_api_key,
+        ));
+        router.route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            oidc::require_token,
+        ))
+    }
+
+    /// Ollama's own API under `prefix`
+    #[cfg(feature = "ollama")]
+    fn ollama_routes(prefix: &str, state: &AppState) -> Router<Arc<AppState>> {
+        let router = Router::new()
+            .route(&format!("{}/chat", prefix), post(Self::ollama_chat))
+            .route(&format!("{}/generate", prefix), post(Self::ollama_generate))
+            .route(&format!("{}/tags", prefix), get(Self::ollama_tags))
+            .route(&format!("{}/show", prefix), post(Self::ollama_show))
+            .route(&format!("{}/version", prefix), get(Self::ollama_version));
+
+        // Model management stubs for Ollama clients, which have nothing to manage here
+        if state.config().ollama.model_management {
+            router
+                .route(&format!("{}/pull", prefix), post(Self::ollama_pull))
+                .route(
+                    &format!("{}/delete", prefix),
+                    axum::routing::delete(Self::ollama_delete),
+                )
+                .route(&format!("{}/create", prefix), post(Self::ollama_create))
+        } else {
+            router
+        }
+    }
+
+    /// Router of the dedicated Ollama listener: Ollama's API, and the OpenAI-compatible
+    /// routes Ollama serves next to it, so clients expecting an Ollama server work unchanged
+    #[cfg(feature = "ollama")]
+    fn create_ollama_router(state: Arc<AppState>) -> Router {
+        let router = Router::new()
+            .merge(Self::ollama_routes("/api", &state))
+            .route("/v1/chat/completions", post(Self::chat_completions))
+            .route("/v1/models", get(Self::list_models));
+        let router = Self::with_api_keys(router, &state).route("/", get(ollama_root));
+
+        Self::with_request_middleware(router, &state).with_state(state)
+    }
+
+    /// Wrap `router` in the middleware every request goes through, outermost first:
+    ///
+    /// 1. `load_request_config` pins the configuration the request is served with
+    /// 2. `resolve_client_ip` finds the client's address past any `[server] trusted_proxies`
+    /// 3. `log_access` writes the access log, when enabled, with the status clients finally got
+    /// 4. `record_responses` counts responses for `/metrics`
+    /// 5. `enforce_allowed_clients` refuses clients outside `[server] allowed_clients`
+    /// 6. `explain_payload_too_large` explains the body limit to clients that exceed it
+    /// 7. `DefaultBodyLimit` accepts bodies up to `[server] max_body_bytes` rather than axum's 2MB
+    /// 8. `select_client_headers` picks the Copilot headers of the client's `[[copilot.clients]]`
+    /// 9. `report_queue_wait` tells, under `[copilot.pacing]`, how long Copilot requests queued
+    /// 10. `mark_dry_run` answers, under `[debug] dry_run`, with what would be sent to Copilot
+    ///
+    /// Client addresses are resolved before anything that logs or filters on them.
+    fn with_request_middleware(
+        router: Router<Arc<AppState>>,
+        state: &Arc<AppState>,
+    ) -> Router<Arc<AppState>> {
+        router
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                dry_run::mark_dry_run,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                pacing::report_queue_wait,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                clients::select_client_headers,
+            ))
+            .layer(DefaultBodyLimit::max(state.config().server.max_body_bytes))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                explain_payload_too_large,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                client_ip::enforce_allowed_clients,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                metrics::record_responses,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                access_log::log_access,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                client_ip::resolve_client_ip,
+            ))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                load_request_config,
+            ))
     }
 
     pub(crate) async fn get_token(state: Arc<AppState>) -> Result<CopilotTokenResponse, AppError> {
-        token_manager::get_valid_token(&state.config, &state.client)
+        let profile = state.profile.as_deref();
+        token_manager::get_valid_token_at(
+            &state.config(),
+            &state.client,
+            profile.and_then(Profile::copilot_token_path),
+            profile.and_then(Profile::access_token_path),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to get valid token: {}", e);
+            AppError::TokenRefreshFailed(
+                "No valid Copilot token. Please run with --login".to_string(),
+            )
+        })
+    }
+
+    /// A new token in place of `rejected`, which Copilot answered with 401 Unauthorized
+    pub(crate) async fn replace_token(
+        state: Arc<AppState>,
+        rejected: &CopilotTokenResponse,
+    ) -> Result<CopilotTokenResponse, AppError> {
+        let profile = state.profile.as_deref();
+        token_manager::replace_rejected_token_at(
+            &state.config(),
+            &state.client,
+            rejected,
+            profile.and_then(Profile::copilot_token_path),
+            profile.and_then(Profile::access_token_path),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to replace rejected token: {}", e);
+            AppError::TokenRefreshFailed(
+                "Copilot rejected the token and no new one could be fetched. Please run with --login"
+                    .to_string(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_is_shared_by_copies_of_the_state() {
+        let state = AppState::new(&Config::default(), None);
+        let copy = state.clone();
+        let before = state.config();
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = "http://localhost:1".to_string();
+        state.config.store(Arc::new(config));
+
+        assert_eq!(copy.config().copilot.api_base_url, "http://localhost:1");
+        // A configuration already taken is unaffected
+        assert_eq!(
+            before.copilot.api_base_url,
+            Config::default().copilot.api_base_url
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_keeps_the_configuration_it_came_in_under() {
+        async fn replace_config(State(state): State<Arc<AppState>>) -> String {
+            let before = state.config();
+            let mut config = Config::default();
+            config.copilot.api_base_url = "http://localhost:1".to_string();
+            state.config.store(Arc::new(config));
+            assert!(Arc::ptr_eq(&before, &state.config()));
+            state.config().copilot.api_base_url.clone()
+        }
+
+        let state = Arc::new(AppState::new(&Config::default(), None));
+        let router = Router::new()
+            .route("/", get(replace_config))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                load_request_config,
+            ))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let body = reqwest::get(format!("http://{}/", addr))
             .await
-            .map_err(|e| {
-                error!("Failed to get valid token: {}", e);
-                AppError::Unauthorized(
-                    "No valid authentication. Please run with --login".to_string(),
-                )
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, Config::default().copilot.api_base_url);
+        // The next request sees the replacement
+        assert_eq!(state.config().copilot.api_base_url, "http://localhost:1");
+    }
+
+    #[tokio::test]
+    async fn test_state_with_its_own_configuration_reads_it_during_a_request() {
+        let state = AppState::new(&Config::default(), None);
+        let mut config = Config::default();
+        config.copilot.api_base_url = "http://localhost:1".to_string();
+        let mut overriding = state.clone();
+        overriding.config = Arc::new(ArcSwap::from_pointee(config));
+
+        let loaded = (state.config.clone(), state.config.load_full());
+        REQUEST_CONFIG
+            .scope(loaded, async {
+                assert_eq!(
+                    state.config().copilot.api_base_url,
+                    Config::default().copilot.api_base_url
+                );
+                assert_eq!(
+                    overriding.config().copilot.api_base_url,
+                    "http://localhost:1"
+                );
             })
+            .await;
+    }
+
+    #[cfg(feature = "ollama")]
+    #[tokio::test]
+    async fn test_ollama_listener_routes() {
+        let mut config = Config::default();
+        config.ollama.port = Some(0);
+        config.ollama.model_management = true;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).ollama.unwrap().router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "Ollama is running");
+
+        let response = client
+            .post(format!("http://{}/api/pull", addr))
+            .json(&serde_json::json!({"model": "gpt-4o", "stream": false}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Only the proxy's own listener keeps the legacy paths
+        let response = client
+            .get(format!("http://{}/v1/api/version", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_endpoints_are_not_found() {
+        let mut config = Config::default();
+        config.endpoints.responses = false;
+        config.endpoints.ollama = false;
+        config.endpoints.metrics = false;
+        config.ollama.port = Some(0);
+        assert!(Server::new(&config).ollama.is_none());
+
+        let state = Arc::new(AppState::new(&config, None));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::create_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        for (method, path) in [
+            (reqwest::Method::POST, "/v1/responses"),
+            (reqwest::Method::GET, "/api/tags"),
+            (reqwest::Method::GET, "/v1/api/version"),
+            (reqwest::Method::GET, "/metrics"),
+        ] {
+            let response = client
+                .request(method, format!("http://{}{}", addr, path))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+
+        let response = client
+            .get(format!("http://{}/health", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Unknown paths are counted, but not kept among the errors
+        let snapshot = state.metrics.snapshot();
+        assert_eq!(snapshot.responses.get(&404), Some(&4));
+        assert!(snapshot.recent_errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_clients() {
+        let mut config = Config::default();
+        config.server.allowed_clients = vec!["10.0.0.0/8".to_string()];
+        config.server.trusted_proxies = vec!["127.0.0.1".to_string()];
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .await
+            .unwrap()
+        });
+        let client = reqwest::Client::new();
+
+        let response = client
+            .get(format!("http://{}/health", addr))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = client
+            .get(format!("http://{}/health", addr))
+            .header("X-Forwarded-For", "10.1.2.3")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_upload_limit_is_named_under_a_profile() {
+        let mut config = Config::default();
+        config.files.max_upload_bytes = 1024;
+        config
+            .profiles
+            .insert("work".to_string(), crate::config::ProfileConfig::default());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/work/v1/files", addr))
+            .header("Content-Type", "multipart/form-data; boundary=xyz")
+            .body("x".repeat(2048))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("[files] max_upload_bytes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_applies_to_chunked_bodies() {
+        let mut config = Config::default();
+        config.server.max_body_bytes = 4 * 1024 * 1024;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        // A streamed body without Content-Length, its size only known once read
+        let chunked = |size: usize| {
+            let content = "x".repeat(size);
+            let body = format!(
+                r#"{{"messages": [{{"role": "user", "content": "{}"}}]}}"#,
+                content
+            );
+            let chunks: Vec<Result<String, std::io::Error>> = body
+                .as_bytes()
+                .chunks(64 * 1024)
+                .map(|chunk| Ok(String::from_utf8(chunk.to_vec()).unwrap()))
+                .collect();
+            reqwest::Body::wrap_stream(futures_util::stream::iter(chunks))
+        };
+
+        // Past axum's 2MB default, the body is read and only then found to lack a model
+        let response = client
+            .post(format!("http://{}/v1/chat/completions", addr))
+            .header("Content-Type", "application/json")
+            .body(chunked(3 * 1024 * 1024))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = client
+            .post(format!("http://{}/v1/chat/completions", addr))
+            .header("Content-Type", "application/json")
+            .body(chunked(5 * 1024 * 1024))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["code"], "request_too_large");
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("[server] max_body_bytes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_profile_prefix_is_rate_limited() {
+        let mut config = Config::default();
+        config.profiles.insert(
+            "work".to_string(),
+            crate::config::ProfileConfig {
+                requests_per_minute: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        // Rejected by the handler for lacking a model, which still counts
+        let send = |path: &str| {
+            client
+                .post(format!("http://{}{}", addr, path))
+                .json(&serde_json::json!({"messages": []}))
+                .send()
+        };
+        let response = send("/work/v1/chat/completions").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = send("/work/v1/chat/completions").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+        let error: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(error["error"]["code"], "rate_limit_exceeded");
+
+        // The root API is not limited
+        let response = send("/v1/chat/completions").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_error_bodies_carry_type_and_code() {
+        let body = |error: AppError| async move {
+            let response = error.into_response();
+            let status = response.status();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            (status, body["error"].clone())
+        };
+
+        assert_eq!(
+            body(AppError::GatewayTimeout("Copilot timed out".to_string())).await,
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                serde_json::json!({
+                    "message": "Copilot timed out",
+                    "type": "timeout_error",
+                    "param": null,
+                    "code": "upstream_timeout",
+                })
+            )
+        );
+        let (status, error) = body(AppError::ModelNotFound("gpt-9".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error["param"], "model");
+        assert_eq!(error["code"], "model_not_found");
+
+        let filtered = AppError::from_upstream_status(
+            StatusCode::BAD_REQUEST,
+            r#"{"error": {"code": "content_filter", "message": "blocked"}}"#,
+        );
+        let (status, error) = body(filtered).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["code"], "content_filter");
+
+        let (status, error) = body(AppError::TokenRefreshFailed("login".to_string())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error["code"], "token_refresh_failed");
+
+        let (status, error) = body(AppError::StreamParseError("bad".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(error["type"], "upstream_error");
+        assert_eq!(error["code"], "stream_parse_error");
+
+        let upstream =
+            |status: u16| AppError::from_upstream_status(StatusCode::from_u16(status).unwrap(), "");
+        assert_eq!(upstream(400).code(), "bad_request");
+        assert_eq!(upstream(401).status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(upstream(429).code(), "rate_limit_exceeded");
+        assert_eq!(upstream(500).code(), "upstream_error");
     }
 }
@@ -0,0 +1,152 @@
+use axum::body::Bytes;
+
+/// One field of a `multipart/form-data` body
+#[derive(Debug)]
+pub(crate) struct Part {
+    pub name: String,
+    pub filename: Option<String>,
+    pub data: Bytes,
+}
+
+/// The boundary from a `multipart/form-data; boundary=...` content type
+pub(crate) fn boundary(content_type: &str) -> Option<&str> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim_matches('"'))
+        .filter(|value| !value.is_empty())
+}
+
+/// Split a `multipart/form-data` body into its parts (RFC 7578)
+pub(crate) fn parse(body: &Bytes, boundary: &str) -> Result<Vec<Part>, String> {
+    let delimiter = format!("--{}", boundary);
+    let next_delimiter = format!("\r\n--{}", boundary);
+
+    let mut position = find(body, delimiter.as_bytes(), 0)
+        .ok_or("Multipart body has no opening boundary")?
+        + delimiter.len();
+
+    let mut parts = Vec::new();
+    loop {
+        if body[position..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[position..].starts_with(b"\r\n") {
+            return Err("Malformed multipart boundary".to_string());
+        }
+        position += 2;
+
+        let headers_end =
+            find(body, b"\r\n\r\n", position).ok_or("Multipart part has no header terminator")?;
+        let headers = std::str::from_utf8(&body[position..headers_end])
+            .map_err(|_| "Multipart part headers are not UTF-8")?;
+
+        let data_start = headers_end + 4;
+        let data_end = find(body, next_delimiter.as_bytes(), data_start)
+            .ok_or("Multipart body has no closing boundary")?;
+
+        if let Some((name, filename)) = content_disposition(headers) {
+            parts.push(Part {
+                name,
+                filename,
+                data: body.slice(data_start..data_end),
+            });
+        }
+
+        position = data_end + next_delimiter.len();
+    }
+}
+
+/// `name` and `filename` from a part's `Content-Disposition: form-data` header
+fn content_disposition(headers: &str) -> Option<(String, Option<String>)> {
+    let value = headers.lines().find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case("content-disposition")
+            .then_some(value)
+    })?;
+
+    let mut name = None;
+    let mut filename = None;
+    for param in value.split(';').skip(1) {
+        let Some((key, value)) = param.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"').to_string();
+        match key.trim() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => {}
+        }
+    }
+
+    Some((name?, filename))
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| from + index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=----abc123"),
+            Some("----abc123")
+        );
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; boundary=\"xyz\""),
+            Some("xyz")
+        );
+        assert_eq!(boundary("application/json"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_parse_parts() {
+        let body = Bytes::from(
+            "--xyz\r\n\
+             Content-Disposition: form-data; name=\"purpose\"\r\n\
+             \r\n\
+             batch\r\n\
+             --xyz\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"input.jsonl\"\r\n\
+             Content-Type: application/jsonl\r\n\
+             \r\n\
+             {\"a\":1}\r\n{\"b\":2}\r\n\
+             --xyz--\r\n",
+        );
+
+        let parts = parse(&body, "xyz").unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "purpose");
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(&parts[0].data[..], b"batch");
+        assert_eq!(parts[1].name, "file");
+        assert_eq!(parts[1].filename.as_deref(), Some("input.jsonl"));
+        assert_eq!(&parts[1].data[..], b"{\"a\":1}\r\n{\"b\":2}");
+    }
+
+    #[test]
+    fn test_parse_rejects_truncated_body() {
+        let body = Bytes::from("--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue");
+
+        assert!(parse(&body, "xyz").is_err());
+        assert!(parse(&Bytes::from("no boundary here"), "xyz").is_err());
+    }
+}
@@ -0,0 +1,549 @@
+use crate::config::OidcConfig;
+use crate::server::{AppError, AppState};
+use anyhow::{Context, Result};
+use aws_lc_rs::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+use tracing::log::{debug, warn};
+
+/// Clock skew tolerated on `exp` and `nbf`
+const LEEWAY_SECS: u64 = 60;
+
+/// How soon a token signed with an unknown key may refetch the key set
+const MIN_REFRESH: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    /// The user a validated token was issued to, under `[oidc] enabled`
+    static IDENTITY: Identity;
+}
+
+/// Who a validated token names, and their limits
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Identity {
+    /// The `[oidc] user_claim`
+    pub user: String,
+    /// From `[oidc] group_requests_per_minute`, when one of the user's groups has a limit
+    pub requests_per_minute: Option<u32>,
+}
+
+/// One key of the issuer's JSON Web Key Set
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    #[serde(default)]
+    n: String,
+    #[serde(default)]
+    e: String,
+    #[serde(default)]
+    crv: String,
+    #[serde(default)]
+    x: String,
+    #[serde(default)]
+    y: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Validates client JWTs against `[oidc]`, caching the issuer's keys
+#[derive(Debug)]
+pub struct OidcVerifier {
+    config: OidcConfig,
+    client: Client,
+    jwks: RwLock<Option<(Instant, Vec<Jwk>)>>,
+}
+
+impl OidcVerifier {
+    /// The verifier the server checks requests with. `None` unless `[oidc] enabled`.
+    pub fn from_config(config: &OidcConfig, client: Client) -> Option<Self> {
+        config.enabled.then(|| Self {
+            config: config.clone(),
+            client,
+            jwks: RwLock::new(None),
+        })
+    }
+
+    /// The identity `token` vouches for, once its signature, issuer, audience and lifetime
+    /// are checked
+    pub(crate) async fn validate(&self, token: &str) -> Result<Identity> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("not a JWT");
+        };
+        let jwt_header: JwtHeader =
+            serde_json::from_slice(&decode(header)?).context("Malformed JWT header")?;
+        let claims: Map<String, Value> =
+            serde_json::from_slice(&decode(payload)?).context("Malformed JWT claims")?;
+        let signature = decode(signature)?;
+
+        let key = self.key(jwt_header.kid.as_deref()).await?;
+        let signed = &token[..header.len() + 1 + payload.len()];
+        verify(&jwt_header.alg, &key, signed.as_bytes(), &signature)?;
+
+        self.check_claims(&claims)?;
+        self.identity(&claims)
+    }
+
+    fn check_claims(&self, claims: &Map<String, Value>) -> Result<()> {
+        let issuer = claims
+            .get("iss")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if issuer.trim_end_matches('/') != self.config.issuer.trim_end_matches('/') {
+            anyhow::bail!("issued by {:?}, not {}", issuer, self.config.issuer);
+        }
+
+        if let Some(audience) = &self.config.audience {
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+                _ => false,
+            };
+            if !matches {
+                anyhow::bail!("not issued for audience {}", audience);
+            }
+        }
+
+        let now = now();
+        let time = |claim: &str| claims.get(claim).and_then(Value::as_u64);
+        match time("exp") {
+            Some(exp) if exp + LEEWAY_SECS > now => {}
+            Some(_) => anyhow::bail!("expired"),
+            None => anyhow::bail!("no expiry"),
+        }
+        if time("nbf").is_some_and(|nbf| nbf > now + LEEWAY_SECS) {
+            anyhow::bail!("not valid yet");
+        }
+        Ok(())
+    }
+
+    /// Who `claims` name; tokens without the `[oidc] user_claim` name nobody and are refused
+    fn identity(&self, claims: &Map<String, Value>) -> Result<Identity> {
+        let user = match claims.get(&self.config.user_claim) {
+            Some(Value::String(user)) if !user.trim().is_empty() => user.clone(),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => value.to_string(),
+            _ => anyhow::bail!("no {} claim naming the user", self.config.user_claim),
+        };
+        let groups: Vec<&str> = match claims.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(groups)) => groups.split_whitespace().collect(),
+            _ => Vec::new(),
+        };
+        let requests_per_minute = groups
+            .iter()
+            .filter_map(|group| self.config.group_requests_per_minute.get(*group))
+            .max()
+            .copied();
+
+        Ok(Identity {
+            user,
+            requests_per_minute,
+        })
+    }
+
+    /// The issuer's key `kid`, refetching the key set when it is stale or lacks that key
+    async fn key(&self, kid: Option<&str>) -> Result<Jwk> {
+        let max_age = Duration::from_secs(self.config.jwks_cache_secs);
+        let find = |keys: &[Jwk]| {
+            keys.iter()
+                .find(|key| kid.is_none() || key.kid.as_deref() == kid)
+                .cloned()
+        };
+
+        // The answer the cached key set gives, unless it should be refetched
+        let cached = |jwks: &Option<(Instant, Vec<Jwk>)>| {
+            let (fetched, keys) = jwks.as_ref()?;
+            match find(keys) {
+                Some(key) if fetched.elapsed() < max_age => Some(Ok(key)),
+                None if fetched.elapsed() < MIN_REFRESH => {
+                    Some(Err(anyhow::anyhow!("signed with unknown key {:?}", kid)))
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(key) = cached(&*self.jwks.read().await) {
+            return key;
+        }
+
+        let mut jwks = self.jwks.write().await;
+        // Another request may have refetched the key set while this one waited for the lock
+        if let Some(key) = cached(&jwks) {
+            return key;
+        }
+        let keys = self.fetch_keys().await?;
+        let key = find(&keys);
+        *jwks = Some((Instant::now(), keys));
+        key.with_context(|| format!("signed with unknown key {:?}", kid))
+    }
+
+    async fn fetch_keys(&self) -> Result<Vec<Jwk>> {
+        let url = match &self.config.jwks_url {
+            Some(url) => url.clone(),
+            None => {
+                let discovery = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                self.get::<Discovery>(&discovery).await?.jwks_uri
+            }
+        };
+        debug!("Fetching OIDC signing keys from {}", url);
+        Ok(self.get::<JwkSet>(&url).await?.keys)
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T> {
+        self.client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Failed to fetch {}", url))?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse {}", url))
+    }
+}
+
+/// Check `signature` over `message` with `key`, for the JWS algorithm `alg`
+fn verify(alg: &str, key: &Jwk, message: &[u8], signature: &[u8]) -> Result<()> {
+    let verified = match (alg, key.kty.as_str()) {
+        ("RS256" | "RS384" | "RS512", "RSA") => {
+            let algorithm = match alg {
+                "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                _ => &signature::RSA_PKCS1_2048_8192_SHA512,
+            };
+            let components = RsaPublicKeyComponents {
+                n: decode(&key.n)?,
+                e: decode(&key.e)?,
+            };
+            components.verify(algorithm, message, signature)
+        }
+        ("ES256" | "ES384", "EC") => {
+            let algorithm = match (alg, key.crv.as_str()) {
+                ("ES256", "P-256") => &signature::ECDSA_P256_SHA256_FIXED,
+                ("ES384", "P-384") => &signature::ECDSA_P384_SHA384_FIXED,
+                _ => anyhow::bail!("{} does not match curve {}", alg, key.crv),
+            };
+            let point = [vec![0x04], decode(&key.x)?, decode(&key.y)?].concat();
+            UnparsedPublicKey::new(algorithm, point).verify(message, signature)
+        }
+        _ => anyhow::bail!("unsupported algorithm {} for a {} key", alg, key.kty),
+    };
+    verified.map_err(|_| anyhow::anyhow!("bad signature"))
+}
+
+fn decode(part: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(part.trim_end_matches('='))
+        .context("Malformed base64url")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should go forward")
+        .as_secs()
+}
+
+/// Whether a bearer token is a JWT rather than a client API key
+fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+/// Middleware admitting requests with a valid token from `[oidc] issuer`. Other bearer
+/// credentials are left to client API keys when `[keys] enabled`.
+pub(crate) async fn require_token(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(oidc) = state.oidc.clone() else {
+        return next.run(request).await;
+    };
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| is_jwt(token))
+        .map(str::to_string);
+
+    let Some(token) = token else {
+        #[cfg(feature = "keys")]
+        if state.keys.is_some() {
+            return next.run(request).await;
+        }
+        debug!("Rejecting request without an OIDC token");
+        return AppError::Unauthorized("Missing bearer token from the OIDC issuer".to_string())
+            .into_response();
+    };
+
+    match oidc.validate(&token).await {
+        Ok(identity) => {
+            debug!("Authenticated {} with an OIDC token", identity.user);
+            request.extensions_mut().insert(identity.clone());
+            let mut response = IDENTITY.scope(identity.clone(), next.run(request)).await;
+            response.extensions_mut().insert(identity);
+            response
+        }
+        Err(e) => {
+            warn!("Rejecting OIDC token: {:#}", e);
+            AppError::Unauthorized(format!("Invalid OIDC token: {}", e)).into_response()
+        }
+    }
+}
+
+/// The identity of the token the request being served was authenticated with
+pub(crate) fn current() -> Option<Identity> {
+    IDENTITY.try_with(Identity::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lc_rs::rand::SystemRandom;
+    use aws_lc_rs::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    struct Issuer {
+        server: MockServer,
+        key: EcdsaKeyPair,
+    }
+
+    impl Issuer {
+        async fn start() -> Self {
+            let server = MockServer::start().await;
+            let key = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_FIXED_SIGNING).unwrap();
+            let point = key.public_key().as_ref();
+            Mock::given(method("GET"))
+                .and(path("/.well-known/openid-configuration"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "issuer": server.uri(),
+                    "jwks_uri": format!("{}/jwks", server.uri()),
+                })))
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path("/jwks"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "keys": [{
+                        "kid": "k1",
+                        "kty": "EC",
+                        "crv": "P-256",
+                        "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                        "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                    }]
+                })))
+                .expect(1)
+                .mount(&server)
+                .await;
+            Self { server, key }
+        }
+
+        fn token(&self, kid: &str, claims: Value) -> String {
+            let header = URL_SAFE_NO_PAD.encode(json!({"alg": "ES256", "kid": kid}).to_string());
+            let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+            let signed = format!("{}.{}", header, payload);
+            let signature = self
+                .key
+                .sign(&SystemRandom::new(), signed.as_bytes())
+                .unwrap();
+            format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(signature.as_ref()))
+        }
+
+        fn verifier(&self) -> OidcVerifier {
+            let config = OidcConfig {
+                enabled: true,
+                issuer: self.server.uri(),
+                audience: Some("passenger".to_string()),
+                user_claim: "email".to_string(),
+                group_requests_per_minute: [("ml".to_string(), 100), ("staff".to_string(), 10)]
+                    .into(),
+                ..Default::default()
+            };
+            OidcVerifier::from_config(&config, Client::new()).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let issuer = Issuer::start().await;
+        let verifier = issuer.verifier();
+        let claims = |aud: &str, exp: u64| {
+            json!({
+                "iss": issuer.server.uri(),
+                "aud": [aud],
+                "exp": exp,
+                "email": "alice@example.com",
+                "groups": ["staff", "ml"],
+            })
+        };
+
+        let identity = verifier
+            .validate(&issuer.token("k1", claims("passenger", now() + 300)))
+            .await
+            .unwrap();
+        assert_eq!(
+            identity,
+            Identity {
+                user: "alice@example.com".to_string(),
+                requests_per_minute: Some(100),
+            }
+        );
+
+        for (token, reason) in [
+            (issuer.token("k1", claims("other", now() + 300)), "audience"),
+            (
+                issuer.token("k1", claims("passenger", now() - 600)),
+                "expired",
+            ),
+            (
+                issuer.token("k2", claims("passenger", now() + 300)),
+                "unknown key",
+            ),
+        ] {
+            let error = verifier.validate(&token).await.unwrap_err().to_string();
+            assert!(error.contains(reason), "{}", error);
+        }
+
+        let mut forged = issuer.token("k1", claims("passenger", now() + 300));
+        forged.replace_range(forged.len() - 4.., "AAAA");
+        assert!(verifier.validate(&forged).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_rejects_tokens_without_a_user() {
+        let issuer = Issuer::start().await;
+        let verifier = issuer.verifier();
+
+        for email in [None, Some(json!("")), Some(Value::Null)] {
+            let mut claims = json!({
+                "iss": issuer.server.uri(),
+                "aud": "passenger",
+                "exp": now() + 300,
+            });
+            if let Some(email) = email {
+                claims["email"] = email;
+            }
+            let error = verifier
+                .validate(&issuer.token("k1", claims))
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains("no email claim"), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_fetch_the_keys_once() {
+        // The issuer expects its key set to be fetched once
+        let issuer = Issuer::start().await;
+        let verifier = issuer.verifier();
+        let token = issuer.token(
+            "k1",
+            json!({
+                "iss": issuer.server.uri(),
+                "aud": "passenger",
+                "exp": now() + 300,
+                "email": "alice@example.com",
+            }),
+        );
+
+        // All of them find the cache empty before the first takes the write lock
+        let lock = verifier.jwks.write().await;
+        let (results, ()) = tokio::join!(
+            futures_util::future::join_all((0..8).map(|_| verifier.validate(&token))),
+            async move {
+                tokio::task::yield_now().await;
+                drop(lock);
+            }
+        );
+        assert!(results.iter().all(Result::is_ok));
+    }
+
+    #[cfg(feature = "ollama")]
+    #[tokio::test]
+    async fn test_group_limit_applies_to_ollama_chat() {
+        let issuer = Issuer::start().await;
+        let config = crate::config::Config {
+            oidc: OidcConfig {
+                enabled: true,
+                issuer: issuer.server.uri(),
+                user_claim: "email".to_string(),
+                group_requests_per_minute: [("staff".to_string(), 1)].into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let token = issuer.token(
+            "k1",
+            json!({
+                "iss": issuer.server.uri(),
+                "exp": now() + 300,
+                "email": "alice@example.com",
+                "groups": ["staff"],
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = crate::server::Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        // Admitted, then refused for its tool_choice before reaching Copilot
+        let send = || {
+            Client::new()
+                .post(format!("http://{}/api/chat", addr))
+                .bearer_auth(&token)
+                .json(&json!({
+                    "model": "gpt-4o",
+                    "messages": [{"role": "user", "content": "Hi"}],
+                    "tool_choice": "bogus",
+                }))
+                .send()
+        };
+        let response = send().await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+
+        let response = send().await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        let error: Value = response.json().await.unwrap();
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("alice@example.com")
+        );
+    }
+}
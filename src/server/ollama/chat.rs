@@ -1,17 +1,31 @@
 use crate::copilot::CopilotChatRequest;
 use crate::copilot::CopilotChatResponse;
+use crate::copilot::finish_reason::FinishReason;
+use crate::copilot::stream::{ChatDelta, ChatDeltas, StreamLine};
+use crate::copilot::tool_calls::ToolCallCheck;
 use crate::openai::completion::models::OpenAIChatRequest;
-use crate::server::copilot::CopilotIntegration;
+use crate::server::admission::{ChatAdmission, PreparedChat, admit};
+use crate::server::cancellation::CancellableStream;
+use crate::server::capabilities::with_adjustments_header;
+use crate::server::copilot::{CopilotIntegration, upstream_headers, with_upstream_headers};
+use crate::server::racing::ModelRacing;
+use crate::server::server_tools::auto_tools;
+use crate::server::session::with_session_header;
+use crate::server::sse_lines::SseLines;
+use crate::server::stream_errors::{end_with_error, read_error};
+use crate::server::stream_stats::StreamStats;
+use crate::server::with_tool_calls_header;
 use crate::server::{AppError, AppState, Server};
+use axum::body::Bytes;
+use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
 use axum::{Json, extract::State};
-use futures_util::{StreamExt as _, TryStreamExt as _};
-use reqwest::Error;
+use futures_util::TryStreamExt as _;
 use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
 use std::sync::Arc;
-use tokio_util::bytes::Bytes;
 use tracing::debug;
-use tracing::log::{error, info, warn};
+use tracing::log::{error, info};
 
 /// Ollama-compatible chat response
 #[derive(Debug, Serialize, Deserialize)]
@@ -65,23 +79,30 @@ pub struct OllamaFunction {
 pub(crate) trait OllamaChatEndpoint: CopilotIntegration {
     async fn ollama_chat(
         state: State<Arc<AppState>>,
+        headers: HeaderMap,
         request: Json<OpenAIChatRequest>,
     ) -> Result<Response, AppError>;
 
+    /// `thinking`: pass reasoning on in Ollama's `thinking` field, per `[ollama] thinking`
     async fn ollama_chat_sse(
         model: String,
         response: reqwest::Response,
+        stats: Arc<StreamStats>,
+        thinking: bool,
     ) -> Result<Response, AppError>;
 
     async fn ollama_chat_no_sse(
         copilot_request: CopilotChatRequest,
         response: reqwest::Response,
+        tool_check: ToolCallCheck,
+        thinking: bool,
     ) -> Result<Response, AppError>;
 }
 
 impl OllamaChatEndpoint for Server {
     async fn ollama_chat(
         State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
         request: Json<OpenAIChatRequest>,
     ) -> Result<Response, AppError> {
         let mut request = request.0;
@@ -95,11 +116,17 @@ impl OllamaChatEndpoint for Server {
 
         let is_stream = request.stream;
 
-        // Get a valid Copilot token
-        let token = Self::get_token(state.clone()).await?;
+        let admission = admit(&state, &headers, request.user.as_deref())?;
+        let session_id = admission.session_id.clone();
 
         // Transform OpenAI request to Copilot format
-        let copilot_request: CopilotChatRequest = request.into();
+        let mut copilot_request: CopilotChatRequest = request.into();
+        let PreparedChat {
+            state,
+            token,
+            adjustments,
+        } = Self::prepare_chat(state, &headers, &admission, &mut copilot_request).await?;
+        let config = state.config();
 
         debug!(
             "copilot_request:\n{}",
@@ -107,30 +134,60 @@ impl OllamaChatEndpoint for Server {
         );
 
         // Forward request to Copilot API
-        let copilot_url = format!("{}/chat/completions", state.config.copilot.api_base_url);
-
-        let response = Self::forward_prompt(state, token, copilot_url, &copilot_request).await?;
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+
+        let tool_check =
+            ToolCallCheck::new(&config.copilot.tool_calls, copilot_request.tools.as_deref());
+        let stats = StreamStats::new(state.metrics.clone(), "ollama_chat", &copilot_request.model);
+        let thinking = config.ollama.thinking;
+        let auto_tools = auto_tools(&config.mcp, &headers);
+        let response = Self::forward_raced(
+            state.clone(),
+            token,
+            copilot_url,
+            &copilot_request,
+            &session_id,
+            is_stream,
+            auto_tools,
+        )
+        .await?;
+        stats.served_by(&response);
 
         let status = response.status();
         if !status.is_success() {
             return Err(Self::handle_errors(response).await.unwrap_err());
         }
+        let upstream = upstream_headers(&config.copilot.response_headers, response.headers());
 
-        if is_stream {
-            Self::ollama_chat_sse(copilot_request.model.clone(), response).await
+        let response = if is_stream {
+            Self::ollama_chat_sse(copilot_request.model.clone(), response, stats, thinking).await
         } else {
-            Self::ollama_chat_no_sse(copilot_request, response).await
-        }
+            Self::ollama_chat_no_sse(copilot_request, response, tool_check, thinking).await
+        };
+
+        response.map(|response| {
+            let response = with_upstream_headers(response, upstream);
+            with_adjustments_header(with_session_header(response, &session_id), &adjustments)
+        })
     }
 
     async fn ollama_chat_no_sse(
         copilot_request: CopilotChatRequest,
         response: reqwest::Response,
+        tool_check: ToolCallCheck,
+        thinking: bool,
     ) -> Result<Response, AppError> {
-        let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+        let mut copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
             error!("Failed to parse Copilot response: {}", e);
-            AppError::InternalServerError(format!("Failed to parse Copilot response: {}", e))
+            AppError::upstream("Failed to parse Copilot response", e)
         })?;
+        if !thinking {
+            for choice in &mut copilot_response.choices {
+                choice.message.reasoning_text = None;
+            }
+        }
+
+        let tool_report = tool_check.apply(&mut copilot_response);
 
         debug!(
             "copilot_response:\n{}",
@@ -147,65 +204,84 @@ impl OllamaChatEndpoint for Server {
 
         info!("Successfully processed Ollama chat request");
 
-        Ok(Json(ollama_response).into_response())
+        Ok(with_tool_calls_header(
+            Json(ollama_response).into_response(),
+            &tool_report,
+        ))
     }
 
     async fn ollama_chat_sse(
         model: String,
         response: reqwest::Response,
+        stats: Arc<StreamStats>,
+        thinking: bool,
     ) -> Result<Response, AppError> {
         use axum::body::Body;
         use axum::http::header;
 
         let byte_stream = response.bytes_stream();
 
-        // Each Copilot SSE chunk may carry one or more "data: <json>\n" lines.
-        // We parse the OpenAI-format delta and re-emit as Ollama NDJSON chunks.
-        // The final Copilot chunk is "data: [DONE]" — we emit the terminal
+        // Copilot sends "data: <json>" SSE lines. We parse each OpenAI-format
+        // delta and re-emit it as an Ollama NDJSON chunk, one line at a time.
+        // The final Copilot line is "data: [DONE]" — we emit the terminal
         // Ollama object (done: true) at that point.
-        let ndjson_stream = byte_stream
-            .map_err(|e: Error| {
-                error!("Error reading streaming response from Copilot: {}", e);
-                std::io::Error::other(e.to_string())
+        let byte_stream = byte_stream.map_err(read_error);
+        let mut deltas = ChatDeltas::default();
+        let ndjson_stream = SseLines::new(byte_stream)
+            .inspect_ok({
+                let stats = stats.clone();
+                move |line| stats.observe_line(line)
             })
-            .flat_map(move |result| {
-                let model = model.clone();
-                let lines: Vec<Result<Bytes, std::io::Error>> = match result {
-                    Err(e) => vec![Err(e)],
-                    Ok(bytes) => {
-                        let text = String::from_utf8_lossy(&bytes).into_owned();
-                        text.lines()
-                            .filter_map(|line| match translate_sse_line(&model, line) {
-                                SseLineOutput::Line(s) => Some(Ok(Bytes::from(s))),
-                                SseLineOutput::Skip | SseLineOutput::Unexpected(_) => None,
-                            })
-                            .collect()
+            .try_filter_map(move |line| {
+                let chunk = match translate_sse_line(&model, &line, thinking, &mut deltas) {
+                    SseLineOutput::Line(s) => Ok(Some(Bytes::from(s))),
+                    SseLineOutput::Skip | SseLineOutput::Unexpected(_) => Ok(None),
+                    SseLineOutput::Malformed(message) => {
+                        Err(std::io::Error::new(ErrorKind::InvalidData, message))
                     }
                 };
-                futures_util::stream::iter(lines)
+                futures_util::future::ready(chunk)
             });
 
         info!("Streaming Ollama chat response");
+        let ndjson_stream = end_with_error(ndjson_stream, ollama_error_line);
+        let ndjson_stream = CancellableStream::new(ndjson_stream, stats);
         let body = Body::from_stream(ndjson_stream);
         Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response())
     }
 }
 
-/// Minimal structs to deserialize OpenAI-format SSE delta chunks from Copilot
-#[derive(Debug, Deserialize)]
-struct OpenAIStreamChunk {
-    choices: Vec<OpenAIStreamChoice>,
+/// The NDJSON line ending an Ollama stream that failed, in Ollama's `{"error": ...}` form
+pub(crate) fn ollama_error_line(error: AppError) -> Bytes {
+    let mut json = serde_json::json!({"error": error.message()}).to_string();
+    json.push('\n');
+    Bytes::from(json)
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAIStreamChoice {
-    delta: OpenAIStreamDelta,
+/// What the deltas of one Copilot chunk add to an Ollama line
+#[derive(Debug, Default)]
+pub(crate) struct OllamaDelta {
+    pub(crate) content: String,
+    pub(crate) thinking: Option<String>,
+    /// Why the reply finished, when the chunk ends it
+    pub(crate) done: Option<FinishReason>,
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAIStreamDelta {
-    #[serde(default)]
-    content: Option<String>,
+impl From<Vec<ChatDelta>> for OllamaDelta {
+    fn from(deltas: Vec<ChatDelta>) -> Self {
+        let mut delta = OllamaDelta::default();
+        for piece in deltas {
+            match piece {
+                ChatDelta::Content(text) => delta.content.push_str(&text),
+                ChatDelta::Reasoning(text) => {
+                    delta.thinking.get_or_insert_default().push_str(&text)
+                }
+                ChatDelta::Done(reason) => delta.done = Some(reason),
+                _ => {}
+            }
+        }
+        delta
+    }
 }
 
 /// Result of translating a single Copilot SSE line into Ollama NDJSON output.
@@ -217,84 +293,53 @@ pub(crate) enum SseLineOutput {
     Skip,
     /// The line was not a valid `data: …` SSE line (logged as a warning).
     Unexpected(String),
+    /// The `data:` payload was not JSON; the stream ends with an error line.
+    Malformed(String),
 }
 
 /// Translate one line of Copilot SSE output into the matching Ollama NDJSON
 /// representation.
 ///
-/// * `data: [DONE]`       → terminal `{ …, "done": true }` object
-/// * `data: <json-chunk>` → intermediate `{ …, "done": false }` object
+/// * `data: [DONE]`       → terminal `{ …, "done": true }` object, whose `done_reason`
+///   is the `finish_reason` recorded in `finished` by an earlier chunk
+/// * `data: <json-chunk>` → intermediate `{ …, "done": false }` object, with any
+///   reasoning delta in `message.thinking` when `thinking` is set
 /// * empty / whitespace   → `SseLineOutput::Skip`
 /// * anything else        → `SseLineOutput::Unexpected`
-pub(crate) fn translate_sse_line(model: &str, line: &str) -> SseLineOutput {
-    if let Some(payload) = line.strip_prefix("data: ") {
-        if payload == "[DONE]" {
-            let done_obj = OllamaChatResponse {
-                model: model.to_string(),
-                created_at: chrono::Utc::now().to_rfc3339(),
-                message: OllamaMessage {
-                    role: "assistant".to_string(),
-                    content: String::new(),
-                    thinking: None,
-                    tool_calls: None,
-                    images: None,
-                },
-                done: true,
-                done_reason: Some("stop".to_string()),
-                total_duration: None,
-                load_duration: None,
-                prompt_eval_count: None,
-                prompt_eval_duration: None,
-                eval_count: None,
-                eval_duration: None,
-            };
-            let mut json = serde_json::to_string(&done_obj).expect("serialization cannot fail");
-            json.push('\n');
-            SseLineOutput::Line(json)
-        } else {
-            match serde_json::from_str::<OpenAIStreamChunk>(payload) {
-                Ok(chunk) => {
-                    let content = chunk
-                        .choices
-                        .first()
-                        .and_then(|c| c.delta.content.clone())
-                        .unwrap_or_default();
-                    let chunk_obj = OllamaChatResponse {
-                        model: model.to_string(),
-                        created_at: chrono::Utc::now().to_rfc3339(),
-                        message: OllamaMessage {
-                            role: "assistant".to_string(),
-                            content,
-                            thinking: None,
-                            tool_calls: None,
-                            images: None,
-                        },
-                        done: false,
-                        done_reason: None,
-                        total_duration: None,
-                        load_duration: None,
-                        prompt_eval_count: None,
-                        prompt_eval_duration: None,
-                        eval_count: None,
-                        eval_duration: None,
-                    };
-                    let mut json =
-                        serde_json::to_string(&chunk_obj).expect("serialization cannot fail");
-                    json.push('\n');
-                    SseLineOutput::Line(json)
-                }
-                Err(e) => {
-                    warn!("Failed to parse Copilot SSE chunk: {} — {}", e, payload);
-                    SseLineOutput::Unexpected(payload.to_string())
-                }
-            }
-        }
-    } else if line.trim().is_empty() {
-        SseLineOutput::Skip
-    } else {
-        warn!("Unexpected SSE line from Copilot: {}", line);
-        SseLineOutput::Unexpected(line.to_string())
-    }
+pub(crate) fn translate_sse_line(
+    model: &str,
+    line: &str,
+    thinking: bool,
+    deltas: &mut ChatDeltas,
+) -> SseLineOutput {
+    let delta = match deltas.line(line) {
+        StreamLine::Deltas(deltas) => OllamaDelta::from(deltas),
+        StreamLine::Skip => return SseLineOutput::Skip,
+        StreamLine::Unexpected(line) => return SseLineOutput::Unexpected(line),
+        StreamLine::Malformed(e) => return SseLineOutput::Malformed(e.to_string()),
+    };
+    let chunk_obj = OllamaChatResponse {
+        model: model.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        message: OllamaMessage {
+            role: "assistant".to_string(),
+            content: delta.content,
+            thinking: delta.thinking.filter(|_| thinking),
+            tool_calls: None,
+            images: None,
+        },
+        done: delta.done.is_some(),
+        done_reason: delta.done.map(|reason| reason.ollama().to_string()),
+        total_duration: None,
+        load_duration: None,
+        prompt_eval_count: None,
+        prompt_eval_duration: None,
+        eval_count: None,
+        eval_duration: None,
+    };
+    let mut json = serde_json::to_string(&chunk_obj).expect("serialization cannot fail");
+    json.push('\n');
+    SseLineOutput::Line(json)
 }
 
 /// Transform CopilotChatResponse to OllamaChatResponse
@@ -302,16 +347,16 @@ fn transform_to_ollama_response(
     copilot_request: &CopilotChatRequest,
     copilot: CopilotChatResponse,
 ) -> Result<OllamaChatResponse, AppError> {
-    let choice = copilot.choices.first().ok_or_else(|| {
-        AppError::InternalServerError("No choices in Copilot response".to_string())
-    })?;
-
-    // Map finish_reason to done_reason
-    let done_reason = match choice.finish_reason.as_str() {
-        "stop" => Some("stop".to_string()),
-        "length" => Some("length".to_string()),
-        _ => Some(choice.finish_reason.clone()),
-    };
+    let choice = copilot
+        .choices
+        .first()
+        .ok_or_else(|| AppError::EmptyChoices("No choices in Copilot response".to_string()))?;
+
+    let done_reason = Some(
+        FinishReason::finished(Some(&choice.finish_reason), false)
+            .ollama()
+            .to_string(),
+    );
 
     // Create timestamp in RFC3339 format
     let created_at = if let Some(created) = copilot.created {
@@ -360,8 +405,13 @@ fn transform_to_ollama_response(
         created_at,
         message: OllamaMessage {
             role: choice.message.role.clone(),
-            content: choice.message.content.clone().unwrap_or_default(),
-            thinking: None,
+            content: choice
+                .message
+                .content
+                .as_ref()
+                .map(|content| content.to_text())
+                .unwrap_or_default(),
+            thinking: choice.message.reasoning_text.clone(),
             tool_calls: ollama_tool_calls,
             images: None,
         },
@@ -382,14 +432,16 @@ mod tests {
     use crate::copilot::CopilotMessage;
     use crate::openai::completion::models::FunctionDefinition;
     use crate::openai::completion::models::{OpenAIChatRequest, Tool};
+    use crate::server::metrics::Metrics;
     use crate::server::openai::chat_completion::{CopilotChoice, CopilotUsage};
+    use proptest::prelude::*;
 
     // -----------------------------------------------------------------------
     // translate_sse_line — streaming conversion tests
     // -----------------------------------------------------------------------
 
     fn parse_line(line: &str) -> OllamaChatResponse {
-        match translate_sse_line("llama3", line) {
+        match translate_sse_line("llama3", line, true, &mut ChatDeltas::default()) {
             SseLineOutput::Line(s) => {
                 serde_json::from_str(s.trim_end_matches('\n')).expect("valid JSON")
             }
@@ -399,7 +451,8 @@ mod tests {
 
     #[test]
     fn test_sse_done_emits_terminal_object() {
-        let result = translate_sse_line("my-model", "data: [DONE]");
+        let result =
+            translate_sse_line("my-model", "data: [DONE]", true, &mut ChatDeltas::default());
         let SseLineOutput::Line(json) = result else {
             panic!("expected Line");
         };
@@ -413,6 +466,22 @@ mod tests {
         assert_eq!(obj.message.role, "assistant");
     }
 
+    #[test]
+    fn test_sse_done_reason_follows_finish_reason() {
+        let mut deltas = ChatDeltas::default();
+        let payload =
+            r#"{"choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":"max_tokens"}]}"#;
+        let line = format!("data: {}", payload);
+        translate_sse_line("m", &line, true, &mut deltas);
+
+        let SseLineOutput::Line(json) = translate_sse_line("m", "data: [DONE]", true, &mut deltas)
+        else {
+            panic!("expected Line");
+        };
+        let obj: OllamaChatResponse = serde_json::from_str(json.trim_end()).unwrap();
+        assert_eq!(obj.done_reason, Some("length".to_string()));
+    }
+
     #[test]
     fn test_sse_content_chunk_emits_intermediate_object() {
         let payload = r#"{"id":"x","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"role":"assistant","content":"Hello"},"finish_reason":null}]}"#;
@@ -451,12 +520,33 @@ mod tests {
         assert_eq!(obj.message.content, "");
     }
 
+    #[test]
+    fn test_sse_reasoning_delta_maps_to_thinking() {
+        let payload =
+            r#"{"choices":[{"index":0,"delta":{"content":null,"reasoning_text":"Let me see"}}]}"#;
+        let line = format!("data: {}", payload);
+
+        let obj = parse_line(&line);
+        assert_eq!(obj.message.thinking.as_deref(), Some("Let me see"));
+        assert_eq!(obj.message.content, "");
+
+        let SseLineOutput::Line(s) =
+            translate_sse_line("m", &line, false, &mut ChatDeltas::default())
+        else {
+            panic!("expected Line");
+        };
+        let obj: OllamaChatResponse = serde_json::from_str(s.trim_end()).unwrap();
+        assert!(obj.message.thinking.is_none());
+    }
+
     #[test]
     fn test_sse_output_is_newline_terminated() {
         let payload = r#"{"id":"x","object":"chat.completion.chunk","created":1,"model":"m","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#;
         let line = format!("data: {}", payload);
 
-        let SseLineOutput::Line(s) = translate_sse_line("model", &line) else {
+        let SseLineOutput::Line(s) =
+            translate_sse_line("model", &line, true, &mut ChatDeltas::default())
+        else {
             panic!("expected Line");
         };
         assert!(s.ends_with('\n'));
@@ -464,22 +554,46 @@ mod tests {
 
     #[test]
     fn test_sse_empty_line_is_skipped() {
-        assert_eq!(translate_sse_line("m", ""), SseLineOutput::Skip);
-        assert_eq!(translate_sse_line("m", "   "), SseLineOutput::Skip);
-        assert_eq!(translate_sse_line("m", "\t"), SseLineOutput::Skip);
+        assert_eq!(
+            translate_sse_line("m", "", true, &mut ChatDeltas::default()),
+            SseLineOutput::Skip
+        );
+        assert_eq!(
+            translate_sse_line("m", "   ", true, &mut ChatDeltas::default()),
+            SseLineOutput::Skip
+        );
+        assert_eq!(
+            translate_sse_line("m", "\t", true, &mut ChatDeltas::default()),
+            SseLineOutput::Skip
+        );
     }
 
     #[test]
     fn test_sse_non_data_line_is_unexpected() {
-        match translate_sse_line("m", "event: ping") {
+        match translate_sse_line("m", "event: ping", true, &mut ChatDeltas::default()) {
             SseLineOutput::Unexpected(_) => {}
             other => panic!("expected Unexpected, got {:?}", other),
         }
     }
 
     #[test]
-    fn test_sse_malformed_json_is_unexpected() {
-        match translate_sse_line("m", "data: {not valid json}") {
+    fn test_sse_malformed_json_is_malformed() {
+        match translate_sse_line(
+            "m",
+            "data: {not valid json}",
+            true,
+            &mut ChatDeltas::default(),
+        ) {
+            SseLineOutput::Malformed(_) => {}
+            other => panic!("expected Malformed, got {:?}", other),
+        }
+        // JSON of another shape is skipped rather than failing the stream
+        match translate_sse_line(
+            "m",
+            r#"data: {"choices": "none"}"#,
+            true,
+            &mut ChatDeltas::default(),
+        ) {
             SseLineOutput::Unexpected(_) => {}
             other => panic!("expected Unexpected, got {:?}", other),
         }
@@ -552,6 +666,7 @@ mod tests {
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_text: None,
             }],
             model: "gpt-4".to_string(),
             temperature: None,
@@ -566,6 +681,13 @@ mod tests {
                 },
             }]),
             tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
         };
 
         let copilot_response = CopilotChatResponse {
@@ -576,19 +698,23 @@ mod tests {
                 index: Some(0),
                 message: CopilotMessage {
                     role: "assistant".to_string(),
-                    content: Some("Hello, World!".to_string()),
+                    content: Some("Hello, World!".into()),
                     padding: None,
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    reasoning_text: None,
                 },
+                logprobs: None,
                 finish_reason: "stop".to_string(),
+                content_filter_results: None,
             }],
             usage: Some(CopilotUsage {
                 prompt_tokens: 10,
                 completion_tokens: 5,
                 total_tokens: 15,
             }),
+            prompt_filter_results: None,
         };
 
         let result = transform_to_ollama_response(&copilot_request, copilot_response);
@@ -614,6 +740,7 @@ mod tests {
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_text: None,
             }],
             model: "model".to_string(),
             temperature: None,
@@ -628,6 +755,13 @@ mod tests {
                 },
             }]),
             tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
         };
 
         let copilot_response = CopilotChatResponse {
@@ -638,15 +772,19 @@ mod tests {
                 index: Some(0),
                 message: CopilotMessage {
                     role: "assistant".to_string(),
-                    content: Some("Test".to_string()),
+                    content: Some("Test".into()),
                     padding: None,
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    reasoning_text: None,
                 },
+                logprobs: None,
                 finish_reason: "length".to_string(),
+                content_filter_results: None,
             }],
             usage: None,
+            prompt_filter_results: None,
         };
 
         let result = transform_to_ollama_response(&copilot_request, copilot_response);
@@ -688,17 +826,25 @@ mod tests {
             model: model.to_string(),
             messages: vec![CopilotMessage {
                 role: "user".to_string(),
-                content: Some("Hello".to_string()),
+                content: Some("Hello".into()),
                 padding: None,
                 tool_calls: None,
                 tool_call_id: None,
                 name: None,
+                reasoning_text: None,
             }],
             temperature: None,
             max_tokens: None,
             stream: None,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
         }
     }
 
@@ -723,9 +869,14 @@ mod tests {
         let response = make_reqwest_response(body.to_string());
         let copilot_request = make_copilot_request("llama3");
 
-        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(copilot_request, response)
-            .await
-            .expect("should not error");
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(
+            copilot_request,
+            response,
+            ToolCallCheck::default(),
+            true,
+        )
+        .await
+        .expect("should not error");
 
         assert_eq!(result.status(), 200);
 
@@ -741,6 +892,41 @@ mod tests {
         assert_eq!(parsed.done_reason, Some("stop".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_no_sse_reasoning_maps_to_thinking() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-abc",
+            "model": "claude-sonnet-4",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "4",
+                    "reasoning_content": "2 + 2 is 4"
+                },
+                "finish_reason": "stop"
+            }]
+        });
+
+        for (thinking, expected) in [(true, Some("2 + 2 is 4")), (false, None)] {
+            let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(
+                make_copilot_request("claude-sonnet-4"),
+                make_reqwest_response(body.to_string()),
+                ToolCallCheck::default(),
+                thinking,
+            )
+            .await
+            .unwrap();
+
+            let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let parsed: OllamaChatResponse = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(parsed.message.thinking.as_deref(), expected);
+            assert_eq!(parsed.message.content, "4");
+        }
+    }
+
     #[tokio::test]
     async fn test_no_sse_usage_is_mapped() {
         let body = serde_json::json!({
@@ -758,9 +944,14 @@ mod tests {
         let response = make_reqwest_response(body.to_string());
         let copilot_request = make_copilot_request("llama3");
 
-        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(copilot_request, response)
-            .await
-            .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(
+            copilot_request,
+            response,
+            ToolCallCheck::default(),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -787,9 +978,14 @@ mod tests {
         let response = make_reqwest_response(body.to_string());
         let copilot_request = make_copilot_request("llama3");
 
-        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(copilot_request, response)
-            .await
-            .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(
+            copilot_request,
+            response,
+            ToolCallCheck::default(),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -816,9 +1012,14 @@ mod tests {
         let response = make_reqwest_response(body.to_string());
         let copilot_request = make_copilot_request("llama3");
 
-        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(copilot_request, response)
-            .await
-            .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_no_sse(
+            copilot_request,
+            response,
+            ToolCallCheck::default(),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -838,10 +1039,14 @@ mod tests {
         let body = format!("data: {chunk}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result =
-            <Server as OllamaChatEndpoint>::ollama_chat_sse("llama3".to_string(), response)
-                .await
-                .expect("should not error");
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_sse(
+            "llama3".to_string(),
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "ollama_chat", "gpt-4o"),
+            true,
+        )
+        .await
+        .expect("should not error");
 
         assert_eq!(result.status(), 200);
         let ct = result
@@ -862,10 +1067,14 @@ mod tests {
         let body = format!("data: {chunk}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result =
-            <Server as OllamaChatEndpoint>::ollama_chat_sse("llama3".to_string(), response)
-                .await
-                .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_sse(
+            "llama3".to_string(),
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "ollama_chat", "gpt-4o"),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -891,10 +1100,14 @@ mod tests {
         let body = format!("data: {chunk}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result =
-            <Server as OllamaChatEndpoint>::ollama_chat_sse("my-model".to_string(), response)
-                .await
-                .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_sse(
+            "my-model".to_string(),
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "ollama_chat", "gpt-4o"),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -918,10 +1131,14 @@ mod tests {
         let body = format!("data: {chunk1}\ndata: {chunk2}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result =
-            <Server as OllamaChatEndpoint>::ollama_chat_sse("llama3".to_string(), response)
-                .await
-                .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_sse(
+            "llama3".to_string(),
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "ollama_chat", "gpt-4o"),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -950,10 +1167,14 @@ mod tests {
         let body = format!("\ndata: {chunk}\n\ndata: [DONE]\n\n");
 
         let response = make_reqwest_response(body);
-        let result =
-            <Server as OllamaChatEndpoint>::ollama_chat_sse("llama3".to_string(), response)
-                .await
-                .unwrap();
+        let result = <Server as OllamaChatEndpoint>::ollama_chat_sse(
+            "llama3".to_string(),
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "ollama_chat", "gpt-4o"),
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -967,4 +1188,50 @@ mod tests {
         let done: OllamaChatResponse = serde_json::from_str(lines[1]).unwrap();
         assert!(done.done);
     }
+
+    proptest! {
+        #[test]
+        fn prop_sse_lines_translate_to_ndjson_lines(
+            lines in proptest::collection::vec(
+                prop_oneof![any::<String>(), any::<String>().prop_map(|s| format!("data: {}", s))],
+                0..8,
+            ),
+        ) {
+            let mut deltas = ChatDeltas::default();
+            for line in lines.iter().map(String::as_str).chain(["data: [DONE]"]) {
+                if let SseLineOutput::Line(json) = translate_sse_line("m", line, true, &mut deltas) {
+                    prop_assert!(json.ends_with('\n'));
+                    prop_assert_eq!(json.matches('\n').count(), 1);
+                    serde_json::from_str::<OllamaChatResponse>(&json).unwrap();
+                }
+            }
+        }
+
+        #[test]
+        fn prop_content_reaches_the_ndjson_lines(
+            pieces in proptest::collection::vec(any::<String>(), 0..8),
+        ) {
+            let mut deltas = ChatDeltas::default();
+            let mut content = String::new();
+            let mut done = false;
+            let lines = pieces
+                .iter()
+                .map(|piece| {
+                    let chunk = serde_json::json!({"choices": [{"delta": {"content": piece}}]});
+                    format!("data: {}", chunk)
+                })
+                .chain(["data: [DONE]".to_string()]);
+            for line in lines {
+                let SseLineOutput::Line(json) = translate_sse_line("m", &line, false, &mut deltas)
+                else {
+                    panic!("expected an NDJSON line for {}", line);
+                };
+                let chunk: OllamaChatResponse = serde_json::from_str(&json).unwrap();
+                content.push_str(&chunk.message.content);
+                done = chunk.done;
+            }
+            prop_assert!(done);
+            prop_assert_eq!(content, pieces.concat());
+        }
+    }
 }
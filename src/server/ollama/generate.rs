@@ -0,0 +1,579 @@
+use crate::copilot::completions::CopilotCompletionRequest;
+use crate::copilot::finish_reason::FinishReason;
+use crate::copilot::stream::{ChatDeltas, StreamLine};
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
+use crate::openai::completion::models::{OpenAIChatRequest, OpenAIMessage};
+use crate::server::admission::{ChatAdmission, PreparedChat, admit};
+use crate::server::cancellation::CancellableStream;
+use crate::server::capabilities::with_adjustments_header;
+use crate::server::copilot::{CopilotIntegration, upstream_headers, with_upstream_headers};
+use crate::server::keys::presented_key_id;
+use crate::server::ollama::chat::{OllamaDelta, ollama_error_line};
+use crate::server::openai::completions::{
+    CopilotTextCompletions, collect_completion, completion_chunks,
+};
+use crate::server::racing::ModelRacing;
+use crate::server::server_tools::auto_tools;
+use crate::server::session::{SESSION_ID_HEADER, with_session_header};
+use crate::server::sse_lines::SseLines;
+use crate::server::stream_errors::{end_with_error, read_error};
+use crate::server::stream_stats::StreamStats;
+use crate::server::users::tenant;
+use crate::server::{AppError, AppState, Server, client_ip};
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, extract::State};
+use futures_util::{StreamExt as _, TryStreamExt as _};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tracing::log::{error, info};
+
+/// How many past `/api/generate` turns are remembered for `context` continuity
+const MAX_TURNS: usize = 1024;
+
+/// `context` entries making up the random id of one turn
+const TURN_ID_WORDS: usize = 4;
+
+type TurnId = [u32; TURN_ID_WORDS];
+
+/// Ollama `/api/generate` request. Ollama streams unless `stream` is `false`.
+#[derive(Debug, Deserialize)]
+pub struct OllamaGenerateRequest {
+    pub model: String,
+    #[serde(default)]
+    pub prompt: String,
+    pub system: Option<String>,
+    /// Base64-encoded images for vision models
+    pub images: Option<Vec<String>>,
+    /// `context` of the previous response, to continue that conversation
+    pub context: Option<Vec<u32>>,
+    /// Code after the cursor: the prompt is completed in the middle, by Copilot's code
+    /// completion API
+    pub suffix: Option<String>,
+    /// Complete `prompt` as it is, by Copilot's code completion API
+    #[serde(default)]
+    pub raw: bool,
+    pub options: Option<OllamaOptions>,
+    #[serde(default = "default_stream")]
+    pub stream: bool,
+}
+
+fn default_stream() -> bool {
+    true
+}
+
+/// The Ollama model options Copilot has an equivalent for
+#[derive(Debug, Default, Deserialize)]
+pub struct OllamaOptions {
+    pub temperature: Option<f32>,
+    pub num_predict: Option<u32>,
+    pub stop: Option<Vec<String>>,
+}
+
+/// Ollama `/api/generate` response, or one line of it when streaming
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaGenerateResponse {
+    pub model: String,
+    pub created_at: String,
+    pub response: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    pub done: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub done_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_eval_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eval_count: Option<u32>,
+}
+
+impl OllamaGenerateResponse {
+    fn new(model: &str, response: String) -> Self {
+        OllamaGenerateResponse {
+            model: model.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            response,
+            thinking: None,
+            done: false,
+            done_reason: None,
+            context: None,
+            prompt_eval_count: None,
+            eval_count: None,
+        }
+    }
+}
+
+/// One prompt and its answer, and the caller they belong to
+#[derive(Debug, Clone, PartialEq)]
+struct Turn {
+    owner: String,
+    prompt: String,
+    response: String,
+}
+
+/// Past `/api/generate` turns by the random id their response appended to `context`.
+///
+/// Ollama's `context` is the conversation's token ids, which older clients send back
+/// to continue it. Here it is the previous `context` followed by a 128-bit id per turn,
+/// so earlier turns can be replayed as chat messages while they are remembered. A turn
+/// is only replayed to the caller it was generated for.
+#[derive(Debug, Default)]
+pub struct GenerateContexts {
+    turns: Mutex<(HashMap<TurnId, Turn>, VecDeque<TurnId>)>,
+}
+
+impl GenerateContexts {
+    /// Remember a turn of `owner`, returning the `context` of its response
+    pub fn record(&self, owner: &str, context: &[u32], prompt: &str, response: &str) -> Vec<u32> {
+        let mut bytes = [0u8; TURN_ID_WORDS * 4];
+        aws_lc_rs::rand::fill(&mut bytes).expect("system random source is available");
+        let id: TurnId = std::array::from_fn(|word| {
+            u32::from_le_bytes(bytes[word * 4..word * 4 + 4].try_into().expect("4 bytes"))
+        });
+
+        let mut turns = self.turns.lock().expect("generate contexts lock poisoned");
+        let (by_id, order) = &mut *turns;
+        by_id.insert(
+            id,
+            Turn {
+                owner: owner.to_string(),
+                prompt: prompt.to_string(),
+                response: response.to_string(),
+            },
+        );
+        order.push_back(id);
+        while order.len() > MAX_TURNS {
+            if let Some(oldest) = order.pop_front() {
+                by_id.remove(&oldest);
+            }
+        }
+
+        let mut next = context.to_vec();
+        next.extend(id);
+        next
+    }
+
+    /// The remembered turns of `owner` in `context` as user and assistant messages.
+    /// Unknown entries, such as real Ollama token ids, forgotten turns or the turns of
+    /// another caller, are skipped.
+    pub fn messages(&self, owner: &str, context: &[u32]) -> Vec<OpenAIMessage> {
+        let turns = self.turns.lock().expect("generate contexts lock poisoned");
+        let mut messages = Vec::new();
+        let mut rest = context;
+        while rest.len() >= TURN_ID_WORDS {
+            let id: TurnId = rest[..TURN_ID_WORDS].try_into().expect("a whole turn id");
+            match turns.0.get(&id).filter(|turn| turn.owner == owner) {
+                Some(turn) => {
+                    messages.push(message("user", &turn.prompt, None));
+                    messages.push(message("assistant", &turn.response, None));
+                    rest = &rest[TURN_ID_WORDS..];
+                }
+                None => rest = &rest[1..],
+            }
+        }
+
+        messages
+    }
+}
+
+/// Who a request's `/api/generate` turns belong to: its OIDC user, API key, pinned
+/// session or, failing those, its client address
+fn context_owner(headers: &HeaderMap) -> String {
+    if let Some(tenant) = tenant() {
+        return tenant;
+    }
+    if let Some(key_id) = presented_key_id(headers) {
+        return format!("key:{}", key_id);
+    }
+    if let Some(session_id) = headers
+        .get(SESSION_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    {
+        return format!("session:{}", session_id);
+    }
+
+    client_ip::current()
+        .map(|ip| format!("ip:{}", ip))
+        .unwrap_or_default()
+}
+
+fn message(role: &str, content: &str, images: Option<Vec<String>>) -> OpenAIMessage {
+    OpenAIMessage {
+        role: role.to_string(),
+        content: Some(content.to_string()),
+        tool_calls: None,
+        tool_call_id: None,
+        name: None,
+        images,
+        reasoning_content: None,
+        reasoning: None,
+    }
+}
+
+impl OllamaGenerateRequest {
+    /// The equivalent chat request, with the turns of `owner` in `context` replayed
+    /// before the prompt
+    fn into_chat_request(self, contexts: &GenerateContexts, owner: &str) -> OpenAIChatRequest {
+        let mut messages = Vec::new();
+        if let Some(system) = &self.system {
+            messages.push(message("system", system, None));
+        }
+        messages.extend(contexts.messages(owner, self.context.as_deref().unwrap_or_default()));
+        messages.push(message("user", &self.prompt, self.images));
+
+        let options = self.options.unwrap_or_default();
+        OpenAIChatRequest {
+            model: self.model,
+            messages,
+            stream: self.stream,
+            temperature: options.temperature,
+            max_tokens: options.num_predict,
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            user: None,
+            n: None,
+            best_of: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+        }
+    }
+}
+
+pub(crate) trait OllamaGenerateEndpoint:
+    CopilotIntegration + CopilotTextCompletions
+{
+    async fn ollama_generate(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        request: Json<OllamaGenerateRequest>,
+    ) -> Result<Response, AppError>;
+
+    /// `raw` and `suffix` requests, completed by `[copilot.completions]` rather than chat
+    async fn ollama_generate_completion(
+        state: Arc<AppState>,
+        session_id: String,
+        request: OllamaGenerateRequest,
+    ) -> Result<Response, AppError>;
+}
+
+impl OllamaGenerateEndpoint for Server {
+    async fn ollama_generate(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        request: Json<OllamaGenerateRequest>,
+    ) -> Result<Response, AppError> {
+        let request = request.0;
+        info!(
+            "Received Ollama generate request for model: {} (stream={})",
+            request.model, request.stream
+        );
+
+        let is_stream = request.stream;
+        let context = request.context.clone().unwrap_or_default();
+        let prompt = request.prompt.clone();
+        let stop = request.options.as_ref().and_then(|o| o.stop.clone());
+        let admission = admit(&state, &headers, None)?;
+        let session_id = admission.session_id.clone();
+        if request.raw || request.suffix.is_some() {
+            return Self::ollama_generate_completion(state, session_id, request).await;
+        }
+
+        let owner = context_owner(&headers);
+        let mut copilot_request: CopilotChatRequest = request
+            .into_chat_request(&state.generate_contexts, &owner)
+            .into();
+        copilot_request.stop = stop;
+        let PreparedChat {
+            state,
+            token,
+            adjustments,
+        } = Self::prepare_chat(state, &headers, &admission, &mut copilot_request).await?;
+        let config = state.config();
+
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+        let stats = StreamStats::new(
+            state.metrics.clone(),
+            "ollama_generate",
+            &copilot_request.model,
+        );
+        let auto_tools = auto_tools(&config.mcp, &headers);
+        let response = Self::forward_raced(
+            state.clone(),
+            token,
+            copilot_url,
+            &copilot_request,
+            &session_id,
+            is_stream,
+            auto_tools,
+        )
+        .await?;
+        stats.served_by(&response);
+
+        if !response.status().is_success() {
+            return Self::handle_errors(response).await;
+        }
+        let upstream = upstream_headers(&config.copilot.response_headers, response.headers());
+
+        let model = copilot_request.model;
+        let response = if is_stream {
+            generate_stream(state, model, owner, context, prompt, response, stats)
+        } else {
+            let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+                error!("Failed to parse Copilot response: {}", e);
+                AppError::upstream("Failed to parse Copilot response", e)
+            })?;
+            let choice = copilot_response.choices.first().ok_or_else(|| {
+                AppError::EmptyChoices("No choices in Copilot response".to_string())
+            })?;
+            let text = choice
+                .message
+                .content
+                .as_ref()
+                .map(|content| content.to_text())
+                .unwrap_or_default();
+
+            let mut generated = OllamaGenerateResponse::new(&model, String::new());
+            generated.done = true;
+            generated.done_reason = Some(
+                FinishReason::finished(Some(&choice.finish_reason), false)
+                    .ollama()
+                    .to_string(),
+            );
+            generated.context = Some(
+                state
+                    .generate_contexts
+                    .record(&owner, &context, &prompt, &text),
+            );
+            generated.prompt_eval_count = copilot_response.usage.as_ref().map(|u| u.prompt_tokens);
+            generated.eval_count = copilot_response.usage.as_ref().map(|u| u.completion_tokens);
+            generated.response = text;
+            generated.thinking = choice
+                .message
+                .reasoning_text
+                .clone()
+                .filter(|_| config.ollama.thinking);
+
+            info!("Successfully processed Ollama generate request");
+            Ok(Json(generated).into_response())
+        };
+
+        response.map(|response| {
+            let response = with_upstream_headers(response, upstream);
+            with_adjustments_header(with_session_header(response, &session_id), &adjustments)
+        })
+    }
+
+    async fn ollama_generate_completion(
+        state: Arc<AppState>,
+        session_id: String,
+        request: OllamaGenerateRequest,
+    ) -> Result<Response, AppError> {
+        let options = request.options.unwrap_or_default();
+        let mut completion = CopilotCompletionRequest::new(request.prompt, request.suffix);
+        completion.max_tokens = options.num_predict.unwrap_or(completion.max_tokens);
+        completion.temperature = options.temperature.unwrap_or(completion.temperature);
+        completion.stop = options.stop.unwrap_or_default();
+
+        let response = Self::forward_completion(state.clone(), &completion, &session_id).await?;
+        if !response.status().is_success() {
+            return Self::handle_errors(response).await;
+        }
+
+        let model = request.model;
+        let stats = StreamStats::new(state.metrics.clone(), "ollama_generate", &model);
+        let chunks = completion_chunks(response, stats.clone());
+        let response = if request.stream {
+            // The last chunk's finish reason, for the closing line
+            let finished = Arc::new(Mutex::new(None));
+            let lines = chunks
+                .map_ok({
+                    let model = model.clone();
+                    let finished = finished.clone();
+                    move |chunk| {
+                        let choice = chunk.choices.into_iter().next();
+                        if let Some(reason) = choice
+                            .as_ref()
+                            .and_then(|choice| FinishReason::parse(choice.finish_reason.as_deref()))
+                        {
+                            *finished.lock().unwrap() = Some(reason);
+                        }
+                        let text = choice.map(|choice| choice.text).unwrap_or_default();
+                        OllamaGenerateResponse::new(&model, text)
+                    }
+                })
+                .chain(futures_util::stream::once(async move {
+                    let finished = *finished.lock().unwrap();
+                    let mut done = OllamaGenerateResponse::new(&model, String::new());
+                    done.done = true;
+                    done.done_reason =
+                        Some(finished.unwrap_or(FinishReason::Stop).ollama().to_string());
+                    Ok(done)
+                }))
+                .map_ok(|line| {
+                    let mut json = serde_json::to_string(&line).expect("serialization cannot fail");
+                    json.push('\n');
+                    Bytes::from(json)
+                });
+            info!("Streaming Ollama raw generate response");
+            let lines = end_with_error(lines, ollama_error_line);
+            let lines = CancellableStream::new(lines, stats);
+            (
+                [(header::CONTENT_TYPE, "application/x-ndjson")],
+                Body::from_stream(lines),
+            )
+                .into_response()
+        } else {
+            let text = collect_completion(chunks).await?;
+            stats.finish(true);
+            let mut generated = OllamaGenerateResponse::new(&model, text.text().to_string());
+            generated.done = true;
+            generated.done_reason = Some(
+                FinishReason::finished(text.finish_reason(), false)
+                    .ollama()
+                    .to_string(),
+            );
+            info!("Successfully processed Ollama raw generate request");
+            Json(generated).into_response()
+        };
+        Ok(with_session_header(response, &session_id))
+    }
+}
+
+/// Re-emit Copilot's SSE deltas as Ollama generate NDJSON lines. The final line
+/// carries the `context` of the whole answer.
+fn generate_stream(
+    state: Arc<AppState>,
+    model: String,
+    owner: String,
+    context: Vec<u32>,
+    prompt: String,
+    response: reqwest::Response,
+    stats: Arc<StreamStats>,
+) -> Result<Response, AppError> {
+    let byte_stream = response.bytes_stream().map_err(read_error);
+
+    let thinking = state.config().ollama.thinking;
+    let mut answer = String::new();
+    let mut deltas = ChatDeltas::default();
+    let ndjson_stream = SseLines::new(byte_stream)
+        .inspect_ok({
+            let stats = stats.clone();
+            move |line| stats.observe_line(line)
+        })
+        .try_filter_map(move |line| {
+            let delta = match deltas.line(&line) {
+                StreamLine::Deltas(deltas) => OllamaDelta::from(deltas),
+                StreamLine::Skip | StreamLine::Unexpected(_) => {
+                    return futures_util::future::ready(Ok(None));
+                }
+                StreamLine::Malformed(e) => return futures_util::future::ready(Err(e)),
+            };
+            let line = match delta.done {
+                Some(reason) => {
+                    let mut done = OllamaGenerateResponse::new(&model, String::new());
+                    done.done = true;
+                    done.done_reason = Some(reason.ollama().to_string());
+                    done.context = Some(
+                        state
+                            .generate_contexts
+                            .record(&owner, &context, &prompt, &answer),
+                    );
+                    Some(done)
+                }
+                None => {
+                    answer.push_str(&delta.content);
+                    let mut line = OllamaGenerateResponse::new(&model, delta.content);
+                    line.thinking = delta.thinking.filter(|_| thinking);
+                    Some(line)
+                }
+            };
+
+            let bytes = line.map(|line| {
+                let mut json = serde_json::to_string(&line).expect("serialization cannot fail");
+                json.push('\n');
+                Bytes::from(json)
+            });
+            futures_util::future::ready(Ok(bytes))
+        });
+
+    info!("Streaming Ollama generate response");
+    let ndjson_stream = end_with_error(ndjson_stream, ollama_error_line);
+    let ndjson_stream = CancellableStream::new(ndjson_stream, stats);
+    let body = Body::from_stream(ndjson_stream);
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_replays_remembered_turns() {
+        let contexts = GenerateContexts::default();
+
+        let first = contexts.record("key:a", &[], "Hi", "Hello!");
+        let second = contexts.record("key:a", &first, "Who are you?", "A model.");
+        assert_eq!(first.len(), TURN_ID_WORDS);
+        assert_eq!(second[..TURN_ID_WORDS], first[..]);
+
+        // Unknown entries, e.g. real Ollama token ids, are echoed but not replayed
+        let mut context = vec![42];
+        context.extend(&second);
+        let messages = contexts.messages("key:a", &context);
+        let contents: Vec<_> = messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                ("user", "Hi"),
+                ("assistant", "Hello!"),
+                ("user", "Who are you?"),
+                ("assistant", "A model."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_context_is_only_replayed_to_its_owner() {
+        let contexts = GenerateContexts::default();
+        let context = contexts.record("key:a", &[], "Secret", "Reply");
+
+        assert!(contexts.messages("key:b", &context).is_empty());
+        assert!(contexts.messages("", &context).is_empty());
+        assert_eq!(contexts.messages("key:a", &context).len(), 2);
+    }
+
+    #[test]
+    fn test_into_chat_request() {
+        let contexts = GenerateContexts::default();
+        let context = contexts.record("", &[], "Hi", "Hello!");
+        let request: OllamaGenerateRequest = serde_json::from_value(serde_json::json!({
+            "model": "gpt-4o",
+            "prompt": "Again",
+            "system": "Be brief",
+            "context": context,
+            "options": { "temperature": 0.2, "num_predict": 64 }
+        }))
+        .unwrap();
+        assert!(request.stream);
+
+        let chat = request.into_chat_request(&contexts, "");
+
+        let roles: Vec<_> = chat.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user"]);
+        assert_eq!(chat.messages[3].content.as_deref(), Some("Again"));
+        assert_eq!(chat.temperature, Some(0.2));
+        assert_eq!(chat.max_tokens, Some(64));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::server::Server;
+use axum::Json;
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use tracing::log::info;
+
+/// Body of `/api/pull`, `/api/delete` and `/api/create`. Older clients send `name`.
+#[derive(Debug, Default, Deserialize)]
+pub struct OllamaModelRequest {
+    #[serde(default, alias = "name")]
+    pub model: String,
+    #[serde(default)]
+    pub stream: Option<bool>,
+}
+
+/// One line of Ollama's pull and create progress
+#[derive(Debug, Serialize)]
+pub struct OllamaProgress {
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
+
+impl OllamaProgress {
+    fn status(status: &str) -> Self {
+        OllamaProgress {
+            status: status.to_string(),
+            digest: None,
+            total: None,
+            completed: None,
+        }
+    }
+
+    fn download(model: &str, completed: u64) -> Self {
+        OllamaProgress {
+            status: format!("pulling {}", model),
+            digest: Some(model.to_string()),
+            total: Some(1),
+            completed: Some(completed),
+        }
+    }
+}
+
+/// Model management stubs for Ollama clients, under `[ollama] model_management`.
+/// Copilot models need no pulling, so these succeed immediately without doing anything.
+#[allow(async_fn_in_trait)]
+pub trait OllamaModelManagement {
+    async fn ollama_pull(request: Json<OllamaModelRequest>) -> Response;
+
+    async fn ollama_delete(request: Json<OllamaModelRequest>) -> StatusCode;
+
+    async fn ollama_create(request: Json<OllamaModelRequest>) -> Response;
+}
+
+impl OllamaModelManagement for Server {
+    async fn ollama_pull(Json(request): Json<OllamaModelRequest>) -> Response {
+        info!(
+            "Received ollama pull request for {}, nothing to pull",
+            request.model
+        );
+        progress_response(
+            request.stream,
+            vec![
+                OllamaProgress::status("pulling manifest"),
+                OllamaProgress::download(&request.model, 0),
+                OllamaProgress::download(&request.model, 1),
+                OllamaProgress::status("verifying sha256 digest"),
+                OllamaProgress::status("writing manifest"),
+                OllamaProgress::status("success"),
+            ],
+        )
+    }
+
+    async fn ollama_delete(Json(request): Json<OllamaModelRequest>) -> StatusCode {
+        info!(
+            "Received ollama delete request for {}, nothing to delete",
+            request.model
+        );
+        StatusCode::OK
+    }
+
+    async fn ollama_create(Json(request): Json<OllamaModelRequest>) -> Response {
+        info!(
+            "Received ollama create request for {}, nothing to create",
+            request.model
+        );
+        progress_response(
+            request.stream,
+            vec![
+                OllamaProgress::status("reading model metadata"),
+                OllamaProgress::status("writing manifest"),
+                OllamaProgress::status("success"),
+            ],
+        )
+    }
+}
+
+/// NDJSON progress when streaming, which Ollama does by default, or the final status
+fn progress_response(stream: Option<bool>, mut progress: Vec<OllamaProgress>) -> Response {
+    if stream == Some(false) {
+        let last = progress
+            .pop()
+            .unwrap_or_else(|| OllamaProgress::status("success"));
+        return Json(last).into_response();
+    }
+
+    let body: String = progress
+        .iter()
+        .filter_map(|line| serde_json::to_string(line).ok())
+        .map(|line| line + "\n")
+        .collect();
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from(body),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_lines(response: Response) -> Vec<serde_json::Value> {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_pull_streams_progress_then_success() {
+        let request: OllamaModelRequest = serde_json::from_str(r#"{"name": "gpt-4o"}"#).unwrap();
+
+        let response = Server::ollama_pull(Json(request)).await;
+
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        let lines = body_lines(response).await;
+        assert_eq!(lines[0]["status"], "pulling manifest");
+        assert_eq!(lines[2]["completed"], lines[2]["total"]);
+        assert_eq!(lines.last().unwrap()["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_create_without_stream_returns_success() {
+        let request = OllamaModelRequest {
+            model: "mine".to_string(),
+            stream: Some(false),
+        };
+
+        let response = Server::ollama_create(Json(request)).await;
+
+        assert_eq!(
+            body_lines(response).await,
+            vec![serde_json::json!({"status": "success"})]
+        );
+    }
+}
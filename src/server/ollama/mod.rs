@@ -1,3 +1,6 @@
 pub mod chat;
+pub mod generate;
+pub mod manage;
+pub mod show;
 pub mod tags;
 pub mod version;
@@ -0,0 +1,159 @@
+use crate::config::ModelDisplay;
+use crate::copilot::models::CopilotModel;
+use crate::copilot::models::aliases::resolve_model_id;
+use crate::server::ollama::tags::OllamaModelDetails;
+use crate::server::openai::list_models::CoPilotListModels;
+use crate::server::{AppError, AppState, Server};
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tracing::log::info;
+
+/// Body of `/api/show`. Older clients send `name`.
+#[derive(Deserialize)]
+pub struct OllamaShowRequest {
+    #[serde(alias = "name")]
+    pub model: String,
+}
+
+#[derive(Serialize)]
+pub struct OllamaShowResponse {
+    pub modelfile: String,
+    pub parameters: String,
+    pub template: String,
+    pub details: OllamaModelDetails,
+    /// GGUF-style metadata: architecture, display name, description and context length
+    pub model_info: BTreeMap<String, serde_json::Value>,
+    pub capabilities: Vec<&'static str>,
+}
+
+#[allow(async_fn_in_trait)]
+pub trait OllamaShow {
+    async fn ollama_show(
+        state: State<Arc<AppState>>,
+        request: Json<OllamaShowRequest>,
+    ) -> Result<Json<OllamaShowResponse>, AppError>;
+}
+
+impl OllamaShow for Server {
+    async fn ollama_show(
+        State(state): State<Arc<AppState>>,
+        Json(request): Json<OllamaShowRequest>,
+    ) -> Result<Json<OllamaShowResponse>, AppError> {
+        info!("Received ollama show request for {}", request.model);
+        let config = state.config();
+        let models = &config.models;
+        let id = models
+            .id_for_display_name(&request.model)
+            .unwrap_or(&request.model);
+        // A virtual model is shown as the model it stands for
+        let name = config
+            .virtual_models
+            .get(id)
+            .map_or(id, |virtual_model| virtual_model.model.as_str());
+
+        let copilot_response = Self::copilot_models(state.clone()).await?;
+        let catalogue: HashMap<String, &CopilotModel> = copilot_response
+            .models
+            .iter()
+            .map(|model| (model.id.clone(), model))
+            .collect();
+        let model = resolve_model_id(name, &models.aliases, &catalogue)
+            .and_then(|id| catalogue.get(&id).copied())
+            .ok_or_else(|| AppError::ModelNotFound(request.model.clone()))?;
+
+        let display = models.display(id).or_else(|| models.display(&model.id));
+        Ok(Json(show_response(model, display)))
+    }
+}
+
+fn show_response(model: &CopilotModel, display: Option<&ModelDisplay>) -> OllamaShowResponse {
+    let mut model_info = BTreeMap::from([
+        (
+            "general.architecture".to_string(),
+            model.family.clone().into(),
+        ),
+        (
+            "general.basename".to_string(),
+            display
+                .and_then(|display| display.name.clone())
+                .unwrap_or_else(|| model.name.clone())
+                .into(),
+        ),
+    ]);
+    if let Some(description) = display.and_then(|display| display.description.clone()) {
+        model_info.insert("general.description".to_string(), description.into());
+    }
+    if model.limit.context > 0 {
+        model_info.insert(
+            format!("{}.context_length", model.family),
+            model.limit.context.into(),
+        );
+    }
+
+    let mut capabilities = vec!["completion"];
+    if model.tool_call {
+        capabilities.push("tools");
+    }
+    if model.vision() {
+        capabilities.push("vision");
+    }
+    if model.reasoning {
+        capabilities.push("thinking");
+    }
+
+    OllamaShowResponse {
+        modelfile: String::new(),
+        parameters: String::new(),
+        template: String::new(),
+        details: OllamaModelDetails::from(model),
+        model_info,
+        capabilities,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_show_response() {
+        let model: CopilotModel = serde_json::from_value(serde_json::json!({
+            "id": "claude-sonnet-4.5",
+            "name": "Claude Sonnet 4.5",
+            "family": "claude-sonnet",
+            "tool_call": true,
+            "reasoning": true,
+            "modalities": { "input": ["text", "image"], "output": ["text"] },
+            "limit": { "context": 200000, "output": 64000 }
+        }))
+        .unwrap();
+        let display = ModelDisplay {
+            name: Some("Claude Sonnet 4.5 (work)".to_string()),
+            description: Some("Best for code review".to_string()),
+            order: None,
+        };
+
+        let response = show_response(&model, Some(&display));
+
+        assert_eq!(
+            response.capabilities,
+            vec!["completion", "tools", "vision", "thinking"]
+        );
+        assert_eq!(
+            response.model_info["general.basename"],
+            "Claude Sonnet 4.5 (work)"
+        );
+        assert_eq!(
+            response.model_info["general.description"],
+            "Best for code review"
+        );
+        assert_eq!(response.model_info["claude-sonnet.context_length"], 200000);
+        assert_eq!(response.details.family, "claude-sonnet");
+
+        let response = show_response(&model, None);
+        assert_eq!(response.model_info["general.basename"], "Claude Sonnet 4.5");
+        assert!(!response.model_info.contains_key("general.description"));
+    }
+}
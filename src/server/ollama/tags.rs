@@ -1,6 +1,9 @@
-use crate::copilot::models::CopilotModelsResponse;
+use crate::copilot::models::{CopilotModel, CopilotModelsResponse, ModelFilter};
 use crate::server::{AppError, AppState, Server};
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    extract::{Query, State},
+};
 use serde::Serialize;
 use std::sync::Arc;
 use tracing::log::{error, info};
@@ -30,35 +33,50 @@ pub struct OllamaModelDetails {
     pub quantization_level: String,
 }
 
+impl From<&CopilotModel> for OllamaModelDetails {
+    fn from(model: &CopilotModel) -> Self {
+        OllamaModelDetails {
+            parent_model: String::new(),
+            format: "api".to_string(),
+            family: model.family.clone(),
+            families: vec![model.family.clone()],
+            parameter_size: String::new(),
+            quantization_level: String::new(),
+        }
+    }
+}
+
 #[allow(async_fn_in_trait)]
 pub trait OllamaTags {
-    async fn ollama_tags(state: State<Arc<AppState>>)
-    -> Result<Json<OllamaTagsResponse>, AppError>;
+    async fn ollama_tags(
+        state: State<Arc<AppState>>,
+        filter: Query<ModelFilter>,
+    ) -> Result<Json<OllamaTagsResponse>, AppError>;
 }
 
 impl OllamaTags for Server {
     async fn ollama_tags(
         State(state): State<Arc<AppState>>,
+        Query(filter): Query<ModelFilter>,
     ) -> Result<Json<OllamaTagsResponse>, AppError> {
         info!("Received ollama tags request");
+        filter.validate().map_err(AppError::BadRequest)?;
 
         let token = Self::get_token(state.clone()).await?;
 
         let response = state
             .client
-            .get(&state.config.github.copilot_models_url)
+            .get(&state.config().github.copilot_models_url)
             .header("Authorization", format!("Bearer {}", token.token))
             .header("Content-Type", "application/json")
             .header("Accept", "application/vnd.github+json")
             .header("X-GitHub-Api-Version", "2022-11-28")
+            .timeout(state.config().copilot.timeouts.total(false))
             .send()
             .await
             .map_err(|e| {
                 error!("Failed to send request to Copilot API: {}", e);
-                AppError::InternalServerError(format!(
-                    "Failed to communicate with Copilot API: {}",
-                    e
-                ))
+                AppError::upstream("Failed to communicate with Copilot API", e)
             })?;
 
         let status = response.status();
@@ -68,34 +86,54 @@ impl OllamaTags for Server {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Copilot API returned error: {} - {}", status, error_text);
-            return Err(AppError::InternalServerError(format!(
-                "Copilot API error: {} - {}",
-                status, error_text
-            )));
+            return Err(AppError::from_upstream_status(status, &error_text));
         }
 
         let copilot_response: CopilotModelsResponse = response.json().await.map_err(|e| {
             error!("Failed to parse Copilot response: {}", e);
-            AppError::InternalServerError(format!("Failed to parse Copilot response: {}", e))
+            AppError::upstream("Failed to parse Copilot response", e)
         })?;
 
-        let models = copilot_response
+        let config = state.config();
+        let display = &config.models;
+        let matching: Vec<_> = copilot_response
             .models
+            .iter()
+            .filter(|m| filter.matches(m))
+            .collect();
+        // Virtual models are listed with the details of the model they stand for
+        let mut models: Vec<(&str, &CopilotModel)> = matching
+            .iter()
+            .map(|m| (m.id.as_str(), *m))
+            .chain(
+                config
+                    .virtual_models
+                    .iter()
+                    .filter_map(|(id, virtual_model)| {
+                        let m = matching.iter().find(|m| m.id == virtual_model.model)?;
+                        Some((id.as_str(), *m))
+                    }),
+            )
+            .collect();
+        models.sort_by(|(a, _), (b, _)| {
+            display
+                .display_order(a)
+                .cmp(&display.display_order(b))
+                .then_with(|| a.cmp(b))
+        });
+        let models = models
             .into_iter()
-            .map(|m| OllamaModel {
-                name: m.id.clone(),
-                model: m.id,
+            .map(|(id, m)| OllamaModel {
+                // Clients send the name back as the model, which resolves to the id
+                name: display
+                    .display(id)
+                    .and_then(|display| display.name.clone())
+                    .unwrap_or_else(|| id.to_string()),
+                model: id.to_string(),
                 modified_at: "1970-01-01T00:00:00Z".to_string(),
                 size: 0,
                 digest: String::new(),
-                details: OllamaModelDetails {
-                    parent_model: String::new(),
-                    format: "api".to_string(),
-                    family: m.family.clone(),
-                    families: vec![m.family],
-                    parameter_size: String::new(),
-                    quantization_level: String::new(),
-                },
+                details: OllamaModelDetails::from(m),
             })
             .collect();
 
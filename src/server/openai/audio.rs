@@ -0,0 +1,128 @@
+use crate::server::openai::passthrough::forward_to_backend;
+use crate::server::{AppError, AppState, Server};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use std::sync::Arc;
+
+/// OpenAI audio endpoints, forwarded to `[audio] backend_url` when one is configured.
+///
+/// Copilot has no audio models. Frontends such as Open WebUI probe these endpoints, so
+/// without a backend they answer a clean `501` rather than failing to connect.
+pub(crate) trait AudioEndpoints {
+    /// `POST /v1/audio/speech`
+    async fn audio_speech(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError>;
+
+    /// `POST /v1/audio/transcriptions`
+    async fn audio_transcriptions(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError>;
+}
+
+impl AudioEndpoints for Server {
+    async fn audio_speech(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError> {
+        forward_to_backend(
+            &state,
+            &state.config().audio,
+            "audio/speech",
+            &headers,
+            body,
+        )
+        .await
+    }
+
+    async fn audio_transcriptions(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError> {
+        forward_to_backend(
+            &state,
+            &state.config().audio,
+            "audio/transcriptions",
+            &headers,
+            body,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::http::header;
+    use axum::response::IntoResponse;
+    use wiremock::matchers::{body_string, header as header_is, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn state(backend_url: Option<String>) -> Arc<AppState> {
+        let mut config = Config::default();
+        config.audio.backend_url = backend_url;
+        config.audio.api_key = Some("secret".to_string());
+        Arc::new(AppState::new(&config, None))
+    }
+
+    #[tokio::test]
+    async fn test_no_backend_is_501() {
+        let result = Server::audio_speech(
+            State(state(None)),
+            HeaderMap::new(),
+            Bytes::from_static(b"{}"),
+        )
+        .await;
+
+        let response = result.unwrap_err().into_response();
+        assert_eq!(response.status(), 501);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "not_implemented");
+    }
+
+    #[tokio::test]
+    async fn test_forwards_to_backend() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/audio/speech"))
+            .and(header_is("authorization", "Bearer secret"))
+            .and(body_string(r#"{"input":"Hi"}"#))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "audio/mpeg")
+                    .set_body_bytes(b"ID3".to_vec()),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+        let response = Server::audio_speech(
+            State(state(Some(format!("{}/v1/", mock_server.uri())))),
+            headers,
+            Bytes::from_static(br#"{"input":"Hi"}"#),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "audio/mpeg");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"ID3");
+    }
+}
@@ -0,0 +1,166 @@
+use crate::config::AzureConfig;
+use crate::openai::completion::models::OpenAIChatRequest;
+use crate::server::openai::chat_completion::CoPilotChatCompletions;
+use crate::server::{AppError, AppState, Server, constant_time_eq};
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, header};
+use axum::response::Response;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::log::{error, info};
+
+/// Header Azure OpenAI clients send their key in
+pub const AZURE_API_KEY_HEADER: &str = "api-key";
+
+/// Azure OpenAI path scheme: `/openai/deployments/{deployment}/chat/completions`.
+///
+/// The deployment is mapped to a Copilot model through `[azure.deployments]`, then the
+/// request runs through the regular chat completions pipeline. `api-version` is ignored.
+pub(crate) trait AzureChatCompletions: CoPilotChatCompletions {
+    async fn azure_chat_completions(
+        state: State<Arc<AppState>>,
+        deployment: Path<String>,
+        headers: HeaderMap,
+        body: Json<Value>,
+    ) -> Result<Response, AppError>;
+}
+
+impl AzureChatCompletions for Server {
+    async fn azure_chat_completions(
+        State(state): State<Arc<AppState>>,
+        Path(deployment): Path<String>,
+        headers: HeaderMap,
+        Json(body): Json<Value>,
+    ) -> Result<Response, AppError> {
+        let config = state.config();
+        check_api_key(&config.azure, &headers)?;
+
+        let model = deployment_model(&config.azure, &deployment);
+        info!(
+            "Received Azure chat completion request for deployment {} (model: {})",
+            deployment, model
+        );
+
+        let request = with_model(body, model)?;
+
+        Self::chat_completions(State(state), headers, Json(request)).await
+    }
+}
+
+/// When `[azure] api_key` is set, require it in `api-key` (or as a bearer token)
+fn check_api_key(azure: &AzureConfig, headers: &HeaderMap) -> Result<(), AppError> {
+    let Some(expected) = azure.api_key.as_deref() else {
+        return Ok(());
+    };
+
+    let provided = headers
+        .get(AZURE_API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        });
+
+    match provided {
+        Some(key) if constant_time_eq(key.as_bytes(), expected.as_bytes()) => Ok(()),
+        _ => {
+            error!("Rejecting Azure request with a missing or invalid api-key");
+            Err(AppError::Unauthorized(
+                "Access denied due to invalid subscription key".to_string(),
+            ))
+        }
+    }
+}
+
+/// Copilot model for a deployment; unmapped deployments are taken to be model names
+fn deployment_model<'a>(azure: &'a AzureConfig, deployment: &'a str) -> &'a str {
+    azure
+        .deployments
+        .get(deployment)
+        .map(String::as_str)
+        .unwrap_or(deployment)
+}
+
+/// Azure request bodies carry no `model`; the deployment decides it
+fn with_model(mut body: Value, model: &str) -> Result<OpenAIChatRequest, AppError> {
+    let Some(object) = body.as_object_mut() else {
+        return Err(AppError::BadRequest(
+            "Request body must be a JSON object".to_string(),
+        ));
+    };
+    object.insert("model".to_string(), Value::String(model.to_string()));
+
+    serde_json::from_value(body).map_err(|e| {
+        error!("Failed to deserialize Azure request: {}", e);
+        AppError::BadRequest(format!("Invalid request: {}", e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn azure(api_key: Option<&str>) -> AzureConfig {
+        AzureConfig {
+            deployments: HashMap::from([("prod-chat".to_string(), "gpt-4o".to_string())]),
+            api_key: api_key.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_deployment_model() {
+        let azure = azure(None);
+
+        assert_eq!(deployment_model(&azure, "prod-chat"), "gpt-4o");
+        assert_eq!(deployment_model(&azure, "gpt-4.1"), "gpt-4.1");
+    }
+
+    #[test]
+    fn test_check_api_key() {
+        let mut headers = HeaderMap::new();
+        assert!(check_api_key(&azure(None), &headers).is_ok());
+        assert!(check_api_key(&azure(Some("secret")), &headers).is_err());
+
+        headers.insert(AZURE_API_KEY_HEADER, HeaderValue::from_static("wrong"));
+        assert!(check_api_key(&azure(Some("secret")), &headers).is_err());
+
+        headers.insert(AZURE_API_KEY_HEADER, HeaderValue::from_static("secret"));
+        assert!(check_api_key(&azure(Some("secret")), &headers).is_ok());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        assert!(check_api_key(&azure(Some("secret")), &headers).is_ok());
+    }
+
+    #[test]
+    fn test_with_model_overrides_body() {
+        let body = json!({
+            "model": "ignored",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "stream": true
+        });
+
+        let request = with_model(body, "gpt-4o").unwrap();
+        assert_eq!(request.model, "gpt-4o");
+        assert!(request.stream);
+        assert_eq!(request.messages.len(), 1);
+
+        assert!(matches!(
+            with_model(json!([]), "gpt-4o"),
+            Err(AppError::BadRequest(_))
+        ));
+        assert!(matches!(
+            with_model(json!({"messages": "nope"}), "gpt-4o"),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
@@ -1,15 +1,32 @@
+use crate::config::ReasoningOutput;
 use crate::copilot::CopilotMessage;
+use crate::copilot::content_filter::strip_chunk_annotations;
+use crate::copilot::finish_reason::{FinishReason, FinishReasonStream, null_as_empty};
+use crate::copilot::reasoning::ReasoningStream;
+use crate::copilot::tool_calls::ToolCallCheck;
 use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
 use crate::openai::completion::models::{
     OpenAIChatRequest, OpenAIChatResponse, OpenAIChoice, OpenAIMessage, OpenAIUsage,
 };
-use crate::server::copilot::CopilotIntegration;
+use crate::server::admission::{ChatAdmission, PreparedChat, admit};
+use crate::server::cancellation::CancellableStream;
+use crate::server::capabilities::with_adjustments_header;
+use crate::server::copilot::{CopilotIntegration, upstream_headers, with_upstream_headers};
+use crate::server::openai::fan_out::{ChatFanOut, FanOut};
+use crate::server::openai::structured_outputs::StructuredOutputs;
+use crate::server::racing::ModelRacing;
+use crate::server::server_tools::auto_tools;
+use crate::server::session::with_session_header;
+use crate::server::sse_lines::SseLines;
+use crate::server::stream_errors::{end_with_error, read_error};
+use crate::server::stream_stats::StreamStats;
+use crate::server::with_tool_calls_header;
 use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderMap;
 use axum::response::IntoResponse;
 use axum::{Json, extract::State};
-use futures_util::{StreamExt as _, TryStreamExt as _};
+use futures_util::TryStreamExt as _;
 use serde::{Deserialize, Serialize};
-use std::io::Error;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::log::{error, info, warn};
@@ -18,35 +35,65 @@ use tracing::log::{error, info, warn};
 pub struct CopilotChoice {
     /// Optional index (defaults to position in array if not provided)
     pub index: Option<u32>,
+    /// Missing from choices Copilot's content filter stopped before any output
+    #[serde(default = "filtered_message")]
     pub message: CopilotMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<serde_json::Value>,
+    #[serde(default, deserialize_with = "null_as_empty")]
     pub finish_reason: String,
+    /// Copilot's content filter verdicts on this choice, under `[copilot.content_filter]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// An empty assistant message, for a choice without one
+fn filtered_message() -> CopilotMessage {
+    CopilotMessage {
+        role: "assistant".to_string(),
+        content: None,
+        padding: None,
+        tool_calls: None,
+        tool_call_id: None,
+        name: None,
+        reasoning_text: None,
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct CopilotUsage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
-pub(crate) trait CoPilotChatCompletions: CopilotIntegration {
+pub(crate) trait CoPilotChatCompletions: CopilotIntegration + StructuredOutputs {
     async fn chat_completions(
         state: State<Arc<AppState>>,
+        headers: HeaderMap,
         request: Json<OpenAIChatRequest>,
     ) -> Result<axum::response::Response, AppError>;
 
+    /// `annotations`: pass Copilot's content filter annotations on, per
+    /// `[copilot.content_filter] annotations`
     async fn chat_completions_sse(
         response: reqwest::Response,
+        stats: Arc<StreamStats>,
+        reasoning: ReasoningOutput,
+        annotations: bool,
     ) -> Result<axum::response::Response, AppError>;
 
     async fn chat_completions_no_sse(
         response: reqwest::Response,
+        tool_check: ToolCallCheck,
+        reasoning: ReasoningOutput,
     ) -> Result<axum::response::Response, AppError>;
 }
 
 impl CoPilotChatCompletions for Server {
     async fn chat_completions(
         State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
         request: Json<OpenAIChatRequest>,
     ) -> Result<axum::response::Response, AppError> {
         let mut request = request.0;
@@ -58,133 +105,257 @@ impl CoPilotChatCompletions for Server {
         );
 
         let is_stream = request.stream;
+        let config = state.config();
+        let fan_out = FanOut::from_request(
+            request.n,
+            request.best_of,
+            is_stream,
+            config.copilot.fan_out.max_requests,
+        )
+        .map_err(|e| {
+            error!("Rejecting request with invalid n/best_of: {}", e);
+            AppError::BadRequest(e)
+        })?;
 
-        // Get a valid Copilot token
-        let token = Self::get_token(state.clone()).await?;
+        let admission = admit(&state, &headers, request.user.as_deref())?;
+        let session_id = admission.session_id.clone();
 
         // Transform OpenAI request to Copilot format
-        let copilot_request: CopilotChatRequest = request.into();
+        let mut copilot_request: CopilotChatRequest = request.into();
+        let PreparedChat {
+            state,
+            token,
+            adjustments,
+        } = Self::prepare_chat(state, &headers, &admission, &mut copilot_request).await?;
+        let config = state.config();
 
         // Forward request to Copilot API
-        let copilot_url = format!("{}/chat/completions", state.config.copilot.api_base_url);
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+
+        let tool_check =
+            ToolCallCheck::new(&config.copilot.tool_calls, copilot_request.tools.as_deref());
+
+        if let Some(fan_out) = fan_out {
+            let response = Self::chat_completions_fan_out(
+                state,
+                token,
+                &copilot_request,
+                &session_id,
+                fan_out,
+                tool_check,
+            )
+            .await?;
+            return Ok(with_adjustments_header(
+                with_session_header(response, &session_id),
+                &adjustments,
+            ));
+        }
 
-        let response = Self::forward_prompt(state, token, copilot_url, &copilot_request).await?;
+        let stats = StreamStats::new(
+            state.metrics.clone(),
+            "chat_completions",
+            &copilot_request.model,
+        );
+        let auto_tools = auto_tools(&config.mcp, &headers);
+        let response = Self::forward_raced(
+            state.clone(),
+            token.clone(),
+            copilot_url,
+            &copilot_request,
+            &session_id,
+            is_stream,
+            auto_tools,
+        )
+        .await?;
+        stats.served_by(&response);
 
         let status = response.status();
         if !status.is_success() {
             return Self::handle_errors(response).await;
         }
+        let upstream = upstream_headers(&config.copilot.response_headers, response.headers());
 
-        if is_stream {
-            Self::chat_completions_sse(response).await
-        } else {
-            Self::chat_completions_no_sse(response).await
-        }
+        let reasoning = config.copilot.reasoning.output;
+        let response = match copilot_request.strict_schema() {
+            _ if is_stream => {
+                let annotations = config.copilot.content_filter.annotations;
+                Self::chat_completions_sse(response, stats, reasoning, annotations).await
+            }
+            Some(schema) => {
+                Self::chat_completions_strict(
+                    state,
+                    token,
+                    &copilot_request,
+                    schema,
+                    &session_id,
+                    response,
+                    tool_check,
+                )
+                .await
+            }
+            None => Self::chat_completions_no_sse(response, tool_check, reasoning).await,
+        };
+
+        response.map(|response| {
+            let response = with_upstream_headers(response, upstream);
+            with_adjustments_header(with_session_header(response, &session_id), &adjustments)
+        })
     }
 
     async fn chat_completions_no_sse(
         response: reqwest::Response,
+        tool_check: ToolCallCheck,
+        reasoning: ReasoningOutput,
     ) -> Result<axum::response::Response, AppError> {
         // Non-streaming path: buffer the full response and return JSON.
         let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
             error!("Failed to parse Copilot response: {}", e);
-            AppError::InternalServerError(format!("Failed to parse Copilot response: {}", e))
+            AppError::upstream("Failed to parse Copilot response", e)
         })?;
 
-        let since_the_epoch = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("time should go forward");
-
-        // Transform Copilot response to OpenAI format
-        let openai_response = OpenAIChatResponse {
-            id: copilot_response.id,
-            object: "chat.completion".to_string(),
-            // IMPORTANT: Handle optional `created` field from GitHub Copilot API
-            // - GitHub Copilot's response may omit the `created` field
-            // - OpenAI's API spec requires `created` as a mandatory integer (Unix timestamp)
-            // - We default to the current timestamp if Copilot doesn't provide one
-            created: copilot_response
-                .created
-                .unwrap_or(since_the_epoch.as_secs()),
-            model: copilot_response.model,
-            choices: copilot_response
-                .choices
-                .into_iter()
-                .enumerate()
-                .map(|(i, c)| OpenAIChoice {
-                    // Use the index from Copilot if available, otherwise use position
-                    index: c.index.unwrap_or(i as u32),
-                    message: OpenAIMessage {
-                        role: c.message.role,
-                        content: c.message.content,
-                        tool_calls: c.message.tool_calls,
-                        tool_call_id: c.message.tool_call_id,
-                        name: c.message.name,
-                    },
-                    finish_reason: c.finish_reason,
-                })
-                .collect(),
-            usage: copilot_response
-                .usage
-                .map(|u| OpenAIUsage {
-                    prompt_tokens: u.prompt_tokens,
-                    completion_tokens: u.completion_tokens,
-                    total_tokens: u.total_tokens,
-                })
-                .unwrap_or(OpenAIUsage {
-                    prompt_tokens: 0,
-                    completion_tokens: 0,
-                    total_tokens: 0,
-                }),
-        };
-
         info!("Successfully processed chat completion request");
-        Ok(Json(openai_response).into_response())
+        Ok(openai_chat_response(
+            copilot_response,
+            tool_check,
+            reasoning,
+        ))
     }
 
     async fn chat_completions_sse(
         response: reqwest::Response,
+        stats: Arc<StreamStats>,
+        reasoning: ReasoningOutput,
+        annotations: bool,
     ) -> Result<axum::response::Response, AppError> {
         use axum::response::sse::{Event, Sse};
 
         let byte_stream = response.bytes_stream();
 
-        // Each chunk from Copilot is raw SSE text, potentially containing
-        // one or more lines of the form "data: <json>\n\n".
-        // We split on newlines, strip the "data: " prefix from each line,
-        // and re-emit the bare JSON payload as an axum SSE Event.
-        let sse_stream = byte_stream
-            .map_err(|e: reqwest::Error| {
-                error!("Error reading streaming response from Copilot: {}", e);
-                Error::other(e.to_string())
+        // Copilot sends raw SSE text: lines of the form "data: <json>".
+        // We strip the "data: " prefix from each line and re-emit the bare
+        // JSON payload as an axum SSE Event, one line at a time. Chunks are relayed rather
+        // than rebuilt from `ChatDelta`s, which do not carry the fields the proxy does not
+        // model, such as logprobs and content filter results.
+        let byte_stream = byte_stream.map_err(read_error);
+        let sse_stream = SseLines::new(byte_stream)
+            .inspect_ok({
+                let stats = stats.clone();
+                move |line| stats.observe_line(line)
             })
-            .flat_map(|result| {
-                let events: Vec<Result<Event, Error>> = match result {
-                    Err(e) => vec![Err(e)],
-                    Ok(bytes) => {
-                        let text = String::from_utf8_lossy(&bytes).into_owned();
-                        text.lines()
-                            .filter_map(|line| match translate_sse_line(line) {
-                                ChatSseLineOutput::Data(payload) => {
-                                    Some(Ok(Event::default().data(payload)))
-                                }
-                                ChatSseLineOutput::Skip => None,
-                                ChatSseLineOutput::Unexpected(raw) => {
-                                    warn!("Unexpected SSE line from Copilot: {}", raw);
-                                    None
-                                }
-                            })
-                            .collect()
-                    }
-                };
-                futures_util::stream::iter(events)
+            .try_filter_map({
+                let mut reasoning = ReasoningStream::new(reasoning);
+                let mut finish_reasons = FinishReasonStream::default();
+                move |line| {
+                    let event = match translate_sse_line(&line) {
+                        ChatSseLineOutput::Data(payload) => {
+                            let mut payload = finish_reasons.rewrite(&reasoning.rewrite(&payload));
+                            if !annotations {
+                                payload = strip_chunk_annotations(&payload);
+                            }
+                            Some(Event::default().data(payload))
+                        }
+                        ChatSseLineOutput::Skip => None,
+                        ChatSseLineOutput::Unexpected(raw) => {
+                            warn!("Unexpected SSE line from Copilot: {}", raw);
+                            None
+                        }
+                    };
+                    futures_util::future::ready(Ok(event))
+                }
             });
 
+        // A failure mid-stream ends it with an OpenAI error chunk
+        let sse_stream = end_with_error(sse_stream, |error| {
+            Event::default().data(error.body().to_string())
+        });
+
         info!("Streaming chat completion response");
+        let sse_stream = CancellableStream::new(sse_stream, stats);
         Ok(Sse::new(sse_stream).into_response())
     }
 }
 
+/// Convert a complete Copilot response into an OpenAI chat completion, after
+/// running the configured tool-call checks over it
+pub(crate) fn openai_chat_response(
+    mut copilot_response: CopilotChatResponse,
+    tool_check: ToolCallCheck,
+    reasoning: ReasoningOutput,
+) -> axum::response::Response {
+    let tool_report = tool_check.apply(&mut copilot_response);
+    let openai_response = openai_chat_completion(copilot_response, reasoning);
+    with_tool_calls_header(Json(openai_response).into_response(), &tool_report)
+}
+
+/// A Copilot reply as an OpenAI chat completion, with its reasoning shaped per `reasoning`
+pub(crate) fn openai_chat_completion(
+    mut copilot_response: CopilotChatResponse,
+    reasoning: ReasoningOutput,
+) -> OpenAIChatResponse {
+    copilot_response.apply_reasoning_output(reasoning);
+
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should go forward");
+
+    // Transform Copilot response to OpenAI format
+    OpenAIChatResponse {
+        id: copilot_response.id,
+        object: "chat.completion".to_string(),
+        // IMPORTANT: Handle optional `created` field from GitHub Copilot API
+        // - GitHub Copilot's response may omit the `created` field
+        // - OpenAI's API spec requires `created` as a mandatory integer (Unix timestamp)
+        // - We default to the current timestamp if Copilot doesn't provide one
+        created: copilot_response
+            .created
+            .unwrap_or(since_the_epoch.as_secs()),
+        model: copilot_response.model,
+        choices: copilot_response
+            .choices
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| OpenAIChoice {
+                // Use the index from Copilot if available, otherwise use position
+                index: c.index.unwrap_or(i as u32),
+                finish_reason: FinishReason::finished(
+                    Some(&c.finish_reason),
+                    c.message
+                        .tool_calls
+                        .as_ref()
+                        .is_some_and(|calls| !calls.is_empty()),
+                )
+                .openai()
+                .to_string(),
+                message: OpenAIMessage {
+                    role: c.message.role,
+                    content: c.message.content.map(|content| content.to_text()),
+                    tool_calls: c.message.tool_calls,
+                    tool_call_id: c.message.tool_call_id,
+                    name: c.message.name,
+                    images: None,
+                    reasoning_content: c.message.reasoning_text.clone(),
+                    reasoning: c.message.reasoning_text,
+                },
+                logprobs: c.logprobs,
+                content_filter_results: c.content_filter_results,
+            })
+            .collect(),
+        prompt_filter_results: copilot_response.prompt_filter_results,
+        usage: copilot_response
+            .usage
+            .map(|u| OpenAIUsage {
+                prompt_tokens: u.prompt_tokens,
+                completion_tokens: u.completion_tokens,
+                total_tokens: u.total_tokens,
+            })
+            .unwrap_or(OpenAIUsage {
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                total_tokens: 0,
+            }),
+    }
+}
+
 /// Result of processing a single Copilot SSE line for the OpenAI chat completions endpoint.
 #[derive(Debug, PartialEq)]
 pub(crate) enum ChatSseLineOutput {
@@ -215,6 +386,7 @@ pub(crate) fn translate_sse_line(line: &str) -> ChatSseLineOutput {
 mod tests {
     use super::*;
     use crate::openai::completion::models::{FunctionCall, ToolCall};
+    use crate::server::metrics::Metrics;
 
     // -----------------------------------------------------------------------
     // Helper
@@ -232,6 +404,36 @@ mod tests {
     // chat_completions_no_sse
     // -----------------------------------------------------------------------
 
+    #[tokio::test]
+    async fn test_no_sse_exposes_reasoning_content() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-abc123",
+            "model": "claude-sonnet-4",
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": "4", "reasoning_text": "2 + 2" },
+                "finish_reason": "stop"
+            }]
+        });
+
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            make_reqwest_response(body.to_string()),
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .expect("should not error");
+
+        let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let message = &parsed["choices"][0]["message"];
+        assert_eq!(message["content"], "4");
+        assert_eq!(message["reasoning_content"], "2 + 2");
+        assert_eq!(message["reasoning"], "2 + 2");
+    }
+
     #[tokio::test]
     async fn test_no_sse_returns_openai_chat_response() {
         let body = serde_json::json!({
@@ -247,9 +449,13 @@ mod tests {
         });
 
         let response = make_reqwest_response(body.to_string());
-        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(response)
-            .await
-            .expect("should not error");
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .expect("should not error");
 
         assert_eq!(result.status(), 200);
 
@@ -275,6 +481,54 @@ mod tests {
         assert_eq!(parsed.usage.total_tokens, 8);
     }
 
+    #[tokio::test]
+    async fn test_no_sse_logprobs_passthrough_and_null() {
+        let logprobs = serde_json::json!({
+            "content": [{ "token": "Hi", "logprob": -0.01, "bytes": [72, 105], "top_logprobs": [] }]
+        });
+        let body = serde_json::json!({
+            "id": "chatcmpl-logprobs",
+            "model": "gpt-4o",
+            "choices": [
+                {
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Hi" },
+                    "logprobs": logprobs,
+                    "finish_reason": "stop"
+                },
+                {
+                    "index": 1,
+                    "message": { "role": "assistant", "content": "Hi" },
+                    "finish_reason": "stop"
+                }
+            ]
+        });
+
+        let response = make_reqwest_response(body.to_string());
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .expect("should not error");
+
+        let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parsed["choices"][0]["logprobs"], logprobs);
+        // Strict clients expect the key even when there is nothing to report
+        assert!(parsed["choices"][1]["logprobs"].is_null());
+        assert!(
+            parsed["choices"][1]
+                .as_object()
+                .unwrap()
+                .contains_key("logprobs")
+        );
+    }
+
     #[tokio::test]
     async fn test_no_sse_uses_current_time_when_created_missing() {
         let before = SystemTime::now()
@@ -294,9 +548,13 @@ mod tests {
         });
 
         let response = make_reqwest_response(body.to_string());
-        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .unwrap();
 
         let after = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -331,9 +589,13 @@ mod tests {
         });
 
         let response = make_reqwest_response(body.to_string());
-        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -345,6 +607,86 @@ mod tests {
         assert_eq!(parsed.usage.total_tokens, 0);
     }
 
+    #[tokio::test]
+    async fn test_no_sse_normalises_finish_reason() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-finish",
+            "model": "gpt-4o",
+            "choices": [
+                {
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Hi" },
+                    "finish_reason": null
+                },
+                {
+                    "index": 1,
+                    "message": {
+                        "role": "assistant",
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{}"}
+                        }]
+                    },
+                    "finish_reason": "function_call"
+                }
+            ]
+        });
+
+        let response = make_reqwest_response(body.to_string());
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .unwrap();
+
+        let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parsed: OpenAIChatResponse = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parsed.choices[0].finish_reason, "stop");
+        assert_eq!(parsed.choices[1].finish_reason, "tool_calls");
+    }
+
+    #[tokio::test]
+    async fn test_no_sse_filtered_choice_keeps_annotations() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-filtered",
+            "model": "gpt-4o",
+            "prompt_filter_results": [{"prompt_index": 0, "content_filter_results": {}}],
+            "choices": [{
+                "index": 0,
+                "finish_reason": "content_filter",
+                "content_filter_results": {"violence": {"filtered": true, "severity": "high"}}
+            }]
+        });
+
+        let response = make_reqwest_response(body.to_string());
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .unwrap();
+
+        let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parsed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parsed["choices"][0]["finish_reason"], "content_filter");
+        assert_eq!(parsed["choices"][0]["message"]["role"], "assistant");
+        assert_eq!(
+            parsed["choices"][0]["content_filter_results"]["violence"]["filtered"],
+            true
+        );
+        assert_eq!(parsed["prompt_filter_results"][0]["prompt_index"], 0);
+    }
+
     #[tokio::test]
     async fn test_no_sse_choice_index_falls_back_to_position() {
         let body = serde_json::json!({
@@ -367,9 +709,13 @@ mod tests {
         });
 
         let response = make_reqwest_response(body.to_string());
-        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::default(),
+            ReasoningOutput::Include,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -380,6 +726,47 @@ mod tests {
         assert_eq!(parsed.choices[1].index, 7);
     }
 
+    #[tokio::test]
+    async fn test_no_sse_repairs_tool_call_arguments() {
+        let body = serde_json::json!({
+            "id": "chatcmpl-tools",
+            "model": "gpt-4o",
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{'city': 'Paris'}" }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        });
+        let config = crate::config::CopilotToolCallsConfig {
+            repair: true,
+            validate: false,
+        };
+
+        let response = make_reqwest_response(body.to_string());
+        let result = <Server as CoPilotChatCompletions>::chat_completions_no_sse(
+            response,
+            ToolCallCheck::new(&config, None),
+            ReasoningOutput::Include,
+        )
+        .await
+        .expect("should not error");
+
+        assert_eq!(result.headers()["X-Passenger-Tool-Calls"], "repaired");
+
+        let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parsed: OpenAIChatResponse = serde_json::from_slice(&bytes).unwrap();
+        let tool_calls = parsed.choices[0].message.tool_calls.as_ref().unwrap();
+        assert_eq!(tool_calls[0].function.arguments, r#"{"city":"Paris"}"#);
+    }
+
     // -----------------------------------------------------------------------
     // chat_completions_sse
     // -----------------------------------------------------------------------
@@ -390,9 +777,14 @@ mod tests {
         let body = format!("data: {chunk}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(response)
-            .await
-            .expect("should not error");
+        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "chat_completions", "gpt-4o"),
+            ReasoningOutput::Include,
+            true,
+        )
+        .await
+        .expect("should not error");
 
         assert_eq!(result.status(), 200);
         let ct = result
@@ -404,15 +796,60 @@ mod tests {
         assert!(ct.contains("text/event-stream"), "must be SSE content-type");
     }
 
+    #[tokio::test]
+    async fn test_sse_upstream_error_ends_with_error_chunk() {
+        let chunk = r#"{"id":"x","choices":[{"index":0,"delta":{"content":"Hel"}}]}"#;
+        let body = futures_util::stream::iter(vec![
+            Ok(bytes::Bytes::from(format!("data: {chunk}\n"))),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "connection reset",
+            )),
+        ]);
+        let http_resp = http::Response::builder()
+            .status(200)
+            .body(reqwest::Body::wrap_stream(body))
+            .unwrap();
+
+        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(
+            reqwest::Response::from(http_resp),
+            StreamStats::new(Arc::new(Metrics::default()), "chat_completions", "gpt-4o"),
+            ReasoningOutput::Include,
+            true,
+        )
+        .await
+        .unwrap();
+
+        // The body completes rather than failing, its last event an OpenAI error
+        let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let raw = std::str::from_utf8(&bytes).unwrap();
+        let data: Vec<&str> = raw
+            .split("\n\n")
+            .filter_map(|block| block.strip_prefix("data: "))
+            .collect();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0], chunk);
+        let error: serde_json::Value = serde_json::from_str(data[1]).unwrap();
+        assert_eq!(error["error"]["code"], "upstream_error");
+        assert_eq!(error["error"]["type"], "upstream_error");
+    }
+
     #[tokio::test]
     async fn test_sse_passthrough_data_lines() {
         let chunk = r#"{"id":"x","object":"chat.completion.chunk","created":1,"model":"gpt-4o","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#;
         let body = format!("data: {chunk}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "chat_completions", "gpt-4o"),
+            ReasoningOutput::Include,
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -443,9 +880,14 @@ mod tests {
         let body = format!("\ndata: {chunk}\n\ndata: [DONE]\n\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "chat_completions", "gpt-4o"),
+            ReasoningOutput::Include,
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -475,9 +917,14 @@ mod tests {
         let body = format!("data: {chunk1}\ndata: {chunk2}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as CoPilotChatCompletions>::chat_completions_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "chat_completions", "gpt-4o"),
+            ReasoningOutput::Include,
+            true,
+        )
+        .await
+        .unwrap();
 
         let bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -559,7 +1006,7 @@ mod tests {
         assert_eq!(response.choices.len(), 1);
         assert_eq!(
             response.choices[0].message.content,
-            Some("Hello, World!".to_string())
+            Some("Hello, World!".into())
         );
     }
 
@@ -609,6 +1056,7 @@ mod tests {
             model: "gpt-4".to_string(),
             choices: vec![],
             usage: None,
+            prompt_filter_results: None,
         };
 
         let since_the_epoch = SystemTime::now()
@@ -628,6 +1076,7 @@ mod tests {
                 completion_tokens: 0,
                 total_tokens: 0,
             },
+            prompt_filter_results: None,
         };
 
         // Verify that 'created' is always populated in OpenAI response
@@ -649,40 +1098,50 @@ mod tests {
                     index: None, // No index provided
                     message: CopilotMessage {
                         role: "assistant".to_string(),
-                        content: Some("First response".to_string()),
+                        content: Some("First response".into()),
                         padding: None,
                         tool_calls: None,
                         tool_call_id: None,
                         name: None,
+                        reasoning_text: None,
                     },
+                    logprobs: None,
                     finish_reason: "stop".to_string(),
+                    content_filter_results: None,
                 },
                 CopilotChoice {
                     index: Some(5), // Explicit index provided
                     message: CopilotMessage {
                         role: "assistant".to_string(),
-                        content: Some("Second response".to_string()),
+                        content: Some("Second response".into()),
                         padding: None,
                         tool_calls: None,
                         tool_call_id: None,
                         name: None,
+                        reasoning_text: None,
                     },
+                    logprobs: None,
                     finish_reason: "stop".to_string(),
+                    content_filter_results: None,
                 },
                 CopilotChoice {
                     index: None, // No index provided
                     message: CopilotMessage {
                         role: "assistant".to_string(),
-                        content: Some("Third response".to_string()),
+                        content: Some("Third response".into()),
                         padding: None,
                         tool_calls: None,
                         tool_call_id: None,
                         name: None,
+                        reasoning_text: None,
                     },
+                    logprobs: None,
                     finish_reason: "stop".to_string(),
+                    content_filter_results: None,
                 },
             ],
             usage: None,
+            prompt_filter_results: None,
         };
 
         let since_the_epoch = SystemTime::now()
@@ -704,12 +1163,17 @@ mod tests {
                     index: c.index.unwrap_or(i as u32),
                     message: OpenAIMessage {
                         role: c.message.role,
-                        content: c.message.content,
+                        content: c.message.content.map(|content| content.to_text()),
                         tool_calls: c.message.tool_calls,
                         tool_call_id: c.message.tool_call_id,
                         name: c.message.name,
+                        images: None,
+                        reasoning_content: None,
+                        reasoning: None,
                     },
+                    logprobs: c.logprobs,
                     finish_reason: c.finish_reason,
+                    content_filter_results: c.content_filter_results,
                 })
                 .collect(),
             usage: OpenAIUsage {
@@ -717,6 +1181,7 @@ mod tests {
                 completion_tokens: 0,
                 total_tokens: 0,
             },
+            prompt_filter_results: None,
         };
 
         // Verify indices: 0 (from position), 5 (from Copilot), 2 (from position)
@@ -853,6 +1318,9 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
                 OpenAIMessage {
                     role: "assistant".to_string(),
@@ -867,6 +1335,9 @@ mod tests {
                     }]),
                     tool_call_id: None,
                     name: None,
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
                 OpenAIMessage {
                     role: "tool".to_string(),
@@ -874,6 +1345,9 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: Some("call_123".to_string()),
                     name: Some("get_weather".to_string()),
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
             ],
             temperature: None,
@@ -881,6 +1355,14 @@ mod tests {
             stream: false,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
+            user: None,
+            n: None,
+            best_of: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
         };
 
         request.prepare_for_copilot();
@@ -940,6 +1422,9 @@ mod tests {
                     ]),
                     tool_call_id: None,
                     name: None,
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
                 OpenAIMessage {
                     role: "tool".to_string(),
@@ -947,6 +1432,9 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: Some("call_1".to_string()),
                     name: Some("get_weather".to_string()),
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
                 OpenAIMessage {
                     role: "tool".to_string(),
@@ -954,6 +1442,9 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: Some("call_2".to_string()),
                     name: Some("get_stock".to_string()),
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
             ],
             temperature: None,
@@ -961,6 +1452,14 @@ mod tests {
             stream: false,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
+            user: None,
+            n: None,
+            best_of: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
         };
 
         request.prepare_for_copilot();
@@ -1003,6 +1502,9 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
                 OpenAIMessage {
                     role: "user".to_string(),
@@ -1010,6 +1512,9 @@ mod tests {
                     tool_calls: None,
                     tool_call_id: None,
                     name: None,
+                    images: None,
+                    reasoning_content: None,
+                    reasoning: None,
                 },
             ],
             temperature: None,
@@ -1017,6 +1522,14 @@ mod tests {
             stream: false,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
+            user: None,
+            n: None,
+            best_of: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
         };
 
         request.prepare_for_copilot();
@@ -1039,12 +1552,23 @@ mod tests {
                 tool_calls: None,
                 tool_call_id: None, // Missing
                 name: None,         // Missing
+                images: None,
+                reasoning_content: None,
+                reasoning: None,
             }],
             temperature: None,
             max_tokens: None,
             stream: false,
             tools: None,
             tool_choice: None,
+            parallel_tool_calls: None,
+            user: None,
+            n: None,
+            best_of: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
         };
 
         request.prepare_for_copilot();
@@ -0,0 +1,318 @@
+use crate::auth::CopilotTokenResponse;
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
+use crate::openai::completion::models::{OpenAIChatRequest, OpenAIChatResponse, OpenAIUsage};
+use crate::server::admission::admit;
+use crate::server::capabilities::ModelAdaptation;
+use crate::server::context_window::ContextWindow;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::openai::chat_completion::openai_chat_completion;
+use crate::server::session::with_session_header;
+use crate::server::{AppError, AppState, Server};
+use axum::Json;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::log::{error, info};
+
+/// Body of `POST /v1/compare`: a chat completion request with `models` in place of `model`
+#[derive(Debug, Deserialize)]
+pub struct CompareRequest {
+    pub models: Vec<String>,
+    #[serde(flatten)]
+    pub request: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub object: String,
+    pub created: u64,
+    pub results: Vec<ComparisonResult>,
+}
+
+/// One model's answer, or the error a direct chat completion request would have returned
+#[derive(Debug, Serialize)]
+pub struct ComparisonResult {
+    pub model: String,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<OpenAIUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<OpenAIChatResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Value>,
+}
+
+/// Side by side answers of several models to the same chat completion request
+pub(crate) trait ChatComparison: CopilotIntegration {
+    async fn compare(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        request: Json<CompareRequest>,
+    ) -> Result<axum::response::Response, AppError>;
+
+    /// `request` answered by `model` through the chat completion pipeline
+    async fn compare_model(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: CopilotChatRequest,
+        model: &str,
+        session_id: &str,
+    ) -> Result<OpenAIChatResponse, AppError>;
+}
+
+impl ChatComparison for Server {
+    async fn compare(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Json(compare): Json<CompareRequest>,
+    ) -> Result<axum::response::Response, AppError> {
+        info!(
+            "Received comparison request for models: {:?}",
+            compare.models
+        );
+
+        let max_models = state.config().copilot.fan_out.max_requests as usize;
+        if compare.models.is_empty() {
+            return Err(AppError::BadRequest(
+                "models must list at least one model".to_string(),
+            ));
+        }
+        if compare.models.len() > max_models {
+            return Err(AppError::BadRequest(format!(
+                "At most {} models can be compared, got {}",
+                max_models,
+                compare.models.len()
+            )));
+        }
+
+        let mut body = compare.request;
+        if let Some(fields) = body.as_object_mut() {
+            fields.insert("model".to_string(), compare.models[0].clone().into());
+        }
+        let mut request: OpenAIChatRequest = serde_json::from_value(body).map_err(|e| {
+            error!("Rejecting invalid comparison request: {}", e);
+            AppError::BadRequest(format!("Invalid chat completion request: {}", e))
+        })?;
+        if request.stream {
+            return Err(AppError::BadRequest(
+                "stream is not supported by /v1/compare".to_string(),
+            ));
+        }
+        request.prepare_for_copilot();
+
+        let session_id = admit(&state, &headers, request.user.as_deref())?.session_id;
+        let mut copilot_request: CopilotChatRequest = request.into();
+        copilot_request
+            .expand_preset(&state.config().presets)
+            .map_err(|e| {
+                error!("Rejecting request: {}", e);
+                AppError::BadRequest(e)
+            })?;
+        copilot_request.expand_virtual_model(&state.config().virtual_models);
+        copilot_request.validate_tool_choice().map_err(|e| {
+            error!("Rejecting request with invalid tool_choice: {}", e);
+            AppError::BadRequest(e)
+        })?;
+
+        let token = Self::get_token(state.clone()).await?;
+
+        let comparisons = compare.models.iter().map(|model| {
+            let state = state.clone();
+            let token = token.clone();
+            let request = copilot_request.clone();
+            let session_id = session_id.as_str();
+            async move {
+                let started = Instant::now();
+                let result = Self::compare_model(state, token, request, model, session_id).await;
+                comparison_result(model, started, result).await
+            }
+        });
+        let results = futures_util::future::join_all(comparisons).await;
+
+        info!("Compared {} models", results.len());
+        let comparison = Comparison {
+            object: "comparison".to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("time should go forward")
+                .as_secs(),
+            results,
+        };
+        Ok(with_session_header(
+            Json(comparison).into_response(),
+            &session_id,
+        ))
+    }
+
+    async fn compare_model(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        mut request: CopilotChatRequest,
+        model: &str,
+        session_id: &str,
+    ) -> Result<OpenAIChatResponse, AppError> {
+        let config = state.config();
+        request.model = model.to_string();
+        request.stream = Some(false);
+
+        Self::resolve_model(state.clone(), &mut request).await?;
+        Self::adapt_to_model(state.clone(), &mut request).await;
+        Self::fit_context_window(state.clone(), &mut request, session_id).await;
+
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+        let response = Self::forward_prompt(
+            state.clone(),
+            token,
+            copilot_url,
+            &request,
+            session_id,
+            false,
+        )
+        .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(Self::handle_errors(response)
+                .await
+                .err()
+                .unwrap_or_else(|| {
+                    AppError::UpstreamError(format!("Copilot API error: {}", status))
+                }));
+        }
+
+        let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse Copilot response: {}", e);
+            AppError::upstream("Failed to parse Copilot response", e)
+        })?;
+        Ok(openai_chat_completion(
+            copilot_response,
+            config.copilot.reasoning.output,
+        ))
+    }
+}
+
+/// The result for `model`, with errors rendered as their JSON body
+async fn comparison_result(
+    model: &str,
+    started: Instant,
+    result: Result<OpenAIChatResponse, AppError>,
+) -> ComparisonResult {
+    let elapsed = started.elapsed();
+    match result {
+        Ok(response) => {
+            let usage = OpenAIUsage {
+                prompt_tokens: response.usage.prompt_tokens,
+                completion_tokens: response.usage.completion_tokens,
+                total_tokens: response.usage.total_tokens,
+            };
+            let tokens_per_second = (usage.completion_tokens > 0 && !elapsed.is_zero())
+                .then(|| usage.completion_tokens as f64 / elapsed.as_secs_f64());
+            ComparisonResult {
+                model: model.to_string(),
+                latency_ms: elapsed.as_millis() as u64,
+                usage: Some(usage),
+                tokens_per_second,
+                response: Some(response),
+                error: None,
+            }
+        }
+        Err(e) => {
+            let body = axum::body::to_bytes(e.into_response().into_body(), usize::MAX)
+                .await
+                .unwrap_or_default();
+            let error = serde_json::from_slice::<Value>(&body)
+                .ok()
+                .and_then(|mut body| body.get_mut("error").map(Value::take))
+                .unwrap_or_else(|| Value::String(String::from_utf8_lossy(&body).into_owned()));
+            ComparisonResult {
+                model: model.to_string(),
+                latency_ms: elapsed.as_millis() as u64,
+                usage: None,
+                tokens_per_second: None,
+                response: None,
+                error: Some(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::test_token;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_compare_model_reports_answers_and_errors() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({"model": "gpt-4o"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "chatcmpl-1",
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hello"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4}
+            })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({"model": "o3"})))
+            .respond_with(ResponseTemplate::new(400).set_body_string("model not supported"))
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = mock_server.uri();
+        let state = Arc::new(AppState::new(&config, None));
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}]
+        }))
+        .unwrap();
+        let request: CopilotChatRequest = request.into();
+
+        let started = Instant::now();
+        let answered = Server::compare_model(
+            state.clone(),
+            test_token(),
+            request.clone(),
+            "gpt-4o",
+            "session",
+        )
+        .await;
+        let answered = comparison_result("gpt-4o", started, answered).await;
+        let failed = Server::compare_model(state, test_token(), request, "o3", "session").await;
+        let failed = comparison_result("o3", started, failed).await;
+
+        let response = answered.response.unwrap();
+        assert_eq!(
+            response.choices[0].message.content.as_deref(),
+            Some("Hello")
+        );
+        assert_eq!(answered.usage.unwrap().total_tokens, 4);
+        assert!(answered.error.is_none());
+
+        assert!(failed.response.is_none());
+        let error = failed.error.unwrap();
+        assert!(
+            error["message"]
+                .as_str()
+                .unwrap()
+                .contains("model not supported")
+        );
+    }
+}
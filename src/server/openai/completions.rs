@@ -0,0 +1,172 @@
+use crate::copilot::completions::{
+    CompletionText, CopilotCompletionChunk, CopilotCompletionRequest,
+};
+use crate::openai::text_completion::models::OpenAICompletionRequest;
+use crate::server::admission::admit;
+use crate::server::cancellation::CancellableStream;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::session::with_session_header;
+use crate::server::sse_lines::SseLines;
+use crate::server::stream_errors::{end_with_error, malformed_chunk, read_error};
+use crate::server::stream_stats::StreamStats;
+use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, extract::State};
+use futures_util::{Stream, StreamExt as _, TryStreamExt as _};
+use std::io::Error;
+use std::sync::Arc;
+use tracing::log::{error, info, warn};
+
+/// Code completion through Copilot's fill-in-the-middle API, under `[copilot.completions]`
+pub(crate) trait CopilotTextCompletions: CopilotIntegration {
+    /// OpenAI-compatible `/v1/completions`: `prompt` is the code before the cursor and
+    /// `suffix` the code after it
+    async fn completions(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        request: Json<OpenAICompletionRequest>,
+    ) -> Result<Response, AppError>;
+
+    /// Send `request` to the configured engine. Copilot always streams the reply; failed
+    /// requests are returned for `handle_errors`.
+    async fn forward_completion(
+        state: Arc<AppState>,
+        request: &CopilotCompletionRequest,
+        session_id: &str,
+    ) -> Result<reqwest::Response, AppError>;
+}
+
+impl CopilotTextCompletions for Server {
+    async fn completions(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        Json(request): Json<OpenAICompletionRequest>,
+    ) -> Result<Response, AppError> {
+        info!(
+            "Received completion request for model: {} (stream={})",
+            request.model, request.stream
+        );
+        let is_stream = request.stream;
+        let session_id = admit(&state, &headers, request.user.as_deref())?.session_id;
+        let completion = CopilotCompletionRequest::try_from(request).map_err(|e| {
+            error!("Rejecting completion request: {}", e);
+            AppError::BadRequest(e)
+        })?;
+
+        let response = Self::forward_completion(state.clone(), &completion, &session_id).await?;
+        if !response.status().is_success() {
+            return Self::handle_errors(response).await;
+        }
+
+        let engine = state.config().copilot.completions.engine.clone();
+        let stats = StreamStats::new(state.metrics.clone(), "completions", &engine);
+        let chunks = completion_chunks(response, stats.clone());
+        let response = if is_stream {
+            let events = chunks.map_ok(move |chunk| {
+                let chunk = chunk.into_openai(&engine);
+                Event::default()
+                    .data(serde_json::to_string(&chunk).expect("serialization cannot fail"))
+            });
+            let done = futures_util::stream::once(async {
+                Ok::<_, Error>(Event::default().data("[DONE]"))
+            });
+            let events = end_with_error(events.chain(done), |error| {
+                Event::default().data(error.body().to_string())
+            });
+            let events = CancellableStream::new(events, stats);
+            info!("Streaming completion response");
+            Sse::new(events).into_response()
+        } else {
+            let text = collect_completion(chunks).await?;
+            stats.finish(true);
+            info!("Successfully processed completion request");
+            Json(text.into_openai(&engine)).into_response()
+        };
+        Ok(with_session_header(response, &session_id))
+    }
+
+    async fn forward_completion(
+        state: Arc<AppState>,
+        request: &CopilotCompletionRequest,
+        session_id: &str,
+    ) -> Result<reqwest::Response, AppError> {
+        let token = Self::get_token(state.clone()).await?;
+        let url = state.config().copilot.completions.url();
+        Self::forward_prompt(state, token, url, request, session_id, true).await
+    }
+}
+
+/// The chunks of a streamed Copilot completion
+pub(crate) fn completion_chunks(
+    response: reqwest::Response,
+    stats: Arc<StreamStats>,
+) -> impl Stream<Item = Result<CopilotCompletionChunk, Error>> {
+    let byte_stream = response.bytes_stream().map_err(read_error);
+    SseLines::new(byte_stream)
+        .inspect_ok(move |line| stats.observe_line(line))
+        .try_filter_map(|line| {
+            let chunk = match line.strip_prefix("data:").map(str::trim) {
+                Some("[DONE]") | None => Ok(None),
+                Some(payload) => match serde_json::from_str::<CopilotCompletionChunk>(payload) {
+                    Ok(chunk) => Ok(Some(chunk)),
+                    Err(e) => match malformed_chunk(&e, payload) {
+                        Some(e) => Err(e),
+                        None => {
+                            warn!(
+                                "Failed to parse Copilot completion chunk: {} — {}",
+                                e, payload
+                            );
+                            Ok(None)
+                        }
+                    },
+                },
+            };
+            futures_util::future::ready(chunk)
+        })
+}
+
+/// The whole completion, once Copilot has streamed all of it
+pub(crate) async fn collect_completion(
+    chunks: impl Stream<Item = Result<CopilotCompletionChunk, Error>>,
+) -> Result<CompletionText, AppError> {
+    chunks
+        .try_fold(CompletionText::default(), |mut text, chunk| {
+            text.push(chunk);
+            futures_util::future::ready(Ok(text))
+        })
+        .await
+        .map_err(|e| AppError::StreamParseError(format!("Failed to read completion: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::metrics::Metrics;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_collect_completion() {
+        let sse = "data: {\"id\":\"cmpl-1\",\"choices\":[{\"index\":0,\"text\":\"a \"}]}\n\n\
+                   data: {\"id\":\"cmpl-1\",\"choices\":[{\"index\":0,\"text\":\"+ b\",\"finish_reason\":\"stop\"}]}\n\n\
+                   data: [DONE]\n\n";
+        let response = reqwest::Response::from(axum::http::Response::new(sse));
+        let stats = StreamStats::new(Arc::new(Metrics::default()), "completions", "test");
+
+        let text = collect_completion(completion_chunks(response, stats))
+            .await
+            .unwrap();
+        assert_eq!(text.text(), "a + b");
+        assert_eq!(text.finish_reason(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn test_multiple_prompts_are_rejected() {
+        let state = Arc::new(AppState::new(&Config::default(), None));
+        let request = serde_json::from_value(json!({"prompt": ["a", "b"]})).unwrap();
+        let result = Server::completions(State(state), HeaderMap::new(), Json(request)).await;
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,714 @@
+use crate::auth::CopilotTokenResponse;
+use crate::config::FanOutRanking;
+use crate::copilot::tool_calls::ToolCallCheck;
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse, CopilotMessage};
+use crate::server::copilot::CopilotIntegration;
+use crate::server::openai::chat_completion::{CopilotUsage, openai_chat_response};
+use crate::server::stream_stats::StreamStats;
+use crate::server::{AppError, AppState, Server};
+use axum::response::Response;
+use futures_util::TryFutureExt;
+use std::fmt::Write;
+use std::sync::Arc;
+use tracing::log::{error, info, warn};
+
+/// How a request for several candidates is answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FanOut {
+    /// Every candidate becomes a choice (`n`)
+    All(u32),
+    /// Only the `returned` best ranked of `candidates` are returned (`best_of`, with `n`)
+    Best { candidates: u32, returned: u32 },
+}
+
+impl FanOut {
+    /// The fan-out asked for by `n` / `best_of`, or `None` for a single-choice request
+    pub(crate) fn from_request(
+        n: Option<u32>,
+        best_of: Option<u32>,
+        stream: bool,
+        max_requests: u32,
+    ) -> Result<Option<Self>, String> {
+        let fan_out = match (n.unwrap_or(1), best_of.unwrap_or(1)) {
+            (0, _) => return Err("n must be at least 1".to_string()),
+            (_, 0) => return Err("best_of must be at least 1".to_string()),
+            (1, 1) => return Ok(None),
+            (n, 1) => FanOut::All(n),
+            (n, best_of) if best_of < n => {
+                return Err(format!(
+                    "best_of must be at least n, got best_of={} and n={}",
+                    best_of, n
+                ));
+            }
+            (n, best_of) if best_of == n => FanOut::All(n),
+            (n, best_of) => FanOut::Best {
+                candidates: best_of,
+                returned: n,
+            },
+        };
+
+        if stream {
+            return Err("n and best_of are not supported with stream".to_string());
+        }
+
+        if fan_out.requests() > max_requests {
+            return Err(format!(
+                "At most {} candidates can be requested, got {}",
+                max_requests,
+                fan_out.requests()
+            ));
+        }
+
+        Ok(Some(fan_out))
+    }
+
+    pub(crate) fn requests(self) -> u32 {
+        match self {
+            FanOut::All(count)
+            | FanOut::Best {
+                candidates: count, ..
+            } => count,
+        }
+    }
+}
+
+/// Parallel Copilot requests for clients that cannot fan out themselves, under
+/// `[copilot.fan_out]`. Copilot only ever returns one choice per request.
+pub(crate) trait ChatFanOut: CopilotIntegration {
+    async fn chat_completions_fan_out(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        fan_out: FanOut,
+        tool_check: ToolCallCheck,
+    ) -> Result<Response, AppError>;
+
+    /// Indices of the candidates the judge model prefers, best first, or `None` if it
+    /// could not decide
+    async fn judge(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        candidates: &[CopilotChatResponse],
+        session_id: &str,
+    ) -> Option<Vec<usize>>;
+}
+
+impl ChatFanOut for Server {
+    async fn chat_completions_fan_out(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        fan_out: FanOut,
+        tool_check: ToolCallCheck,
+    ) -> Result<Response, AppError> {
+        let config = state.config();
+        info!(
+            "Fanning out chat completion for model {} into {} requests",
+            request.model,
+            fan_out.requests()
+        );
+
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+        let requests = (0..fan_out.requests()).map(|_| {
+            let stats = StreamStats::new(state.metrics.clone(), "chat_completions", &request.model);
+            Self::forward_prompt(
+                state.clone(),
+                token.clone(),
+                copilot_url.clone(),
+                request,
+                session_id,
+                false,
+            )
+            .map_ok(move |response| {
+                stats.served_by(&response);
+                (response, stats)
+            })
+        });
+        // Every call runs to its end, so those already sent are charged or refunded for what
+        // Copilot answered even when another is refused
+        let responses = futures_util::future::join_all(requests)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut candidates = Vec::with_capacity(responses.len());
+        for (response, stats) in responses {
+            if !response.status().is_success() {
+                return Self::handle_errors(response).await;
+            }
+
+            let candidate: CopilotChatResponse = response.json().await.map_err(|e| {
+                error!("Failed to parse Copilot response: {}", e);
+                AppError::upstream("Failed to parse Copilot response", e)
+            })?;
+            finish_stats(&stats, &candidate);
+            candidates.push(candidate);
+        }
+
+        let copilot_response = match fan_out {
+            FanOut::All(_) => merge_candidates(candidates),
+            FanOut::Best { returned, .. } => {
+                let by_length = rank_by_length(&candidates);
+                let ranking = match config.copilot.fan_out.ranking {
+                    FanOutRanking::Longest => by_length,
+                    FanOutRanking::Judge => {
+                        match Self::judge(state.clone(), token, request, &candidates, session_id)
+                            .await
+                        {
+                            Some(ranking) => complete_ranking(ranking, by_length),
+                            None => by_length,
+                        }
+                    }
+                };
+                let selected = &ranking[..returned as usize];
+                info!(
+                    "Selected candidates {:?} of {}",
+                    selected.iter().map(|index| index + 1).collect::<Vec<_>>(),
+                    candidates.len()
+                );
+                select_candidates(candidates, selected)
+            }
+        };
+
+        info!("Successfully processed chat completion request");
+        Ok(openai_chat_response(
+            copilot_response,
+            tool_check,
+            config.copilot.reasoning.output,
+        ))
+    }
+
+    async fn judge(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        candidates: &[CopilotChatResponse],
+        session_id: &str,
+    ) -> Option<Vec<usize>> {
+        let config = state.config();
+        let model = config
+            .copilot
+            .fan_out
+            .judge_model
+            .clone()
+            .unwrap_or_else(|| request.model.clone());
+        let stats = StreamStats::new(state.metrics.clone(), "chat_completions", &model);
+        let result = Self::complete_once(
+            state,
+            token,
+            &judge_request(&model, request, candidates),
+            session_id,
+        )
+        .await
+        .map(|response| {
+            finish_stats(&stats, &response);
+            response.choices.into_iter().next().and_then(|choice| {
+                let reply = choice.message.content?.to_text();
+                parse_ranking(&reply, candidates.len())
+            })
+        });
+
+        match result {
+            Ok(Some(ranking)) => Some(ranking),
+            Ok(None) => {
+                warn!("Judge model gave no usable ranking, falling back to the longest candidate");
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Judge model call failed, falling back to the longest candidate: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Every choice of every candidate, re-indexed, with usage summed
+fn merge_candidates(candidates: Vec<CopilotChatResponse>) -> CopilotChatResponse {
+    let usage = total_usage(&candidates);
+    let mut candidates = candidates.into_iter();
+    let mut merged = candidates
+        .next()
+        .expect("fan-out issues at least one request");
+
+    merged
+        .choices
+        .extend(candidates.flat_map(|candidate| candidate.choices));
+    for (index, choice) in merged.choices.iter_mut().enumerate() {
+        choice.index = Some(index as u32);
+    }
+    merged.usage = usage;
+
+    merged
+}
+
+/// The `selected` candidates' choices in that order, re-indexed; usage still covers
+/// every candidate generated
+fn select_candidates(
+    candidates: Vec<CopilotChatResponse>,
+    selected: &[usize],
+) -> CopilotChatResponse {
+    let usage = total_usage(&candidates);
+    let mut candidates: Vec<_> = candidates.into_iter().map(Some).collect();
+    let mut chosen = selected.iter().map(|&index| {
+        candidates[index]
+            .take()
+            .expect("each candidate is selected at most once")
+    });
+    let mut merged = chosen.next().expect("at least one candidate is selected");
+
+    merged
+        .choices
+        .extend(chosen.flat_map(|candidate| candidate.choices));
+    for (index, choice) in merged.choices.iter_mut().enumerate() {
+        choice.index = Some(index as u32);
+    }
+    merged.usage = usage;
+
+    merged
+}
+
+/// Record a non-streamed reply in the stream metrics, counting the tokens it reports
+fn finish_stats(stats: &StreamStats, reply: &CopilotChatResponse) {
+    if let Some(usage) = &reply.usage {
+        stats.observe_completion_tokens(usage.completion_tokens.into());
+    }
+    stats.finish(true);
+}
+
+fn total_usage(candidates: &[CopilotChatResponse]) -> Option<CopilotUsage> {
+    candidates
+        .iter()
+        .filter_map(|candidate| candidate.usage.as_ref())
+        .fold(None, |total, usage| {
+            let total = total.unwrap_or(CopilotUsage {
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                total_tokens: 0,
+            });
+            Some(CopilotUsage {
+                prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+                completion_tokens: total.completion_tokens + usage.completion_tokens,
+                total_tokens: total.total_tokens + usage.total_tokens,
+            })
+        })
+}
+
+/// The answer a candidate gives: its first choice's text and tool calls
+fn candidate_text(candidate: &CopilotChatResponse) -> String {
+    let Some(choice) = candidate.choices.first() else {
+        return String::new();
+    };
+
+    let mut text = choice
+        .message
+        .content
+        .as_ref()
+        .map(|content| content.to_text())
+        .unwrap_or_default();
+    for call in choice.message.tool_calls.iter().flatten() {
+        let _ = write!(
+            text,
+            "\n{}({})",
+            call.function.name, call.function.arguments
+        );
+    }
+
+    text
+}
+
+/// Indices of the candidates from the most content to the least; the earliest wins ties
+fn rank_by_length(candidates: &[CopilotChatResponse]) -> Vec<usize> {
+    let lengths: Vec<_> = candidates
+        .iter()
+        .map(|candidate| candidate_text(candidate).chars().count())
+        .collect();
+    let mut ranking: Vec<_> = (0..candidates.len()).collect();
+    ranking.sort_by_key(|&index| std::cmp::Reverse(lengths[index]));
+
+    ranking
+}
+
+/// The judge's `ranking`, followed by any candidates it left out in `fallback` order
+fn complete_ranking(mut ranking: Vec<usize>, fallback: Vec<usize>) -> Vec<usize> {
+    for index in fallback {
+        if !ranking.contains(&index) {
+            ranking.push(index);
+        }
+    }
+
+    ranking
+}
+
+/// Ask `model` which candidate best answers the conversation's latest user message
+fn judge_request(
+    model: &str,
+    request: &CopilotChatRequest,
+    candidates: &[CopilotChatResponse],
+) -> CopilotChatRequest {
+    let question = request
+        .messages
+        .iter()
+        .rev()
+        .find(|message| message.role == "user")
+        .and_then(|message| message.content.as_ref())
+        .map(|content| content.to_text())
+        .unwrap_or_default();
+
+    let mut prompt = format!("Question:\n{}\n", question);
+    for (index, candidate) in candidates.iter().enumerate() {
+        let _ = write!(
+            prompt,
+            "\nCandidate {}:\n{}\n",
+            index + 1,
+            candidate_text(candidate)
+        );
+    }
+
+    CopilotChatRequest {
+        messages: vec![
+            CopilotMessage::text(
+                "system",
+                "You compare candidate answers to a question. Judge correctness first, then \
+                 helpfulness. Reply with only the candidate numbers, best first, separated \
+                 by commas.",
+            ),
+            CopilotMessage::text("user", prompt),
+        ],
+        model: model.to_string(),
+        temperature: Some(0.0),
+        max_tokens: Some(8 * candidates.len() as u32 + 8),
+        stream: Some(false),
+        tools: None,
+        tool_choice: None,
+        parallel_tool_calls: None,
+        stop: None,
+        response_format: None,
+        logprobs: None,
+        top_logprobs: None,
+        reasoning_effort: None,
+        user: None,
+    }
+}
+
+/// Zero-based indices, best first, from a reply such as "2, 3, 1" or "Candidate 2.";
+/// numbers out of range or repeated are skipped
+fn parse_ranking(reply: &str, count: usize) -> Option<Vec<usize>> {
+    let mut ranking = Vec::new();
+    for number in reply
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|digits| digits.parse::<usize>().ok())
+    {
+        if (1..=count).contains(&number) && !ranking.contains(&(number - 1)) {
+            ranking.push(number - 1);
+        }
+    }
+
+    (!ranking.is_empty()).then_some(ranking)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::openai::completion::models::OpenAIChatResponse;
+    use crate::server::test_token;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn candidate(id: &str, content: &str, completion_tokens: u32) -> CopilotChatResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop"
+            }],
+            "usage": {
+                "prompt_tokens": 10,
+                "completion_tokens": completion_tokens,
+                "total_tokens": 10 + completion_tokens
+            }
+        }))
+        .unwrap()
+    }
+
+    fn question(text: &str) -> CopilotChatRequest {
+        CopilotChatRequest {
+            messages: vec![CopilotMessage {
+                role: "user".to_string(),
+                content: Some(text.into()),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            }],
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        }
+    }
+
+    #[test]
+    fn test_fan_out_from_request() {
+        assert_eq!(FanOut::from_request(None, None, false, 4), Ok(None));
+        assert_eq!(FanOut::from_request(Some(1), Some(1), true, 4), Ok(None));
+        assert_eq!(
+            FanOut::from_request(Some(3), None, false, 4),
+            Ok(Some(FanOut::All(3)))
+        );
+        assert_eq!(
+            FanOut::from_request(None, Some(4), false, 4),
+            Ok(Some(FanOut::Best {
+                candidates: 4,
+                returned: 1
+            }))
+        );
+        assert_eq!(
+            FanOut::from_request(Some(2), Some(3), false, 4),
+            Ok(Some(FanOut::Best {
+                candidates: 3,
+                returned: 2
+            }))
+        );
+        assert_eq!(
+            FanOut::from_request(Some(2), Some(2), false, 4),
+            Ok(Some(FanOut::All(2)))
+        );
+
+        assert!(FanOut::from_request(Some(0), None, false, 4).is_err());
+        assert!(FanOut::from_request(Some(3), Some(2), false, 4).is_err());
+        assert!(FanOut::from_request(Some(2), Some(5), false, 4).is_err());
+        assert!(FanOut::from_request(Some(2), None, true, 4).is_err());
+        assert!(FanOut::from_request(Some(5), None, false, 4).is_err());
+    }
+
+    #[test]
+    fn test_merge_candidates() {
+        let merged = merge_candidates(vec![
+            candidate("a", "First", 3),
+            candidate("b", "Second", 5),
+        ]);
+
+        assert_eq!(merged.id, "a");
+        assert_eq!(merged.choices.len(), 2);
+        assert_eq!(merged.choices[1].index, Some(1));
+        assert_eq!(
+            merged.choices[1]
+                .message
+                .content
+                .as_ref()
+                .unwrap()
+                .to_text(),
+            "Second"
+        );
+        let usage = merged.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 20);
+        assert_eq!(usage.completion_tokens, 8);
+        assert_eq!(usage.total_tokens, 28);
+    }
+
+    #[test]
+    fn test_rank_by_length_and_select_candidates() {
+        let candidates = vec![
+            candidate("a", "Short", 1),
+            candidate("b", "A much longer answer", 4),
+            candidate("c", "Same length answer!!", 4),
+        ];
+
+        let ranking = rank_by_length(&candidates);
+        assert_eq!(ranking, vec![1, 2, 0]);
+
+        let selected = select_candidates(candidates, &ranking[..1]);
+        assert_eq!(selected.id, "b");
+        assert_eq!(selected.choices.len(), 1);
+        assert_eq!(selected.usage.unwrap().completion_tokens, 9);
+    }
+
+    #[test]
+    fn test_select_candidates_keeps_the_ranking_order() {
+        let candidates = vec![
+            candidate("a", "Short", 1),
+            candidate("b", "A much longer answer", 4),
+            candidate("c", "Same length answer!!", 4),
+        ];
+
+        let selected = select_candidates(candidates, &[2, 0]);
+        assert_eq!(selected.id, "c");
+        let texts: Vec<_> = selected
+            .choices
+            .iter()
+            .map(|choice| {
+                (
+                    choice.index,
+                    choice.message.content.as_ref().unwrap().to_text(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                (Some(0), "Same length answer!!".to_string()),
+                (Some(1), "Short".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ranking() {
+        assert_eq!(parse_ranking("2", 3), Some(vec![1]));
+        assert_eq!(parse_ranking("Candidate 3.", 3), Some(vec![2]));
+        assert_eq!(parse_ranking("3, 1, 3, 2", 3), Some(vec![2, 0, 1]));
+        assert_eq!(parse_ranking("4, 2", 3), Some(vec![1]));
+        assert_eq!(parse_ranking("4", 3), None);
+        assert_eq!(parse_ranking("0", 3), None);
+        assert_eq!(parse_ranking("none of them", 3), None);
+    }
+
+    #[test]
+    fn test_complete_ranking() {
+        assert_eq!(complete_ranking(vec![2], vec![1, 2, 0]), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_judge_request_lists_candidates() {
+        let request = question("What is 2 + 2?");
+
+        let judge = judge_request(
+            "gpt-4o-mini",
+            &request,
+            &[candidate("a", "4", 1), candidate("b", "5", 1)],
+        );
+
+        assert_eq!(judge.model, "gpt-4o-mini");
+        let prompt = judge.messages[1].content.as_ref().unwrap().to_text();
+        assert_eq!(
+            prompt,
+            "Question:\nWhat is 2 + 2?\n\nCandidate 1:\n4\n\nCandidate 2:\n5\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_returns_every_candidate() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hello"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+            })))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = mock_server.uri();
+        let state = Arc::new(AppState::new(&config, None));
+        let metrics = state.metrics.clone();
+        let token = test_token();
+        let request = question("Hi");
+
+        let response = Server::chat_completions_fan_out(
+            state,
+            token,
+            &request,
+            "session",
+            FanOut::All(3),
+            ToolCallCheck::default(),
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: OpenAIChatResponse = serde_json::from_slice(&body).unwrap();
+        let indices: Vec<_> = response.choices.iter().map(|c| c.index).collect();
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(response.usage.total_tokens, 18);
+        assert_eq!(metrics.streams_completed("chat_completions"), 3);
+        assert!(
+            metrics
+                .render()
+                .contains("passenger_stream_tokens_total{model=\"gpt-4o\"} 3")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_returns_the_best_n_of_best_of() {
+        let mock_server = MockServer::start().await;
+        for (content, tokens) in [("Hi", 1), ("Hello there, friend", 4), ("Hello there", 2)] {
+            Mock::given(method("POST"))
+                .and(path("/chat/completions"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "model": "gpt-4o",
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": content},
+                        "finish_reason": "stop"
+                    }],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": tokens, "total_tokens": 5 + tokens}
+                })))
+                .up_to_n_times(1)
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+        }
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = mock_server.uri();
+        let state = Arc::new(AppState::new(&config, None));
+        let token = test_token();
+        let request = question("Hi");
+
+        let response = Server::chat_completions_fan_out(
+            state,
+            token,
+            &request,
+            "session",
+            FanOut::Best {
+                candidates: 3,
+                returned: 2,
+            },
+            ToolCallCheck::default(),
+        )
+        .await
+        .unwrap();
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: OpenAIChatResponse = serde_json::from_slice(&body).unwrap();
+        let contents: Vec<_> = response
+            .choices
+            .iter()
+            .map(|c| c.message.content.clone().unwrap())
+            .collect();
+        assert_eq!(contents, vec!["Hello there, friend", "Hello there"]);
+        assert_eq!(response.usage.total_tokens, 22);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::server::openai::passthrough::forward_to_backend;
+use crate::server::{AppError, AppState, Server};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use std::sync::Arc;
+
+/// OpenAI image generation, forwarded to `[images] backend_url` when one is configured, so
+/// clients mixing chat and images can keep one base URL
+pub(crate) trait ImagesEndpoint {
+    /// `POST /v1/images/generations`
+    async fn image_generations(
+        state: State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError>;
+}
+
+impl ImagesEndpoint for Server {
+    async fn image_generations(
+        State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError> {
+        forward_to_backend(
+            &state,
+            &state.config().images,
+            "images/generations",
+            &headers,
+            body,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::response::IntoResponse;
+
+    #[tokio::test]
+    async fn test_no_backend_is_501() {
+        let state = Arc::new(AppState::new(&Config::default(), None));
+        let result = Server::image_generations(
+            State(state),
+            HeaderMap::new(),
+            Bytes::from_static(br#"{"prompt":"A lighthouse"}"#),
+        )
+        .await;
+
+        let response = result.unwrap_err().into_response();
+        assert_eq!(response.status(), 501);
+    }
+}
@@ -1,7 +1,13 @@
-use crate::copilot::models::CopilotModelsResponse;
-use crate::openai::completion::models::OpenAIModelsResponse;
+use crate::config::{ModelsConfig, PresetConfig, VirtualModelConfig};
+use crate::copilot::models::{CopilotModelsResponse, ModelFilter};
+use crate::copilot::presets::PRESET_PREFIX;
+use crate::openai::completion::models::{OpenAIModel, OpenAIModelsResponse};
 use crate::server::{AppError, AppState, Server};
-use axum::{Json, extract::State};
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tracing::log::{error, info};
 
@@ -10,34 +16,77 @@ pub trait CoPilotListModels {
     // List available models (OpenAI-compatible)
     async fn list_models(
         state: State<Arc<AppState>>,
+        filter: Query<ModelFilter>,
     ) -> Result<Json<OpenAIModelsResponse>, AppError>;
+
+    // Fetch the Copilot model catalogue
+    async fn copilot_models(state: Arc<AppState>) -> Result<CopilotModelsResponse, AppError>;
 }
 
 impl CoPilotListModels for Server {
     /// List available models (OpenAI-compatible)
     async fn list_models(
         State(state): State<Arc<AppState>>,
+        Query(filter): Query<ModelFilter>,
     ) -> Result<Json<OpenAIModelsResponse>, AppError> {
+        let config = state.config();
         info!("Received list models request");
+        filter.validate().map_err(AppError::BadRequest)?;
+
+        let mut copilot_response = Self::copilot_models(state.clone()).await?;
+        copilot_response
+            .models
+            .retain(|model| filter.matches(model));
+
+        // Presets stay listed when the model they forward to does
+        let presets: HashMap<String, PresetConfig> = config
+            .presets
+            .iter()
+            .filter(|(_, preset)| {
+                filter.is_empty()
+                    || copilot_response
+                        .models
+                        .iter()
+                        .any(|model| model.id == preset.model)
+            })
+            .map(|(name, preset)| (name.clone(), preset.clone()))
+            .collect();
+
+        // Virtual models are listed with the model they stand for
+        let virtual_models = virtual_models(&config.virtual_models, &copilot_response);
+
+        let display = &config.models;
+        copilot_response.models.sort_by(|a, b| a.id.cmp(&b.id));
+        let mut models: OpenAIModelsResponse = copilot_response.into();
+        models.data.extend(virtual_models);
+        models.data.extend(preset_models(&presets));
+        if config.echo.enabled && filter.is_empty() {
+            models.data.push(local_model(&config.echo.model));
+        }
+        apply_display(&mut models.data, display);
 
+        info!("Successfully processed model request");
+        Ok(Json(models))
+    }
+
+    async fn copilot_models(state: Arc<AppState>) -> Result<CopilotModelsResponse, AppError> {
+        let config = state.config();
         // Get a valid Copilot token
         let token = Self::get_token(state.clone()).await?;
 
         let response = state
             .client
-            .get(&state.config.github.copilot_models_url)
+            .get(&config.github.copilot_models_url)
             .header("Authorization", format!("Bearer {}", token.token))
             .header("Content-Type", "application/json")
             .header("Accept", "application/vnd.github+json")
             .header("X-GitHub-Api-Version", "2022-11-28")
+            .timeout(config.copilot.timeouts.total(false))
             .send()
             .await
             .map_err(|e| {
                 error!("Failed to send request to Copilot API: {}", e);
-                AppError::InternalServerError(format!(
-                    "Failed to communicate with Copilot API: {}",
-                    e
-                ))
+                AppError::upstream("Failed to communicate with Copilot API", e)
             })?;
 
         let status = response.status();
@@ -47,18 +96,172 @@ impl CoPilotListModels for Server {
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
             error!("Copilot API returned error: {} - {}", status, error_text);
-            return Err(AppError::InternalServerError(format!(
-                "Copilot API error: {} - {}",
-                status, error_text
-            )));
+            return Err(AppError::from_upstream_status(status, &error_text));
         }
 
-        let copilot_response: CopilotModelsResponse = response.json().await.map_err(|e| {
+        response.json().await.map_err(|e| {
             error!("Failed to parse Copilot response: {}", e);
-            AppError::InternalServerError(format!("Failed to parse Copilot response: {}", e))
-        })?;
+            AppError::upstream("Failed to parse Copilot response", e)
+        })
+    }
+}
 
-        info!("Successfully processed model request");
-        Ok(Json(copilot_response.into()))
+/// Presets are listed as virtual models so clients can pick them
+fn preset_models(presets: &HashMap<String, PresetConfig>) -> Vec<OpenAIModel> {
+    let mut names: Vec<&String> = presets.keys().collect();
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| local_model(&format!("{}{}", PRESET_PREFIX, name)))
+        .collect()
+}
+
+/// A model served by passenger-rs itself
+fn local_model(id: &str) -> OpenAIModel {
+    OpenAIModel {
+        id: id.to_string(),
+        object: "model".to_string(),
+        created: 1687882411,
+        owned_by: "passenger-rs".to_string(),
+        name: None,
+        description: None,
+    }
+}
+
+/// Virtual models whose model is among `listed`, by id
+fn virtual_models(
+    virtual_models: &HashMap<String, VirtualModelConfig>,
+    listed: &CopilotModelsResponse,
+) -> Vec<OpenAIModel> {
+    let mut models: Vec<OpenAIModel> = virtual_models
+        .iter()
+        .filter_map(|(id, virtual_model)| {
+            let model = listed
+                .models
+                .iter()
+                .find(|model| model.id == virtual_model.model)?;
+            Some(OpenAIModel {
+                id: id.clone(),
+                object: "model".to_string(),
+                created: 1687882411,
+                owned_by: model.family.clone(),
+                name: None,
+                description: None,
+            })
+        })
+        .collect();
+    models.sort_by(|a, b| a.id.cmp(&b.id));
+    models
+}
+
+/// Name, describe and order `models` as `[models.display]` says. Models without an
+/// `order` keep their relative position, after those with one.
+fn apply_display(models: &mut [OpenAIModel], display: &ModelsConfig) {
+    models.sort_by_key(|model| display.display_order(&model.id));
+    for model in models {
+        if let Some(display) = display.display(&model.id) {
+            model.name = display.name.clone();
+            model.description = display.description.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_models() {
+        let preset = |model: &str| PresetConfig {
+            model: model.to_string(),
+            system_prompt: None,
+            temperature: None,
+            stop: None,
+        };
+        let presets = HashMap::from([
+            ("translate".to_string(), preset("gpt-4o-mini")),
+            ("code-review".to_string(), preset("gpt-4o")),
+        ]);
+
+        let ids: Vec<_> = preset_models(&presets)
+            .into_iter()
+            .map(|model| model.id)
+            .collect();
+
+        assert_eq!(ids, vec!["preset:code-review", "preset:translate"]);
+    }
+
+    #[test]
+    fn test_virtual_models_follow_their_model() {
+        let listed: CopilotModelsResponse = serde_json::from_value(serde_json::json!({
+            "github-copilot": { "models": {
+                "gpt-4o": { "id": "gpt-4o", "name": "GPT-4o", "family": "gpt-4o" }
+            } }
+        }))
+        .unwrap();
+        let virtual_model = |model: &str| VirtualModelConfig {
+            model: model.to_string(),
+            temperature: Some(0.0),
+            max_tokens: None,
+            stop: None,
+            response_format: None,
+            reasoning_effort: None,
+        };
+        let configured = HashMap::from([
+            ("gpt-4o-json".to_string(), virtual_model("gpt-4o")),
+            ("gpt-4o-cold".to_string(), virtual_model("gpt-4o")),
+            ("o3-json".to_string(), virtual_model("o3")),
+        ]);
+
+        let models = virtual_models(&configured, &listed);
+
+        let ids: Vec<_> = models.iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(ids, vec!["gpt-4o-cold", "gpt-4o-json"]);
+        assert_eq!(models[0].owned_by, "gpt-4o");
+    }
+
+    #[test]
+    fn test_apply_display() {
+        let models: ModelsConfig = toml::from_str(
+            r#"
+            [display."claude-sonnet-4.5"]
+            name = "Claude Sonnet 4.5 (work)"
+            description = "Best for code review"
+            order = 2
+
+            [display."preset:translate"]
+            order = 1
+        "#,
+        )
+        .unwrap();
+        let model = |id: &str| OpenAIModel {
+            id: id.to_string(),
+            object: "model".to_string(),
+            created: 0,
+            owned_by: String::new(),
+            name: None,
+            description: None,
+        };
+        let mut data = vec![
+            model("gpt-4o"),
+            model("claude-sonnet-4.5"),
+            model("gpt-4.1"),
+            model("preset:translate"),
+        ];
+
+        apply_display(&mut data, &models);
+
+        let ids: Vec<_> = data.iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec!["preset:translate", "claude-sonnet-4.5", "gpt-4o", "gpt-4.1"]
+        );
+        assert_eq!(data[1].name.as_deref(), Some("Claude Sonnet 4.5 (work)"));
+        assert_eq!(data[1].description.as_deref(), Some("Best for code review"));
+        assert_eq!(data[0].name, None);
+
+        let json = serde_json::to_value(&data[2]).unwrap();
+        assert!(json.get("name").is_none());
     }
 }
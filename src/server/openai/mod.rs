@@ -1,3 +1,13 @@
+pub mod audio;
+pub mod azure;
 pub mod chat_completion;
+pub mod compare;
+pub mod completions;
+pub(crate) mod fan_out;
+pub mod images;
 pub mod list_models;
+pub mod moderations;
+pub(crate) mod passthrough;
+#[cfg(feature = "responses")]
 pub mod responses_chat;
+pub(crate) mod structured_outputs;
@@ -0,0 +1,180 @@
+use crate::copilot::{CopilotChatRequest, CopilotMessage};
+use crate::openai::moderation::models::{ModerationRequest, ModerationResponse, ModerationResult};
+use crate::openai::moderation::rules::merge_scores;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::{AppError, AppState, Server};
+use axum::{Json, extract::State};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::log::{info, warn};
+
+/// Reported as the model when no classifier model is involved
+const RULES_MODEL: &str = "passenger-rules";
+
+pub(crate) trait ModerationsEndpoint: CopilotIntegration {
+    /// OpenAI-compatible moderation, scored by the `[moderation]` rules
+    async fn moderations(
+        state: State<Arc<AppState>>,
+        request: Json<ModerationRequest>,
+    ) -> Result<Json<ModerationResponse>, AppError>;
+
+    /// Scores from the classifier model, or `None` if it could not be asked
+    async fn classify(
+        state: Arc<AppState>,
+        model: &str,
+        text: &str,
+        categories: &[String],
+    ) -> Option<BTreeMap<String, f64>>;
+}
+
+impl ModerationsEndpoint for Server {
+    async fn moderations(
+        State(state): State<Arc<AppState>>,
+        Json(request): Json<ModerationRequest>,
+    ) -> Result<Json<ModerationResponse>, AppError> {
+        info!("Received moderation request");
+
+        let texts = request.input.texts();
+        let categories = state.moderation.category_names();
+        let classifier = state.config().moderation.model.clone();
+
+        let mut results = Vec::with_capacity(texts.len());
+        for text in &texts {
+            let mut scores = state.moderation.scores(text);
+            if let Some(model) = &classifier
+                && let Some(model_scores) =
+                    Self::classify(state.clone(), model, text, &categories).await
+            {
+                scores = merge_scores(scores, model_scores);
+            }
+            results.push(ModerationResult::from(scores));
+        }
+
+        let flagged = results.iter().filter(|result| result.flagged).count();
+        info!("Moderated {} inputs, {} flagged", results.len(), flagged);
+
+        Ok(Json(ModerationResponse {
+            id: format!("modr-{}", uuid::Uuid::new_v4().simple()),
+            model: classifier
+                .or(request.model)
+                .unwrap_or_else(|| RULES_MODEL.to_string()),
+            results,
+        }))
+    }
+
+    async fn classify(
+        state: Arc<AppState>,
+        model: &str,
+        text: &str,
+        categories: &[String],
+    ) -> Option<BTreeMap<String, f64>> {
+        let session_id = uuid::Uuid::new_v4().to_string();
+
+        let result = async {
+            let token = Self::get_token(state.clone()).await?;
+            Self::complete_once(
+                state,
+                token,
+                &classification_request(model, text, categories),
+                &session_id,
+            )
+            .await
+        }
+        .await
+        .map(|response| {
+            response.choices.into_iter().next().and_then(|choice| {
+                let reply = choice.message.content?.to_text();
+                parse_classification(&reply, categories)
+            })
+        });
+
+        match result {
+            Ok(Some(scores)) => Some(scores),
+            Ok(None) => {
+                warn!("Moderation model gave no usable scores, using the local rules only");
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Moderation model call failed, using the local rules only: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+fn classification_request(model: &str, text: &str, categories: &[String]) -> CopilotChatRequest {
+    let system = format!(
+        "You are a content moderation classifier. Score the user's text for each of these \
+         categories with a probability between 0 and 1: {}. Reply with only a JSON object \
+         mapping every category to its score.",
+        categories.join(", ")
+    );
+
+    CopilotChatRequest {
+        messages: vec![
+            CopilotMessage::text("system", system),
+            CopilotMessage::text("user", text),
+        ],
+        model: model.to_string(),
+        temperature: Some(0.0),
+        max_tokens: Some(512),
+        stream: Some(false),
+        tools: None,
+        tool_choice: None,
+        parallel_tool_calls: None,
+        stop: None,
+        response_format: None,
+        logprobs: None,
+        top_logprobs: None,
+        reasoning_effort: None,
+        user: None,
+    }
+}
+
+/// Scores for known categories from a JSON object reply, tolerating surrounding prose or fences
+fn parse_classification(reply: &str, categories: &[String]) -> Option<BTreeMap<String, f64>> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(reply.get(start..=end)?).ok()?;
+
+    let scores: BTreeMap<String, f64> = object
+        .into_iter()
+        .filter(|(category, _)| categories.contains(category))
+        .filter_map(|(category, score)| Some((category, score.as_f64()?.clamp(0.0, 1.0))))
+        .collect();
+
+    (!scores.is_empty()).then_some(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn categories() -> Vec<String> {
+        vec!["hate".to_string(), "violence".to_string()]
+    }
+
+    #[test]
+    fn test_parse_classification() {
+        let reply = "```json\n{\"hate\": 0.9, \"violence\": 1.5, \"made-up\": 1}\n```";
+
+        let scores = parse_classification(reply, &categories()).unwrap();
+
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores["hate"], 0.9);
+        assert_eq!(scores["violence"], 1.0);
+    }
+
+    #[test]
+    fn test_parse_classification_rejects_unusable_replies() {
+        assert_eq!(parse_classification("I cannot help", &categories()), None);
+        assert_eq!(
+            parse_classification("{\"other\": 0.2}", &categories()),
+            None
+        );
+    }
+}
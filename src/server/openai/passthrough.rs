@@ -0,0 +1,48 @@
+use crate::config::BackendConfig;
+use crate::server::{AppError, AppState};
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, header};
+use axum::response::{IntoResponse, Response};
+use tracing::log::{error, info};
+
+/// Pass a request for `/v1/<path>` through to `backend` as it is, streaming its answer
+/// back, or refuse it with `501` when no backend is configured
+pub(crate) async fn forward_to_backend(
+    state: &AppState,
+    backend: &BackendConfig,
+    path: &str,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let Some(backend_url) = &backend.backend_url else {
+        info!("Refusing /v1/{}: no backend_url configured", path);
+        return Err(AppError::NotImplemented(format!(
+            "/v1/{} is not supported by GitHub Copilot",
+            path
+        )));
+    };
+
+    let url = format!("{}/{}", backend_url.trim_end_matches('/'), path);
+    info!("Forwarding /v1/{} to {}", path, url);
+    let mut request = state.client.post(&url).body(body);
+    if let Some(content_type) = headers.get(header::CONTENT_TYPE) {
+        request = request.header(header::CONTENT_TYPE, content_type);
+    }
+    if let Some(api_key) = &backend.api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    let upstream = request.send().await.map_err(|e| {
+        error!("Failed to reach {}: {}", url, e);
+        AppError::upstream("Failed to communicate with the backend", e)
+    })?;
+
+    let mut response = Response::builder().status(upstream.status());
+    if let Some(content_type) = upstream.headers().get(header::CONTENT_TYPE) {
+        response = response.header(header::CONTENT_TYPE, content_type);
+    }
+    response
+        .body(Body::from_stream(upstream.bytes_stream()))
+        .map(IntoResponse::into_response)
+        .map_err(|e| AppError::InternalServerError(e.to_string()))
+}
@@ -1,16 +1,29 @@
 use crate::copilot::CopilotChatRequest;
 use crate::copilot::CopilotChatResponse;
-use crate::openai::responses::models::prompt_request::PromptRequest;
+use crate::copilot::finish_reason::FinishReason;
+use crate::copilot::tool_calls::ToolCallCheck;
+use crate::openai::responses::models::prompt_request::parse_prompt_request;
 use crate::openai::responses::models::prompt_response::{
-    AdditionalParameters, AssistantContent, CompletionResponse, ContentPartText, Output,
-    OutputMessage, OutputRole, ResponseObject, ResponseStatus, ResponseStreamEvent, Text,
+    AdditionalParameters, AssistantContent, CompletionResponse, ContentPartText,
+    IncompleteDetailsReason, Output, OutputMessage, OutputRole, ResponseError, ResponseObject,
+    ResponseStatus, ResponseStreamEvent, Text,
 };
-use crate::server::copilot::CopilotIntegration;
+use crate::server::admission::{ChatAdmission, PreparedChat, admit};
+use crate::server::cancellation::CancellableStream;
+use crate::server::capabilities::with_adjustments_header;
+use crate::server::copilot::{CopilotIntegration, upstream_headers, with_upstream_headers};
+use crate::server::racing::ModelRacing;
+use crate::server::server_tools::auto_tools;
+use crate::server::session::with_session_header;
+use crate::server::sse_lines::SseLines;
+use crate::server::stream_errors::{malformed_chunk, read_error, reported_error, stream_error};
+use crate::server::stream_stats::StreamStats;
+use crate::server::with_tool_calls_header;
 use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
 use axum::{Json, extract::State};
 use futures_util::{StreamExt as _, TryStreamExt as _};
-use serde_json::Value;
 use std::io::Error;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -20,39 +33,44 @@ use tracing::log::{error, info, warn};
 pub(crate) trait OpenAiResponsesEndpoint: CopilotIntegration {
     async fn openai_responses_chat(
         state: State<Arc<AppState>>,
+        headers: HeaderMap,
         request_as_text: String,
     ) -> Result<Response, AppError>;
 
-    async fn openai_responses_chat_sse(response: reqwest::Response) -> Result<Response, AppError>;
+    async fn openai_responses_chat_sse(
+        response: reqwest::Response,
+        stats: Arc<StreamStats>,
+    ) -> Result<Response, AppError>;
 
     async fn openai_responses_chat_no_sse(
         response: reqwest::Response,
+        tool_check: ToolCallCheck,
     ) -> Result<Response, AppError>;
 }
 
 impl OpenAiResponsesEndpoint for Server {
     async fn openai_responses_chat(
         State(state): State<Arc<AppState>>,
+        headers: HeaderMap,
         request_as_text: String,
     ) -> Result<Response, AppError> {
         /*
          * We are not destructuring directly into a Json<PromptRequest> because the openai request
          * coming from Rig contains 2 "role" keys within the input["role" == "user"].
-         * It is causing serde to fail on doing serde_json::from_str::<PromptRequest>(&request_as_text), yet
-         * it is somewhat more laxist when parsing it into a json_serde::Value instead.
+         * PromptRequest's own Deserialize tolerates that; parsing it ourselves also lets us
+         * report the exact path of any malformed value.
          */
-        let request_as_value: Value = serde_json::from_str(&request_as_text).map_err(|e| {
-            error!("Failed to parse request body as JSON: {}", e);
-            AppError::BadRequest(format!("Invalid JSON: {}", e))
-        })?;
-        debug!(
-            "request_as_value:\n{}",
-            serde_json::to_string_pretty(&request_as_value).unwrap()
-        );
+        let request = parse_prompt_request(&request_as_text).map_err(|(path, e)| {
+            if e.is_syntax() || e.is_eof() {
+                error!("Failed to parse request body as JSON: {}", e);
+                return AppError::BadRequest(format!("Invalid JSON: {}", e));
+            }
 
-        let request: PromptRequest = serde_json::from_value(request_as_value).map_err(|e| {
-            error!("Failed to deserialize request into PromptRequest: {}", e);
-            AppError::BadRequest(format!("Invalid request structure: {}", e))
+            error!("Failed to deserialize request at {}: {}", path, e);
+            AppError::InvalidRequest {
+                message: format!("Invalid request structure at {}: {}", path, e),
+                param: path,
+            }
         })?;
 
         debug!(
@@ -62,11 +80,17 @@ impl OpenAiResponsesEndpoint for Server {
 
         let is_stream = request.stream;
 
-        // Get a valid Copilot token
-        let token = Self::get_token(state.clone()).await?;
+        let admission = admit(&state, &headers, request.user.as_deref())?;
+        let session_id = admission.session_id.clone();
 
         // Transform OpenAI request to Copilot format
-        let copilot_request: CopilotChatRequest = request.into();
+        let mut copilot_request: CopilotChatRequest = request.into();
+        let PreparedChat {
+            state,
+            token,
+            adjustments,
+        } = Self::prepare_chat(state, &headers, &admission, &mut copilot_request).await?;
+        let config = state.config();
 
         debug!(
             "copilot_request:\n{}",
@@ -74,23 +98,46 @@ impl OpenAiResponsesEndpoint for Server {
         );
 
         // Forward request to Copilot API
-        let copilot_url = format!("{}/chat/completions", state.config.copilot.api_base_url);
-
-        let response = Self::forward_prompt(state, token, copilot_url, &copilot_request).await?;
+        let copilot_url = format!("{}/chat/completions", config.copilot.api_base_url);
+
+        let tool_check =
+            ToolCallCheck::new(&config.copilot.tool_calls, copilot_request.tools.as_deref());
+        let stats = StreamStats::new(state.metrics.clone(), "responses", &copilot_request.model);
+        let auto_tools = auto_tools(&config.mcp, &headers);
+        let response = Self::forward_raced(
+            state.clone(),
+            token,
+            copilot_url,
+            &copilot_request,
+            &session_id,
+            is_stream,
+            auto_tools,
+        )
+        .await?;
+        stats.served_by(&response);
 
         let status = response.status();
         if !status.is_success() {
             return Self::handle_errors(response).await;
         }
+        let upstream = upstream_headers(&config.copilot.response_headers, response.headers());
 
-        if is_stream {
-            Self::openai_responses_chat_sse(response).await
+        let response = if is_stream {
+            Self::openai_responses_chat_sse(response, stats).await
         } else {
-            Self::openai_responses_chat_no_sse(response).await
-        }
+            Self::openai_responses_chat_no_sse(response, tool_check).await
+        };
+
+        response.map(|response| {
+            let response = with_upstream_headers(response, upstream);
+            with_adjustments_header(with_session_header(response, &session_id), &adjustments)
+        })
     }
 
-    async fn openai_responses_chat_sse(response: reqwest::Response) -> Result<Response, AppError> {
+    async fn openai_responses_chat_sse(
+        response: reqwest::Response,
+        stats: Arc<StreamStats>,
+    ) -> Result<Response, AppError> {
         use axum::response::sse::{Event, Sse};
 
         let now = SystemTime::now()
@@ -102,47 +149,70 @@ impl OpenAiResponsesEndpoint for Server {
 
         // State accumulated across chunks, captured by move into the closure.
         let mut accumulated_text = String::new();
+        let mut incomplete = None;
         let mut response_id = String::new();
         let mut response_model = String::new();
 
-        let sse_stream = byte_stream
-            .map_err(|e: reqwest::Error| {
-                error!("Error reading streaming response from Copilot: {}", e);
-                Error::other(e.to_string())
+        // One Copilot SSE line expands into at most a handful of Responses events. Lines
+        // are read as chunks rather than `ChatDelta`s for the chunk id and model.
+        // A failure mid-stream ends it with `response.failed`
+        let byte_stream = byte_stream.map_err(read_error);
+        let sse_stream = SseLines::new(byte_stream)
+            .inspect_ok({
+                let stats = stats.clone();
+                move |line| stats.observe_line(line)
             })
-            .flat_map(move |result| {
-                let events: Vec<Result<Event, Error>> = match result {
+            .scan(false, move |failed, result| {
+                if *failed {
+                    return futures_util::future::ready(None);
+                }
+                let translated = match result {
                     Err(e) => vec![Err(e)],
-                    Ok(bytes) => {
-                        let text = String::from_utf8_lossy(&bytes).into_owned();
-                        text.lines()
-                            .flat_map(|line| {
-                                translate_sse_line(
-                                    line,
-                                    now,
-                                    &mut response_id,
-                                    &mut response_model,
-                                    &mut accumulated_text,
-                                )
-                            })
-                            .collect()
-                    }
+                    Ok(line) => translate_sse_line(
+                        &line,
+                        now,
+                        &mut response_id,
+                        &mut response_model,
+                        &mut accumulated_text,
+                        &mut incomplete,
+                    ),
                 };
-                futures_util::stream::iter(events)
-            });
+                let mut events: Vec<Result<Event, Error>> = Vec::new();
+                for event in translated {
+                    if let Err(e) = event {
+                        error!("Ending Responses stream after an upstream error: {}", e);
+                        *failed = true;
+                        let error = stream_error(&e);
+                        events.push(emit_failed_event(
+                            now,
+                            &response_id,
+                            &response_model,
+                            &error,
+                        ));
+                        break;
+                    }
+                    events.push(event);
+                }
+                futures_util::future::ready(Some(futures_util::stream::iter(events)))
+            })
+            .flatten();
 
         info!("Streaming OpenAI Responses chat response");
+        let sse_stream = CancellableStream::new(sse_stream, stats);
         Ok(Sse::new(sse_stream).into_response())
     }
 
     async fn openai_responses_chat_no_sse(
         response: reqwest::Response,
+        tool_check: ToolCallCheck,
     ) -> Result<Response, AppError> {
-        let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+        let mut copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
             error!("Failed to parse Copilot response: {}", e);
-            AppError::InternalServerError(format!("Failed to parse Copilot response: {}", e))
+            AppError::upstream("Failed to parse Copilot response", e)
         })?;
 
+        let tool_report = tool_check.apply(&mut copilot_response);
+
         debug!(
             "copilot_response:\n{}",
             serde_json::to_string_pretty(&copilot_response).unwrap()
@@ -157,7 +227,10 @@ impl OpenAiResponsesEndpoint for Server {
 
         info!("Successfully processed OpenAI Responses chat request");
 
-        Ok(Json(openai_response).into_response())
+        Ok(with_tool_calls_header(
+            Json(openai_response).into_response(),
+            &tool_report,
+        ))
     }
 }
 
@@ -177,12 +250,13 @@ struct CopilotChunk {
 
 #[derive(Debug, serde::Deserialize)]
 struct CopilotChunkChoice {
+    /// Missing from chunks carrying only content filter annotations
+    #[serde(default)]
     delta: CopilotChunkDelta,
-    #[allow(dead_code)]
     finish_reason: Option<String>,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Default, serde::Deserialize)]
 struct CopilotChunkDelta {
     content: Option<String>,
 }
@@ -199,13 +273,15 @@ struct CopilotChunkUsage {
 /// Responses API SSE events.
 ///
 /// State that accumulates across calls (response_id, response_model,
-/// accumulated_text) is passed as mutable references.
+/// accumulated_text, and why the model stopped early if it did) is passed as
+/// mutable references.
 pub(crate) fn translate_sse_line(
     line: &str,
     created_at: u64,
     response_id: &mut String,
     response_model: &mut String,
     accumulated_text: &mut String,
+    incomplete: &mut Option<IncompleteDetailsReason>,
 ) -> Vec<Result<axum::response::sse::Event, Error>> {
     // Strip the "data: " prefix produced by Copilot's SSE format.
     let payload = match line.strip_prefix("data: ") {
@@ -220,13 +296,22 @@ pub(crate) fn translate_sse_line(
 
     // "[DONE]" signals the end of the Copilot stream.
     if payload == "[DONE]" {
-        return emit_completed_events(created_at, response_id, response_model, accumulated_text);
+        return emit_completed_events(
+            created_at,
+            response_id,
+            response_model,
+            accumulated_text,
+            incomplete.take(),
+        );
     }
 
     // Parse the chunk JSON.
     let chunk: CopilotChunk = match serde_json::from_str(payload) {
         Ok(c) => c,
         Err(e) => {
+            if let Some(e) = malformed_chunk(&e, payload).or_else(|| reported_error(payload)) {
+                return vec![Err(e)];
+            }
             warn!(
                 "Could not parse Copilot SSE chunk as JSON: {}: {}",
                 e, payload
@@ -235,6 +320,12 @@ pub(crate) fn translate_sse_line(
         }
     };
 
+    if let Some(reason) = chunk.choices.iter().find_map(|choice| {
+        FinishReason::parse(choice.finish_reason.as_deref())?.incomplete_details()
+    }) {
+        *incomplete = Some(reason);
+    }
+
     // On the first chunk, capture id/model and emit the lifecycle open events.
     if response_id.is_empty() && !chunk.id.is_empty() {
         *response_id = chunk.id.clone();
@@ -296,13 +387,20 @@ fn emit_delta_events(
         .collect()
 }
 
-/// Emit the four terminal lifecycle events once `[DONE]` is received.
+/// Emit the four terminal lifecycle events once `[DONE]` is received. The last is
+/// `response.incomplete` rather than `response.completed` when the model stopped early.
 fn emit_completed_events(
     created_at: u64,
     response_id: &str,
     response_model: &str,
     accumulated_text: &str,
+    incomplete: Option<IncompleteDetailsReason>,
 ) -> Vec<Result<axum::response::sse::Event, Error>> {
+    let status = if incomplete.is_some() {
+        ResponseStatus::Incomplete
+    } else {
+        ResponseStatus::Completed
+    };
     let full_text = accumulated_text.to_string();
 
     let text_done = make_event(ResponseStreamEvent::ResponseOutputTextDone {
@@ -325,7 +423,7 @@ fn emit_completed_events(
     let finished_message = OutputMessage {
         id: response_id.to_string(),
         role: OutputRole::Assistant,
-        status: ResponseStatus::Completed,
+        status: status.clone(),
         content: vec![AssistantContent::OutputText(Text {
             text: full_text.clone(),
         })],
@@ -340,9 +438,9 @@ fn emit_completed_events(
         id: response_id.to_string(),
         object: ResponseObject::Response,
         created_at,
-        status: ResponseStatus::Completed,
+        status,
         error: None,
-        incomplete_details: None,
+        incomplete_details: incomplete,
         instructions: None,
         max_output_tokens: None,
         model: response_model.to_string(),
@@ -352,8 +450,14 @@ fn emit_completed_events(
         additional_parameters: AdditionalParameters::default(),
     };
 
-    let completed = make_event(ResponseStreamEvent::ResponseCompleted {
-        response: completed_response,
+    let completed = make_event(if completed_response.incomplete_details.is_some() {
+        ResponseStreamEvent::ResponseIncomplete {
+            response: completed_response,
+        }
+    } else {
+        ResponseStreamEvent::ResponseCompleted {
+            response: completed_response,
+        }
     });
 
     vec![text_done, part_done, item_done, completed]
@@ -363,6 +467,26 @@ fn emit_completed_events(
 // Small constructors
 // ---------------------------------------------------------------------------
 
+/// The `response.failed` event ending a stream that failed with `error`
+fn emit_failed_event(
+    created_at: u64,
+    response_id: &str,
+    response_model: &str,
+    error: &AppError,
+) -> Result<axum::response::sse::Event, Error> {
+    let mut response = make_in_progress_response(
+        response_id.to_string(),
+        response_model.to_string(),
+        created_at,
+    );
+    response.status = ResponseStatus::Failed;
+    response.error = Some(ResponseError {
+        code: error.code().to_string(),
+        message: error.message(),
+    });
+    make_event(ResponseStreamEvent::ResponseFailed { response })
+}
+
 fn make_in_progress_response(id: String, model: String, created_at: u64) -> CompletionResponse {
     CompletionResponse {
         id,
@@ -400,6 +524,8 @@ fn make_event(event: ResponseStreamEvent) -> Result<axum::response::sse::Event,
         ResponseStreamEvent::ResponseContentPartDone { .. } => "response.content_part.done",
         ResponseStreamEvent::ResponseOutputItemDone { .. } => "response.output_item.done",
         ResponseStreamEvent::ResponseCompleted { .. } => "response.completed",
+        ResponseStreamEvent::ResponseIncomplete { .. } => "response.incomplete",
+        ResponseStreamEvent::ResponseFailed { .. } => "response.failed",
     };
 
     let data = serde_json::to_string(&event)
@@ -420,6 +546,8 @@ mod tests {
     use crate::openai::responses::models::prompt_response::{
         AssistantContent, Output, ResponseStatus,
     };
+    use crate::server::metrics::Metrics;
+    use proptest::prelude::*;
 
     // -----------------------------------------------------------------------
     // Helpers
@@ -467,7 +595,8 @@ mod tests {
         let mut id = String::new();
         let mut model = String::new();
         let mut text = String::new();
-        let result = translate_sse_line("", 0, &mut id, &mut model, &mut text);
+        let mut incomplete = None;
+        let result = translate_sse_line("", 0, &mut id, &mut model, &mut text, &mut incomplete);
         assert!(result.is_empty(), "empty line should produce no events");
     }
 
@@ -476,7 +605,8 @@ mod tests {
         let mut id = String::new();
         let mut model = String::new();
         let mut text = String::new();
-        let result = translate_sse_line("   ", 0, &mut id, &mut model, &mut text);
+        let mut incomplete = None;
+        let result = translate_sse_line("   ", 0, &mut id, &mut model, &mut text, &mut incomplete);
         assert!(result.is_empty());
     }
 
@@ -485,17 +615,44 @@ mod tests {
         let mut id = String::new();
         let mut model = String::new();
         let mut text = String::new();
+        let mut incomplete = None;
         // Lines that don't start with "data: " are silently skipped (warned but no events).
-        let result = translate_sse_line("event: ping", 0, &mut id, &mut model, &mut text);
+        let result = translate_sse_line(
+            "event: ping",
+            0,
+            &mut id,
+            &mut model,
+            &mut text,
+            &mut incomplete,
+        );
         assert!(result.is_empty());
     }
 
     #[test]
-    fn test_translate_malformed_json_returns_no_events() {
+    fn test_translate_malformed_json_returns_an_error() {
         let mut id = String::new();
         let mut model = String::new();
         let mut text = String::new();
-        let result = translate_sse_line("data: {bad json}", 0, &mut id, &mut model, &mut text);
+        let mut incomplete = None;
+        let result = translate_sse_line(
+            "data: {bad json}",
+            0,
+            &mut id,
+            &mut model,
+            &mut text,
+            &mut incomplete,
+        );
+        assert!(matches!(result.as_slice(), [Err(_)]));
+
+        // JSON of another shape is skipped
+        let result = translate_sse_line(
+            r#"data: {"id": 1}"#,
+            0,
+            &mut id,
+            &mut model,
+            &mut text,
+            &mut incomplete,
+        );
         assert!(result.is_empty());
     }
 
@@ -507,8 +664,10 @@ mod tests {
         let mut id = String::new();
         let mut model = String::new();
         let mut text = String::new();
+        let mut incomplete = None;
 
-        let events = translate_sse_line(&line, 100, &mut id, &mut model, &mut text);
+        let events =
+            translate_sse_line(&line, 100, &mut id, &mut model, &mut text, &mut incomplete);
 
         // First chunk: response.created, output_item.added, content_part.added, output_text.delta
         assert_eq!(events.len(), 4, "first chunk must emit 4 events");
@@ -542,8 +701,10 @@ mod tests {
         let mut id = "resp-1".to_string();
         let mut model = "gpt-4o".to_string();
         let mut text = "Hello".to_string();
+        let mut incomplete = None;
 
-        let events = translate_sse_line(&line, 100, &mut id, &mut model, &mut text);
+        let events =
+            translate_sse_line(&line, 100, &mut id, &mut model, &mut text, &mut incomplete);
 
         assert_eq!(
             events.len(),
@@ -566,8 +727,10 @@ mod tests {
         let mut id = "resp-1".to_string();
         let mut model = "gpt-4o".to_string();
         let mut text = String::new();
+        let mut incomplete = None;
 
-        let events = translate_sse_line(&line, 100, &mut id, &mut model, &mut text);
+        let events =
+            translate_sse_line(&line, 100, &mut id, &mut model, &mut text, &mut incomplete);
         assert!(events.is_empty(), "empty delta must not emit any event");
     }
 
@@ -576,8 +739,16 @@ mod tests {
         let mut id = "resp-1".to_string();
         let mut model = "gpt-4o".to_string();
         let mut text = "Hello world".to_string();
-
-        let events = translate_sse_line("data: [DONE]", 100, &mut id, &mut model, &mut text);
+        let mut incomplete = None;
+
+        let events = translate_sse_line(
+            "data: [DONE]",
+            100,
+            &mut id,
+            &mut model,
+            &mut text,
+            &mut incomplete,
+        );
 
         assert_eq!(events.len(), 4, "[DONE] must emit 4 terminal events");
 
@@ -622,9 +793,12 @@ mod tests {
         });
 
         let response = make_reqwest_response(copilot_body.to_string());
-        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_no_sse(response)
-            .await
-            .expect("should not error");
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_no_sse(
+            response,
+            ToolCallCheck::default(),
+        )
+        .await
+        .expect("should not error");
 
         assert_eq!(result.status(), 200);
 
@@ -661,9 +835,12 @@ mod tests {
         let body = format!("data: {chunk_payload}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(response)
-            .await
-            .expect("should not error");
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .expect("should not error");
 
         assert_eq!(result.status(), 200);
         let ct = result
@@ -681,9 +858,12 @@ mod tests {
         let body = format!("data: {chunk_payload}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .unwrap();
 
         let body_bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -717,15 +897,94 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_sse_response_malformed_chunk_fails_the_response() {
+        let chunk_payload = r#"{"id":"r1","model":"gpt-4o","choices":[{"delta":{"content":"Hi"},"finish_reason":null}]}"#;
+        let body = format!("data: {chunk_payload}\ndata: {{\"id\": \"r1\", \"cho\ndata: [DONE]\n");
+
+        let response = make_reqwest_response(body);
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .unwrap();
+
+        let body_bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let blocks = parse_sse_blocks(std::str::from_utf8(&body_bytes).unwrap());
+        let (event, failed) = blocks.last().unwrap();
+        assert_eq!(event, "response.failed");
+        assert_eq!(failed["response"]["id"], "r1");
+        assert_eq!(failed["response"]["status"], "failed");
+        assert_eq!(failed["response"]["error"]["code"], "stream_parse_error");
+        assert!(
+            !blocks
+                .iter()
+                .any(|(event, _)| event == "response.completed")
+        );
+    }
+
+    async fn sse_blocks(body: String) -> Vec<(String, serde_json::Value)> {
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            make_reqwest_response(body),
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .unwrap();
+        let body_bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        parse_sse_blocks(std::str::from_utf8(&body_bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_sse_response_length_finish_is_incomplete() {
+        let chunk = r#"{"id":"r1","model":"gpt-4o","choices":[{"delta":{"content":"Hi"},"finish_reason":null}]}"#;
+        let last =
+            r#"{"id":"r1","model":"gpt-4o","choices":[{"delta":{},"finish_reason":"length"}]}"#;
+        let blocks = sse_blocks(format!("data: {chunk}\ndata: {last}\ndata: [DONE]\n")).await;
+
+        let (event, incomplete) = blocks.last().unwrap();
+        assert_eq!(event, "response.incomplete");
+        assert_eq!(incomplete["response"]["status"], "incomplete");
+        assert_eq!(
+            incomplete["response"]["incomplete_details"]["reason"],
+            "max_output_tokens"
+        );
+        assert_eq!(incomplete["response"]["output"][0]["status"], "incomplete");
+    }
+
+    #[tokio::test]
+    async fn test_sse_response_reported_error_fails_the_response() {
+        let chunk = r#"{"id":"r1","model":"gpt-4o","choices":[{"delta":{"content":"Hi"},"finish_reason":null}]}"#;
+        let error = r#"{"error":{"message":"Upstream overloaded","code":"server_error"}}"#;
+        let blocks = sse_blocks(format!("data: {chunk}\ndata: {error}\ndata: [DONE]\n")).await;
+
+        let (event, failed) = blocks.last().unwrap();
+        assert_eq!(event, "response.failed");
+        assert_eq!(failed["response"]["error"]["code"], "upstream_error");
+        assert!(
+            failed["response"]["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("Upstream overloaded")
+        );
+    }
+
     #[tokio::test]
     async fn test_sse_response_delta_carries_correct_text() {
         let chunk_payload = r#"{"id":"r2","model":"gpt-4o","choices":[{"delta":{"content":"Hello"},"finish_reason":null}]}"#;
         let body = format!("data: {chunk_payload}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .unwrap();
 
         let body_bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -755,9 +1014,12 @@ mod tests {
         let body = format!("data: {chunk_payload}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .unwrap();
 
         let body_bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -780,9 +1042,12 @@ mod tests {
         let body = format!("data: {chunk1}\ndata: {chunk2}\ndata: [DONE]\n");
 
         let response = make_reqwest_response(body);
-        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(response)
-            .await
-            .unwrap();
+        let result = <Server as OpenAiResponsesEndpoint>::openai_responses_chat_sse(
+            response,
+            StreamStats::new(Arc::new(Metrics::default()), "responses", "gpt-4o"),
+        )
+        .await
+        .unwrap();
 
         let body_bytes = axum::body::to_bytes(result.into_body(), usize::MAX)
             .await
@@ -807,4 +1072,50 @@ mod tests {
             "accumulated text must be FooBar"
         );
     }
+
+    proptest! {
+        #[test]
+        fn prop_any_line_is_handled(line in any::<String>()) {
+            let mut id = String::new();
+            let mut model = String::new();
+            let mut text = String::new();
+            let mut incomplete = None;
+            for line in [line.clone(), format!("data: {}", line)] {
+                translate_sse_line(&line, 0, &mut id, &mut model, &mut text, &mut incomplete);
+            }
+        }
+
+        #[test]
+        fn prop_content_accumulates_across_chunks(
+            pieces in proptest::collection::vec(any::<String>(), 1..8),
+        ) {
+            let mut id = String::new();
+            let mut model = String::new();
+            let mut text = String::new();
+            let mut incomplete = None;
+            for piece in &pieces {
+                let chunk = serde_json::json!({
+                    "id": "resp-1",
+                    "model": "gpt-4o",
+                    "choices": [{"delta": {"content": piece}, "finish_reason": null}]
+                });
+                let line = format!("data: {}", chunk);
+                let events =
+                    translate_sse_line(&line, 0, &mut id, &mut model, &mut text, &mut incomplete);
+                prop_assert!(events.iter().all(Result::is_ok));
+            }
+            prop_assert_eq!(&text, &pieces.concat());
+            prop_assert_eq!(&id, "resp-1");
+
+            let done = translate_sse_line(
+                "data: [DONE]",
+                0,
+                &mut id,
+                &mut model,
+                &mut text,
+                &mut incomplete,
+            );
+            prop_assert!(!done.is_empty() && done.iter().all(Result::is_ok));
+        }
+    }
 }
@@ -0,0 +1,218 @@
+use crate::auth::CopilotTokenResponse;
+use crate::copilot::structured_outputs::{
+    SCHEMA_HEADER, SCHEMA_RETRIES_HEADER, SchemaReport, check_output, output_text,
+};
+use crate::copilot::tool_calls::ToolCallCheck;
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse};
+use crate::server::copilot::CopilotIntegration;
+use crate::server::openai::chat_completion::openai_chat_response;
+use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderValue;
+use axum::response::Response;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::log::{error, info, warn};
+
+/// Server-side enforcement of strict `response_format` schemas, under
+/// `[copilot.structured_outputs]`. Only non-streaming chat completions are checked.
+pub(crate) trait StructuredOutputs: CopilotIntegration {
+    async fn chat_completions_strict(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        schema: &Value,
+        session_id: &str,
+        response: reqwest::Response,
+        tool_check: ToolCallCheck,
+    ) -> Result<Response, AppError>;
+
+    /// Check `response` against `schema`, sending invalid replies back to the model with
+    /// the validation error until one matches or `max_retries` is reached
+    async fn enforce_schema(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        schema: &Value,
+        session_id: &str,
+        response: CopilotChatResponse,
+    ) -> (CopilotChatResponse, SchemaReport);
+}
+
+impl StructuredOutputs for Server {
+    async fn chat_completions_strict(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        schema: &Value,
+        session_id: &str,
+        response: reqwest::Response,
+        tool_check: ToolCallCheck,
+    ) -> Result<Response, AppError> {
+        let copilot_response: CopilotChatResponse = response.json().await.map_err(|e| {
+            error!("Failed to parse Copilot response: {}", e);
+            AppError::upstream("Failed to parse Copilot response", e)
+        })?;
+
+        let reasoning = state.config().copilot.reasoning.output;
+        let (copilot_response, report) =
+            Self::enforce_schema(state, token, request, schema, session_id, copilot_response).await;
+
+        info!("Successfully processed chat completion request");
+        Ok(with_schema_headers(
+            openai_chat_response(copilot_response, tool_check, reasoning),
+            &report,
+        ))
+    }
+
+    async fn enforce_schema(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        request: &CopilotChatRequest,
+        schema: &Value,
+        session_id: &str,
+        mut response: CopilotChatResponse,
+    ) -> (CopilotChatResponse, SchemaReport) {
+        let config = state.config();
+        let max_retries = config.copilot.structured_outputs.max_retries;
+        let mut conversation = request.clone();
+        let mut report = SchemaReport::default();
+
+        loop {
+            // Replies made of tool calls have no output to check
+            let Some(output) = output_text(&response) else {
+                return (response, report);
+            };
+            let Err(invalid) = check_output(&output, schema) else {
+                report.error = None;
+                return (response, report);
+            };
+            report.error = Some(invalid.clone());
+
+            if report.retries >= max_retries {
+                warn!(
+                    "Reply from {} still does not match the strict schema after {} retries: {}",
+                    request.model, report.retries, invalid
+                );
+                return (response, report);
+            }
+
+            info!(
+                "Reply from {} does not match the strict schema ({}), retrying",
+                request.model, invalid
+            );
+            conversation = conversation.with_schema_correction(&output, &invalid);
+
+            let retry =
+                Self::complete_once(state.clone(), token.clone(), &conversation, session_id).await;
+
+            match retry {
+                Ok(retried) => {
+                    report.retries += 1;
+                    response = retried;
+                }
+                Err(e) => {
+                    warn!(
+                        "Schema correction request failed, returning the invalid reply: {:?}",
+                        e
+                    );
+                    return (response, report);
+                }
+            }
+        }
+    }
+}
+
+/// Report the retries and outcome of strict schema enforcement on a response
+fn with_schema_headers(mut response: Response, report: &SchemaReport) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(SCHEMA_RETRIES_HEADER, HeaderValue::from(report.retries));
+    headers.insert(
+        SCHEMA_HEADER,
+        HeaderValue::from_static(report.header_value()),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::copilot::CopilotMessage;
+    use crate::server::test_token;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn reply(content: &str) -> serde_json::Value {
+        json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop"
+            }]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_enforce_schema_retries_with_correction() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains(
+                "does not match the required JSON schema",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(reply(r#"{"name": "Paris"}"#)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.copilot.api_base_url = mock_server.uri();
+        let state = Arc::new(AppState::new(&config, None));
+        let token = test_token();
+        let request = CopilotChatRequest {
+            messages: vec![CopilotMessage {
+                role: "user".to_string(),
+                content: Some("Name a city".into()),
+                padding: None,
+                tool_calls: None,
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            }],
+            model: "gpt-4o".to_string(),
+            temperature: None,
+            max_tokens: None,
+            stream: Some(false),
+            tools: None,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            stop: None,
+            response_format: None,
+            logprobs: None,
+            top_logprobs: None,
+            reasoning_effort: None,
+            user: None,
+        };
+        let schema = json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" } },
+            "required": ["name"]
+        });
+        let invalid = serde_json::from_value(reply("Paris")).unwrap();
+
+        let (response, report) =
+            Server::enforce_schema(state, token, &request, &schema, "session", invalid).await;
+
+        assert_eq!(
+            report,
+            SchemaReport {
+                retries: 1,
+                error: None
+            }
+        );
+        assert_eq!(output_text(&response).unwrap(), r#"{"name": "Paris"}"#);
+    }
+}
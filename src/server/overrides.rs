@@ -0,0 +1,196 @@
+use crate::config::AdminConfig;
+use crate::copilot::CopilotChatRequest;
+use crate::server::admin::grants_admin;
+use crate::server::{AppError, AppState};
+use arc_swap::ArcSwap;
+use axum::http::HeaderMap;
+use reqwest::Url;
+use std::sync::Arc;
+use tracing::log::{error, info};
+
+/// Replaces the requested model, for clients with a hard-coded one
+pub const MODEL_OVERRIDE_HEADER: &str = "X-Passenger-Model-Override";
+/// Replaces the requested temperature
+pub const TEMPERATURE_HEADER: &str = "X-Passenger-Temperature";
+/// Sends the request to another Copilot API; needs [`ADMIN_TOKEN_HEADER`]
+pub const BASE_URL_HEADER: &str = "X-Passenger-Copilot-Base-Url";
+/// The `[admin] token`, authorising [`BASE_URL_HEADER`]
+pub const ADMIN_TOKEN_HEADER: &str = "X-Passenger-Admin-Token";
+
+/// Request fields overridden through headers, applied by the proxy before anything else
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct RequestOverrides {
+    model: Option<String>,
+    temperature: Option<f32>,
+    base_url: Option<String>,
+}
+
+impl RequestOverrides {
+    pub(crate) fn from_headers(headers: &HeaderMap, admin: &AdminConfig) -> Result<Self, AppError> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| {
+                    value
+                        .to_str()
+                        .map(|value| value.trim().to_string())
+                        .map_err(|_| AppError::BadRequest(format!("{} is not valid text", name)))
+                })
+                .transpose()
+        };
+
+        let model = header(MODEL_OVERRIDE_HEADER)?.filter(|model| !model.is_empty());
+        let temperature = header(TEMPERATURE_HEADER)?
+            .map(|temperature| {
+                temperature
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|temperature| (0.0..=2.0).contains(temperature))
+                    .ok_or_else(|| {
+                        AppError::BadRequest(format!(
+                            "{} must be a number between 0 and 2, got \"{}\"",
+                            TEMPERATURE_HEADER, temperature
+                        ))
+                    })
+            })
+            .transpose()?;
+
+        let base_url = header(BASE_URL_HEADER)?
+            .map(|base_url| {
+                let token = headers
+                    .get(ADMIN_TOKEN_HEADER)
+                    .and_then(|value| value.to_str().ok());
+                // The Copilot token goes wherever this points
+                if !grants_admin(admin, token) {
+                    error!("Rejecting {} without the admin token", BASE_URL_HEADER);
+                    return Err(AppError::Unauthorized(format!(
+                        "{} requires [admin] to be enabled with a token, sent in {}",
+                        BASE_URL_HEADER, ADMIN_TOKEN_HEADER
+                    )));
+                }
+                Url::parse(&base_url).map_err(|e| {
+                    AppError::BadRequest(format!("Invalid {}: {}", BASE_URL_HEADER, e))
+                })?;
+                Ok(base_url.trim_end_matches('/').to_string())
+            })
+            .transpose()?;
+
+        Ok(Self {
+            model,
+            temperature,
+            base_url,
+        })
+    }
+
+    /// Override the fields of `request`
+    pub(crate) fn apply(&self, request: &mut CopilotChatRequest) {
+        if let Some(model) = &self.model {
+            info!("Overriding model {} with {}", request.model, model);
+            request.model = model.clone();
+        }
+        if let Some(temperature) = self.temperature {
+            request.temperature = Some(temperature);
+        }
+    }
+
+    /// `state`, pointed at the overriding Copilot API when there is one
+    pub(crate) fn state(&self, state: Arc<AppState>) -> Arc<AppState> {
+        let Some(base_url) = &self.base_url else {
+            return state;
+        };
+        info!("Sending request to Copilot API at {}", base_url);
+        let mut config = (*state.config()).clone();
+        config.copilot.api_base_url = base_url.clone();
+        let mut state = (*state).clone();
+        state.config = Arc::new(ArcSwap::from_pointee(config));
+        Arc::new(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    fn admin(token: Option<&str>) -> AdminConfig {
+        AdminConfig {
+            enabled: true,
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_model_and_temperature() {
+        let mut headers = HeaderMap::new();
+        headers.insert(MODEL_OVERRIDE_HEADER, HeaderValue::from_static("gpt-4.1"));
+        headers.insert(TEMPERATURE_HEADER, HeaderValue::from_static("0.3"));
+        let overrides = RequestOverrides::from_headers(&headers, &AdminConfig::default()).unwrap();
+
+        let mut request: CopilotChatRequest = serde_json::from_value(json!({
+            "model": "gpt-3.5-turbo",
+            "messages": [],
+            "temperature": 1.0
+        }))
+        .unwrap();
+        overrides.apply(&mut request);
+        assert_eq!(request.model, "gpt-4.1");
+        assert_eq!(request.temperature, Some(0.3));
+
+        headers.insert(TEMPERATURE_HEADER, HeaderValue::from_static("hot"));
+        assert!(matches!(
+            RequestOverrides::from_headers(&headers, &AdminConfig::default()),
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_base_url_needs_admin_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            BASE_URL_HEADER,
+            HeaderValue::from_static("https://copilot.example.com/"),
+        );
+        assert!(matches!(
+            RequestOverrides::from_headers(&headers, &AdminConfig::default()),
+            Err(AppError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            RequestOverrides::from_headers(&headers, &admin(Some("secret"))),
+            Err(AppError::Unauthorized(_))
+        ));
+
+        headers.insert(ADMIN_TOKEN_HEADER, HeaderValue::from_static("secret"));
+        let overrides = RequestOverrides::from_headers(&headers, &admin(Some("secret"))).unwrap();
+        assert_eq!(
+            overrides.base_url.as_deref(),
+            Some("https://copilot.example.com")
+        );
+    }
+
+    #[test]
+    fn test_base_url_rejected_without_admin_token_configured() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            BASE_URL_HEADER,
+            HeaderValue::from_static("https://attacker.example.com"),
+        );
+        assert!(matches!(
+            RequestOverrides::from_headers(&headers, &admin(None)),
+            Err(AppError::Unauthorized(_))
+        ));
+
+        headers.insert(ADMIN_TOKEN_HEADER, HeaderValue::from_static("anything"));
+        assert!(matches!(
+            RequestOverrides::from_headers(&headers, &admin(None)),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_no_headers_change_nothing() {
+        let overrides =
+            RequestOverrides::from_headers(&HeaderMap::new(), &AdminConfig::default()).unwrap();
+        assert_eq!(overrides, RequestOverrides::default());
+    }
+}
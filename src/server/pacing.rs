@@ -0,0 +1,174 @@
+use crate::config::CopilotPacingConfig;
+use crate::server::{AppError, AppState};
+use axum::extract::{Request, State};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::log::{debug, warn};
+
+/// Response header with the milliseconds the request's Copilot calls spent queueing
+pub(crate) const QUEUE_WAIT_HEADER: &str = "x-passenger-queue-wait-ms";
+
+tokio::task_local! {
+    /// Time the request being served has queued so far, under `[copilot.pacing]`
+    static QUEUE_WAIT: Arc<Mutex<Option<Duration>>>;
+}
+
+/// Token bucket spreading requests to Copilot over time, under `[copilot.pacing]`
+#[derive(Debug)]
+pub struct UpstreamPacer {
+    rate: f64,
+    burst: f64,
+    max_wait: Duration,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Requests that may leave now; negative once later ones are queued
+    tokens: f64,
+    updated: Instant,
+}
+
+impl UpstreamPacer {
+    /// The pacer requests to Copilot go through. `None` without `requests_per_second`.
+    pub fn from_config(config: &CopilotPacingConfig) -> Option<Self> {
+        let rate = config.requests_per_second.filter(|rate| *rate > 0.0)?;
+        let burst = f64::from(config.burst.max(1));
+        Some(Self {
+            rate,
+            burst,
+            max_wait: Duration::from_secs(config.max_wait_secs),
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                updated: Instant::now(),
+            }),
+        })
+    }
+
+    /// Take the next slot, returning how long to wait for it, or how long it would have
+    /// been when that is past `max_wait`
+    fn reserve(&self, now: Instant) -> Result<Duration, Duration> {
+        let mut bucket = self.bucket.lock().expect("pacing lock poisoned");
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        let wait = Duration::from_secs_f64((1.0 - bucket.tokens).max(0.0) / self.rate);
+        if wait > self.max_wait {
+            return Err(wait);
+        }
+        bucket.tokens -= 1.0;
+        Ok(wait)
+    }
+
+    /// Wait for a slot to send a request to Copilot, counting the wait towards the
+    /// response's [`QUEUE_WAIT_HEADER`]
+    pub(crate) async fn pace(&self) -> Result<(), AppError> {
+        let wait = self.reserve(Instant::now()).map_err(|wait| {
+            warn!(
+                "Refusing request: Copilot pacing queue is {}s long, over max_wait_secs = {}",
+                wait.as_secs(),
+                self.max_wait.as_secs()
+            );
+            AppError::RateLimited(format!(
+                "Too many requests queued for Copilot. Retry in {}s.",
+                wait.as_secs().max(1)
+            ))
+        })?;
+
+        if !wait.is_zero() {
+            debug!("Pacing request to Copilot for {}ms", wait.as_millis());
+            tokio::time::sleep(wait).await;
+        }
+        let _ = QUEUE_WAIT.try_with(|total| {
+            let mut total = total.lock().expect("queue wait lock poisoned");
+            *total = Some(total.unwrap_or_default() + wait);
+        });
+        Ok(())
+    }
+}
+
+/// Middleware reporting in [`QUEUE_WAIT_HEADER`] how long the request queued for Copilot,
+/// under `[copilot.pacing]`
+pub(crate) async fn report_queue_wait(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.pacer.is_none() {
+        return next.run(request).await;
+    }
+
+    let total = Arc::new(Mutex::new(None));
+    let mut response = QUEUE_WAIT.scope(total.clone(), next.run(request)).await;
+    let waited = *total.lock().expect("queue wait lock poisoned");
+    if let Some(waited) = waited {
+        response.headers_mut().insert(
+            QUEUE_WAIT_HEADER,
+            HeaderValue::from(waited.as_millis() as u64),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pacer(requests_per_second: f64, burst: u32) -> UpstreamPacer {
+        UpstreamPacer::from_config(&CopilotPacingConfig {
+            requests_per_second: Some(requests_per_second),
+            burst,
+            max_wait_secs: 2,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_reserve() {
+        let pacer = pacer(2.0, 2);
+        let start = Instant::now();
+
+        // The burst leaves at once, later requests every half second
+        assert_eq!(pacer.reserve(start), Ok(Duration::ZERO));
+        assert_eq!(pacer.reserve(start), Ok(Duration::ZERO));
+        assert_eq!(pacer.reserve(start), Ok(Duration::from_millis(500)));
+        assert_eq!(pacer.reserve(start), Ok(Duration::from_secs(1)));
+
+        // Past max_wait_secs, nothing is reserved
+        let later = start + Duration::from_millis(500);
+        assert_eq!(pacer.reserve(later), Ok(Duration::from_secs(1)));
+        assert_eq!(pacer.reserve(later), Ok(Duration::from_millis(1500)));
+        assert_eq!(pacer.reserve(later), Ok(Duration::from_secs(2)));
+        assert_eq!(pacer.reserve(later), Err(Duration::from_millis(2500)));
+
+        // An idle bucket refills up to the burst only
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(pacer.reserve(idle), Ok(Duration::ZERO));
+        assert_eq!(pacer.reserve(idle), Ok(Duration::ZERO));
+        assert_eq!(pacer.reserve(idle), Ok(Duration::from_millis(500)));
+    }
+
+    #[tokio::test]
+    async fn test_pace_reports_queue_wait() {
+        let pacer = pacer(20.0, 1);
+        let total = Arc::new(Mutex::new(None));
+
+        QUEUE_WAIT
+            .scope(total.clone(), async {
+                pacer.pace().await.unwrap();
+                pacer.pace().await.unwrap();
+            })
+            .await;
+        let waited = total.lock().unwrap().unwrap();
+        assert!(
+            waited > Duration::from_millis(40) && waited <= Duration::from_millis(50),
+            "{:?}",
+            waited
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use crate::config::DebugConfig;
+use crate::utc::UtcTime;
+use axum::http::{self, HeaderMap};
+use futures_util::StreamExt as _;
+use reqwest::{Request, Response};
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use tracing::log::{debug, warn};
+use uuid::Uuid;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// One Copilot exchange being written out, under `[debug] dump_payloads`
+pub(crate) struct PayloadDump {
+    /// The reply's file; the request's sits next to it
+    response_path: PathBuf,
+    redact: Vec<String>,
+}
+
+impl PayloadDump {
+    /// Write `request` to a new file in `[debug] dump_dir`, or return `None` when dumping is
+    /// off or the directory cannot be created
+    pub(crate) fn request(config: &DebugConfig, request: &Request) -> Option<Self> {
+        if !config.dump_payloads {
+            return None;
+        }
+
+        let dir = dump_dir(config);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!(
+                "Failed to create payload dump directory {}: {}",
+                dir.display(),
+                e
+            );
+            return None;
+        }
+
+        let id = format!(
+            "{}-{}",
+            UtcTime::now().to_compact(),
+            Uuid::new_v4().simple()
+        );
+        let mut out = format!("{} {}\n", request.method(), request.url());
+        write_headers(&mut out, request.headers(), &config.redact);
+        out.push('\n');
+        if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+            out.push_str(&redact_text(&String::from_utf8_lossy(body), &config.redact));
+            out.push('\n');
+        }
+        write(&dir.join(format!("{}.request.txt", id)), &out);
+
+        Some(Self {
+            response_path: dir.join(format!("{}.response.txt", id)),
+            redact: config.redact.clone(),
+        })
+    }
+
+    /// Pass `response` on unchanged, writing it out once its body has been read or dropped
+    pub(crate) fn response(self, response: Response) -> Response {
+        let mut head = format!("{:?} {}\n", response.version(), response.status());
+        write_headers(&mut head, response.headers(), &self.redact);
+        head.push('\n');
+
+        let mut builder = http::Response::builder()
+            .status(response.status())
+            .version(response.version());
+        if let Some(headers) = builder.headers_mut() {
+            *headers = response.headers().clone();
+        }
+
+        let mut pending = PendingResponse {
+            dump: self,
+            head,
+            body: Vec::new(),
+        };
+        let body = response.bytes_stream().map(move |chunk| {
+            if let Ok(bytes) = &chunk {
+                pending.body.extend_from_slice(bytes);
+            }
+            chunk
+        });
+        Response::from(
+            builder
+                .body(reqwest::Body::wrap_stream(body))
+                .expect("parts of a received response are valid"),
+        )
+    }
+}
+
+/// Writes the reply once its body is done or dropped
+struct PendingResponse {
+    dump: PayloadDump,
+    head: String,
+    body: Vec<u8>,
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        let body = redact_text(&String::from_utf8_lossy(&self.body), &self.dump.redact);
+        write(
+            &self.dump.response_path,
+            &format!("{}{}\n", self.head, body),
+        );
+    }
+}
+
+/// `[debug] dump_dir`, or `payloads` in the token storage directory
+fn dump_dir(config: &DebugConfig) -> PathBuf {
+    match &config.dump_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => crate::storage::get_storage_dir()
+            .map(|dir| dir.join("payloads"))
+            .unwrap_or_else(|_| PathBuf::from("payloads")),
+    }
+}
+
+fn write(path: &Path, contents: &str) {
+    match std::fs::write(path, contents) {
+        Ok(()) => debug!("Dumped Copilot payload to {}", path.display()),
+        Err(e) => warn!(
+            "Failed to dump Copilot payload to {}: {}",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn write_headers(out: &mut String, headers: &HeaderMap, redact: &[String]) {
+    for (name, value) in headers {
+        let value = value.to_str().unwrap_or("<binary>");
+        let value = if name == http::header::AUTHORIZATION {
+            match value.split_once(' ') {
+                Some((scheme, _)) => format!("{} {}", scheme, REDACTED),
+                None => REDACTED.to_string(),
+            }
+        } else if is_redacted(name.as_str(), redact) {
+            REDACTED.to_string()
+        } else {
+            value.to_string()
+        };
+        let _ = writeln!(out, "{}: {}", name, value);
+    }
+}
+
+fn is_redacted(name: &str, redact: &[String]) -> bool {
+    redact.iter().any(|field| field.eq_ignore_ascii_case(name))
+}
+
+/// Replace the values of fields named in `redact`, at any depth
+fn redact_value(value: &mut Value, redact: &[String]) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields.iter_mut() {
+                if is_redacted(name, redact) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(value, redact);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| redact_value(item, redact)),
+        _ => {}
+    }
+}
+
+/// A JSON body, or the JSON `data:` lines of an event stream, with the fields named in
+/// `redact` replaced. Anything else, and everything when there is nothing to redact, is
+/// kept as is.
+fn redact_text(text: &str, redact: &[String]) -> String {
+    if redact.is_empty() {
+        return text.to_string();
+    }
+    if let Ok(mut value) = serde_json::from_str::<Value>(text) {
+        redact_value(&mut value, redact);
+        return value.to_string();
+    }
+
+    text.split('\n')
+        .map(|line| {
+            match line
+                .strip_prefix("data: ")
+                .and_then(|data| serde_json::from_str::<Value>(data).ok())
+            {
+                Some(mut value) => {
+                    redact_value(&mut value, redact);
+                    format!("data: {}", value)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redact() -> Vec<String> {
+        vec!["content".to_string(), "X-Api-Key".to_string()]
+    }
+
+    #[test]
+    fn test_redact_text() {
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "my password"}]
+        })
+        .to_string();
+        let redacted: Value = serde_json::from_str(&redact_text(&body, &redact())).unwrap();
+        assert_eq!(redacted["messages"][0]["content"], REDACTED);
+        assert_eq!(redacted["messages"][0]["role"], "user");
+
+        let stream = "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\ndata: [DONE]\n";
+        assert_eq!(
+            redact_text(stream, &redact()),
+            "data: {\"choices\":[{\"delta\":{\"content\":\"[REDACTED]\"}}]}\n\ndata: [DONE]\n"
+        );
+        assert_eq!(redact_text(stream, &[]), stream);
+    }
+
+    #[tokio::test]
+    async fn test_dump_exchange() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DebugConfig {
+            dump_payloads: true,
+            dump_dir: Some(dir.path().display().to_string()),
+            redact: redact(),
+            dry_run: false,
+        };
+
+        let request = reqwest::Client::new()
+            .post("http://copilot.invalid/chat/completions")
+            .header("Authorization", "Bearer secret-token")
+            .header("X-Api-Key", "secret-key")
+            .json(&json!({"messages": [{"role": "user", "content": "hello"}]}))
+            .build()
+            .unwrap();
+        let dump = PayloadDump::request(&config, &request).unwrap();
+
+        let reply = http::Response::builder()
+            .status(200)
+            .header("content-type", "application/json")
+            .body(reqwest::Body::from(r#"{"choices":[]}"#))
+            .unwrap();
+        let response = dump.response(Response::from(reply));
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), r#"{"choices":[]}"#);
+
+        let mut files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+
+        let request = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(files[0].to_string_lossy().ends_with(".request.txt"));
+        assert!(request.starts_with("POST http://copilot.invalid/chat/completions\n"));
+        assert!(request.contains("authorization: Bearer [REDACTED]\n"));
+        assert!(request.contains("x-api-key: [REDACTED]\n"));
+        assert!(request.contains(r#""content":"[REDACTED]""#));
+        assert!(!request.contains("secret"));
+
+        let response = std::fs::read_to_string(&files[1]).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\ncontent-type: application/json\n\n"));
+        assert!(response.ends_with("{\"choices\":[]}\n"));
+    }
+}
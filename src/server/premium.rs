@@ -0,0 +1,419 @@
+use crate::copilot::premium::multiplier;
+use crate::server::metrics::escape_label;
+use crate::server::users::resolve_user;
+use crate::server::{AppError, AppState, Server};
+use crate::utc::UtcTime;
+use axum::{Json, extract::State};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use tracing::log::{debug, info, warn};
+
+/// Estimated premium request consumption for the current month, in memory
+#[derive(Debug, Default)]
+pub struct PremiumUsage {
+    ledger: Mutex<Ledger>,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    /// `YYYY-MM` the counts belong to; Copilot quotas reset on the 1st (UTC)
+    month: String,
+    models: BTreeMap<String, ModelUsage>,
+    /// Keyed on the OpenAI `user` field, for requests that send one
+    users: BTreeMap<String, ModelUsage>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ModelUsage {
+    pub requests: u64,
+    pub premium_requests: f64,
+}
+
+/// Body of `/v1/usage/premium`
+#[derive(Debug, Serialize)]
+pub struct PremiumUsageReport {
+    pub month: String,
+    pub premium_requests: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_budget: Option<f64>,
+    pub models: BTreeMap<String, ModelUsage>,
+    /// Per end user, as sent in the OpenAI `user` field
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<String, ModelUsage>,
+}
+
+fn current_month() -> String {
+    UtcTime::now().month_key()
+}
+
+impl PremiumUsage {
+    /// Count `requests` requests costing `premium` premium requests in total, against
+    /// `model` and `user` when given, unless that would exceed `budget`. Returns the month's
+    /// total so far when they are refused.
+    pub fn charge(
+        &self,
+        model: &str,
+        user: Option<&str>,
+        requests: u32,
+        premium: f64,
+        budget: Option<f64>,
+    ) -> Result<(), f64> {
+        self.charge_in(&current_month(), model, user, requests, premium, budget)
+    }
+
+    fn charge_in(
+        &self,
+        month: &str,
+        model: &str,
+        user: Option<&str>,
+        requests: u32,
+        premium: f64,
+        budget: Option<f64>,
+    ) -> Result<(), f64> {
+        let mut ledger = self.ledger.lock().expect("premium usage lock poisoned");
+        if ledger.month != month {
+            ledger.month = month.to_string();
+            ledger.models.clear();
+            ledger.users.clear();
+        }
+
+        let total: f64 = ledger.models.values().map(|m| m.premium_requests).sum();
+        if let Some(budget) = budget
+            && premium > 0.0
+            && total + premium > budget
+        {
+            return Err(total);
+        }
+
+        let usage = ledger.models.entry(model.to_string()).or_default();
+        usage.requests += u64::from(requests);
+        usage.premium_requests += premium;
+        if let Some(user) = user {
+            let usage = ledger.users.entry(user.to_string()).or_default();
+            usage.requests += u64::from(requests);
+            usage.premium_requests += premium;
+        }
+        Ok(())
+    }
+
+    /// Take back a [`charge`](Self::charge) made this month, for requests Copilot did not
+    /// answer
+    pub fn refund(&self, model: &str, user: Option<&str>, requests: u32, premium: f64) {
+        self.refund_in(&current_month(), model, user, requests, premium)
+    }
+
+    fn refund_in(&self, month: &str, model: &str, user: Option<&str>, requests: u32, premium: f64) {
+        let mut ledger = self.ledger.lock().expect("premium usage lock poisoned");
+        if ledger.month != month {
+            return;
+        }
+
+        let ledger = &mut *ledger;
+        let charged = [(&mut ledger.models, Some(model)), (&mut ledger.users, user)];
+        for (counts, key) in charged {
+            let Some(key) = key else { continue };
+            let Some(usage) = counts.get_mut(key) else {
+                continue;
+            };
+            usage.requests = usage.requests.saturating_sub(u64::from(requests));
+            usage.premium_requests = (usage.premium_requests - premium).max(0.0);
+            if usage.requests == 0 {
+                counts.remove(key);
+            }
+        }
+    }
+
+    pub fn report(&self, monthly_budget: Option<f64>) -> PremiumUsageReport {
+        let month = current_month();
+        let ledger = self.ledger.lock().expect("premium usage lock poisoned");
+        let (models, users) = if ledger.month == month {
+            (ledger.models.clone(), ledger.users.clone())
+        } else {
+            (BTreeMap::new(), BTreeMap::new())
+        };
+
+        PremiumUsageReport {
+            month,
+            premium_requests: models.values().map(|m| m.premium_requests).sum(),
+            monthly_budget,
+            models,
+            users,
+        }
+    }
+
+    /// Render the month's counts in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let report = self.report(None);
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP passenger_premium_requests Estimated premium requests consumed this month"
+        );
+        let _ = writeln!(out, "# TYPE passenger_premium_requests gauge");
+        for (model, usage) in &report.models {
+            let _ = writeln!(
+                out,
+                "passenger_premium_requests{{model=\"{}\"}} {}",
+                escape_label(model),
+                usage.premium_requests
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP passenger_model_requests Requests forwarded to each model this month"
+        );
+        let _ = writeln!(out, "# TYPE passenger_model_requests gauge");
+        for (model, usage) in &report.models {
+            let _ = writeln!(
+                out,
+                "passenger_model_requests{{model=\"{}\"}} {}",
+                escape_label(model),
+                usage.requests
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP passenger_user_premium_requests Estimated premium requests consumed this month by each end user"
+        );
+        let _ = writeln!(out, "# TYPE passenger_user_premium_requests gauge");
+        for (user, usage) in &report.users {
+            let _ = writeln!(
+                out,
+                "passenger_user_premium_requests{{user=\"{}\"}} {}",
+                escape_label(user),
+                usage.premium_requests
+            );
+        }
+
+        out
+    }
+}
+
+/// Premium request accounting, under `[premium]`
+pub(crate) trait PremiumAccounting {
+    /// Count `requests` requests to `model` on behalf of `user`, or the OIDC user the request
+    /// was authenticated as, refusing them past the monthly budget
+    fn charge_premium(
+        state: &AppState,
+        model: &str,
+        user: Option<&str>,
+        requests: u32,
+    ) -> Result<(), AppError>;
+
+    /// Take back what [`charge_premium`](Self::charge_premium) counted, for requests Copilot
+    /// did not answer
+    fn refund_premium(state: &AppState, model: &str, user: Option<&str>, requests: u32);
+
+    async fn premium_usage(state: State<Arc<AppState>>) -> Json<PremiumUsageReport>;
+}
+
+impl PremiumAccounting for Server {
+    fn charge_premium(
+        state: &AppState,
+        model: &str,
+        user: Option<&str>,
+        requests: u32,
+    ) -> Result<(), AppError> {
+        let config = state.config();
+        if config.echo.serves(model) {
+            return Ok(());
+        }
+        let config = &config.premium;
+        let premium = multiplier(config, model) * f64::from(requests);
+        let user = resolve_user(user);
+
+        state
+            .premium
+            .charge(model, user.as_deref(), requests, premium, config.monthly_budget)
+            .map_err(|used| {
+                warn!(
+                    "Refusing request to {}: {} premium requests would exceed the monthly budget ({} of {} used)",
+                    model,
+                    premium,
+                    used,
+                    config.monthly_budget.unwrap_or_default()
+                );
+                AppError::QuotaExceeded(format!(
+                    "Monthly premium request budget exhausted: {} of {} used, and model {} costs {}",
+                    used,
+                    config.monthly_budget.unwrap_or_default(),
+                    model,
+                    premium
+                ))
+            })
+    }
+
+    fn refund_premium(state: &AppState, model: &str, user: Option<&str>, requests: u32) {
+        let config = state.config();
+        if config.echo.serves(model) {
+            return;
+        }
+        let premium = multiplier(&config.premium, model) * f64::from(requests);
+        let user = resolve_user(user);
+        debug!("Refunding {} premium requests to {}", premium, model);
+        state
+            .premium
+            .refund(model, user.as_deref(), requests, premium);
+    }
+
+    async fn premium_usage(State(state): State<Arc<AppState>>) -> Json<PremiumUsageReport> {
+        info!("Received premium usage request");
+        Json(state.premium.report(state.config().premium.monthly_budget))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge_within_budget() {
+        let usage = PremiumUsage::default();
+
+        assert_eq!(
+            usage.charge_in("2025-01", "claude-opus-4", None, 1, 10.0, Some(12.0)),
+            Ok(())
+        );
+        assert_eq!(
+            usage.charge_in("2025-01", "gpt-4.1", None, 1, 0.0, Some(12.0)),
+            Ok(())
+        );
+        assert_eq!(
+            usage.charge_in("2025-01", "claude-sonnet-4", None, 1, 1.0, Some(12.0)),
+            Ok(())
+        );
+        // Over budget: refused and not counted, but included models still go through
+        assert_eq!(
+            usage.charge_in("2025-01", "claude-opus-4", None, 1, 10.0, Some(12.0)),
+            Err(11.0)
+        );
+        assert_eq!(
+            usage.charge_in("2025-01", "gpt-4.1", None, 1, 0.0, Some(12.0)),
+            Ok(())
+        );
+
+        let ledger = usage.ledger.lock().unwrap();
+        assert_eq!(
+            ledger.models["claude-opus-4"],
+            ModelUsage {
+                requests: 1,
+                premium_requests: 10.0
+            }
+        );
+        assert_eq!(ledger.models["gpt-4.1"].requests, 2);
+    }
+
+    #[test]
+    fn test_charge_per_user() {
+        let usage = PremiumUsage::default();
+
+        usage
+            .charge_in("2025-01", "claude-opus-4", Some("alice"), 1, 10.0, None)
+            .unwrap();
+        usage
+            .charge_in("2025-01", "claude-sonnet-4", Some("alice"), 2, 2.0, None)
+            .unwrap();
+        usage
+            .charge_in("2025-01", "claude-sonnet-4", None, 1, 1.0, None)
+            .unwrap();
+
+        let ledger = usage.ledger.lock().unwrap();
+        assert_eq!(
+            ledger.users["alice"],
+            ModelUsage {
+                requests: 3,
+                premium_requests: 12.0
+            }
+        );
+        assert_eq!(ledger.users.len(), 1);
+        assert_eq!(ledger.models["claude-sonnet-4"].requests, 3);
+    }
+
+    #[test]
+    fn test_new_month_resets_usage() {
+        let usage = PremiumUsage::default();
+
+        usage
+            .charge_in("2025-01", "o1", None, 1, 10.0, Some(10.0))
+            .unwrap();
+        assert!(
+            usage
+                .charge_in("2025-01", "o1", None, 1, 10.0, Some(10.0))
+                .is_err()
+        );
+        assert!(
+            usage
+                .charge_in("2025-02", "o1", None, 1, 10.0, Some(10.0))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_refund() {
+        let usage = PremiumUsage::default();
+        usage
+            .charge_in(
+                "2025-01",
+                "claude-opus-4",
+                Some("alice"),
+                1,
+                10.0,
+                Some(12.0),
+            )
+            .unwrap();
+        usage
+            .charge_in(
+                "2025-01",
+                "claude-sonnet-4",
+                Some("alice"),
+                1,
+                1.0,
+                Some(12.0),
+            )
+            .unwrap();
+
+        usage.refund_in("2025-01", "claude-opus-4", Some("alice"), 1, 10.0);
+        {
+            let ledger = usage.ledger.lock().unwrap();
+            assert!(!ledger.models.contains_key("claude-opus-4"));
+            assert_eq!(
+                ledger.users["alice"],
+                ModelUsage {
+                    requests: 1,
+                    premium_requests: 1.0
+                }
+            );
+        }
+        // The refunded budget can be spent again
+        assert_eq!(
+            usage.charge_in("2025-01", "claude-opus-4", None, 1, 10.0, Some(12.0)),
+            Ok(())
+        );
+
+        // Charges of a past month are gone already
+        usage.refund_in("2024-12", "claude-opus-4", None, 1, 10.0);
+        assert_eq!(
+            usage.ledger.lock().unwrap().models["claude-opus-4"].requests,
+            1
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let usage = PremiumUsage::default();
+        usage
+            .charge("claude-sonnet-4", Some("alice"), 2, 2.0, None)
+            .unwrap();
+
+        let rendered = usage.render();
+
+        assert!(rendered.contains("passenger_premium_requests{model=\"claude-sonnet-4\"} 2"));
+        assert!(rendered.contains("passenger_model_requests{model=\"claude-sonnet-4\"} 2"));
+        assert!(rendered.contains("passenger_user_premium_requests{user=\"alice\"} 2"));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::ProfileConfig;
+use crate::server::{AppError, AppState};
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::log::warn;
+
+pub(crate) const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// A `[profiles.<name>]`, serving the API under `/<name>`
+#[derive(Debug)]
+pub struct Profile {
+    pub name: String,
+    pub config: ProfileConfig,
+    limiter: Option<RateLimiter>,
+}
+
+impl Profile {
+    pub fn new(name: &str, config: &ProfileConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config: config.clone(),
+            limiter: config.requests_per_minute.map(RateLimiter::new),
+        }
+    }
+
+    pub fn copilot_token_path(&self) -> Option<&Path> {
+        self.config.copilot_token_path.as_deref().map(Path::new)
+    }
+
+    pub fn access_token_path(&self) -> Option<&Path> {
+        self.config.access_token_path.as_deref().map(Path::new)
+    }
+
+    /// The model for requests that name none
+    pub fn default_model(&self) -> Option<&str> {
+        self.config.default_model.as_deref()
+    }
+}
+
+/// At most `limit` requests per minute, counted in fixed one-minute windows
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    pub(crate) limit: u32,
+    window: Mutex<(Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Count one request, or return how long until the next one is allowed
+    pub(crate) fn acquire(&self) -> Result<(), Duration> {
+        let mut window = self.window.lock().expect("rate limiter lock poisoned");
+        let (started, count) = &mut *window;
+        let elapsed = started.elapsed();
+        if elapsed >= RATE_LIMIT_WINDOW {
+            *started = Instant::now();
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Err(RATE_LIMIT_WINDOW.saturating_sub(started.elapsed()));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+/// Reject requests over the profile's `requests_per_minute` with `429`
+pub(crate) async fn enforce_rate_limit(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(profile) = state.profile.as_deref() else {
+        return next.run(request).await;
+    };
+    let Some(limiter) = &profile.limiter else {
+        return next.run(request).await;
+    };
+
+    match limiter.acquire() {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let retry_after = retry_after.as_secs().max(1);
+            warn!(
+                "Profile {} is over its limit of {} requests per minute",
+                profile.name, limiter.limit
+            );
+            let mut response = AppError::RateLimited(format!(
+                "Rate limit of {} requests per minute reached for profile {}. Retry in {}s.",
+                limiter.limit, profile.name, retry_after
+            ))
+            .into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2);
+        assert!(limiter.acquire().is_ok());
+        assert!(limiter.acquire().is_ok());
+        let retry_after = limiter.acquire().unwrap_err();
+        assert!(retry_after <= RATE_LIMIT_WINDOW);
+
+        // A new window starts once the current one has passed
+        limiter.window.lock().unwrap().0 = Instant::now() - RATE_LIMIT_WINDOW;
+        assert!(limiter.acquire().is_ok());
+    }
+}
@@ -0,0 +1,549 @@
+use crate::auth::CopilotTokenResponse;
+use crate::config::RacePolicy;
+use crate::copilot::CopilotChatRequest;
+use crate::server::content_filter::ContentFilter;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::dedup::dedup_key;
+use crate::server::empty_choices::EmptyChoicesRetry;
+use crate::server::fallback::{ModelFallback, with_served_model};
+#[cfg(feature = "history")]
+use crate::server::history::ConversationHistory;
+use crate::server::keys;
+use crate::server::server_tools::ServerTools;
+use crate::server::{AppError, AppState, Server};
+use axum::http;
+use futures_util::StreamExt as _;
+use reqwest::Response;
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::log::info;
+
+/// Which of the two raced models answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Racer {
+    Strong,
+    Fast,
+}
+
+impl fmt::Display for Racer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Racer::Strong => write!(f, "strong"),
+            Racer::Fast => write!(f, "fast"),
+        }
+    }
+}
+
+/// Speculative dual-model racing, under `[copilot.racing]`
+pub(crate) trait ModelRacing:
+    CopilotIntegration + EmptyChoicesRetry + ContentFilter + ServerTools
+{
+    /// Forward `request`, racing it against the configured fast model when it targets
+    /// the strong one, and down its `[copilot.fallbacks]` chain while its model is
+    /// unavailable. Otherwise behaves exactly like `forward_prompt`.
+    ///
+    /// Identical concurrent requests share one call, under `[copilot.deduplication]`.
+    /// Non-streaming replies without any choices are retried, under `[copilot.empty_choices]`,
+    /// and filtered ones under `[copilot.content_filter]`. Calls to server tools are run on
+    /// the proxy when `auto_tools`, and the exchange is logged under `[history]`.
+    async fn forward_raced(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        stream: bool,
+        auto_tools: bool,
+    ) -> Result<Response, AppError>;
+
+    /// The racing itself, without the empty choices retries
+    async fn race_models(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        stream: bool,
+    ) -> Result<Response, AppError>;
+}
+
+impl ModelRacing for Server {
+    async fn forward_raced(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        stream: bool,
+        auto_tools: bool,
+    ) -> Result<Response, AppError> {
+        let tooled = if auto_tools {
+            Self::with_server_tools(state.clone(), request, stream).await
+        } else {
+            None
+        };
+        let forwarded = tooled.as_ref().unwrap_or(request);
+
+        let raced = Self::forward_with_fallbacks(
+            state.clone(),
+            token.clone(),
+            url.clone(),
+            forwarded,
+            session_id,
+            stream,
+        );
+        let deduplication = &state.config().copilot.deduplication;
+        let response = match dedup_key(deduplication, &url, &token.token, forwarded, stream) {
+            Some(key) => state.in_flight.coalesce(key, raced).await?,
+            None => raced.await?,
+        };
+        let response = if stream {
+            response
+        } else {
+            Self::retry_empty_choices(
+                state.clone(),
+                token.clone(),
+                url.clone(),
+                forwarded,
+                session_id,
+                response,
+            )
+            .await?
+        };
+        let response = if stream {
+            response
+        } else {
+            Self::handle_content_filter(
+                state.clone(),
+                token.clone(),
+                url.clone(),
+                forwarded,
+                session_id,
+                response,
+            )
+            .await?
+        };
+        let response = match &tooled {
+            Some(tooled) => {
+                Self::run_server_tools(state.clone(), token, url, tooled, session_id, response)
+                    .await?
+            }
+            None => response,
+        };
+        #[cfg(feature = "history")]
+        let response = Self::record_exchange(&state, session_id, request, response, stream).await?;
+        Ok(response)
+    }
+
+    async fn race_models(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        stream: bool,
+    ) -> Result<Response, AppError> {
+        let racing = state.config().copilot.racing.clone();
+        // The fast model is held to the key's models like the requested one
+        let fast_model = racing
+            .fast_model_for(&request.model)
+            .filter(|model| keys::allows_model(model));
+        let Some(fast_model) = fast_model else {
+            return Self::forward_prompt(state, token, url, request, session_id, stream).await;
+        };
+
+        let mut fast_request = serde_json::to_value(request).map_err(|e| {
+            AppError::InternalServerError(format!("Failed to serialize request: {}", e))
+        })?;
+        fast_request["model"] = Value::String(fast_model.to_string());
+
+        let strong = arrive(
+            Self::forward_prompt(
+                state.clone(),
+                token.clone(),
+                url.clone(),
+                request,
+                session_id,
+                stream,
+            ),
+            stream,
+        );
+        let fast = arrive(
+            Self::forward_prompt(state, token, url, &fast_request, session_id, stream),
+            stream,
+        );
+
+        // A streamed answer cannot be swapped once it started, so only whole bodies upgrade
+        let upgrade_grace = (!stream).then(|| racing.upgrade_grace());
+        let (winner, arrival) = race(
+            strong,
+            fast,
+            usable,
+            racing.policy,
+            upgrade_grace,
+            racing.strong_timeout(),
+        )
+        .await;
+
+        info!(
+            "Raced {} against {}: answering with the {} model",
+            request.model, fast_model, winner
+        );
+        match winner {
+            Racer::Fast => arrival.map(|response| with_served_model(response, fast_model)),
+            Racer::Strong => arrival,
+        }
+    }
+}
+
+fn usable(arrival: &Result<Response, AppError>) -> bool {
+    matches!(arrival, Ok(response) if response.status().is_success())
+}
+
+/// Wait until an answer has arrived: its first chunk when streaming, or its whole body.
+///
+/// The body read so far is put back in front of the rest, so callers see an untouched
+/// response. Error responses are returned as they are for `handle_errors`.
+async fn arrive(
+    response: impl Future<Output = Result<Response, AppError>>,
+    stream: bool,
+) -> Result<Response, AppError> {
+    let response = response.await?;
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let headers = response.headers().clone();
+
+    let body = if stream {
+        let mut chunks = response.bytes_stream();
+        let first = match chunks.next().await {
+            Some(chunk) => {
+                chunk.map_err(|e| AppError::upstream("Failed to read Copilot response", e))?
+            }
+            None => Default::default(),
+        };
+        reqwest::Body::wrap_stream(futures_util::stream::iter([Ok(first)]).chain(chunks))
+    } else {
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| AppError::upstream("Failed to read Copilot response", e))?;
+        reqwest::Body::from(bytes)
+    };
+
+    let mut rebuilt = http::Response::new(body);
+    *rebuilt.status_mut() = status;
+    *rebuilt.headers_mut() = headers;
+    Ok(Response::from(rebuilt))
+}
+
+/// Pick between two racing answers according to `policy`. Both requests are under way
+/// from the start, whatever the policy.
+///
+/// Whichever answer is not returned is dropped, cancelling its request. When both
+/// fail, the strong model's failure is the one reported.
+async fn race<T>(
+    strong: impl Future<Output = T>,
+    fast: impl Future<Output = T>,
+    usable: impl Fn(&T) -> bool,
+    policy: RacePolicy,
+    upgrade_grace: Option<Duration>,
+    strong_timeout: Duration,
+) -> (Racer, T) {
+    tokio::pin!(strong, fast);
+
+    match policy {
+        RacePolicy::Fastest => tokio::select! {
+            strong_answer = &mut strong => {
+                if usable(&strong_answer) {
+                    return (Racer::Strong, strong_answer);
+                }
+                let fast_answer = fast.await;
+                if usable(&fast_answer) {
+                    (Racer::Fast, fast_answer)
+                } else {
+                    (Racer::Strong, strong_answer)
+                }
+            }
+            fast_answer = &mut fast => {
+                if !usable(&fast_answer) {
+                    return (Racer::Strong, strong.await);
+                }
+                if let Some(grace) = upgrade_grace
+                    && let Ok(strong_answer) = tokio::time::timeout(grace, &mut strong).await
+                    && usable(&strong_answer)
+                {
+                    return (Racer::Strong, strong_answer);
+                }
+                (Racer::Fast, fast_answer)
+            }
+        },
+        RacePolicy::StrongUnlessTimeout => {
+            // The fast answer is held back until the strong one fails or times out
+            let deadline = tokio::time::sleep(strong_timeout);
+            tokio::pin!(deadline);
+            let mut fast_answer = None;
+            loop {
+                tokio::select! {
+                    strong_answer = &mut strong => {
+                        if usable(&strong_answer) {
+                            return (Racer::Strong, strong_answer);
+                        }
+                        let fast_answer = match fast_answer {
+                            Some(fast_answer) => fast_answer,
+                            None => fast.await,
+                        };
+                        return if usable(&fast_answer) {
+                            (Racer::Fast, fast_answer)
+                        } else {
+                            (Racer::Strong, strong_answer)
+                        };
+                    }
+                    answer = &mut fast, if fast_answer.is_none() => fast_answer = Some(answer),
+                    () = &mut deadline => {
+                        let fast_answer = match fast_answer {
+                            Some(fast_answer) => fast_answer,
+                            None => fast.await,
+                        };
+                        return if usable(&fast_answer) {
+                            (Racer::Fast, fast_answer)
+                        } else {
+                            (Racer::Strong, strong.await)
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, CopilotRacingConfig};
+    use crate::openai::completion::models::OpenAIChatRequest;
+    use crate::server::keys::ApiKey;
+    use crate::server::test_token;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn answer(after_ms: u64, ok: bool) -> Result<&'static str, &'static str> {
+        tokio::time::sleep(Duration::from_millis(after_ms)).await;
+        if ok { Ok("answer") } else { Err("failed") }
+    }
+
+    async fn run(
+        strong: (u64, bool),
+        fast: (u64, bool),
+        policy: RacePolicy,
+        upgrade_grace: Option<Duration>,
+    ) -> Racer {
+        race(
+            answer(strong.0, strong.1),
+            answer(fast.0, fast.1),
+            Result::is_ok,
+            policy,
+            upgrade_grace,
+            Duration::from_millis(100),
+        )
+        .await
+        .0
+    }
+
+    #[tokio::test]
+    async fn test_fastest_policy() {
+        let fastest = RacePolicy::Fastest;
+
+        assert_eq!(
+            run((10, true), (200, true), fastest, None).await,
+            Racer::Strong
+        );
+        assert_eq!(
+            run((200, true), (10, true), fastest, None).await,
+            Racer::Fast
+        );
+        // The fast model failing leaves the strong one
+        assert_eq!(
+            run((200, true), (10, false), fastest, None).await,
+            Racer::Strong
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fastest_policy_upgrades_within_grace() {
+        let fastest = RacePolicy::Fastest;
+        let grace = Some(Duration::from_millis(150));
+
+        assert_eq!(
+            run((60, true), (10, true), fastest, grace).await,
+            Racer::Strong
+        );
+        assert_eq!(
+            run((400, true), (10, true), fastest, grace).await,
+            Racer::Fast
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strong_unless_timeout_policy() {
+        let policy = RacePolicy::StrongUnlessTimeout;
+
+        assert_eq!(
+            run((50, true), (10, true), policy, None).await,
+            Racer::Strong
+        );
+        assert_eq!(
+            run((400, true), (10, true), policy, None).await,
+            Racer::Fast
+        );
+        assert_eq!(
+            run((10, false), (50, true), policy, None).await,
+            Racer::Fast
+        );
+        // Both failing reports the strong model's failure
+        assert_eq!(
+            run((10, false), (50, false), policy, None).await,
+            Racer::Strong
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strong_unless_timeout_starts_both_requests() {
+        // The fast answer, 90ms in, is ready when the strong model times out at 100ms
+        let started = std::time::Instant::now();
+        let winner = run(
+            (400, true),
+            (90, true),
+            RacePolicy::StrongUnlessTimeout,
+            None,
+        )
+        .await;
+
+        assert_eq!(winner, Racer::Fast);
+        assert!(
+            started.elapsed() < Duration::from_millis(170),
+            "took {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_arrive_keeps_streamed_body() {
+        let response = Response::from(
+            http::Response::builder()
+                .status(200)
+                .header("content-type", "text/event-stream")
+                .body("data: {}\n\ndata: [DONE]\n\n")
+                .unwrap(),
+        );
+
+        let response = arrive(async { Ok(response) }, true).await.unwrap();
+
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        assert_eq!(
+            response.text().await.unwrap(),
+            "data: {}\n\ndata: [DONE]\n\n"
+        );
+    }
+
+    fn racing_state() -> Arc<AppState> {
+        let mut config = Config::default();
+        config.copilot.racing = CopilotRacingConfig {
+            enabled: true,
+            fast_model: Some("gpt-4o-mini".to_string()),
+            strong_model: Some("gpt-4o".to_string()),
+            ..CopilotRacingConfig::default()
+        };
+        Arc::new(AppState::new(&config, None))
+    }
+
+    fn request(model: &str) -> CopilotChatRequest {
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "Hi"}],
+        }))
+        .unwrap();
+        request.into()
+    }
+
+    async fn mount(server: &MockServer, model: &str, calls: u64, delay_ms: u64) {
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(json!({"model": model})))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"choices": []}))
+                    .set_delay(Duration::from_millis(delay_ms)),
+            )
+            .expect(calls)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_race_charges_both_models() {
+        let server = MockServer::start().await;
+        // The strong answer follows within the upgrade grace, so both calls complete
+        mount(&server, "gpt-4o", 1, 100).await;
+        mount(&server, "gpt-4o-mini", 1, 0).await;
+        let state = racing_state();
+        let url = format!("{}/chat/completions", server.uri());
+
+        Server::race_models(
+            state.clone(),
+            test_token(),
+            url,
+            &request("gpt-4o"),
+            "session",
+            false,
+        )
+        .await
+        .unwrap();
+
+        let report = state.premium.report(None);
+        let mut models: Vec<_> = report.models.keys().collect();
+        models.sort();
+        assert_eq!(models, ["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[tokio::test]
+    async fn test_race_only_fast_models_the_key_allows() {
+        let server = MockServer::start().await;
+        mount(&server, "gpt-4o", 1, 0).await;
+        mount(&server, "gpt-4o-mini", 0, 0).await;
+        let state = racing_state();
+        let url = format!("{}/chat/completions", server.uri());
+        let key = ApiKey {
+            id: "0123abcd".to_string(),
+            label: "ci".to_string(),
+            monthly_quota: None,
+            models: vec!["gpt-4o".to_string()],
+            created: 0,
+            revoked: false,
+            requests_this_month: 0,
+        };
+
+        keys::with_key(
+            key,
+            Server::race_models(
+                state.clone(),
+                test_token(),
+                url,
+                &request("gpt-4o"),
+                "session",
+                false,
+            ),
+        )
+        .await
+        .unwrap();
+
+        let report = state.premium.report(None);
+        assert_eq!(report.models.keys().collect::<Vec<_>>(), ["gpt-4o"]);
+    }
+}
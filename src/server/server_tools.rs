@@ -0,0 +1,382 @@
+use crate::auth::CopilotTokenResponse;
+use crate::config::McpConfig;
+use crate::copilot::{CopilotChatRequest, CopilotChatResponse, CopilotMessage};
+use crate::openai::completion::models::{Tool, ToolCall, ToolChoice};
+use crate::server::capabilities::ModelAdaptation;
+use crate::server::copilot::CopilotIntegration;
+use crate::server::empty_choices::buffered;
+use crate::server::web_search::{self, WEB_SEARCH_TOOL};
+use crate::server::{AppError, AppState, Server};
+use axum::http::HeaderMap;
+use futures_util::future::join_all;
+use reqwest::Response;
+use std::sync::Arc;
+use tracing::log::{debug, info, warn};
+
+/// Header turning the server-side tool loop on or off for one request, over `[mcp] auto_tools`
+pub const AUTO_TOOLS_HEADER: &str = "X-Passenger-Auto-Tools";
+
+/// Whether the proxy runs the tool loop for a request: `X-Passenger-Auto-Tools: true|false`
+/// when sent, else `[mcp] auto_tools`
+pub(crate) fn auto_tools(config: &McpConfig, headers: &HeaderMap) -> bool {
+    headers
+        .get(AUTO_TOOLS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<bool>().ok())
+        .unwrap_or(config.auto_tools)
+}
+
+/// Tools of `[mcp.servers]` and `[web_search]`, run on the proxy instead of by the client
+pub(crate) trait ServerTools: CopilotIntegration + ModelAdaptation {
+    /// `request` with the server tools added to its own, or `None` when it should be
+    /// forwarded as it is: streamed, `tool_choice: "none"`, a model without tool calling, or
+    /// a client tool of the same name.
+    async fn with_server_tools(
+        state: Arc<AppState>,
+        request: &CopilotChatRequest,
+        stream: bool,
+    ) -> Option<CopilotChatRequest>;
+
+    /// Run the server tools `response` calls and send their results back to Copilot, until
+    /// a reply calls none or `max_tool_rounds` is reached. Replies calling client tools are
+    /// returned as they are, for the client to run.
+    async fn run_server_tools(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        response: Response,
+    ) -> Result<Response, AppError>;
+}
+
+impl ServerTools for Server {
+    async fn with_server_tools(
+        state: Arc<AppState>,
+        request: &CopilotChatRequest,
+        stream: bool,
+    ) -> Option<CopilotChatRequest> {
+        let web_search = state.config().web_search.enabled;
+        if (state.mcp_tools.is_empty() && !web_search) || stream {
+            return None;
+        }
+        if matches!(&request.tool_choice, Some(ToolChoice::String(choice)) if choice == "none") {
+            return None;
+        }
+        if let Some(capabilities) = Self::model_capabilities(state.clone(), &request.model).await
+            && !capabilities.tool_call
+        {
+            debug!(
+                "{} cannot call tools, not offering server tools",
+                request.model
+            );
+            return None;
+        }
+
+        let server_tools = server_tools(&state).await;
+        if server_tools.is_empty() {
+            return None;
+        }
+        let client_tools = request.tools.as_deref().unwrap_or_default();
+        if let Some(clash) = client_tools.iter().find(|tool| {
+            server_tools
+                .iter()
+                .any(|server_tool| server_tool.function.name == tool.function.name)
+        }) {
+            warn!(
+                "Client tool {} clashes with a server tool, not offering server tools",
+                clash.function.name
+            );
+            return None;
+        }
+
+        let mut tooled = request.clone();
+        tooled.tools = Some(client_tools.iter().cloned().chain(server_tools).collect());
+        Some(tooled)
+    }
+
+    async fn run_server_tools(
+        state: Arc<AppState>,
+        token: CopilotTokenResponse,
+        url: String,
+        request: &CopilotChatRequest,
+        session_id: &str,
+        mut response: Response,
+    ) -> Result<Response, AppError> {
+        let max_rounds = state.config().mcp.max_tool_rounds;
+        let mut request = request.clone();
+
+        for round in 1..=max_rounds {
+            let (buffered, body) = buffered(response).await?;
+            let Ok(reply) = serde_json::from_slice::<CopilotChatResponse>(&body) else {
+                return Ok(buffered);
+            };
+            let tool_calls: Vec<ToolCall> = reply
+                .choices
+                .iter()
+                .flat_map(|choice| choice.message.tool_calls.iter().flatten())
+                .cloned()
+                .collect();
+            if tool_calls.is_empty() {
+                return Ok(buffered);
+            }
+            for call in &tool_calls {
+                if !owns(&state, &call.function.name).await {
+                    debug!(
+                        "{} is a client tool, returning the reply",
+                        call.function.name
+                    );
+                    return Ok(buffered);
+                }
+            }
+
+            info!(
+                "Running {} server tool calls (round {}/{})",
+                tool_calls.len(),
+                round,
+                max_rounds
+            );
+            let results = join_all(
+                tool_calls
+                    .iter()
+                    .map(|call| run(&state, &call.function.name, &call.function.arguments)),
+            )
+            .await;
+
+            let content = reply
+                .choices
+                .iter()
+                .filter_map(|choice| choice.message.content.as_ref())
+                .map(|content| content.to_text())
+                .collect::<Vec<_>>()
+                .join("\n");
+            request.messages.push(CopilotMessage {
+                role: "assistant".to_string(),
+                content: (!content.is_empty()).then(|| content.into()),
+                padding: None,
+                tool_calls: Some(tool_calls.clone()),
+                tool_call_id: None,
+                name: None,
+                reasoning_text: None,
+            });
+            request
+                .messages
+                .extend(
+                    tool_calls
+                        .iter()
+                        .zip(results)
+                        .map(|(call, result)| CopilotMessage {
+                            role: "tool".to_string(),
+                            content: Some(result.into()),
+                            padding: None,
+                            tool_calls: None,
+                            tool_call_id: call.id.clone(),
+                            name: Some(call.function.name.clone()),
+                            reasoning_text: None,
+                        }),
+                );
+            if round == max_rounds {
+                // Out of rounds: ask for an answer from what the tools returned so far
+                request.tool_choice = Some(ToolChoice::String("none".to_string()));
+            }
+
+            response = Self::forward_prompt(
+                state.clone(),
+                token.clone(),
+                url.clone(),
+                &request,
+                session_id,
+                false,
+            )
+            .await?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Every tool the proxy runs itself
+async fn server_tools(state: &AppState) -> Vec<Tool> {
+    let web_search = state
+        .config()
+        .web_search
+        .enabled
+        .then(web_search::definition);
+    let mcp_tools = if state.mcp_tools.is_empty() {
+        &[]
+    } else {
+        state.mcp_tools.tools().await
+    };
+    web_search
+        .into_iter()
+        .chain(mcp_tools.iter().map(|tool| tool.definition.clone()))
+        .collect()
+}
+
+async fn owns(state: &AppState, name: &str) -> bool {
+    if name == WEB_SEARCH_TOOL {
+        return state.config().web_search.enabled;
+    }
+    !state.mcp_tools.is_empty() && state.mcp_tools.owns(name).await
+}
+
+async fn run(state: &AppState, name: &str, arguments: &str) -> String {
+    if name == WEB_SEARCH_TOOL {
+        web_search::search(&state.config().web_search, &state.client, arguments).await
+    } else {
+        state.mcp_tools.call(name, arguments).await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::openai::completion::models::OpenAIChatRequest;
+    use crate::server::mcp_client::tests::weather_server;
+    use crate::server::test_token;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn state() -> Arc<AppState> {
+        let mut config = Config::default();
+        config.mcp.servers = HashMap::from([("weather".to_string(), weather_server())]);
+        let state = Arc::new(AppState::new(&config, None));
+        // Without a catalogue the model is assumed able to call tools
+        state.model_catalogue.store(HashMap::new());
+        state
+    }
+
+    fn reply(message: serde_json::Value) -> serde_json::Value {
+        json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": message, "finish_reason": "stop"}]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_server_tool_calls_are_run_on_the_proxy() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_string_contains("72F and sunny"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(reply(
+                json!({"role": "assistant", "content": "It is 72F and sunny in SF"}),
+            )))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let state = state();
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Weather in SF?"}]
+        }))
+        .unwrap();
+        let request = Server::with_server_tools(state.clone(), &request.into(), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            request.tools.as_ref().unwrap()[0].function.name,
+            "weather__get_weather"
+        );
+
+        let first = reply(json!({
+            "role": "assistant",
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "weather__get_weather", "arguments": "{\"city\":\"SF\"}"}
+            }]
+        }));
+        let first = Response::from(axum::http::Response::new(first.to_string()));
+        let url = format!("{}/chat/completions", mock_server.uri());
+
+        let response =
+            Server::run_server_tools(state, test_token(), url, &request, "session", first)
+                .await
+                .unwrap();
+        let answer: CopilotChatResponse = response.json().await.unwrap();
+        assert_eq!(
+            answer.choices[0]
+                .message
+                .content
+                .as_ref()
+                .unwrap()
+                .to_text(),
+            "It is 72F and sunny in SF"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_server_tools_for_streams_or_tool_choice_none() {
+        let state = state();
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "tool_choice": "none"
+        }))
+        .unwrap();
+        let request: CopilotChatRequest = request.into();
+        assert!(
+            Server::with_server_tools(state.clone(), &request, true)
+                .await
+                .is_none()
+        );
+        assert!(
+            Server::with_server_tools(state, &request, false)
+                .await
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_auto_tools_header_overrides_config() {
+        let mut config = McpConfig::default();
+        let mut headers = HeaderMap::new();
+        assert!(auto_tools(&config, &headers));
+
+        config.auto_tools = false;
+        assert!(!auto_tools(&config, &headers));
+        headers.insert(AUTO_TOOLS_HEADER, "true".parse().unwrap());
+        assert!(auto_tools(&config, &headers));
+
+        config.auto_tools = true;
+        headers.insert(AUTO_TOOLS_HEADER, "false".parse().unwrap());
+        assert!(!auto_tools(&config, &headers));
+        headers.insert(AUTO_TOOLS_HEADER, "maybe".parse().unwrap());
+        assert!(auto_tools(&config, &headers));
+    }
+
+    #[tokio::test]
+    async fn test_web_search_is_offered_next_to_client_tools() {
+        let mut config = Config::default();
+        config.web_search.enabled = true;
+        let state = Arc::new(AppState::new(&config, None));
+        state.model_catalogue.store(HashMap::new());
+
+        let request: OpenAIChatRequest = serde_json::from_value(json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Who won yesterday?"}],
+            "tools": [{"type": "function", "function": {"name": "lookup", "parameters": {}}}]
+        }))
+        .unwrap();
+        let request = Server::with_server_tools(state.clone(), &request.into(), false)
+            .await
+            .unwrap();
+        let names: Vec<&str> = request
+            .tools
+            .iter()
+            .flatten()
+            .map(|tool| tool.function.name.as_str())
+            .collect();
+        assert_eq!(names, ["lookup", "web_search"]);
+        assert!(owns(&state, "web_search").await);
+        assert!(!owns(&state, "lookup").await);
+    }
+}
@@ -0,0 +1,186 @@
+use axum::http::{HeaderMap, HeaderValue};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Header clients may set to pin a conversation to an explicit session id.
+/// The resolved session id is also echoed back under this name on every response.
+pub const SESSION_ID_HEADER: &str = "X-Session-Id";
+
+/// Header carrying the session id on upstream Copilot requests.
+pub const COPILOT_INTERACTION_ID_HEADER: &str = "X-Interaction-Id";
+
+/// Sessions kept before idle ones are forgotten
+const MAX_SESSIONS: usize = 10_000;
+
+/// How long a session id outlives the last request of its `user`
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Per-conversation session ids, keyed by the client-provided `user` field.
+///
+/// Copilot behaves more consistently when every turn of a conversation carries the
+/// same interaction id, so the id generated for a given `user` is kept until they
+/// have been idle for `SESSION_IDLE_TIMEOUT`. At most `MAX_SESSIONS` are kept; past
+/// that, idle sessions are dropped first, then the least recently used.
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+#[derive(Debug)]
+struct Session {
+    id: String,
+    last_used: Instant,
+}
+
+impl SessionStore {
+    /// Resolve the session id for an incoming request.
+    ///
+    /// An explicit `X-Session-Id` header wins, then the id previously generated for
+    /// `user`. Requests with neither get a fresh, unpersisted id.
+    pub fn resolve(&self, headers: &HeaderMap, user: Option<&str>) -> String {
+        if let Some(session_id) = headers
+            .get(SESSION_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+        {
+            return session_id.to_string();
+        }
+
+        match user.map(str::trim).filter(|user| !user.is_empty()) {
+            Some(user) => self.user_session(user, Instant::now()),
+            None => Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// The session id of `user` as of `now`, renewed if it has been idle too long
+    fn user_session(&self, user: &str, now: Instant) -> String {
+        let mut sessions = self.sessions.lock().expect("session store lock poisoned");
+        if sessions.len() >= MAX_SESSIONS && !sessions.contains_key(user) {
+            sessions.retain(|_, session| !session.idle(now));
+            if sessions.len() >= MAX_SESSIONS
+                && let Some(oldest) = sessions
+                    .iter()
+                    .min_by_key(|(_, session)| session.last_used)
+                    .map(|(user, _)| user.clone())
+            {
+                sessions.remove(&oldest);
+            }
+        }
+
+        let session = sessions.entry(user.to_string()).or_insert_with(|| Session {
+            id: Uuid::new_v4().to_string(),
+            last_used: now,
+        });
+        if session.idle(now) {
+            session.id = Uuid::new_v4().to_string();
+        }
+        session.last_used = now;
+        session.id.clone()
+    }
+}
+
+impl Session {
+    fn idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_used) >= SESSION_IDLE_TIMEOUT
+    }
+}
+
+/// Expose the session id used upstream on the response, for debugging.
+pub(crate) fn with_session_header(
+    mut response: axum::response::Response,
+    session_id: &str,
+) -> axum::response::Response {
+    if let Ok(value) = HeaderValue::from_str(session_id) {
+        response.headers_mut().insert(SESSION_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_takes_precedence_over_user() {
+        let store = SessionStore::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(SESSION_ID_HEADER, HeaderValue::from_static("abc-123"));
+
+        assert_eq!(store.resolve(&headers, Some("alice")), "abc-123");
+    }
+
+    #[test]
+    fn test_user_session_is_persisted() {
+        let store = SessionStore::default();
+        let headers = HeaderMap::new();
+
+        let first = store.resolve(&headers, Some("alice"));
+        let second = store.resolve(&headers, Some("alice"));
+        let other = store.resolve(&headers, Some("bob"));
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_idle_user_session_is_renewed() {
+        let store = SessionStore::default();
+        let start = Instant::now();
+
+        let first = store.user_session("alice", start);
+        let active = store.user_session("alice", start + SESSION_IDLE_TIMEOUT / 2);
+        let renewed = store.user_session("alice", start + SESSION_IDLE_TIMEOUT * 2);
+
+        assert_eq!(first, active);
+        assert_ne!(active, renewed);
+    }
+
+    #[test]
+    fn test_store_is_bounded() {
+        let store = SessionStore::default();
+        let start = Instant::now();
+
+        let idle = store.user_session("idle", start);
+        for user in 1..MAX_SESSIONS {
+            let used = start + SESSION_IDLE_TIMEOUT + Duration::from_millis(user as u64);
+            store.user_session(&format!("user-{}", user), used);
+        }
+        let later = start + SESSION_IDLE_TIMEOUT + Duration::from_secs(60);
+        let first = store.user_session("user-1", later);
+        assert_eq!(store.sessions.lock().unwrap().len(), MAX_SESSIONS);
+
+        // A new user first pushes out the sessions that went idle
+        store.user_session("new", later);
+        assert_eq!(store.sessions.lock().unwrap().len(), MAX_SESSIONS);
+        assert!(!store.sessions.lock().unwrap().contains_key("idle"));
+
+        // Then the least recently used active one
+        store.user_session("newer", later);
+        assert_eq!(store.sessions.lock().unwrap().len(), MAX_SESSIONS);
+        assert!(!store.sessions.lock().unwrap().contains_key("user-2"));
+        assert_eq!(store.user_session("user-1", later), first);
+        assert_ne!(store.user_session("idle", later), idle);
+    }
+
+    #[test]
+    fn test_anonymous_requests_get_fresh_ids() {
+        let store = SessionStore::default();
+        let headers = HeaderMap::new();
+
+        assert_ne!(store.resolve(&headers, None), store.resolve(&headers, None));
+        assert_ne!(
+            store.resolve(&headers, Some("  ")),
+            store.resolve(&headers, Some("  "))
+        );
+    }
+
+    #[test]
+    fn test_session_header_is_exposed_on_response() {
+        let response = with_session_header(axum::response::Response::default(), "abc-123");
+
+        assert_eq!(response.headers()[SESSION_ID_HEADER], "abc-123");
+    }
+}
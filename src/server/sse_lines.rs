@@ -0,0 +1,238 @@
+use axum::body::Bytes;
+use futures_util::Stream;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Longest SSE line accepted from Copilot before the stream is failed.
+/// A single line holds one JSON chunk, normally well under a kilobyte.
+pub(crate) const MAX_SSE_LINE_BYTES: usize = 1024 * 1024;
+
+/// Splits the upstream Copilot byte stream into complete SSE lines.
+///
+/// The stream is pull-based: upstream is only polled once every line decoded from the
+/// previous chunk has been taken by the client, so a slow client backs up into the
+/// TCP window towards Copilot instead of into memory. Memory held here is bounded by
+/// one upstream chunk plus a partial line of at most `max_line_bytes`.
+///
+/// Lines and multi-byte UTF-8 characters split across chunk boundaries are reassembled.
+pub(crate) struct SseLines<S> {
+    inner: Pin<Box<S>>,
+    partial: Vec<u8>,
+    lines: VecDeque<String>,
+    max_line_bytes: usize,
+    upstream_done: bool,
+}
+
+impl<S> SseLines<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self::with_max_line_bytes(inner, MAX_SSE_LINE_BYTES)
+    }
+
+    pub(crate) fn with_max_line_bytes(inner: S, max_line_bytes: usize) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            partial: Vec::new(),
+            lines: VecDeque::new(),
+            max_line_bytes,
+            upstream_done: false,
+        }
+    }
+
+    fn push_line(&mut self, mut line: Vec<u8>) {
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        self.lines
+            .push_back(String::from_utf8_lossy(&line).into_owned());
+    }
+
+    fn decode(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut rest = bytes;
+
+        while let Some(newline) = rest.iter().position(|&b| b == b'\n') {
+            let mut line = std::mem::take(&mut self.partial);
+            line.extend_from_slice(&rest[..newline]);
+            self.push_line(line);
+            rest = &rest[newline + 1..];
+        }
+
+        if self.partial.len() + rest.len() > self.max_line_bytes {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "SSE line from Copilot exceeds {} bytes",
+                    self.max_line_bytes
+                ),
+            ));
+        }
+        self.partial.extend_from_slice(rest);
+
+        Ok(())
+    }
+}
+
+impl<S> Stream for SseLines<S>
+where
+    S: Stream<Item = Result<Bytes, Error>>,
+{
+    type Item = Result<String, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Poll::Ready(Some(Ok(line)));
+            }
+
+            if self.upstream_done {
+                if self.partial.is_empty() {
+                    return Poll::Ready(None);
+                }
+                // Final line without a trailing newline
+                let line = std::mem::take(&mut self.partial);
+                self.push_line(line);
+                continue;
+            }
+
+            match self.inner.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(bytes))) => {
+                    if let Err(e) = self.decode(&bytes) {
+                        self.upstream_done = true;
+                        self.partial.clear();
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => self.upstream_done = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// `bytes` as an upstream stream cut at `cuts`, offsets past the end and repeats ignored
+#[cfg(test)]
+pub(crate) fn chunked(bytes: &[u8], cuts: &[usize]) -> impl Stream<Item = Result<Bytes, Error>> {
+    let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (bytes.len() + 1)).collect();
+    cuts.sort_unstable();
+    cuts.dedup();
+    cuts.push(bytes.len());
+    let mut start = 0;
+    let chunks: Vec<_> = cuts
+        .into_iter()
+        .map(|end| {
+            let chunk = Bytes::copy_from_slice(&bytes[start..end]);
+            start = end;
+            Ok(chunk)
+        })
+        .collect();
+    futures_util::stream::iter(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{FutureExt, StreamExt};
+    use proptest::prelude::*;
+
+    fn chunks(chunks: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, Error>> {
+        futures_util::stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk)))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    async fn collect_lines(
+        stream: SseLines<impl Stream<Item = Result<Bytes, Error>>>,
+    ) -> Vec<String> {
+        stream.map(|line| line.unwrap()).collect().await
+    }
+
+    #[tokio::test]
+    async fn test_multiple_lines_in_one_chunk() {
+        let lines = collect_lines(SseLines::new(chunks(&[
+            b"data: {\"a\":1}\n\ndata: [DONE]\n",
+        ])))
+        .await;
+
+        assert_eq!(lines, vec!["data: {\"a\":1}", "", "data: [DONE]"]);
+    }
+
+    #[tokio::test]
+    async fn test_line_split_across_chunks() {
+        let lines = collect_lines(SseLines::new(chunks(&[
+            b"data: {\"content\":",
+            b"\"hello\"}\r\n",
+            b"data: [DO",
+            b"NE]",
+        ])))
+        .await;
+
+        assert_eq!(lines, vec!["data: {\"content\":\"hello\"}", "data: [DONE]"]);
+    }
+
+    #[tokio::test]
+    async fn test_multibyte_character_split_across_chunks() {
+        // "é" is 0xC3 0xA9
+        let lines = collect_lines(SseLines::new(chunks(&[b"data: caf\xC3", b"\xA9\n"]))).await;
+
+        assert_eq!(lines, vec!["data: café"]);
+    }
+
+    #[tokio::test]
+    async fn test_overlong_line_fails_stream() {
+        let mut stream =
+            SseLines::with_max_line_bytes(chunks(&[b"data: 0123456789", b"0123456789"]), 16);
+
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_is_forwarded() {
+        let upstream = futures_util::stream::iter(vec![
+            Ok(Bytes::from_static(b"data: 1\n")),
+            Err(Error::other("connection reset")),
+        ]);
+        let mut stream = SseLines::new(upstream);
+
+        assert_eq!(stream.next().await.unwrap().unwrap(), "data: 1");
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    fn lines_of(bytes: &[u8], cuts: &[usize]) -> Vec<String> {
+        SseLines::new(chunked(bytes, cuts))
+            .map(|line| line.unwrap())
+            .collect()
+            .now_or_never()
+            .unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_chunk_boundaries_do_not_change_lines(
+            bytes in proptest::collection::vec(any::<u8>(), 0..512),
+            cuts in proptest::collection::vec(any::<usize>(), 0..16),
+        ) {
+            prop_assert_eq!(lines_of(&bytes, &cuts), lines_of(&bytes, &[]));
+        }
+
+        #[test]
+        fn prop_lines_are_the_text_between_newlines(
+            text in "[a-zé\\n\\r ]{0,200}",
+            cuts in proptest::collection::vec(any::<usize>(), 0..16),
+        ) {
+            let mut expected: Vec<String> = text
+                .split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+                .collect();
+            if text.is_empty() || text.ends_with('\n') {
+                expected.pop();
+            }
+            prop_assert_eq!(lines_of(text.as_bytes(), &cuts), expected);
+        }
+    }
+}
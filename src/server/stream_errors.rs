@@ -0,0 +1,109 @@
+use crate::server::AppError;
+use futures_util::{Stream, StreamExt};
+use serde::de::IgnoredAny;
+use std::io::{Error, ErrorKind};
+use tracing::log::error;
+
+/// An error reading Copilot's stream, keeping whether it timed out
+pub(crate) fn read_error(e: reqwest::Error) -> Error {
+    error!("Error reading streaming response from Copilot: {}", e);
+    let kind = if e.is_timeout() {
+        ErrorKind::TimedOut
+    } else {
+        ErrorKind::Other
+    };
+    Error::new(kind, e.to_string())
+}
+
+/// The error for a `data:` payload `e` failed to parse, when it is not JSON at all.
+/// Payloads that are JSON of an unexpected shape are skipped instead, so new kinds of
+/// chunks do not end streams.
+pub(crate) fn malformed_chunk(e: &serde_json::Error, payload: &str) -> Option<Error> {
+    if serde_json::from_str::<IgnoredAny>(payload).is_ok() {
+        return None;
+    }
+    Some(Error::new(
+        ErrorKind::InvalidData,
+        format!("malformed chunk from Copilot: {}: {}", e, payload),
+    ))
+}
+
+/// The error Copilot reported in a `data:` payload of the form `{"error": {...}}`
+#[cfg(feature = "responses")]
+pub(crate) fn reported_error(payload: &str) -> Option<Error> {
+    let payload: serde_json::Value = serde_json::from_str(payload).ok()?;
+    let error = payload.get("error")?;
+    let message = error
+        .get("message")
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| error.to_string());
+    Some(Error::other(format!(
+        "Copilot reported an error: {}",
+        message
+    )))
+}
+
+/// The error a stream that failed with `e` is reported to the client as
+pub(crate) fn stream_error(e: &Error) -> AppError {
+    let message = format!("Copilot stream failed: {}", e);
+    match e.kind() {
+        ErrorKind::TimedOut => AppError::GatewayTimeout(message),
+        ErrorKind::InvalidData => AppError::StreamParseError(message),
+        _ => AppError::UpstreamError(message),
+    }
+}
+
+/// End `stream` at its first error with a last item, built by `on_error` in the
+/// endpoint's own format, rather than cutting the response off. Clients would otherwise
+/// take a truncated answer for a complete one.
+pub(crate) fn end_with_error<S, T, F>(stream: S, mut on_error: F) -> impl Stream<Item = S::Item>
+where
+    S: Stream<Item = Result<T, Error>>,
+    F: FnMut(AppError) -> T,
+{
+    stream.scan(false, move |failed, item| {
+        let item = match item {
+            _ if *failed => None,
+            Ok(item) => Some(Ok(item)),
+            Err(e) => {
+                error!("Ending stream to the client after an upstream error: {}", e);
+                *failed = true;
+                Some(Ok(on_error(stream_error(&e))))
+            }
+        };
+        futures_util::future::ready(item)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_end_with_error() {
+        let stream = futures_util::stream::iter(vec![
+            Ok("a".to_string()),
+            Err(Error::new(ErrorKind::InvalidData, "bad chunk")),
+            Ok("b".to_string()),
+        ]);
+        let items: Vec<String> = end_with_error(stream, |error| error.code().to_string())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(items, ["a", "stream_parse_error"]);
+
+        let reset = Error::new(ErrorKind::ConnectionReset, "reset");
+        assert_eq!(stream_error(&reset).code(), "upstream_error");
+    }
+
+    #[test]
+    fn test_malformed_chunk() {
+        let parse = |payload: &str| {
+            let e = serde_json::from_str::<Vec<u32>>(payload).unwrap_err();
+            malformed_chunk(&e, payload)
+        };
+        assert!(parse(r#"{"choices": ["#).is_some());
+        assert!(parse(r#"{"choices": []}"#).is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::server::fallback::SERVED_MODEL_HEADER;
+use crate::server::metrics::{Metrics, tokens_per_second};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::log::info;
+
+/// Performance of one streaming request, observed on the raw Copilot SSE lines.
+///
+/// The clock starts when the request is forwarded to Copilot, so time-to-first-token
+/// includes upstream queueing. Tokens are counted as content deltas, unless Copilot
+/// reports `usage.completion_tokens` on a chunk, which then takes precedence.
+#[derive(Debug)]
+pub(crate) struct StreamStats {
+    metrics: Arc<Metrics>,
+    endpoint: &'static str,
+    model: String,
+    started: Instant,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    /// The model that answered, when a fallback or raced model took the request's place
+    served_model: Option<String>,
+    first_token: Option<Duration>,
+    deltas: u64,
+    reported_tokens: Option<u64>,
+}
+
+/// Just enough of an OpenAI-format chunk to tell whether it carries generated tokens
+#[derive(Debug, Deserialize)]
+struct ChunkProbe {
+    #[serde(default)]
+    choices: Vec<ChoiceProbe>,
+    usage: Option<UsageProbe>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChoiceProbe {
+    delta: Option<DeltaProbe>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeltaProbe {
+    content: Option<String>,
+    tool_calls: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageProbe {
+    completion_tokens: Option<u64>,
+}
+
+impl StreamStats {
+    pub(crate) fn new(metrics: Arc<Metrics>, endpoint: &'static str, model: &str) -> Arc<Self> {
+        Arc::new(Self {
+            metrics,
+            endpoint,
+            model: model.to_string(),
+            started: Instant::now(),
+            progress: Mutex::new(Progress::default()),
+        })
+    }
+
+    pub(crate) fn endpoint(&self) -> &'static str {
+        self.endpoint
+    }
+
+    /// Record the stream under the model that answered `response`, when Copilot's answer
+    /// came from a fallback or raced model
+    pub(crate) fn served_by(&self, response: &reqwest::Response) {
+        let Some(model) = response
+            .headers()
+            .get(SERVED_MODEL_HEADER)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return;
+        };
+        let mut progress = self.progress.lock().expect("stream stats lock poisoned");
+        progress.served_model = Some(model.to_string());
+    }
+
+    /// Inspect one SSE line from Copilot
+    pub(crate) fn observe_line(&self, line: &str) {
+        let Some(payload) = line.strip_prefix("data:").map(str::trim_start) else {
+            return;
+        };
+        let Ok(chunk) = serde_json::from_str::<ChunkProbe>(payload) else {
+            return;
+        };
+
+        let mut progress = self.progress.lock().expect("stream stats lock poisoned");
+
+        if let Some(tokens) = chunk.usage.and_then(|u| u.completion_tokens) {
+            progress.reported_tokens = Some(tokens);
+        }
+
+        let has_tokens = chunk
+            .choices
+            .iter()
+            .filter_map(|c| c.delta.as_ref())
+            .any(|d| d.content.as_deref().is_some_and(|c| !c.is_empty()) || d.tool_calls.is_some());
+
+        if has_tokens {
+            progress.deltas += 1;
+            if progress.first_token.is_none() {
+                progress.first_token = Some(self.started.elapsed());
+            }
+        }
+    }
+
+    /// Take the completion tokens Copilot reported for a reply that was not streamed
+    pub(crate) fn observe_completion_tokens(&self, tokens: u64) {
+        let mut progress = self.progress.lock().expect("stream stats lock poisoned");
+        progress.reported_tokens = Some(tokens);
+    }
+
+    /// Record the outcome of the stream and log its timings
+    pub(crate) fn finish(&self, completed: bool) {
+        let duration = self.started.elapsed();
+        let progress = self.progress.lock().expect("stream stats lock poisoned");
+        let tokens = progress.reported_tokens.unwrap_or(progress.deltas);
+        let model = progress.served_model.as_deref().unwrap_or(&self.model);
+
+        if completed {
+            self.metrics.record_stream_completed(self.endpoint);
+            self.metrics
+                .record_stream_timings(model, progress.first_token, duration, tokens);
+        } else {
+            self.metrics.record_stream_cancelled(self.endpoint);
+        }
+
+        info!(
+            "{} stream {} for model {}: time to first token {}, duration {}ms, {} tokens, {}",
+            self.endpoint,
+            if completed { "completed" } else { "cancelled" },
+            model,
+            progress
+                .first_token
+                .map(|ttft| format!("{}ms", ttft.as_millis()))
+                .unwrap_or_else(|| "n/a".to_string()),
+            duration.as_millis(),
+            tokens,
+            tokens_per_second(progress.first_token, duration, tokens)
+                .map(|tps| format!("{:.1} tokens/s", tps))
+                .unwrap_or_else(|| "n/a tokens/s".to_string()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_content_deltas_and_first_token() {
+        let stats = StreamStats::new(Arc::new(Metrics::default()), "chat_completions", "gpt-4o");
+
+        stats.observe_line(r#"data: {"choices":[{"delta":{"role":"assistant","content":""}}]}"#);
+        stats.observe_line("");
+        assert!(stats.progress.lock().unwrap().first_token.is_none());
+
+        stats.observe_line(r#"data: {"choices":[{"delta":{"content":"Hel"}}]}"#);
+        stats.observe_line(r#"data: {"choices":[{"delta":{"content":"lo"}}]}"#);
+        stats.observe_line(
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{"}}]}}]}"#,
+        );
+        stats.observe_line("data: [DONE]");
+
+        let progress = stats.progress.lock().unwrap();
+        assert!(progress.first_token.is_some());
+        assert_eq!(progress.deltas, 3);
+        assert_eq!(progress.reported_tokens, None);
+    }
+
+    #[test]
+    fn test_reported_usage_takes_precedence() {
+        let metrics = Arc::new(Metrics::default());
+        let stats = StreamStats::new(metrics.clone(), "ollama_chat", "gpt-4o");
+
+        stats.observe_line(r#"data: {"choices":[{"delta":{"content":"Hi"}}]}"#);
+        stats.observe_line(r#"data: {"choices":[],"usage":{"completion_tokens":12}}"#);
+        stats.finish(true);
+
+        assert_eq!(stats.progress.lock().unwrap().reported_tokens, Some(12));
+        assert_eq!(metrics.streams_completed("ollama_chat"), 1);
+        assert!(
+            metrics
+                .render()
+                .contains("passenger_stream_duration_seconds_count{model=\"gpt-4o\"} 1")
+        );
+    }
+
+    #[test]
+    fn test_cancelled_stream_records_no_timings() {
+        let metrics = Arc::new(Metrics::default());
+        let stats = StreamStats::new(metrics.clone(), "responses", "gpt-4o");
+
+        stats.finish(false);
+
+        assert_eq!(metrics.streams_cancelled("responses"), 1);
+        assert!(!metrics.render().contains("model=\"gpt-4o\""));
+    }
+}
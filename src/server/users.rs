@@ -0,0 +1,170 @@
+use crate::server::AppError;
+use crate::server::AppState;
+use crate::server::client_ip;
+use crate::server::keys;
+use crate::server::oidc;
+use crate::server::profiles::RATE_LIMIT_WINDOW;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::log::warn;
+
+/// Most users tracked at once
+const MAX_TRACKED_USERS: usize = 10_000;
+
+/// Request counts per end user, under `[users] requests_per_minute`. At most
+/// `MAX_TRACKED_USERS` are tracked; past that, idle users are forgotten first, then the
+/// one whose window started longest ago.
+#[derive(Debug, Default)]
+pub struct UserRateLimits {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+/// Requests of one user in the fixed one-minute window started at `started`
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+impl UserRateLimits {
+    /// Count one request from `user` against `limit`, the limit currently configured for
+    /// them, or return the seconds until the next one is allowed
+    fn acquire(&self, user: &str, limit: u32) -> Result<(), u64> {
+        self.acquire_at(user, limit, Instant::now())
+    }
+
+    fn acquire_at(&self, user: &str, limit: u32, now: Instant) -> Result<(), u64> {
+        let mut windows = self.windows.lock().expect("user rate limits lock poisoned");
+        if windows.len() >= MAX_TRACKED_USERS && !windows.contains_key(user) {
+            windows.retain(|_, window| !window.idle(now));
+            if windows.len() >= MAX_TRACKED_USERS
+                && let Some(oldest) = windows
+                    .iter()
+                    .min_by_key(|(_, window)| window.started)
+                    .map(|(user, _)| user.clone())
+            {
+                windows.remove(&oldest);
+            }
+        }
+
+        let window = windows.entry(user.to_string()).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        if window.idle(now) {
+            window.started = now;
+            window.count = 0;
+        }
+        if window.count >= limit {
+            let retry_after = RATE_LIMIT_WINDOW.saturating_sub(now - window.started);
+            return Err(retry_after.as_secs().max(1));
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+impl Window {
+    /// Whether the window has passed, so forgetting it loses no count
+    fn idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= RATE_LIMIT_WINDOW
+    }
+}
+
+/// Who a request from `user`, the OpenAI `user` field, comes from: the user of its OIDC
+/// token when it was authenticated with one
+pub(crate) fn resolve_user(user: Option<&str>) -> Option<String> {
+    oidc::current()
+        .map(|identity| identity.user)
+        .filter(|user| !user.is_empty())
+        .or_else(|| user.map(str::to_string))
+}
+
+/// Whose data the request being served may see under `[oidc]` or `[keys]`: its OIDC user,
+/// else the API key it was admitted with. `None` without either.
+pub(crate) fn tenant() -> Option<String> {
+    oidc::current()
+        .map(|identity| format!("user:{}", identity.user))
+        .or_else(|| keys::current().map(|key| format!("key:{}", key.id)))
+}
+
+/// Refuse a request from `user`, the OpenAI `user` field, past `[users] requests_per_minute`.
+/// Requests without one count against their client's address under `[users] limit_by_ip`,
+/// and are not limited here otherwise. A user authenticated with an OIDC token is counted
+/// instead of `user`, against their group's limit when it has one.
+pub(crate) fn admit_user(state: &AppState, user: Option<&str>) -> Result<(), AppError> {
+    let config = state.config();
+    let identity = oidc::current();
+    let Some(limit) = identity
+        .as_ref()
+        .and_then(|identity| identity.requests_per_minute)
+        .or(config.users.requests_per_minute)
+    else {
+        return Ok(());
+    };
+    let (key, who) = match (resolve_user(user), client_ip::current()) {
+        (Some(user), _) => (user.clone(), format!("user {}", user)),
+        (None, Some(ip)) if config.users.limit_by_ip => {
+            (format!("ip:{}", ip), format!("client {}", ip))
+        }
+        _ => return Ok(()),
+    };
+
+    state
+        .user_limits
+        .acquire(&key, limit)
+        .map_err(|retry_after| {
+            warn!(
+                "Rate limit of {} requests per minute reached for {}",
+                limit, who
+            );
+            AppError::RateLimited(format!(
+                "Rate limit of {} requests per minute reached for {}. Retry in {}s.",
+                limit, who, retry_after
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_are_limited_separately() {
+        let limits = UserRateLimits::default();
+
+        assert!(limits.acquire("alice", 2).is_ok());
+        assert!(limits.acquire("alice", 2).is_ok());
+        assert!(limits.acquire("alice", 2).is_err());
+        assert!(limits.acquire("bob", 2).is_ok());
+    }
+
+    #[test]
+    fn test_changed_limit_applies_to_tracked_users() {
+        let limits = UserRateLimits::default();
+
+        assert!(limits.acquire("alice", 1).is_ok());
+        assert!(limits.acquire("alice", 1).is_err());
+        // Raised by a config reload or a new OIDC group
+        assert!(limits.acquire("alice", 3).is_ok());
+        assert!(limits.acquire("alice", 3).is_ok());
+        assert!(limits.acquire("alice", 3).is_err());
+    }
+
+    #[test]
+    fn test_tracked_users_are_bounded() {
+        let limits = UserRateLimits::default();
+        let start = Instant::now();
+
+        for user in 0..MAX_TRACKED_USERS {
+            assert!(limits.acquire_at(&user.to_string(), 1, start).is_ok());
+        }
+        let later = start + RATE_LIMIT_WINDOW / 2;
+        assert!(limits.acquire_at("new", 1, later).is_ok());
+
+        let windows = limits.windows.lock().unwrap();
+        assert_eq!(windows.len(), MAX_TRACKED_USERS);
+        assert!(windows.contains_key("new"));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::config::WebSearchConfig;
+use crate::openai::completion::models::{FunctionDefinition, Tool};
+use reqwest::{Client, Url};
+use serde_json::{Value, json};
+use std::time::Duration;
+use tracing::log::{info, warn};
+
+/// Name the built-in search tool is offered under
+pub const WEB_SEARCH_TOOL: &str = "web_search";
+
+/// How long the search API has to answer
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The `web_search` tool as offered to models
+pub fn definition() -> Tool {
+    Tool {
+        tool_type: "function".to_string(),
+        function: FunctionDefinition {
+            name: WEB_SEARCH_TOOL.to_string(),
+            description: Some(
+                "Search the web. Returns the top results' titles, URLs and snippets.".to_string(),
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "query": {"type": "string", "description": "The search terms"}
+                },
+                "required": ["query"]
+            }),
+        },
+    }
+}
+
+/// Run a `web_search` call; failures are returned as text for the model
+pub async fn search(config: &WebSearchConfig, client: &Client, arguments: &str) -> String {
+    let query = match serde_json::from_str::<Value>(arguments) {
+        Ok(arguments) => match arguments["query"].as_str() {
+            Some(query) => query.to_string(),
+            None => return "Error: web_search needs a query".to_string(),
+        },
+        Err(e) => return format!("Error: invalid arguments: {}", e),
+    };
+    info!("Searching the web for {:?}", query);
+
+    let mut url = match Url::parse(&config.url) {
+        Ok(url) => url,
+        Err(e) => return format!("Error: invalid [web_search] url: {}", e),
+    };
+    url.query_pairs_mut()
+        .append_pair(&config.query_param, &query)
+        .extend_pairs(&config.params);
+
+    let mut request = client.get(url).timeout(SEARCH_TIMEOUT);
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+
+    let body = match request.send().await.and_then(|r| r.error_for_status()) {
+        Ok(response) => response.json::<Value>().await,
+        Err(e) => Err(e),
+    };
+    match body {
+        Ok(body) => format_results(&search_results(&body, config.max_results)),
+        Err(e) => {
+            warn!("Web search failed: {}", e);
+            format!("Error: web search failed: {}", e)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct SearchResult {
+    title: String,
+    url: String,
+    snippet: String,
+}
+
+/// The results in a search API's reply: every object with a `title` and a `url` (or
+/// `link`), in order, which covers the shapes of SearXNG, Brave, Serper and others
+fn search_results(body: &Value, max_results: usize) -> Vec<SearchResult> {
+    let mut results = Vec::new();
+    collect_results(body, &mut results);
+    results.truncate(max_results);
+    results
+}
+
+fn collect_results(value: &Value, results: &mut Vec<SearchResult>) {
+    match value {
+        Value::Object(object) => {
+            let title = object.get("title").and_then(Value::as_str);
+            let url = object
+                .get("url")
+                .or_else(|| object.get("link"))
+                .and_then(Value::as_str);
+            if let (Some(title), Some(url)) = (title, url) {
+                let snippet = ["description", "snippet", "content"]
+                    .iter()
+                    .find_map(|key| object.get(*key).and_then(Value::as_str))
+                    .unwrap_or_default();
+                results.push(SearchResult {
+                    title: title.to_string(),
+                    url: url.to_string(),
+                    snippet: snippet.to_string(),
+                });
+                return;
+            }
+            object
+                .values()
+                .for_each(|value| collect_results(value, results));
+        }
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_results(value, results)),
+        _ => {}
+    }
+}
+
+fn format_results(results: &[SearchResult]) -> String {
+    if results.is_empty() {
+        return "No results found.".to_string();
+    }
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            format!(
+                "{}. {}\n{}\n{}",
+                i + 1,
+                result.title,
+                result.url,
+                result.snippet
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_search_results() {
+        let searxng = json!({"results": [
+            {"title": "Rust", "url": "https://www.rust-lang.org", "content": "A language"},
+            {"title": "Crates", "url": "https://crates.io", "content": "Packages"}
+        ]});
+        assert_eq!(
+            search_results(&searxng, 1),
+            [SearchResult {
+                title: "Rust".to_string(),
+                url: "https://www.rust-lang.org".to_string(),
+                snippet: "A language".to_string(),
+            }]
+        );
+
+        let serper = json!({"organic": [{"title": "Rust", "link": "https://www.rust-lang.org"}]});
+        assert_eq!(
+            search_results(&serper, 5)[0].url,
+            "https://www.rust-lang.org"
+        );
+        assert!(search_results(&json!({"answer": 42}), 5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("q", "rust async"))
+            .and(query_param("format", "json"))
+            .and(header("X-Api-Key", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"web": {"results": [
+                {"title": "Async Rust", "url": "https://rust-lang.github.io/async-book", "description": "The book"}
+            ]}})))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config = WebSearchConfig {
+            enabled: true,
+            url: mock_server.uri(),
+            params: [("format".to_string(), "json".to_string())].into(),
+            headers: [("X-Api-Key".to_string(), "secret".to_string())].into(),
+            ..WebSearchConfig::default()
+        };
+        let results = search(&config, &Client::new(), r#"{"query": "rust async"}"#).await;
+        assert_eq!(
+            results,
+            "1. Async Rust\nhttps://rust-lang.github.io/async-book\nThe book"
+        );
+        assert!(
+            search(&config, &Client::new(), "{}")
+                .await
+                .starts_with("Error")
+        );
+    }
+}
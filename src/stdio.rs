@@ -0,0 +1,340 @@
+use crate::config::Config;
+use crate::server::Server;
+use crate::server::sse_lines::SseLines;
+use anyhow::{Context, Result};
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, header};
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::Error;
+use std::sync::Arc;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tower::ServiceExt;
+use tracing::log::{debug, warn};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The proxy answered with an HTTP error; its status and body are in `data`
+const SERVER_ERROR: i64 = -32000;
+
+/// A JSON-RPC request, or a notification when `id` is absent
+#[derive(Debug, Deserialize)]
+struct Call {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut error = json!({"code": self.code, "message": self.message});
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        error
+    }
+}
+
+/// Read one `Content-Length` framed message, or `None` at end of input
+async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            content_length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .with_context(|| format!("Invalid Content-Length: {}", value.trim()))?,
+            );
+        }
+    }
+
+    let mut body = vec![0; content_length.unwrap_or_default()];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+/// Write `message` with a `Content-Length` header
+async fn write_message<W: AsyncWrite + Unpin>(writer: &Mutex<W>, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    let mut writer = writer.lock().await;
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+        .await?;
+    writer.write_all(&body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Serve JSON-RPC on stdin/stdout until `exit` or end of input
+pub async fn serve(config: &Config) -> Result<()> {
+    let router = Server::new(config).router;
+    let writer = Arc::new(Mutex::new(tokio::io::stdout()));
+    let mut reader = BufReader::new(tokio::io::stdin());
+    let mut calls = JoinSet::new();
+
+    while let Some(message) = read_message(&mut reader).await? {
+        let call = match serde_json::from_slice::<Value>(&message) {
+            Ok(message) => match serde_json::from_value::<Call>(message) {
+                Ok(call) => call,
+                Err(e) => {
+                    let error = RpcError::new(INVALID_REQUEST, e.to_string());
+                    write_message(&writer, &response(Value::Null, Err(error))).await?;
+                    continue;
+                }
+            },
+            Err(e) => {
+                let error = RpcError::new(PARSE_ERROR, e.to_string());
+                write_message(&writer, &response(Value::Null, Err(error))).await?;
+                continue;
+            }
+        };
+
+        debug!("JSON-RPC call: {}", call.method);
+        if call.method == "exit" {
+            break;
+        }
+        calls.spawn(handle(router.clone(), writer.clone(), call));
+    }
+
+    while let Some(result) = calls.join_next().await {
+        if let Err(e) = result
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result)
+        {
+            warn!("JSON-RPC call failed: {:#}", e);
+        }
+    }
+    Ok(())
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error.to_json()}),
+    }
+}
+
+async fn handle<W: AsyncWrite + Unpin>(
+    router: Router,
+    writer: Arc<Mutex<W>>,
+    call: Call,
+) -> Result<()> {
+    let result = match call.method.as_str() {
+        "initialize" => Ok(json!({
+            "serverInfo": {"name": "passenger-rs", "version": env!("CARGO_PKG_VERSION")},
+            "methods": ["chat/completions", "models/list", "shutdown", "exit"],
+        })),
+        "shutdown" => Ok(Value::Null),
+        "models/list" => dispatch(router, Method::GET, "/v1/models", Body::empty()).await,
+        "chat/completions" => chat_completions(router, &writer, call.id.clone(), call.params).await,
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    };
+
+    match call.id {
+        Some(id) => write_message(&writer, &response(id, result)).await,
+        None => Ok(()),
+    }
+}
+
+/// A chat completion; streamed chunks are sent as `chat/completions/chunk` notifications
+/// and the result is `null` once the stream ends
+async fn chat_completions<W: AsyncWrite + Unpin>(
+    router: Router,
+    writer: &Mutex<W>,
+    id: Option<Value>,
+    params: Value,
+) -> Result<Value, RpcError> {
+    let stream = params["stream"].as_bool().unwrap_or(false);
+    let body = Body::from(serde_json::to_vec(&params).unwrap_or_default());
+    if !stream {
+        return dispatch(router, Method::POST, "/v1/chat/completions", body).await;
+    }
+
+    let response = send(router, Method::POST, "/v1/chat/completions", body).await?;
+    let mut lines = SseLines::new(
+        response
+            .into_body()
+            .into_data_stream()
+            .map_err(|e| Error::other(e.to_string())),
+    );
+    while let Some(line) = lines
+        .try_next()
+        .await
+        .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?
+    {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            break;
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            continue;
+        };
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "chat/completions/chunk",
+            "params": {"id": id, "chunk": chunk},
+        });
+        write_message(writer, &notification)
+            .await
+            .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+    }
+    Ok(Value::Null)
+}
+
+/// Send a request through the proxy's router, failing on HTTP errors
+async fn send(
+    router: Router,
+    method: Method,
+    uri: &str,
+    body: Body,
+) -> Result<axum::response::Response, RpcError> {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))?;
+    let response = router
+        .oneshot(request)
+        .await
+        .map_err(|e| RpcError::new(SERVER_ERROR, format!("{:?}", e)))?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let body = serde_json::from_slice::<Value>(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+    let message = body["error"]["message"]
+        .as_str()
+        .or(body.as_str())
+        .unwrap_or("Request failed")
+        .to_string();
+    Err(RpcError {
+        code: SERVER_ERROR,
+        message,
+        data: Some(json!({"status": status.as_u16(), "body": body})),
+    })
+}
+
+/// [`send`], returning the JSON body
+async fn dispatch(
+    router: Router,
+    method: Method,
+    uri: &str,
+    body: Body,
+) -> Result<Value, RpcError> {
+    let response = send(router, method, uri, body).await?;
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+    serde_json::from_slice(&body).map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn call(method: &str, params: Value) -> Value {
+        let config = Config::default();
+        let router = Server::new(&config).router;
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let call = Call {
+            id: Some(json!(1)),
+            method: method.to_string(),
+            params,
+        };
+        handle(router, writer.clone(), call).await.unwrap();
+
+        let output = writer.lock().await.clone();
+        let mut reader = BufReader::new(output.as_slice());
+        let message = read_message(&mut reader).await.unwrap().unwrap();
+        serde_json::from_slice(&message).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_message_framing() {
+        let writer = Mutex::new(Vec::new());
+        write_message(&writer, &json!({"jsonrpc": "2.0", "method": "exit"}))
+            .await
+            .unwrap();
+        write_message(
+            &writer,
+            &json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"}),
+        )
+        .await
+        .unwrap();
+        let output = writer.into_inner();
+        assert!(output.starts_with(b"Content-Length: 33\r\n\r\n{"));
+
+        let mut reader = BufReader::new(output.as_slice());
+        let first = read_message(&mut reader).await.unwrap().unwrap();
+        let second = read_message(&mut reader).await.unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&first).unwrap()["method"],
+            "exit"
+        );
+        assert_eq!(serde_json::from_slice::<Value>(&second).unwrap()["id"], 1);
+        assert!(read_message(&mut reader).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_method() {
+        let response = call("completions/legacy", Value::Null).await;
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_http_errors_become_rpc_errors() {
+        let response = call("chat/completions", json!({"messages": []})).await;
+        assert_eq!(response["error"]["code"], SERVER_ERROR);
+        assert_eq!(response["error"]["data"]["status"], 422);
+    }
+}
@@ -1,24 +1,296 @@
 use crate::auth::{AccessTokenResponse, CopilotTokenResponse};
 use anyhow::{Context, Result};
+use directories::{BaseDirs, ProjectDirs};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-/// Get the token storage directory path (~/.config/passenger-rs/)
+const APP_NAME: &str = "passenger-rs";
+
+/// Get the token storage directory path.
+///
+/// This is the platform config directory: `~/.config/passenger-rs` on Linux (honouring
+/// `XDG_CONFIG_HOME`), `~/Library/Application Support/passenger-rs` on macOS and
+/// `%APPDATA%\passenger-rs\config` on Windows. An existing `~/.config/passenger-rs`
+/// directory from earlier releases takes precedence so upgrades keep their tokens.
 pub fn get_storage_dir() -> Result<PathBuf> {
-    let home = std::env::var("HOME")
-        .or_else(|_| std::env::var("USERPROFILE"))
-        .context("Could not determine home directory")?;
+    let project_dirs =
+        ProjectDirs::from("", "", APP_NAME).context("Could not determine home directory")?;
+    let legacy_dir = BaseDirs::new().map(|dirs| dirs.home_dir().join(".config").join(APP_NAME));
+
+    Ok(resolve_storage_dir(
+        project_dirs.config_dir(),
+        legacy_dir.as_deref(),
+    ))
+}
+
+fn resolve_storage_dir(config_dir: &Path, legacy_dir: Option<&Path>) -> PathBuf {
+    match legacy_dir {
+        Some(legacy_dir) if legacy_dir.is_dir() => legacy_dir.to_path_buf(),
+        _ => config_dir.to_path_buf(),
+    }
+}
+
+/// Create a storage directory, readable by the current user only
+pub(crate) fn create_private_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).context("Failed to create storage directory")?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+            .context("Failed to restrict storage directory permissions")?;
+    }
+
+    Ok(())
+}
+
+/// Write a token or uploaded file, readable and writable by the current user only
+/// (0600 on Unix, an owner-only ACL on Windows)
+#[cfg(not(windows))]
+pub(crate) fn write_private_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
 
-    let config_dir = PathBuf::from(home).join(".config").join("passenger-rs");
-    Ok(config_dir)
+    // `mode` only applies to newly created files; tighten pre-existing ones too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(contents.as_ref())?;
+    Ok(())
+}
+
+/// Write a token or uploaded file, readable and writable by the current user only
+/// (0600 on Unix, an owner-only ACL on Windows)
+///
+/// The contents go to a new file created with the owner-only ACL, which then replaces
+/// `path`, so they are never readable under an inherited ACL, and a pre-existing file's
+/// ACL goes with it.
+#[cfg(windows)]
+pub(crate) fn write_private_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let descriptor = windows_acl::SecurityDescriptor::owner_only()?;
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{}.tmp", uuid::Uuid::new_v4().simple()));
+    let temp_path = PathBuf::from(temp_path);
+
+    let written = descriptor
+        .create_new(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(written?)
+}
+
+/// Owner-only security descriptors for files on Windows, for the user of the process
+/// token rather than whoever `%USERNAME%` names
+#[cfg(windows)]
+mod windows_acl {
+    use anyhow::{Context, Result};
+    use std::fs::File;
+    use std::io::Error;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::FromRawHandle;
+    use std::path::Path;
+    use std::ptr;
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE, LocalFree,
+    };
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW,
+        SDDL_REVISION_1,
+    };
+    use windows_sys::Win32::Security::{
+        GetTokenInformation, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, TOKEN_QUERY, TOKEN_USER,
+        TokenUser,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{CREATE_NEW, CreateFileW, FILE_ATTRIBUTE_NORMAL};
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    /// A security descriptor, freed on drop
+    pub(super) struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+    impl SecurityDescriptor {
+        /// A protected DACL, inheriting nothing, with full control for the current user only
+        pub(super) fn owner_only() -> Result<Self> {
+            let sddl = format!("D:P(A;;FA;;;{})", current_user_sid()?);
+            let sddl: Vec<u16> = sddl.encode_utf16().chain([0]).collect();
+            let mut descriptor = ptr::null_mut();
+            // SAFETY: `sddl` is NUL-terminated and `descriptor` receives a LocalAlloc'd
+            // descriptor, freed on drop
+            let converted = unsafe {
+                ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                    sddl.as_ptr(),
+                    SDDL_REVISION_1,
+                    &mut descriptor,
+                    ptr::null_mut(),
+                )
+            };
+            if converted == 0 {
+                return Err(Error::last_os_error())
+                    .context("Failed to build an owner-only security descriptor");
+            }
+            Ok(Self(descriptor))
+        }
+
+        /// Create a new file at `path` with this descriptor, failing if one exists
+        pub(super) fn create_new(&self, path: &Path) -> std::io::Result<File> {
+            let attributes = SECURITY_ATTRIBUTES {
+                nLength: size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: self.0,
+                bInheritHandle: 0,
+            };
+            let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+            // SAFETY: `path` is NUL-terminated and `attributes` outlives the call
+            let handle = unsafe {
+                CreateFileW(
+                    path.as_ptr(),
+                    GENERIC_WRITE,
+                    0,
+                    &attributes,
+                    CREATE_NEW,
+                    FILE_ATTRIBUTE_NORMAL,
+                    ptr::null_mut(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(Error::last_os_error());
+            }
+            // SAFETY: a handle just opened, owned from here on by the `File`
+            Ok(unsafe { File::from_raw_handle(handle) })
+        }
+    }
+
+    impl Drop for SecurityDescriptor {
+        fn drop(&mut self) {
+            // SAFETY: allocated by ConvertStringSecurityDescriptorToSecurityDescriptorW
+            unsafe { LocalFree(self.0) };
+        }
+    }
+
+    /// The SID of the user this process runs as, from its access token, e.g. `S-1-5-21-…`
+    pub(super) fn current_user_sid() -> Result<String> {
+        let mut token: HANDLE = ptr::null_mut();
+        // SAFETY: the pseudo-handle of the current process needs no closing
+        if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+            return Err(Error::last_os_error()).context("Failed to open the process token");
+        }
+
+        let mut len = 0;
+        // SAFETY: a first call with no buffer only reports the length needed
+        unsafe { GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len) };
+        // u64s to align the TOKEN_USER at the start of the buffer
+        let mut buffer = vec![0u64; (len as usize).div_ceil(size_of::<u64>())];
+        // SAFETY: `buffer` holds at least `len` bytes
+        let read = unsafe {
+            GetTokenInformation(token, TokenUser, buffer.as_mut_ptr().cast(), len, &mut len)
+        };
+        let error = Error::last_os_error();
+        // SAFETY: opened above and not used past here
+        unsafe { CloseHandle(token) };
+        if read == 0 {
+            return Err(error).context("Failed to read the user of the process token");
+        }
+
+        // SAFETY: GetTokenInformation wrote a TOKEN_USER, whose SID points into `buffer`
+        let sid = unsafe { (*buffer.as_ptr().cast::<TOKEN_USER>()).User.Sid };
+        let mut string = ptr::null_mut();
+        // SAFETY: `sid` is valid while `buffer` lives; `string` is LocalAlloc'd
+        if unsafe { ConvertSidToStringSidW(sid, &mut string) } == 0 {
+            return Err(Error::last_os_error()).context("Failed to format the user's SID");
+        }
+        // SAFETY: `string` is a NUL-terminated wide string, freed once copied
+        let sid = unsafe {
+            let len = (0..).take_while(|&i| *string.add(i) != 0).count();
+            let sid = String::from_utf16_lossy(std::slice::from_raw_parts(string, len));
+            LocalFree(string.cast());
+            sid
+        };
+        Ok(sid)
+    }
+
+    /// The DACL of the file at `path`, as SDDL
+    #[cfg(test)]
+    pub(super) fn file_dacl(path: &Path) -> Result<String> {
+        use windows_sys::Win32::Security::Authorization::ConvertSecurityDescriptorToStringSecurityDescriptorW;
+        use windows_sys::Win32::Security::{DACL_SECURITY_INFORMATION, GetFileSecurityW};
+
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+        let mut len = 0;
+        // SAFETY: a first call with no buffer only reports the length needed
+        unsafe {
+            GetFileSecurityW(
+                path.as_ptr(),
+                DACL_SECURITY_INFORMATION,
+                ptr::null_mut(),
+                0,
+                &mut len,
+            )
+        };
+        let mut buffer = vec![0u64; (len as usize).div_ceil(size_of::<u64>())];
+        // SAFETY: `buffer` holds at least `len` bytes
+        let read = unsafe {
+            GetFileSecurityW(
+                path.as_ptr(),
+                DACL_SECURITY_INFORMATION,
+                buffer.as_mut_ptr().cast(),
+                len,
+                &mut len,
+            )
+        };
+        if read == 0 {
+            return Err(Error::last_os_error()).context("Failed to read the file's DACL");
+        }
+
+        let mut string = ptr::null_mut();
+        let mut len = 0;
+        // SAFETY: `buffer` holds the descriptor; `string` is LocalAlloc'd
+        let converted = unsafe {
+            ConvertSecurityDescriptorToStringSecurityDescriptorW(
+                buffer.as_mut_ptr().cast(),
+                SDDL_REVISION_1,
+                DACL_SECURITY_INFORMATION,
+                &mut string,
+                &mut len,
+            )
+        };
+        if converted == 0 {
+            return Err(Error::last_os_error()).context("Failed to format the file's DACL");
+        }
+        // SAFETY: `string` holds `len` wide characters, including the NUL, freed once copied
+        let sddl = unsafe {
+            let wide = std::slice::from_raw_parts(string, len as usize);
+            let sddl = String::from_utf16_lossy(wide)
+                .trim_end_matches('\0')
+                .to_string();
+            LocalFree(string.cast());
+            sddl
+        };
+        Ok(sddl)
+    }
 }
 
 pub fn get_access_token_path() -> Result<PathBuf> {
     Ok(get_storage_dir()?.join("access_token.json"))
 }
 
-/// Get the token file path (~/.config/passenger-rs/token.json)
+/// Get the token file path (`token.json` in the storage directory)
 pub fn get_token_path() -> Result<PathBuf> {
     Ok(get_storage_dir()?.join("token.json"))
 }
@@ -39,15 +311,14 @@ pub fn save_token_to_path(token: &CopilotTokenResponse, custom_path: Option<&Pat
             path.to_path_buf()
         }
         None => {
-            let storage_dir = get_storage_dir()?;
             // Create the directory if it doesn't exist
-            fs::create_dir_all(&storage_dir).context("Failed to create storage directory")?;
+            create_private_dir(&get_storage_dir()?)?;
             get_token_path()?
         }
     };
 
     let token_json = serde_json::to_string_pretty(token).context("Failed to serialize token")?;
-    fs::write(&token_path, token_json).context("Failed to write token to disk")?;
+    write_private_file(&token_path, &token_json).context("Failed to write token to disk")?;
 
     Ok(())
 }
@@ -75,16 +346,15 @@ pub fn save_access_token_to_path(
             path.to_path_buf()
         }
         None => {
-            let storage_dir = get_storage_dir()?;
             // Create the directory if it doesn't exist
-            fs::create_dir_all(&storage_dir).context("Failed to create storage directory")?;
+            create_private_dir(&get_storage_dir()?)?;
             get_access_token_path()?
         }
     };
 
     let token_json =
         serde_json::to_string_pretty(token).context("Failed to serialize access token")?;
-    fs::write(&token_path, token_json).context("Failed to write access token to disk")?;
+    write_private_file(&token_path, &token_json).context("Failed to write access token to disk")?;
 
     Ok(())
 }
@@ -160,20 +430,69 @@ pub fn token_exists() -> bool {
 }
 
 /// Check if a token exists at custom path
-#[allow(unused)]
 pub fn token_exists_at_path(path: &Path) -> bool {
     path.exists()
 }
 
-/// Check if a token is expired (returns true if expired or within 60 seconds of expiring)
-pub fn is_token_expired(token: &CopilotTokenResponse) -> bool {
-    let now = SystemTime::now()
+/// A token file or directory that users other than its owner can access
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsecurePath {
+    pub path: PathBuf,
+    /// Unix permission bits (e.g. `0o644`)
+    pub mode: u32,
+}
+
+/// Report which of `paths` are group- or world-accessible. Missing paths are skipped.
+///
+/// Only Unix permission bits are inspected; on other platforms nothing is reported.
+pub fn find_insecure_paths(paths: &[PathBuf]) -> Vec<InsecurePath> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        paths
+            .iter()
+            .filter_map(|path| {
+                let mode = fs::metadata(path).ok()?.permissions().mode() & 0o777;
+                (mode & 0o077 != 0).then(|| InsecurePath {
+                    path: path.clone(),
+                    mode,
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = paths;
+        Vec::new()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_secs();
+        .as_secs()
+}
+
+/// Check if a token is expired (returns true if expired or within `buffer_secs` of expiring)
+pub fn is_token_expired(token: &CopilotTokenResponse, buffer_secs: u64) -> bool {
+    token.expires_at <= now() + buffer_secs
+}
+
+/// Check if a token should be replaced: it is expired as for [`is_token_expired`], or the
+/// `refresh_in` Copilot gave with it has passed since it was fetched
+pub fn is_token_due_for_refresh(token: &CopilotTokenResponse, buffer_secs: u64) -> bool {
+    is_token_expired(token, buffer_secs)
+        || token.fetched_at.is_some_and(|fetched_at| {
+            token.refresh_in > 0 && fetched_at + token.refresh_in <= now()
+        })
+}
 
-    // Consider expired if we're within 60 seconds of expiration (buffer)
-    token.expires_at <= now + 60
+/// Stamp a freshly fetched token with the current time, for [`is_token_due_for_refresh`]
+pub fn mark_token_fetched(token: &mut CopilotTokenResponse) {
+    token.fetched_at = Some(now());
 }
 
 /// Delete the stored token
@@ -194,15 +513,137 @@ mod tests {
     use std::time::{SystemTime, UNIX_EPOCH};
 
     #[test]
-    fn test_get_storage_dir() {
-        let dir = get_storage_dir().unwrap();
-        assert!(dir.ends_with(".config/passenger-rs"));
+    fn test_resolve_storage_dir_uses_platform_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config_dir = tmp.path().join("config").join("passenger-rs");
+        let legacy_dir = tmp.path().join(".config").join("passenger-rs");
+
+        let dir = resolve_storage_dir(&config_dir, Some(&legacy_dir));
+        assert_eq!(dir, config_dir);
+
+        let dir = resolve_storage_dir(&config_dir, None);
+        assert_eq!(dir, config_dir);
+    }
+
+    #[test]
+    fn test_resolve_storage_dir_prefers_existing_legacy_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config_dir = tmp.path().join("config").join("passenger-rs");
+        let legacy_dir = tmp.path().join(".config").join("passenger-rs");
+        fs::create_dir_all(&legacy_dir).unwrap();
+
+        let dir = resolve_storage_dir(&config_dir, Some(&legacy_dir));
+        assert_eq!(dir, legacy_dir);
+    }
+
+    #[test]
+    fn test_save_and_load_token_with_custom_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("token.json");
+        let token = CopilotTokenResponse {
+            token: "test".to_string(),
+            expires_at: 42,
+            refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
+        };
+
+        save_token_to_path(&token, Some(&path)).unwrap();
+        let loaded = load_token_from_path(Some(&path)).unwrap();
+
+        assert_eq!(loaded.token, "test");
+        assert_eq!(loaded.expires_at, 42);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("token.json");
+
+        // Pre-existing world-readable file gets tightened on save
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let token = CopilotTokenResponse {
+            token: "test".to_string(),
+            expires_at: 42,
+            refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
+        };
+        save_token_to_path(&token, Some(&path)).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_token_files_are_owner_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("token.json");
+
+        // Pre-existing files under the directory's inherited ACL get replaced on save
+        fs::write(&path, "{}").unwrap();
+
+        let token = CopilotTokenResponse {
+            token: "test".to_string(),
+            expires_at: 42,
+            refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
+        };
+        save_token_to_path(&token, Some(&path)).unwrap();
+
+        let dacl = windows_acl::file_dacl(&path).unwrap();
+        let sid = windows_acl::current_user_sid().unwrap();
+        let (flags, aces) = dacl.strip_prefix("D:").unwrap().split_once('(').unwrap();
+        assert!(flags.contains('P'), "{}", dacl);
+        assert_eq!(aces, format!("A;;FA;;;{})", sid), "{}", dacl);
+        assert_eq!(load_token_from_path(Some(&path)).unwrap().token, "test");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_get_token_path() {
-        let path = get_token_path().unwrap();
-        assert!(path.ends_with(".config/passenger-rs/token.json"));
+    fn test_find_insecure_paths() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let private = tmp.path().join("private.json");
+        let shared = tmp.path().join("shared.json");
+        let missing = tmp.path().join("missing.json");
+        fs::write(&private, "{}").unwrap();
+        fs::write(&shared, "{}").unwrap();
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::set_permissions(&shared, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let insecure = find_insecure_paths(&[private, shared.clone(), missing]);
+
+        assert_eq!(
+            insecure,
+            vec![InsecurePath {
+                path: shared,
+                mode: 0o644
+            }]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_storage_dir_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("passenger-rs");
+
+        create_private_dir(&dir).unwrap();
+
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
     }
 
     #[test]
@@ -217,23 +658,52 @@ mod tests {
             token: "test".to_string(),
             expires_at: now - 3600,
             refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
         };
-        assert!(is_token_expired(&expired_token));
+        assert!(is_token_expired(&expired_token, 60));
 
         // Token expires in 30 seconds (within buffer, should be considered expired)
         let almost_expired_token = CopilotTokenResponse {
             token: "test".to_string(),
             expires_at: now + 30,
             refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
         };
-        assert!(is_token_expired(&almost_expired_token));
+        assert!(is_token_expired(&almost_expired_token, 60));
 
         // Token expires in 10 minutes (valid)
         let valid_token = CopilotTokenResponse {
             token: "test".to_string(),
             expires_at: now + 600,
             refresh_in: 0,
+            fetched_at: None,
+            entitlements: Default::default(),
         };
-        assert!(!is_token_expired(&valid_token));
+        assert!(!is_token_expired(&valid_token, 60));
+        // ...unless the buffer is longer
+        assert!(is_token_expired(&valid_token, 900));
+    }
+
+    #[test]
+    fn test_is_token_due_for_refresh() {
+        let now = now();
+        let mut token = CopilotTokenResponse {
+            token: "test".to_string(),
+            expires_at: now + 1800,
+            refresh_in: 1500,
+            fetched_at: Some(now - 1000),
+            entitlements: Default::default(),
+        };
+        assert!(!is_token_due_for_refresh(&token, 60));
+
+        // Past `refresh_in`, though well before expiry
+        token.fetched_at = Some(now - 1500);
+        assert!(is_token_due_for_refresh(&token, 60));
+
+        // Tokens saved without a fetch time only go by expiry
+        token.fetched_at = None;
+        assert!(!is_token_due_for_refresh(&token, 60));
     }
 }
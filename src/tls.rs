@@ -0,0 +1,371 @@
+use crate::config::CopilotTlsConfig;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::ClientBuilder;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+/// Apply `[copilot.tls]` to a client builder.
+///
+/// Extra CA certificates are trusted on top of the platform roots. When pins are
+/// configured, the TLS stack is replaced by a rustls config whose verifier also
+/// checks the validated chain against the pinned public keys.
+pub fn configure(builder: ClientBuilder, tls: &CopilotTlsConfig) -> Result<ClientBuilder> {
+    let extra_roots = load_ca_certificates(&tls.ca_certificates)?;
+
+    if tls.pins.is_empty() {
+        let certs = extra_roots
+            .iter()
+            .map(|der| reqwest::Certificate::from_der(der))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Invalid CA certificate")?;
+        return Ok(builder.tls_certs_merge(certs));
+    }
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let verifier =
+        rustls_platform_verifier::Verifier::new_with_extra_roots(extra_roots, provider.clone())
+            .context("Failed to initialise certificate verifier")?;
+
+    let pinning_verifier = PinningVerifier {
+        inner: Arc::new(verifier),
+        pins: parse_pins(&tls.pins)?,
+        algorithms: provider.signature_verification_algorithms,
+    };
+
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(pinning_verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(builder.tls_backend_preconfigured(config))
+}
+
+/// Read every certificate from the given PEM files
+fn load_ca_certificates(paths: &[String]) -> Result<Vec<CertificateDer<'static>>> {
+    let mut certs = Vec::new();
+
+    for path in paths {
+        let pem =
+            fs::read(path).context(format!("Failed to read CA certificate file: {}", path))?;
+        let before = certs.len();
+
+        for cert in CertificateDer::pem_slice_iter(&pem) {
+            certs.push(cert.context(format!("Invalid PEM in CA certificate file: {}", path))?);
+        }
+
+        if certs.len() == before {
+            return Err(anyhow::anyhow!("No certificates found in {}", path));
+        }
+    }
+
+    Ok(certs)
+}
+
+/// Decode `sha256/<base64>` pins into raw SPKI digests, keyed by lowercase host
+fn parse_pins(pins: &HashMap<String, Vec<String>>) -> Result<HashMap<String, Vec<Vec<u8>>>> {
+    pins.iter()
+        .map(|(host, host_pins)| {
+            let digests = host_pins
+                .iter()
+                .map(|pin| {
+                    let encoded = pin.strip_prefix("sha256/").ok_or_else(|| {
+                        anyhow::anyhow!("Pin for {} must start with \"sha256/\": {}", host, pin)
+                    })?;
+                    STANDARD
+                        .decode(encoded)
+                        .context(format!("Pin for {} is not valid base64: {}", host, pin))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok((host.to_ascii_lowercase(), digests))
+        })
+        .collect()
+}
+
+/// SHA-256 of a certificate's SubjectPublicKeyInfo, as pinned by `curl --pinnedpubkey`
+fn spki_sha256(cert: &CertificateDer<'_>) -> Result<Vec<u8>, webpki::Error> {
+    let cert = webpki::EndEntityCert::try_from(cert)?;
+    Ok(Sha256::digest(cert.subject_public_key_info().as_ref()).to_vec())
+}
+
+/// Normal chain validation, then require the leaf, or a presented certificate the leaf
+/// chains to, to match a pin for hosts that have any. Pinning an intermediate keeps
+/// working across leaf renewals.
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: HashMap<String, Vec<Vec<u8>>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinningVerifier {
+    fn check_pins(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        now: UnixTime,
+    ) -> Result<(), rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            other => other.to_str().to_string(),
+        };
+
+        let Some(pins) = self.pins.get(&host) else {
+            return Ok(());
+        };
+
+        let invalid = |e: webpki::Error| {
+            rustls::Error::General(format!("invalid certificate for {}: {:?}", host, e))
+        };
+        if pins.contains(&spki_sha256(end_entity).map_err(invalid)?) {
+            return Ok(());
+        }
+
+        // A pinned intermediate only counts if the leaf's signatures chain up to it,
+        // not merely because the server sent it along
+        let leaf = webpki::EndEntityCert::try_from(end_entity).map_err(invalid)?;
+        for cert in intermediates {
+            if !pins.contains(&spki_sha256(cert).map_err(invalid)?) {
+                continue;
+            }
+            let anchor = webpki::anchor_from_trusted_cert(cert).map_err(invalid)?;
+            let chains = leaf
+                .verify_for_usage(
+                    self.algorithms.all,
+                    &[anchor],
+                    intermediates,
+                    now,
+                    webpki::KeyUsage::server_auth(),
+                    None,
+                    None,
+                )
+                .is_ok();
+            if chains {
+                return Ok(());
+            }
+        }
+
+        Err(rustls::Error::General(format!(
+            "certificate chain for {} does not match any pinned public key",
+            host
+        )))
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        self.check_pins(end_entity, intermediates, server_name, now)?;
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_CA: &str = include_str!("resources/test_ca.pem");
+    const TEST_CA_PIN: &str = "sha256/9d/udzVREm3mXB7GI5JYseavHGrM5d+HL3z+y2Za8dA=";
+    const TEST_CHAIN: &str = include_str!("resources/test_chain.pem");
+
+    fn test_cert() -> CertificateDer<'static> {
+        CertificateDer::from_pem_slice(TEST_CA.as_bytes()).unwrap()
+    }
+
+    fn pinning_verifier(pins: &[(&str, &str)]) -> PinningVerifier {
+        let pins = pins
+            .iter()
+            .map(|(host, pin)| (host.to_string(), vec![pin.to_string()]))
+            .collect();
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+
+        PinningVerifier {
+            inner: Arc::new(rustls_platform_verifier::Verifier::new(provider.clone()).unwrap()),
+            pins: parse_pins(&pins).unwrap(),
+            algorithms: provider.signature_verification_algorithms,
+        }
+    }
+
+    #[test]
+    fn test_spki_pin_matches_openssl() {
+        let pin = format!(
+            "sha256/{}",
+            STANDARD.encode(spki_sha256(&test_cert()).unwrap())
+        );
+        assert_eq!(pin, TEST_CA_PIN);
+    }
+
+    /// A leaf for api.githubcopilot.com and the CA that issued it
+    fn test_chain() -> (CertificateDer<'static>, CertificateDer<'static>) {
+        let mut certs = CertificateDer::pem_slice_iter(TEST_CHAIN.as_bytes()).map(Result::unwrap);
+        (certs.next().unwrap(), certs.next().unwrap())
+    }
+
+    fn pin_of(cert: &CertificateDer<'_>) -> String {
+        format!("sha256/{}", STANDARD.encode(spki_sha256(cert).unwrap()))
+    }
+
+    #[test]
+    fn test_check_pins() {
+        let (leaf, issuer) = test_chain();
+        let pinned = ServerName::try_from("api.githubcopilot.com").unwrap();
+        let unpinned = ServerName::try_from("github.com").unwrap();
+        let now = UnixTime::now();
+
+        let leaf_pin = pin_of(&leaf);
+        let verifier = pinning_verifier(&[("API.githubcopilot.com", &leaf_pin)]);
+        assert!(verifier.check_pins(&leaf, &[], &pinned, now).is_ok());
+        assert!(verifier.check_pins(&leaf, &[], &unpinned, now).is_ok());
+
+        let other_pin = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        let verifier = pinning_verifier(&[("api.githubcopilot.com", other_pin)]);
+        assert!(
+            verifier
+                .check_pins(&leaf, std::slice::from_ref(&issuer), &pinned, now)
+                .is_err()
+        );
+
+        // A pinned issuer is enough, even if the leaf does not match
+        let issuer_pin = pin_of(&issuer);
+        let verifier = pinning_verifier(&[("api.githubcopilot.com", &issuer_pin)]);
+        assert!(verifier.check_pins(&leaf, &[issuer], &pinned, now).is_ok());
+    }
+
+    #[test]
+    fn test_check_pins_ignores_pinned_certificate_outside_chain() {
+        let (leaf, _) = test_chain();
+        let pinned = ServerName::try_from("api.githubcopilot.com").unwrap();
+
+        // The pinned CA is presented, but did not issue the leaf
+        let verifier = pinning_verifier(&[("api.githubcopilot.com", TEST_CA_PIN)]);
+        assert!(
+            verifier
+                .check_pins(&leaf, &[test_cert()], &pinned, UnixTime::now())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_check_pins_rejects_unparseable_certificate() {
+        let (leaf, issuer) = test_chain();
+        let pinned = ServerName::try_from("api.githubcopilot.com").unwrap();
+        let garbage = CertificateDer::from(vec![0u8; 4]);
+        let issuer_pin = pin_of(&issuer);
+        let verifier = pinning_verifier(&[("api.githubcopilot.com", &issuer_pin)]);
+        let now = UnixTime::now();
+
+        assert!(
+            verifier
+                .check_pins(&garbage, std::slice::from_ref(&issuer), &pinned, now)
+                .is_err()
+        );
+        assert!(
+            verifier
+                .check_pins(&leaf, &[garbage, issuer], &pinned, now)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_pins_requires_sha256_prefix() {
+        let pins = HashMap::from([(
+            "api.githubcopilot.com".to_string(),
+            vec!["9d/udzVREm3mXB7GI5JYseavHGrM5d+HL3z+y2Za8dA=".to_string()],
+        )]);
+
+        assert!(parse_pins(&pins).is_err());
+    }
+
+    #[test]
+    fn test_load_ca_certificates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ca = tmp.path().join("ca.pem");
+        let empty = tmp.path().join("empty.pem");
+        fs::write(&ca, TEST_CA).unwrap();
+        fs::write(&empty, "not a certificate").unwrap();
+
+        let certs = load_ca_certificates(&[ca.display().to_string()]).unwrap();
+        assert_eq!(certs, vec![test_cert()]);
+
+        assert!(load_ca_certificates(&[empty.display().to_string()]).is_err());
+        assert!(load_ca_certificates(&["/nonexistent/ca.pem".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_configure_builds_client() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ca = tmp.path().join("ca.pem");
+        fs::write(&ca, TEST_CA).unwrap();
+
+        let mut tls = CopilotTlsConfig {
+            ca_certificates: vec![ca.display().to_string()],
+            pins: HashMap::new(),
+        };
+        assert!(
+            configure(reqwest::Client::builder(), &tls)
+                .unwrap()
+                .build()
+                .is_ok()
+        );
+
+        tls.pins.insert(
+            "api.githubcopilot.com".to_string(),
+            vec![TEST_CA_PIN.to_string()],
+        );
+        assert!(
+            configure(reqwest::Client::builder(), &tls)
+                .unwrap()
+                .build()
+                .is_ok()
+        );
+    }
+}
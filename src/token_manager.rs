@@ -3,35 +3,106 @@ use crate::config::Config;
 use crate::storage;
 use anyhow::{Context, Result, bail};
 use reqwest::Client;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
 use tracing::log::debug;
 use tracing::{info, warn};
 
+/// Held while a token file is being refreshed
+type RefreshLock = Arc<tokio::sync::Mutex<()>>;
+
+/// One lock per token file, so that requests finding the token expired at the same time
+/// refresh it once and the rest reuse the result
+static REFRESH_LOCKS: LazyLock<Mutex<HashMap<Option<PathBuf>, RefreshLock>>> =
+    LazyLock::new(Mutex::default);
+
+fn refresh_lock(token_path: Option<&Path>) -> RefreshLock {
+    REFRESH_LOCKS
+        .lock()
+        .expect("token refresh locks poisoned")
+        .entry(token_path.map(Path::to_path_buf))
+        .or_default()
+        .clone()
+}
+
 /// Get a valid Copilot token, either from cache or by refreshing
 pub async fn get_valid_token(
     config: &Config,
     client: &Client,
     // github_access_token: Option<&str>,
 ) -> Result<CopilotTokenResponse> {
-    // Try to load token from disk
-    if storage::token_exists() {
-        match storage::load_token() {
-            Ok(token) => {
-                if !storage::is_token_expired(&token) {
-                    debug!("Using cached Copilot token");
-                    return Ok(token);
-                } else {
-                    debug!("Cached token is expired, refreshing...");
-                }
-            }
-            Err(e) => {
-                warn!("Failed to load cached token: {}", e);
-            }
-        }
+    get_valid_token_at(config, client, None, None).await
+}
+
+/// Like [`get_valid_token`], with the tokens at custom paths, as for `[profiles]`
+pub async fn get_valid_token_at(
+    config: &Config,
+    client: &Client,
+    token_path: Option<&Path>,
+    access_token_path: Option<&Path>,
+) -> Result<CopilotTokenResponse> {
+    if let Some(token) = cached_token(config, token_path) {
+        return Ok(token);
+    }
+
+    let lock = refresh_lock(token_path);
+    let _refreshing = lock.lock().await;
+    // Another request may have refreshed the token while this one waited
+    if let Some(token) = cached_token(config, token_path) {
+        return Ok(token);
     }
 
     // If we get here, we need to refresh the token
-    let github_access_token = storage::load_access_token()?;
-    refresh_token(config, client, github_access_token).await
+    let github_access_token = storage::load_access_token_from_path(access_token_path)?;
+    refresh_token(config, client, github_access_token, token_path).await
+}
+
+/// A new Copilot token in place of `rejected`, which Copilot refused before it expired, as
+/// when the subscription is revoked. When another request already replaced it, the token
+/// it got is returned rather than refreshing again.
+pub async fn replace_rejected_token_at(
+    config: &Config,
+    client: &Client,
+    rejected: &CopilotTokenResponse,
+    token_path: Option<&Path>,
+    access_token_path: Option<&Path>,
+) -> Result<CopilotTokenResponse> {
+    let lock = refresh_lock(token_path);
+    let _refreshing = lock.lock().await;
+    if let Some(token) = cached_token(config, token_path)
+        && token.token != rejected.token
+    {
+        return Ok(token);
+    }
+
+    info!("Copilot rejected its token before expiry, refreshing...");
+    let github_access_token = storage::load_access_token_from_path(access_token_path)?;
+    refresh_token(config, client, github_access_token, token_path).await
+}
+
+/// The token on disk, unless it is missing or due for refresh
+fn cached_token(config: &Config, token_path: Option<&Path>) -> Option<CopilotTokenResponse> {
+    let token_exists = token_path.map_or_else(storage::token_exists, storage::token_exists_at_path);
+    if !token_exists {
+        return None;
+    }
+
+    match storage::load_token_from_path(token_path) {
+        Ok(token) => {
+            if !storage::is_token_due_for_refresh(&token, config.copilot.token.expiry_buffer_secs) {
+                debug!("Using cached Copilot token");
+                Some(token)
+            } else {
+                debug!("Cached token is due for refresh, refreshing...");
+                None
+            }
+        }
+        Err(e) => {
+            warn!("Failed to load cached token: {}", e);
+            None
+        }
+    }
 }
 
 /// Refresh the Copilot token using a GitHub access token
@@ -39,6 +110,7 @@ async fn refresh_token(
     config: &Config,
     client: &Client,
     github_access_token: Option<AccessTokenResponse>,
+    token_path: Option<&Path>,
 ) -> Result<CopilotTokenResponse> {
     let access_token = match github_access_token {
         Some(token) => token.access_token.to_string(),
@@ -48,13 +120,19 @@ async fn refresh_token(
     };
 
     info!("Refreshing Copilot token...");
-    let copilot_token =
-        auth::get_copilot_token(client, &config.github.copilot_token_url, &access_token)
-            .await
-            .context("Failed to refresh Copilot token")?;
+    let mut copilot_token = auth::get_copilot_token(
+        client,
+        &config.github.copilot_token_url,
+        &access_token,
+        &config.copilot.headers,
+    )
+    .await
+    .context("Failed to refresh Copilot token")?;
+    storage::mark_token_fetched(&mut copilot_token);
 
     // Save the new token
-    storage::save_token(&copilot_token).context("Failed to save refreshed token")?;
+    storage::save_token_to_path(&copilot_token, token_path)
+        .context("Failed to save refreshed token")?;
 
     debug!("Copilot token refreshed and saved");
     Ok(copilot_token)
@@ -63,6 +141,10 @@ async fn refresh_token(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
     async fn test_get_valid_token_no_cache() {
@@ -72,7 +154,7 @@ mod tests {
         // Clean up any existing token
         let _ = storage::delete_token();
 
-        let config = Config::from_file("config.toml").unwrap();
+        let config = Config::default();
         let client = Client::new();
 
         // Without access token, should fail
@@ -84,10 +166,10 @@ mod tests {
 
     #[tokio::test]
     async fn test_refresh_token_no_access_token() {
-        let config = Config::from_file("config.toml").unwrap();
+        let config = Config::default();
         let client = Client::new();
 
-        let result = refresh_token(&config, &client, None).await;
+        let result = refresh_token(&config, &client, None, None).await;
         assert!(result.is_err());
         assert!(
             result
@@ -96,4 +178,59 @@ mod tests {
                 .contains("No GitHub access token")
         );
     }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_call_github_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let token_path = dir.path().join("token.json");
+        let access_token_path = dir.path().join("access_token.json");
+        std::fs::write(
+            &access_token_path,
+            json!({"access_token": "gho_test", "token_type": "bearer", "scope": ""}).to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            &token_path,
+            json!({"token": "expired", "expires_at": 0, "refresh_in": 0}).to_string(),
+        )
+        .unwrap();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/copilot_internal/v2/token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({
+                        "token": "fresh",
+                        "expires_at": now + 1800,
+                        "refresh_in": 1500
+                    }))
+                    .set_delay(Duration::from_millis(100)),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut config = Config::default();
+        config.github.copilot_token_url =
+            format!("{}/copilot_internal/v2/token", mock_server.uri());
+        let client = Client::new();
+
+        let requests = (0..8).map(|_| {
+            get_valid_token_at(
+                &config,
+                &client,
+                Some(&token_path),
+                Some(&access_token_path),
+            )
+        });
+        for token in futures_util::future::join_all(requests).await {
+            assert_eq!(token.unwrap().token, "fresh");
+        }
+        mock_server.verify().await;
+    }
 }
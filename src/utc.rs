@@ -0,0 +1,124 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A UTC date and time, enough to format timestamps without a date library
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcTime {
+    pub year: i64,
+    /// 1 to 12
+    pub month: u32,
+    /// 1 to 31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millis: u32,
+}
+
+impl UtcTime {
+    pub fn now() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time should go forward");
+        let mut time = Self::from_unix(since_epoch.as_secs());
+        time.millis = since_epoch.subsec_millis();
+        time
+    }
+
+    /// The time `secs` seconds after the Unix epoch
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let of_day = (secs % 86_400) as u32;
+
+        // Howard Hinnant's `civil_from_days`, in 400-year eras starting on 1 March
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        Self {
+            year,
+            month,
+            day,
+            hour: of_day / 3_600,
+            minute: of_day / 60 % 60,
+            second: of_day % 60,
+            millis: 0,
+        }
+    }
+
+    /// `YYYY-MM`
+    pub fn month_key(self) -> String {
+        format!("{:04}-{:02}", self.year, self.month)
+    }
+
+    /// RFC 3339 with whole seconds, e.g. `2026-01-02T03:04:05+00:00`
+    pub fn to_rfc3339(self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    /// Common Log Format, e.g. `02/Jan/2026:03:04:05 +0000`
+    pub fn to_common_log(self) -> String {
+        format!(
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// Compact and sortable, for file names, e.g. `20260102T030405.123`
+    pub fn to_compact(self) -> String {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_unix() {
+        assert_eq!(
+            UtcTime::from_unix(0).to_rfc3339(),
+            "1970-01-01T00:00:00+00:00"
+        );
+        let time = UtcTime::from_unix(1_767_323_045);
+        assert_eq!(time.to_rfc3339(), "2026-01-02T03:04:05+00:00");
+        assert_eq!(time.to_common_log(), "02/Jan/2026:03:04:05 +0000");
+        assert_eq!(time.to_compact(), "20260102T030405.000");
+        assert_eq!(time.month_key(), "2026-01");
+
+        // Leap days, including a century divisible by 400
+        assert_eq!(
+            UtcTime::from_unix(1_709_164_800).to_rfc3339(),
+            "2024-02-29T00:00:00+00:00"
+        );
+        assert_eq!(
+            UtcTime::from_unix(951_782_400).to_rfc3339(),
+            "2000-02-29T00:00:00+00:00"
+        );
+    }
+}
@@ -5,12 +5,13 @@ use reqwest::Client;
 #[tokio::test]
 #[ignore = "makes a live API call to GitHub; requires network access and a valid client_id"]
 async fn test_request_device_code() {
-    let config = Config::from_file("config.toml").expect("Failed to load config");
+    let config = Config::default();
     let client = Client::new();
     let result = request_device_code(
         &client,
         &config.github.device_code_url,
         &config.github.client_id,
+        &config.copilot.headers,
     )
     .await;
 
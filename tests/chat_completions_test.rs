@@ -14,7 +14,7 @@ async fn test_chat_completions_with_real_api() {
     setup_test_tokens().await;
 
     // Load config
-    let mut config = Config::from_file("config.toml").expect("Failed to load config");
+    let mut config = Config::default();
     config.server.port = 0; // Use dynamic port
 
     // Create server
@@ -112,7 +112,7 @@ async fn test_chat_completions_without_auth() {
     }
 
     // Load config
-    let mut config = Config::from_file("config.toml").expect("Failed to load config");
+    let mut config = Config::default();
 
     // Use a different port to avoid conflicts
     config.server.port = 0; // OS will assign available port
@@ -193,7 +193,7 @@ async fn test_chat_completions_without_auth() {
 #[tokio::test]
 async fn test_chat_completions_invalid_request() {
     // Load config
-    let mut config = Config::from_file("config.toml").expect("Failed to load config");
+    let mut config = Config::default();
     config.server.port = 0; // Use dynamic port
 
     // Create server
@@ -247,7 +247,7 @@ async fn setup_test_tokens() {
         // Verify token is valid
 
         if let Ok(token) = storage::load_token()
-            && !storage::is_token_expired(&token)
+            && !storage::is_token_expired(&token, 60)
         {
             println!("Using existing valid token");
             return;
@@ -263,7 +263,7 @@ async fn setup_test_tokens() {
 #[ignore] // TODO: Implement streaming support
 async fn test_chat_completions_streaming() {
     // Load config
-    let mut config = Config::from_file("config.toml").expect("Failed to load config");
+    let mut config = Config::default();
     config.server.port = 0; // Use dynamic port
 
     // Create server
@@ -327,7 +327,7 @@ async fn test_chat_completions_with_tools() {
     setup_test_tokens().await;
 
     // Load config
-    let mut config = Config::from_file("config.toml").expect("Failed to load config");
+    let mut config = Config::default();
     config.server.port = 0; // Use dynamic port
 
     // Create server
@@ -0,0 +1,205 @@
+//! A wiremock Copilot and a proxy pointed at it, shared by the integration tests. The
+//! GitHub token endpoint and the model list are mocked; tests mount Copilot's replies.
+#![allow(dead_code)]
+
+use passenger_rs::config::Config;
+use passenger_rs::server::Server;
+use reqwest::Client;
+use serde_json::{Value, json};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+use wiremock::matchers::{body_partial_json, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Name of the profile the tests are served under, bound to the temporary token files
+pub const PROFILE: &str = "e2e";
+
+/// The Copilot token the mocked GitHub hands out
+pub const COPILOT_TOKEN: &str = "copilot-token";
+
+/// A proxy in front of a mocked Copilot
+pub struct Harness {
+    pub copilot: MockServer,
+    pub addr: String,
+    pub client: Client,
+    _tokens: TempDir,
+}
+
+impl Harness {
+    /// Start a mocked Copilot and a proxy whose config points at it. The proxy has only a
+    /// GitHub access token, and fetches its Copilot token from the mock on first use.
+    pub async fn start() -> Self {
+        Self::start_with("").await
+    }
+
+    /// [`Harness::start`], with `extra` appended to the proxy's config. `{dir}` in it is
+    /// replaced by a temporary directory, also returned by [`Harness::dir`].
+    pub async fn start_with(extra: &str) -> Self {
+        let copilot = MockServer::start().await;
+        mount_token(&copilot, COPILOT_TOKEN).await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(header("authorization", format!("Bearer {}", COPILOT_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "github-copilot": { "models": {
+                    "gpt-4o": { "id": "gpt-4o", "name": "GPT-4o", "family": "gpt-4o", "tool_call": true },
+                    "o3-mini": { "id": "o3-mini", "name": "o3-mini", "family": "o3", "reasoning": true }
+                } }
+            })))
+            .mount(&copilot)
+            .await;
+
+        let tokens = tempfile::tempdir().unwrap();
+        let access_token_path = tokens.path().join("access_token.json");
+        std::fs::write(
+            &access_token_path,
+            json!({"access_token": "gho_test", "token_type": "bearer", "scope": "read:user"})
+                .to_string(),
+        )
+        .unwrap();
+
+        let uri = copilot.uri();
+        let config = Config::parse(&format!(
+            r#"
+            [github]
+            copilot_token_url = "{uri}/copilot_internal/v2/token"
+            copilot_models_url = "{uri}/models"
+
+            [copilot]
+            api_base_url = "{uri}"
+
+            [copilot.completions]
+            base_url = "{uri}"
+            engine = "gpt-41-copilot"
+
+            [profiles.{PROFILE}]
+            access_token_path = "{access}"
+            copilot_token_path = "{copilot_token}"
+
+            {extra}
+            "#,
+            access = access_token_path.display(),
+            copilot_token = tokens.path().join("token.json").display(),
+            extra = extra.replace("{dir}", &tokens.path().display().to_string()),
+        ))
+        .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let router = Server::new(&config).router;
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        Harness {
+            copilot,
+            addr,
+            client: Client::new(),
+            _tokens: tokens,
+        }
+    }
+
+    /// The temporary directory holding the proxy's tokens
+    pub fn dir(&self) -> &Path {
+        self._tokens.path()
+    }
+
+    /// URL of `path` on the proxy, under the test profile
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}/{}{}", self.addr, PROFILE, path)
+    }
+
+    pub async fn post(&self, path: &str, body: Value) -> reqwest::Response {
+        self.client
+            .post(self.url(path))
+            .json(&body)
+            .send()
+            .await
+            .unwrap()
+    }
+
+    /// Answer Copilot chat requests matching `body` with `response`
+    pub async fn mock_chat(&self, body: Value, response: ResponseTemplate) {
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("authorization", format!("Bearer {}", COPILOT_TOKEN)))
+            .and(body_partial_json(body))
+            .respond_with(response)
+            .mount(&self.copilot)
+            .await;
+    }
+
+    /// Answer non-streaming Copilot chat requests with `content`
+    pub async fn mock_reply(&self, content: &str) {
+        self.mock_chat(
+            json!({}),
+            ResponseTemplate::new(200).set_body_json(reply(content)),
+        )
+        .await;
+    }
+
+    /// Answer streaming Copilot chat requests with the `data:` `chunks`
+    pub async fn mock_stream(&self, chunks: &[Value]) {
+        self.mock_chat(json!({"stream": true}), sse(chunks)).await;
+    }
+}
+
+/// A non-streaming Copilot chat reply
+pub fn reply(content: &str) -> Value {
+    json!({
+        "id": "chatcmpl-1",
+        "created": 1700000000,
+        "model": "gpt-4o",
+        "choices": [{
+            "index": 0,
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop"
+        }],
+        "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}
+    })
+}
+
+/// Have the GitHub token endpoint hand out `token`
+pub async fn mount_token(copilot: &MockServer, token: &str) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    Mock::given(method("GET"))
+        .and(path("/copilot_internal/v2/token"))
+        .and(header("authorization", "token gho_test"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "token": token,
+            "expires_at": now + 1800,
+            "refresh_in": 1500
+        })))
+        .up_to_n_times(1)
+        .mount(copilot)
+        .await;
+}
+
+/// An SSE response of `chunks`, ended by `[DONE]`
+pub fn sse(chunks: &[Value]) -> ResponseTemplate {
+    let mut body: String = chunks
+        .iter()
+        .map(|chunk| format!("data: {}\n\n", chunk))
+        .collect();
+    body.push_str("data: [DONE]\n\n");
+    ResponseTemplate::new(200).set_body_raw(body, "text/event-stream")
+}
+
+/// The JSON payloads of the `data:` lines of an SSE body, without `[DONE]`
+pub fn data_payloads(body: &str) -> Vec<Value> {
+    body.lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|payload| *payload != "[DONE]")
+        .map(|payload| serde_json::from_str(payload).unwrap())
+        .collect()
+}
+
+/// The objects of an NDJSON body
+pub fn ndjson(body: &str) -> Vec<Value> {
+    body.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
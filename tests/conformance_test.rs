@@ -0,0 +1,205 @@
+//! Golden-file tests of the protocol translations. Each case under
+//! `tests/conformance/<group>/<case>/` is a client request, the reply Copilot gives it and
+//! the response the client should get:
+//!
+//! - `request.json`: the body posted to the group's endpoint
+//! - `upstream_request.json` (optional): fields the request sent to Copilot must have
+//! - `upstream.json` or `upstream.sse`: Copilot's reply, whole or as an SSE body
+//! - `expected.json`: the status and body the client gets. Streamed bodies are the array
+//!   of their SSE `data:` payloads or NDJSON lines. `"<any>"` matches any value, for ids
+//!   and timestamps.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write the responses the proxy gives as the expected ones,
+//! keeping their `"<any>"` wildcards.
+
+mod common;
+
+use common::*;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use wiremock::ResponseTemplate;
+
+/// Stands for any value in an expected response
+const ANY: &str = "<any>";
+
+/// The case directories of `group`, in name order
+fn cases(group: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/conformance")
+        .join(group);
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    cases.sort();
+    cases
+}
+
+fn read_json(path: &Path) -> Value {
+    let text =
+        std::fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+/// Copilot's reply in `case`
+fn upstream(case: &Path) -> ResponseTemplate {
+    let sse = case.join("upstream.sse");
+    if sse.exists() {
+        let body = std::fs::read_to_string(&sse).unwrap();
+        return ResponseTemplate::new(200).set_body_raw(body, "text/event-stream");
+    }
+    ResponseTemplate::new(200).set_body_json(read_json(&case.join("upstream.json")))
+}
+
+/// The status and body of the proxy's response to `case`
+async fn respond(case: &Path, endpoint: &str) -> Value {
+    let harness = Harness::start().await;
+    let upstream_request = case.join("upstream_request.json");
+    let upstream_request = if upstream_request.exists() {
+        read_json(&upstream_request)
+    } else {
+        json!({})
+    };
+    harness.mock_chat(upstream_request, upstream(case)).await;
+
+    let response = harness
+        .post(endpoint, read_json(&case.join("request.json")))
+        .await;
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let text = response.text().await.unwrap();
+    let body = if content_type.contains("event-stream") {
+        Value::Array(data_payloads(&text))
+    } else if content_type.contains("ndjson") {
+        Value::Array(ndjson(&text))
+    } else {
+        serde_json::from_str(&text).unwrap_or(Value::String(text))
+    };
+    json!({"status": status, "body": body})
+}
+
+/// Whether `actual` is `expected`, wildcards aside
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::String(any), _) if any == ANY => true,
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len()
+                && expected.iter().zip(actual).all(|(e, a)| matches(e, a))
+        }
+        (Value::Object(expected), Value::Object(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .all(|(key, e)| actual.get(key).is_some_and(|a| matches(e, a)))
+        }
+        _ => expected == actual,
+    }
+}
+
+/// `actual` with the wildcards of `expected` where it has them
+fn with_wildcards(expected: &Value, actual: Value) -> Value {
+    match (expected, actual) {
+        (Value::String(any), _) if any == ANY => Value::String(ANY.to_string()),
+        (Value::Array(expected), Value::Array(actual)) => Value::Array(
+            actual
+                .into_iter()
+                .enumerate()
+                .map(|(i, a)| match expected.get(i) {
+                    Some(e) => with_wildcards(e, a),
+                    None => a,
+                })
+                .collect(),
+        ),
+        (Value::Object(expected), Value::Object(actual)) => Value::Object(
+            actual
+                .into_iter()
+                .map(|(key, a)| {
+                    let a = match expected.get(&key) {
+                        Some(e) => with_wildcards(e, a),
+                        None => a,
+                    };
+                    (key, a)
+                })
+                .collect(),
+        ),
+        (_, actual) => actual,
+    }
+}
+
+/// Run every case of `group` against `endpoint`, failing with the cases that differ
+async fn run(group: &str, endpoint: &str) {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    for case in cases(group) {
+        let actual = respond(&case, endpoint).await;
+        let expected_path = case.join("expected.json");
+        let expected = expected_path.exists().then(|| read_json(&expected_path));
+
+        if update || expected.is_none() {
+            let actual = match &expected {
+                Some(expected) => with_wildcards(expected, actual),
+                None => actual,
+            };
+            let text = serde_json::to_string_pretty(&actual).unwrap();
+            std::fs::write(&expected_path, text + "\n").unwrap();
+            continue;
+        }
+        let expected = expected.unwrap();
+        if !matches(&expected, &actual) {
+            failures.push(format!(
+                "{}\nexpected: {}\nactual:   {}",
+                case.display(),
+                serde_json::to_string_pretty(&expected).unwrap(),
+                serde_json::to_string_pretty(&actual).unwrap()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[tokio::test]
+async fn test_openai_chat() {
+    run("openai_chat", "/v1/chat/completions").await;
+}
+
+#[cfg(feature = "ollama")]
+#[tokio::test]
+async fn test_ollama_chat() {
+    run("ollama_chat", "/api/chat").await;
+}
+
+#[cfg(feature = "ollama")]
+#[tokio::test]
+async fn test_ollama_generate() {
+    run("ollama_generate", "/api/generate").await;
+}
+
+#[cfg(feature = "responses")]
+#[tokio::test]
+async fn test_responses() {
+    run("responses", "/v1/responses").await;
+}
+
+#[test]
+fn test_wildcards() {
+    let expected = json!({"id": ANY, "choices": [{"text": "Hi"}]});
+    assert!(matches(
+        &expected,
+        &json!({"id": "chatcmpl-9", "choices": [{"text": "Hi"}]})
+    ));
+    assert!(!matches(
+        &expected,
+        &json!({"id": "chatcmpl-9", "choices": [{"text": "Ho"}]})
+    ));
+    assert!(!matches(&expected, &json!({"choices": [{"text": "Hi"}]})));
+    assert_eq!(
+        with_wildcards(&expected, json!({"id": "chatcmpl-9", "choices": []})),
+        json!({"id": ANY, "choices": []})
+    );
+}
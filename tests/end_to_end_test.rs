@@ -0,0 +1,497 @@
+//! The proxy end to end against a wiremock Copilot: the GitHub token endpoint, the model
+//! list, chat and code completions are all mocked and injected via config, so these tests
+//! need neither `config.toml` nor a Copilot subscription.
+
+mod common;
+
+use common::*;
+#[cfg(feature = "keys")]
+use passenger_rs::config::KeysConfig;
+#[cfg(feature = "keys")]
+use passenger_rs::server::keys::KeyStore;
+use reqwest::StatusCode;
+use serde_json::{Value, json};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// A streamed Copilot chat chunk adding `delta`
+fn chunk(delta: Value, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": "chatcmpl-1",
+        "created": 1700000000,
+        "model": "gpt-4o",
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+    })
+}
+
+/// A streamed text reply of `pieces`
+fn text_stream(pieces: &[&str]) -> Vec<Value> {
+    let mut chunks = vec![chunk(json!({"role": "assistant", "content": ""}), None)];
+    chunks.extend(
+        pieces
+            .iter()
+            .map(|piece| chunk(json!({"content": piece}), None)),
+    );
+    chunks.push(chunk(json!({}), Some("stop")));
+    chunks
+}
+
+/// Responses API input of one user message
+#[cfg(feature = "responses")]
+fn input(text: &str) -> Value {
+    json!([{
+        "type": "message",
+        "role": "user",
+        "content": [{"type": "input_text", "text": text}]
+    }])
+}
+
+#[tokio::test]
+async fn test_health_needs_no_copilot() {
+    let harness = Harness::start().await;
+    let response = reqwest::get(format!("http://{}/health", harness.addr))
+        .await
+        .unwrap();
+    assert_eq!(response.text().await.unwrap(), "OK");
+}
+
+#[tokio::test]
+async fn test_chat_completion() {
+    let harness = Harness::start().await;
+    harness.mock_reply("Hello, World!").await;
+
+    let response = harness
+        .post(
+            "/v1/chat/completions",
+            json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Say hello"}]}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["choices"][0]["message"]["content"], "Hello, World!");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert_eq!(body["usage"]["total_tokens"], 7);
+}
+
+#[tokio::test]
+async fn test_chat_completion_stream() {
+    let harness = Harness::start().await;
+    harness.mock_stream(&text_stream(&["Hel", "lo"])).await;
+
+    let response = harness
+        .post(
+            "/v1/chat/completions",
+            json!({
+                "model": "gpt-4o",
+                "stream": true,
+                "messages": [{"role": "user", "content": "Say hello"}]
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(body.trim_end().ends_with("data: [DONE]"), "{}", body);
+
+    let chunks = data_payloads(&body);
+    let text: String = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(text, "Hello");
+    assert_eq!(
+        chunks.last().unwrap()["choices"][0]["finish_reason"],
+        "stop"
+    );
+}
+
+#[tokio::test]
+async fn test_chat_completion_stream_with_tool_calls() {
+    let harness = Harness::start().await;
+    harness
+        .mock_stream(&[
+            chunk(
+                json!({"role": "assistant", "tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": ""}
+                }]}),
+                None,
+            ),
+            chunk(
+                json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":"}}]}),
+                None,
+            ),
+            chunk(
+                json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"Paris\"}"}}]}),
+                None,
+            ),
+            // Some models behind Copilot finish tool calls with `stop`
+            chunk(json!({}), Some("stop")),
+        ])
+        .await;
+
+    let response = harness
+        .post(
+            "/v1/chat/completions",
+            json!({
+                "model": "gpt-4o",
+                "stream": true,
+                "messages": [{"role": "user", "content": "Weather in Paris?"}],
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "get_weather",
+                        "parameters": {"type": "object", "properties": {"city": {"type": "string"}}}
+                    }
+                }]
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let chunks = data_payloads(&response.text().await.unwrap());
+
+    let calls: Vec<&Value> = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["tool_calls"].as_array())
+        .flatten()
+        .collect();
+    assert_eq!(calls[0]["id"], "call_1");
+    assert_eq!(calls[0]["function"]["name"], "get_weather");
+    let arguments: String = calls
+        .iter()
+        .filter_map(|call| call["function"]["arguments"].as_str())
+        .collect();
+    assert_eq!(arguments, r#"{"city":"Paris"}"#);
+    assert_eq!(
+        chunks.last().unwrap()["choices"][0]["finish_reason"],
+        "tool_calls"
+    );
+}
+
+#[tokio::test]
+async fn test_models() {
+    let harness = Harness::start().await;
+
+    let response = harness
+        .client
+        .get(harness.url("/v1/models"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    let mut ids: Vec<&str> = body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|model| model["id"].as_str())
+        .collect();
+    ids.sort();
+    assert_eq!(ids, ["gpt-4o", "o3-mini"]);
+}
+
+#[tokio::test]
+async fn test_rejected_token_is_replaced() {
+    let harness = Harness::start().await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .and(header("authorization", format!("Bearer {}", COPILOT_TOKEN)))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&harness.copilot)
+        .await;
+    mount_token(&harness.copilot, "copilot-token-2").await;
+    Mock::given(method("POST"))
+        .and(path("/chat/completions"))
+        .and(header("authorization", "Bearer copilot-token-2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(reply("Hello again")))
+        .expect(1)
+        .mount(&harness.copilot)
+        .await;
+
+    let response = harness
+        .post(
+            "/v1/chat/completions",
+            json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "Hello again");
+}
+
+#[tokio::test]
+async fn test_copilot_errors_reach_the_client() {
+    let harness = Harness::start().await;
+    harness
+        .mock_chat(
+            json!({}),
+            ResponseTemplate::new(400).set_body_json(json!({
+                "error": {"message": "model not supported", "code": "model_not_supported"}
+            })),
+        )
+        .await;
+
+    let response = harness
+        .post(
+            "/v1/chat/completions",
+            json!({"model": "gpt-2", "messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "model_not_found");
+    assert_eq!(body["error"]["param"], "model");
+}
+
+#[cfg(feature = "responses")]
+#[tokio::test]
+async fn test_responses() {
+    let harness = Harness::start().await;
+    harness.mock_reply("Hello from responses").await;
+
+    let response = harness
+        .post(
+            "/v1/responses",
+            json!({"model": "gpt-4o", "input": input("Hi")}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "completed");
+    assert_eq!(
+        body["output"][0]["content"][0]["text"],
+        "Hello from responses"
+    );
+}
+
+#[cfg(feature = "responses")]
+#[tokio::test]
+async fn test_responses_stream() {
+    let harness = Harness::start().await;
+    harness.mock_stream(&text_stream(&["Hel", "lo"])).await;
+
+    let response = harness
+        .post(
+            "/v1/responses",
+            json!({"model": "gpt-4o", "input": input("Hi"), "stream": true}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = data_payloads(&response.text().await.unwrap());
+
+    let text: String = events
+        .iter()
+        .filter(|event| event["type"] == "response.output_text.delta")
+        .filter_map(|event| event["delta"].as_str())
+        .collect();
+    assert_eq!(text, "Hello");
+    assert_eq!(events.last().unwrap()["type"], "response.completed");
+}
+
+#[tokio::test]
+async fn test_completions() {
+    let harness = Harness::start().await;
+    Mock::given(method("POST"))
+        .and(path("/v1/engines/gpt-41-copilot/completions"))
+        .and(header("authorization", format!("Bearer {}", COPILOT_TOKEN)))
+        .respond_with(sse(&[
+            json!({"id": "cmpl-1", "created": 1700000000, "choices": [{"index": 0, "text": "fn main() "}]}),
+            json!({"id": "cmpl-1", "created": 1700000000, "choices": [{"index": 0, "text": "{}", "finish_reason": "stop"}]}),
+        ]))
+        .mount(&harness.copilot)
+        .await;
+
+    let response = harness
+        .post(
+            "/v1/completions",
+            json!({"model": "gpt-4o", "prompt": "// Rust\n"}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "text_completion");
+    assert_eq!(body["choices"][0]["text"], "fn main() {}");
+}
+
+#[cfg(feature = "ollama")]
+#[tokio::test]
+async fn test_ollama_chat_stream() {
+    let harness = Harness::start().await;
+    harness.mock_stream(&text_stream(&["Hel", "lo"])).await;
+
+    let response = harness
+        .post(
+            "/api/chat",
+            json!({
+                "model": "gpt-4o",
+                "stream": true,
+                "messages": [{"role": "user", "content": "Hi"}]
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let lines = ndjson(&response.text().await.unwrap());
+
+    let text: String = lines
+        .iter()
+        .filter_map(|line| line["message"]["content"].as_str())
+        .collect();
+    assert_eq!(text, "Hello");
+    let last = lines.last().unwrap();
+    assert_eq!(last["done"], true);
+    assert_eq!(last["done_reason"], "stop");
+}
+
+#[cfg(feature = "ollama")]
+#[tokio::test]
+async fn test_ollama_generate_and_tags() {
+    let harness = Harness::start().await;
+    harness.mock_reply("Hello from generate").await;
+
+    let response = harness
+        .post(
+            "/api/generate",
+            json!({"model": "gpt-4o", "prompt": "Hi", "stream": false}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["response"], "Hello from generate");
+    assert_eq!(body["done"], true);
+
+    let response = harness
+        .client
+        .get(harness.url("/api/tags"))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["models"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_azure_chat_completion() {
+    let harness = Harness::start().await;
+    harness
+        .mock_chat(
+            json!({"model": "gpt-4o"}),
+            ResponseTemplate::new(200).set_body_json(reply("Hello from Azure")),
+        )
+        .await;
+
+    let response = harness
+        .post(
+            "/openai/deployments/gpt-4o/chat/completions?api-version=2024-06-01",
+            json!({"messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "Hello from Azure");
+}
+
+/// A proxy requiring API keys, with a key that may only use gpt-4o
+#[cfg(feature = "keys")]
+async fn keyed_harness() -> (Harness, String) {
+    let harness = Harness::start_with(
+        r#"
+        [keys]
+        enabled = true
+        path = "{dir}/keys.sqlite"
+        "#,
+    )
+    .await;
+    let store = KeyStore::open(&KeysConfig {
+        enabled: true,
+        path: Some(harness.dir().join("keys.sqlite").display().to_string()),
+        ..KeysConfig::default()
+    })
+    .unwrap();
+    let (_, secret) = store.create("e2e", None, &["gpt-4o".to_string()]).unwrap();
+    harness.mock_reply("Hello").await;
+    (harness, secret)
+}
+
+#[cfg(feature = "keys")]
+#[tokio::test]
+async fn test_key_models_are_enforced() {
+    let (harness, key) = keyed_harness().await;
+    let chat =
+        |model: &str| json!({"model": model, "messages": [{"role": "user", "content": "Hi"}]});
+
+    let response = harness
+        .client
+        .post(harness.url("/v1/chat/completions"))
+        .bearer_auth(&key)
+        .json(&chat("gpt-4o"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = harness
+        .client
+        .post(harness.url("/v1/chat/completions"))
+        .bearer_auth(&key)
+        .json(&chat("o3-mini"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "keys")]
+#[tokio::test]
+async fn test_key_models_are_enforced_on_chunked_bodies() {
+    let (harness, key) = keyed_harness().await;
+    let body = json!({"model": "o3-mini", "messages": [{"role": "user", "content": "Hi"}]});
+    // A streamed body has no Content-Length to peek at
+    let chunks = futures_util::stream::iter([Ok::<_, std::io::Error>(body.to_string())]);
+
+    let response = harness
+        .client
+        .post(harness.url("/v1/chat/completions"))
+        .bearer_auth(&key)
+        .header("content-type", "application/json")
+        .body(reqwest::Body::wrap_stream(chunks))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "keys")]
+#[tokio::test]
+async fn test_key_models_are_enforced_on_azure_deployments() {
+    let (harness, key) = keyed_harness().await;
+
+    let response = harness
+        .client
+        .post(harness.url("/openai/deployments/o3-mini/chat/completions?api-version=2024-06-01"))
+        .header("api-key", &key)
+        .json(&json!({"messages": [{"role": "user", "content": "Hi"}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "keys")]
+#[tokio::test]
+async fn test_key_models_are_enforced_on_model_overrides() {
+    let (harness, key) = keyed_harness().await;
+
+    let response = harness
+        .client
+        .post(harness.url("/v1/chat/completions"))
+        .bearer_auth(&key)
+        .header("X-Passenger-Model-Override", "o3-mini")
+        .json(&json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Hi"}]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}